@@ -0,0 +1,59 @@
+//! Reading a pack's event log, on one thread and on all of them
+//!
+//! `cargo bench --bench event_log`
+
+#![allow(clippy::expect_used)] // Setup failing should stop the benchmark
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use wolfpack::crypto::KeyPair;
+use wolfpack::events::{Event, EventLog, PrefValue};
+
+const DEVICES: usize = 8;
+const FILES_PER_DEVICE: usize = 25;
+const EVENTS_PER_FILE: usize = 50;
+
+/// A sync dir holding event files from several devices, which share a key
+/// so one of them can read the rest
+fn write_pack(path: &std::path::Path) -> EventLog {
+    let keypair = KeyPair::generate();
+    for device in 0..DEVICES {
+        let mut log = EventLog::new(
+            path.to_path_buf(),
+            format!("device-{}", device),
+            keypair.clone(),
+        );
+        for file in 0..FILES_PER_DEVICE {
+            let events = (0..EVENTS_PER_FILE)
+                .map(|n| Event::PrefSet {
+                    key: format!("bench.pref.{}", n),
+                    value: PrefValue::Int((file * EVENTS_PER_FILE + n) as i64),
+                })
+                .collect();
+            log.write_events(events, &[]).expect("writing bench events");
+        }
+    }
+    EventLog::new(path.to_path_buf(), "device-0".to_string(), keypair)
+}
+
+fn read_all_events(c: &mut Criterion) {
+    let dir = tempfile::tempdir().expect("creating bench dir");
+    let log = write_pack(dir.path());
+    let single = rayon::ThreadPoolBuilder::new()
+        .num_threads(1)
+        .build()
+        .expect("building thread pool");
+
+    let mut group = c.benchmark_group("read_all_events");
+    group.bench_function("serial", |b| {
+        b.iter(|| single.install(|| black_box(log.read_all_events(&[]).expect("reading"))));
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| black_box(log.read_all_events(&[]).expect("reading")));
+    });
+    group.finish();
+}
+
+criterion_group!(benches, read_all_events);
+criterion_main!(benches);
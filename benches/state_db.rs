@@ -0,0 +1,89 @@
+//! The state database's hot paths: applying events, skipping ones already
+//! applied, and listing pending tabs
+//!
+//! `cargo bench --bench state_db`
+
+#![allow(clippy::expect_used)] // Setup failing should stop the benchmark
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+use wolfpack::events::{Event, EventEnvelope, PrefValue, VectorClock};
+use wolfpack::state::{StateDb, materialize_events};
+
+const EVENTS: usize = 1000;
+const PENDING_TABS: usize = 1000;
+
+fn events() -> Vec<EventEnvelope> {
+    (0..EVENTS)
+        .map(|n| {
+            EventEnvelope::new(
+                format!("device-{}", n % 4),
+                VectorClock::new(),
+                Event::PrefSet {
+                    key: format!("bench.pref.{}", n % 100),
+                    value: PrefValue::Int(n as i64),
+                },
+            )
+        })
+        .collect()
+}
+
+/// A database file, which unlike one in memory pays for each commit
+fn open() -> (tempfile::TempDir, StateDb) {
+    let dir = tempfile::tempdir().expect("creating bench dir");
+    let db = StateDb::open(&dir.path().join("state.db")).expect("opening state db");
+    (dir, db)
+}
+
+fn materialize(c: &mut Criterion) {
+    let events = events();
+    let mut group = c.benchmark_group("materialize");
+
+    group.bench_function("new", |b| {
+        b.iter_batched(
+            open,
+            |(_dir, db)| black_box(materialize_events(&db, &events, "device-0").expect("applying")),
+            BatchSize::PerIteration,
+        );
+    });
+
+    // What every sync cycle does with the events it has seen before
+    let (_dir, db) = open();
+    materialize_events(&db, &events, "device-0").expect("applying");
+    group.bench_function("applied", |b| {
+        b.iter(|| black_box(materialize_events(&db, &events, "device-0").expect("applying")));
+    });
+    group.bench_function("is_event_applied", |b| {
+        b.iter(|| {
+            for envelope in &events {
+                black_box(db.is_event_applied(envelope.id).expect("checking"));
+            }
+        });
+    });
+    group.finish();
+}
+
+fn pending_tabs(c: &mut Criterion) {
+    let (_dir, db) = open();
+    db.transaction(|db| {
+        for n in 0..PENDING_TABS {
+            db.add_pending_tab(
+                &format!("tab-{}", n),
+                &format!("https://example.com/{}", n),
+                None,
+                "device-1",
+                &format!("2024-01-01T00:{:02}:{:02}Z", (n / 60) % 60, n % 60),
+            )?;
+        }
+        Ok(())
+    })
+    .expect("adding tabs");
+
+    c.bench_function("get_pending_tabs", |b| {
+        b.iter(|| black_box(db.get_pending_tabs().expect("listing")));
+    });
+}
+
+criterion_group!(benches, materialize, pending_tabs);
+criterion_main!(benches);
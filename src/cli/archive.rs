@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::events::{import_archive, write_archive};
+
+/// Bundle the sync dir's encrypted event files and XPI chunks into an
+/// archive for offline backup
+pub fn create_archive(config_path: &Path, output: &Path) -> Result<()> {
+    let config = Config::load(config_path).unwrap_or_default();
+    let sync_dir = &config.paths.sync_dir;
+    if !sync_dir.join("events").exists() {
+        anyhow::bail!("Nothing to archive (no events in {})", sync_dir.display());
+    }
+
+    let file =
+        File::create(output).with_context(|| format!("Failed to create {}", output.display()))?;
+    let manifest = write_archive(sync_dir, &config.device.id, BufWriter::new(file))?;
+
+    let files: usize = manifest.event_files.values().sum();
+    println!(
+        "Archived {} event files from {} devices and {} XPI chunks to {}",
+        files,
+        manifest.event_files.len(),
+        manifest.blobs,
+        output.display()
+    );
+    println!("Event files stay encrypted; restoring them needs a key paired with the pack");
+    Ok(())
+}
+
+/// Restore the history in an archive into the sync dir
+pub fn restore_archive(config_path: &Path, path: &Path) -> Result<()> {
+    let config = Config::load(config_path).unwrap_or_default();
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let import = import_archive(&config.paths.sync_dir, file)?;
+
+    println!(
+        "Restored {} files from an archive made on {} at {} ({} already present)",
+        import.restored, import.manifest.device_id, import.manifest.created_at, import.skipped
+    );
+    if import.conflicts > 0 {
+        println!(
+            "Kept {} event files that differ from their archived copies",
+            import.conflicts
+        );
+    }
+    if import.restored > 0 {
+        println!("Run `wolfpack replay` to rebuild the state from the restored history");
+    }
+    Ok(())
+}
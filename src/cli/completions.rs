@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use clap_complete::CompletionCandidate;
+use clap_complete::env::Shells;
+
+use crate::config::Config;
+use crate::ipc::{Command, Payload};
+use crate::state::StateDb;
+
+use super::devices::stored_device_names;
+use super::ipc;
+
+/// Environment variable that switches `wolfpack` into completion mode
+pub const COMPLETE_VAR: &str = "COMPLETE";
+
+/// Print the script that hooks a shell's completion up to `wolfpack`
+///
+/// The script calls back into `wolfpack` while completing, so device
+/// names and extension IDs come from the daemon as they are typed.
+pub fn print_completions(shell: &str, command: &clap::Command) -> Result<()> {
+    let shells = Shells::builtins();
+    let completer = shells
+        .completer(shell)
+        .with_context(|| format!("Unsupported shell: {}", shell))?;
+    let program = std::env::current_exe()
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_else(|_| command.get_name().to_string());
+
+    completer.write_registration(
+        COMPLETE_VAR,
+        command.get_name(),
+        command.get_name(),
+        &program,
+        &mut std::io::stdout(),
+    )?;
+    Ok(())
+}
+
+/// Names of other devices, from the daemon or the state database
+pub fn device_candidates() -> Vec<CompletionCandidate> {
+    let names = match daemon_answer(Command::DeviceNames) {
+        Some(Payload::DeviceNames { names }) => Some(names),
+        _ => stored_device_names().ok(),
+    };
+    names
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// IDs of synced extensions, described by their names
+pub fn extension_candidates() -> Vec<CompletionCandidate> {
+    let extensions = match daemon_answer(Command::Extensions) {
+        Some(Payload::Extensions { extensions }) => Some(
+            extensions
+                .into_iter()
+                .map(|extension| (extension.id, extension.name))
+                .collect(),
+        ),
+        _ => stored_extensions().ok(),
+    };
+
+    extensions
+        .unwrap_or_default()
+        .into_iter()
+        .map(|(id, name)| CompletionCandidate::new(id).help(Some(name.into())))
+        .collect()
+}
+
+/// The daemon's answer to a command, if it's running
+fn daemon_answer(command: Command) -> Option<Payload> {
+    if !ipc::is_daemon_running() {
+        return None;
+    }
+    ipc::send_command(command).ok()
+}
+
+fn stored_extensions() -> Result<Vec<(String, String)>> {
+    let config_path = Config::default_path();
+    if !config_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    // Left unmigrated: completing shouldn't move databases around
+    let state_db_path = Config::load(&config_path)?.state_db_path();
+    if !state_db_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let db = StateDb::open(&state_db_path)?;
+    Ok(db
+        .get_extensions()?
+        .into_iter()
+        .map(|(id, name, _)| (id, name))
+        .collect())
+}
@@ -1,7 +1,22 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::BTreeMap;
 use std::fs;
+use std::io::IsTerminal;
 
 use crate::config::Config;
+use crate::ipc::Command;
+use crate::state::{DeviceRecord, DeviceReport, StateDb};
+
+use super::ipc;
+
+/// Devices seen within this window are shown as online; the daemon asks
+/// connected peers for their clock every 30 seconds
+const ONLINE_WINDOW: TimeDelta = TimeDelta::minutes(2);
+
+/// Daemons report their device's state every 6 hours, so a device seen
+/// this long after its last report has stopped sending them
+const REPORT_WINDOW: TimeDelta = TimeDelta::hours(13);
 
 pub fn list_devices() -> Result<()> {
     let config_path = Config::default_path();
@@ -11,37 +26,263 @@ pub fn list_devices() -> Result<()> {
     }
 
     let config = Config::load(&config_path)?;
-    let keys_dir = config.paths.sync_dir.join("keys");
+
+    let mut records = read_device_records(&config)?;
+    let reports = read_device_reports(&config)?;
+    let this_device = records.remove(&config.device.id);
 
     println!("This device:");
     println!("  ID: {}", config.device.id);
     println!("  Name: {}", config.device.name);
+    if let Some(label) = this_device.as_ref().and_then(describe_label) {
+        println!("  Label: {}", label);
+    }
     println!();
 
-    if !keys_dir.exists() {
+    let mut keys = read_device_keys(&config)?;
+    keys.remove(&config.device.id);
+
+    if keys.is_empty() && records.is_empty() {
         println!("No other devices paired yet.");
         println!("To pair, share your public key with: wolfpack pair");
         return Ok(());
     }
 
+    let mut ids: Vec<&String> = keys.keys().chain(records.keys()).collect();
+    ids.sort();
+    ids.dedup();
+
     println!("Known devices:");
-    let mut found = false;
-    for entry in
-        fs::read_dir(&keys_dir).with_context(|| format!("Failed to read {}", keys_dir.display()))?
-    {
-        let entry = entry?;
-        let path = entry.path();
-        if path.extension().is_some_and(|e| e == "pub") {
-            let name = path.file_stem().unwrap_or_default().to_string_lossy();
-            let content = fs::read_to_string(&path)?;
-            println!("  {}: {}", name, content.trim());
-            found = true;
+    let now = Utc::now();
+    for id in ids {
+        print_device(id, keys.get(id), records.get(id), now);
+        if let Some(report) = reports.get(id) {
+            print_report(report, records.get(id));
         }
     }
 
-    if !found {
-        println!("  (none)");
+    Ok(())
+}
+
+fn print_device(id: &str, key: Option<&String>, record: Option<&DeviceRecord>, now: DateTime<Utc>) {
+    match record.filter(|r| r.name.is_some()) {
+        Some(record) => println!(
+            "  {} ({}): {}",
+            display_name(record),
+            id,
+            presence(Some(record), now)
+        ),
+        None => println!("  {}: {}", id, presence(record, now)),
+    }
+    if let Some(key) = key {
+        println!(
+            "    Key: {} ({})",
+            crate::redact::public_key(key),
+            key_verification(key, record)
+        );
+    }
+    let Some(record) = record else {
+        return;
+    };
+    if let Some(last_seen) = &record.last_seen {
+        println!("    Last seen: {}", format_time(last_seen));
+    }
+    if let Some(last_sync) = &record.last_sync {
+        println!("    Last sync: {}", format_time(last_sync));
+    }
+    if let Some(address) = &record.address {
+        println!("    Address: {}", address);
+    }
+    if let Some(onion) = &record.onion {
+        println!("    Onion: {}", onion);
+    }
+}
+
+/// Whether a device's key is the one marked verified with
+/// `wolfpack keys verify`
+pub(super) fn key_verification(key: &str, record: Option<&DeviceRecord>) -> &'static str {
+    match record.and_then(|r| r.verified_key.as_deref()) {
+        Some(verified) if verified == key => "verified",
+        Some(_) => "changed since it was verified",
+        None => "not verified",
     }
+}
 
+/// What the device last reported it's running
+fn print_report(report: &DeviceReport, record: Option<&DeviceRecord>) {
+    println!("    Wolfpack: {}", report.wolfpack_version);
+    if let Some(browser_version) = &report.browser_version {
+        println!("    Browser: {}", browser_version);
+    }
+    println!("    Extensions: {}", report.extensions.len());
+    if let Some(last_scan) = &report.last_scan {
+        println!("    Last scan: {}", format_time(last_scan));
+    }
+    println!("    Last report: {}", format_time(&report.reported_at));
+
+    let reported_at = DateTime::parse_from_rfc3339(&report.reported_at).ok();
+    let last_seen = record
+        .and_then(|r| r.last_seen.as_deref())
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+    if let (Some(reported_at), Some(last_seen)) = (reported_at, last_seen)
+        && last_seen.signed_duration_since(reported_at) > REPORT_WINDOW
+    {
+        println!(
+            "    Warning: seen since without reporting; its daemon may have stopped applying events"
+        );
+    }
+}
+
+pub fn rename_device(device: &str, name: &str) -> Result<()> {
+    let command = Command::RenameDevice {
+        device: device.to_string(),
+        name: name.to_string(),
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
+
+/// Forget the peer a device is bound to, after its p2p identity changed
+pub fn unbind_device(device: &str) -> Result<()> {
+    let command = Command::UnbindDevice {
+        device: device.to_string(),
+    };
+    println!("{}", ipc::send_command(command)?);
     Ok(())
 }
+
+/// Other devices' names as recorded by the daemon, for when it isn't
+/// running to ask
+pub(super) fn stored_device_names() -> Result<Vec<String>> {
+    let config_path = Config::default_path();
+    if !config_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let config = Config::load(&config_path)?;
+    Ok(read_device_records(&config)?
+        .into_values()
+        .filter(|record| record.id != config.device.id)
+        .map(|record| record.name.unwrap_or(record.id))
+        .collect())
+}
+
+/// Label a device with an emoji and color, or clear its label
+pub fn label_device(device: &str, emoji: Option<&str>, color: Option<&str>) -> Result<()> {
+    let command = Command::LabelDevice {
+        device: device.to_string(),
+        emoji: emoji.map(String::from),
+        color: color.map(String::from),
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
+
+/// Device name with its emoji, in its color when printing to a terminal
+fn display_name(record: &DeviceRecord) -> String {
+    let name = record.name.as_deref().unwrap_or(&record.id);
+    let name = match record.color.as_deref().and_then(ansi_color) {
+        Some(code) if std::io::stdout().is_terminal() => format!("\x1b[{}m{}\x1b[0m", code, name),
+        _ => name.to_string(),
+    };
+    match &record.emoji {
+        Some(emoji) => format!("{} {}", emoji, name),
+        None => name,
+    }
+}
+
+fn describe_label(record: &DeviceRecord) -> Option<String> {
+    let parts: Vec<&str> = [record.emoji.as_deref(), record.color.as_deref()]
+        .into_iter()
+        .flatten()
+        .collect();
+    (!parts.is_empty()).then(|| parts.join(" "))
+}
+
+/// Terminal color for each of the device label colors
+fn ansi_color(color: &str) -> Option<&'static str> {
+    match color {
+        "blue" => Some("34"),
+        "turquoise" => Some("36"),
+        "green" => Some("32"),
+        "yellow" => Some("33"),
+        "orange" => Some("38;5;208"),
+        "red" => Some("31"),
+        "pink" => Some("38;5;205"),
+        "purple" => Some("35"),
+        _ => None,
+    }
+}
+
+/// Device ID -> public key, for devices with a key in the sync directory
+pub(super) fn read_device_keys(config: &Config) -> Result<BTreeMap<String, String>> {
+    let keys_dir = config.paths.sync_dir.join("keys");
+    let mut keys = BTreeMap::new();
+    if keys_dir.exists() {
+        for entry in fs::read_dir(&keys_dir)
+            .with_context(|| format!("Failed to read {}", keys_dir.display()))?
+        {
+            let entry = entry?;
+            let path = entry.path();
+            if path.extension().is_some_and(|e| e == "pub") {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy();
+                let content = fs::read_to_string(&path)?;
+                keys.insert(name.to_string(), content.trim().to_string());
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Presence recorded by the daemon, without creating a state database
+/// if it has never run
+pub(super) fn read_device_records(config: &Config) -> Result<BTreeMap<String, DeviceRecord>> {
+    let state_db_path = config.migrate_state_db()?;
+    if !state_db_path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let db = StateDb::open(&state_db_path)?;
+    Ok(db
+        .get_devices()?
+        .into_iter()
+        .map(|record| (record.id.clone(), record))
+        .collect())
+}
+
+/// Each device's last report, without creating a state database if the
+/// daemon has never run
+fn read_device_reports(config: &Config) -> Result<BTreeMap<String, DeviceReport>> {
+    let state_db_path = config.migrate_state_db()?;
+    if !state_db_path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let db = StateDb::open(&state_db_path)?;
+    Ok(db
+        .get_device_reports()?
+        .into_iter()
+        .map(|report| (report.device.clone(), report))
+        .collect())
+}
+
+fn presence(record: Option<&DeviceRecord>, now: DateTime<Utc>) -> &'static str {
+    let last_seen = record
+        .and_then(|r| r.last_seen.as_deref())
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok());
+    match last_seen {
+        Some(t) if now.signed_duration_since(t) <= ONLINE_WINDOW => "online",
+        Some(_) => "offline",
+        None => "never seen",
+    }
+}
+
+fn format_time(timestamp: &str) -> String {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
@@ -0,0 +1,19 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+use crate::sync::ReplayPoint;
+
+use super::ipc;
+
+/// Show what changed in the synced state between two points in history
+pub fn diff_history(from: &str, to: Option<&str>) -> Result<()> {
+    // Check the points here so mistakes are reported without the daemon
+    let command = Command::Diff {
+        from: from.parse::<ReplayPoint>()?.to_arg(),
+        to: to
+            .map(|to| to.parse::<ReplayPoint>().map(|to| to.to_arg()))
+            .transpose()?,
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
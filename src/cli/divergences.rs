@@ -0,0 +1,20 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+
+use super::ipc;
+
+pub fn list_divergences() -> Result<()> {
+    println!("{}", ipc::send_command(Command::Divergences)?);
+    Ok(())
+}
+
+pub fn resolve_divergence(category: &str, item: &str, keep: &str) -> Result<()> {
+    let command = Command::ResolveDivergence {
+        category: category.to_string(),
+        item: item.to_string(),
+        keep: keep.to_string(),
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use anyhow::{Context, Result};
+use clap_mangen::Man;
+use std::path::Path;
+
+/// Write a man page for the command and each visible subcommand, named
+/// like `wolfpack-send.1`
+pub fn write_man_pages(command: clap::Command, out_dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+
+    let mut command = command.disable_help_subcommand(true);
+    command.build();
+
+    let mut written = Vec::new();
+    write_pages(command, out_dir, &mut written)?;
+    for path in &written {
+        println!("{}", path.display());
+    }
+
+    Ok(())
+}
+
+fn write_pages(
+    command: clap::Command,
+    out_dir: &Path,
+    written: &mut Vec<std::path::PathBuf>,
+) -> Result<()> {
+    for subcommand in command.get_subcommands().filter(|s| !s.is_hide_set()) {
+        write_pages(subcommand.clone(), out_dir, written)?;
+    }
+
+    let path = Man::new(command)
+        .generate_to(out_dir)
+        .with_context(|| format!("Failed to write man page to {}", out_dir.display()))?;
+    written.push(path);
+    Ok(())
+}
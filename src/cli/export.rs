@@ -0,0 +1,59 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::ipc::Command;
+use crate::state::StateDb;
+use crate::sync::{ExportFormat, StateExport};
+
+use super::ipc;
+
+/// Dump materialized state to a file, or stdout without one
+pub fn export_state(config_path: &Path, format: &str, out: Option<&Path>) -> Result<()> {
+    let format = ExportFormat::parse(format).with_context(|| {
+        format!(
+            "Unknown export format: {} (use json, tar, or home-manager)",
+            format
+        )
+    })?;
+    let config = Config::load(config_path).unwrap_or_default();
+    let state_path = config.migrate_state_db()?;
+
+    if !state_path.exists() {
+        anyhow::bail!("Nothing to export (state database not initialized)");
+    }
+
+    let db = StateDb::open(&state_path)?;
+    let export = StateExport::from_db(&db, &config.device.id)?;
+
+    match out {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?;
+            export.write(format, file)?;
+            println!("Exported state to {}", path.display());
+        }
+        None => {
+            let mut stdout = std::io::stdout().lock();
+            export.write(format, &mut stdout)?;
+            stdout.flush()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Import an export through the daemon, which syncs it to the pack
+pub fn import_state(path: &Path) -> Result<()> {
+    // Check the file here so mistakes are reported without the daemon
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    StateExport::read(file)?;
+
+    let path = path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", path.display()))?;
+    println!("{}", ipc::send_command(Command::Import { path })?);
+    Ok(())
+}
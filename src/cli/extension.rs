@@ -1,9 +1,15 @@
 use anyhow::{Context, Result};
+use std::io::Write;
 use std::path::Path;
 
+use super::ipc;
 use crate::config::Config;
-use crate::events::Event;
-use crate::extensions::{install_from_xpi, install_to_profile};
+use crate::events::ExtensionSource;
+use crate::extensions::{
+    amo_xpi, build_from_git, check_sha256, download_client, download_xpi, parse_addon_list,
+    sha256_hex,
+};
+use crate::ipc::{Command, Payload};
 use crate::state::StateDb;
 
 /// Load config or use defaults if it doesn't exist
@@ -11,73 +17,173 @@ fn load_or_default_config(config_path: &Path) -> Config {
     Config::load(config_path).unwrap_or_default()
 }
 
-/// Install an extension from a local XPI file
-pub fn install_extension(xpi_path: &Path, config_path: &Path) -> Result<()> {
-    let config = load_or_default_config(config_path);
-    let profile_dir = config.profile_dir()?;
+/// Largest XPI to download, the most an event can carry
+fn max_download_bytes(config: &Config) -> u64 {
+    config.limits.max_xpi_mb * 1024 * 1024
+}
 
-    println!("Installing extension from {}...", xpi_path.display());
+/// Hand a downloaded XPI to the daemon as a temporary file, to install with
+/// where it came from
+fn install_download(xpi: &[u8], source: ExtensionSource) -> Result<Payload> {
+    let mut file = tempfile::Builder::new().suffix(".xpi").tempfile()?;
+    file.write_all(xpi)?;
+    let command = Command::InstallExtension {
+        path: file.path().to_path_buf(),
+        source: Some(source),
+    };
+    ipc::send_command(command)
+}
 
-    let result = install_from_xpi(xpi_path)?;
+/// How to build an extension installed from a git repository
+#[derive(Debug, Default)]
+pub struct GitOptions {
+    pub ref_spec: Option<String>,
+    pub build: Option<String>,
+}
 
-    println!("Loaded {} v{}", result.name, result.version);
-    println!("Extension ID: {}", result.id);
+impl GitOptions {
+    fn is_set(&self) -> bool {
+        self.ref_spec.is_some() || self.build.is_some()
+    }
+}
 
-    // Store in local state
-    let state_path = config.state_db_path();
-    std::fs::create_dir_all(state_path.parent().unwrap_or(Path::new(".")))?;
-    let db = StateDb::open(&state_path)?;
-    db.store_extension_xpi(
-        &result.id,
-        &result.version,
-        &result.source,
-        &result.xpi_data,
-    )?;
-    db.add_extension(&result.id, &result.name, None)?;
-
-    // Install to profile
-    install_to_profile(&result.xpi_data, &profile_dir, &result.id)?;
-
-    let installed_path = profile_dir
-        .join("extensions")
-        .join(format!("{}.xpi", result.id));
-
-    // Verify file exists after install
-    if installed_path.exists() {
-        let meta = std::fs::metadata(&installed_path)?;
-        println!(
-            "Verified: {} exists ({} bytes)",
-            installed_path.display(),
-            meta.len()
-        );
-    } else {
-        println!(
-            "WARNING: File does not exist after install: {}",
-            installed_path.display()
-        );
+/// Whether a source names a git repository, not an XPI to download
+///
+/// URLs that don't end in `.xpi` are taken for repositories, as are
+/// `git@host:path` addresses.
+fn is_git_source(source: &str) -> bool {
+    if source.starts_with("git@") {
+        return true;
+    }
+    let Ok(url) = reqwest::Url::parse(source) else {
+        return false;
+    };
+    source.contains("://") && !url.path().to_ascii_lowercase().ends_with(".xpi")
+}
+
+/// Install an extension from a local XPI file, an HTTPS URL, or a git
+/// repository it's built from, through the daemon, which syncs it to the
+/// pack
+///
+/// An XPI is checked against `sha256` when given.
+pub async fn install_extension(
+    config_path: &Path,
+    source: &str,
+    sha256: Option<&str>,
+    git: GitOptions,
+) -> Result<()> {
+    if git.is_set() || is_git_source(source) {
+        if sha256.is_some() {
+            anyhow::bail!("--sha256 checks an XPI, not a git repository");
+        }
+        // Fail before building if there's nothing to install through
+        if !ipc::is_daemon_running() {
+            anyhow::bail!("Daemon is not running. Start with: wolfpack daemon");
+        }
+        let url = source.to_string();
+        let built = tokio::task::spawn_blocking(move || {
+            build_from_git(&url, git.ref_spec.as_deref(), git.build.as_deref())
+        })
+        .await??;
+        println!("{}", install_download(&built.xpi, built.source)?);
+        return Ok(());
     }
 
-    // Store pending event for daemon to sync
-    store_pending_extension_event(
-        &state_path,
-        Event::ExtensionInstalled {
-            id: result.id.clone(),
-            name: result.name.clone(),
-            version: result.version,
-            source: result.source,
-            xpi_data: result.xpi_data,
-        },
-    )?;
+    if source.contains("://") {
+        let config = load_or_default_config(config_path);
+        let client = download_client(config.net.socks5.as_deref())?;
+        let xpi = download_xpi(&client, source, max_download_bytes(&config)).await?;
+        if let Some(expected) = sha256 {
+            check_sha256(&xpi, expected)?;
+        }
+        let source = ExtensionSource::Url {
+            url: source.to_string(),
+            sha256: sha256_hex(&xpi),
+        };
+        println!("{}", install_download(&xpi, source)?);
+        return Ok(());
+    }
+
+    let xpi_path = Path::new(source);
+    let path = xpi_path
+        .canonicalize()
+        .with_context(|| format!("Failed to resolve {}", xpi_path.display()))?;
+    if let Some(expected) = sha256 {
+        check_sha256(&std::fs::read(&path)?, expected)?;
+    }
+    let command = Command::InstallExtension { path, source: None };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
 
-    println!("Restart LibreWolf to activate the extension.");
+/// Install every add-on in a list of AMO slugs, IDs, or links, then sum up
+/// which were installed and which failed
+pub async fn import_extension_list(config_path: &Path, list_path: &Path) -> Result<()> {
+    let content = std::fs::read_to_string(list_path)
+        .with_context(|| format!("Failed to read {}", list_path.display()))?;
+    let addons = parse_addon_list(&content)
+        .with_context(|| format!("Failed to parse {}", list_path.display()))?;
+    if addons.is_empty() {
+        println!("No extensions in {}", list_path.display());
+        return Ok(());
+    }
+    // Fail before downloading anything if there's nothing to install through
+    if !ipc::is_daemon_running() {
+        anyhow::bail!("Daemon is not running. Start with: wolfpack daemon");
+    }
+
+    let config = load_or_default_config(config_path);
+    let client = download_client(config.net.socks5.as_deref())?;
+    let mut failed = Vec::new();
+    for addon in &addons {
+        match install_from_amo(&client, addon, max_download_bytes(&config)).await {
+            Ok(reply) => println!("{}: {}", addon, reply),
+            Err(e) => {
+                println!("{}: failed: {:#}", addon, e);
+                failed.push(addon.as_str());
+            }
+        }
+    }
 
+    println!();
+    println!(
+        "Installed {} of {} extensions",
+        addons.len() - failed.len(),
+        addons.len()
+    );
+    if !failed.is_empty() {
+        anyhow::bail!("Failed to install: {}", failed.join(", "));
+    }
     Ok(())
 }
 
-/// List installed extensions
+async fn install_from_amo(
+    client: &reqwest::Client,
+    addon: &str,
+    max_bytes: u64,
+) -> Result<Payload> {
+    let amo = amo_xpi(client, addon).await?;
+    let xpi = download_xpi(client, &amo.url, max_bytes).await?;
+    check_sha256(&xpi, &amo.sha256)?;
+    let source = ExtensionSource::Amo { amo_slug: amo.slug };
+    install_download(&xpi, source)
+}
+
+/// List synced extensions
+///
+/// With the daemon running, shows which devices in the pack have each one,
+/// as the devices reported; otherwise only this device's local state.
 pub fn list_extensions(config_path: &Path, show_missing: bool) -> Result<()> {
+    if ipc::is_daemon_running() {
+        let command = Command::ExtensionMatrix {
+            missing: show_missing,
+        };
+        println!("{}", ipc::send_command(command)?);
+        return Ok(());
+    }
+
     let config = load_or_default_config(config_path);
-    let state_path = config.state_db_path();
+    let state_path = config.migrate_state_db()?;
 
     if !state_path.exists() {
         println!("No synced extensions (state database not initialized).");
@@ -111,69 +217,12 @@ pub fn list_extensions(config_path: &Path, show_missing: bool) -> Result<()> {
     Ok(())
 }
 
-/// Uninstall an extension
-pub fn uninstall_extension(extension_id: &str, config_path: &Path) -> Result<()> {
-    let config = load_or_default_config(config_path);
-    let state_path = config.state_db_path();
-
-    if !state_path.exists() {
-        anyhow::bail!(
-            "State database not found. Extension {} may not be tracked.",
-            extension_id
-        );
-    }
-
-    let db = StateDb::open(&state_path)?;
-
-    // Check if extension exists
-    let extensions = db.get_extensions()?;
-    let found = extensions.iter().any(|(id, _, _)| id == extension_id);
-    if !found {
-        anyhow::bail!("Extension {} not found in sync database", extension_id);
-    }
-
-    // Remove from local state
-    db.remove_extension(extension_id)?;
-    db.remove_extension_xpi(extension_id)?;
-
-    // Remove from profile
-    let profile_dir = config.profile_dir()?;
-    let xpi_path = profile_dir
-        .join("extensions")
-        .join(format!("{}.xpi", extension_id));
-    if xpi_path.exists() {
-        std::fs::remove_file(&xpi_path)
-            .with_context(|| format!("Failed to remove {}", xpi_path.display()))?;
-        println!("Removed XPI from profile.");
-    }
-
-    // Store pending event for daemon to sync
-    store_pending_extension_event(
-        &state_path,
-        Event::ExtensionUninstalled {
-            id: extension_id.to_string(),
-        },
-    )?;
-
-    println!("Extension {} uninstalled.", extension_id);
-    println!("The daemon will sync this removal to other devices.");
-    println!("Restart LibreWolf to complete removal.");
-
-    Ok(())
-}
-
-/// Store a pending extension event for the daemon to sync
-fn store_pending_extension_event(state_path: &Path, event: Event) -> Result<()> {
-    let pending_dir = state_path
-        .parent()
-        .unwrap_or(Path::new("."))
-        .join("pending_events");
-    std::fs::create_dir_all(&pending_dir)?;
-
-    let event_id = uuid::Uuid::now_v7();
-    let event_path = pending_dir.join(format!("{}.json", event_id));
-    let event_json = serde_json::to_string_pretty(&event)?;
-    std::fs::write(&event_path, event_json)?;
-
+/// Uninstall an extension, through the daemon, which syncs the removal to
+/// the pack
+pub fn uninstall_extension(extension_id: &str) -> Result<()> {
+    let command = Command::UninstallExtension {
+        id: extension_id.to_string(),
+    };
+    println!("{}", ipc::send_command(command)?);
     Ok(())
 }
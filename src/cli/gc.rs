@@ -0,0 +1,10 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+
+use super::ipc;
+
+pub fn collect_garbage() -> Result<()> {
+    println!("{}", ipc::send_command(Command::Gc)?);
+    Ok(())
+}
@@ -1,32 +1,96 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use std::collections::HashMap;
 use std::io::{BufRead, BufReader, Write};
 use std::os::unix::net::UnixStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::config::Config;
+
+static SOCKET_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Talk to the daemon on the socket set in the config file at `config_path`
+pub fn use_config(config_path: &Path) {
+    let _ = SOCKET_PATH.set(Config::ipc_socket_for(config_path));
+}
+
+use crate::ipc::{Command, Payload, Request, Response};
 
 pub fn socket_path() -> PathBuf {
-    dirs::runtime_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join("wolfpack.sock")
+    SOCKET_PATH
+        .get_or_init(|| Config::ipc_socket_for(&Config::default_path()))
+        .clone()
 }
 
-pub fn send_command(command: &str) -> Result<String> {
-    let path = socket_path();
-    let mut stream = UnixStream::connect(&path).with_context(|| {
-        format!(
-            "Failed to connect to daemon at {}. Is the daemon running?",
-            path.display()
-        )
-    })?;
+/// A connection to the daemon, which can have several requests in flight
+pub struct IpcClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+    next_id: u64,
+    /// Responses read while waiting for another request's
+    received: HashMap<u64, Response>,
+}
 
-    stream.write_all(command.as_bytes())?;
-    stream.write_all(b"\n")?;
-    stream.flush()?;
+impl IpcClient {
+    pub fn connect() -> Result<Self> {
+        let path = socket_path();
+        let stream = UnixStream::connect(&path).with_context(|| {
+            format!(
+                "Failed to connect to daemon at {}. Is the daemon running?",
+                path.display()
+            )
+        })?;
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            next_id: 1,
+            received: HashMap::new(),
+        })
+    }
 
-    let mut reader = BufReader::new(stream);
-    let mut response = String::new();
-    reader.read_line(&mut response)?;
+    /// Send a request without waiting for its response, returning its ID
+    pub fn send(&mut self, command: Command) -> Result<u64> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut line = serde_json::to_string(&Request::new(id, command))?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())?;
+        self.writer.flush()?;
+        Ok(id)
+    }
+
+    /// Wait for the response to a request sent earlier
+    pub fn response(&mut self, id: u64) -> Result<Payload> {
+        loop {
+            if let Some(response) = self.received.remove(&id) {
+                return Ok(response.result?);
+            }
+
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("The daemon closed the connection without answering");
+            }
+            let response: Response = serde_json::from_str(&line)
+                .context("The daemon sent a response this version doesn't understand")?;
+            if response.id == 0 {
+                // Requests the daemon couldn't read are answered without an ID
+                return Ok(response.result?);
+            }
+            self.received.insert(response.id, response);
+        }
+    }
+
+    /// Send a request and wait for its response
+    pub fn request(&mut self, command: Command) -> Result<Payload> {
+        let id = self.send(command)?;
+        self.response(id)
+    }
+}
 
-    Ok(response.trim().to_string())
+/// Send one request to the daemon on a new connection
+pub fn send_command(command: Command) -> Result<Payload> {
+    IpcClient::connect()?.request(command)
 }
 
 pub fn is_daemon_running() -> bool {
@@ -0,0 +1,130 @@
+use anyhow::{Result, bail};
+use std::collections::BTreeMap;
+
+use super::devices::{key_verification, read_device_keys, read_device_records};
+use super::ipc;
+use crate::config::Config;
+use crate::crypto::{Fingerprint, KeyPair, public_key_from_hex};
+use crate::ipc::Command;
+use crate::state::{DeviceRecord, StateDb};
+
+/// Print the fingerprints of this device's key and paired devices' keys, or
+/// of one device's, to compare with what the other device shows
+pub fn show_fingerprints(device: Option<&str>) -> Result<()> {
+    let config_path = Config::default_path();
+    if !config_path.exists() {
+        println!("Not initialized. Run: wolfpack init");
+        return Ok(());
+    }
+
+    let config = Config::load(&config_path)?;
+    let mut keys = read_device_keys(&config)?;
+    keys.remove(&config.device.id);
+    let records = read_device_records(&config)?;
+
+    let this_device = device.is_none_or(|d| d == config.device.id || d == config.device.name);
+    if this_device {
+        let path = Config::local_key_path();
+        if path.exists() {
+            println!("This device ({}):", config.device.name);
+            print_fingerprint(&Fingerprint::of(&KeyPair::load(&path)?.public_key()));
+        } else {
+            println!("This device has no key yet. Run: wolfpack init");
+        }
+        if device.is_some() {
+            return Ok(());
+        }
+    }
+
+    let ids: Vec<&str> = match device {
+        Some(device) => vec![resolve_device(device, &keys, &records)?],
+        None => keys.keys().map(String::as_str).collect(),
+    };
+    if ids.is_empty() {
+        println!();
+        println!("No other devices paired yet.");
+        return Ok(());
+    }
+
+    if this_device {
+        println!();
+        println!("Paired devices:");
+    }
+    for id in ids {
+        print_device_fingerprint(id, &keys[id], records.get(id));
+    }
+
+    Ok(())
+}
+
+fn print_device_fingerprint(id: &str, key: &str, record: Option<&DeviceRecord>) {
+    let name = record.and_then(|r| r.name.as_deref()).unwrap_or(id);
+    println!("  {} ({}): {}", name, id, key_verification(key, record));
+    match public_key_from_hex(key) {
+        Ok(key) => print_fingerprint(&Fingerprint::of(&key)),
+        Err(e) => println!("    Unreadable key: {}", e),
+    }
+}
+
+fn print_fingerprint(fingerprint: &Fingerprint) {
+    println!("    Hex: {}", fingerprint.hex);
+    println!("    Words: {}", fingerprint.words);
+}
+
+/// Mark a paired device's current key as verified on this device, once its
+/// fingerprint has been compared with what the device itself shows
+pub fn verify_key(device: &str) -> Result<()> {
+    let config_path = Config::default_path();
+    if !config_path.exists() {
+        bail!("Not initialized. Run: wolfpack init");
+    }
+
+    let config = Config::load(&config_path)?;
+    if device == config.device.id || device == config.device.name {
+        bail!(
+            "{} is this device; verify its key on the other devices",
+            device
+        );
+    }
+    let keys = read_device_keys(&config)?;
+    let records = read_device_records(&config)?;
+    let id = resolve_device(device, &keys, &records)?;
+    let key = &keys[id];
+    let fingerprint = Fingerprint::of(&public_key_from_hex(key)?);
+
+    // Through the daemon when it's running, which holds the state database
+    if ipc::is_daemon_running() {
+        ipc::send_command(Command::VerifyKey {
+            device_id: id.to_string(),
+            key: key.clone(),
+        })?;
+    } else {
+        let db = StateDb::open(&config.migrate_state_db()?)?;
+        db.set_device_verified_key(id, Some(key))?;
+    }
+
+    println!("Marked the key of {} verified:", device);
+    print_fingerprint(&fingerprint);
+    Ok(())
+}
+
+/// The ID of a device with a paired key, given its ID or name
+fn resolve_device<'a>(
+    device: &str,
+    keys: &'a BTreeMap<String, String>,
+    records: &BTreeMap<String, DeviceRecord>,
+) -> Result<&'a str> {
+    if let Some((id, _)) = keys.get_key_value(device) {
+        return Ok(id);
+    }
+    let named: Vec<&str> = records
+        .values()
+        .filter(|r| r.name.as_deref() == Some(device))
+        .filter_map(|r| keys.get_key_value(&r.id).map(|(id, _)| id.as_str()))
+        .collect();
+    match named.as_slice() {
+        [found] => Ok(found),
+        [] => bail!("No paired key for device {}", device),
+        _ => bail!("Several devices are named {}, use the device ID", device),
+    }
+}
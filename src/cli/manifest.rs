@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::config::Config;
+use crate::state::StateDb;
+use crate::sync::{ProfileManifest, StateExport};
+
+/// Check a profile manifest and show where the synced state has drifted
+/// from it, without changing anything
+pub fn check_manifest(config_path: &Path, file: Option<&Path>) -> Result<()> {
+    let config = Config::load(config_path).unwrap_or_default();
+    let path = file
+        .map(Path::to_path_buf)
+        .or(config.paths.manifest.clone())
+        .context("No manifest configured; set paths.manifest or pass --file")?;
+
+    let manifest = ProfileManifest::load(&path)?;
+    println!(
+        "{}: {} extensions, {} prefs, {} containers",
+        path.display(),
+        manifest.extensions.len(),
+        manifest.prefs.len(),
+        manifest.containers.len()
+    );
+
+    let state_path = config.migrate_state_db()?;
+    if !state_path.exists() {
+        println!("No synced state to compare (state database not initialized).");
+        return Ok(());
+    }
+
+    let db = StateDb::open(&state_path)?;
+    let drift = manifest.drift(&StateExport::from_db(&db, &config.device.id)?);
+    if drift.is_empty() {
+        println!("Synced state matches the manifest.");
+        return Ok(());
+    }
+
+    println!("The daemon will reconcile on its next sync (every 30 seconds):");
+    for event in drift {
+        println!("  {}", event.describe());
+    }
+    Ok(())
+}
@@ -1,13 +1,49 @@
+mod archive;
+mod completions;
 mod devices;
+mod diff;
+mod divergences;
+mod docs;
+mod export;
 mod extension;
+mod gc;
 mod ipc;
+mod keys;
+mod manifest;
+mod net;
 mod pair;
+mod pause;
+mod queue;
+mod read_later;
+mod recover;
+mod replay;
+mod report;
+mod restore;
 mod send;
 mod status;
 
-pub use devices::list_devices;
-pub use extension::{install_extension, list_extensions, uninstall_extension};
-pub use ipc::{is_daemon_running, send_command};
+pub use archive::{create_archive, restore_archive};
+pub use completions::{COMPLETE_VAR, device_candidates, extension_candidates, print_completions};
+pub use devices::{label_device, list_devices, rename_device, unbind_device};
+pub use diff::diff_history;
+pub use divergences::{list_divergences, resolve_divergence};
+pub use docs::write_man_pages;
+pub use export::{export_state, import_state};
+pub use extension::{
+    GitOptions, import_extension_list, install_extension, list_extensions, uninstall_extension,
+};
+pub use gc::collect_garbage;
+pub use ipc::{IpcClient, is_daemon_running, send_command, use_config};
+pub use keys::{show_fingerprints, verify_key};
+pub use manifest::check_manifest;
+pub use net::list_peers;
 pub use pair::pair_device;
-pub use send::send_tab;
+pub use pause::{pause_sync, resume_sync};
+pub use queue::{apply_queue, discard_queue, list_queue};
+pub use read_later::{list_reading_list, read_later, remove_from_reading_list};
+pub use recover::{recover_key, show_recovery_code};
+pub use replay::replay_events;
+pub use report::show_report;
+pub use restore::restore_file;
+pub use send::{list_tabs, send_tab};
 pub use status::show_status;
@@ -0,0 +1,12 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+
+use super::ipc;
+
+/// List connected peers; verbose adds how each connection is carried and
+/// secured, and whether it maps to a paired device
+pub fn list_peers(verbose: bool) -> Result<()> {
+    println!("{}", ipc::send_command(Command::Peers { verbose })?);
+    Ok(())
+}
@@ -7,6 +7,7 @@ use std::time::Duration;
 use crate::config::Config;
 use crate::crypto::{KeyPair, public_key_to_hex};
 use crate::daemon::ApiTokenManager;
+use crate::net::NearbyDevice;
 
 const API_BASE: &str = "http://127.0.0.1";
 
@@ -47,12 +48,18 @@ struct PendingRequest {
     public_key_fingerprint: String,
 }
 
+#[derive(Deserialize)]
+struct NearbyDevicesResponse {
+    enabled: bool,
+    devices: Vec<NearbyDevice>,
+}
+
 #[derive(Serialize)]
 struct RespondRequest {
     accept: bool,
 }
 
-pub async fn pair_device(config_path: &Path, code: Option<&str>) -> Result<()> {
+pub async fn pair_device(config_path: &Path, code: Option<&str>, nearby: bool) -> Result<()> {
     if !config_path.exists() {
         println!("Not initialized. Run: wolfpack init");
         return Ok(());
@@ -71,12 +78,64 @@ pub async fn pair_device(config_path: &Path, code: Option<&str>) -> Result<()> {
         .timeout(Duration::from_secs(30))
         .build()?;
 
+    if nearby {
+        return list_nearby(&client, port, token_manager.token()).await;
+    }
+
     match code {
         Some(code) => join_session(&client, port, token_manager.token(), &config, code).await,
         None => initiate_session(&client, port, token_manager.token()).await,
     }
 }
 
+async fn fetch_nearby(
+    client: &reqwest::Client,
+    port: u16,
+    token: &str,
+) -> Result<NearbyDevicesResponse> {
+    Ok(client
+        .get(format!("{API_BASE}:{port}/pair/nearby"))
+        .header("X-Wolfpack-Token", token)
+        .send()
+        .await
+        .context("Failed to connect to daemon. Is it running?")?
+        .error_for_status()
+        .context("Failed to list nearby devices")?
+        .json()
+        .await?)
+}
+
+async fn list_nearby(client: &reqwest::Client, port: u16, token: &str) -> Result<()> {
+    let resp = fetch_nearby(client, port, token).await?;
+    if !resp.enabled {
+        println!(
+            "Local discovery is off. Set enable_mdns = true under [sync] to find nearby devices."
+        );
+        return Ok(());
+    }
+    if resp.devices.is_empty() {
+        println!("No devices found nearby.");
+        return Ok(());
+    }
+
+    println!("Devices nearby:");
+    for device in &resp.devices {
+        let status = if device.pairing {
+            "  (ready to pair)"
+        } else {
+            ""
+        };
+        println!("  {} ({}){}", device.device_name, device.device_id, status);
+    }
+    if resp.devices.iter().any(|d| d.pairing) {
+        println!();
+        println!("Join with the code shown on that device:");
+        println!("  wolfpack pair --code CODE");
+    }
+
+    Ok(())
+}
+
 #[allow(clippy::too_many_lines)] // Complete user interaction flow
 async fn initiate_session(client: &reqwest::Client, port: u16, token: &str) -> Result<()> {
     println!("Starting pairing session...");
@@ -162,17 +221,23 @@ async fn join_session(
     code: &str,
 ) -> Result<()> {
     // Load our keypair
-    let keys_dir = dirs::data_dir()
-        .unwrap_or_else(|| std::path::PathBuf::from("."))
-        .join("wolfpack")
-        .join("keys");
-    std::fs::create_dir_all(&keys_dir)?;
-
-    let keypair_path = keys_dir.join("local.key");
-    let keypair = KeyPair::load_or_generate(&keypair_path)?;
+    std::fs::create_dir_all(Config::keys_dir())?;
+    let keypair = KeyPair::load_or_generate(&Config::local_key_path())?;
     let public_key = public_key_to_hex(&keypair.public_key());
 
-    println!("Joining pairing session...");
+    // Name the device being joined when discovery can tell which it is
+    let open: Vec<NearbyDevice> = fetch_nearby(client, port, token)
+        .await
+        .map(|resp| resp.devices.into_iter().filter(|d| d.pairing).collect())
+        .unwrap_or_default();
+    match open.as_slice() {
+        [device] => println!("Joining pairing session on {}...", device.device_name),
+        [] => println!("Joining pairing session..."),
+        devices => {
+            let names: Vec<&str> = devices.iter().map(|d| d.device_name.as_str()).collect();
+            println!("Joining pairing session (open on {})...", names.join(", "));
+        }
+    }
     println!();
 
     let req = JoinRequest {
@@ -182,17 +247,24 @@ async fn join_session(
         public_key,
     };
 
-    let resp: JoinResponse = client
+    let resp = client
         .post(format!("{API_BASE}:{port}/pair/join"))
         .header("X-Wolfpack-Token", token)
         .json(&req)
         .send()
         .await
-        .context("Failed to connect to daemon. Is it running?")?
-        .error_for_status()
-        .context("Failed to join pairing session")?
-        .json()
-        .await?;
+        .context("Failed to connect to daemon. Is it running?")?;
+    // A wrong or expired code comes back as 401, saying which it was
+    let resp: JoinResponse = if resp.status() == reqwest::StatusCode::UNAUTHORIZED {
+        resp.json()
+            .await
+            .context("Failed to join pairing session")?
+    } else {
+        resp.error_for_status()
+            .context("Failed to join pairing session")?
+            .json()
+            .await?
+    };
 
     match resp.status.as_str() {
         "accepted" => {
@@ -0,0 +1,32 @@
+use anyhow::Result;
+
+use crate::events::SyncCategory;
+use crate::ipc::Command;
+use crate::sync::parse_duration;
+
+use super::ipc;
+
+pub fn pause_sync(category: Option<&str>, duration: Option<&str>) -> Result<()> {
+    // Check both here so mistakes are reported without the daemon
+    let command = Command::Pause {
+        category: parse_category(category)?,
+        seconds: duration
+            .map(parse_duration)
+            .transpose()?
+            .map(|duration| duration.num_seconds()),
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
+
+pub fn resume_sync(category: Option<&str>) -> Result<()> {
+    let command = Command::Resume {
+        category: parse_category(category)?,
+    };
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
+
+fn parse_category(category: Option<&str>) -> Result<Option<SyncCategory>> {
+    category.map(str::parse).transpose()
+}
@@ -0,0 +1,22 @@
+use anyhow::Result;
+
+use crate::ipc::{Command, QueueAction};
+
+use super::ipc;
+
+pub fn list_queue() -> Result<()> {
+    run_queue_command(QueueAction::List)
+}
+
+pub fn apply_queue() -> Result<()> {
+    run_queue_command(QueueAction::Apply)
+}
+
+pub fn discard_queue() -> Result<()> {
+    run_queue_command(QueueAction::Discard)
+}
+
+fn run_queue_command(action: QueueAction) -> Result<()> {
+    println!("{}", ipc::send_command(Command::Queue { action })?);
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+
+use super::ipc;
+
+pub fn read_later(url: &str, title: Option<&str>) -> Result<()> {
+    run_reading_list_command(Command::ReadingListAdd {
+        url: url.to_string(),
+        title: title.map(String::from),
+    })
+}
+
+pub fn remove_from_reading_list(url: &str) -> Result<()> {
+    run_reading_list_command(Command::ReadingListRemove {
+        url: url.to_string(),
+    })
+}
+
+pub fn list_reading_list() -> Result<()> {
+    run_reading_list_command(Command::ReadingList)
+}
+
+fn run_reading_list_command(command: Command) -> Result<()> {
+    println!("{}", ipc::send_command(command)?);
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use anyhow::{Result, bail};
+
+use super::ipc;
+use crate::config::Config;
+use crate::crypto::{KeyPair, recovery_code, secret_from_recovery_code};
+
+/// Words per line when printing a recovery code
+const WORDS_PER_LINE: usize = 6;
+
+/// Print the recovery code for this device's key, generating the key first
+/// if there isn't one yet
+pub fn show_recovery_code() -> Result<()> {
+    let keypair = KeyPair::load_or_generate(&Config::local_key_path())?;
+    let code = recovery_code(&keypair.secret_key());
+    let words: Vec<&str> = code.split(' ').collect();
+
+    println!("Recovery code (write it down and keep it somewhere safe):");
+    for line in words.chunks(WORDS_PER_LINE) {
+        println!("    {}", line.join(" "));
+    }
+    println!("Restore this device's key with: wolfpack recover --code \"...\"");
+    Ok(())
+}
+
+/// Rebuild this device's key from its recovery code
+pub fn recover_key(code: &str, force: bool) -> Result<()> {
+    if ipc::is_daemon_running() {
+        bail!("Stop the daemon before recovering the device key");
+    }
+
+    let keypair = KeyPair::from_bytes(&secret_from_recovery_code(code)?);
+    let path = Config::local_key_path();
+    if path.exists() {
+        let current = KeyPair::load(&path).ok();
+        if current.is_some_and(|current| current.public_key() == keypair.public_key()) {
+            println!("The key at {} already matches this code", path.display());
+            return Ok(());
+        }
+        if !force {
+            bail!(
+                "{} holds a different key; pass --force to replace it",
+                path.display()
+            );
+        }
+    }
+
+    keypair.save(&path)?;
+    println!("Recovered device key to {}", path.display());
+
+    // The code only covers the encryption key: a lost p2p identity comes
+    // back as a new peer ID, which peers without this key on file won't
+    // take as this device until they forget the old one
+    if !Config::keys_dir().join("p2p.key").exists() {
+        let device = Config::load(&Config::default_path())
+            .map_or_else(|_| "DEVICE".to_string(), |config| config.device.name);
+        println!("This device will have a new peer ID when the daemon starts.");
+        println!(
+            "Devices that still know it by its old one need to run: wolfpack devices unbind {}",
+            device
+        );
+    }
+    Ok(())
+}
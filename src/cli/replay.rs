@@ -0,0 +1,16 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+use crate::sync::ReplayPoint;
+
+use super::ipc;
+
+/// Rebuild the daemon's state from the event log, up to a point when given
+pub fn replay_events(until: Option<&str>) -> Result<()> {
+    // Check the point here so mistakes are reported without the daemon
+    let until = until
+        .map(|until| until.parse::<ReplayPoint>().map(|until| until.to_arg()))
+        .transpose()?;
+    println!("{}", ipc::send_command(Command::Replay { until })?);
+    Ok(())
+}
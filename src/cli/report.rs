@@ -0,0 +1,11 @@
+use anyhow::Result;
+
+use crate::ipc::Command;
+
+use super::ipc;
+
+/// Show what's taking up space in the sync dir and how fast it's growing
+pub fn show_report() -> Result<()> {
+    println!("{}", ipc::send_command(Command::Report)?);
+    Ok(())
+}
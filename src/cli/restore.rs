@@ -0,0 +1,36 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::Config;
+use crate::profile::is_browser_running;
+
+/// Restore a profile file from a wolfpack backup, or list its backups
+pub fn restore_file(
+    file: &str,
+    timestamp: Option<&str>,
+    list: bool,
+    config_path: &Path,
+) -> Result<()> {
+    let config = Config::load(config_path).unwrap_or_default();
+    let backups = config.profile_backups();
+
+    if list {
+        let timestamps = backups.list(file)?;
+        if timestamps.is_empty() {
+            println!("No backups of {} in {}", file, backups.dir().display());
+        }
+        for timestamp in timestamps {
+            println!("{}", timestamp);
+        }
+        return Ok(());
+    }
+
+    let profile_dir = config.profile_dir()?;
+    if is_browser_running(&profile_dir) {
+        anyhow::bail!("Close the browser before restoring profile files");
+    }
+
+    let restored = backups.restore(&profile_dir, file, timestamp)?;
+    println!("Restored {} from backup {}", file, restored);
+    Ok(())
+}
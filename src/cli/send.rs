@@ -1,16 +1,78 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use std::io::{IsTerminal, Read};
+use std::process::Command;
+
+use crate::ipc::Command as IpcCommand;
+use crate::sync::normalize_tab_url;
 
 use super::ipc;
 
-pub fn send_tab(url: &str, to_device: &str) -> Result<()> {
-    let command = format!("send {} {}", to_device, url);
-    let response = ipc::send_command(&command)?;
+/// Clipboard readers tried in order (Wayland, then X)
+const CLIPBOARD_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
 
-    if response.starts_with("OK:") {
-        println!("Tab sent to {}", to_device);
-    } else {
-        anyhow::bail!("{}", response);
+/// Send a URL, or the one on stdin or the clipboard, to another device
+pub fn send_tab(url: Option<&str>, to_device: &str, from_clipboard: bool) -> Result<()> {
+    let url = match url {
+        _ if from_clipboard => read_clipboard()?,
+        Some(url) if url != "-" => url.to_string(),
+        _ => read_stdin()?,
+    };
+    // The daemon checks it too, but this reports mistakes without one
+    let url = normalize_tab_url(&url)?;
+
+    let command = IpcCommand::Send {
+        device: to_device.to_string(),
+        url: url.clone(),
+        title: None,
+    };
+    println!("{}: {}", ipc::send_command(command)?, url);
+    Ok(())
+}
+
+fn read_stdin() -> Result<String> {
+    let mut stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        bail!("No URL given; pass one, pipe it in, or use --from-clipboard");
+    }
+
+    let mut input = String::new();
+    stdin
+        .read_to_string(&mut input)
+        .context("Failed to read URL from stdin")?;
+    first_line(&input).context("No URL on stdin")
+}
+
+fn read_clipboard() -> Result<String> {
+    for command in CLIPBOARD_COMMANDS {
+        let Ok(output) = Command::new(command[0]).args(&command[1..]).output() else {
+            continue;
+        };
+        if output.status.success() {
+            let text = String::from_utf8_lossy(&output.stdout);
+            return first_line(&text).context("The clipboard is empty");
+        }
     }
+    bail!("Couldn't read the clipboard (install wl-clipboard, xclip, or xsel)")
+}
 
+fn first_line(text: &str) -> Option<String> {
+    text.lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .map(String::from)
+}
+
+/// List tabs waiting to be opened here, or tabs this device sent
+pub fn list_tabs(sent: bool) -> Result<()> {
+    let command = if sent {
+        IpcCommand::SentTabs
+    } else {
+        IpcCommand::Tabs
+    };
+    println!("{}", ipc::send_command(command)?);
     Ok(())
 }
@@ -1,21 +1,31 @@
 use anyhow::Result;
 
-use super::ipc;
+use super::ipc::{self, IpcClient};
+use crate::ipc::Command;
+use crate::redact;
 
-pub fn show_status() -> Result<()> {
+pub fn show_status(history: bool) -> Result<()> {
     if !ipc::is_daemon_running() {
         println!("Daemon is not running");
         println!("Start with: wolfpack daemon");
         return Ok(());
     }
 
-    let response = ipc::send_command("status")?;
-    println!("{}", response);
+    // All in flight at once on one connection
+    let mut client = IpcClient::connect()?;
+    let status = client.send(Command::Status)?;
+    let tabs = client.send(Command::Tabs)?;
+    let sessions = history
+        .then(|| client.send(Command::SyncHistory))
+        .transpose()?;
 
-    // Also show pending tabs
-    let tabs_response = ipc::send_command("tabs")?;
+    println!("{}", redact::text(&client.response(status)?.to_string()));
     println!("\nPending tabs:");
-    println!("{}", tabs_response);
+    println!("{}", redact::text(&client.response(tabs)?.to_string()));
+    if let Some(sessions) = sessions {
+        println!("\nSync history:");
+        println!("{}", redact::text(&client.response(sessions)?.to_string()));
+    }
 
     Ok(())
 }
@@ -1,7 +1,21 @@
 use anyhow::{Context, Result};
 use prefer::{ConfigValue, FromValue};
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
+use crate::crypto::KeyEpoch;
+use crate::events::SyncCategory;
+use crate::profile::{PrefPatterns, ProfileBackups, SiteFilter, should_include};
+use crate::state::StateDb;
+
+/// Settings shared across the pack with `PackConfigSet` events, so changing
+/// one on any device changes it on every device
+pub const PACK_SETTINGS: [&str; 3] = [
+    "prefs.whitelist",
+    "sync.disabled_categories",
+    "sync.key_epoch",
+];
+
 #[derive(Debug, Clone)]
 pub struct Config {
     pub device: DeviceConfig,
@@ -9,6 +23,16 @@ pub struct Config {
     pub sync: SyncConfig,
     pub api: ApiConfig,
     pub prefs: PrefsConfig,
+    pub backups: BackupConfig,
+    pub autofill: AutofillConfig,
+    pub permissions: PermissionsConfig,
+    pub certificates: CertificatesConfig,
+    pub handlers: HandlersConfig,
+    pub notifications: NotificationsConfig,
+    pub net: NetConfig,
+    pub limits: LimitsConfig,
+    pub policies: PoliciesConfig,
+    pub logging: LoggingConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -21,9 +45,17 @@ pub struct DeviceConfig {
 pub struct PathConfig {
     pub profile: Option<PathBuf>,
     pub sync_dir: PathBuf,
+    /// Declarative `profile.toml` the daemon reconciles the profile toward
+    pub manifest: Option<PathBuf>,
+    /// Directory of hook scripts run on sync events
+    pub hooks_dir: PathBuf,
+    /// SQLite database of materialized state, shared by the daemon and CLI
+    pub state_db: PathBuf,
+    /// Unix socket the daemon listens on for CLI and tray commands
+    pub ipc_socket: Option<PathBuf>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone)]
 pub struct SyncConfig {
     /// Port for P2P connections (0 for random)
     pub listen_port: Option<u16>,
@@ -31,8 +63,62 @@ pub struct SyncConfig {
     pub enable_mdns: bool,
     /// Enable DHT for internet-wide discovery (default: false)
     pub enable_dht: bool,
+    /// Only listen on and connect to private network addresses, with relay
+    /// and DHT disabled (default: false)
+    pub lan_only: bool,
+    /// Disconnect peers that don't turn out to be paired devices (default: false)
+    pub strict_peers: bool,
     /// Bootstrap peers for DHT (multiaddr format)
     pub bootstrap_peers: Vec<String>,
+    /// Circuit relays to be reachable through when behind NAT, each a
+    /// multiaddr ending in `/p2p/<peer id>`. The fastest reachable one is
+    /// used, failing over to the next when it goes down.
+    pub relays: Vec<String>,
+    /// Categories never synced, on any device in the pack
+    pub disabled_categories: Vec<String>,
+    /// Hold profile writes until approved with `wolfpack queue apply` (default: false)
+    pub require_approval: bool,
+    /// Drop unopened tabs sent to a device not seen for this many days,
+    /// 0 keeps them indefinitely (default: 14)
+    pub tab_expiry_days: u64,
+    /// Forget sent tabs that were delivered or opened after this many days,
+    /// 0 keeps them indefinitely (default: 30)
+    pub tab_retention_days: u64,
+    /// Show the same URL sent again by the same device within this many
+    /// minutes as one pending tab, 0 shows each (default: 60)
+    pub tab_dedup_minutes: u64,
+    /// Pass other devices' events on to peers that can't reach them
+    /// (default: true)
+    pub forward_events: bool,
+    /// How events are encoded in the event files this device writes, `json`
+    /// or `cbor`; only use `cbor` once every device reads it (default: json)
+    pub event_codec: String,
+    /// How the group secret for the event files the pack writes is derived,
+    /// 1 (XOR) or 2 (HKDF); shared with the pack, and only raised to 2 once
+    /// every device reads it (default: 1)
+    pub key_epoch: u64,
+}
+
+impl Default for SyncConfig {
+    fn default() -> Self {
+        Self {
+            listen_port: None,
+            enable_mdns: false,
+            enable_dht: false,
+            lan_only: false,
+            strict_peers: false,
+            bootstrap_peers: Vec::new(),
+            relays: Vec::new(),
+            disabled_categories: Vec::new(),
+            require_approval: false,
+            tab_expiry_days: 14,
+            tab_retention_days: 30,
+            tab_dedup_minutes: 60,
+            forward_events: true,
+            event_codec: "json".to_string(),
+            key_epoch: 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -50,6 +136,441 @@ impl Default for ApiConfig {
 #[derive(Debug, Clone, Default)]
 pub struct PrefsConfig {
     pub whitelist: Vec<String>,
+    /// Prefs kept to this device even when they match the whitelist, like
+    /// `network.proxy.*`: never scanned, never written to the profile
+    pub local_only: Vec<String>,
+}
+
+impl PrefsConfig {
+    pub fn is_local_only(&self, key: &str) -> bool {
+        should_include(key, &self.local_only)
+    }
+
+    /// Patterns that don't parse, and so are left out when matching
+    pub fn pattern_errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        for (key, patterns) in [
+            ("prefs.whitelist", &self.whitelist),
+            ("prefs.local_only", &self.local_only),
+        ] {
+            for pattern in patterns {
+                if let Err(e) = PrefPatterns::parse(std::slice::from_ref(pattern)) {
+                    errors.push(format!("{} entry: {:#}", key, e));
+                }
+            }
+        }
+        errors
+    }
+
+    fn to_toml_section(&self) -> String {
+        let mut content = "[prefs]\n".to_string();
+        for (key, patterns) in [
+            ("whitelist", &self.whitelist),
+            ("local_only", &self.local_only),
+        ] {
+            if !patterns.is_empty() {
+                content.push_str(&format!(
+                    "{} = [{}]\n",
+                    key,
+                    patterns
+                        .iter()
+                        .map(|p| format!("\"{}\"", p))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ));
+            }
+        }
+        content
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupConfig {
+    /// Directory for backups of overwritten profile files
+    pub dir: PathBuf,
+    /// Backups kept per profile file, 0 disables backups (default: 10)
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: Config::default_backup_dir(),
+            keep: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct AutofillConfig {
+    /// Sync saved form-fill addresses (default: false)
+    pub addresses: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PermissionsConfig {
+    /// Only sync permissions for these domains (empty: all domains)
+    pub allow: Vec<String>,
+    /// Never sync permissions for these domains
+    pub block: Vec<String>,
+}
+
+impl PermissionsConfig {
+    pub fn site_filter(&self) -> SiteFilter {
+        SiteFilter {
+            allow: self.allow.clone(),
+            block: self.block.clone(),
+        }
+    }
+
+    fn to_toml_section(&self) -> String {
+        let list = |domains: &[String]| {
+            domains
+                .iter()
+                .map(|d| format!("\"{}\"", d))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut content = String::from("[permissions]\n");
+        if !self.allow.is_empty() {
+            content.push_str(&format!("allow = [{}]\n", list(&self.allow)));
+        }
+        if !self.block.is_empty() {
+            content.push_str(&format!("block = [{}]\n", list(&self.block)));
+        }
+        content
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CertificatesConfig {
+    /// Sync certificate exceptions accepted on any device (default: false)
+    pub overrides: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HandlersConfig {
+    /// Local application to use on this device in place of one another
+    /// device's protocol handler opens, by the application's name (its
+    /// file name without extension, lowercased) or full path
+    pub translate: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NotificationsConfig {
+    /// Endpoint the daemon POSTs JSON notifications to (e.g. an ntfy topic)
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct NetConfig {
+    /// SOCKS5 proxy (`host:port`) for P2P dials and outgoing HTTP, e.g. Tor
+    pub socks5: Option<String>,
+    /// This device's Tor onion endpoint (`<address>.onion:<port>`), published
+    /// so other devices can dial it through their proxy
+    pub onion_address: Option<String>,
+    /// Offer zstd-compressed sync messages to peers (default: true)
+    pub compression: bool,
+    /// P2P transports to listen and dial on, `tcp` and/or `quic`
+    /// (default: both)
+    pub transports: Vec<String>,
+    /// IPs to listen on (default: `0.0.0.0` and `::`)
+    pub listen_addresses: Vec<String>,
+    /// Listen only on these network interfaces instead
+    pub interfaces: Vec<String>,
+    /// Never listen on these network interfaces
+    pub disabled_interfaces: Vec<String>,
+    /// Find pack devices with UDP broadcast beacons, for networks that
+    /// filter mDNS (default: false)
+    pub broadcast_discovery: bool,
+    /// UDP port beacons are sent to and received on (default: 9779)
+    pub broadcast_port: u16,
+    /// Also send beacons to every address in the local /24 subnets, for
+    /// networks that filter broadcast too (default: false)
+    pub broadcast_scan: bool,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            socks5: None,
+            onion_address: None,
+            compression: true,
+            transports: vec!["tcp".to_string(), "quic".to_string()],
+            listen_addresses: vec!["0.0.0.0".to_string(), "::".to_string()],
+            interfaces: Vec::new(),
+            disabled_interfaces: Vec::new(),
+            broadcast_discovery: false,
+            broadcast_port: 9779,
+            broadcast_scan: false,
+        }
+    }
+}
+
+/// Limits on what a device may add to the sync dir, so one misbehaving
+/// device can't balloon everyone's storage. 0 disables a limit.
+#[derive(Debug, Clone)]
+pub struct LimitsConfig {
+    /// Largest event, not counting XPI data, in KiB (default: 1024)
+    pub max_event_kb: u64,
+    /// Largest compressed XPI carried by an event, in MiB (default: 64)
+    pub max_xpi_mb: u64,
+    /// Most events written to one event file or accepted in one push (default: 10000)
+    pub max_events_per_file: usize,
+    /// Most a single device's event files may take up in the sync dir, in MiB
+    /// (default: 1024)
+    pub device_quota_mb: u64,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_event_kb: 1024,
+            max_xpi_mb: 64,
+            max_events_per_file: 10_000,
+            device_quota_mb: 1024,
+        }
+    }
+}
+
+/// Extensions and prefs managed through the browser's enterprise
+/// `policies.json` instead of profile files
+#[derive(Debug, Clone)]
+pub struct PoliciesConfig {
+    /// Write `policies.json` instead of sideloading XPIs and writing user.js
+    /// (default: false)
+    pub enabled: bool,
+    /// The browser's `policies.json`, which the daemon must be able to write
+    pub path: PathBuf,
+    /// Directory the XPIs the policies install from are kept in
+    pub xpi_dir: PathBuf,
+}
+
+impl Default for PoliciesConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: Config::default_policies_path(),
+            xpi_dir: Config::default_policy_xpi_dir(),
+        }
+    }
+}
+
+/// What's kept out of logs and status output
+#[derive(Debug, Clone, Default)]
+pub struct LoggingConfig {
+    /// Scrub the API token and public keys from logs and status output
+    /// (default: false)
+    pub redact: bool,
+    /// Also cut URLs down to their origin (default: false)
+    pub redact_tab_urls: bool,
+    /// Prefs whose values are hidden, as patterns like `prefs.whitelist`
+    pub sensitive_prefs: Vec<String>,
+}
+
+impl LoggingConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = format!(
+            "[logging]\nredact = {}\nredact_tab_urls = {}\n",
+            self.redact, self.redact_tab_urls
+        );
+        if !self.sensitive_prefs.is_empty() {
+            content.push_str(&format!(
+                "sensitive_prefs = [{}]\n",
+                self.sensitive_prefs
+                    .iter()
+                    .map(|p| format!("\"{}\"", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        content
+    }
+}
+
+impl PoliciesConfig {
+    fn to_toml_section(&self) -> String {
+        format!(
+            "[policies]\nenabled = {}\npath = \"{}\"\nxpi_dir = \"{}\"\n",
+            self.enabled,
+            self.path.display(),
+            self.xpi_dir.display()
+        )
+    }
+}
+
+impl LimitsConfig {
+    pub fn max_event_bytes(&self) -> u64 {
+        self.max_event_kb * 1024
+    }
+
+    pub fn max_xpi_bytes(&self) -> u64 {
+        self.max_xpi_mb * 1024 * 1024
+    }
+
+    pub fn device_quota_bytes(&self) -> u64 {
+        self.device_quota_mb * 1024 * 1024
+    }
+
+    fn to_toml_section(&self) -> String {
+        format!(
+            "[limits]
+max_event_kb = {}
+max_xpi_mb = {}
+max_events_per_file = {}
+             device_quota_mb = {}
+",
+            self.max_event_kb, self.max_xpi_mb, self.max_events_per_file, self.device_quota_mb
+        )
+    }
+}
+
+impl PathConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[paths]\n");
+        if let Some(ref profile) = self.profile {
+            content.push_str(&format!("profile = \"{}\"\n", profile.display()));
+        }
+        content.push_str(&format!("sync_dir = \"{}\"\n", self.sync_dir.display()));
+        if let Some(ref manifest) = self.manifest {
+            content.push_str(&format!("manifest = \"{}\"\n", manifest.display()));
+        }
+        content.push_str(&format!("hooks_dir = \"{}\"\n", self.hooks_dir.display()));
+        content.push_str(&format!("state_db = \"{}\"\n", self.state_db.display()));
+        if let Some(ref ipc_socket) = self.ipc_socket {
+            content.push_str(&format!("ipc_socket = \"{}\"\n", ipc_socket.display()));
+        }
+        content
+    }
+}
+
+impl NotificationsConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[notifications]\n");
+        if let Some(ref url) = self.webhook_url {
+            content.push_str(&format!("webhook_url = \"{}\"\n", url));
+        }
+        content
+    }
+}
+
+impl NetConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[net]\n");
+        if let Some(ref proxy) = self.socks5 {
+            content.push_str(&format!("socks5 = \"{}\"\n", proxy));
+        }
+        if let Some(ref onion) = self.onion_address {
+            content.push_str(&format!("onion_address = \"{}\"\n", onion));
+        }
+        content.push_str(&format!("compression = {}\n", self.compression));
+        content.push_str(&format!("transports = {}\n", toml_list(&self.transports)));
+        content.push_str(&format!(
+            "listen_addresses = {}\n",
+            toml_list(&self.listen_addresses)
+        ));
+        if !self.interfaces.is_empty() {
+            content.push_str(&format!("interfaces = {}\n", toml_list(&self.interfaces)));
+        }
+        if !self.disabled_interfaces.is_empty() {
+            content.push_str(&format!(
+                "disabled_interfaces = {}\n",
+                toml_list(&self.disabled_interfaces)
+            ));
+        }
+        content.push_str(&format!(
+            "broadcast_discovery = {}\n",
+            self.broadcast_discovery
+        ));
+        content.push_str(&format!("broadcast_port = {}\n", self.broadcast_port));
+        content.push_str(&format!("broadcast_scan = {}\n", self.broadcast_scan));
+        content
+    }
+}
+
+impl CertificatesConfig {
+    fn to_toml_section(&self) -> String {
+        format!("[certificates]\noverrides = {}\n", self.overrides)
+    }
+}
+
+impl HandlersConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[handlers]\n");
+        if !self.translate.is_empty() {
+            let quote = |s: &String| toml::Value::String(s.clone()).to_string();
+            content.push_str("\n[handlers.translate]\n");
+            for (from, to) in &self.translate {
+                content.push_str(&format!("{} = {}\n", quote(from), quote(to)));
+            }
+        }
+        content
+    }
+}
+
+impl AutofillConfig {
+    fn to_toml_section(&self) -> String {
+        format!("[autofill]\naddresses = {}\n", self.addresses)
+    }
+}
+
+impl BackupConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[backups]\n");
+        content.push_str(&format!("dir = \"{}\"\n", self.dir.display()));
+        content.push_str(&format!("keep = {}\n", self.keep));
+        content
+    }
+}
+
+impl SyncConfig {
+    fn to_toml_section(&self) -> String {
+        let mut content = String::from("[sync]\n");
+        if let Some(port) = self.listen_port {
+            content.push_str(&format!("listen_port = {}\n", port));
+        }
+        content.push_str(&format!("enable_mdns = {}\n", self.enable_mdns));
+        content.push_str(&format!("enable_dht = {}\n", self.enable_dht));
+        content.push_str(&format!("lan_only = {}\n", self.lan_only));
+        content.push_str(&format!("strict_peers = {}\n", self.strict_peers));
+        if !self.bootstrap_peers.is_empty() {
+            content.push_str(&format!(
+                "bootstrap_peers = [{}]\n",
+                self.bootstrap_peers
+                    .iter()
+                    .map(|p| format!("\"{}\"", p))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if !self.relays.is_empty() {
+            content.push_str(&format!(
+                "relays = [{}]\n",
+                self.relays
+                    .iter()
+                    .map(|r| format!("\"{}\"", r))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+        }
+        if !self.disabled_categories.is_empty() {
+            content.push_str(&format!(
+                "disabled_categories = {}\n",
+                toml_list(&self.disabled_categories)
+            ));
+        }
+        content.push_str(&format!("require_approval = {}\n", self.require_approval));
+        content.push_str(&format!("tab_expiry_days = {}\n", self.tab_expiry_days));
+        content.push_str(&format!(
+            "tab_retention_days = {}\n",
+            self.tab_retention_days
+        ));
+        content.push_str(&format!("tab_dedup_minutes = {}\n", self.tab_dedup_minutes));
+        content.push_str(&format!("forward_events = {}\n", self.forward_events));
+        content.push_str(&format!("event_codec = \"{}\"\n", self.event_codec));
+        content.push_str(&format!("key_epoch = {}\n", self.key_epoch));
+        content
+    }
 }
 
 // FromValue implementations for prefer integration
@@ -67,25 +588,49 @@ impl FromValue for Config {
         Ok(Self {
             device: DeviceConfig::from_value(obj.get("device").unwrap_or(&ConfigValue::Null))?,
             paths: PathConfig::from_value(obj.get("paths").unwrap_or(&ConfigValue::Null))?,
-            sync: obj
-                .get("sync")
-                .map(SyncConfig::from_value)
-                .transpose()?
-                .unwrap_or_default(),
-            api: obj
-                .get("api")
-                .map(ApiConfig::from_value)
-                .transpose()?
-                .unwrap_or_default(),
-            prefs: obj
-                .get("prefs")
-                .map(PrefsConfig::from_value)
-                .transpose()?
-                .unwrap_or_default(),
+            sync: section(obj, "sync")?,
+            api: section(obj, "api")?,
+            prefs: section(obj, "prefs")?,
+            backups: section(obj, "backups")?,
+            autofill: section(obj, "autofill")?,
+            permissions: section(obj, "permissions")?,
+            certificates: section(obj, "certificates")?,
+            handlers: section(obj, "handlers")?,
+            notifications: section(obj, "notifications")?,
+            net: section(obj, "net")?,
+            limits: section(obj, "limits")?,
+            policies: section(obj, "policies")?,
+            logging: section(obj, "logging")?,
         })
     }
 }
 
+/// Parse an optional config section, using its defaults when missing
+fn section<T: FromValue + Default>(
+    obj: &std::collections::HashMap<String, ConfigValue>,
+    key: &str,
+) -> prefer::Result<T> {
+    obj.get(key)
+        .map(T::from_value)
+        .transpose()
+        .map(Option::unwrap_or_default)
+}
+
+/// Strings in an array value, if it is one
+fn string_list(value: Option<&ConfigValue>) -> Option<Vec<String>> {
+    value.and_then(|v| v.as_array()).map(|arr| {
+        arr.iter()
+            .filter_map(|v| v.as_str().map(String::from))
+            .collect()
+    })
+}
+
+/// A TOML array of strings
+fn toml_list(values: &[String]) -> String {
+    let quoted: Vec<String> = values.iter().map(|v| format!("\"{}\"", v)).collect();
+    format!("[{}]", quoted.join(", "))
+}
+
 impl FromValue for DeviceConfig {
     fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
         let obj = value
@@ -135,11 +680,30 @@ impl FromValue for PathConfig {
                 .and_then(|v| v.as_str())
                 .map(PathBuf::from)
                 .unwrap_or_else(Config::default_sync_dir),
+            manifest: obj
+                .get("manifest")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from),
+            hooks_dir: obj
+                .get("hooks_dir")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .unwrap_or_else(Config::default_hooks_dir),
+            state_db: obj
+                .get("state_db")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .unwrap_or_else(Config::default_state_db),
+            ipc_socket: obj
+                .get("ipc_socket")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from),
         })
     }
 }
 
 impl FromValue for SyncConfig {
+    #[allow(clippy::too_many_lines)] // One field per setting
     fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
         let obj = value
             .as_object()
@@ -162,15 +726,43 @@ impl FromValue for SyncConfig {
                 .get("enable_dht")
                 .and_then(|v| v.as_bool())
                 .unwrap_or(false),
-            bootstrap_peers: obj
-                .get("bootstrap_peers")
-                .and_then(|v| v.as_array())
-                .map(|arr| {
-                    arr.iter()
-                        .filter_map(|v| v.as_str().map(String::from))
-                        .collect()
-                })
-                .unwrap_or_default(),
+            lan_only: obj
+                .get("lan_only")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            strict_peers: obj
+                .get("strict_peers")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            bootstrap_peers: string_list(obj.get("bootstrap_peers")).unwrap_or_default(),
+            relays: string_list(obj.get("relays")).unwrap_or_default(),
+            disabled_categories: string_list(obj.get("disabled_categories")).unwrap_or_default(),
+            require_approval: obj
+                .get("require_approval")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            tab_expiry_days: obj
+                .get("tab_expiry_days")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(14),
+            tab_retention_days: obj
+                .get("tab_retention_days")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(30),
+            tab_dedup_minutes: obj
+                .get("tab_dedup_minutes")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(60),
+            forward_events: obj
+                .get("forward_events")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true),
+            event_codec: obj
+                .get("event_codec")
+                .and_then(|v| v.as_str())
+                .unwrap_or("json")
+                .to_string(),
+            key_epoch: obj.get("key_epoch").and_then(|v| v.as_u64()).unwrap_or(1),
         })
     }
 }
@@ -205,9 +797,127 @@ impl FromValue for PrefsConfig {
                 source: "expected object".into(),
             })?;
 
+        let list = |key: &str| -> Vec<String> {
+            obj.get(key)
+                .and_then(|v| v.as_array())
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Ok(Self {
+            whitelist: list("whitelist"),
+            local_only: list("local_only"),
+        })
+    }
+}
+
+impl FromValue for BackupConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "BackupConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            dir: obj
+                .get("dir")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .unwrap_or_else(Config::default_backup_dir),
+            keep: obj
+                .get("keep")
+                .and_then(|v| v.as_u64())
+                .map(|n| n as usize)
+                .unwrap_or(10),
+        })
+    }
+}
+
+impl FromValue for AutofillConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "AutofillConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            addresses: obj
+                .get("addresses")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+}
+
+impl FromValue for CertificatesConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "CertificatesConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            overrides: obj
+                .get("overrides")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+}
+
+impl FromValue for HandlersConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "HandlersConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            translate: obj
+                .get("translate")
+                .and_then(|v| v.as_object())
+                .map(|translate| {
+                    translate
+                        .iter()
+                        .filter_map(|(from, to)| Some((from.clone(), to.as_str()?.to_string())))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        })
+    }
+}
+
+impl FromValue for LoggingConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "LoggingConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        let flag = |key: &str| obj.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
         Ok(Self {
-            whitelist: obj
-                .get("whitelist")
+            redact: flag("redact"),
+            redact_tab_urls: flag("redact_tab_urls"),
+            sensitive_prefs: obj
+                .get("sensitive_prefs")
                 .and_then(|v| v.as_array())
                 .map(|arr| {
                     arr.iter()
@@ -219,6 +929,157 @@ impl FromValue for PrefsConfig {
     }
 }
 
+impl FromValue for NotificationsConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "NotificationsConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            webhook_url: obj
+                .get("webhook_url")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+        })
+    }
+}
+
+impl FromValue for NetConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "NetConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            socks5: obj.get("socks5").and_then(|v| v.as_str()).map(String::from),
+            onion_address: obj
+                .get("onion_address")
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            compression: obj
+                .get("compression")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(true),
+            transports: string_list(obj.get("transports"))
+                .unwrap_or_else(|| NetConfig::default().transports),
+            listen_addresses: string_list(obj.get("listen_addresses"))
+                .unwrap_or_else(|| NetConfig::default().listen_addresses),
+            interfaces: string_list(obj.get("interfaces")).unwrap_or_default(),
+            disabled_interfaces: string_list(obj.get("disabled_interfaces")).unwrap_or_default(),
+            broadcast_discovery: obj
+                .get("broadcast_discovery")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            broadcast_port: obj
+                .get("broadcast_port")
+                .and_then(|v| v.as_u64())
+                .map_or(9779, |n| n as u16),
+            broadcast_scan: obj
+                .get("broadcast_scan")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        })
+    }
+}
+
+impl FromValue for PoliciesConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "PoliciesConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        Ok(Self {
+            enabled: obj
+                .get("enabled")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+            path: obj
+                .get("path")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .unwrap_or_else(Config::default_policies_path),
+            xpi_dir: obj
+                .get("xpi_dir")
+                .and_then(|v| v.as_str())
+                .map(PathBuf::from)
+                .unwrap_or_else(Config::default_policy_xpi_dir),
+        })
+    }
+}
+
+impl FromValue for LimitsConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "LimitsConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        let defaults = Self::default();
+        Ok(Self {
+            max_event_kb: obj
+                .get("max_event_kb")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(defaults.max_event_kb),
+            max_xpi_mb: obj
+                .get("max_xpi_mb")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(defaults.max_xpi_mb),
+            max_events_per_file: obj
+                .get("max_events_per_file")
+                .and_then(|v| v.as_u64())
+                .map(|n| n as usize)
+                .unwrap_or(defaults.max_events_per_file),
+            device_quota_mb: obj
+                .get("device_quota_mb")
+                .and_then(|v| v.as_u64())
+                .unwrap_or(defaults.device_quota_mb),
+        })
+    }
+}
+
+impl FromValue for PermissionsConfig {
+    fn from_value(value: &ConfigValue) -> prefer::Result<Self> {
+        let obj = value
+            .as_object()
+            .ok_or_else(|| prefer::Error::ConversionError {
+                key: String::new(),
+                type_name: "PermissionsConfig".into(),
+                source: "expected object".into(),
+            })?;
+
+        let domains = |key: &str| -> Vec<String> {
+            obj.get(key)
+                .and_then(|v| v.as_array())
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|v| v.as_str().map(String::from))
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+
+        Ok(Self {
+            allow: domains("allow"),
+            block: domains("block"),
+        })
+    }
+}
+
 impl Config {
     /// Load config using prefer's multi-format support
     /// This allows users to use any supported format (TOML, JSON, YAML, etc.)
@@ -231,6 +1092,49 @@ impl Config {
             .map_err(|e| anyhow::anyhow!("Failed to parse config: {}", e))
     }
 
+    /// Current value of a setting in [`PACK_SETTINGS`]
+    ///
+    /// Key epoch 1 is left empty like an unset list, so a device that was
+    /// never changed doesn't take the pack back to it.
+    pub fn pack_setting(&self, key: &str) -> Option<Vec<String>> {
+        match key {
+            "prefs.whitelist" => Some(self.prefs.whitelist.clone()),
+            "sync.disabled_categories" => Some(self.sync.disabled_categories.clone()),
+            "sync.key_epoch" if self.sync.key_epoch == 1 => Some(Vec::new()),
+            "sync.key_epoch" => Some(vec![self.sync.key_epoch.to_string()]),
+            _ => None,
+        }
+    }
+
+    /// Change a setting in [`PACK_SETTINGS`], returning false for any other
+    /// key or a value it can't take
+    pub fn set_pack_setting(&mut self, key: &str, value: Vec<String>) -> bool {
+        match key {
+            "prefs.whitelist" => self.prefs.whitelist = value,
+            "sync.disabled_categories" => self.sync.disabled_categories = value,
+            "sync.key_epoch" => {
+                let epoch = value.first().map_or(Ok(1), |epoch| epoch.parse::<u64>());
+                match epoch {
+                    Ok(epoch) if KeyEpoch::from_number(epoch).is_ok() => {
+                        self.sync.key_epoch = epoch;
+                    }
+                    _ => return false,
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Categories in `sync.disabled_categories`, skipping unknown names
+    pub fn disabled_categories(&self) -> Vec<SyncCategory> {
+        self.sync
+            .disabled_categories
+            .iter()
+            .filter_map(|name| name.parse().ok())
+            .collect()
+    }
+
     /// Load config from a specific path (TOML format)
     pub fn load(path: &Path) -> Result<Self> {
         let content = std::fs::read_to_string(path)
@@ -265,33 +1169,10 @@ impl Config {
         content.push_str(&format!("name = \"{}\"\n", self.device.name));
         content.push('\n');
 
-        content.push_str("[paths]\n");
-        if let Some(ref profile) = self.paths.profile {
-            content.push_str(&format!("profile = \"{}\"\n", profile.display()));
-        }
-        content.push_str(&format!(
-            "sync_dir = \"{}\"\n",
-            self.paths.sync_dir.display()
-        ));
+        content.push_str(&self.paths.to_toml_section());
         content.push('\n');
 
-        content.push_str("[sync]\n");
-        if let Some(port) = self.sync.listen_port {
-            content.push_str(&format!("listen_port = {}\n", port));
-        }
-        content.push_str(&format!("enable_mdns = {}\n", self.sync.enable_mdns));
-        content.push_str(&format!("enable_dht = {}\n", self.sync.enable_dht));
-        if !self.sync.bootstrap_peers.is_empty() {
-            content.push_str(&format!(
-                "bootstrap_peers = [{}]\n",
-                self.sync
-                    .bootstrap_peers
-                    .iter()
-                    .map(|p| format!("\"{}\"", p))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ));
-        }
+        content.push_str(&self.sync.to_toml_section());
         content.push('\n');
 
         content.push_str("[api]\n");
@@ -300,18 +1181,37 @@ impl Config {
         }
         content.push('\n');
 
-        content.push_str("[prefs]\n");
-        if !self.prefs.whitelist.is_empty() {
-            content.push_str(&format!(
-                "whitelist = [{}]\n",
-                self.prefs
-                    .whitelist
-                    .iter()
-                    .map(|p| format!("\"{}\"", p))
-                    .collect::<Vec<_>>()
-                    .join(", ")
-            ));
-        }
+        content.push_str(&self.backups.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.autofill.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.permissions.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.certificates.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.handlers.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.notifications.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.net.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.limits.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.policies.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.logging.to_toml_section());
+        content.push('\n');
+
+        content.push_str(&self.prefs.to_toml_section());
 
         Ok(content)
     }
@@ -323,6 +1223,13 @@ impl Config {
             .join("config.toml")
     }
 
+    pub fn default_hooks_dir() -> PathBuf {
+        dirs::config_dir()
+            .unwrap_or_else(|| PathBuf::from(".config"))
+            .join("wolfpack")
+            .join("hooks")
+    }
+
     pub fn default_sync_dir() -> PathBuf {
         dirs::data_dir()
             .unwrap_or_else(|| PathBuf::from("."))
@@ -337,11 +1244,98 @@ impl Config {
             .join("state.db")
     }
 
+    /// The daemon's IPC socket, in `$XDG_RUNTIME_DIR` or else a per-user
+    /// directory under `/tmp`
+    pub fn default_ipc_socket() -> PathBuf {
+        dirs::runtime_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp").join(format!("wolfpack-{}", current_uid())))
+            .join("wolfpack.sock")
+    }
+
+    /// Where this device's encryption key and p2p identity are kept
+    pub(crate) fn keys_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("wolfpack")
+            .join("keys")
+    }
+
+    /// This device's encryption key, which its recovery code restores
+    pub(crate) fn local_key_path() -> PathBuf {
+        Self::keys_dir().join("local.key")
+    }
+
+    pub fn default_backup_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("wolfpack")
+            .join("wolfpack-backups")
+    }
+
+    /// Where LibreWolf reads enterprise policies from
+    pub fn default_policies_path() -> PathBuf {
+        if cfg!(target_os = "macos") {
+            PathBuf::from(
+                "/Applications/LibreWolf.app/Contents/Resources/distribution/policies.json",
+            )
+        } else if cfg!(target_os = "windows") {
+            PathBuf::from(r"C:\Program Files\LibreWolf\distribution\policies.json")
+        } else {
+            PathBuf::from("/etc/librewolf/policies/policies.json")
+        }
+    }
+
+    pub fn default_policy_xpi_dir() -> PathBuf {
+        dirs::data_dir()
+            .unwrap_or_else(|| PathBuf::from("."))
+            .join("wolfpack")
+            .join("policy-extensions")
+    }
+
+    /// Get the backup store for overwritten profile files
+    pub fn profile_backups(&self) -> ProfileBackups {
+        ProfileBackups::new(self.backups.dir.clone(), self.backups.keep)
+    }
+
     /// Get the path to the state database
     pub fn state_db_path(&self) -> PathBuf {
+        self.paths.state_db.clone()
+    }
+
+    /// Where older versions kept a second state database, in the sync
+    /// directory
+    pub fn legacy_state_db_path(&self) -> PathBuf {
         self.paths.sync_dir.join("state.db")
     }
 
+    /// Get the path to the state database, after merging in the one an
+    /// older version left at the legacy location
+    pub fn migrate_state_db(&self) -> Result<PathBuf> {
+        let path = self.state_db_path();
+        StateDb::merge_legacy(&path, &self.legacy_state_db_path()).with_context(|| {
+            format!(
+                "Failed to merge {} into {}",
+                self.legacy_state_db_path().display(),
+                path.display()
+            )
+        })?;
+        Ok(path)
+    }
+
+    /// Get the path to the daemon's IPC socket
+    pub fn ipc_socket_path(&self) -> PathBuf {
+        self.paths
+            .ipc_socket
+            .clone()
+            .unwrap_or_else(Self::default_ipc_socket)
+    }
+
+    /// Get the IPC socket path from the config file at `config_path`, so the
+    /// CLI finds the socket wherever that config put the daemon's
+    pub fn ipc_socket_for(config_path: &Path) -> PathBuf {
+        Self::load(config_path).map_or_else(|_| Self::default_ipc_socket(), |c| c.ipc_socket_path())
+    }
+
     /// Get the LibreWolf profile directory
     pub fn profile_dir(&self) -> Result<PathBuf> {
         if let Some(ref profile) = self.paths.profile {
@@ -364,14 +1358,33 @@ impl Default for Config {
             paths: PathConfig {
                 profile: None,
                 sync_dir: Self::default_sync_dir(),
+                manifest: None,
+                hooks_dir: Self::default_hooks_dir(),
+                state_db: Self::default_state_db(),
+                ipc_socket: None,
             },
             sync: SyncConfig::default(),
             api: ApiConfig::default(),
             prefs: PrefsConfig::default(),
+            backups: BackupConfig::default(),
+            autofill: AutofillConfig::default(),
+            permissions: PermissionsConfig::default(),
+            certificates: CertificatesConfig::default(),
+            handlers: HandlersConfig::default(),
+            notifications: NotificationsConfig::default(),
+            net: NetConfig::default(),
+            limits: LimitsConfig::default(),
+            policies: PoliciesConfig::default(),
+            logging: LoggingConfig::default(),
         }
     }
 }
 
+/// The user this process runs as
+pub(crate) fn current_uid() -> u32 {
+    rustix::process::getuid().as_raw()
+}
+
 /// Convert toml::Value to prefer::ConfigValue
 fn toml_to_config_value(value: toml::Value) -> ConfigValue {
     match value {
@@ -410,6 +1423,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::cognitive_complexity)] // One assertion per option
     fn test_config_default_values() {
         let config = Config::default();
 
@@ -437,9 +1451,33 @@ mod tests {
 
         // Prefs whitelist should be empty
         assert!(config.prefs.whitelist.is_empty());
+
+        // Backups should be kept in the data dir
+        assert!(config.backups.dir.ends_with("wolfpack/wolfpack-backups"));
+        assert_eq!(config.backups.keep, 10);
+
+        // Address and certificate exception sync are opt-in
+        assert!(!config.autofill.addresses);
+        assert!(!config.certificates.overrides);
+
+        // Webhook notifications are off until an endpoint is given
+        assert!(config.notifications.webhook_url.is_none());
+        assert!(config.net.socks5.is_none());
+        assert!(config.net.compression);
+        assert_eq!(config.net.transports, vec!["tcp", "quic"]);
+        assert_eq!(config.net.listen_addresses, vec!["0.0.0.0", "::"]);
+        assert!(config.net.interfaces.is_empty());
+        assert!(!config.net.broadcast_discovery);
+        assert_eq!(config.net.broadcast_port, 9779);
+
+        assert_eq!(config.limits.max_event_bytes(), 1024 * 1024);
+        assert_eq!(config.limits.max_events_per_file, 10_000);
+        assert_eq!(config.limits.device_quota_mb, 1024);
     }
 
     #[test]
+    #[allow(clippy::cognitive_complexity)] // One assertion per option
+    #[allow(clippy::too_many_lines)] // One assertion per option
     fn test_config_with_custom_values() {
         let dir = tempdir().unwrap();
         let path = dir.path().join("config.toml");
@@ -447,22 +1485,130 @@ mod tests {
         let mut config = Config::default();
         config.device.name = "test-device".to_string();
         config.paths.profile = Some(PathBuf::from("/custom/profile"));
+        config.paths.manifest = Some(PathBuf::from("/custom/profile.toml"));
+        config.paths.hooks_dir = PathBuf::from("/custom/hooks");
+        config.paths.state_db = PathBuf::from("/custom/state.db");
+        config.paths.ipc_socket = Some(PathBuf::from("/run/wolfpack/wolfpack.sock"));
         config.sync.enable_dht = true;
+        config.sync.lan_only = true;
+        config.sync.strict_peers = true;
         config.sync.listen_port = Some(9999);
         config.sync.bootstrap_peers = vec!["/ip4/1.2.3.4/tcp/4001".to_string()];
+        config.sync.relays = vec![
+            "/ip4/5.6.7.8/tcp/4001/p2p/12D3KooWRelayA".to_string(),
+            "/ip4/9.10.11.12/tcp/4001/p2p/12D3KooWRelayB".to_string(),
+        ];
+        config.sync.disabled_categories = vec!["tabs".to_string()];
+        config.sync.require_approval = true;
+        config.sync.tab_expiry_days = 0;
+        config.sync.tab_retention_days = 7;
+        config.sync.tab_dedup_minutes = 0;
+        config.sync.forward_events = false;
+        config.sync.event_codec = "cbor".to_string();
+        config.sync.key_epoch = 2;
         config.api.port = Some(8080);
         config.prefs.whitelist = vec!["browser.*".to_string(), "extensions.*".to_string()];
+        config.prefs.local_only = vec!["browser.download.dir".to_string()];
+        config.backups.dir = PathBuf::from("/custom/backups");
+        config.backups.keep = 3;
+        config.autofill.addresses = true;
+        config.permissions.block = vec!["bank.example.com".to_string()];
+        config.certificates.overrides = true;
+        config.handlers.translate.insert(
+            "thunderbird".to_string(),
+            r"C:\Program Files\Mozilla Thunderbird\thunderbird.exe".to_string(),
+        );
+        config.handlers.translate.insert(
+            "/usr/bin/hexchat".to_string(),
+            "/opt/hexchat/hexchat".to_string(),
+        );
+        config.notifications.webhook_url = Some("https://ntfy.sh/wolfpack-test".to_string());
+        config.net.socks5 = Some("127.0.0.1:9050".to_string());
+        config.net.onion_address = Some("example.onion:4001".to_string());
+        config.net.compression = false;
+        config.net.transports = vec!["quic".to_string()];
+        config.net.listen_addresses = vec!["::".to_string()];
+        config.net.interfaces = vec!["eth0".to_string(), "wlan0".to_string()];
+        config.net.disabled_interfaces = vec!["docker0".to_string()];
+        config.net.broadcast_discovery = true;
+        config.net.broadcast_port = 19779;
+        config.net.broadcast_scan = true;
+        config.limits.max_event_kb = 64;
+        config.limits.max_xpi_mb = 0;
+        config.limits.max_events_per_file = 500;
+        config.limits.device_quota_mb = 100;
+        config.policies.enabled = true;
+        config.policies.path = PathBuf::from("/etc/librewolf/policies/policies.json");
+        config.policies.xpi_dir = PathBuf::from("/srv/wolfpack/xpis");
+        config.logging.redact = true;
+        config.logging.sensitive_prefs = vec!["browser.download.*".to_string()];
 
         config.save(&path).unwrap();
 
         let loaded = Config::load(&path).unwrap();
         assert_eq!(loaded.device.name, "test-device");
         assert_eq!(loaded.paths.profile, Some(PathBuf::from("/custom/profile")));
+        assert_eq!(
+            loaded.paths.manifest,
+            Some(PathBuf::from("/custom/profile.toml"))
+        );
+        assert_eq!(loaded.paths.hooks_dir, PathBuf::from("/custom/hooks"));
+        assert_eq!(loaded.paths.state_db, PathBuf::from("/custom/state.db"));
+        assert_eq!(
+            loaded.paths.ipc_socket,
+            Some(PathBuf::from("/run/wolfpack/wolfpack.sock"))
+        );
         assert!(loaded.sync.enable_dht);
+        assert!(loaded.sync.lan_only);
+        assert!(loaded.sync.strict_peers);
         assert_eq!(loaded.sync.listen_port, Some(9999));
         assert_eq!(loaded.sync.bootstrap_peers.len(), 1);
+        assert_eq!(loaded.sync.relays, config.sync.relays);
+        assert_eq!(loaded.sync.disabled_categories, vec!["tabs".to_string()]);
+        assert!(loaded.sync.require_approval);
+        assert_eq!(loaded.sync.tab_expiry_days, 0);
+        assert_eq!(loaded.sync.tab_retention_days, 7);
+        assert_eq!(loaded.sync.tab_dedup_minutes, 0);
+        assert!(!loaded.sync.forward_events);
+        assert_eq!(loaded.sync.event_codec, "cbor");
+        assert_eq!(loaded.sync.key_epoch, 2);
         assert_eq!(loaded.api.port, Some(8080));
         assert_eq!(loaded.prefs.whitelist.len(), 2);
+        assert!(loaded.prefs.is_local_only("browser.download.dir"));
+        assert!(!loaded.prefs.is_local_only("browser.startup.page"));
+        assert_eq!(loaded.backups.dir, PathBuf::from("/custom/backups"));
+        assert_eq!(loaded.backups.keep, 3);
+        assert!(loaded.autofill.addresses);
+        assert!(loaded.permissions.allow.is_empty());
+        assert_eq!(loaded.permissions.block, vec!["bank.example.com"]);
+        assert!(loaded.certificates.overrides);
+        assert_eq!(loaded.handlers.translate, config.handlers.translate);
+        assert_eq!(
+            loaded.notifications.webhook_url.as_deref(),
+            Some("https://ntfy.sh/wolfpack-test")
+        );
+        assert_eq!(loaded.net.socks5.as_deref(), Some("127.0.0.1:9050"));
+        assert_eq!(
+            loaded.net.onion_address.as_deref(),
+            Some("example.onion:4001")
+        );
+        assert!(!loaded.net.compression);
+        assert_eq!(loaded.net.transports, vec!["quic"]);
+        assert_eq!(loaded.net.listen_addresses, vec!["::"]);
+        assert_eq!(loaded.net.interfaces, vec!["eth0", "wlan0"]);
+        assert_eq!(loaded.net.disabled_interfaces, vec!["docker0"]);
+        assert!(loaded.net.broadcast_discovery);
+        assert_eq!(loaded.net.broadcast_port, 19779);
+        assert!(loaded.net.broadcast_scan);
+        assert_eq!(loaded.limits.max_event_kb, 64);
+        assert_eq!(loaded.limits.max_xpi_mb, 0);
+        assert_eq!(loaded.limits.max_events_per_file, 500);
+        assert_eq!(loaded.limits.device_quota_mb, 100);
+        assert!(loaded.policies.enabled);
+        assert_eq!(loaded.policies.xpi_dir, PathBuf::from("/srv/wolfpack/xpis"));
+        assert!(loaded.logging.redact);
+        assert!(!loaded.logging.redact_tab_urls);
+        assert_eq!(loaded.logging.sensitive_prefs, vec!["browser.download.*"]);
     }
 
     #[test]
@@ -498,6 +1644,10 @@ mod tests {
         assert!(config_path.to_string_lossy().contains("wolfpack"));
         assert!(config_path.to_string_lossy().ends_with("config.toml"));
 
+        let hooks_dir = Config::default_hooks_dir();
+        assert_eq!(hooks_dir.parent(), config_path.parent());
+        assert!(hooks_dir.ends_with("hooks"));
+
         let sync_dir = Config::default_sync_dir();
         assert!(sync_dir.to_string_lossy().contains("wolfpack"));
         assert!(sync_dir.to_string_lossy().ends_with("sync"));
@@ -552,13 +1702,64 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_ipc_socket_path() {
+        let dir = tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        assert_eq!(
+            Config::ipc_socket_for(&config_path),
+            Config::default_ipc_socket()
+        );
+
+        let mut config = Config::default();
+        assert!(config.paths.ipc_socket.is_none());
+        assert!(config.ipc_socket_path().ends_with("wolfpack.sock"));
+        if dirs::runtime_dir().is_none() {
+            let parent = config.ipc_socket_path().parent().unwrap().to_path_buf();
+            assert!(parent.starts_with("/tmp"));
+            assert!(parent.to_string_lossy().starts_with("/tmp/wolfpack-"));
+            assert!(!parent.ends_with("wolfpack-unknown"));
+        }
+
+        config.paths.ipc_socket = Some(dir.path().join("custom.sock"));
+        config.save(&config_path).unwrap();
+        assert_eq!(
+            Config::ipc_socket_for(&config_path),
+            dir.path().join("custom.sock")
+        );
+    }
+
     #[test]
     fn test_state_db_path() {
         let mut config = Config::default();
         config.paths.sync_dir = PathBuf::from("/custom/sync");
+        assert_eq!(config.state_db_path(), Config::default_state_db());
+        assert_eq!(
+            config.legacy_state_db_path(),
+            PathBuf::from("/custom/sync/state.db")
+        );
+
+        config.paths.state_db = PathBuf::from("/custom/state.db");
+        assert_eq!(config.state_db_path(), PathBuf::from("/custom/state.db"));
+    }
 
-        let state_db = config.state_db_path();
-        assert_eq!(state_db, PathBuf::from("/custom/sync/state.db"));
+    #[test]
+    fn test_migrate_state_db() {
+        let temp = tempfile::tempdir().unwrap();
+        let mut config = Config::default();
+        config.paths.sync_dir = temp.path().join("sync");
+        config.paths.state_db = temp.path().join("state.db");
+        std::fs::create_dir_all(&config.paths.sync_dir).unwrap();
+        StateDb::open(&config.legacy_state_db_path())
+            .unwrap()
+            .add_extension("old@example.com", "Old", None)
+            .unwrap();
+
+        let path = config.migrate_state_db().unwrap();
+        assert_eq!(path, config.paths.state_db);
+        assert!(!config.legacy_state_db_path().exists());
+        let db = StateDb::open(&path).unwrap();
+        assert_eq!(db.get_extensions().unwrap().len(), 1);
     }
 
     #[test]
@@ -571,8 +1772,46 @@ mod tests {
     fn test_sync_config_default() {
         let sync = SyncConfig::default();
         assert!(!sync.enable_dht);
+        assert!(!sync.lan_only);
+        assert!(!sync.strict_peers);
         assert!(sync.listen_port.is_none());
         assert!(sync.bootstrap_peers.is_empty());
+        assert!(!sync.require_approval);
+        assert_eq!(sync.tab_expiry_days, 14);
+        assert_eq!(sync.tab_retention_days, 30);
+        assert_eq!(sync.tab_dedup_minutes, 60);
+        assert!(sync.forward_events);
+        assert_eq!(sync.event_codec, "json");
+        assert_eq!(sync.key_epoch, 1);
+    }
+
+    #[test]
+    fn test_pack_settings() {
+        let mut config = Config::default();
+        for key in PACK_SETTINGS {
+            assert_eq!(config.pack_setting(key), Some(Vec::new()));
+        }
+        assert!(config.pack_setting("device.name").is_none());
+
+        let whitelist = vec!["browser.*".to_string()];
+        assert!(config.set_pack_setting("prefs.whitelist", whitelist.clone()));
+        assert_eq!(config.prefs.whitelist, whitelist);
+        assert!(!config.set_pack_setting("device.name", vec!["laptop".to_string()]));
+
+        let categories = vec!["tabs".to_string(), "bogus".to_string()];
+        config.set_pack_setting("sync.disabled_categories", categories);
+        assert_eq!(config.disabled_categories(), vec![SyncCategory::Tabs]);
+
+        // The key epoch is raised for the whole pack, and only to one it knows
+        assert!(config.set_pack_setting("sync.key_epoch", vec!["2".to_string()]));
+        assert_eq!(config.sync.key_epoch, 2);
+        assert_eq!(
+            config.pack_setting("sync.key_epoch"),
+            Some(vec!["2".to_string()])
+        );
+        assert!(!config.set_pack_setting("sync.key_epoch", vec!["3".to_string()]));
+        assert!(!config.set_pack_setting("sync.key_epoch", vec!["x".to_string()]));
+        assert_eq!(config.sync.key_epoch, 2);
     }
 
     #[test]
@@ -581,6 +1820,17 @@ mod tests {
         assert!(prefs.whitelist.is_empty());
     }
 
+    #[test]
+    fn test_prefs_pattern_errors() {
+        let prefs = PrefsConfig {
+            whitelist: vec!["browser.*".to_string(), "/browser.(/".to_string()],
+            local_only: vec![r"/^network\.proxy\./".to_string()],
+        };
+        let errors = prefs.pattern_errors();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("prefs.whitelist entry: Invalid pref pattern /browser.(/"));
+    }
+
     #[test]
     fn test_config_profile_dir_explicit() {
         let mut config = Config::default();
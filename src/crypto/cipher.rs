@@ -5,6 +5,7 @@ use aes_gcm::{
 use anyhow::Result;
 use chacha20poly1305::{XChaCha20Poly1305, XNonce};
 use sha2::{Digest, Sha256};
+use tracing::instrument;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
@@ -64,6 +65,7 @@ pub fn derive_nonce_xchacha(device_id: &str, counter: u64) -> [u8; 24] {
     nonce
 }
 
+#[instrument(skip_all, fields(?cipher, bytes = plaintext.len()))]
 pub fn encrypt(
     cipher: Cipher,
     key: &[u8; 32],
@@ -93,6 +95,7 @@ pub fn encrypt(
     }
 }
 
+#[instrument(skip_all, fields(?cipher, bytes = ciphertext.len()))]
 pub fn decrypt(cipher: Cipher, key: &[u8; 32], nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
     match cipher {
         Cipher::Aes256Gcm => {
@@ -117,10 +120,46 @@ pub fn decrypt(cipher: Cipher, key: &[u8; 32], nonce: &[u8], ciphertext: &[u8])
     }
 }
 
+/// Encrypt a short message with XChaCha20-Poly1305 under a random nonce,
+/// which is put in front of the ciphertext
+///
+/// For messages other than event files, with no counter to derive a nonce
+/// from; XChaCha20's nonces are long enough to pick at random.
+pub fn seal(key: &[u8; 32], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce: [u8; 24] = rand::random();
+    let chacha = XChaCha20Poly1305::new_from_slice(key)
+        .map_err(|e| anyhow::anyhow!("Invalid key: {}", e))?;
+    let ciphertext = chacha
+        .encrypt(XNonce::from_slice(&nonce), plaintext)
+        .map_err(|e| anyhow::anyhow!("Encryption failed: {}", e))?;
+    Ok([nonce.to_vec(), ciphertext].concat())
+}
+
+/// Decrypt a message from [`seal`]
+pub fn open(key: &[u8; 32], sealed: &[u8]) -> Result<Vec<u8>> {
+    let nonce_size = Cipher::XChaCha20Poly1305.nonce_size();
+    if sealed.len() < nonce_size {
+        anyhow::bail!("Sealed message is too short");
+    }
+    let (nonce, ciphertext) = sealed.split_at(nonce_size);
+    decrypt(Cipher::XChaCha20Poly1305, key, nonce, ciphertext)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_seal_roundtrip() {
+        let key = [42u8; 32];
+        let sealed = seal(&key, b"laptop").unwrap();
+        assert_eq!(open(&key, &sealed).unwrap(), b"laptop");
+        // A fresh nonce every time
+        assert_ne!(seal(&key, b"laptop").unwrap(), sealed);
+        assert!(open(&[7u8; 32], &sealed).is_err());
+        assert!(open(&key, &sealed[..10]).is_err());
+    }
+
     #[test]
     fn test_aes_roundtrip() {
         let key = [42u8; 32];
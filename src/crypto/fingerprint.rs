@@ -0,0 +1,60 @@
+use sha2::{Digest, Sha256};
+
+use super::PublicKey;
+use super::recovery::WORDS;
+
+/// Bytes of the key's hash shown, as hex and as words
+const FINGERPRINT_BYTES: usize = 16;
+
+/// A public key's fingerprint, for reading out and comparing between
+/// devices: the start of its SHA-256 hash, as hex in groups of four digits
+/// and as words from the recovery code word list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub hex: String,
+    pub words: String,
+}
+
+impl Fingerprint {
+    pub fn of(key: &PublicKey) -> Self {
+        let hash = Sha256::digest(key);
+        let bytes = &hash[..FINGERPRINT_BYTES];
+        Self {
+            hex: bytes
+                .chunks(2)
+                .map(hex::encode)
+                .collect::<Vec<_>>()
+                .join(" "),
+            words: bytes
+                .iter()
+                .map(|&byte| WORDS[byte as usize])
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_fingerprint() {
+        let key = KeyPair::generate().public_key();
+        let fingerprint = Fingerprint::of(&key);
+        assert_eq!(fingerprint, Fingerprint::of(&key));
+        assert_eq!(fingerprint.hex.split(' ').count(), FINGERPRINT_BYTES / 2);
+        assert_eq!(fingerprint.words.split(' ').count(), FINGERPRINT_BYTES);
+        assert_ne!(
+            fingerprint,
+            Fingerprint::of(&KeyPair::generate().public_key())
+        );
+
+        // Fixed for a given key, so every device shows the same
+        assert_eq!(
+            Fingerprint::of(&[0u8; 32]).hex,
+            "6668 7aad f862 bd77 6c8f c18b 8e9f 8e20"
+        );
+    }
+}
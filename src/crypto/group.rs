@@ -0,0 +1,189 @@
+use anyhow::{Result, bail};
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::{KeyPair, PublicKey};
+
+/// Salt for the HKDF the group secret is derived with
+const GROUP_SALT: &[u8] = b"wolfpack group secret";
+
+/// What the derived key is for, bound into it
+const GROUP_INFO: &[u8] = b"wolfpack event files";
+
+/// What the announcement key derived from the group secret is for
+const ANNOUNCEMENT_INFO: &[u8] = b"wolfpack announcements";
+
+/// How the pack's group secret is derived from the pairwise secrets,
+/// recorded in each event file so files from before a change still decrypt
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyEpoch {
+    /// The pairwise secrets XORed together, read by every version but weak:
+    /// secrets that repeat cancel out
+    #[default]
+    Xor = 1,
+    /// HKDF-SHA256 over each device's ID and pairwise secret, sorted by ID,
+    /// which devices from before it can't read
+    Hkdf = 2,
+}
+
+impl KeyEpoch {
+    /// Parse an epoch from `sync.key_epoch`, or from an event file
+    pub fn from_number(number: u64) -> Result<Self> {
+        match number {
+            1 => Ok(Self::Xor),
+            2 => Ok(Self::Hkdf),
+            other => bail!("Unknown key epoch {}, expected 1 or 2", other),
+        }
+    }
+}
+
+/// The pack's group secret under each key epoch, so files written under an
+/// earlier one can still be read
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct GroupKeys {
+    xor: [u8; 32],
+    hkdf: [u8; 32],
+}
+
+impl GroupKeys {
+    /// Derive the group secrets from this device's keypair and the devices
+    /// it knows
+    ///
+    /// A device that knows none keys files with its own key alone, under no
+    /// ID, so devices sharing a keypair derive the same secret.
+    pub fn derive(keypair: &KeyPair, known_devices: &[(String, PublicKey)]) -> Self {
+        let own = [(String::new(), keypair.public_key())];
+        let devices = if known_devices.is_empty() {
+            &own[..]
+        } else {
+            known_devices
+        };
+        Self {
+            xor: xor_secret(keypair, devices),
+            hkdf: hkdf_secret(keypair, devices),
+        }
+    }
+
+    pub fn get(&self, epoch: KeyEpoch) -> &[u8; 32] {
+        match epoch {
+            KeyEpoch::Xor => &self.xor,
+            KeyEpoch::Hkdf => &self.hkdf,
+        }
+    }
+
+    /// Every epoch's secret, newest first
+    pub fn all(&self) -> [&[u8; 32]; 2] {
+        [&self.hkdf, &self.xor]
+    }
+
+    /// Key for sealing what a device announces about itself on the network,
+    /// so only the pack can tell which device an announcement is from
+    pub fn announcement_key(&self) -> [u8; 32] {
+        let mut key = [0u8; 32];
+        // 32 bytes is well within what HKDF-SHA256 can expand to
+        let _ = Hkdf::<Sha256>::new(None, &self.hkdf).expand(ANNOUNCEMENT_INFO, &mut key);
+        key
+    }
+}
+
+fn xor_secret(keypair: &KeyPair, devices: &[(String, PublicKey)]) -> [u8; 32] {
+    let mut combined = [0u8; 32];
+    for (_, public_key) in devices {
+        let shared = keypair.derive_shared_secret(public_key);
+        for (byte, shared) in combined.iter_mut().zip(shared) {
+            *byte ^= shared;
+        }
+    }
+    combined
+}
+
+fn hkdf_secret(keypair: &KeyPair, devices: &[(String, PublicKey)]) -> [u8; 32] {
+    let mut sorted: Vec<&(String, PublicKey)> = devices.iter().collect();
+    sorted.sort_by(|a, b| a.0.cmp(&b.0));
+    sorted.dedup_by(|a, b| a.0 == b.0);
+
+    // Each ID is length-prefixed, so no two device lists run together alike
+    let mut input = Vec::with_capacity(sorted.len() * 72);
+    for (device_id, public_key) in sorted {
+        input.extend((device_id.len() as u32).to_be_bytes());
+        input.extend(device_id.as_bytes());
+        input.extend(keypair.derive_shared_secret(public_key));
+    }
+
+    let mut key = [0u8; 32];
+    // 32 bytes is well within what HKDF-SHA256 can expand to
+    let _ = Hkdf::<Sha256>::new(Some(GROUP_SALT), &input).expand(GROUP_INFO, &mut key);
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xor_matches_legacy_derivation() {
+        let keypair = KeyPair::generate();
+        let (a, b) = (KeyPair::generate(), KeyPair::generate());
+        let known = vec![
+            ("device-a".to_string(), a.public_key()),
+            ("device-b".to_string(), b.public_key()),
+        ];
+        let mut expected = keypair.derive_shared_secret(&a.public_key());
+        for (byte, shared) in expected
+            .iter_mut()
+            .zip(keypair.derive_shared_secret(&b.public_key()))
+        {
+            *byte ^= shared;
+        }
+        let keys = GroupKeys::derive(&keypair, &known);
+        assert_eq!(*keys.get(KeyEpoch::Xor), expected);
+
+        // Alone, a device keys files with itself
+        let alone = GroupKeys::derive(&keypair, &[]);
+        assert_eq!(
+            *alone.get(KeyEpoch::Xor),
+            keypair.derive_shared_secret(&keypair.public_key())
+        );
+    }
+
+    #[test]
+    fn test_hkdf_secret() {
+        let keypair = KeyPair::generate();
+        let (a, b) = (KeyPair::generate(), KeyPair::generate());
+        let known = vec![
+            ("device-a".to_string(), a.public_key()),
+            ("device-b".to_string(), b.public_key()),
+        ];
+        let keys = GroupKeys::derive(&keypair, &known);
+
+        // Order-independent, like the XOR it replaces
+        let reversed: Vec<_> = known.iter().rev().cloned().collect();
+        assert!(GroupKeys::derive(&keypair, &reversed) == keys);
+
+        // A device listed twice doesn't cancel out, as it does with XOR
+        let doubled = vec![known[0].clone(), known[0].clone()];
+        let doubled = GroupKeys::derive(&keypair, &doubled);
+        assert_eq!(*doubled.get(KeyEpoch::Xor), [0u8; 32]);
+        assert_ne!(*doubled.get(KeyEpoch::Hkdf), [0u8; 32]);
+
+        // The device IDs are part of the key
+        let renamed = vec![
+            ("device-c".to_string(), a.public_key()),
+            ("device-b".to_string(), b.public_key()),
+        ];
+        let renamed = GroupKeys::derive(&keypair, &renamed);
+        assert_ne!(renamed.get(KeyEpoch::Hkdf), keys.get(KeyEpoch::Hkdf));
+        assert_eq!(renamed.get(KeyEpoch::Xor), keys.get(KeyEpoch::Xor));
+
+        // Announcements are sealed with a key of their own
+        assert_ne!(keys.announcement_key(), *keys.get(KeyEpoch::Hkdf));
+        assert_eq!(keys.announcement_key(), keys.announcement_key());
+    }
+
+    #[test]
+    fn test_key_epoch_from_number() {
+        assert_eq!(KeyEpoch::from_number(1).unwrap(), KeyEpoch::Xor);
+        assert_eq!(KeyEpoch::from_number(2).unwrap(), KeyEpoch::Hkdf);
+        assert!(KeyEpoch::from_number(3).is_err());
+    }
+}
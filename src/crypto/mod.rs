@@ -1,5 +1,13 @@
 mod cipher;
+mod fingerprint;
+mod group;
 mod keypair;
+mod proof;
+mod recovery;
 
-pub use cipher::{Cipher, decrypt, detect_preferred_cipher, encrypt};
+pub use cipher::{Cipher, decrypt, detect_preferred_cipher, encrypt, open, seal};
+pub use fingerprint::Fingerprint;
+pub use group::{GroupKeys, KeyEpoch};
 pub use keypair::{KeyPair, PublicKey, SecretKey, public_key_from_hex, public_key_to_hex};
+pub use proof::{device_proof, verify_device_proof};
+pub use recovery::{RECOVERY_CODE_WORDS, recovery_code, secret_from_recovery_code};
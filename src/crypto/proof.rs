@@ -0,0 +1,104 @@
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use super::{KeyPair, PublicKey};
+
+/// What a device proof is for, bound into it
+const PROOF_INFO: &[u8] = b"wolfpack device proof";
+
+/// Prove to the device holding `their_public` that the peer `peer_id` holds
+/// the key of `device_id`, answering the `challenge` it sent
+///
+/// The proof is keyed by the pairwise secret, which only the two devices'
+/// keys can derive, and covers the peer ID so it can't be replayed by
+/// another peer relaying the challenge.
+pub fn device_proof(
+    keypair: &KeyPair,
+    their_public: &PublicKey,
+    challenge: &[u8],
+    claim: (&str, &str),
+) -> [u8; 32] {
+    let (peer_id, device_id) = claim;
+    let shared = keypair.derive_shared_secret(their_public);
+
+    // Each field is length-prefixed, so no two claims run together alike
+    let mut info = PROOF_INFO.to_vec();
+    for field in [peer_id.as_bytes(), device_id.as_bytes()] {
+        info.extend((field.len() as u32).to_be_bytes());
+        info.extend(field);
+    }
+
+    let mut proof = [0u8; 32];
+    // 32 bytes is well within what HKDF-SHA256 can expand to
+    let _ = Hkdf::<Sha256>::new(Some(challenge), &shared).expand(&info, &mut proof);
+    proof
+}
+
+/// Check a proof from the device holding `their_public`, in constant time
+pub fn verify_device_proof(
+    keypair: &KeyPair,
+    their_public: &PublicKey,
+    challenge: &[u8],
+    claim: (&str, &str),
+    proof: &[u8],
+) -> bool {
+    let expected = device_proof(keypair, their_public, challenge, claim);
+    proof.len() == expected.len()
+        && proof
+            .iter()
+            .zip(expected.iter())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_proof() {
+        let (ours, theirs) = (KeyPair::generate(), KeyPair::generate());
+        let challenge = [7u8; 32];
+        let claim = ("peer-b", "device-b");
+
+        let proof = device_proof(&theirs, &ours.public_key(), &challenge, claim);
+        assert!(verify_device_proof(
+            &ours,
+            &theirs.public_key(),
+            &challenge,
+            claim,
+            &proof
+        ));
+
+        // A different key, challenge, peer, or device doesn't verify
+        let other = KeyPair::generate();
+        let forged = device_proof(&other, &ours.public_key(), &challenge, claim);
+        assert!(!verify_device_proof(
+            &ours,
+            &theirs.public_key(),
+            &challenge,
+            claim,
+            &forged
+        ));
+        for (challenge, claim) in [
+            (&[8u8; 32], claim),
+            (&challenge, ("peer-c", "device-b")),
+            (&challenge, ("peer-b", "device-c")),
+        ] {
+            assert!(!verify_device_proof(
+                &ours,
+                &theirs.public_key(),
+                challenge,
+                claim,
+                &proof
+            ));
+        }
+        assert!(!verify_device_proof(
+            &ours,
+            &theirs.public_key(),
+            &challenge,
+            claim,
+            &proof[..16]
+        ));
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{Result, bail};
+use sha2::{Digest, Sha256};
+
+use super::SecretKey;
+
+/// Words in a recovery code: one per byte of the secret key, then a
+/// checksum word
+pub const RECOVERY_CODE_WORDS: usize = 33;
+
+/// One word per byte value, sorted, no two sharing their first four letters
+/// so a word can be written down or typed by its first four
+pub(super) const WORDS: [&str; 256] = [
+    "able", "acorn", "adapt", "adult", "ahead", "alarm", "alert", "alpha", "ample", "angle",
+    "april", "arena", "armor", "aspect", "attic", "august", "avoid", "award", "baby", "bagel",
+    "balance", "banana", "barley", "batch", "beard", "bench", "birch", "blanket", "board",
+    "border", "boxer", "brave", "bridge", "brush", "buffalo", "butter", "cactus", "canal",
+    "canvas", "carbon", "carpet", "castle", "cement", "chair", "cherry", "chimney", "citrus",
+    "clay", "clock", "clover", "coast", "coffee", "copper", "cotton", "crater", "cream", "crown",
+    "curtain", "cycle", "dance", "debate", "delta", "depot", "detail", "dinner", "domain", "door",
+    "dream", "drum", "dune", "earth", "echo", "effort", "elder", "ember", "empire", "enjoy",
+    "equal", "errand", "essay", "exact", "exotic", "falcon", "farm", "feather", "ferry", "figure",
+    "finger", "fish", "flavor", "flower", "foam", "fossil", "fresh", "frost", "funnel", "garden",
+    "gate", "gentle", "ginger", "glacier", "globe", "gold", "grain", "gravel", "group", "gulf",
+    "hammer", "harvest", "hazel", "heart", "hero", "highway", "hobby", "honey", "horizon", "hotel",
+    "hunter", "idea", "image", "inch", "infant", "insect", "ivory", "jelly", "jigsaw", "journey",
+    "jungle", "kayak", "kettle", "kitchen", "kitten", "knife", "ladder", "lake", "lantern", "lawn",
+    "lemon", "letter", "library", "limb", "lion", "lizard", "lotus", "lunar", "mammal", "maple",
+    "market", "medal", "metal", "milk", "mirror", "monkey", "morning", "motor", "music", "narrow",
+    "nephew", "network", "normal", "notice", "number", "oasis", "ocean", "office", "onion",
+    "orange", "orchard", "outdoor", "owner", "oyster", "panda", "paper", "parrot", "peach",
+    "pebble", "pencil", "picnic", "pillow", "pioneer", "plastic", "polar", "poppy", "powder",
+    "puppet", "puzzle", "quarter", "quick", "quiz", "raccoon", "rain", "raven", "recipe", "remote",
+    "ribbon", "river", "rodeo", "rose", "rubber", "rural", "salt", "sandal", "saucer", "scout",
+    "second", "shadow", "shovel", "sister", "sketch", "snake", "soda", "spider", "spring",
+    "stable", "steel", "summit", "swan", "symbol", "talent", "tavern", "temple", "tent", "ticket",
+    "timber", "tomato", "tornado", "tractor", "tulip", "turkey", "twelve", "uncle", "unit",
+    "urban", "utility", "vanilla", "venture", "vessel", "video", "visa", "vivid", "volcano",
+    "walnut", "wander", "water", "wedding", "wheat", "widget", "wisdom", "wolf", "wool", "yacht",
+    "yellow", "yogurt", "zebra", "zigzag", "zipper",
+];
+
+/// Encode a secret key as a recovery code, in the style of a BIP39 mnemonic
+///
+/// Each byte of the key becomes a word, and the first byte of its SHA-256
+/// hash is added as a last word to catch mistakes when it's typed back in.
+pub fn recovery_code(secret: &SecretKey) -> String {
+    secret
+        .iter()
+        .chain(std::iter::once(&checksum(secret)))
+        .map(|&byte| WORDS[byte as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Decode a recovery code back into the secret key it was made from
+///
+/// Words may be separated by spaces, newlines, or hyphens, in any case, and
+/// shortened to their first four letters.
+pub fn secret_from_recovery_code(code: &str) -> Result<SecretKey> {
+    let words: Vec<&str> = code
+        .split(|c: char| c.is_whitespace() || c == '-')
+        .filter(|word| !word.is_empty())
+        .collect();
+    if words.len() != RECOVERY_CODE_WORDS {
+        bail!(
+            "Recovery code has {} words, expected {}",
+            words.len(),
+            RECOVERY_CODE_WORDS
+        );
+    }
+
+    let mut bytes = Vec::with_capacity(RECOVERY_CODE_WORDS);
+    for (i, word) in words.iter().enumerate() {
+        match word_value(word) {
+            Some(byte) => bytes.push(byte),
+            None => bail!(
+                "Unknown word {:?} at position {} of the recovery code",
+                word,
+                i + 1
+            ),
+        }
+    }
+
+    let check = bytes.pop();
+    let secret: SecretKey = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Secret key must be 32 bytes"))?;
+    if check != Some(checksum(&secret)) {
+        bail!("Recovery code checksum doesn't match, check for a mistyped word");
+    }
+    Ok(secret)
+}
+
+fn checksum(secret: &SecretKey) -> u8 {
+    Sha256::digest(secret)[0]
+}
+
+/// The byte a word stands for, given whole or as a prefix of at least four
+/// letters that only one word starts with
+fn word_value(word: &str) -> Option<u8> {
+    let word = word.to_lowercase();
+    if word.chars().count() < 4 {
+        return None;
+    }
+    let mut matches = WORDS
+        .iter()
+        .enumerate()
+        .filter(|(_, candidate)| candidate.starts_with(word.as_str()));
+    match (matches.next(), matches.next()) {
+        (Some((index, _)), None) => Some(index as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+
+    #[test]
+    fn test_words_are_distinct_by_prefix() {
+        let mut prefixes: Vec<&str> = WORDS.iter().map(|word| &word[..4]).collect();
+        prefixes.dedup();
+        assert_eq!(prefixes.len(), 256);
+        assert!(WORDS.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_recovery_code_roundtrip() {
+        let secret = KeyPair::generate().secret_key();
+        let code = recovery_code(&secret);
+        assert_eq!(code.split(' ').count(), RECOVERY_CODE_WORDS);
+        assert_eq!(secret_from_recovery_code(&code).unwrap(), secret);
+
+        // Shortened, shouted, and split across lines
+        let short: Vec<String> = code
+            .split(' ')
+            .map(|word| word[..4].to_uppercase())
+            .collect();
+        assert_eq!(
+            secret_from_recovery_code(&short.join("\n")).unwrap(),
+            secret
+        );
+    }
+
+    #[test]
+    fn test_invalid_recovery_code() {
+        let secret = [7u8; 32];
+        let code = recovery_code(&secret);
+        let mut words: Vec<&str> = code.split(' ').collect();
+
+        let err = secret_from_recovery_code(&words[..32].join(" ")).unwrap_err();
+        assert!(err.to_string().contains("32 words"));
+
+        words[3] = "notaword";
+        let err = secret_from_recovery_code(&words.join(" ")).unwrap_err();
+        assert!(err.to_string().contains("position 4"));
+
+        // Too short to tell words apart, or a prefix run past its word
+        for word in ["a", "ab", "abl", "ablex", "bananas"] {
+            assert_eq!(word_value(word), None, "{word}");
+        }
+        assert!(word_value("banana").is_some());
+        assert_eq!(word_value("bana"), word_value("banana"));
+        assert_eq!(word_value("banan"), word_value("banana"));
+        words[3] = "a";
+        let err = secret_from_recovery_code(&words.join(" ")).unwrap_err();
+        assert!(err.to_string().contains("position 4"));
+
+        // A valid word in the wrong place fails the checksum
+        words[3] = WORDS[8];
+        let err = secret_from_recovery_code(&words.join(" ")).unwrap_err();
+        assert!(err.to_string().contains("checksum"));
+    }
+}
@@ -28,6 +28,7 @@ impl ApiTokenManager {
             token
         };
 
+        crate::redact::add_secret(&token);
         Ok(Self { token_path, token })
     }
 
@@ -45,6 +46,7 @@ impl ApiTokenManager {
     pub fn regenerate(&mut self) -> Result<&str> {
         self.token = generate_token();
         save_token(&self.token_path, &self.token)?;
+        crate::redact::add_secret(&self.token);
         Ok(&self.token)
     }
 
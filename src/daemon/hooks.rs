@@ -0,0 +1,210 @@
+use anyhow::{Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::config::Config;
+use crate::sync::SyncEngine;
+
+/// How long a hook may run before it's killed
+const HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A sync event user hook scripts can run on
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEvent {
+    TabReceived {
+        url: String,
+        title: Option<String>,
+        from_device: String,
+        from_device_id: String,
+    },
+    SyncComplete {
+        /// Name of the peer the events came from
+        peer: String,
+        events_applied: usize,
+    },
+    ExtensionInstalled {
+        id: String,
+        name: String,
+    },
+}
+
+impl HookEvent {
+    /// File name of the script in the hooks directory
+    pub fn script_name(&self) -> &'static str {
+        match self {
+            Self::TabReceived { .. } => "on-tab-received",
+            Self::SyncComplete { .. } => "on-sync-complete",
+            Self::ExtensionInstalled { .. } => "on-extension-installed",
+        }
+    }
+
+    /// Event details passed to the script as environment variables
+    fn env(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::TabReceived {
+                url,
+                title,
+                from_device,
+                from_device_id,
+            } => {
+                let mut env = vec![
+                    ("WOLFPACK_TAB_URL", url.clone()),
+                    ("WOLFPACK_FROM_DEVICE", from_device.clone()),
+                    ("WOLFPACK_FROM_DEVICE_ID", from_device_id.clone()),
+                ];
+                if let Some(title) = title {
+                    env.push(("WOLFPACK_TAB_TITLE", title.clone()));
+                }
+                env
+            }
+            Self::SyncComplete {
+                peer,
+                events_applied,
+            } => vec![
+                ("WOLFPACK_PEER", peer.clone()),
+                ("WOLFPACK_EVENTS_APPLIED", events_applied.to_string()),
+            ],
+            Self::ExtensionInstalled { id, name } => vec![
+                ("WOLFPACK_EXTENSION_ID", id.clone()),
+                ("WOLFPACK_EXTENSION_NAME", name.clone()),
+            ],
+        }
+    }
+}
+
+/// Runs executables from the hooks directory on sync events
+///
+/// Hooks run in the background with the event in `WOLFPACK_*` environment
+/// variables; a missing, failing, or slow hook never holds up syncing.
+#[derive(Debug, Clone)]
+pub struct Hooks {
+    dir: PathBuf,
+    device_id: String,
+    device_name: String,
+}
+
+impl Hooks {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            dir: config.paths.hooks_dir.clone(),
+            device_id: config.device.id.clone(),
+            device_name: config.device.name.clone(),
+        }
+    }
+
+    pub fn run(&self, event: HookEvent) {
+        let Some(script) = find_script(&self.dir, event.script_name()) else {
+            return;
+        };
+
+        let mut env = vec![
+            ("WOLFPACK_HOOK", event.script_name().to_string()),
+            ("WOLFPACK_DEVICE_ID", self.device_id.clone()),
+            ("WOLFPACK_DEVICE_NAME", self.device_name.clone()),
+        ];
+        env.extend(event.env());
+
+        tokio::spawn(async move {
+            match execute(&script, env).await {
+                Ok(status) if status.success() => debug!(hook = %script.display(), "Ran hook"),
+                Ok(status) => warn!("Hook {} exited with {}", script.display(), status),
+                Err(e) => warn!("Failed to run hook {}: {}", script.display(), e),
+            }
+        });
+    }
+
+    /// Run `on-extension-installed` for each extension in files written to
+    /// the profile
+    pub fn extensions_installed(&self, engine: &SyncEngine, files: &[String]) {
+        let installed: Vec<&str> = files
+            .iter()
+            .filter_map(|f| f.strip_prefix("extensions/")?.strip_suffix(".xpi"))
+            .collect();
+        if installed.is_empty() {
+            return;
+        }
+
+        let extensions = engine.get_extensions().unwrap_or_default();
+        for id in installed {
+            let name = extensions
+                .iter()
+                .find(|(ext_id, _, _)| ext_id == id)
+                .map_or_else(|| id.to_string(), |(_, name, _)| name.clone());
+            self.run(HookEvent::ExtensionInstalled {
+                id: id.to_string(),
+                name,
+            });
+        }
+    }
+}
+
+/// The hook script for `name`, if one is installed and executable
+fn find_script(dir: &Path, name: &str) -> Option<PathBuf> {
+    let path = dir.join(name);
+    let metadata = std::fs::metadata(&path).ok()?;
+    if !metadata.is_file() {
+        return None;
+    }
+    if metadata.permissions().mode() & 0o111 == 0 {
+        warn!("Hook {} is not executable, skipping", path.display());
+        return None;
+    }
+    Some(path)
+}
+
+async fn execute(script: &Path, env: Vec<(&'static str, String)>) -> Result<ExitStatus> {
+    let mut child = tokio::process::Command::new(script)
+        .envs(env)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .context("Failed to start")?;
+
+    tokio::time::timeout(HOOK_TIMEOUT, child.wait())
+        .await
+        .with_context(|| format!("Timed out after {}s", HOOK_TIMEOUT.as_secs()))?
+        .context("Failed to wait for hook")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_tab_received_env() {
+        let event = HookEvent::TabReceived {
+            url: "https://example.com/".to_string(),
+            title: None,
+            from_device: "phone".to_string(),
+            from_device_id: "device-b".to_string(),
+        };
+
+        assert_eq!(event.script_name(), "on-tab-received");
+        assert_eq!(
+            event.env(),
+            vec![
+                ("WOLFPACK_TAB_URL", "https://example.com/".to_string()),
+                ("WOLFPACK_FROM_DEVICE", "phone".to_string()),
+                ("WOLFPACK_FROM_DEVICE_ID", "device-b".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_script_requires_executable() {
+        let dir = tempdir().unwrap();
+        assert_eq!(find_script(dir.path(), "on-sync-complete"), None);
+
+        let path = dir.path().join("on-sync-complete");
+        std::fs::write(&path, "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(find_script(dir.path(), "on-sync-complete"), None);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(find_script(dir.path(), "on-sync-complete"), Some(path));
+    }
+}
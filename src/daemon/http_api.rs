@@ -1,18 +1,26 @@
 use axum::{
     Json, Router,
-    extract::State,
+    extract::{ConnectInfo, Request, State},
     http::{HeaderMap, StatusCode, header},
-    response::IntoResponse,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
     routing::{get, post},
 };
 use serde::{Deserialize, Serialize};
 use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Instant;
+use tokio::sync::{Mutex, RwLock};
 use tracing::{info, warn};
 
 use super::api_token::ApiTokenManager;
 use super::pairing::{PairingManager, PairingRequest, PairingResult};
+use super::rate_limit::{Client, RateLimiter};
+use super::webhook::{Notification, Webhook};
+use crate::net::{NearbyDevice, ServiceAdvertiser};
+use crate::redact;
+use crate::state::DeviceRecord;
+use crate::sync::{EngineHandle, Keep};
 
 /// Shared state for the HTTP API
 pub struct ApiState {
@@ -21,6 +29,10 @@ pub struct ApiState {
     pub device_id: String,
     pub device_name: String,
     pub public_key: String,
+    pub engine: EngineHandle,
+    pub webhook: Webhook,
+    /// Pairing advertisement, when mDNS is enabled
+    pub advertiser: Option<ServiceAdvertiser>,
 }
 
 /// Status response
@@ -29,9 +41,42 @@ struct StatusResponse {
     status: String,
     device_id: String,
     device_name: String,
+    emoji: Option<String>,
+    color: Option<String>,
     version: String,
 }
 
+/// Known devices response
+#[derive(Serialize)]
+struct DevicesResponse {
+    devices: Vec<DeviceEntry>,
+}
+
+#[derive(Serialize)]
+struct DeviceEntry {
+    id: String,
+    name: Option<String>,
+    emoji: Option<String>,
+    color: Option<String>,
+    onion: Option<String>,
+    last_seen: Option<String>,
+    last_sync: Option<String>,
+}
+
+impl From<DeviceRecord> for DeviceEntry {
+    fn from(device: DeviceRecord) -> Self {
+        Self {
+            id: device.id,
+            name: device.name,
+            emoji: device.emoji,
+            color: device.color,
+            onion: device.onion,
+            last_seen: device.last_seen,
+            last_sync: device.last_sync,
+        }
+    }
+}
+
 /// Pairing session created response
 #[derive(Serialize)]
 struct PairingSessionResponse {
@@ -57,6 +102,26 @@ struct JoinPairingResponse {
     public_key: Option<String>,
 }
 
+impl JoinPairingResponse {
+    /// Pairing didn't go through, for the reason `status` gives
+    fn failed(status: &str) -> Self {
+        Self {
+            status: status.to_string(),
+            device_id: None,
+            device_name: None,
+            public_key: None,
+        }
+    }
+}
+
+/// Devices advertising on the local network
+#[derive(Serialize)]
+struct NearbyDevicesResponse {
+    /// Whether this device is browsing (mDNS enabled)
+    enabled: bool,
+    devices: Vec<NearbyDevice>,
+}
+
 /// Pending pairing request response
 #[derive(Serialize)]
 struct PendingRequestResponse {
@@ -77,16 +142,83 @@ struct RespondToPairingRequest {
     accept: bool,
 }
 
+/// Reading list response
+#[derive(Serialize)]
+struct ReadingListResponse {
+    items: Vec<ReadingListEntry>,
+}
+
+#[derive(Serialize)]
+struct ReadingListEntry {
+    url: String,
+    title: Option<String>,
+    added_at: String,
+}
+
+/// Save a page to the reading list
+#[derive(Deserialize)]
+struct AddReadingListRequest {
+    url: String,
+    title: Option<String>,
+}
+
+/// Remove a page from the reading list
+#[derive(Deserialize)]
+struct RemoveReadingListRequest {
+    url: String,
+}
+
+/// Items held because the profile and the pack both changed them
+#[derive(Serialize)]
+struct DivergencesResponse {
+    divergences: Vec<DivergenceEntry>,
+}
+
+#[derive(Serialize)]
+struct DivergenceEntry {
+    category: String,
+    item: String,
+    local: String,
+    pack: String,
+    found_at: String,
+}
+
+/// Keep one side of a divergence, `local` or `pack`
+#[derive(Deserialize)]
+struct ResolveDivergenceRequest {
+    category: String,
+    item: String,
+    keep: String,
+}
+
+const TOKEN_HEADER: &str = "X-Wolfpack-Token";
+
+/// The rate limiter, and the API state to check tokens against
+type RateLimitState = (Arc<Mutex<RateLimiter>>, Arc<RwLock<ApiState>>);
+
 /// Create the HTTP API router
 pub fn create_router(state: Arc<RwLock<ApiState>>) -> Router {
     Router::new()
         .route("/health", get(health_check))
         .route("/status", get(get_status))
+        .route("/devices", get(get_devices))
         .route("/pair/initiate", post(initiate_pairing))
         .route("/pair/join", post(join_pairing))
         .route("/pair/pending", get(get_pending_request))
+        .route("/pair/nearby", get(get_nearby_devices))
         .route("/pair/respond", post(respond_to_pairing))
         .route("/pair/cancel", post(cancel_pairing))
+        .route(
+            "/reading-list",
+            get(get_reading_list).post(add_to_reading_list),
+        )
+        .route("/reading-list/remove", post(remove_from_reading_list))
+        .route("/divergences", get(get_divergences))
+        .route("/divergences/resolve", post(resolve_divergence))
+        .layer(middleware::from_fn_with_state(
+            (Arc::new(Mutex::new(RateLimiter::default())), state.clone()),
+            rate_limit,
+        ))
         .with_state(state)
 }
 
@@ -100,15 +232,61 @@ pub async fn start_server(state: Arc<RwLock<ApiState>>, port: u16) -> anyhow::Re
 
     info!("HTTP API listening on http://{}", addr);
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
 
+/// Reject clients over their request limit or locked out for failing to
+/// authenticate, and record whether each request did
+async fn rate_limit(
+    State((limiter, state)): State<RateLimitState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let ip = addr.ip();
+    let token = request
+        .headers()
+        .get(TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+    let client = match token {
+        Some(token) if state.read().await.token_manager.validate(&token) => Client::Token(token),
+        _ => Client::Address(ip),
+    };
+
+    let allowed = limiter.lock().await.check(ip, &client, Instant::now());
+    if let Err(retry_after) = allowed {
+        // Round up, so clients never retry before the limit lifts
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, seconds.to_string())],
+        )
+            .into_response();
+    }
+
+    // Only a right pairing code clears failures, so requests made between
+    // wrong ones don't let a guesser off
+    let joining = request.uri().path() == "/pair/join";
+    let response = next.run(request).await;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        limiter.lock().await.record_failure(&client, Instant::now());
+    } else if joining && response.status().is_success() {
+        limiter.lock().await.record_success(&client);
+    }
+    response
+}
+
 /// Validate API token from request headers
 fn validate_token(headers: &HeaderMap, state: &ApiState) -> Result<(), StatusCode> {
     let token = headers
-        .get("X-Wolfpack-Token")
+        .get(TOKEN_HEADER)
         .and_then(|v| v.to_str().ok())
         .ok_or(StatusCode::UNAUTHORIZED)?;
 
@@ -156,14 +334,47 @@ async fn get_status(
     check_origin(&headers)?;
     validate_token(&headers, &state)?;
 
+    // The name may have changed since startup with `wolfpack devices rename`
+    let this_device = state
+        .engine
+        .try_call(|engine| engine.devices())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .into_iter()
+        .find(|d| d.id == state.device_id)
+        .unwrap_or_default();
+
     Ok(Json(StatusResponse {
         status: "running".to_string(),
         device_id: state.device_id.clone(),
-        device_name: state.device_name.clone(),
+        device_name: this_device
+            .name
+            .unwrap_or_else(|| state.device_name.clone()),
+        emoji: this_device.emoji,
+        color: this_device.color,
         version: env!("CARGO_PKG_VERSION").to_string(),
     }))
 }
 
+async fn get_devices(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+) -> Result<Json<DevicesResponse>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let devices = state
+        .engine
+        .try_call(|engine| engine.devices())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(DevicesResponse {
+        devices: devices.into_iter().map(DeviceEntry::from).collect(),
+    }))
+}
+
 async fn initiate_pairing(
     headers: HeaderMap,
     State(state): State<Arc<RwLock<ApiState>>>,
@@ -184,11 +395,15 @@ async fn initiate_pairing(
     }))
 }
 
+/// Join a pairing session by its code
+///
+/// A wrong or expired code is `401`, like a wrong token, so guessing codes
+/// counts towards a lockout.
 async fn join_pairing(
     headers: HeaderMap,
     State(state): State<Arc<RwLock<ApiState>>>,
     Json(req): Json<JoinPairingRequest>,
-) -> Result<Json<JoinPairingResponse>, StatusCode> {
+) -> Result<(StatusCode, Json<JoinPairingResponse>), StatusCode> {
     let state = state.read().await;
     check_origin(&headers)?;
     validate_token(&headers, &state)?;
@@ -205,34 +420,32 @@ async fn join_pairing(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
-    let response = match result {
-        PairingResult::Accepted(resp) => JoinPairingResponse {
-            status: "accepted".to_string(),
-            device_id: Some(resp.device_id),
-            device_name: Some(resp.device_name),
-            public_key: Some(resp.public_key),
-        },
-        PairingResult::Rejected => JoinPairingResponse {
-            status: "rejected".to_string(),
-            device_id: None,
-            device_name: None,
-            public_key: None,
-        },
-        PairingResult::Expired => JoinPairingResponse {
-            status: "expired".to_string(),
-            device_id: None,
-            device_name: None,
-            public_key: None,
-        },
-        PairingResult::InvalidCode => JoinPairingResponse {
-            status: "invalid_code".to_string(),
-            device_id: None,
-            device_name: None,
-            public_key: None,
-        },
+    let (status, response) = match result {
+        PairingResult::Accepted(resp) => {
+            state.webhook.send(Notification::DevicePaired {
+                device_id: resp.device_id.clone(),
+                device_name: resp.device_name.clone(),
+            });
+            let response = JoinPairingResponse {
+                status: "accepted".to_string(),
+                device_id: Some(resp.device_id),
+                device_name: Some(resp.device_name),
+                public_key: Some(resp.public_key),
+            };
+            (StatusCode::OK, response)
+        }
+        PairingResult::Rejected => (StatusCode::OK, JoinPairingResponse::failed("rejected")),
+        PairingResult::Expired => (
+            StatusCode::UNAUTHORIZED,
+            JoinPairingResponse::failed("expired"),
+        ),
+        PairingResult::InvalidCode => (
+            StatusCode::UNAUTHORIZED,
+            JoinPairingResponse::failed("invalid_code"),
+        ),
     };
 
-    Ok(Json(response))
+    Ok((status, Json(response)))
 }
 
 async fn get_pending_request(
@@ -255,7 +468,7 @@ async fn get_pending_request(
             request: Some(PairingRequestInfo {
                 device_id: req.device_id,
                 device_name: req.device_name,
-                public_key_fingerprint: fingerprint(&req.public_key),
+                public_key_fingerprint: redact::fingerprint(&req.public_key),
             }),
         },
         None => PendingRequestResponse {
@@ -267,6 +480,25 @@ async fn get_pending_request(
     Ok(Json(response))
 }
 
+async fn get_nearby_devices(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+) -> Result<Json<NearbyDevicesResponse>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let devices = match &state.advertiser {
+        Some(advertiser) => advertiser.nearby().await,
+        None => Vec::new(),
+    };
+
+    Ok(Json(NearbyDevicesResponse {
+        enabled: state.advertiser.is_some(),
+        devices,
+    }))
+}
+
 async fn respond_to_pairing(
     headers: HeaderMap,
     State(state): State<Arc<RwLock<ApiState>>>,
@@ -276,6 +508,17 @@ async fn respond_to_pairing(
     check_origin(&headers)?;
     validate_token(&headers, &state)?;
 
+    // Who is being paired with, for the notification once accepted
+    let requester = if req.accept {
+        state
+            .pairing_manager
+            .get_pending_request()
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+    } else {
+        None
+    };
+
     let response = if req.accept {
         Some(super::pairing::PairingResponse {
             device_id: state.device_id.clone(),
@@ -292,6 +535,13 @@ async fn respond_to_pairing(
         .await
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
+    if let Some(requester) = requester {
+        state.webhook.send(Notification::DevicePaired {
+            device_id: requester.device_id,
+            device_name: requester.device_name,
+        });
+    }
+
     Ok(Json(serde_json::json!({ "status": "ok" })))
 }
 
@@ -312,15 +562,295 @@ async fn cancel_pairing(
     Ok(Json(serde_json::json!({ "status": "ok" })))
 }
 
-/// Generate a short fingerprint from a public key
-fn fingerprint(public_key: &str) -> String {
-    if public_key.len() >= 16 {
-        format!(
-            "{}...{}",
-            &public_key[..8],
-            &public_key[public_key.len() - 8..]
-        )
-    } else {
-        public_key.to_string()
+async fn get_reading_list(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+) -> Result<Json<ReadingListResponse>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let items = state
+        .engine
+        .try_call(|engine| engine.get_reading_list())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(ReadingListResponse {
+        items: items
+            .into_iter()
+            .map(|item| ReadingListEntry {
+                url: item.url,
+                title: item.title,
+                added_at: item.added_at,
+            })
+            .collect(),
+    }))
+}
+
+async fn add_to_reading_list(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+    Json(req): Json<AddReadingListRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    if req.url.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    state
+        .engine
+        .try_call(move |engine| engine.add_to_reading_list(&req.url, req.title.as_deref()))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn remove_from_reading_list(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+    Json(req): Json<RemoveReadingListRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let removed = state
+        .engine
+        .try_call(move |engine| {
+            let items = engine.get_reading_list()?;
+            if !items.iter().any(|item| item.url == req.url) {
+                return Ok(false);
+            }
+            engine.remove_from_reading_list(&req.url)?;
+            Ok(true)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !removed {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn get_divergences(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+) -> Result<Json<DivergencesResponse>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let divergences = state
+        .engine
+        .try_call(|engine| engine.divergences())
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(DivergencesResponse {
+        divergences: divergences
+            .into_iter()
+            .map(|divergence| DivergenceEntry {
+                local: divergence.local.describe(),
+                pack: divergence.describe_pack(),
+                category: divergence.category,
+                item: divergence.item,
+                found_at: divergence.found_at,
+            })
+            .collect(),
+    }))
+}
+
+async fn resolve_divergence(
+    headers: HeaderMap,
+    State(state): State<Arc<RwLock<ApiState>>>,
+    Json(req): Json<ResolveDivergenceRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let state = state.read().await;
+    check_origin(&headers)?;
+    validate_token(&headers, &state)?;
+
+    let keep = Keep::from_name(&req.keep).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let resolved = state
+        .engine
+        .try_call(move |engine| {
+            let divergences = engine.divergences()?;
+            if !divergences
+                .iter()
+                .any(|d| d.category == req.category && d.item == req.item)
+            {
+                return Ok(false);
+            }
+            engine.resolve_divergence(&req.category, &req.item, keep)?;
+            Ok(true)
+        })
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !resolved {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::crypto::KeyPair;
+    use crate::daemon::pairing::{PairingResponse, PairingState};
+    use crate::events::EventLog;
+    use crate::state::StateDb;
+    use crate::sync::SyncEngine;
+    use axum::body::Body;
+    use std::net::{IpAddr, Ipv4Addr};
+    use tower::ServiceExt;
+
+    const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn test_state(dir: &std::path::Path) -> ApiState {
+        let mut config = Config::default();
+        config.paths.profile = Some(dir.join("profile"));
+        config.paths.sync_dir = dir.join("sync");
+        config.backups.dir = dir.join("backups");
+        let event_log = EventLog::new(
+            config.paths.sync_dir.clone(),
+            config.device.id.clone(),
+            KeyPair::generate(),
+        );
+        let engine =
+            SyncEngine::new(config, event_log, StateDb::open_in_memory().unwrap()).unwrap();
+
+        let (pairing_manager, mut commands) = PairingManager::new();
+        tokio::spawn(async move {
+            let mut pairing = PairingState::new();
+            while let Some(command) = commands.recv().await {
+                pairing.handle_command(command);
+            }
+        });
+        ApiState {
+            token_manager: ApiTokenManager::load_or_create(dir).unwrap(),
+            pairing_manager,
+            device_id: "device-a".to_string(),
+            device_name: "laptop".to_string(),
+            public_key: "key-a".to_string(),
+            engine: EngineHandle::spawn(engine).unwrap(),
+            webhook: Webhook::start(None, "laptop", None).unwrap(),
+            advertiser: None,
+        }
+    }
+
+    /// Send a request to the router as the server would, with the address
+    /// it came from
+    async fn send(
+        app: &Router,
+        from: IpAddr,
+        path: &str,
+        token: Option<&str>,
+        body: Option<&str>,
+    ) -> Response {
+        let method = if body.is_some() { "POST" } else { "GET" };
+        let mut request = Request::builder().method(method).uri(path);
+        if let Some(token) = token {
+            request = request.header(TOKEN_HEADER, token);
+        }
+        if body.is_some() {
+            request = request.header(header::CONTENT_TYPE, "application/json");
+        }
+        let mut request = request
+            .body(Body::from(body.unwrap_or_default().to_string()))
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(ConnectInfo(SocketAddr::new(from, 40000)));
+        app.clone().oneshot(request).await.unwrap()
+    }
+
+    fn join(code: &str) -> String {
+        serde_json::json!({
+            "code": code,
+            "device_id": "device-b",
+            "device_name": "desktop",
+            "public_key": "key-b",
+        })
+        .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_locks_out_failing_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = create_router(Arc::new(RwLock::new(test_state(dir.path()))));
+
+        for _ in 0..6 {
+            let response = send(&app, CLIENT, "/status", Some("wrong"), None).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = send(&app, CLIENT, "/health", None, None).await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+
+        // Other addresses are counted on their own
+        let other = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2));
+        let response = send(&app, other, "/health", None, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_resets_on_pairing() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = Arc::new(RwLock::new(test_state(dir.path())));
+        let app = create_router(state.clone());
+        let token = state.read().await.token_manager.token().to_string();
+        let token = Some(token.as_str());
+
+        // One short of a lockout
+        for _ in 0..5 {
+            let response = send(&app, CLIENT, "/pair/join", token, Some(&join("000000"))).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+
+        let code = state
+            .read()
+            .await
+            .pairing_manager
+            .create_session()
+            .await
+            .unwrap();
+        let accepting = state.clone();
+        tokio::spawn(async move {
+            let state = accepting.read().await;
+            while state
+                .pairing_manager
+                .get_pending_request()
+                .await
+                .unwrap()
+                .is_none()
+            {
+                tokio::task::yield_now().await;
+            }
+            let response = PairingResponse {
+                device_id: "device-a".to_string(),
+                device_name: "laptop".to_string(),
+                public_key: "key-a".to_string(),
+            };
+            state
+                .pairing_manager
+                .respond(true, Some(response))
+                .await
+                .unwrap();
+        });
+        let response = send(&app, CLIENT, "/pair/join", token, Some(&join(&code))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Counting starts over, so one more wrong code isn't a lockout
+        let response = send(&app, CLIENT, "/pair/join", token, Some(&join("000000"))).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(&app, CLIENT, "/health", token, None).await;
+        assert_eq!(response.status(), StatusCode::OK);
     }
 }
@@ -1,131 +1,981 @@
 use anyhow::Result;
-use libp2p::PeerId;
-use std::collections::HashMap;
+use chrono::TimeDelta;
+use std::path::Path;
 use std::sync::Arc;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
+use tokio::sync::{Notify, mpsc, watch};
 
-use crate::sync::SyncEngine;
+use crate::events::{ExtensionSource, SyncCategory};
+use crate::ipc::{
+    Command, DaemonStatus, Envelope, ExtensionEntry, IPC_VERSION, IpcError, Payload, QueueAction,
+    Request, Response, TabEntry,
+};
+use crate::lanes::Lane;
+use crate::state::{SyncSession, TabDelivery};
+use crate::sync::{
+    Divergence, EngineHandle, Keep, ReadingListItem, ReplayPoint, StateExport, SyncEngine,
+};
+
+use super::{ConnectedPeer, Hooks};
+
+type Reply = std::result::Result<Payload, IpcError>;
+/// The answer to a command whose answer is only shown to people
+type TextReply = std::result::Result<String, IpcError>;
+
+/// Tells subscribed clients to look at the daemon's state again
+///
+/// It carries the number of connected peers, which only the daemon loop
+/// knows; everything else subscribers are sent comes from the engine.
+#[derive(Debug, Clone)]
+pub struct StateChanges {
+    peers: Arc<watch::Sender<usize>>,
+}
+
+impl Default for StateChanges {
+    fn default() -> Self {
+        Self {
+            peers: Arc::new(watch::Sender::new(0)),
+        }
+    }
+}
+
+impl StateChanges {
+    /// Something subscribers are sent may have changed
+    pub fn notify(&self) {
+        self.peers.send_modify(|_| {});
+    }
+
+    pub fn set_peers(&self, peers: usize) {
+        self.peers.send_replace(peers);
+    }
+
+    fn subscribe(&self) -> watch::Receiver<usize> {
+        self.peers.subscribe()
+    }
+}
+
+/// What commands on one connection are carried out with
+struct IpcContext {
+    engine: EngineHandle,
+    peers: Vec<ConnectedPeer>,
+    /// Times the engine's or swarm's queue was full when the client connected
+    overflows: u64,
+    hooks: Hooks,
+    shutdown: Arc<Notify>,
+    changes: StateChanges,
+}
 
 /// Handle an IPC client connection
+///
+/// Each request is carried out in its own task, and its response written
+/// as soon as it's ready, so a slow command doesn't hold up the others.
+/// Subscriptions are sent updates until the client disconnects.
+#[allow(clippy::too_many_arguments)] // Everything a connection's commands can reach
 pub async fn handle_ipc_client(
     stream: tokio::net::UnixStream,
-    engine: Arc<Mutex<SyncEngine>>,
-    peers: HashMap<PeerId, String>,
+    engine: EngineHandle,
+    peers: Vec<ConnectedPeer>,
+    overflows: u64,
+    hooks: Hooks,
+    shutdown: Arc<Notify>,
+    changes: StateChanges,
 ) -> Result<()> {
     let (reader, mut writer) = stream.into_split();
-    let mut reader = BufReader::new(reader);
-    let mut line = String::new();
+    let mut lines = BufReader::new(reader).lines();
+    let context = Arc::new(IpcContext {
+        engine,
+        peers,
+        overflows,
+        hooks,
+        shutdown,
+        changes,
+    });
+
+    let (responses, mut outgoing) = mpsc::unbounded_channel::<Response>();
+    let writing = tokio::spawn(async move {
+        while let Some(response) = outgoing.recv().await {
+            let mut line = serde_json::to_string(&response)?;
+            line.push('\n');
+            writer.write_all(line.as_bytes()).await?;
+        }
+        anyhow::Ok(())
+    });
+
+    let mut subscriptions = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match parse_request(&line) {
+            Ok(request) => request,
+            Err(response) => {
+                let _ = responses.send(response);
+                continue;
+            }
+        };
+        let subscribe = request.command == Command::Subscribe;
+        let task = tokio::spawn(answer(request, context.clone(), responses.clone()));
+        if subscribe {
+            subscriptions.push(task);
+        }
+    }
 
-    while reader.read_line(&mut line).await? > 0 {
-        let response = process_command(line.trim(), &engine, &peers).await;
-        writer.write_all(response.as_bytes()).await?;
-        writer.write_all(b"\n").await?;
-        line.clear();
+    // The writer finishes once every request's task has answered and the
+    // subscriptions are gone
+    for subscription in subscriptions {
+        subscription.abort();
     }
+    drop(responses);
+    writing.await?
+}
 
-    Ok(())
+/// Answer a request, or keep a subscription's updates coming
+async fn answer(
+    request: Request,
+    context: Arc<IpcContext>,
+    responses: mpsc::UnboundedSender<Response>,
+) {
+    if request.command == Command::Subscribe {
+        return push_updates(request.id, &context, &responses).await;
+    }
+    let result = process_command(request.command, &context).await;
+    let _ = responses.send(Response {
+        id: request.id,
+        result,
+    });
+    // Subscribers are only sent what actually changed
+    context.changes.notify();
 }
 
-async fn process_command(
-    command: &str,
-    engine: &Arc<Mutex<SyncEngine>>,
-    peers: &HashMap<PeerId, String>,
-) -> String {
-    let parts: Vec<&str> = command.split_whitespace().collect();
-    if parts.is_empty() {
-        return "ERROR: Empty command".to_string();
+/// Send a subscribed client the status and pending tabs, then again each
+/// time they change
+async fn push_updates(id: u64, context: &IpcContext, responses: &mpsc::UnboundedSender<Response>) {
+    let mut changes = context.changes.subscribe();
+    let mut last = None;
+    loop {
+        let peer_count = *changes.borrow_and_update();
+        let overflows = context.overflows;
+        let result = context
+            .engine
+            .call(move |engine| cmd_update(engine, peer_count, overflows))
+            .await
+            .unwrap_or_else(|e| Err(IpcError::failed(e)));
+        if last.as_ref() != Some(&result) {
+            let update = Response {
+                id,
+                result: result.clone(),
+            };
+            if responses.send(update).is_err() {
+                return;
+            }
+            last = Some(result);
+        }
+        if changes.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read a request, or the response refusing it
+fn parse_request(line: &str) -> std::result::Result<Request, Response> {
+    let envelope: Envelope = serde_json::from_str(line).map_err(|e| Response {
+        id: 0,
+        result: Err(IpcError::bad_request(e)),
+    })?;
+    if envelope.version != IPC_VERSION {
+        return Err(Response {
+            id: envelope.id,
+            result: Err(IpcError::UnsupportedVersion {
+                daemon: IPC_VERSION,
+                client: envelope.version,
+            }),
+        });
+    }
+    serde_json::from_str(line).map_err(|e| Response {
+        id: envelope.id,
+        result: Err(IpcError::bad_request(e)),
+    })
+}
+
+/// Answer a request, on the engine's task unless it doesn't need the engine
+async fn process_command(command: Command, context: &IpcContext) -> Reply {
+    match command {
+        Command::Peers { verbose } => Ok(Payload::message(cmd_peers(&context.peers, verbose))),
+        Command::Send { device, url, title } => cmd_send(device, url, title, &context.engine)
+            .await
+            .map(Payload::message),
+        Command::Shutdown => {
+            context.shutdown.notify_one();
+            Ok(Payload::message("Shutting down"))
+        }
+        command => {
+            let lane = command_lane(&command);
+            let peer_count = context.peers.len();
+            let overflows = context.overflows;
+            let hooks = context.hooks.clone();
+            context
+                .engine
+                .call_in(lane, move |engine| {
+                    run_command(command, engine, peer_count, overflows, &hooks)
+                })
+                .await
+                .map_err(IpcError::failed)?
+        }
     }
+}
 
-    match parts[0] {
-        "status" => cmd_status(engine, peers).await,
-        "peers" => cmd_peers(peers),
-        "tabs" => cmd_tabs(engine).await,
-        "send" => cmd_send(&parts, engine).await,
-        "open" => cmd_open(&parts, engine).await,
-        _ => format!("ERROR: Unknown command: {}", parts[0]),
+/// Commands that rebuild or walk the whole event log wait behind tab sends and
+/// quick queries
+fn command_lane(command: &Command) -> Lane {
+    match command {
+        Command::Import { .. }
+        | Command::Gc
+        | Command::Replay { .. }
+        | Command::Diff { .. }
+        | Command::Report
+        | Command::Queue {
+            action: QueueAction::Apply,
+        } => Lane::Bulk,
+        _ => Lane::Interactive,
     }
 }
 
-async fn cmd_status(engine: &Arc<Mutex<SyncEngine>>, peers: &HashMap<PeerId, String>) -> String {
-    let engine = engine.lock().await;
-    format!(
-        "OK: Device {} - {} peers connected",
-        engine.device_id(),
-        peers.len()
-    )
+/// Answer a command on the engine's task
+#[allow(clippy::cognitive_complexity)] // Command dispatcher with an arm per command
+#[allow(clippy::too_many_lines)] // One arm per command
+fn run_command(
+    command: Command,
+    engine: &mut SyncEngine,
+    peer_count: usize,
+    overflows: u64,
+    hooks: &Hooks,
+) -> Reply {
+    let text = match command {
+        Command::Status => return cmd_status(engine, peer_count, overflows),
+        Command::Tabs => return cmd_tabs(engine),
+        Command::DeviceNames => return cmd_device_names(engine),
+        Command::Extensions => return cmd_extensions(engine),
+        Command::SyncHistory => cmd_sync_history(engine),
+        Command::SentTabs => cmd_sent_tabs(engine),
+        Command::Open { tab_id } => cmd_open(&tab_id, engine),
+        Command::Pause { category, seconds } => {
+            cmd_pause(category, seconds.map(TimeDelta::seconds), engine)
+        }
+        Command::Resume { category } => cmd_resume(category, engine),
+        Command::Queue { action } => cmd_queue(action, engine, hooks),
+        Command::Divergences => cmd_divergences(engine),
+        Command::ResolveDivergence {
+            category,
+            item,
+            keep,
+        } => cmd_resolve_divergence(&category, &item, &keep, engine),
+        Command::ReadingList => cmd_reading_list(engine),
+        Command::ReadingListAdd { url, title } => {
+            cmd_reading_list_add(&url, title.as_deref(), engine)
+        }
+        Command::ReadingListRemove { url } => cmd_reading_list_remove(&url, engine),
+        Command::RenameDevice { device, name } => cmd_rename_device(&device, &name, engine),
+        Command::LabelDevice {
+            device,
+            emoji,
+            color,
+        } => cmd_label_device(&device, emoji.as_deref(), color.as_deref(), engine),
+        Command::UnbindDevice { device } => cmd_unbind_device(&device, engine),
+        Command::VerifyKey { device_id, key } => cmd_verify_key(&device_id, &key, engine),
+        Command::ExtensionMatrix { missing } => cmd_extension_matrix(missing, engine),
+        Command::InstallExtension { path, source } => cmd_install_extension(&path, source, engine),
+        Command::UninstallExtension { id } => cmd_uninstall_extension(&id, engine),
+        Command::Import { path } => cmd_import(&path, engine),
+        Command::Gc => cmd_gc(engine),
+        Command::Replay { until } => cmd_replay(until.as_deref(), engine),
+        Command::Diff { from, to } => cmd_diff(&from, to.as_deref(), engine),
+        Command::Report => cmd_report(engine),
+        // Answered by process_command without the engine
+        Command::Peers { .. } | Command::Send { .. } | Command::Shutdown | Command::Subscribe => {
+            return Err(IpcError::failed(
+                "Command isn't answered by the sync engine",
+            ));
+        }
+    };
+    text.map(Payload::message)
 }
 
-fn cmd_peers(peers: &HashMap<PeerId, String>) -> String {
+fn cmd_status(engine: &SyncEngine, peer_count: usize, overflows: u64) -> Reply {
+    daemon_status(engine, peer_count, overflows).map(Payload::Status)
+}
+
+fn daemon_status(
+    engine: &SyncEngine,
+    peer_count: usize,
+    overflows: u64,
+) -> std::result::Result<DaemonStatus, IpcError> {
+    let paused = engine.pause_state().map_err(IpcError::failed)?;
+    let pauses = describe_pauses(engine).map_err(IpcError::failed)?;
+    Ok(DaemonStatus {
+        device_id: engine.device_id().to_string(),
+        device_label: engine.device_label(engine.device_id()),
+        peers: peer_count,
+        paused: paused.is_some(),
+        pauses,
+        observing: engine.is_observing(),
+        overflows,
+    })
+}
+
+/// What's pushed to subscribers: the status and pending tabs together
+fn cmd_update(engine: &SyncEngine, peer_count: usize, overflows: u64) -> Reply {
+    Ok(Payload::Update {
+        status: Box::new(daemon_status(engine, peer_count, overflows)?),
+        tabs: pending_tabs(engine)?,
+    })
+}
+
+/// Sessions shown by `wolfpack status --history`
+const HISTORY_SESSIONS: u32 = 20;
+
+fn cmd_sync_history(engine: &mut SyncEngine) -> TextReply {
+    let sessions = engine
+        .sync_sessions(HISTORY_SESSIONS)
+        .map_err(IpcError::failed)?;
+    if sessions.is_empty() {
+        return Ok("No sync sessions yet".to_string());
+    }
+    let converged: Vec<(String, String)> = engine
+        .last_converged()
+        .map_err(IpcError::failed)?
+        .into_iter()
+        .map(|(device, at)| (engine.device_label(&device), at))
+        .collect();
+    let sessions: Vec<(String, SyncSession)> = sessions
+        .into_iter()
+        .map(|session| {
+            let name = match &session.device {
+                Some(device) => engine.device_label(device),
+                None => session.peer.clone(),
+            };
+            (name, session)
+        })
+        .collect();
+    Ok(format_sync_history(&sessions, &converged))
+}
+
+/// How sync is paused, or else how each paused category is
+fn describe_pauses(engine: &SyncEngine) -> Result<Vec<String>> {
+    if let Some(state) = engine.pause_state()? {
+        return Ok(vec![format!("sync {}", state.describe())]);
+    }
+
+    Ok(engine
+        .category_pauses()?
+        .iter()
+        .map(|(category, state)| format!("{} {}", category, state.describe()))
+        .collect())
+}
+
+fn cmd_peers(peers: &[ConnectedPeer], verbose: bool) -> String {
     if peers.is_empty() {
-        return "OK: No peers connected".to_string();
+        return "No peers connected".to_string();
     }
     let list: Vec<String> = peers
         .iter()
-        .map(|(id, name)| format!("  {}: {}", id, name))
+        .map(|peer| {
+            if verbose {
+                peer.describe()
+            } else {
+                peer.summary()
+            }
+        })
+        .collect();
+    let separator = if verbose { "\n\n" } else { "\n" };
+    list.join(separator)
+}
+
+fn cmd_tabs(engine: &mut SyncEngine) -> Reply {
+    pending_tabs(engine).map(|tabs| Payload::Tabs { tabs })
+}
+
+fn pending_tabs(engine: &SyncEngine) -> std::result::Result<Vec<TabEntry>, IpcError> {
+    let tabs = engine.get_pending_tabs().map_err(IpcError::failed)?;
+    Ok(tabs
+        .into_iter()
+        .map(|t| TabEntry {
+            from: engine.device_label(&t.from_device),
+            duplicates: t.duplicates.len(),
+            id: t.id,
+            url: t.url,
+        })
+        .collect())
+}
+
+fn cmd_sent_tabs(engine: &mut SyncEngine) -> TextReply {
+    let tabs = engine.get_sent_tabs().map_err(IpcError::failed)?;
+    if tabs.is_empty() {
+        return Ok("No sent tabs".to_string());
+    }
+    let list: Vec<String> = tabs
+        .iter()
+        .map(|t| {
+            let to = engine.device_label(&t.to_device);
+            let status = match (t.status, t.attempts) {
+                (TabDelivery::Pending, 0) => "pending".to_string(),
+                (TabDelivery::Pending, 1) => "pending, 1 attempt".to_string(),
+                (TabDelivery::Pending, n) => format!("pending, {} attempts", n),
+                (status, _) => status.as_str().to_string(),
+            };
+            format!("{}: {} (to {}) - {}", t.id, t.url, to, status)
+        })
+        .collect();
+    Ok(list.join("\n"))
+}
+
+async fn cmd_send(
+    device: String,
+    url: String,
+    title: Option<String>,
+    engine: &EngineHandle,
+) -> TextReply {
+    let tab = engine
+        .send_tab(device, url, title)
+        .await
+        .map_err(IpcError::failed)?;
+    let to = engine
+        .call(move |engine| engine.device_label(&tab.to_device))
+        .await
+        .map_err(IpcError::failed)?;
+    Ok(format!("Tab queued for {}", to))
+}
+
+fn cmd_open(tab_id: &str, engine: &mut SyncEngine) -> TextReply {
+    let tabs = engine.get_pending_tabs().map_err(IpcError::failed)?;
+    let Some(tab) = tabs.iter().find(|t| t.id == tab_id) else {
+        return Err(IpcError::not_found("Tab not found"));
+    };
+
+    engine.open_tab(&tab.url).map_err(IpcError::failed)?;
+    engine.acknowledge_tab(tab_id).map_err(IpcError::failed)?;
+    Ok("Tab opened".to_string())
+}
+
+fn cmd_pause(
+    category: Option<SyncCategory>,
+    duration: Option<TimeDelta>,
+    engine: &mut SyncEngine,
+) -> TextReply {
+    let scope = category.map_or("Sync".to_string(), |c| format!("{} sync", c));
+    match engine.pause(category, duration) {
+        Ok(state) => Ok(format!("{} {}", scope, state.describe())),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_resume(category: Option<SyncCategory>, engine: &mut SyncEngine) -> TextReply {
+    engine.resume(category).map_err(IpcError::failed)?;
+    Ok(match category {
+        Some(category) => format!("{} sync resumed", category),
+        None => "Sync resumed".to_string(),
+    })
+}
+
+fn cmd_queue(action: QueueAction, engine: &mut SyncEngine, hooks: &Hooks) -> TextReply {
+    match action {
+        QueueAction::List => match engine.describe_write_queue() {
+            Ok(writes) if writes.is_empty() => Ok("No pending writes".to_string()),
+            Ok(writes) => Ok(format_queue(&writes)),
+            Err(e) => Err(IpcError::failed(e)),
+        },
+        QueueAction::Apply => match engine.apply_write_queue() {
+            Ok(files) if files.is_empty() => Ok("No pending writes".to_string()),
+            Ok(files) => {
+                hooks.extensions_installed(engine, &files);
+                Ok(format!("Applied {}", files.join(", ")))
+            }
+            Err(e) => Err(IpcError::failed(e)),
+        },
+        QueueAction::Discard => Ok(format!(
+            "Discarded {} pending writes",
+            engine.discard_write_queue()
+        )),
+    }
+}
+
+fn cmd_divergences(engine: &mut SyncEngine) -> TextReply {
+    match engine.divergences() {
+        Ok(divergences) if divergences.is_empty() => Ok("No divergences".to_string()),
+        Ok(divergences) => Ok(format_divergences(&divergences)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_resolve_divergence(
+    category: &str,
+    item: &str,
+    keep: &str,
+    engine: &mut SyncEngine,
+) -> TextReply {
+    let keep = Keep::from_name(keep).map_err(IpcError::bad_request)?;
+    match engine.resolve_divergence(category, item, keep) {
+        Ok(_) => Ok(format!("Kept the {} change to {}", keep.as_str(), item)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_reading_list(engine: &mut SyncEngine) -> TextReply {
+    match engine.get_reading_list() {
+        Ok(items) if items.is_empty() => Ok("Reading list is empty".to_string()),
+        Ok(items) => Ok(format_reading_list(&items)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_reading_list_add(url: &str, title: Option<&str>, engine: &mut SyncEngine) -> TextReply {
+    match engine.add_to_reading_list(url, title) {
+        Ok(_) => Ok(format!("Saved {} to reading list", url)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_reading_list_remove(url: &str, engine: &mut SyncEngine) -> TextReply {
+    match engine.remove_from_reading_list(url) {
+        Ok(_) => Ok(format!("Removed {} from reading list", url)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_rename_device(device: &str, name: &str, engine: &mut SyncEngine) -> TextReply {
+    match engine.rename_device(device, name) {
+        Ok(id) => Ok(format!("Renamed {} to {}", id, name)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_label_device(
+    device: &str,
+    emoji: Option<&str>,
+    color: Option<&str>,
+    engine: &mut SyncEngine,
+) -> TextReply {
+    match engine.label_device(device, emoji, color) {
+        Ok(id) => Ok(format!("Labeled {} as {}", id, engine.device_label(&id))),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_unbind_device(device: &str, engine: &mut SyncEngine) -> TextReply {
+    match engine.unbind_device(device) {
+        Ok(id) => Ok(format!(
+            "Unbound {}; the next peer to prove it holds its key is bound",
+            id
+        )),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_verify_key(device_id: &str, key: &str, engine: &mut SyncEngine) -> TextReply {
+    match engine.verify_device_key(device_id, key) {
+        Ok(()) => Ok(format!("Marked the key of {} verified", device_id)),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+/// Other devices' names, for shell completion
+fn cmd_device_names(engine: &mut SyncEngine) -> Reply {
+    let devices = engine.devices().map_err(IpcError::failed)?;
+    let names = devices
+        .into_iter()
+        .filter(|d| d.id != engine.device_id())
+        .map(|d| d.name.unwrap_or(d.id))
+        .collect();
+    Ok(Payload::DeviceNames { names })
+}
+
+/// Synced extensions' IDs and names, for shell completion
+fn cmd_extensions(engine: &mut SyncEngine) -> Reply {
+    let extensions = engine.get_extensions().map_err(IpcError::failed)?;
+    let extensions = extensions
+        .into_iter()
+        .map(|(id, name, _)| ExtensionEntry { id, name })
         .collect();
-    format!("OK:\n{}", list.join("\n"))
+    Ok(Payload::Extensions { extensions })
+}
+
+fn cmd_extension_matrix(missing: bool, engine: &mut SyncEngine) -> TextReply {
+    match engine.extension_matrix() {
+        Ok(matrix) => Ok(matrix.describe(missing)),
+        Err(e) => Err(IpcError::failed(format!("{:#}", e))),
+    }
+}
+
+fn cmd_install_extension(
+    path: &Path,
+    source: Option<ExtensionSource>,
+    engine: &mut SyncEngine,
+) -> TextReply {
+    match engine.install_extension(path, source) {
+        Ok(result) => Ok(format!(
+            "Installed {} v{} ({}) for the pack. It's written to the profile with the next sync; restart LibreWolf then to activate it.",
+            result.name, result.version, result.id
+        )),
+        Err(e) => Err(IpcError::failed(format!("{:#}", e))),
+    }
+}
+
+fn cmd_uninstall_extension(id: &str, engine: &mut SyncEngine) -> TextReply {
+    match engine.uninstall_extension(id) {
+        Ok(()) => Ok(format!(
+            "Uninstalled {} from the pack. Restart LibreWolf to complete removal.",
+            id
+        )),
+        Err(e) => Err(IpcError::failed(e)),
+    }
+}
+
+fn cmd_gc(engine: &mut SyncEngine) -> TextReply {
+    match engine.collect_garbage() {
+        Ok(report) => Ok(report.describe()),
+        Err(e) => Err(IpcError::failed(format!(
+            "Garbage collection failed: {}",
+            e
+        ))),
+    }
+}
+
+fn cmd_replay(until: Option<&str>, engine: &mut SyncEngine) -> TextReply {
+    let until = until
+        .map(str::parse::<ReplayPoint>)
+        .transpose()
+        .map_err(IpcError::bad_request)?;
+
+    match engine.replay(until) {
+        Ok(report) => Ok(report.describe()),
+        Err(e) => Err(IpcError::failed(format!("Replay failed: {:#}", e))),
+    }
 }
 
-async fn cmd_tabs(engine: &Arc<Mutex<SyncEngine>>) -> String {
-    let engine = engine.lock().await;
-    match engine.get_pending_tabs() {
-        Ok(tabs) if tabs.is_empty() => "OK: No pending tabs".to_string(),
-        Ok(tabs) => {
-            let list: Vec<String> = tabs
+fn cmd_report(engine: &mut SyncEngine) -> TextReply {
+    match engine.health_report() {
+        Ok(report) => Ok(report.describe()),
+        Err(e) => Err(IpcError::failed(format!("Report failed: {:#}", e))),
+    }
+}
+
+fn cmd_diff(from: &str, to: Option<&str>, engine: &mut SyncEngine) -> TextReply {
+    let from = from.parse::<ReplayPoint>().map_err(IpcError::bad_request)?;
+    let to = to
+        .map(str::parse::<ReplayPoint>)
+        .transpose()
+        .map_err(IpcError::bad_request)?;
+
+    let to_label = to.map_or("now".to_string(), |to| to.to_string());
+    match engine.history_diff(from, to) {
+        Ok(changes) if changes.is_empty() => {
+            Ok(format!("No changes between {} and {}", from, to_label))
+        }
+        Ok(changes) => {
+            let lines: Vec<String> = changes
                 .iter()
-                .map(|t| format!("{}: {} (from {})", t.id, t.url, t.from_device))
+                .map(|event| format!("{}: {}", event.category(), event.describe()))
                 .collect();
-            format!("OK:\n{}", list.join("\n"))
+            Ok(lines.join("\n"))
         }
-        Err(e) => format!("ERROR: {}", e),
+        Err(e) => Err(IpcError::failed(format!("{:#}", e))),
     }
 }
 
-async fn cmd_send(parts: &[&str], engine: &Arc<Mutex<SyncEngine>>) -> String {
-    if parts.len() < 3 {
-        return "ERROR: Usage: send <device> <url> [title]".to_string();
+fn cmd_import(path: &Path, engine: &mut SyncEngine) -> TextReply {
+    let export = std::fs::File::open(path)
+        .map_err(anyhow::Error::from)
+        .and_then(StateExport::read)
+        .map_err(|e| IpcError::failed(format!("Failed to read {}: {}", path.display(), e)))?;
+    match engine.import_state(&export) {
+        Ok(0) => Ok("Nothing to import, state already matches".to_string()),
+        Ok(count) => Ok(format!(
+            "Imported {} changes from {}",
+            count, export.device_id
+        )),
+        Err(e) => Err(IpcError::failed(e)),
     }
+}
 
-    let device = parts[1];
-    let url = parts[2];
-    let title = if parts.len() > 3 {
-        Some(parts[3..].join(" "))
-    } else {
-        None
-    };
+fn format_reading_list(items: &[ReadingListItem]) -> String {
+    let lines: Vec<String> = items
+        .iter()
+        .map(|item| match &item.title {
+            Some(title) => format!("{} ({})", item.url, title),
+            None => item.url.clone(),
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn format_divergences(divergences: &[Divergence]) -> String {
+    let lines: Vec<String> = divergences
+        .iter()
+        .map(|d| {
+            format!(
+                "{} {}\n  local: {}\n  pack: {}",
+                d.category,
+                d.item,
+                d.local.describe(),
+                d.describe_pack()
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
 
-    let mut engine = engine.lock().await;
-    match engine.send_tab(device, url, title.as_deref()) {
-        Ok(_) => format!("OK: Tab queued for {}", device),
-        Err(e) => format!("ERROR: {}", e),
+/// When each device last caught up, then each session, newest first
+fn format_sync_history(
+    sessions: &[(String, SyncSession)],
+    converged: &[(String, String)],
+) -> String {
+    let mut lines = vec!["Last caught up:".to_string()];
+    if converged.is_empty() {
+        lines.push("  (never)".to_string());
+    }
+    for (name, at) in converged {
+        lines.push(format!("  {}: {}", name, format_time(at)));
+    }
+    lines.push("\nRecent sessions:".to_string());
+    for (name, session) in sessions {
+        let seconds = f64::from(session.duration_ms) / 1000.0;
+        let outcome = match &session.error {
+            None => format!("caught up in {:.1}s", seconds),
+            Some(error) => format!("failed after {:.1}s: {}", seconds, error),
+        };
+        lines.push(format!(
+            "  {} {} - {}, {} files received, {} new events",
+            format_time(&session.finished_at),
+            name,
+            outcome,
+            session.files_received,
+            session.events_applied
+        ));
     }
+    lines.join("\n")
 }
 
-async fn cmd_open(parts: &[&str], engine: &Arc<Mutex<SyncEngine>>) -> String {
-    if parts.len() < 2 {
-        return "ERROR: Usage: open <tab_id>".to_string();
+fn format_time(timestamp: &str) -> String {
+    chrono::DateTime::parse_from_rfc3339(timestamp)
+        .map(|t| {
+            t.with_timezone(&chrono::Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_else(|_| timestamp.to_string())
+}
+
+fn format_queue(writes: &[(String, Vec<String>)]) -> String {
+    let mut lines = Vec::new();
+    for (target, diff) in writes {
+        lines.push(target.clone());
+        lines.extend(diff.iter().map(|l| format!("  {}", l)));
     }
+    lines.join("\n")
+}
 
-    let tab_id = parts[1];
-    let mut engine = engine.lock().await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::crypto::KeyPair;
+    use crate::events::EventLog;
+    use crate::state::StateDb;
+    use tokio::io::Lines;
+    use tokio::net::UnixStream;
+    use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 
-    let tabs = match engine.get_pending_tabs() {
-        Ok(t) => t,
-        Err(e) => return format!("ERROR: {}", e),
-    };
+    fn test_engine(dir: &Path) -> SyncEngine {
+        let profile = dir.join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        std::fs::write(profile.join("prefs.js"), "").unwrap();
+        let mut config = Config::default();
+        config.paths.profile = Some(profile);
+        config.paths.sync_dir = dir.join("sync");
+        config.backups.dir = dir.join("backups");
+        let event_log = EventLog::new(
+            config.paths.sync_dir.clone(),
+            config.device.id.clone(),
+            KeyPair::generate(),
+        );
+        SyncEngine::new(config, event_log, StateDb::open_in_memory().unwrap()).unwrap()
+    }
 
-    let Some(tab) = tabs.iter().find(|t| t.id == tab_id) else {
-        return "ERROR: Tab not found".to_string();
-    };
+    /// The next update pushed for a subscription, skipping other responses
+    async fn next_update(
+        lines: &mut Lines<BufReader<OwnedReadHalf>>,
+        id: u64,
+    ) -> (DaemonStatus, Vec<TabEntry>) {
+        loop {
+            let line = tokio::time::timeout(std::time::Duration::from_secs(5), lines.next_line())
+                .await
+                .expect("no update was pushed")
+                .unwrap()
+                .unwrap();
+            let response: Response = serde_json::from_str(&line).unwrap();
+            if response.id != id {
+                continue;
+            }
+            let Ok(Payload::Update { status, tabs }) = response.result else {
+                panic!("expected an update, got {:?}", response.result);
+            };
+            return (*status, tabs);
+        }
+    }
+
+    async fn send(writer: &mut OwnedWriteHalf, id: u64, command: Command) {
+        let mut line = serde_json::to_string(&Request::new(id, command)).unwrap();
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_subscribe() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = EngineHandle::spawn(test_engine(dir.path())).unwrap();
+        let hooks = Hooks::new(&Config::default());
+        let changes = StateChanges::default();
+        let (client, server) = UnixStream::pair().unwrap();
+        let shutdown = Arc::new(Notify::new());
+        let daemon = tokio::spawn(handle_ipc_client(
+            server,
+            engine,
+            Vec::new(),
+            0,
+            hooks,
+            shutdown,
+            changes.clone(),
+        ));
+
+        let (reader, mut writer) = client.into_split();
+        let mut lines = BufReader::new(reader).lines();
+
+        send(&mut writer, 1, Command::Subscribe).await;
+        let (status, tabs) = next_update(&mut lines, 1).await;
+        assert!(!status.paused);
+        assert!(tabs.is_empty());
+
+        // Commands' changes are pushed
+        let pause = Command::Pause {
+            category: None,
+            seconds: None,
+        };
+        send(&mut writer, 2, pause).await;
+        let (status, _) = next_update(&mut lines, 1).await;
+        assert!(status.paused);
+
+        // As are peers connecting, which only the daemon loop sees
+        changes.set_peers(2);
+        let (status, _) = next_update(&mut lines, 1).await;
+        assert_eq!(status.peers, 2);
 
-    if let Err(e) = engine.open_tab(&tab.url) {
-        return format!("ERROR: {}", e);
+        // The subscription ends with the connection
+        drop(writer);
+        let done = tokio::time::timeout(std::time::Duration::from_secs(5), daemon).await;
+        assert!(done.unwrap().unwrap().is_ok());
     }
 
-    if let Err(e) = engine.acknowledge_tab(tab_id) {
-        return format!("ERROR: {}", e);
+    #[test]
+    fn test_format_queue() {
+        let writes = vec![(
+            "user.js".to_string(),
+            vec!["+ user_pref(\"a\", 1);".to_string()],
+        )];
+        assert_eq!(format_queue(&writes), "user.js\n  + user_pref(\"a\", 1);");
     }
 
-    "OK: Tab opened".to_string()
+    #[test]
+    fn test_format_divergences() {
+        let divergences = vec![Divergence {
+            category: "prefs".to_string(),
+            item: "browser.a".to_string(),
+            local: crate::events::Event::PrefRemoved {
+                key: "browser.a".to_string(),
+            },
+            pack: Some("3".to_string()),
+            found_at: String::new(),
+        }];
+        assert_eq!(
+            format_divergences(&divergences),
+            "prefs browser.a\n  local: remove pref browser.a\n  pack: 3"
+        );
+    }
+
+    #[test]
+    fn test_format_sync_history() {
+        let session = |error: Option<&str>| SyncSession {
+            peer: "12D3KooWpeer".to_string(),
+            device: None,
+            finished_at: "not a time".to_string(),
+            duration_ms: 1300,
+            files_received: 3,
+            events_applied: 2,
+            converged: error.is_none(),
+            error: error.map(str::to_string),
+        };
+        let sessions = vec![
+            ("Laptop".to_string(), session(None)),
+            ("12D3KooWpeer".to_string(), session(Some("Timed out"))),
+        ];
+        let converged = vec![("Laptop".to_string(), "not a time".to_string())];
+        assert_eq!(
+            format_sync_history(&sessions, &converged),
+            "Last caught up:\n  Laptop: not a time\n\nRecent sessions:\n  \
+             not a time Laptop - caught up in 1.3s, 3 files received, 2 new events\n  \
+             not a time 12D3KooWpeer - failed after 1.3s: Timed out, 3 files received, 2 new events"
+        );
+        assert!(format_sync_history(&[], &[]).contains("(never)"));
+    }
+
+    #[test]
+    fn test_format_reading_list() {
+        let items = vec![
+            ReadingListItem {
+                url: "https://a.example".to_string(),
+                title: Some("A".to_string()),
+                added_at: String::new(),
+            },
+            ReadingListItem {
+                url: "https://b.example".to_string(),
+                title: None,
+                added_at: String::new(),
+            },
+        ];
+        assert_eq!(
+            format_reading_list(&items),
+            "https://a.example (A)\nhttps://b.example"
+        );
+    }
+
+    #[test]
+    fn test_command_lane() {
+        assert_eq!(command_lane(&Command::Tabs), Lane::Interactive);
+        assert_eq!(command_lane(&Command::Gc), Lane::Bulk);
+        let apply = Command::Queue {
+            action: QueueAction::Apply,
+        };
+        assert_eq!(command_lane(&apply), Lane::Bulk);
+        let list = Command::Queue {
+            action: QueueAction::List,
+        };
+        assert_eq!(command_lane(&list), Lane::Interactive);
+    }
+
+    #[test]
+    fn test_parse_request() {
+        let line = serde_json::to_string(&Request::new(4, Command::Gc)).unwrap();
+        assert_eq!(parse_request(&line).unwrap().command, Command::Gc);
+
+        // Answered with the request's ID even when it can't be carried out
+        let refused = parse_request(r#"{"version":99,"id":5,"command":"gc"}"#).unwrap_err();
+        assert_eq!(refused.id, 5);
+        assert!(matches!(
+            refused.result,
+            Err(IpcError::UnsupportedVersion { client: 99, .. })
+        ));
+        let refused = parse_request(r#"{"version":2,"id":6,"command":"teleport"}"#).unwrap_err();
+        assert_eq!(refused.id, 6);
+        assert!(matches!(refused.result, Err(IpcError::BadRequest { .. })));
+        let refused = parse_request("status").unwrap_err();
+        assert_eq!(refused.id, 0);
+    }
 }
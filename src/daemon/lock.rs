@@ -0,0 +1,258 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+use tracing::{info, warn};
+
+use crate::ipc::{Command, Request, Response};
+
+/// How long to wait for a replaced daemon to shut down
+const RELEASE_TIMEOUT: Duration = Duration::from_secs(30);
+const RELEASE_POLL: Duration = Duration::from_millis(100);
+
+/// Lock held by the running daemon, so only one at a time writes the state
+/// database and sync dir
+///
+/// The lock file holds the daemon's PID and is removed when the lock is
+/// dropped. One left behind by a daemon that was killed is stale once its
+/// PID is gone and nothing answers on the IPC socket, and is cleaned up by
+/// the next daemon to start.
+#[derive(Debug)]
+pub struct InstanceLock {
+    path: PathBuf,
+}
+
+impl InstanceLock {
+    /// Take the lock, refusing to start while another daemon holds it
+    ///
+    /// With `replace`, the running daemon is asked over its IPC socket to
+    /// shut down, and the lock is taken once it has. One that's alive but
+    /// not answering is left alone, since it may still be writing.
+    #[allow(clippy::cognitive_complexity)] // Refuses, replaces, or cleans up with logging
+    pub async fn acquire(path: &Path, socket: &Path, replace: bool) -> Result<Self> {
+        if let Some(lock) = Self::try_acquire(path)? {
+            return Ok(lock);
+        }
+
+        let owner = read_owner(path);
+        let serving = UnixStream::connect(socket).await.is_ok();
+        if serving || owner.is_some_and(process_alive) {
+            let pid = owner.map_or_else(|| "unknown".to_string(), |pid| pid.to_string());
+            if !replace {
+                bail!(
+                    "Another wolfpack daemon is already running (PID {}). \
+                     Stop it first, or start with --replace to take over",
+                    pid
+                );
+            }
+            if !serving {
+                bail!(
+                    "The running wolfpack daemon (PID {}) isn't answering on {}, so it \
+                     can't be replaced. Stop it first, or remove {} if that PID isn't a \
+                     wolfpack daemon",
+                    pid,
+                    socket.display(),
+                    path.display()
+                );
+            }
+            info!("Asking the running daemon (PID {}) to shut down", pid);
+            request_shutdown(socket).await?;
+            wait_for_release(path).await?;
+        } else {
+            warn!("Removing stale daemon lock {}", path.display());
+            remove_stale_lock(path, owner)?;
+        }
+
+        Self::try_acquire(path)?.ok_or_else(|| {
+            anyhow!(
+                "Another wolfpack daemon took the lock {} while this one was starting",
+                path.display()
+            )
+        })
+    }
+
+    /// Take the lock if no one holds it
+    ///
+    /// The lock file is written in full before it's linked into place, so
+    /// another daemon never reads it half-written.
+    fn try_acquire(path: &Path) -> Result<Option<Self>> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        std::fs::create_dir_all(dir)?;
+
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        writeln!(file, "{}", std::process::id())?;
+        match file.persist_noclobber(path) {
+            Ok(_) => Ok(Some(Self {
+                path: path.to_path_buf(),
+            })),
+            Err(e) if e.error.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e.error)
+                .with_context(|| format!("Failed to create daemon lock {}", path.display())),
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Only remove the lock while it's still ours
+        if read_owner(&self.path) == Some(std::process::id()) {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// PID of the daemon holding a lock file
+fn read_owner(path: &Path) -> Option<u32> {
+    std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Remove a lock file found stale, held by `owner`
+///
+/// Another daemon starting at the same time can find the same lock stale,
+/// remove it, and take the lock before this one gets to it. The file is
+/// moved aside first, which only one of them can do, and put back if what
+/// was moved turns out to be the other daemon's fresh lock.
+fn remove_stale_lock(path: &Path, owner: Option<u32>) -> Result<()> {
+    let aside = path.with_extension(format!("stale.{}", std::process::id()));
+    match std::fs::rename(path, &aside) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to remove daemon lock {}", path.display()));
+        }
+    }
+    if read_owner(&aside) != owner {
+        // Fails if yet another daemon has taken the lock since, which then
+        // stands
+        let _ = std::fs::hard_link(&aside, path);
+    }
+    std::fs::remove_file(&aside)
+        .with_context(|| format!("Failed to remove daemon lock {}", aside.display()))
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    Path::new("/proc").join(pid.to_string()).exists()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+/// Ask the daemon listening on `socket` to shut down
+async fn request_shutdown(socket: &Path) -> Result<()> {
+    let mut stream = UnixStream::connect(socket)
+        .await
+        .context("Failed to connect to the running daemon")?;
+    let mut request = serde_json::to_string(&Request::new(1, Command::Shutdown))?;
+    request.push('\n');
+    stream.write_all(request.as_bytes()).await?;
+    stream.shutdown().await?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    let refusal = match serde_json::from_str::<Response>(response.trim()) {
+        Ok(Response { result: Ok(_), .. }) => return Ok(()),
+        Ok(Response { result: Err(e), .. }) => e.to_string(),
+        // A daemon from before the IPC protocol was versioned
+        Err(_) => response.trim().to_string(),
+    };
+    bail!(
+        "The running daemon refused to shut down: {}. Stop it before starting another",
+        refusal
+    )
+}
+
+/// Wait for the daemon being replaced to remove its lock on the way out
+async fn wait_for_release(path: &Path) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + RELEASE_TIMEOUT;
+    while path.exists() {
+        if tokio::time::Instant::now() >= deadline {
+            bail!("Timed out waiting for the running daemon to shut down");
+        }
+        tokio::time::sleep(RELEASE_POLL).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_try_acquire() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("daemon.lock");
+
+        let lock = InstanceLock::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(read_owner(&path), Some(std::process::id()));
+        assert!(InstanceLock::try_acquire(&path).unwrap().is_none());
+
+        drop(lock);
+        assert!(!path.exists());
+        assert!(InstanceLock::try_acquire(&path).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_drop_leaves_other_owners_lock() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("daemon.lock");
+
+        let lock = InstanceLock::try_acquire(&path).unwrap().unwrap();
+        // Taken over by another daemon after this one's was removed
+        std::fs::write(&path, "1\n").unwrap();
+        drop(lock);
+        assert_eq!(read_owner(&path), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_replace_unresponsive_daemon() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("daemon.lock");
+        let socket = dir.path().join("wolfpack.sock");
+
+        // Held by a live process with nothing on the socket
+        std::fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert!(InstanceLock::acquire(&path, &socket, true).await.is_err());
+        assert_eq!(read_owner(&path), Some(std::process::id()));
+
+        // Once that process is gone, the lock is stale
+        std::fs::write(&path, format!("{}\n", u32::MAX)).unwrap();
+        let lock = InstanceLock::acquire(&path, &socket, true).await.unwrap();
+        assert_eq!(read_owner(&path), Some(std::process::id()));
+        drop(lock);
+    }
+
+    #[test]
+    fn test_stale_lock_taken_meanwhile() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("daemon.lock");
+
+        // Found stale, then removed and taken by another daemon starting
+        std::fs::write(&path, "1\n").unwrap();
+        remove_stale_lock(&path, Some(u32::MAX)).unwrap();
+        assert_eq!(read_owner(&path), Some(1));
+
+        remove_stale_lock(&path, Some(1)).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        // Already removed by the other daemon
+        remove_stale_lock(&path, Some(1)).unwrap();
+    }
+
+    #[test]
+    fn test_process_alive() {
+        assert!(process_alive(std::process::id()));
+        assert!(!process_alive(u32::MAX));
+    }
+}
@@ -1,16 +1,25 @@
 mod api_token;
+mod hooks;
 mod http_api;
 mod ipc;
+mod lock;
 mod pairing;
+mod peers;
+mod rate_limit;
+mod rounds;
 mod run;
 mod socket;
 mod watcher;
+mod webhook;
 
 pub use api_token::ApiTokenManager;
+pub use hooks::{HookEvent, Hooks};
 pub use http_api::{ApiState, start_server as start_http_api};
 pub use pairing::{
     PairingCommand, PairingManager, PairingRequest, PairingResponse, PairingResult, PairingState,
 };
-pub use run::run_daemon;
+pub use peers::ConnectedPeer;
+pub use run::{DaemonOptions, run_daemon};
 pub use socket::IpcSocket;
 pub use watcher::FileWatcher;
+pub use webhook::{Notification, Webhook};
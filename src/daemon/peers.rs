@@ -0,0 +1,133 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use libp2p::PeerId;
+
+use crate::net::PeerInfo;
+
+/// How long a peer has to show it's a paired device before strict mode
+/// disconnects it, enough for identify and a clock exchange
+const STRICT_PEERS_GRACE: TimeDelta = TimeDelta::seconds(60);
+
+/// A connected peer and the device it turned out to be
+#[derive(Debug, Clone)]
+pub struct ConnectedPeer {
+    pub peer_id: PeerId,
+    /// Device label, or the name the peer announced
+    pub name: Option<String>,
+    /// Device the peer answered a clock request as
+    pub device_id: Option<String>,
+    pub paired: bool,
+    pub info: PeerInfo,
+    /// When a sync round last confirmed we'd caught up with the peer
+    pub last_synced: Option<DateTime<Utc>>,
+}
+
+impl ConnectedPeer {
+    pub fn summary(&self) -> String {
+        format!(
+            "{}: {}",
+            self.peer_id,
+            self.name.as_deref().unwrap_or("(unidentified)")
+        )
+    }
+
+    /// The summary plus how the connection is carried and secured, for
+    /// `wolfpack net peers --verbose`
+    pub fn describe(&self) -> String {
+        let device = match (&self.device_id, self.paired) {
+            (Some(id), true) => format!("{} (paired)", id),
+            (Some(id), false) => format!("{} (not paired)", id),
+            (None, _) => "unknown (not paired)".to_string(),
+        };
+        let protocols = if self.info.protocols.is_empty() {
+            "(not identified yet)".to_string()
+        } else {
+            self.info.protocols.join(", ")
+        };
+
+        let last_synced = self
+            .last_synced
+            .map_or_else(|| "never".to_string(), |at| at.to_rfc3339());
+
+        format!(
+            "{}\n  Transport: {} ({})\n  Encryption: {}, key {}\n  Device: {}\n  Protocols: {}\n  Connected since: {}\n  Last synced: {}",
+            self.summary(),
+            self.info.transport(),
+            self.info.address,
+            self.info.encryption(),
+            self.info.public_key.as_deref().unwrap_or("unknown"),
+            device,
+            protocols,
+            self.info.connected_at.to_rfc3339(),
+            last_synced,
+        )
+    }
+
+    /// Whether strict mode should disconnect the peer: it had the grace
+    /// period to show it's a paired device and didn't
+    pub fn is_unpaired_after_grace(&self, now: DateTime<Utc>) -> bool {
+        !self.paired && now - self.info.connected_at >= STRICT_PEERS_GRACE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(address: &str, paired: bool) -> ConnectedPeer {
+        ConnectedPeer {
+            peer_id: "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN"
+                .parse()
+                .unwrap(),
+            name: Some("laptop".to_string()),
+            device_id: paired.then(|| "device-a".to_string()),
+            paired,
+            info: PeerInfo {
+                name: Some("laptop".to_string()),
+                address: address.parse().unwrap(),
+                protocols: vec![
+                    "/ipfs/id/1.0.0".to_string(),
+                    "/wolfpack/sync/1.0.0".to_string(),
+                ],
+                public_key: Some("ab12".to_string()),
+                device_id: paired.then(|| "device-a".to_string()),
+                connected_at: "2024-01-01T10:00:00Z".parse().unwrap(),
+            },
+            last_synced: paired.then(|| "2024-01-01T10:05:00Z".parse().unwrap()),
+        }
+    }
+
+    #[test]
+    fn test_describe() {
+        assert_eq!(
+            peer("/ip4/192.168.1.20/udp/4001/quic-v1", true).describe(),
+            "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN: laptop\n\
+             \x20 Transport: quic (/ip4/192.168.1.20/udp/4001/quic-v1)\n\
+             \x20 Encryption: TLS 1.3, key ab12\n\
+             \x20 Device: device-a (paired)\n\
+             \x20 Protocols: /ipfs/id/1.0.0, /wolfpack/sync/1.0.0\n\
+             \x20 Connected since: 2024-01-01T10:00:00+00:00\n\
+             \x20 Last synced: 2024-01-01T10:05:00+00:00"
+        );
+
+        let relayed = peer(
+            "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit",
+            false,
+        );
+        let description = relayed.describe();
+        assert!(description.contains("Transport: relay"));
+        assert!(description.contains("Encryption: Noise"));
+        assert!(description.contains("Device: unknown (not paired)"));
+        assert!(description.contains("Last synced: never"));
+    }
+
+    #[test]
+    fn test_strict_grace_period() {
+        let unpaired = peer("/ip4/192.168.1.20/tcp/4001", false);
+        let connected_at = unpaired.info.connected_at;
+        assert!(!unpaired.is_unpaired_after_grace(connected_at + TimeDelta::seconds(30)));
+        assert!(unpaired.is_unpaired_after_grace(connected_at + STRICT_PEERS_GRACE));
+
+        let paired = peer("/ip4/192.168.1.20/tcp/4001", true);
+        assert!(!paired.is_unpaired_after_grace(connected_at + TimeDelta::hours(1)));
+    }
+}
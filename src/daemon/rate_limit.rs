@@ -0,0 +1,331 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Length of the fixed window requests are counted in
+const WINDOW: Duration = Duration::from_secs(60);
+/// Requests allowed per client address per window
+const IP_LIMIT: u32 = 300;
+/// Requests allowed per API token per window
+const TOKEN_LIMIT: u32 = 120;
+/// Failures allowed before the client is locked out
+const FREE_FAILURES: u32 = 5;
+/// First lockout, doubled for each further failure
+const BASE_LOCKOUT: Duration = Duration::from_secs(1);
+const MAX_LOCKOUT: Duration = Duration::from_secs(15 * 60);
+/// How long after its last failure a client's failures are forgotten
+const FAILURE_RESET: Duration = Duration::from_secs(60 * 60);
+/// Tracked windows before stale ones are dropped
+const MAX_TRACKED: usize = 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct Window {
+    started: Instant,
+    count: u32,
+}
+
+impl Window {
+    /// Count a request, or return how long until the window resets
+    fn hit(&mut self, limit: u32, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.started);
+        if elapsed >= WINDOW {
+            self.started = now;
+            self.count = 0;
+        }
+        if self.count >= limit {
+            return Err(WINDOW.saturating_sub(elapsed));
+        }
+        self.count += 1;
+        Ok(())
+    }
+
+    fn is_stale(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.started) >= WINDOW
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+impl Failures {
+    /// Whether the client has gone long enough without failing, and is no
+    /// longer locked out, for its failures to be forgotten
+    fn is_stale(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.last) >= FAILURE_RESET
+            && self.locked_until.is_none_or(|until| until <= now)
+    }
+}
+
+/// What a request's failures count against: the valid token it was made
+/// with, or else its address
+///
+/// Every client of the API is on localhost, so an address alone can't tell
+/// a process guessing tokens from the browser extension. Holders of a valid
+/// token are only locked out for their own failures, like wrong pairing
+/// codes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Client {
+    Token(String),
+    Address(IpAddr),
+}
+
+/// Per-address and per-token request limits for the HTTP API, with an
+/// exponential lockout for clients that keep failing to authenticate
+///
+/// The API only listens on localhost, but any local process can reach it,
+/// including one guessing tokens or pairing codes.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    ips: HashMap<IpAddr, Window>,
+    tokens: HashMap<String, Window>,
+    failures: HashMap<Client, Failures>,
+}
+
+impl RateLimiter {
+    /// Count a request, or return how long the client must wait
+    pub fn check(&mut self, ip: IpAddr, client: &Client, now: Instant) -> Result<(), Duration> {
+        self.prune(now);
+
+        if let Some(until) = self.failures.get(client).and_then(|f| f.locked_until)
+            && until > now
+        {
+            return Err(until - now);
+        }
+
+        self.ips
+            .entry(ip)
+            .or_insert(Window {
+                started: now,
+                count: 0,
+            })
+            .hit(IP_LIMIT, now)?;
+
+        if let Client::Token(token) = client {
+            self.tokens
+                .entry(token.to_string())
+                .or_insert(Window {
+                    started: now,
+                    count: 0,
+                })
+                .hit(TOKEN_LIMIT, now)?;
+        }
+
+        Ok(())
+    }
+
+    /// Record a rejected token or pairing code, locking the client out once
+    /// it has made too many
+    ///
+    /// Failures are forgotten an hour after the last one, so a client that
+    /// mistyped a few times long ago starts over.
+    pub fn record_failure(&mut self, client: &Client, now: Instant) {
+        let fresh = Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        };
+        let failures = self.failures.entry(client.clone()).or_insert(fresh);
+        if failures.is_stale(now) {
+            *failures = fresh;
+        }
+        failures.count += 1;
+        failures.last = now;
+
+        if failures.count > FREE_FAILURES {
+            let exponent = (failures.count - FREE_FAILURES - 1).min(31);
+            let lockout = BASE_LOCKOUT.saturating_mul(1 << exponent).min(MAX_LOCKOUT);
+            failures.locked_until = Some(now + lockout);
+            let who = match client {
+                Client::Token(_) => "an API token".to_string(),
+                Client::Address(ip) => ip.to_string(),
+            };
+            warn!(
+                "Locked out {} for {}s after {} failed attempts",
+                who,
+                lockout.as_secs(),
+                failures.count
+            );
+        }
+    }
+
+    /// Forget a token's failures once a pairing code tried with it is right
+    ///
+    /// Only the action the failures guard clears them, so other requests
+    /// made between wrong codes don't reset the count. An address's failures
+    /// are left to expire, so a process guessing tokens isn't let off by the
+    /// extension's requests from the same one.
+    pub fn record_success(&mut self, client: &Client) {
+        if matches!(client, Client::Token(_)) {
+            self.failures.remove(client);
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        if self.ips.len() > MAX_TRACKED {
+            self.ips.retain(|_, w| !w.is_stale(now));
+        }
+        if self.tokens.len() > MAX_TRACKED {
+            self.tokens.retain(|_, w| !w.is_stale(now));
+        }
+        if self.failures.len() > MAX_TRACKED {
+            self.failures.retain(|_, f| !f.is_stale(now));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    const ADDRESS: Client = Client::Address(LOCALHOST);
+
+    fn token(token: &str) -> Client {
+        Client::Token(token.to_string())
+    }
+
+    #[test]
+    fn test_token_limit() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..TOKEN_LIMIT {
+            assert!(limiter.check(LOCALHOST, &token("token"), now).is_ok());
+        }
+        assert_eq!(
+            limiter.check(LOCALHOST, &token("token"), now + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+
+        // Requests without the token are only held to the address limit
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_ok());
+
+        // The next window starts fresh
+        assert!(
+            limiter
+                .check(LOCALHOST, &token("token"), now + WINDOW)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_ip_limit() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..IP_LIMIT {
+            assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_ok());
+        }
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_err());
+        assert!(
+            limiter
+                .check(
+                    IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+                    &Client::Address(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))),
+                    now
+                )
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_invalid_token_lockout() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..FREE_FAILURES {
+            limiter.record_failure(&ADDRESS, now);
+        }
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_ok());
+
+        // Each further failure doubles the lockout
+        limiter.record_failure(&ADDRESS, now);
+        assert_eq!(
+            limiter.check(LOCALHOST, &ADDRESS, now),
+            Err(Duration::from_secs(1))
+        );
+        limiter.record_failure(&ADDRESS, now);
+        limiter.record_failure(&ADDRESS, now);
+        assert_eq!(
+            limiter.check(LOCALHOST, &ADDRESS, now),
+            Err(Duration::from_secs(4))
+        );
+        assert!(
+            limiter
+                .check(LOCALHOST, &ADDRESS, now + Duration::from_secs(4))
+                .is_ok()
+        );
+
+        // A valid token is held to its own failures, not the address's
+        assert!(limiter.check(LOCALHOST, &token("token"), now).is_ok());
+        limiter.record_success(&token("token"));
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_err());
+    }
+
+    #[test]
+    fn test_invalid_code_lockout() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+        let guesser = token("guesser");
+
+        // Wrong pairing codes lock out the token they were tried with
+        for _ in 0..=FREE_FAILURES {
+            assert!(limiter.check(LOCALHOST, &guesser, now).is_ok());
+            limiter.record_failure(&guesser, now);
+        }
+        assert_eq!(
+            limiter.check(LOCALHOST, &guesser, now),
+            Err(Duration::from_secs(1))
+        );
+        assert!(limiter.check(LOCALHOST, &token("other"), now).is_ok());
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_ok());
+
+        // A right code clears them
+        limiter.record_success(&guesser);
+        limiter.record_failure(&guesser, now);
+        assert!(limiter.check(LOCALHOST, &guesser, now).is_ok());
+    }
+
+    #[test]
+    fn test_failures_reset() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..=FREE_FAILURES {
+            limiter.record_failure(&ADDRESS, now);
+        }
+        assert!(limiter.check(LOCALHOST, &ADDRESS, now).is_err());
+
+        // Failing again soon after the lockout ends locks out for longer
+        let later = now + Duration::from_secs(1);
+        limiter.record_failure(&ADDRESS, later);
+        assert_eq!(
+            limiter.check(LOCALHOST, &ADDRESS, later),
+            Err(Duration::from_secs(2))
+        );
+
+        // An hour on, the count starts over
+        let much_later = later + FAILURE_RESET;
+        for _ in 0..FREE_FAILURES {
+            limiter.record_failure(&ADDRESS, much_later);
+        }
+        assert!(limiter.check(LOCALHOST, &ADDRESS, much_later).is_ok());
+    }
+
+    #[test]
+    fn test_lockout_is_capped() {
+        let mut limiter = RateLimiter::default();
+        let now = Instant::now();
+
+        for _ in 0..100 {
+            limiter.record_failure(&ADDRESS, now);
+        }
+        assert_eq!(limiter.check(LOCALHOST, &ADDRESS, now), Err(MAX_LOCKOUT));
+    }
+}
@@ -0,0 +1,433 @@
+//! Anti-entropy sync rounds, one state machine per connected peer
+//!
+//! A round asks the peer for its clock, works out which devices' events it
+//! has past ours, fetches them a page at a time, then asks for the clock
+//! again to confirm we've caught up. A finished round schedules the next one
+//! a jittered interval later, so peers that connected together don't all
+//! sync at once. A round that fails or stalls is retried sooner, backing off
+//! up to the interval.
+//!
+//! Each round that ends, caught up or not, becomes a [`SyncSession`] for the
+//! daemon to log.
+
+use chrono::{DateTime, Utc};
+use libp2p::PeerId;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::state::SyncSession;
+
+/// Time between rounds with a peer we've caught up with
+const INTERVAL: Duration = Duration::from_secs(30);
+/// Fraction of the interval added or taken off at random
+const JITTER: f64 = 0.2;
+/// First retry after a failed round, doubled for each further failure
+const BASE_RETRY: Duration = Duration::from_secs(5);
+/// How long a round waits on the peer before it counts as failed
+const ROUND_TIMEOUT: Duration = Duration::from_secs(60);
+/// Exchanges in one round before it gives up on catching up with a peer
+/// that keeps getting ahead
+const MAX_EXCHANGES: u32 = 3;
+
+/// A device whose events a peer has past ours: the device, our counter, and
+/// the peer's
+pub type MissingRange = (String, u64, u64);
+
+/// The devices a peer's clock is ahead of ours on, sorted by device
+pub fn missing_ranges(
+    ours: &HashMap<String, u64>,
+    theirs: &HashMap<String, u64>,
+) -> Vec<MissingRange> {
+    let mut missing: Vec<MissingRange> = theirs
+        .iter()
+        .filter_map(|(device, &their)| {
+            let our = ours.get(device).copied().unwrap_or(0);
+            (their > our).then(|| (device.clone(), our, their))
+        })
+        .collect();
+    missing.sort();
+    missing
+}
+
+/// What to ask the peer for next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// The events we're missing
+    Fetch,
+    /// Its clock, to confirm we've caught up
+    Confirm,
+}
+
+/// A page of events received from a peer
+#[derive(Debug, Clone, Copy)]
+pub struct Page {
+    pub files: usize,
+    /// Events in those files that were new here
+    pub applied: usize,
+    /// Whether the peer has more to send
+    pub more: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the next round
+    Idle,
+    /// Asked for the peer's clock
+    Comparing,
+    /// Fetching the events the peer's clock showed we're missing
+    Exchanging { exchanges: u32 },
+    /// Asked for the clock again after fetching
+    Confirming { exchanges: u32 },
+}
+
+#[derive(Debug)]
+struct PeerRound {
+    phase: Phase,
+    started: Instant,
+    /// When the peer last moved the round along
+    updated: Instant,
+    next_round: Instant,
+    failures: u32,
+    files_received: u32,
+    events_applied: u32,
+    last_completed: Option<DateTime<Utc>>,
+}
+
+impl PeerRound {
+    fn new(now: Instant) -> Self {
+        Self {
+            phase: Phase::Idle,
+            started: now,
+            updated: now,
+            next_round: now,
+            failures: 0,
+            files_received: 0,
+            events_applied: 0,
+            last_completed: None,
+        }
+    }
+
+    fn begin(&mut self, now: Instant) {
+        self.advance(Phase::Comparing, now);
+        self.started = now;
+        self.files_received = 0;
+        self.events_applied = 0;
+    }
+
+    fn session(&self, peer: PeerId, now: Instant, error: Option<String>) -> SyncSession {
+        let duration = now.saturating_duration_since(self.started);
+        SyncSession {
+            peer: peer.to_string(),
+            device: None,
+            finished_at: Utc::now().to_rfc3339(),
+            duration_ms: u32::try_from(duration.as_millis()).unwrap_or(u32::MAX),
+            files_received: self.files_received,
+            events_applied: self.events_applied,
+            converged: error.is_none(),
+            error,
+        }
+    }
+
+    fn advance(&mut self, phase: Phase, now: Instant) {
+        self.phase = phase;
+        self.updated = now;
+    }
+
+    fn finish(&mut self, now: Instant) {
+        self.phase = Phase::Idle;
+        self.failures = 0;
+        self.next_round = now + jittered(INTERVAL);
+    }
+
+    fn fail(&mut self, now: Instant) {
+        self.phase = Phase::Idle;
+        self.failures += 1;
+        self.next_round = now + jittered(retry_delay(self.failures));
+    }
+}
+
+/// Every connected peer's round
+#[derive(Debug, Default)]
+pub struct SyncRounds {
+    peers: HashMap<PeerId, PeerRound>,
+    /// Rounds that ended since the daemon last took them
+    finished: Vec<SyncSession>,
+}
+
+impl SyncRounds {
+    /// Follow the connected peers, due a round as soon as they connect, and
+    /// forget disconnected ones
+    pub fn track(&mut self, connected: impl IntoIterator<Item = PeerId>, now: Instant) {
+        let connected: HashSet<PeerId> = connected.into_iter().collect();
+        let finished = &mut self.finished;
+        self.peers.retain(|peer, round| {
+            let keep = connected.contains(peer);
+            if !keep && round.phase != Phase::Idle {
+                let error = "The peer disconnected".to_string();
+                finished.push(round.session(*peer, now, Some(error)));
+            }
+            keep
+        });
+        for peer in connected {
+            self.peers
+                .entry(peer)
+                .or_insert_with(|| PeerRound::new(now));
+        }
+    }
+
+    /// Start a round with each peer that's due one, returning the peers to
+    /// ask for their clock
+    ///
+    /// A round the peer hasn't moved along within the timeout fails first.
+    pub fn start_due(&mut self, now: Instant) -> Vec<PeerId> {
+        let mut due = Vec::new();
+        for (peer, round) in &mut self.peers {
+            if round.phase != Phase::Idle
+                && now.saturating_duration_since(round.updated) >= ROUND_TIMEOUT
+            {
+                debug!("Sync round with {} timed out", peer);
+                let error = "Timed out waiting for the peer".to_string();
+                self.finished.push(round.session(*peer, now, Some(error)));
+                round.fail(now);
+            }
+            if round.phase == Phase::Idle && round.next_round <= now {
+                round.begin(now);
+                due.push(*peer);
+            }
+        }
+        due
+    }
+
+    /// Compare a peer's clock with ours
+    ///
+    /// A clock that arrives outside a round, as when a peer is discovered,
+    /// starts one.
+    #[allow(clippy::cognitive_complexity)] // State transitions with logging
+    pub fn clock_received(
+        &mut self,
+        peer: PeerId,
+        ours: &HashMap<String, u64>,
+        theirs: &HashMap<String, u64>,
+        now: Instant,
+    ) -> Option<Step> {
+        let round = self
+            .peers
+            .entry(peer)
+            .or_insert_with(|| PeerRound::new(now));
+        let missing = missing_ranges(ours, theirs);
+        let exchanges = match round.phase {
+            Phase::Idle => {
+                round.begin(now);
+                0
+            }
+            Phase::Comparing => 0,
+            // Already fetching; the round's own confirmation will follow
+            Phase::Exchanging { .. } => return None,
+            Phase::Confirming { exchanges } => exchanges,
+        };
+
+        if missing.is_empty() {
+            debug!("Sync round with {} complete", peer);
+            round.last_completed = Some(Utc::now());
+            self.finished.push(round.session(peer, now, None));
+            round.finish(now);
+            return None;
+        }
+        if exchanges >= MAX_EXCHANGES {
+            let error = format!("Still behind the peer after {} exchanges", exchanges);
+            debug!("{}: {}", peer, error);
+            self.finished.push(round.session(peer, now, Some(error)));
+            round.fail(now);
+            return None;
+        }
+        debug!("Missing events from {}: {:?}", peer, missing);
+        let exchanges = exchanges + 1;
+        round.advance(Phase::Exchanging { exchanges }, now);
+        Some(Step::Fetch)
+    }
+
+    /// Note a page of events from the peer, confirming once it has sent the
+    /// last one
+    pub fn page_received(&mut self, peer: PeerId, page: Page, now: Instant) -> Option<Step> {
+        let round = self.peers.get_mut(&peer)?;
+        let Phase::Exchanging { exchanges } = round.phase else {
+            return None;
+        };
+        round.files_received = round.files_received.saturating_add(saturate(page.files));
+        round.events_applied = round.events_applied.saturating_add(saturate(page.applied));
+        if page.more {
+            round.updated = now;
+            return None;
+        }
+        round.advance(Phase::Confirming { exchanges }, now);
+        Some(Step::Confirm)
+    }
+
+    /// End a peer's round without exchanging events, as for an unpaired
+    /// device, and schedule the next one as usual
+    pub fn skip(&mut self, peer: PeerId, now: Instant) {
+        if let Some(round) = self.peers.get_mut(&peer) {
+            round.finish(now);
+        }
+    }
+
+    /// Give up on a peer's round and retry it after a backoff
+    pub fn fail(&mut self, peer: PeerId, error: impl ToString, now: Instant) {
+        if let Some(round) = self.peers.get_mut(&peer) {
+            if round.phase != Phase::Idle {
+                let session = round.session(peer, now, Some(error.to_string()));
+                self.finished.push(session);
+            }
+            round.fail(now);
+        }
+    }
+
+    /// The rounds that ended since this was last called
+    pub fn take_finished(&mut self) -> Vec<SyncSession> {
+        std::mem::take(&mut self.finished)
+    }
+
+    /// When a round with the peer last confirmed we'd caught up
+    pub fn last_completed(&self, peer: &PeerId) -> Option<DateTime<Utc>> {
+        self.peers.get(peer).and_then(|round| round.last_completed)
+    }
+}
+
+fn retry_delay(failures: u32) -> Duration {
+    BASE_RETRY
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(INTERVAL)
+}
+
+fn saturate(count: usize) -> u32 {
+    u32::try_from(count).unwrap_or(u32::MAX)
+}
+
+fn jittered(delay: Duration) -> Duration {
+    delay.mul_f64(1.0 + rand::thread_rng().gen_range(-JITTER..=JITTER))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(entries: &[(&str, u64)]) -> HashMap<String, u64> {
+        entries
+            .iter()
+            .map(|(device, count)| (device.to_string(), *count))
+            .collect()
+    }
+
+    fn page(files: usize, applied: usize, more: bool) -> Page {
+        Page {
+            files,
+            applied,
+            more,
+        }
+    }
+
+    #[test]
+    fn test_missing_ranges() {
+        let ours = clock(&[("a", 5), ("b", 3)]);
+        let theirs = clock(&[("a", 5), ("b", 7), ("c", 2)]);
+        assert_eq!(
+            missing_ranges(&ours, &theirs),
+            vec![("b".to_string(), 3, 7), ("c".to_string(), 0, 2)]
+        );
+        assert!(missing_ranges(&theirs, &ours).is_empty());
+    }
+
+    #[test]
+    #[allow(clippy::cognitive_complexity)] // Steps through a whole round
+    fn test_round() {
+        let peer = PeerId::random();
+        let now = Instant::now();
+        let mut rounds = SyncRounds::default();
+        rounds.track([peer], now);
+        assert_eq!(rounds.start_due(now), vec![peer]);
+        // Already in a round
+        assert!(rounds.start_due(now).is_empty());
+
+        let ours = clock(&[("a", 1)]);
+        let theirs = clock(&[("a", 4)]);
+        assert_eq!(
+            rounds.clock_received(peer, &ours, &theirs, now),
+            Some(Step::Fetch)
+        );
+        assert_eq!(rounds.page_received(peer, page(2, 2, true), now), None);
+        let later = now + Duration::from_millis(1500);
+        assert_eq!(
+            rounds.page_received(peer, page(1, 0, false), later),
+            Some(Step::Confirm)
+        );
+        assert_eq!(rounds.clock_received(peer, &theirs, &theirs, later), None);
+        assert!(rounds.last_completed(&peer).is_some());
+
+        let sessions = rounds.take_finished();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].peer, peer.to_string());
+        assert_eq!(sessions[0].duration_ms, 1500);
+        assert_eq!(sessions[0].files_received, 3);
+        assert_eq!(sessions[0].events_applied, 2);
+        assert!(sessions[0].converged);
+        assert!(rounds.take_finished().is_empty());
+
+        // The next round comes around after a jittered interval
+        assert!(rounds.start_due(now + INTERVAL.mul_f64(0.7)).is_empty());
+        assert_eq!(rounds.start_due(now + INTERVAL.mul_f64(1.3)), vec![peer]);
+
+        rounds.track([], now);
+        assert!(rounds.last_completed(&peer).is_none());
+        let sessions = rounds.take_finished();
+        assert_eq!(sessions[0].error.as_deref(), Some("The peer disconnected"));
+    }
+
+    #[test]
+    fn test_round_retries() {
+        let peer = PeerId::random();
+        let now = Instant::now();
+        let mut rounds = SyncRounds::default();
+        rounds.track([peer], now);
+        rounds.start_due(now);
+
+        // A stalled round fails and is retried after the backoff
+        let stalled = now + ROUND_TIMEOUT;
+        assert!(rounds.start_due(stalled).is_empty());
+        assert_eq!(
+            rounds.start_due(stalled + BASE_RETRY.mul_f64(1.3)),
+            vec![peer]
+        );
+
+        // A peer that keeps getting ahead fails after a few exchanges
+        let ours = clock(&[]);
+        for count in 1..=MAX_EXCHANGES {
+            let theirs = clock(&[("a", u64::from(count))]);
+            assert_eq!(
+                rounds.clock_received(peer, &ours, &theirs, now),
+                Some(Step::Fetch)
+            );
+            assert_eq!(
+                rounds.page_received(peer, page(1, 1, false), now),
+                Some(Step::Confirm)
+            );
+        }
+        let theirs = clock(&[("a", 10)]);
+        assert_eq!(rounds.clock_received(peer, &ours, &theirs, now), None);
+        assert!(rounds.last_completed(&peer).is_none());
+
+        let sessions = rounds.take_finished();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(
+            sessions[0].error.as_deref(),
+            Some("Timed out waiting for the peer")
+        );
+        assert_eq!(sessions[1].files_received, MAX_EXCHANGES);
+        assert!(!sessions[1].converged);
+
+        assert_eq!(retry_delay(1), BASE_RETRY);
+        assert_eq!(retry_delay(2), BASE_RETRY * 2);
+        assert_eq!(retry_delay(30), INTERVAL);
+    }
+}
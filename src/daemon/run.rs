@@ -1,53 +1,162 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, RwLock, broadcast};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 use crate::config::Config;
 use crate::crypto::KeyPair;
-use crate::events::EventLog;
-use crate::net::{EncryptedEvent, NetworkEvent, Node};
+use crate::events::{EventLog, HeldFiles, LAYOUT_VERSION, SharedEventLog, migrate_layout};
+use crate::lanes::Lane;
+use crate::net::{
+    BroadcastOptions, EncryptedEvent, EventCursor, ListenOptions, NetworkCommand, NetworkEvent,
+    Node, ServiceAdvertiser, load_or_generate_identity,
+};
 use crate::profile::{find_profile, is_browser_running};
-use crate::state::StateDb;
-use crate::sync::SyncEngine;
+use crate::state::{SentTab, StateDb};
+use crate::supervisor::supervise;
+use crate::sync::{EngineHandle, EventPage, SyncEngine};
 
-use super::ipc::handle_ipc_client;
+use super::ipc::{StateChanges, handle_ipc_client};
+use super::lock::InstanceLock;
+use super::rounds::{Page, Step, SyncRounds};
 use super::{ApiState, ApiTokenManager, FileWatcher, IpcSocket, PairingManager, PairingState};
-use super::{PairingCommand, start_http_api};
+use super::{
+    ConnectedPeer, HookEvent, Hooks, Notification, PairingCommand, Webhook, start_http_api,
+};
 
-fn ipc_socket_path() -> PathBuf {
-    dirs::runtime_dir()
-        .unwrap_or_else(|| PathBuf::from("/tmp"))
-        .join("wolfpack.sock")
+/// How often sent tabs past their retention are forgotten
+const GC_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How often this device advertises what it's running to the pack
+const DEVICE_STATE_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// How often peers are checked for a sync round that's due
+const ROUND_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Beside the state database, so daemons with their own state don't
+/// hold each other off
+fn daemon_lock_path(config: &Config) -> PathBuf {
+    config.state_db_path().with_file_name("daemon.lock")
+}
+
+/// Runtime options for the daemon, set from command-line flags
+#[derive(Debug, Clone, Default)]
+pub struct DaemonOptions {
+    /// Materialize incoming events and report diffs without touching the profile
+    pub observe: bool,
+    /// Config file to update when this device is renamed
+    pub config_path: Option<PathBuf>,
+    /// Shut down a daemon that's already running instead of refusing to start
+    pub replace: bool,
 }
 
 /// Shared daemon context for event handlers
 struct DaemonContext {
-    engine: Arc<Mutex<SyncEngine>>,
+    engine: EngineHandle,
+    /// The engine's event log, locked on its own so answering peers doesn't
+    /// wait behind a profile scan
+    event_log: SharedEventLog,
     node: Node,
     config: Config,
     profile_path: PathBuf,
+    /// Remote address of each connected peer
+    peer_addresses: Mutex<HashMap<libp2p::PeerId, String>>,
+    /// Device ID of each peer that has answered a clock request
+    peer_devices: Mutex<HashMap<libp2p::PeerId, String>>,
+    /// Claims held until the peer answers the challenge sent with it
+    pending_proofs: Mutex<HashMap<libp2p::PeerId, ([u8; 32], ClockClaim)>>,
+    /// Each connected peer's sync round
+    rounds: Mutex<SyncRounds>,
+    webhook: Webhook,
+    hooks: Hooks,
+    /// Wakes IPC clients subscribed to the status and pending tabs
+    changes: StateChanges,
+    /// Pairing advertisement, when mDNS is enabled
+    advertiser: Option<ServiceAdvertiser>,
     _watcher: FileWatcher, // Keep watcher alive
 }
 
 #[allow(clippy::cognitive_complexity)] // Entry point with multiple initialization checks
-pub async fn run_daemon(config: Config) -> Result<()> {
+pub async fn run_daemon(config: Config, options: DaemonOptions) -> Result<()> {
     info!("Starting wolfpack daemon");
     info!("Device: {} ({})", config.device.name, config.device.id);
+    if options.observe {
+        warn!("Observe mode: the browser profile will not be modified");
+    }
+
+    // Held until the daemon has cleaned up, so a daemon replacing this one
+    // doesn't start before the socket is removed
+    let socket = config.ipc_socket_path();
+    let _lock = InstanceLock::acquire(&daemon_lock_path(&config), &socket, options.replace).await?;
 
     // Initialize all daemon components
-    let (ctx, ipc, watcher_events, pairing_rx) = initialize_daemon(&config).await?;
+    let (ctx, ipc, watcher_events, pairing_rx) = initialize_daemon(&config, &options).await?;
 
     // Run the main event loop
     run_event_loop(ctx, ipc, watcher_events, pairing_rx).await
 }
 
+/// Create the sync engine, publishing this device's onion endpoint and
+/// pack-wide settings if they changed, and sending prefs changed while the
+/// daemon was stopped
+#[allow(clippy::cognitive_complexity)] // Sequential setup with logging
+fn init_sync_engine(
+    config: &Config,
+    options: &DaemonOptions,
+    keypair: KeyPair,
+) -> Result<SyncEngine> {
+    let state_db = init_state_db(config)?;
+    if let Some(from) = migrate_layout(&config.paths.sync_dir)? {
+        info!(
+            "Migrated sync dir from layout version {} to {}",
+            from, LAYOUT_VERSION
+        );
+    }
+    let event_log = EventLog::new(
+        config.paths.sync_dir.clone(),
+        config.device.id.clone(),
+        keypair,
+    );
+
+    let mut sync_engine = SyncEngine::new(config.clone(), event_log, state_db)?;
+    sync_engine.set_observe(options.observe);
+    if let Err(e) = sync_engine.publish_onion_address() {
+        warn!("Failed to publish onion address: {}", e);
+    }
+    if let Err(e) = sync_engine.publish_pack_config() {
+        warn!("Failed to publish pack settings: {}", e);
+    }
+    if let Err(e) = sync_engine.publish_installed_extensions() {
+        warn!("Failed to publish installed extensions: {}", e);
+    }
+    // Before any incoming event is applied, which would mix with them
+    match sync_engine.reconcile_offline_prefs() {
+        Ok((sent, held)) => {
+            if sent > 0 {
+                info!("{} prefs changed while the daemon was stopped", sent);
+            }
+            if held > 0 {
+                warn!(
+                    "{} prefs changed here and in the pack while stopped; see `wolfpack divergences`",
+                    held
+                );
+            }
+        }
+        Err(e) => warn!("Failed to check prefs changed while stopped: {}", e),
+    }
+    if let Some(path) = &options.config_path {
+        sync_engine.set_config_path(path.clone());
+    }
+    Ok(sync_engine)
+}
+
 #[allow(clippy::cognitive_complexity)] // Sequential initialization with multiple components
 async fn initialize_daemon(
     config: &Config,
+    options: &DaemonOptions,
 ) -> Result<(
     DaemonContext,
     IpcSocket,
@@ -56,36 +165,48 @@ async fn initialize_daemon(
 )> {
     let keypair = init_keypair()?;
     let public_key_hex = crate::crypto::public_key_to_hex(&keypair.public_key());
-    info!("Public key: {}", public_key_hex);
+    info!("Public key: {}", crate::redact::public_key(&public_key_hex));
 
-    let pairing_rx = init_http_api(config, &public_key_hex).await?;
+    let engine = init_sync_engine(config, options, keypair)?;
+    let event_log = engine.shared_event_log();
+    let webhook = init_webhook(config)?;
+    let advertiser = init_advertiser(config, engine.announcement_key());
+    let node = init_p2p_node(config, &engine).await?;
+    let engine = EngineHandle::spawn(engine)?;
 
-    let state_db = init_state_db()?;
-    let event_log = EventLog::new(
-        config.paths.sync_dir.clone(),
-        config.device.id.clone(),
-        keypair,
-    );
-
-    let sync_engine = SyncEngine::new(config.clone(), event_log, state_db)?;
-    let engine = Arc::new(Mutex::new(sync_engine));
+    let pairing_rx = init_http_api(
+        config,
+        &public_key_hex,
+        engine.clone(),
+        webhook.clone(),
+        advertiser.clone(),
+    )
+    .await?;
 
-    let node = init_p2p_node(config).await?;
     let profile_path = resolve_profile_path(config)?;
     let watcher = FileWatcher::new(&[profile_path.as_path()])?;
     let watcher_events = watcher.events.resubscribe();
-    let ipc = init_ipc_socket().await?;
+    let ipc = init_ipc_socket(config).await?;
 
     // Initial profile scan
-    scan_profile(&engine, "Initial scan").await;
+    scan_profile(&engine, &webhook, "Initial scan").await;
 
     info!("Daemon initialized, waiting for events...");
 
     let ctx = DaemonContext {
         engine,
+        event_log,
         node,
         config: config.clone(),
         profile_path,
+        peer_addresses: Mutex::new(HashMap::new()),
+        peer_devices: Mutex::new(HashMap::new()),
+        pending_proofs: Mutex::new(HashMap::new()),
+        rounds: Mutex::default(),
+        webhook,
+        hooks: Hooks::new(config),
+        changes: StateChanges::default(),
+        advertiser,
         _watcher: watcher,
     };
 
@@ -93,18 +214,16 @@ async fn initialize_daemon(
 }
 
 fn init_keypair() -> Result<KeyPair> {
-    let keys_dir = dirs::data_dir()
-        .unwrap_or_else(|| PathBuf::from("."))
-        .join("wolfpack")
-        .join("keys");
-    std::fs::create_dir_all(&keys_dir)?;
-    let keypair_path = keys_dir.join("local.key");
-    KeyPair::load_or_generate(&keypair_path)
+    std::fs::create_dir_all(Config::keys_dir())?;
+    KeyPair::load_or_generate(&Config::local_key_path())
 }
 
 async fn init_http_api(
     config: &Config,
     public_key_hex: &str,
+    engine: EngineHandle,
+    webhook: Webhook,
+    advertiser: Option<ServiceAdvertiser>,
 ) -> Result<tokio::sync::mpsc::Receiver<PairingCommand>> {
     let data_dir = dirs::data_dir()
         .unwrap_or_else(|| PathBuf::from("."))
@@ -123,21 +242,23 @@ async fn init_http_api(
         device_id: config.device.id.clone(),
         device_name: config.device.name.clone(),
         public_key: public_key_hex.to_string(),
+        engine,
+        webhook,
+        advertiser,
     }));
 
+    // Restarted with the same state if the server fails
     let http_port = config.api.port.unwrap_or(9778);
-    tokio::spawn(async move {
-        if let Err(e) = start_http_api(api_state, http_port).await {
-            error!("HTTP API server error: {}", e);
-        }
+    supervise("HTTP API", move || {
+        start_http_api(api_state.clone(), http_port)
     });
     info!("HTTP API started on port {}", http_port);
 
     Ok(pairing_rx)
 }
 
-fn init_state_db() -> Result<StateDb> {
-    let state_db_path = Config::default_state_db();
+fn init_state_db(config: &Config) -> Result<StateDb> {
+    let state_db_path = config.migrate_state_db()?;
     StateDb::open(&state_db_path).with_context(|| {
         format!(
             "Failed to open state database at {}",
@@ -146,23 +267,129 @@ fn init_state_db() -> Result<StateDb> {
     })
 }
 
-async fn init_p2p_node(config: &Config) -> Result<Node> {
+/// Advertise this device for pairing on the local network, alongside
+/// libp2p's mDNS discovery
+fn init_advertiser(config: &Config, announcement_key: [u8; 32]) -> Option<ServiceAdvertiser> {
+    if !config.sync.enable_mdns {
+        return None;
+    }
+    let port = config.sync.listen_port.unwrap_or(0);
+    match ServiceAdvertiser::start(
+        &config.device.id,
+        &config.device.name,
+        port,
+        announcement_key,
+    ) {
+        Ok(advertiser) => Some(advertiser),
+        Err(e) => {
+            warn!("Failed to advertise on the local network: {}", e);
+            None
+        }
+    }
+}
+
+async fn init_p2p_node(config: &Config, engine: &SyncEngine) -> Result<Node> {
+    let lan_only = config.sync.lan_only;
+    if lan_only {
+        log_lan_only(config);
+    }
+    let socks5 = p2p_proxy(config);
+    let local_key = load_or_generate_identity(&Config::keys_dir().join("p2p.key"))?;
+    let allowed_peers = if config.sync.strict_peers {
+        Some(paired_peers(engine))
+    } else {
+        None
+    };
+    let broadcast = broadcast_options(config, engine);
+
     let mut node = Node::new(
+        local_key,
         config.device.name.clone(),
-        config.sync.listen_port,
+        ListenOptions::from_config(config)?,
         config.sync.enable_mdns,
         config.sync.enable_dht,
+        lan_only,
+        socks5,
+        config.net.compression,
+        allowed_peers,
+        broadcast,
     )
     .await?;
     info!("P2P node started, peer ID: {}", node.peer_id());
 
-    if config.sync.enable_dht {
+    if config.sync.enable_dht && !lan_only {
         add_bootstrap_peers(&mut node, &config.sync.bootstrap_peers).await;
     }
 
     Ok(node)
 }
 
+/// Broadcast discovery as configured, left off behind a proxy, where the
+/// node only listens on loopback
+#[allow(clippy::cognitive_complexity)] // Logging only
+fn broadcast_options(config: &Config, engine: &SyncEngine) -> Option<BroadcastOptions> {
+    if !config.net.broadcast_discovery {
+        return None;
+    }
+    if p2p_proxy(config).is_some() {
+        warn!("Broadcast discovery is off while dialing through a SOCKS5 proxy");
+        return None;
+    }
+    info!(
+        "Broadcast discovery on UDP port {}",
+        config.net.broadcast_port
+    );
+    Some(BroadcastOptions {
+        port: config.net.broadcast_port,
+        scan: config.net.broadcast_scan,
+        key: engine.announcement_key(),
+    })
+}
+
+/// The peers strict mode lets in from the start: paired devices at the
+/// peer ID they last answered from
+fn paired_peers(engine: &SyncEngine) -> HashSet<libp2p::PeerId> {
+    let peer_ids = engine.paired_peer_ids().unwrap_or_else(|e| {
+        warn!("Failed to load paired peers: {}", e);
+        Vec::new()
+    });
+    peer_ids
+        .iter()
+        .filter_map(|peer_id| peer_id.parse().ok())
+        .collect()
+}
+
+fn init_webhook(config: &Config) -> Result<Webhook> {
+    Webhook::start(
+        config.notifications.webhook_url.as_deref(),
+        &config.device.name,
+        config.net.socks5.as_deref(),
+    )
+}
+
+/// The SOCKS5 proxy to dial peers through
+///
+/// The proxy only reaches outside the local network, so LAN-only mode
+/// rules it out.
+fn p2p_proxy(config: &Config) -> Option<&str> {
+    config
+        .net
+        .socks5
+        .as_deref()
+        .filter(|_| !config.sync.lan_only)
+}
+
+#[allow(clippy::cognitive_complexity)] // Logging only
+fn log_lan_only(config: &Config) {
+    info!("LAN-only mode: relay and DHT disabled, private addresses only");
+    if config.sync.enable_dht {
+        warn!("enable_dht is ignored in LAN-only mode");
+    }
+    if config.net.socks5.is_some() {
+        warn!("net.socks5 is ignored in LAN-only mode");
+    }
+}
+
 async fn add_bootstrap_peers(node: &mut Node, peers: &[String]) {
     for peer_addr in peers {
         if let Ok(addr) = peer_addr.parse() {
@@ -183,26 +410,29 @@ fn resolve_profile_path(config: &Config) -> Result<PathBuf> {
         .unwrap_or_else(find_profile)
 }
 
-async fn init_ipc_socket() -> Result<IpcSocket> {
-    let path = ipc_socket_path();
+async fn init_ipc_socket(config: &Config) -> Result<IpcSocket> {
+    let path = config.ipc_socket_path();
     let ipc = IpcSocket::new(&path).await?;
     info!("IPC socket: {}", path.display());
     Ok(ipc)
 }
 
 #[allow(clippy::cognitive_complexity)] // Simple match with multiple arms
-async fn scan_profile(engine: &Arc<Mutex<SyncEngine>>, context: &str) {
-    let mut engine = engine.lock().await;
-    match engine.scan_profile() {
+#[instrument(skip(engine, webhook))]
+async fn scan_profile(engine: &EngineHandle, webhook: &Webhook, context: &str) {
+    match engine.scan().await {
         Ok(events) if !events.is_empty() => {
             info!("{}: {} events to sync", context, events.len());
         }
-        Err(e) => warn!("{} failed: {}", context, e),
+        Err(e) => {
+            warn!("{} failed: {}", context, e);
+            webhook.send(Notification::sync_error(context, &e));
+        }
         _ => {}
     }
 }
 
-#[allow(clippy::cognitive_complexity)] // tokio::select! event loop pattern
+#[allow(clippy::cognitive_complexity, clippy::too_many_lines)] // tokio::select! event loop pattern
 async fn run_event_loop(
     mut ctx: DaemonContext,
     ipc: IpcSocket,
@@ -211,6 +441,9 @@ async fn run_event_loop(
 ) -> Result<()> {
     let mut browser_was_running = is_browser_running(&ctx.profile_path);
     let mut sync_interval = tokio::time::interval(Duration::from_secs(30));
+    let mut gc_interval = tokio::time::interval(GC_INTERVAL);
+    let mut device_state_interval = tokio::time::interval(DEVICE_STATE_INTERVAL);
+    let mut round_interval = tokio::time::interval(ROUND_CHECK_INTERVAL);
     let mut pairing_state = PairingState::new();
 
     loop {
@@ -226,15 +459,33 @@ async fn run_event_loop(
             }
 
             client = ipc.listener().accept() => {
-                handle_ipc_accept(client, &ctx).await;
+                handle_ipc_accept(client, &ipc, &ctx).await;
             }
 
             _ = sync_interval.tick() => {
                 handle_periodic_sync(&ctx).await;
+                // Timed pauses end without a command
+                ctx.changes.notify();
+                drop_unpaired_peers(&ctx, &pairing_state).await;
+                // Sessions expire without a command, so refresh here too
+                publish_pairing(&ctx, &pairing_state).await;
+            }
+
+            _ = round_interval.tick() => {
+                start_sync_rounds(&ctx).await;
+            }
+
+            _ = gc_interval.tick() => {
+                handle_garbage_collection(&ctx).await;
+            }
+
+            _ = device_state_interval.tick() => {
+                publish_device_state(&ctx.engine).await;
             }
 
             Some(cmd) = pairing_rx.recv() => {
                 pairing_state.handle_command(cmd);
+                publish_pairing(&ctx, &pairing_state).await;
             }
 
             _ = tokio::time::sleep(Duration::from_secs(5)) => {
@@ -244,34 +495,100 @@ async fn run_event_loop(
                 ).await;
             }
 
-            _ = tokio::signal::ctrl_c() => {
-                info!("Shutting down...");
+            () = shutdown_requested(&ipc) => {
                 break;
             }
         }
     }
 
-    cleanup_ipc_socket();
+    if let Some(advertiser) = &ctx.advertiser {
+        advertiser.shutdown();
+    }
+    if let Err(e) = ctx
+        .engine
+        .try_call(|engine| engine.save_prefs_snapshot())
+        .await
+    {
+        warn!("Failed to save prefs snapshot: {}", e);
+    }
+    cleanup_ipc_socket(&ctx.config);
     Ok(())
 }
 
+/// Wait for Ctrl-C, or for a daemon replacing this one to ask it to stop
+#[allow(clippy::cognitive_complexity)] // tokio::select! with logging
+async fn shutdown_requested(ipc: &IpcSocket) {
+    let shutdown = ipc.shutdown();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Shutting down..."),
+        () = shutdown.notified() => info!("Shutting down for another daemon..."),
+    }
+}
+
+/// Advertise whether a pairing session is open, and open the strict peer
+/// gate for it so the device joining can connect
+async fn publish_pairing(ctx: &DaemonContext, pairing_state: &PairingState) {
+    let open = pairing_state.has_active_session();
+    if let Some(advertiser) = &ctx.advertiser {
+        advertiser.set_pairing(open);
+    }
+    if ctx.config.sync.strict_peers {
+        let _ = ctx
+            .node
+            .send_command(NetworkCommand::OpenGate { open })
+            .await;
+    }
+}
+
 async fn handle_profile_change(event: notify::Event, ctx: &DaemonContext) {
     debug!("Profile change: {:?}", event.kind);
     // Debounce by waiting briefly for more events
     tokio::time::sleep(Duration::from_millis(100)).await;
-    scan_profile(&ctx.engine, "Profile changed").await;
+    scan_profile(&ctx.engine, &ctx.webhook, "Profile changed").await;
+
+    if event
+        .paths
+        .iter()
+        .any(|path| path.ends_with("extensions.json"))
+    {
+        publish_installed_extensions(&ctx.engine).await;
+    }
+}
+
+/// Advertise what this device is running, on `DEVICE_STATE_INTERVAL`
+async fn publish_device_state(engine: &EngineHandle) {
+    let publish = engine.try_call_in(Lane::Bulk, |engine| engine.publish_device_state());
+    if let Err(e) = publish.await {
+        warn!("Failed to publish device state: {}", e);
+    }
+}
+
+/// Tell the pack which extensions this device has, after they change
+async fn publish_installed_extensions(engine: &EngineHandle) {
+    let publish = engine.try_call_in(Lane::Bulk, |engine| engine.publish_installed_extensions());
+    if let Err(e) = publish.await {
+        warn!("Failed to publish installed extensions: {}", e);
+    }
 }
 
 async fn handle_ipc_accept(
     client: std::io::Result<(tokio::net::UnixStream, tokio::net::unix::SocketAddr)>,
+    ipc: &IpcSocket,
     ctx: &DaemonContext,
 ) {
     match client {
         Ok((stream, _)) => {
             let engine = ctx.engine.clone();
-            let node_peers = ctx.node.peers().await;
+            let node_peers = connected_peers(ctx).await;
+            let overflows = ctx.engine.overflows() + ctx.node.command_overflows();
+            let hooks = ctx.hooks.clone();
+            let shutdown = ipc.shutdown();
+            let changes = ctx.changes.clone();
             tokio::spawn(async move {
-                if let Err(e) = handle_ipc_client(stream, engine, node_peers).await {
+                let client = handle_ipc_client(
+                    stream, engine, node_peers, overflows, hooks, shutdown, changes,
+                );
+                if let Err(e) = client.await {
                     error!("IPC client error: {}", e);
                 }
             });
@@ -280,17 +597,248 @@ async fn handle_ipc_accept(
     }
 }
 
-#[allow(clippy::cognitive_complexity)] // Loop with early return and error handling
+/// Connected peers and the devices they turned out to be, preferring names
+/// given with `wolfpack devices rename` over the name the peer announced
+async fn connected_peers(ctx: &DaemonContext) -> Vec<ConnectedPeer> {
+    let peers = ctx.node.peer_info().await;
+    let peer_devices = ctx.peer_devices.lock().await.clone();
+    let device_ids: Vec<String> = peer_devices.values().cloned().collect();
+    // Each connected device's label and whether it's paired
+    let devices: HashMap<String, (String, bool)> = ctx
+        .engine
+        .call(move |engine| {
+            device_ids
+                .into_iter()
+                .map(|id| {
+                    let device = (engine.device_label(&id), engine.is_paired(&id));
+                    (id, device)
+                })
+                .collect()
+        })
+        .await
+        .unwrap_or_default();
+
+    let rounds = ctx.rounds.lock().await;
+    let mut connected: Vec<ConnectedPeer> = peers
+        .into_iter()
+        .map(|(peer_id, info)| {
+            let device_id = peer_devices.get(&peer_id).cloned();
+            let device = device_id.as_deref().and_then(|id| devices.get(id));
+            ConnectedPeer {
+                peer_id,
+                name: device
+                    .map(|(label, _)| label.clone())
+                    .or_else(|| info.name.clone()),
+                paired: device.is_some_and(|(_, paired)| *paired),
+                device_id,
+                last_synced: rounds.last_completed(&peer_id),
+                info,
+            }
+        })
+        .collect();
+    connected.sort_by_key(|peer| peer.info.connected_at);
+    connected
+}
+
+/// With `sync.strict_peers`, disconnect peers that haven't shown they're a
+/// paired device within the grace period
+///
+/// Nothing is dropped while a pairing session is open, since the device
+/// joining it isn't paired yet.
+async fn drop_unpaired_peers(ctx: &DaemonContext, pairing_state: &PairingState) {
+    if !ctx.config.sync.strict_peers || pairing_state.has_active_session() {
+        return;
+    }
+
+    let now = chrono::Utc::now();
+    for peer in connected_peers(ctx).await {
+        if peer.is_unpaired_after_grace(now) {
+            warn!(
+                "Strict peers: disconnecting unpaired peer {}",
+                peer.summary()
+            );
+            let peer_id = peer.peer_id;
+            let _ = ctx
+                .node
+                .send_command(NetworkCommand::Disconnect { peer_id })
+                .await;
+        }
+    }
+}
+
+#[allow(clippy::cognitive_complexity)] // Match with logging
+async fn handle_garbage_collection(ctx: &DaemonContext) {
+    match ctx
+        .engine
+        .try_call_in(Lane::Bulk, |engine| engine.collect_garbage())
+        .await
+    {
+        Ok(report) => debug!("Garbage collection: {}", report.describe()),
+        Err(e) => warn!("Garbage collection failed: {}", e),
+    }
+}
+
+#[instrument(skip_all)]
 async fn handle_periodic_sync(ctx: &DaemonContext) {
-    let peers = ctx.node.peers().await;
-    if peers.is_empty() {
+    if !sync_engine_state(&ctx.engine, &ctx.webhook).await {
         return;
     }
+    deliver_sent_tabs(ctx).await;
+    report_conflicts(ctx).await;
+    dial_onion_endpoints(ctx).await;
+}
+
+/// The part of the periodic sync that only needs the engine: pruning tabs
+/// for long-unseen devices, reconciling toward the profile manifest, and
+/// bringing synced extensions and `policies.json` up to date
+///
+/// Returns false, having done nothing, while sync is paused.
+#[allow(clippy::cognitive_complexity)] // Early return and error handling
+async fn sync_engine_state(engine: &EngineHandle, webhook: &Webhook) -> bool {
+    if engine
+        .call(|engine| engine.is_paused())
+        .await
+        .unwrap_or(true)
+    {
+        debug!("Sync paused, skipping periodic sync");
+        return false;
+    }
+
+    match engine.try_call(|engine| engine.prune_stale_tabs()).await {
+        Ok(pruned) if pruned > 0 => info!("Dropped {} tabs for long-unseen devices", pruned),
+        Err(e) => warn!("Failed to prune sent tabs: {}", e),
+        _ => {}
+    }
+    reconcile_manifest(engine, webhook).await;
+    write_policies(engine, webhook).await;
+    sync_extensions(engine, webhook).await;
+    true
+}
+
+/// Write events for where the state drifted from the profile manifest
+#[allow(clippy::cognitive_complexity)] // Match with logging
+async fn reconcile_manifest(engine: &EngineHandle, webhook: &Webhook) {
+    let reconcile = engine.try_call_in(Lane::Bulk, |engine| engine.reconcile_manifest());
+    match reconcile.await {
+        Ok(written) if written > 0 => info!("Reconciled {} changes toward the manifest", written),
+        Err(e) => {
+            warn!("Failed to reconcile the profile manifest: {}", e);
+            webhook.send(Notification::sync_error("Reconciling the manifest", &e));
+        }
+        _ => {}
+    }
+}
+
+/// Bring `policies.json` in line with the pack, when policies are enabled
+async fn write_policies(engine: &EngineHandle, webhook: &Webhook) {
+    let write = engine.try_call_in(Lane::Bulk, |engine| engine.write_policies());
+    if let Err(e) = write.await {
+        warn!("Failed to write policies: {}", e);
+        webhook.send(Notification::sync_error("Writing policies", &e));
+    }
+}
+
+/// Write the pack's extensions to the profile, or queue them for when the
+/// browser closes, and remove uninstalled ones
+#[allow(clippy::cognitive_complexity)] // Match with logging
+async fn sync_extensions(engine: &EngineHandle, webhook: &Webhook) {
+    let sync = engine.try_call_in(Lane::Bulk, |engine| engine.sync_extensions());
+    match sync.await {
+        Ok((installed, removed)) if !installed.is_empty() || !removed.is_empty() => {
+            info!(
+                "Installed extensions {:?}, removed {:?}",
+                installed, removed
+            );
+        }
+        Err(e) => {
+            warn!("Failed to sync extensions: {}", e);
+            webhook.send(Notification::sync_error("Installing extensions", &e));
+        }
+        _ => {}
+    }
+}
 
-    debug!("Periodic sync with {} peers", peers.len());
-    for (peer_id, _) in peers {
+/// Ask each peer due a sync round for its clock, which the rest of the round
+/// follows from
+#[allow(clippy::cognitive_complexity)] // Loop with error logging
+async fn start_sync_rounds(ctx: &DaemonContext) {
+    record_sync_sessions(ctx).await;
+    if ctx
+        .engine
+        .call(|engine| engine.is_paused())
+        .await
+        .unwrap_or(true)
+    {
+        return;
+    }
+    let peers = ctx.node.peers().await;
+    let due = {
+        let mut rounds = ctx.rounds.lock().await;
+        let now = Instant::now();
+        rounds.track(peers.into_keys(), now);
+        rounds.start_due(now)
+    };
+    for peer_id in due {
+        debug!("Starting sync round with {}", peer_id);
         if let Err(e) = ctx.node.get_clock(peer_id).await {
             warn!("Failed to request clock from peer: {}", e);
+            ctx.rounds.lock().await.fail(peer_id, e, Instant::now());
+        }
+    }
+}
+
+/// Log the sync rounds that ended since the last call, for
+/// `wolfpack status --history`
+async fn record_sync_sessions(ctx: &DaemonContext) {
+    let mut sessions = ctx.rounds.lock().await.take_finished();
+    if sessions.is_empty() {
+        return;
+    }
+    let peer_devices = ctx.peer_devices.lock().await;
+    for session in &mut sessions {
+        let peer_id = session.peer.parse().ok();
+        session.device = peer_id.and_then(|peer_id| peer_devices.get(&peer_id).cloned());
+    }
+    drop(peer_devices);
+    let record = ctx.engine.try_call_in(Lane::Bulk, move |engine| {
+        sessions
+            .iter()
+            .try_for_each(|session| engine.record_sync_session(session))
+    });
+    if let Err(e) = record.await {
+        warn!("Failed to record sync sessions: {}", e);
+    }
+}
+
+/// Dial devices that published an onion endpoint and aren't connected,
+/// which only works through the proxy
+#[allow(clippy::cognitive_complexity)] // Loop with error logging
+async fn dial_onion_endpoints(ctx: &DaemonContext) {
+    if p2p_proxy(&ctx.config).is_none() {
+        return;
+    }
+    let endpoints = match ctx.engine.try_call(|engine| engine.onion_endpoints()).await {
+        Ok(endpoints) => endpoints,
+        Err(e) => {
+            warn!("Failed to look up onion endpoints: {}", e);
+            return;
+        }
+    };
+
+    let connected: HashSet<String> = ctx.peer_devices.lock().await.values().cloned().collect();
+    for (device_id, onion) in endpoints {
+        if connected.contains(&device_id) {
+            continue;
+        }
+        match crate::net::onion_multiaddr(&onion) {
+            Ok(addr) => {
+                debug!("Dialing {} at {}", device_id, onion);
+                let _ = ctx
+                    .node
+                    .send_command(crate::net::NetworkCommand::Dial { addr })
+                    .await;
+            }
+            Err(e) => warn!("Skipping onion endpoint of {}: {}", device_id, e),
         }
     }
 }
@@ -298,27 +846,95 @@ async fn handle_periodic_sync(ctx: &DaemonContext) {
 #[allow(clippy::cognitive_complexity)] // State check with conditional flushing
 async fn handle_browser_state_check(ctx: &DaemonContext, was_running: bool) -> bool {
     let browser_running = is_browser_running(&ctx.profile_path);
+    if !was_running
+        && browser_running
+        && let Err(e) = ctx
+            .engine
+            .try_call(|engine| engine.note_browser_started())
+            .await
+    {
+        warn!("Failed to note browser start: {}", e);
+    }
     if was_running && !browser_running {
+        let state = ctx
+            .engine
+            .call(|engine| (engine.is_paused(), engine.requires_approval()));
+        let Ok((paused, requires_approval)) = state.await else {
+            return browser_running;
+        };
+        if paused {
+            return browser_running;
+        }
+        if requires_approval {
+            info!("Browser closed, queued writes await approval (wolfpack queue apply)");
+            return browser_running;
+        }
+        check_extension_installs(ctx).await;
         info!("Browser closed, flushing write queue");
-        let mut engine = ctx.engine.lock().await;
-        match engine.flush_write_queue() {
+        match ctx.engine.flush().await {
             Ok(files) if !files.is_empty() => {
                 info!("Flushed write queue: {:?}", files);
+                let hooks = ctx.hooks.clone();
+                let run_hooks =
+                    move |engine: &mut SyncEngine| hooks.extensions_installed(engine, &files);
+                let _ = ctx.engine.call(run_hooks).await;
+            }
+            Err(e) => {
+                warn!("Failed to flush write queue: {}", e);
+                ctx.webhook
+                    .send(Notification::sync_error("Flushing the write queue", &e));
             }
-            Err(e) => warn!("Failed to flush write queue: {}", e),
             _ => {}
         }
     }
     browser_running
 }
 
-fn cleanup_ipc_socket() {
-    let path = ipc_socket_path();
+/// Enable synced extensions the browser left awaiting confirmation, and warn
+/// about XPIs it didn't register the last time it ran
+#[allow(clippy::cognitive_complexity)] // Loop with logging
+async fn check_extension_installs(ctx: &DaemonContext) {
+    let checks = ctx.engine.call(|engine| {
+        (
+            engine.enable_sideloaded_extensions(),
+            engine.verify_extension_installs(),
+        )
+    });
+    let Ok((enabled, missing)) = checks.await else {
+        return;
+    };
+    match enabled {
+        Ok(enabled) if !enabled.is_empty() => {
+            info!(
+                "Enabled synced extensions awaiting confirmation: {:?}",
+                enabled
+            );
+        }
+        Err(e) => warn!("Failed to enable synced extensions: {}", e),
+        _ => {}
+    }
+    match missing {
+        Ok(missing) => {
+            for id in missing {
+                let e = anyhow::anyhow!("The browser didn't register extension {}", id);
+                warn!("{}; it may need enabling in about:addons", e);
+                ctx.webhook
+                    .send(Notification::sync_error("Installing an extension", &e));
+            }
+        }
+        Err(e) => warn!("Failed to verify extension installs: {}", e),
+    }
+}
+
+fn cleanup_ipc_socket(config: &Config) {
+    let path = config.ipc_socket_path();
     if path.exists() {
         let _ = std::fs::remove_file(&path);
     }
 }
 
+#[allow(clippy::cognitive_complexity)] // One arm per event type
+#[allow(clippy::too_many_lines)] // One arm per event type
 async fn handle_network_event(event: NetworkEvent, ctx: &DaemonContext) {
     match event {
         NetworkEvent::PeerDiscovered {
@@ -326,22 +942,58 @@ async fn handle_network_event(event: NetworkEvent, ctx: &DaemonContext) {
             device_name,
         } => handle_peer_discovered(&ctx.node, peer_id, device_name).await,
 
-        NetworkEvent::PeerDisconnected { peer_id } => {
-            info!("Peer disconnected: {}", peer_id);
+        NetworkEvent::PeerConnected { peer_id, address } => {
+            ctx.peer_addresses
+                .lock()
+                .await
+                .insert(peer_id, address.to_string());
+            ctx.changes.set_peers(ctx.node.peer_info().await.len());
+        }
+
+        NetworkEvent::PeerDisconnected { peer_id } => handle_peer_disconnected(ctx, peer_id).await,
+
+        NetworkEvent::ClockReceived {
+            from,
+            clock,
+            device_id,
+            device_name,
+        } => {
+            let claim = ClockClaim {
+                clock,
+                device_id,
+                device_name,
+            };
+            handle_clock_received(ctx, from, claim).await;
         }
 
         NetworkEvent::ClockRequested { from, request_id } => {
             handle_clock_request(ctx, from, request_id).await;
         }
 
+        NetworkEvent::ProofRequested {
+            from,
+            request_id,
+            challenge,
+            public_key,
+        } => handle_proof_request(ctx, from, request_id, challenge, public_key).await,
+
+        NetworkEvent::DeviceProofReceived {
+            from,
+            device_id,
+            proof,
+        } => {
+            handle_device_proof(ctx, from, device_id, proof).await;
+        }
+
         NetworkEvent::EventsRequested {
             from,
             request_id,
-            clock,
-        } => handle_events_request(ctx, from, request_id, clock).await,
+            held,
+            after,
+        } => handle_events_request(ctx, from, request_id, held, after).await,
 
-        NetworkEvent::EventsReceived { from, events } => {
-            handle_events_received(ctx, from, events).await;
+        NetworkEvent::EventsReceived { from, events, next } => {
+            handle_events_received(ctx, from, events, next).await;
         }
 
         NetworkEvent::TabReceived {
@@ -349,7 +1001,12 @@ async fn handle_network_event(event: NetworkEvent, ctx: &DaemonContext) {
             url,
             title,
             from_device,
-        } => handle_tab_received(ctx, from, url, title, from_device).await,
+            tab_id,
+        } => handle_tab_received(ctx, from, url, title, from_device, tab_id).await,
+
+        NetworkEvent::TabDelivered { from, tab_id } => {
+            handle_tab_delivered(ctx, from, tab_id).await;
+        }
 
         NetworkEvent::PairingRequested {
             from,
@@ -359,7 +1016,16 @@ async fn handle_network_event(event: NetworkEvent, ctx: &DaemonContext) {
             device_name,
             public_key,
         } => {
-            handle_pairing_requested(ctx, from, request_id, code, device_id, device_name, public_key).await;
+            handle_pairing_requested(
+                ctx,
+                from,
+                request_id,
+                code,
+                device_id,
+                device_name,
+                public_key,
+            )
+            .await;
         }
 
         NetworkEvent::PairingResponse {
@@ -372,18 +1038,40 @@ async fn handle_network_event(event: NetworkEvent, ctx: &DaemonContext) {
             info!("Pairing response from {}: status={}", from, status);
             // TODO: Forward this to the HTTP API or pairing manager
             // For now, just log it
-            if status == "accepted" {
-                if let (Some(id), Some(name)) = (device_id, device_name) {
-                    info!("Paired with device: {} ({})", name, id);
-                    if let Some(key) = public_key {
-                        debug!("Public key: {}", key);
-                    }
-                }
+            if status == "accepted"
+                && let (Some(id), Some(name)) = (device_id, device_name)
+            {
+                handle_paired(ctx, id, name, public_key);
             }
         }
     }
 }
 
+async fn handle_peer_disconnected(ctx: &DaemonContext, peer_id: libp2p::PeerId) {
+    info!("Peer disconnected: {}", peer_id);
+    ctx.peer_addresses.lock().await.remove(&peer_id);
+    ctx.peer_devices.lock().await.remove(&peer_id);
+    ctx.pending_proofs.lock().await.remove(&peer_id);
+    ctx.changes.set_peers(ctx.node.peer_info().await.len());
+}
+
+#[allow(clippy::cognitive_complexity)] // Logging only
+fn handle_paired(
+    ctx: &DaemonContext,
+    device_id: String,
+    device_name: String,
+    public_key: Option<String>,
+) {
+    info!("Paired with device: {} ({})", device_name, device_id);
+    if let Some(key) = public_key {
+        debug!("Public key: {}", key);
+    }
+    ctx.webhook.send(Notification::DevicePaired {
+        device_id,
+        device_name,
+    });
+}
+
 #[allow(clippy::cognitive_complexity)] // Simple handler with error logging
 async fn handle_peer_discovered(node: &Node, peer_id: libp2p::PeerId, device_name: Option<String>) {
     info!(
@@ -396,14 +1084,20 @@ async fn handle_peer_discovered(node: &Node, peer_id: libp2p::PeerId, device_nam
     }
 }
 
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
 async fn handle_clock_request(
     ctx: &DaemonContext,
     from: libp2p::PeerId,
     request_id: libp2p::request_response::InboundRequestId,
 ) {
     debug!("Clock requested by {}", from);
-    let engine = ctx.engine.lock().await;
-    let clock = engine.get_vector_clock();
+    let clock = match ctx.event_log.clock().await {
+        Ok(clock) => clock,
+        Err(e) => {
+            warn!("Failed to read our clock: {}", e);
+            return;
+        }
+    };
     let _ = ctx
         .node
         .send_command(crate::net::NetworkCommand::RespondClock {
@@ -415,55 +1109,502 @@ async fn handle_clock_request(
         .await;
 }
 
+/// A peer's answer to our clock request, naming the device it claims to be
+struct ClockClaim {
+    clock: HashMap<String, u64>,
+    device_id: String,
+    device_name: String,
+}
+
+/// Check a peer's claim to a device before syncing with it
+///
+/// A peer claiming a device whose key is on file is challenged to prove it
+/// holds the key, once per connection, and its clock is held until it
+/// answers. Other claims are bound as they are.
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
+#[instrument(skip_all, fields(%from))]
+async fn handle_clock_received(ctx: &DaemonContext, from: libp2p::PeerId, claim: ClockClaim) {
+    debug!("Clock received from {} ({})", claim.device_name, from);
+    if ctx.peer_devices.lock().await.get(&from) == Some(&claim.device_id) {
+        return accept_clock(ctx, from, claim, None).await;
+    }
+
+    let id = claim.device_id.clone();
+    let key = ctx
+        .engine
+        .try_call(move |engine| anyhow::Ok(engine.device_key(&id)?.map(|_| engine.public_key())));
+    match key.await {
+        Ok(Some(public_key)) => {
+            let challenge: [u8; 32] = rand::random();
+            let prove = NetworkCommand::ProveDevice {
+                peer_id: from,
+                challenge: hex::encode(challenge),
+                public_key: crate::crypto::public_key_to_hex(&public_key),
+            };
+            ctx.pending_proofs
+                .lock()
+                .await
+                .insert(from, (challenge, claim));
+            let _ = ctx.node.send_command(prove).await;
+        }
+        Ok(None) => accept_clock(ctx, from, claim, None).await,
+        Err(e) => {
+            warn!("Failed to read the key of {}: {}", claim.device_id, e);
+            ctx.rounds.lock().await.fail(from, e, Instant::now());
+        }
+    }
+}
+
+/// Accept a held claim once the peer has answered its challenge
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
+async fn handle_device_proof(
+    ctx: &DaemonContext,
+    from: libp2p::PeerId,
+    device_id: String,
+    proof: String,
+) {
+    let Some((challenge, claim)) = ctx.pending_proofs.lock().await.remove(&from) else {
+        debug!("Unexpected device proof from {}", from);
+        return;
+    };
+    if claim.device_id != device_id {
+        warn!(
+            "Peer {} claimed {} but proved {}",
+            from, claim.device_id, device_id
+        );
+        let error = format!("Claimed {} but proved {}", claim.device_id, device_id);
+        ctx.rounds.lock().await.fail(from, error, Instant::now());
+        return;
+    }
+    accept_clock(ctx, from, claim, Some((challenge, proof))).await;
+}
+
+/// Prove to a peer that this device holds its key
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
+async fn handle_proof_request(
+    ctx: &DaemonContext,
+    from: libp2p::PeerId,
+    request_id: libp2p::request_response::InboundRequestId,
+    challenge: String,
+    public_key: String,
+) {
+    debug!("Device challenge from {}", from);
+    let peer = ctx.node.peer_id().to_string();
+    let proof = ctx
+        .engine
+        .try_call(move |engine| engine.prove_device(&challenge, &public_key, &peer));
+    match proof.await {
+        Ok(proof) => {
+            let device_id = ctx.config.device.id.clone();
+            let respond = NetworkCommand::RespondProof {
+                request_id,
+                device_id,
+                proof,
+            };
+            let _ = ctx.node.send_command(respond).await;
+        }
+        Err(e) => warn!("Failed to answer the device challenge from {}: {}", from, e),
+    }
+}
+
+/// Bind a peer to the device it claimed, by its proof when it gave one, and
+/// sync with it if the device is paired
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
+async fn accept_clock(
+    ctx: &DaemonContext,
+    from: libp2p::PeerId,
+    claim: ClockClaim,
+    proof: Option<([u8; 32], String)>,
+) {
+    let ClockClaim {
+        clock,
+        device_id,
+        device_name,
+    } = claim;
+    let claim = bind_claimed_device(ctx, from, device_id.clone(), device_name, proof);
+    let paired = match claim.await {
+        Ok(Some(paired)) => paired,
+        Ok(None) => {
+            warn!(
+                "Refused {}'s claim to be {}: bound elsewhere or not proven",
+                from, device_id
+            );
+            let error = format!(
+                "Claim to be {} refused: bound elsewhere or not proven",
+                device_id
+            );
+            ctx.rounds.lock().await.fail(from, error, Instant::now());
+            return;
+        }
+        Err(e) => {
+            warn!("Failed to bind peer {} to a device: {}", from, e);
+            ctx.rounds.lock().await.fail(from, e, Instant::now());
+            return;
+        }
+    };
+    ctx.peer_devices
+        .lock()
+        .await
+        .insert(from, device_id.clone());
+    let bind = NetworkCommand::BindDevice {
+        peer_id: from,
+        device_id,
+    };
+    let _ = ctx.node.send_command(bind).await;
+    if paired {
+        let _ = ctx
+            .node
+            .send_command(NetworkCommand::AllowPeer { peer_id: from })
+            .await;
+        compare_clocks(ctx, from, &clock).await;
+    } else {
+        // Unpaired devices get no events, so there's nothing to exchange
+        ctx.rounds.lock().await.skip(from, Instant::now());
+    }
+    // Tabs waiting for this device can be delivered now
+    deliver_sent_tabs(ctx).await;
+}
+
+/// Bind a peer to the device it claimed and record the device as seen,
+/// returning whether the device is paired, or `None` when the claim's refused
+async fn bind_claimed_device(
+    ctx: &DaemonContext,
+    from: libp2p::PeerId,
+    device_id: String,
+    device_name: String,
+    proof: Option<([u8; 32], String)>,
+) -> Result<Option<bool>> {
+    let address = ctx.peer_addresses.lock().await.get(&from).cloned();
+    let peer = from.to_string();
+    ctx.engine
+        .try_call(move |engine| {
+            let claimed = match proof {
+                Some((challenge, proof)) => {
+                    engine.bind_proven_device(&device_id, &peer, &challenge, &proof)?
+                }
+                None => engine.claim_device_peer(&device_id, &peer)?,
+            };
+            if claimed
+                && let Err(e) =
+                    engine.record_device_seen(&device_id, &device_name, address.as_deref())
+            {
+                warn!("Failed to record device presence: {}", e);
+            }
+            anyhow::Ok(claimed.then(|| engine.is_paired(&device_id)))
+        })
+        .await
+}
+
+/// Advance a paired peer's round with its clock, fetching what it has past
+/// ours
+async fn compare_clocks(ctx: &DaemonContext, from: libp2p::PeerId, theirs: &HashMap<String, u64>) {
+    let ours = match ctx.event_log.clock().await {
+        Ok(ours) => ours,
+        Err(e) => {
+            ctx.rounds.lock().await.fail(from, e, Instant::now());
+            return;
+        }
+    };
+    let step = ctx
+        .rounds
+        .lock()
+        .await
+        .clock_received(from, &ours, theirs, Instant::now());
+    if step == Some(Step::Fetch) {
+        request_missing_events(ctx, from, None).await;
+    }
+}
+
+/// Ask a paired device for the event files we don't hold, its own and any
+/// it forwards from devices we can't reach, continuing after `after` when
+/// it's sending them a page at a time
+#[allow(clippy::cognitive_complexity)] // Simple handler with error logging
+async fn request_missing_events(
+    ctx: &DaemonContext,
+    peer_id: libp2p::PeerId,
+    after: Option<String>,
+) {
+    let held = match ctx
+        .event_log
+        .read(EventLog::held_files)
+        .await
+        .and_then(|held| held)
+    {
+        Ok(held) => held,
+        Err(e) => {
+            warn!("Failed to list event files: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = ctx.node.get_events(peer_id, held, after).await {
+        warn!("Failed to request events from peer: {}", e);
+    }
+}
+
+/// Send pending tabs straight to their devices while they're connected,
+/// backing off between attempts until the device acknowledges them
+#[allow(clippy::cognitive_complexity)] // Loop with error logging
+async fn deliver_sent_tabs(ctx: &DaemonContext) {
+    let peer_devices = ctx.peer_devices.lock().await.clone();
+    if peer_devices.is_empty() {
+        return;
+    }
+
+    let connected: HashSet<String> = peer_devices.values().cloned().collect();
+    let due = ctx
+        .engine
+        .try_call(move |engine| tabs_due(engine, &connected));
+    let tabs = match due.await {
+        Ok(tabs) => tabs,
+        Err(e) => {
+            warn!("Failed to read sent tabs: {}", e);
+            return;
+        }
+    };
+
+    for tab in tabs {
+        let Some(peer_id) = peer_devices
+            .iter()
+            .find(|(_, device_id)| **device_id == tab.to_device)
+            .map(|(peer_id, _)| *peer_id)
+        else {
+            continue;
+        };
+        if let Err(e) = ctx
+            .node
+            .send_tab(
+                peer_id,
+                tab.url.clone(),
+                tab.title.clone(),
+                ctx.config.device.id.clone(),
+                Some(tab.id.clone()),
+            )
+            .await
+        {
+            warn!("Failed to send tab to peer: {}", e);
+        }
+    }
+}
+
+/// Tabs waiting for connected devices, recording the attempt about to be made
+/// at each
+#[allow(clippy::cognitive_complexity)] // Loop with logging
+fn tabs_due(engine: &SyncEngine, connected: &HashSet<String>) -> Result<Vec<SentTab>> {
+    let mut due = Vec::new();
+    for tab in engine.tabs_to_deliver()? {
+        if !connected.contains(&tab.to_device) {
+            continue;
+        }
+        debug!(
+            "Delivering tab to {} (attempt {}): {}",
+            engine.device_name(&tab.to_device),
+            tab.attempts + 1,
+            tab.url
+        );
+        if let Err(e) = engine.record_tab_attempt(&tab) {
+            warn!("Failed to record tab delivery attempt: {}", e);
+            continue;
+        }
+        due.push(tab);
+    }
+    Ok(due)
+}
+
 #[allow(clippy::cognitive_complexity)] // Async handler with error handling
+#[instrument(skip_all, fields(%from))]
 async fn handle_events_request(
     ctx: &DaemonContext,
     from: libp2p::PeerId,
     request_id: libp2p::request_response::InboundRequestId,
-    clock: HashMap<String, u64>,
+    held: HeldFiles,
+    after: Option<String>,
 ) {
-    debug!("Events requested by {} with clock {:?}", from, clock);
-    let engine = ctx.engine.lock().await;
-    match engine.get_events_since(&clock) {
-        Ok(events) => {
-            let _ = ctx
-                .node
-                .send_command(crate::net::NetworkCommand::RespondEvents { request_id, events })
-                .await;
+    debug!("Events requested by {} holding {:?}", from, held.highest);
+    let after = match after
+        .map(|cursor| cursor.parse::<EventCursor>())
+        .transpose()
+    {
+        Ok(after) => after,
+        Err(e) => {
+            // Answer with an empty page so the peer stops asking
+            warn!("Ignoring events request from {}: {}", from, e);
+            let respond = NetworkCommand::RespondEvents {
+                request_id,
+                events: Vec::new(),
+                next: None,
+            };
+            let _ = ctx.node.send_command(respond).await;
+            return;
+        }
+    };
+    let device_id = ctx.peer_devices.lock().await.get(&from).cloned();
+    let page = ctx.engine.try_call_in(Lane::Bulk, move |engine| {
+        // Only paired devices get event files, even though they're encrypted
+        let paired = device_id.is_some_and(|id| engine.is_paired(&id));
+        if engine.is_paused() || !paired {
+            return Ok(EventPage::default());
+        }
+        engine.get_events_since(&held, after.as_ref())
+    });
+    match page.await {
+        Ok(page) => {
+            let respond = NetworkCommand::RespondEvents {
+                request_id,
+                events: page.events,
+                next: page.next.map(|cursor| cursor.to_string()),
+            };
+            let _ = ctx.node.send_command(respond).await;
         }
         Err(e) => warn!("Failed to get events for peer: {}", e),
     }
 }
 
 #[allow(clippy::cognitive_complexity)] // Async handler with match arms
+#[instrument(skip_all, fields(%from, count = events.len()))]
 async fn handle_events_received(
     ctx: &DaemonContext,
     from: libp2p::PeerId,
     events: Vec<EncryptedEvent>,
+    next: Option<String>,
 ) {
     info!("Received {} events from {}", events.len(), from);
-    let mut engine = ctx.engine.lock().await;
-    match engine.apply_remote_events(events) {
+    let device_id = ctx.peer_devices.lock().await.get(&from).cloned();
+    let files = events.len();
+    let result = ctx.engine.apply_remote(events).await;
+    match &result {
+        Ok(applied) => {
+            // Ask for the next page once this one is applied, so only a page at
+            // a time is held in memory, and confirm the round after the last
+            let page = Page {
+                files,
+                applied: *applied,
+                more: next.is_some(),
+            };
+            let step = ctx
+                .rounds
+                .lock()
+                .await
+                .page_received(from, page, Instant::now());
+            if page.more {
+                request_missing_events(ctx, from, next).await;
+            } else if step == Some(Step::Confirm)
+                && let Err(e) = ctx.node.get_clock(from).await
+            {
+                warn!("Failed to request clock from peer: {}", e);
+            }
+        }
+        Err(e) => {
+            let error = format!("Applying events: {}", e);
+            ctx.rounds.lock().await.fail(from, error, Instant::now());
+        }
+    }
+    match result {
         Ok(applied) if applied > 0 => {
             info!("Applied {} events from {}", applied, from);
+            write_policies(&ctx.engine, &ctx.webhook).await;
+            ctx.hooks.run(HookEvent::SyncComplete {
+                peer: peer_name(ctx, from, device_id).await,
+                events_applied: applied,
+            });
+        }
+        Err(e) => {
+            warn!("Failed to apply events from {}: {}", from, e);
+            ctx.webhook
+                .send(Notification::sync_error("Applying events", &e));
         }
-        Err(e) => warn!("Failed to apply events from {}: {}", from, e),
         _ => {}
     }
+    report_conflicts(ctx).await;
+    // Tabs sent to this device may have come with the events
+    ctx.changes.notify();
+}
+
+/// The name of the device a peer turned out to be, or its peer ID
+async fn peer_name(
+    ctx: &DaemonContext,
+    peer_id: libp2p::PeerId,
+    device_id: Option<String>,
+) -> String {
+    let Some(device_id) = device_id else {
+        return peer_id.to_string();
+    };
+    let name = ctx
+        .engine
+        .call(move |engine| engine.device_name(&device_id));
+    name.await.unwrap_or_else(|_| peer_id.to_string())
+}
+
+/// Send conflicts found in incoming events to the webhook, naming the
+/// devices involved
+async fn report_conflicts(ctx: &DaemonContext) {
+    let conflicts = ctx.engine.call(|engine| {
+        let mut conflicts = engine.take_conflicts();
+        for device in conflicts.iter_mut().flat_map(|c| c.devices.iter_mut()) {
+            *device = engine.device_name(device);
+        }
+        conflicts
+    });
+    for conflict in conflicts.await.unwrap_or_default() {
+        ctx.webhook.send(Notification::conflict(conflict));
+    }
 }
 
 #[allow(clippy::cognitive_complexity)] // Simple async handler
+#[allow(clippy::too_many_arguments)] // Fields of the tab request
+#[instrument(skip_all, fields(%from))]
 async fn handle_tab_received(
     ctx: &DaemonContext,
     from: libp2p::PeerId,
     url: String,
     title: Option<String>,
     from_device: String,
+    tab_id: Option<String>,
 ) {
-    info!("Tab received from {} ({}): {}", from_device, from, url);
-    let mut engine = ctx.engine.lock().await;
-    if let Err(e) = engine.receive_tab(&url, title.as_deref(), &from_device) {
-        warn!("Failed to save received tab: {}", e);
+    let tab = (url.clone(), title.clone(), from_device.clone());
+    let received = ctx.engine.try_call(move |engine| {
+        let (url, title, from_device) = tab;
+        let device_name = engine.device_name(&from_device);
+        info!("Tab received from {} ({}): {}", device_name, from, url);
+        engine
+            .receive_tab(&url, title.as_deref(), &from_device, tab_id.as_deref())
+            .map(|new| new.then_some(device_name))
+    });
+    let received = received.await;
+    // A duplicate still adds to the pending tab's count
+    ctx.changes.notify();
+    match received {
+        // Duplicates are counted on the pending tab instead of notified
+        Ok(None) => debug!("Tab from {} was a duplicate", from),
+        Ok(Some(device_name)) => {
+            ctx.hooks.run(HookEvent::TabReceived {
+                url: url.clone(),
+                title: title.clone(),
+                from_device: device_name.clone(),
+                from_device_id: from_device,
+            });
+            ctx.webhook.send(Notification::TabReceived {
+                from_device: device_name,
+                url,
+                page_title: title,
+            });
+        }
+        Err(e) => warn!("Failed to save received tab: {}", e),
+    }
+}
+
+#[allow(clippy::cognitive_complexity)] // Simple async handler
+async fn handle_tab_delivered(ctx: &DaemonContext, from: libp2p::PeerId, tab_id: String) {
+    let device_id = ctx.peer_devices.lock().await.get(&from).cloned();
+    let device = peer_name(ctx, from, device_id).await;
+    let id = tab_id.clone();
+    match ctx
+        .engine
+        .try_call(move |engine| engine.mark_tab_delivered(&id))
+        .await
+    {
+        Ok(true) => info!("Tab {} delivered to {}", tab_id, device),
+        Ok(false) => debug!("Tab {} was already delivered", tab_id),
+        Err(e) => warn!("Failed to record tab delivery: {}", e),
     }
 }
 
@@ -482,11 +1623,120 @@ async fn handle_pairing_requested(
 
     // TODO: Check if we have an active pairing session with this code
     // For now, respond with invalid_code
-    let _ = ctx.node.send_command(crate::net::NetworkCommand::RespondPairing {
-        request_id,
-        status: "invalid_code".to_string(),
-        device_id: None,
-        device_name: None,
-        public_key: None,
-    }).await;
+    let _ = ctx
+        .node
+        .send_command(crate::net::NetworkCommand::RespondPairing {
+            request_id,
+            status: "invalid_code".to_string(),
+            device_id: None,
+            device_name: None,
+            public_key: None,
+        })
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{PrefValue, SyncCategory};
+
+    fn test_engine(dir: &std::path::Path, configure: impl FnOnce(&mut Config)) -> SyncEngine {
+        let profile = dir.join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        std::fs::write(profile.join("prefs.js"), "").unwrap();
+
+        let mut config = Config::default();
+        config.paths.profile = Some(profile);
+        config.paths.sync_dir = dir.join("sync");
+        config.backups.dir = dir.join("backups");
+        let manifest = dir.join("profile.toml");
+        std::fs::write(&manifest, "[prefs]\n\"browser.startup.page\" = 3\n").unwrap();
+        config.paths.manifest = Some(manifest);
+        configure(&mut config);
+
+        let event_log = EventLog::new(
+            config.paths.sync_dir.clone(),
+            config.device.id.clone(),
+            KeyPair::generate(),
+        );
+        SyncEngine::new(config, event_log, StateDb::open_in_memory().unwrap()).unwrap()
+    }
+
+    async fn startup_page(engine: &EngineHandle) -> Option<PrefValue> {
+        let export = engine.try_call(|engine| engine.export_state()).await;
+        export.unwrap().prefs.get("browser.startup.page").cloned()
+    }
+
+    #[tokio::test]
+    async fn test_periodic_sync_writes_policies() {
+        let dir = tempfile::tempdir().unwrap();
+        let policies = dir.path().join("policies/policies.json");
+        let engine = test_engine(dir.path(), |config| {
+            config.policies.enabled = true;
+            config.policies.path = policies.clone();
+            config.policies.xpi_dir = dir.path().join("policies/xpi");
+        });
+        let engine = EngineHandle::spawn(engine).unwrap();
+
+        assert!(sync_engine_state(&engine, &Webhook::default()).await);
+        let written = std::fs::read_to_string(&policies).unwrap();
+        let written: serde_json::Value = serde_json::from_str(&written).unwrap();
+        let page = &written["policies"]["Preferences"]["browser.startup.page"];
+        assert_eq!(page["Value"], 3);
+    }
+
+    #[tokio::test]
+    async fn test_periodic_sync_installs_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = EngineHandle::spawn(test_engine(dir.path(), |_| {})).unwrap();
+        let webhook = Webhook::default();
+
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("manifest.json"),
+            r#"{"manifest_version": 2, "name": "Pack", "version": "1.0",
+                "browser_specific_settings": {"gecko": {"id": "pack@test.com"}}}"#,
+        )
+        .unwrap();
+        let (_, xpi_data) = crate::extensions::package_extension(&source).unwrap();
+        let xpi = crate::extensions::decode_base64(&xpi_data).unwrap();
+        let xpi_path = dir.path().join("pack.xpi");
+        std::fs::write(&xpi_path, crate::extensions::decompress_xpi(&xpi).unwrap()).unwrap();
+        engine
+            .try_call(move |engine| engine.install_extension(&xpi_path, None))
+            .await
+            .unwrap();
+
+        let installed = dir.path().join("profile/extensions/pack@test.com.xpi");
+        assert!(!installed.exists());
+        assert!(sync_engine_state(&engine, &webhook).await);
+        assert!(installed.exists());
+    }
+
+    #[tokio::test]
+    async fn test_periodic_sync_reconciles_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = EngineHandle::spawn(test_engine(dir.path(), |_| {})).unwrap();
+        let webhook = Webhook::default();
+
+        // Nothing is reconciled while the category is paused
+        engine
+            .try_call(|engine| engine.pause(Some(SyncCategory::Prefs), None))
+            .await
+            .unwrap();
+        assert!(sync_engine_state(&engine, &webhook).await);
+        assert_eq!(startup_page(&engine).await, None);
+
+        engine.try_call(|engine| engine.resume(None)).await.unwrap();
+        assert!(sync_engine_state(&engine, &webhook).await);
+        assert_eq!(startup_page(&engine).await, Some(PrefValue::Int(3)));
+
+        // Nor while all sync is paused
+        engine
+            .try_call(|engine| engine.pause(None, None))
+            .await
+            .unwrap();
+        assert!(!sync_engine_state(&engine, &webhook).await);
+    }
 }
@@ -1,9 +1,13 @@
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
 use std::path::Path;
+use std::sync::Arc;
 use tokio::net::UnixListener;
+use tokio::sync::Notify;
 
 pub struct IpcSocket {
     listener: UnixListener,
+    shutdown: Arc<Notify>,
 }
 
 impl IpcSocket {
@@ -14,14 +18,84 @@ impl IpcSocket {
         }
 
         if let Some(parent) = path.parent() {
-            std::fs::create_dir_all(parent)?;
+            secure_socket_dir(parent)?;
         }
 
         let listener = UnixListener::bind(path)?;
-        Ok(Self { listener })
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        Ok(Self {
+            listener,
+            shutdown: Arc::new(Notify::new()),
+        })
     }
 
     pub fn listener(&self) -> &UnixListener {
         &self.listener
     }
+
+    /// Notified when a client sends the `shutdown` command
+    pub fn shutdown(&self) -> Arc<Notify> {
+        self.shutdown.clone()
+    }
+}
+
+/// Create the socket's directory private to this user, and refuse one that
+/// belongs to someone else
+///
+/// Anyone who can reach the socket can drive the daemon, so the directory is
+/// kept at 0700 whether it's the runtime dir or the `/tmp` fallback.
+fn secure_socket_dir(dir: &Path) -> Result<()> {
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create socket directory {}", dir.display()))?;
+
+    let meta = std::fs::symlink_metadata(dir)?;
+    if !meta.is_dir() {
+        bail!("Socket directory {} is not a directory", dir.display());
+    }
+    if meta.uid() != crate::config::current_uid() {
+        bail!(
+            "Socket directory {} is owned by another user (uid {})",
+            dir.display(),
+            meta.uid()
+        );
+    }
+    if meta.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[tokio::test]
+    async fn test_socket_dir_is_private() {
+        let dir = tempdir().unwrap();
+        let socket_dir = dir.path().join("wolfpack-run");
+        std::fs::create_dir(&socket_dir).unwrap();
+        std::fs::set_permissions(&socket_dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = socket_dir.join("wolfpack.sock");
+        let _ipc = IpcSocket::new(&path).await.unwrap();
+
+        let dir_mode = std::fs::metadata(&socket_dir).unwrap().mode();
+        assert_eq!(dir_mode & 0o777, 0o700);
+        let socket_mode = std::fs::metadata(&path).unwrap().mode();
+        assert_eq!(socket_mode & 0o777, 0o600);
+    }
+
+    #[tokio::test]
+    async fn test_socket_dir_created_private() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("a").join("b").join("wolfpack.sock");
+        let _ipc = IpcSocket::new(&path).await.unwrap();
+
+        let dir_mode = std::fs::metadata(path.parent().unwrap()).unwrap().mode();
+        assert_eq!(dir_mode & 0o777, 0o700);
+    }
 }
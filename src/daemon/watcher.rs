@@ -1,7 +1,6 @@
 use anyhow::Result;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
 use std::path::Path;
-use std::sync::mpsc;
 use tokio::sync::broadcast;
 
 pub struct FileWatcher {
@@ -14,12 +13,13 @@ impl FileWatcher {
         let (tx, _rx) = broadcast::channel(100);
         let tx_clone = tx.clone();
 
-        let (sync_tx, sync_rx) = mpsc::channel();
-
+        // Sending on a broadcast channel doesn't block, so events go straight
+        // from the watcher's thread to the daemon, with no forwarding thread
+        // that could die on its own
         let mut watcher = RecommendedWatcher::new(
             move |res: Result<notify::Event, notify::Error>| {
                 if let Ok(event) = res {
-                    let _ = sync_tx.send(event);
+                    let _ = tx_clone.send(event);
                 }
             },
             Config::default(),
@@ -29,13 +29,6 @@ impl FileWatcher {
             watcher.watch(path, RecursiveMode::Recursive)?;
         }
 
-        // Spawn a task to forward events from sync channel to async broadcast
-        std::thread::spawn(move || {
-            while let Ok(event) = sync_rx.recv() {
-                let _ = tx_clone.send(event);
-            }
-        });
-
         Ok(Self {
             _watcher: watcher,
             events: tx.subscribe(),
@@ -0,0 +1,217 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::sync::Conflict;
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Something the daemon tells the user about through the webhook
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Notification {
+    DevicePaired {
+        device_id: String,
+        device_name: String,
+    },
+    TabReceived {
+        from_device: String,
+        url: String,
+        /// Page title (`title` is the notification's own)
+        page_title: Option<String>,
+    },
+    SyncError {
+        /// What the daemon was doing, e.g. "Profile scan"
+        context: String,
+        error: String,
+    },
+    ConflictDetected {
+        category: String,
+        entity: String,
+        devices: Vec<String>,
+    },
+}
+
+impl Notification {
+    pub fn sync_error(context: &str, error: &anyhow::Error) -> Self {
+        Self::SyncError {
+            context: context.to_string(),
+            error: error.to_string(),
+        }
+    }
+
+    pub fn conflict(conflict: Conflict) -> Self {
+        Self::ConflictDetected {
+            category: conflict.category.as_str().to_string(),
+            entity: conflict.entity,
+            devices: conflict.devices,
+        }
+    }
+
+    fn title(&self) -> &'static str {
+        match self {
+            Self::DevicePaired { .. } => "Device paired",
+            Self::TabReceived { .. } => "Tab received",
+            Self::SyncError { .. } => "Sync error",
+            Self::ConflictDetected { .. } => "Sync conflict",
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            Self::DevicePaired { device_name, .. } => format!("Paired with {}", device_name),
+            Self::TabReceived {
+                from_device,
+                url,
+                page_title,
+            } => format!(
+                "{} sent {}",
+                from_device,
+                page_title.as_deref().unwrap_or(url.as_str())
+            ),
+            Self::SyncError { context, error } => format!("{} failed: {}", context, error),
+            Self::ConflictDetected {
+                category,
+                entity,
+                devices,
+            } => format!(
+                "{} and {} changed {} {} at the same time; the latest change was kept",
+                devices.first().map_or("?", String::as_str),
+                devices.get(1).map_or("?", String::as_str),
+                category,
+                entity
+            ),
+        }
+    }
+
+    /// JSON body of the webhook request
+    ///
+    /// `title` and `message` are included so services like Gotify can show
+    /// the notification without a template.
+    fn payload(&self, device: &str, timestamp: &str) -> Value {
+        let mut payload = serde_json::to_value(self).unwrap_or_default();
+        if let Value::Object(fields) = &mut payload {
+            fields.insert("title".to_string(), Value::from(self.title()));
+            fields.insert("message".to_string(), Value::from(self.message()));
+            fields.insert("device".to_string(), Value::from(device));
+            fields.insert("timestamp".to_string(), Value::from(timestamp));
+        }
+        payload
+    }
+}
+
+/// Sends notifications to the configured webhook in the background, so
+/// a slow endpoint never holds up syncing
+#[derive(Debug, Clone, Default)]
+pub struct Webhook {
+    sender: Option<mpsc::UnboundedSender<Notification>>,
+}
+
+impl Webhook {
+    /// Start delivering to `url`, or do nothing without one
+    ///
+    /// With a SOCKS5 proxy, requests and their DNS lookups go through it.
+    pub fn start(url: Option<&str>, device_name: &str, socks5: Option<&str>) -> Result<Self> {
+        let Some(url) = url else {
+            return Ok(Self::default());
+        };
+
+        let mut builder = reqwest::Client::builder().timeout(WEBHOOK_TIMEOUT);
+        if let Some(proxy) = socks5 {
+            builder = builder.proxy(reqwest::Proxy::all(format!("socks5h://{}", proxy))?);
+        }
+        let client = builder.build()?;
+        let url = url.to_string();
+        let device_name = device_name.to_string();
+        let (sender, mut receiver) = mpsc::unbounded_channel::<Notification>();
+
+        tokio::spawn(async move {
+            while let Some(notification) = receiver.recv().await {
+                let payload = notification.payload(&device_name, &chrono::Utc::now().to_rfc3339());
+                match deliver(&client, &url, &payload).await {
+                    Ok(()) => debug!(event = notification.title(), "Sent webhook notification"),
+                    Err(e) => warn!("Failed to send webhook notification: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            sender: Some(sender),
+        })
+    }
+
+    pub fn send(&self, notification: Notification) {
+        if let Some(sender) = &self.sender {
+            // Only fails once the daemon is shutting down
+            let _ = sender.send(notification);
+        }
+    }
+}
+
+async fn deliver(client: &reqwest::Client, url: &str, payload: &Value) -> Result<()> {
+    client
+        .post(url)
+        .json(payload)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::SyncCategory;
+
+    #[test]
+    fn test_payload() {
+        let notification = Notification::TabReceived {
+            from_device: "phone".to_string(),
+            url: "https://example.com/".to_string(),
+            page_title: Some("Example".to_string()),
+        };
+
+        let payload = notification.payload("laptop", "2026-01-01T00:00:00+00:00");
+        assert_eq!(
+            payload,
+            serde_json::json!({
+                "event": "tab_received",
+                "from_device": "phone",
+                "url": "https://example.com/",
+                "page_title": "Example",
+                "title": "Tab received",
+                "message": "phone sent Example",
+                "device": "laptop",
+                "timestamp": "2026-01-01T00:00:00+00:00",
+            })
+        );
+    }
+
+    #[test]
+    fn test_conflict_message() {
+        let notification = Notification::conflict(Conflict {
+            category: SyncCategory::Prefs,
+            entity: "browser.startup.page".to_string(),
+            devices: vec!["phone".to_string(), "laptop".to_string()],
+        });
+
+        assert_eq!(
+            notification.message(),
+            "phone and laptop changed prefs browser.startup.page at the same time; \
+             the latest change was kept"
+        );
+    }
+
+    #[test]
+    fn test_disabled_webhook() {
+        let webhook = Webhook::start(None, "laptop", None).unwrap();
+        webhook.send(Notification::sync_error(
+            "Profile scan",
+            &anyhow::anyhow!("unreadable"),
+        ));
+        assert!(webhook.sender.is_none());
+    }
+}
@@ -0,0 +1,377 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::warn;
+
+use super::EventFile;
+use super::log::{
+    device_files, event_file_number, event_file_path, is_safe_device_id, is_shard_name,
+};
+use crate::profile::write_atomic;
+
+/// Bumped when the layout of an archive changes
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Top-level directory of every file in an archive
+const ARCHIVE_DIR: &str = "wolfpack-archive";
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Archives are written once and kept, so they're worth compressing hard
+const ZSTD_LEVEL: i32 = 19;
+
+/// What an archive holds, stored first in it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub version: u32,
+    pub created_at: String,
+    /// Device the archive was made on
+    pub device_id: String,
+    /// Event files from each device
+    pub event_files: BTreeMap<String, usize>,
+    /// XPI chunks from the blob store
+    pub blobs: usize,
+}
+
+/// What importing an archive restored
+#[derive(Debug)]
+pub struct ArchiveImport {
+    pub manifest: ArchiveManifest,
+    /// Event files and chunks written to the sync dir
+    pub restored: usize,
+    /// Files the sync dir already had
+    pub skipped: usize,
+    /// Event files the sync dir has under the same name with other content,
+    /// which are left as they are
+    pub conflicts: usize,
+}
+
+/// Write the sync dir's event files and XPI chunks to a zstd-compressed tar
+/// archive, as they are: still encrypted, so only the pack can read them
+pub fn write_archive(
+    sync_dir: &Path,
+    device_id: &str,
+    writer: impl Write,
+) -> Result<ArchiveManifest> {
+    let event_files = list_event_files(sync_dir)?;
+    let blobs = list_blobs(sync_dir)?;
+
+    let mut manifest = ArchiveManifest {
+        version: ARCHIVE_VERSION,
+        created_at: chrono::Utc::now().to_rfc3339(),
+        device_id: device_id.to_string(),
+        event_files: BTreeMap::new(),
+        blobs: blobs.len(),
+    };
+    for (device, _) in &event_files {
+        *manifest.event_files.entry(device.clone()).or_default() += 1;
+    }
+
+    let encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+    let mut builder = tar::Builder::new(encoder);
+    let content = serde_json::to_vec_pretty(&manifest)?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+    header.set_cksum();
+    builder.append_data(
+        &mut header,
+        format!("{}/{}", ARCHIVE_DIR, MANIFEST_FILE),
+        content.as_slice(),
+    )?;
+
+    for (_, path) in event_files.iter().chain(blobs.iter()) {
+        let name = path.strip_prefix(sync_dir)?;
+        builder
+            .append_path_with_name(path, Path::new(ARCHIVE_DIR).join(name))
+            .with_context(|| format!("Failed to archive {}", path.display()))?;
+    }
+    builder.into_inner()?.finish()?.flush()?;
+    Ok(manifest)
+}
+
+/// Restore an archive's event files and XPI chunks into a sync dir
+///
+/// Files the sync dir already has are never overwritten, so importing into
+/// a sync dir in use only fills in the history it's missing.
+pub fn import_archive(sync_dir: &Path, reader: impl Read) -> Result<ArchiveImport> {
+    let decoder = zstd::Decoder::new(reader).context("Not a zstd-compressed archive")?;
+    let mut archive = tar::Archive::new(decoder);
+    let mut entries = archive.entries()?;
+
+    let manifest = read_manifest(entries.next())?;
+    let mut import = ArchiveImport {
+        manifest,
+        restored: 0,
+        skipped: 0,
+        conflicts: 0,
+    };
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let Some(name) = sync_dir_path(&path) else {
+            warn!("Skipping unexpected file {} in archive", path.display());
+            continue;
+        };
+
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        if name.starts_with("events") {
+            EventFile::read_from(content.as_slice())
+                .with_context(|| format!("{} isn't an event file", path.display()))?;
+        }
+        import.restore(&sync_dir.join(&name), content)?;
+    }
+
+    Ok(import)
+}
+
+impl ArchiveImport {
+    /// Write a file to the sync dir unless it's already there
+    fn restore(&mut self, target: &Path, content: Vec<u8>) -> Result<()> {
+        if !target.exists() {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            write_atomic(target, content)
+                .with_context(|| format!("Failed to restore {}", target.display()))?;
+            self.restored += 1;
+        } else if fs::read(target)? == content {
+            self.skipped += 1;
+        } else {
+            // Chunks are named by their content, so only event files differ
+            warn!(
+                "Keeping {}, which differs from the archived copy",
+                target.display()
+            );
+            self.conflicts += 1;
+        }
+        Ok(())
+    }
+}
+
+fn read_manifest<R: Read>(
+    entry: Option<std::io::Result<tar::Entry<'_, R>>>,
+) -> Result<ArchiveManifest> {
+    let Some(entry) = entry else {
+        bail!("Archive is empty");
+    };
+    let mut entry = entry?;
+    if entry.path()? != Path::new(ARCHIVE_DIR).join(MANIFEST_FILE) {
+        bail!("Not a wolfpack archive (no manifest)");
+    }
+    let mut content = Vec::new();
+    entry.read_to_end(&mut content)?;
+    let manifest: ArchiveManifest =
+        serde_json::from_slice(&content).context("Failed to parse archive manifest")?;
+    if manifest.version > ARCHIVE_VERSION {
+        bail!(
+            "Archive version {} is newer than this wolfpack supports ({})",
+            manifest.version,
+            ARCHIVE_VERSION
+        );
+    }
+    Ok(manifest)
+}
+
+/// Where an archived file goes in the sync dir, for event files and chunks
+/// only
+fn sync_dir_path(path: &Path) -> Option<PathBuf> {
+    let parts: Vec<&str> = path
+        .components()
+        .map(|c| match c {
+            Component::Normal(part) => part.to_str(),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    match parts.as_slice() {
+        [ARCHIVE_DIR, "events", device, rest @ ..] if is_safe_device_id(device) => {
+            // In its shard, or from an archive of a sync dir that wasn't sharded
+            let file = match rest {
+                [file] => file,
+                [shard, file] if is_shard_name(shard) => file,
+                _ => return None,
+            };
+            let number = event_file_number(file)?;
+            Some(event_file_path(
+                &Path::new("events").join(device),
+                number,
+                file,
+            ))
+        }
+        [ARCHIVE_DIR, "blobs", prefix, file] if is_blob_name(prefix, file) => {
+            Some(Path::new("blobs").join(prefix).join(file))
+        }
+        _ => None,
+    }
+}
+
+fn is_event_file_name(name: &str) -> bool {
+    event_file_number(name).is_some()
+}
+
+fn is_blob_name(prefix: &str, name: &str) -> bool {
+    name.strip_suffix(".blk").is_some_and(|id| {
+        id.len() == 64 && id.starts_with(prefix) && id.chars().all(|c| c.is_ascii_hexdigit())
+    })
+}
+
+/// Event files in the sync dir, by device
+fn list_event_files(sync_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut files = Vec::new();
+    for device_dir in read_dir_sorted(&sync_dir.join("events"))? {
+        let Some(device) = device_dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !device_dir.is_dir() || !is_safe_device_id(device) {
+            continue;
+        }
+        let mut paths = device_files(&device_dir)?;
+        paths.sort();
+        for path in paths {
+            if path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(is_event_file_name)
+            {
+                files.push((device.to_string(), path));
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn list_blobs(sync_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let mut blobs = Vec::new();
+    for prefix_dir in read_dir_sorted(&sync_dir.join("blobs"))? {
+        let Some(prefix) = prefix_dir.file_name().and_then(|n| n.to_str()) else {
+            continue;
+        };
+        if !prefix_dir.is_dir() {
+            continue;
+        }
+        for path in read_dir_sorted(&prefix_dir)? {
+            if path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .is_some_and(|name| is_blob_name(prefix, name))
+            {
+                blobs.push((prefix.to_string(), path));
+            }
+        }
+    }
+    Ok(blobs)
+}
+
+fn read_dir_sorted(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::events::{Event, EventLog};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_archive_round_trip() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let known_devices = vec![("device-a".to_string(), keypair.public_key())];
+        let sync_dir = dir.path().join("sync");
+
+        let mut log = EventLog::new(sync_dir.clone(), "device-a".into(), keypair.clone());
+        let xpi = crate::extensions::compress_xpi(&vec![7u8; 4096]).unwrap();
+        log.write_events(
+            vec![Event::ExtensionInstalled {
+                id: "ext@example.com".to_string(),
+                name: "Ext".to_string(),
+                version: "1.0".to_string(),
+                source: crate::events::ExtensionSource::Amo {
+                    amo_slug: "ext".to_string(),
+                },
+                xpi_data: crate::extensions::encode_base64(&xpi),
+                chunks: Vec::new(),
+            }],
+            &known_devices,
+        )
+        .unwrap();
+        let second = log
+            .write_events(
+                vec![Event::PrefRemoved {
+                    key: "browser.startup.page".to_string(),
+                }],
+                &known_devices,
+            )
+            .unwrap();
+
+        let mut archive = Vec::new();
+        let manifest = write_archive(&sync_dir, "device-a", &mut archive).unwrap();
+        assert_eq!(manifest.event_files["device-a"], 2);
+        assert!(manifest.blobs > 0);
+
+        // Restored onto a new sync dir, the history reads back the same
+        let restored_dir = dir.path().join("restored");
+        let import = import_archive(&restored_dir, archive.as_slice()).unwrap();
+        assert_eq!(import.manifest, manifest);
+        assert_eq!(import.restored, 2 + manifest.blobs);
+        let restored = EventLog::new(restored_dir.clone(), "device-a".into(), keypair);
+        let events = restored
+            .read_device_events("device-a", &known_devices)
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0].event,
+            Event::ExtensionInstalled { xpi_data, .. } if !xpi_data.is_empty()
+        ));
+
+        // Nothing is overwritten
+        let second = restored_dir.join(second.strip_prefix(&sync_dir).unwrap());
+        fs::write(second, b"changed").unwrap();
+        let import = import_archive(&restored_dir, archive.as_slice()).unwrap();
+        assert_eq!((import.restored, import.conflicts), (0, 1));
+        assert_eq!(import.skipped, 1 + manifest.blobs);
+    }
+
+    #[test]
+    fn test_sync_dir_path() {
+        assert_eq!(
+            sync_dir_path(Path::new("wolfpack-archive/events/device-a/0001.evt")),
+            Some(PathBuf::from("events/device-a/000/0001.evt"))
+        );
+        assert_eq!(
+            sync_dir_path(Path::new(
+                "wolfpack-archive/events/device-a/001/1234-1a2b3c4d.evt"
+            )),
+            Some(PathBuf::from("events/device-a/001/1234-1a2b3c4d.evt"))
+        );
+        let blob = format!("wolfpack-archive/blobs/ab/ab{}.blk", "0".repeat(62));
+        assert!(sync_dir_path(Path::new(&blob)).is_some());
+
+        assert!(sync_dir_path(Path::new("wolfpack-archive/events/../0001.evt")).is_none());
+        assert!(sync_dir_path(Path::new("wolfpack-archive/events/.hidden/0001.evt")).is_none());
+        assert!(sync_dir_path(Path::new("wolfpack-archive/events/device-a/notes.txt")).is_none());
+        assert!(sync_dir_path(Path::new("wolfpack-archive/events/device-a/x/0001.evt")).is_none());
+        assert!(sync_dir_path(Path::new("/etc/passwd")).is_none());
+    }
+
+    #[test]
+    fn test_import_rejects_other_files() {
+        let dir = tempdir().unwrap();
+        let compressed = zstd::encode_all(b"not a tar".as_slice(), 0).unwrap();
+        assert!(import_archive(dir.path(), compressed.as_slice()).is_err());
+        assert!(import_archive(dir.path(), b"plain".as_slice()).is_err());
+    }
+}
@@ -0,0 +1,286 @@
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+use super::chunking;
+use crate::crypto::{self, Cipher};
+use crate::profile::write_atomic;
+
+/// Chunks of extension XPIs, shared by every event that carries them
+///
+/// Each chunk is stored once under `blobs/<2 hex>/<id>.blk` in the sync dir,
+/// so an extension's new version only adds the chunks that changed, and
+/// libraries vendored into several extensions are stored once. Chunk IDs are
+/// hashes keyed with the group secret, so they don't reveal which files are
+/// in the pack, and chunks are encrypted with a nonce derived from their ID:
+/// the same key and nonce only ever encrypt the same chunk.
+///
+/// Which device added each chunk is recorded under `blobs/owners/`, so the
+/// chunks count towards that device's storage quota.
+pub struct BlobStore {
+    path: PathBuf,
+}
+
+/// Chunks as stored, still encrypted, with their IDs: what a device adds to
+/// the store, or sends a peer along with the event files that refer to them
+pub type ChunkBlobs = Vec<(String, Vec<u8>)>;
+
+/// Where the chunks each device added are listed, under the store
+const OWNERS_DIR: &str = "owners";
+
+impl BlobStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Split data into chunks and store the ones not stored yet, returning
+    /// the IDs to load it back with
+    #[cfg(test)]
+    pub fn store(&self, data: &[u8], secret: &[u8; 32]) -> Result<Vec<String>> {
+        let (ids, new) = self.prepare(data, secret)?;
+        for (id, blob) in &new {
+            self.insert(id, blob)?;
+        }
+        Ok(ids)
+    }
+
+    /// Split data into chunks without storing anything, returning the IDs to
+    /// load it back with and the encrypted chunks the store doesn't have yet
+    pub fn prepare(&self, data: &[u8], secret: &[u8; 32]) -> Result<(Vec<String>, ChunkBlobs)> {
+        let mut ids = Vec::new();
+        let mut new: ChunkBlobs = Vec::new();
+        for chunk in chunking::chunks(data) {
+            let id = chunk_id(chunk, secret);
+            if !self.blob_path(&id).exists() && !new.iter().any(|(new, _)| *new == id) {
+                new.push((id.clone(), encrypt_chunk(&id, chunk, secret)?));
+            }
+            ids.push(id);
+        }
+        Ok((ids, new))
+    }
+
+    /// Write an encrypted chunk under its ID
+    pub fn insert(&self, id: &str, blob: &[u8]) -> Result<()> {
+        let path = self.blob_path(id);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        write_atomic(&path, blob)
+            .with_context(|| format!("Failed to write chunk {}", path.display()))
+    }
+
+    /// Delete a chunk, one written for an event file that then failed to save
+    pub fn remove(&self, id: &str) {
+        let _ = std::fs::remove_file(self.blob_path(id));
+    }
+
+    /// A chunk as stored, still encrypted, or None if it isn't here
+    pub fn read(&self, id: &str) -> Option<Vec<u8>> {
+        if id.len() != 64 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        std::fs::read(self.blob_path(id)).ok()
+    }
+
+    /// Whether an encrypted chunk a peer sent is the one its ID names, under
+    /// any of the secrets
+    pub fn verify(id: &str, blob: &[u8], secrets: &[&[u8; 32]]) -> bool {
+        secrets.iter().any(|secret| {
+            decrypt_chunk(blob, secret).is_ok_and(|chunk| chunk_id(&chunk, secret) == id)
+        })
+    }
+
+    /// Reassemble data from its chunks
+    pub fn load(&self, ids: &[String], secret: &[u8; 32]) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        for id in ids {
+            let path = self.blob_path(id);
+            let blob = std::fs::read(&path)
+                .with_context(|| format!("Failed to read chunk {}", path.display()))?;
+            let chunk = decrypt_chunk(&blob, secret)
+                .with_context(|| format!("Failed to decrypt chunk {}", id))?;
+            if chunk_id(&chunk, secret) != *id {
+                bail!("Chunk {} doesn't match its ID", id);
+            }
+            data.extend(chunk);
+        }
+        Ok(data)
+    }
+
+    /// Whether every chunk is in the store, which they may not be yet while
+    /// the sync dir is still syncing
+    pub fn contains_all(&self, ids: &[String]) -> bool {
+        ids.iter().all(|id| self.blob_path(id).exists())
+    }
+
+    /// Bytes the given chunks take up, leaving out any not synced yet
+    pub fn size_of(&self, ids: &[String]) -> u64 {
+        ids.iter()
+            .filter_map(|id| std::fs::metadata(self.blob_path(id)).ok())
+            .map(|metadata| metadata.len())
+            .sum()
+    }
+
+    /// Bytes every stored chunk takes up
+    pub fn size(&self) -> Result<u64> {
+        if !self.path.exists() {
+            return Ok(0);
+        }
+        let mut size = 0;
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_name() != OWNERS_DIR {
+                size += super::limits::dir_size(&entry.path())?;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Record that a device added these chunks
+    pub fn record_owner(&self, device: &str, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let path = self.owners_path(device);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        file.write_all(format!("{}\n", ids.join("\n")).as_bytes())?;
+        Ok(())
+    }
+
+    /// Bytes the chunks a device added take up
+    pub fn device_size(&self, device: &str) -> Result<u64> {
+        let path = self.owners_path(device);
+        if !path.exists() {
+            return Ok(0);
+        }
+        let owned = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let ids: BTreeSet<String> = owned.lines().map(String::from).collect();
+        Ok(self.size_of(&ids.into_iter().collect::<Vec<_>>()))
+    }
+
+    fn owners_path(&self, device: &str) -> PathBuf {
+        self.path.join(OWNERS_DIR).join(format!("{}.txt", device))
+    }
+
+    fn blob_path(&self, id: &str) -> PathBuf {
+        let prefix = id.get(..2).unwrap_or(id);
+        self.path.join(prefix).join(format!("{}.blk", id))
+    }
+}
+
+fn chunk_id(chunk: &[u8], secret: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"wolfpack-chunk");
+    hasher.update(secret);
+    hasher.update(chunk);
+    hex::encode(hasher.finalize())
+}
+
+/// Blob layout: cipher, nonce length, nonce, then the zstd-compressed chunk,
+/// encrypted
+fn encrypt_chunk(id: &str, chunk: &[u8], secret: &[u8; 32]) -> Result<Vec<u8>> {
+    let compressed = zstd::encode_all(chunk, 19).context("Failed to compress chunk")?;
+    let cipher = crypto::detect_preferred_cipher();
+    let (nonce, ciphertext) = crypto::encrypt(cipher, secret, id, 0, &compressed)?;
+
+    let mut blob = vec![cipher as u8, nonce.len() as u8];
+    blob.extend(nonce);
+    blob.extend(ciphertext);
+    Ok(blob)
+}
+
+fn decrypt_chunk(blob: &[u8], secret: &[u8; 32]) -> Result<Vec<u8>> {
+    let [cipher, nonce_len, rest @ ..] = blob else {
+        bail!("Chunk is truncated");
+    };
+    let cipher = Cipher::from_byte(*cipher)
+        .ok_or_else(|| anyhow::anyhow!("Unknown cipher type: {}", cipher))?;
+    if rest.len() < *nonce_len as usize {
+        bail!("Chunk is truncated");
+    }
+    let (nonce, ciphertext) = rest.split_at(*nonce_len as usize);
+    let compressed = crypto::decrypt(cipher, secret, nonce, ciphertext)?;
+    zstd::decode_all(compressed.as_slice()).context("Failed to decompress chunk")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::chunking::noise;
+    use crate::events::limits::dir_size;
+    use std::path::Path;
+    use tempfile::tempdir;
+
+    fn store_size(path: &Path) -> u64 {
+        std::fs::read_dir(path)
+            .unwrap()
+            .map(|entry| dir_size(&entry.unwrap().path()).unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn test_store_and_load() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().join("blobs"));
+        let secret = [1u8; 32];
+        let data = noise(100 * 1024, 1);
+
+        let ids = store.store(&data, &secret).unwrap();
+        assert!(store.contains_all(&ids));
+        assert_eq!(store.load(&ids, &secret).unwrap(), data);
+        assert!(store.size_of(&ids) > 0);
+        assert_eq!(store.size_of(&ids), store.size().unwrap());
+
+        // Another secret can't read the chunks
+        assert!(store.load(&ids, &[2u8; 32]).is_err());
+    }
+
+    #[test]
+    fn test_shared_chunks_stored_once() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("blobs");
+        let store = BlobStore::new(path.clone());
+        let secret = [1u8; 32];
+        let library = noise(256 * 1024, 2);
+
+        store
+            .store(&[noise(4000, 3), library.clone()].concat(), &secret)
+            .unwrap();
+        let once = store_size(&path);
+        let ids = store
+            .store(&[noise(7000, 4), library].concat(), &secret)
+            .unwrap();
+        let twice = store_size(&path);
+
+        // The second copy of the library adds little beyond its edges
+        assert!(twice - once < 64 * 1024, "grew by {}", twice - once);
+        assert!(store.load(&ids, &secret).is_ok());
+    }
+
+    #[test]
+    fn test_missing_and_tampered_chunks() {
+        let dir = tempdir().unwrap();
+        let store = BlobStore::new(dir.path().join("blobs"));
+        let secret = [1u8; 32];
+
+        let ids = store.store(&noise(4000, 5), &secret).unwrap();
+        let missing = vec!["00".repeat(32)];
+        assert!(!store.contains_all(&missing));
+        assert!(store.load(&missing, &secret).is_err());
+
+        let other = store.store(&noise(4000, 6), &secret).unwrap();
+        std::fs::copy(store.blob_path(&other[0]), store.blob_path(&ids[0])).unwrap();
+        let err = store.load(&ids, &secret).unwrap_err();
+        assert!(err.to_string().contains("doesn't match"));
+    }
+}
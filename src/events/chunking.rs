@@ -0,0 +1,119 @@
+/// Smallest chunk cut, except for the end of the data
+pub const MIN_CHUNK: usize = 2 * 1024;
+/// Chunk size the cut points are normalized around
+pub const AVG_CHUNK: usize = 8 * 1024;
+/// Largest chunk cut
+pub const MAX_CHUNK: usize = 64 * 1024;
+
+/// Harder to match before the average size, so chunks cluster around it
+const MASK_SMALL: u64 = !0 << 49;
+/// Easier to match after it
+const MASK_LARGE: u64 = !0 << 53;
+
+/// Gear hash values, one per byte value, from a fixed splitmix64 sequence so
+/// every device cuts at the same points
+const GEAR: [u64; 256] = gear_table();
+
+const fn gear_table() -> [u64; 256] {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x776f_6c66_7061_636b; // "wolfpack"
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+}
+
+/// Split data into content-defined chunks (FastCDC)
+///
+/// Cut points depend only on the bytes around them, so data shared between
+/// two files, like a library vendored into several extensions, tends to
+/// split into the same chunks in both even when it sits at different
+/// offsets.
+pub fn chunks(data: &[u8]) -> Vec<&[u8]> {
+    let mut chunks = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, tail) = rest.split_at(cut_point(rest));
+        chunks.push(chunk);
+        rest = tail;
+    }
+    chunks
+}
+
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let normal = end.min(AVG_CHUNK);
+
+    let mut hash = 0u64;
+    for (i, &byte) in data.iter().enumerate().take(end).skip(MIN_CHUNK) {
+        hash = (hash << 1).wrapping_add(GEAR[byte as usize]);
+        let mask = if i < normal { MASK_SMALL } else { MASK_LARGE };
+        if hash & mask == 0 {
+            return i;
+        }
+    }
+    end
+}
+
+/// Deterministic bytes that don't repeat, standing in for file contents in
+/// the chunking and blob store tests
+#[cfg(test)]
+pub(super) fn noise(len: usize, seed: u64) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunks_cover_data() {
+        let data = noise(300 * 1024, 1);
+        let chunks = chunks(&data);
+        assert_eq!(chunks.concat(), data);
+        assert!(chunks.len() > 1);
+
+        let (last, rest) = chunks.split_last().unwrap();
+        assert!(
+            rest.iter()
+                .all(|c| (MIN_CHUNK..=MAX_CHUNK).contains(&c.len()))
+        );
+        assert!(last.len() <= MAX_CHUNK);
+
+        assert_eq!(super::chunks(&[1, 2, 3]), vec![&[1u8, 2, 3][..]]);
+        assert!(super::chunks(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_shared_data_chunks_the_same() {
+        let library = noise(200 * 1024, 2);
+        let first = [noise(5000, 3), library.clone(), noise(9000, 4)].concat();
+        let second = [noise(12_345, 5), library, noise(100, 6)].concat();
+
+        let first_chunks = chunks(&first);
+        let shared = chunks(&second)
+            .into_iter()
+            .filter(|chunk| first_chunks.contains(chunk))
+            .map(|chunk| chunk.len())
+            .sum::<usize>();
+        // Everything but the chunks at the library's edges is shared
+        assert!(shared > 150 * 1024, "only {} bytes shared", shared);
+    }
+}
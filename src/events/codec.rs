@@ -0,0 +1,112 @@
+use anyhow::{Context, Result, bail};
+
+use super::EventEnvelope;
+
+/// How events are encoded before they're encrypted into an event file
+///
+/// Files don't record their codec: a JSON list always starts with `[`,
+/// which no CBOR array does, so reading tells them apart and a pack can
+/// hold files written with either.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EventCodec {
+    /// JSON, readable by every version
+    #[default]
+    Json,
+    /// CBOR, smaller and faster to parse, but only read by versions that
+    /// know it
+    Cbor,
+}
+
+impl EventCodec {
+    /// Parse a codec name from `sync.event_codec`
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "cbor" => Ok(Self::Cbor),
+            other => bail!(
+                "Unknown event codec '{}' in sync.event_codec, expected json or cbor",
+                other
+            ),
+        }
+    }
+
+    /// The codec events were encoded with
+    pub fn detect(plaintext: &[u8]) -> Self {
+        match plaintext.first() {
+            Some(b'[') => Self::Json,
+            _ => Self::Cbor,
+        }
+    }
+
+    pub fn encode(self, events: &[EventEnvelope]) -> Result<Vec<u8>> {
+        match self {
+            Self::Json => serde_json::to_vec(events).context("Failed to serialize events"),
+            Self::Cbor => {
+                let mut bytes = Vec::new();
+                ciborium::into_writer(events, &mut bytes).context("Failed to serialize events")?;
+                Ok(bytes)
+            }
+        }
+    }
+
+    pub fn decode(self, plaintext: &[u8]) -> Result<Vec<EventEnvelope>> {
+        match self {
+            Self::Json => serde_json::from_slice(plaintext).context("Failed to deserialize events"),
+            Self::Cbor => ciborium::from_reader(plaintext).context("Failed to deserialize events"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Event, PrefValue, VectorClock};
+
+    fn events() -> Vec<EventEnvelope> {
+        vec![
+            EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::PrefSet {
+                    key: "browser.startup.page".to_string(),
+                    value: PrefValue::Int(3),
+                },
+            ),
+            EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::ExtensionAdded {
+                    id: "ext@example.com".to_string(),
+                    name: "Example".to_string(),
+                    url: None,
+                },
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let events = events();
+        for codec in [EventCodec::Json, EventCodec::Cbor] {
+            let bytes = codec.encode(&events).unwrap();
+            assert_eq!(EventCodec::detect(&bytes), codec);
+            let decoded = codec.decode(&bytes).unwrap();
+            assert_eq!(decoded.len(), events.len());
+            for (decoded, event) in decoded.iter().zip(&events) {
+                assert_eq!(decoded.id, event.id);
+                assert_eq!(decoded.timestamp, event.timestamp);
+                assert_eq!(decoded.event, event.event);
+            }
+        }
+        // An empty list too
+        let empty = EventCodec::Cbor.encode(&[]).unwrap();
+        assert_eq!(EventCodec::detect(&empty), EventCodec::Cbor);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(EventCodec::from_name("json").unwrap(), EventCodec::Json);
+        assert_eq!(EventCodec::from_name("CBOR").unwrap(), EventCodec::Cbor);
+        assert!(EventCodec::from_name("msgpack").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::Path;
+use tracing::{info, warn};
+
+use super::EventFile;
+use super::log::{event_file_name, event_file_number, event_file_path};
+use crate::profile::write_atomic;
+
+/// File in the sync dir recording how it's laid out
+pub const LAYOUT_FILE: &str = "layout.version";
+
+/// Bumped when how the sync dir is laid out changes, with a migration from
+/// the version before added to `MIGRATIONS`
+///
+/// 1. Event files named by number alone (`0007.evt`)
+/// 2. Event files named by number and content hash (`0007-1a2b3c4d.evt`)
+/// 3. Event files in shards of a thousand under each device's directory
+///    (`000/0007-1a2b3c4d.evt`)
+pub const LAYOUT_VERSION: u32 = 3;
+
+type Migration = fn(&Path) -> Result<()>;
+
+/// Each version's migration from the one before it, in order
+const MIGRATIONS: &[(u32, Migration)] = &[(2, hash_event_file_names), (3, shard_event_files)];
+
+/// The layout version of a sync dir: what its marker file says, or 1 for a
+/// sync dir that has events but no marker, from before there was one
+pub fn layout_version(sync_dir: &Path) -> Result<Option<u32>> {
+    let path = sync_dir.join(LAYOUT_FILE);
+    if path.exists() {
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let version = content
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid layout version in {}", path.display()))?;
+        return Ok(Some(version));
+    }
+    Ok(sync_dir.join("events").exists().then_some(1))
+}
+
+/// Bring a sync dir up to the current layout, returning the version it was
+/// at when it needed migrating
+///
+/// Each migration is recorded in the marker file as it finishes, so one
+/// that's interrupted picks up where it left off. Migrations only ever
+/// produce what the same migration on another device of the pack would, so
+/// devices sharing the sync dir can run them at once.
+pub fn migrate_layout(sync_dir: &Path) -> Result<Option<u32>> {
+    let Some(from) = layout_version(sync_dir)? else {
+        fs::create_dir_all(sync_dir)?;
+        write_version(sync_dir, LAYOUT_VERSION)?;
+        return Ok(None);
+    };
+    check_version(sync_dir, from)?;
+    if from == LAYOUT_VERSION {
+        return Ok(None);
+    }
+
+    warn!(
+        "Migrating sync dir from layout version {} to {}; devices of the pack running an \
+         older wolfpack won't see events until they're updated",
+        from, LAYOUT_VERSION
+    );
+    for (version, migration) in MIGRATIONS.iter().filter(|(v, _)| *v > from) {
+        info!("Migrating sync dir to layout version {}", version);
+        migration(sync_dir)
+            .with_context(|| format!("Failed to migrate sync dir to layout version {}", version))?;
+        write_version(sync_dir, *version)?;
+    }
+    Ok(Some(from))
+}
+
+/// Fail if the sync dir has been moved to a layout newer than this
+/// wolfpack reads
+///
+/// Checked whenever events are read or written, not only at startup, since
+/// a newer wolfpack on another device of the pack can migrate a sync dir
+/// they share while this one is running, and this one would otherwise carry
+/// on without seeing events written since.
+pub(super) fn check_layout(sync_dir: &Path) -> Result<()> {
+    match layout_version(sync_dir)? {
+        Some(version) => check_version(sync_dir, version),
+        None => Ok(()),
+    }
+}
+
+fn check_version(sync_dir: &Path, version: u32) -> Result<()> {
+    if version > LAYOUT_VERSION {
+        bail!(
+            "Sync dir {} has layout version {}, newer than this wolfpack supports ({}); update wolfpack",
+            sync_dir.display(),
+            version,
+            LAYOUT_VERSION
+        );
+    }
+    Ok(())
+}
+
+fn write_version(sync_dir: &Path, version: u32) -> Result<()> {
+    write_atomic(&sync_dir.join(LAYOUT_FILE), format!("{}\n", version))
+}
+
+/// 1 → 2: rename `0007.evt` to `0007-<hash>.evt`
+///
+/// Files that don't load, such as ones Syncthing is partway through
+/// writing, are left as they are rather than holding up the migration.
+fn hash_event_file_names(sync_dir: &Path) -> Result<()> {
+    let events = sync_dir.join("events");
+    if !events.exists() {
+        return Ok(());
+    }
+    for device in fs::read_dir(&events)? {
+        let device = device?;
+        if !device.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(device.path())? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(number) = event_file_number(name).filter(|_| !name.contains('-')) else {
+                continue;
+            };
+            let file = match EventFile::load(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    // Likely still syncing, and read under its old name
+                    // once it's whole
+                    warn!(file = %path.display(), "Leaving an event file that doesn't load: {}", e);
+                    continue;
+                }
+            };
+            let target = path.with_file_name(event_file_name(number, &file));
+            if target.exists() {
+                // Already renamed, by another device or before an interruption
+                fs::remove_file(&path)?;
+            } else {
+                fs::rename(&path, &target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 2 → 3: move event files into their device's shards
+fn shard_event_files(sync_dir: &Path) -> Result<()> {
+    let events = sync_dir.join("events");
+    if !events.exists() {
+        return Ok(());
+    }
+    for device in fs::read_dir(&events)? {
+        let device = device?;
+        if !device.file_type()?.is_dir() {
+            continue;
+        }
+        for entry in fs::read_dir(device.path())? {
+            let entry = entry?;
+            let path = entry.path();
+            let Some(number) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(event_file_number)
+                .filter(|_| entry.file_type().is_ok_and(|t| t.is_file()))
+            else {
+                continue;
+            };
+            let target =
+                event_file_path(&device.path(), number, &entry.file_name().to_string_lossy());
+            if target.exists() {
+                fs::remove_file(&path)?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::rename(&path, &target)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::events::{Event, EventLog};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_new_sync_dir_gets_current_layout() {
+        let dir = tempdir().unwrap();
+        let sync_dir = dir.path().join("sync");
+        assert_eq!(layout_version(&sync_dir).unwrap(), None);
+        assert_eq!(migrate_layout(&sync_dir).unwrap(), None);
+        assert_eq!(layout_version(&sync_dir).unwrap(), Some(LAYOUT_VERSION));
+    }
+
+    #[test]
+    fn test_migrates_flat_unhashed_names() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let mut log = EventLog::new(dir.path().to_path_buf(), "device-a".into(), keypair);
+        let event = Event::HandlerRemoved {
+            protocol: "mailto".to_string(),
+        };
+        let (hashed, _) = log.write_envelopes(vec![event], &[]).unwrap();
+
+        // Named as it was before files carried a hash, and before the
+        // device's directory was sharded
+        let legacy = log.device_events_path("device-a").join("0001.evt");
+        fs::rename(&hashed, &legacy).unwrap();
+        assert_eq!(layout_version(dir.path()).unwrap(), Some(1));
+
+        assert_eq!(migrate_layout(dir.path()).unwrap(), Some(1));
+        assert!(hashed.exists());
+        assert!(!legacy.exists());
+        assert_eq!(layout_version(dir.path()).unwrap(), Some(LAYOUT_VERSION));
+        assert_eq!(log.read_all_events(&[]).unwrap().len(), 1);
+
+        // Already current
+        assert_eq!(migrate_layout(dir.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_migrates_past_corrupt_file() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let mut log = EventLog::new(dir.path().to_path_buf(), "device-a".into(), keypair);
+        let event = Event::HandlerRemoved {
+            protocol: "mailto".to_string(),
+        };
+        let (hashed, _) = log.write_envelopes(vec![event], &[]).unwrap();
+        let device_dir = log.device_events_path("device-a");
+        fs::rename(&hashed, device_dir.join("0001.evt")).unwrap();
+
+        // Cut short partway through syncing
+        fs::write(device_dir.join("0002.evt"), b"WOLF").unwrap();
+
+        assert_eq!(migrate_layout(dir.path()).unwrap(), Some(1));
+        assert_eq!(layout_version(dir.path()).unwrap(), Some(LAYOUT_VERSION));
+        assert!(hashed.exists());
+        assert!(device_dir.join("000/0002.evt").exists());
+    }
+
+    #[test]
+    fn test_refuses_newer_layout() {
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join(LAYOUT_FILE), "99\n").unwrap();
+        let err = migrate_layout(dir.path()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("newer than this wolfpack supports")
+        );
+
+        fs::write(dir.path().join(LAYOUT_FILE), "two").unwrap();
+        assert!(layout_version(dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_running_log_stops_at_newer_layout() {
+        let dir = tempdir().unwrap();
+        let mut log = EventLog::new(
+            dir.path().to_path_buf(),
+            "device-a".into(),
+            KeyPair::generate(),
+        );
+        let event = || {
+            vec![Event::HandlerRemoved {
+                protocol: "mailto".to_string(),
+            }]
+        };
+        migrate_layout(dir.path()).unwrap();
+        log.write_events(event(), &[]).unwrap();
+
+        // Another device of the pack running a newer wolfpack migrated it
+        fs::write(
+            dir.path().join(LAYOUT_FILE),
+            format!("{}\n", LAYOUT_VERSION + 1),
+        )
+        .unwrap();
+        let err = log.read_all_events(&[]).unwrap_err();
+        assert!(err.to_string().contains("update wolfpack"));
+        assert!(log.write_events(event(), &[]).is_err());
+        assert_eq!(log.next_event_number("device-a").unwrap(), 2);
+    }
+}
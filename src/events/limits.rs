@@ -0,0 +1,187 @@
+use anyhow::{Result, bail};
+use std::fs;
+use std::path::Path;
+
+use super::Event;
+use crate::config::LimitsConfig;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * 1024;
+
+/// Check the number of events going into one event file or push
+pub fn check_count(count: usize, limits: &LimitsConfig) -> Result<()> {
+    if limits.max_events_per_file > 0 && count > limits.max_events_per_file {
+        bail!(
+            "{} events at once is over the limit of {}",
+            count,
+            limits.max_events_per_file
+        );
+    }
+    Ok(())
+}
+
+/// Check an event's size, counting the XPI it carries against the XPI limit
+/// and everything else against the event limit
+pub fn check_event(event: &Event, limits: &LimitsConfig) -> Result<()> {
+    let xpi_data = match event {
+        Event::ExtensionInstalled { xpi_data, .. } => xpi_data.len() as u64,
+        _ => 0,
+    };
+    let size = serde_json::to_vec(event)?.len() as u64 - xpi_data;
+    if limits.max_event_kb > 0 && size > limits.max_event_bytes() {
+        bail!(
+            "Event to {} is {} KiB, over the limit of {} KiB",
+            event.describe(),
+            size.div_ceil(KIB),
+            limits.max_event_kb
+        );
+    }
+
+    // XPI data is base64, so it decodes to three quarters of its length
+    let xpi = xpi_data / 4 * 3;
+    if limits.max_xpi_mb > 0 && xpi > limits.max_xpi_bytes() {
+        bail!(
+            "XPI to {} is {} MiB, over the limit of {} MiB",
+            event.describe(),
+            xpi.div_ceil(MIB),
+            limits.max_xpi_mb
+        );
+    }
+    Ok(())
+}
+
+/// Check the size of an encrypted event from another device
+///
+/// Its contents can't be seen, so it may be as large as the largest event
+/// carrying the largest XPI, base64 encoded.
+pub fn check_encrypted_event(size: u64, limits: &LimitsConfig) -> Result<()> {
+    if limits.max_event_kb == 0 || limits.max_xpi_mb == 0 {
+        return Ok(());
+    }
+    let max = limits.max_event_bytes() + limits.max_xpi_bytes() / 3 * 4;
+    if size > max {
+        bail!(
+            "Encrypted event is {} MiB, larger than any event can be",
+            size.div_ceil(MIB)
+        );
+    }
+    Ok(())
+}
+
+/// Check that adding `adding` bytes keeps a device within its storage quota
+pub fn check_quota(device: &str, used: u64, adding: u64, limits: &LimitsConfig) -> Result<()> {
+    if limits.device_quota_mb > 0 && used + adding > limits.device_quota_bytes() {
+        bail!(
+            "Device {} would use {} MiB of the sync dir, over its quota of {} MiB",
+            device,
+            (used + adding).div_ceil(MIB),
+            limits.device_quota_mb
+        );
+    }
+    Ok(())
+}
+
+/// Bytes taken up by the files in a directory and those under it
+pub fn dir_size(path: &Path) -> Result<u64> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let mut size = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_file() {
+            size += metadata.len();
+        } else if metadata.is_dir() {
+            size += dir_size(&entry.path())?;
+        }
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ExtensionSource, PrefValue};
+    use tempfile::tempdir;
+
+    fn limits() -> LimitsConfig {
+        LimitsConfig {
+            max_event_kb: 1,
+            max_xpi_mb: 1,
+            max_events_per_file: 2,
+            device_quota_mb: 1,
+        }
+    }
+
+    fn pref(value: &str) -> Event {
+        Event::PrefSet {
+            key: "browser.startup.homepage".to_string(),
+            value: PrefValue::String(value.to_string()),
+        }
+    }
+
+    fn install(xpi_data: String) -> Event {
+        Event::ExtensionInstalled {
+            id: "ext@example.com".to_string(),
+            name: "Ext".to_string(),
+            version: "1.0".to_string(),
+            source: ExtensionSource::Amo {
+                amo_slug: "ext".to_string(),
+            },
+            xpi_data,
+            chunks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_check_count() {
+        assert!(check_count(2, &limits()).is_ok());
+        assert!(check_count(3, &limits()).is_err());
+
+        let unlimited = LimitsConfig {
+            max_events_per_file: 0,
+            ..limits()
+        };
+        assert!(check_count(100_000, &unlimited).is_ok());
+    }
+
+    #[test]
+    fn test_check_event() {
+        assert!(check_event(&pref("https://example.com"), &limits()).is_ok());
+        let err = check_event(&pref(&"a".repeat(2048)), &limits()).unwrap_err();
+        assert!(err.to_string().contains("over the limit of 1 KiB"));
+
+        // XPI data counts against its own limit, not the event's
+        assert!(check_event(&install("a".repeat(512 * 1024)), &limits()).is_ok());
+        let err = check_event(&install("a".repeat(2 * 1024 * 1024)), &limits()).unwrap_err();
+        assert!(err.to_string().contains("over the limit of 1 MiB"));
+    }
+
+    #[test]
+    fn test_check_encrypted_event() {
+        assert!(check_encrypted_event(1024 * 1024, &limits()).is_ok());
+        assert!(check_encrypted_event(2 * 1024 * 1024, &limits()).is_err());
+    }
+
+    #[test]
+    fn test_check_quota() {
+        assert!(check_quota("device-a", 512 * 1024, 512 * 1024, &limits()).is_ok());
+        let err = check_quota("device-a", 1024 * 1024, 1, &limits()).unwrap_err();
+        assert!(err.to_string().contains("quota of 1 MiB"));
+    }
+
+    #[test]
+    fn test_dir_size() {
+        let dir = tempdir().unwrap();
+        assert_eq!(dir_size(&dir.path().join("missing")).unwrap(), 0);
+
+        fs::write(dir.path().join("0001.evt"), [0u8; 100]).unwrap();
+        fs::write(dir.path().join("0002.evt"), [0u8; 50]).unwrap();
+        assert_eq!(dir_size(dir.path()).unwrap(), 150);
+
+        fs::create_dir(dir.path().join("001")).unwrap();
+        fs::write(dir.path().join("001/1000.evt"), [0u8; 25]).unwrap();
+        assert_eq!(dir_size(dir.path()).unwrap(), 175);
+    }
+}
@@ -1,48 +1,143 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use lru::LruCache;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+use tracing::{instrument, warn};
 
-use super::{EventEnvelope, EventFile, VectorClock};
-use crate::crypto::{KeyPair, PublicKey};
+use super::blobs::{BlobStore, ChunkBlobs};
+use super::layout::check_layout;
+use super::{Event, EventCodec, EventEnvelope, EventFile, VectorClock, limits};
+use crate::config::LimitsConfig;
+use crate::crypto::{GroupKeys, KeyEpoch, KeyPair, PublicKey};
+use crate::extensions::{decode_base64, decompress_xpi, encode_base64};
+
+/// How many event files' decrypted events are kept between reads
+const CACHED_FILES: usize = 4096;
+
+/// Event files in each shard of a device's directory, keeping directories
+/// small enough for Syncthing and `read_dir` to get through quickly
+const SHARD_SIZE: u32 = 1000;
+
+/// In the names of copies Syncthing makes when two devices change a file at
+/// once
+const SYNC_CONFLICT: &str = ".sync-conflict-";
+
+/// An event file's events as decrypted, and what the file looked like then
+struct CachedFile {
+    modified: SystemTime,
+    len: u64,
+    keys: GroupKeys,
+    events: Arc<Vec<EventEnvelope>>,
+}
+
+/// A page of event files to send a peer
+#[derive(Default)]
+pub struct FilePage {
+    /// Each file with the device that wrote it, its number, and the chunks
+    /// of the XPIs its events refer to, for peers that only sync over P2P
+    pub files: Vec<(String, u32, EventFile, ChunkBlobs)>,
+    /// The number and device of the last file, when more follow it
+    pub next: Option<(u32, String)>,
+}
+
+/// What a device holds of each device's event files, sent to a peer to ask
+/// for the rest
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HeldFiles {
+    /// The highest file number held from each device, all that peers from
+    /// before files were exchanged by name send
+    #[serde(rename = "clock")]
+    pub highest: HashMap<String, u64>,
+    /// Names of the files held from each device, so files written under a
+    /// number below the highest, as by a device restored from a backup,
+    /// are still sent
+    #[serde(default, rename = "files", skip_serializing_if = "HashMap::is_empty")]
+    pub names: HashMap<String, BTreeSet<String>>,
+}
+
+impl HeldFiles {
+    /// Whether this holds a device's file, by name when the files held from
+    /// the device were listed and otherwise by number
+    pub fn holds(&self, device: &str, number: u32, name: &str) -> bool {
+        match self.names.get(device) {
+            Some(names) => names.contains(name),
+            None => u64::from(number) <= self.highest.get(device).copied().unwrap_or(0),
+        }
+    }
+}
 
 pub struct EventLog {
     base_path: PathBuf,
     device_id: String,
     keypair: KeyPair,
     clock: VectorClock,
+    limits: LimitsConfig,
+    /// How events are encoded in the files this device writes
+    codec: EventCodec,
+    /// How the group secret for the files this device writes is derived
+    key_epoch: KeyEpoch,
+    blobs: BlobStore,
+    /// Decrypted event files by path, so sync cycles only decrypt files
+    /// that are new or changed
+    cache: Mutex<LruCache<PathBuf, CachedFile>>,
 }
 
 impl EventLog {
     pub fn new(base_path: PathBuf, device_id: String, keypair: KeyPair) -> Self {
         Self {
+            blobs: BlobStore::new(base_path.join("blobs")),
             base_path,
             device_id,
             keypair,
             clock: VectorClock::new(),
+            limits: LimitsConfig::default(),
+            codec: EventCodec::default(),
+            key_epoch: KeyEpoch::default(),
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(CACHED_FILES).unwrap_or(NonZeroUsize::MIN),
+            )),
         }
     }
 
+    /// This device's keypair
+    pub fn keypair(&self) -> &KeyPair {
+        &self.keypair
+    }
+
+    pub fn set_limits(&mut self, limits: LimitsConfig) {
+        self.limits = limits;
+    }
+
+    pub fn set_codec(&mut self, codec: EventCodec) {
+        self.codec = codec;
+    }
+
+    pub fn set_key_epoch(&mut self, key_epoch: KeyEpoch) {
+        self.key_epoch = key_epoch;
+    }
+
+    /// Bytes a device's event files, and the XPI chunks it added, take up
+    /// in the sync dir
+    pub fn device_usage(&self, device: &str) -> Result<u64> {
+        Ok(limits::dir_size(&self.device_events_path(device))? + self.blobs.device_size(device)?)
+    }
+
     pub fn device_events_path(&self, device: &str) -> PathBuf {
         self.base_path.join("events").join(device)
     }
 
     pub fn next_event_number(&self, device: &str) -> Result<u32> {
-        let path = self.device_events_path(device);
-        if !path.exists() {
-            return Ok(1);
-        }
-
-        let mut max = 0u32;
-        for entry in fs::read_dir(&path)? {
-            let entry = entry?;
-            let name = entry.file_name();
-            let name = name.to_string_lossy();
-            if let Some(num_str) = name.strip_suffix(".evt")
-                && let Ok(num) = num_str.parse::<u32>()
-            {
-                max = max.max(num);
-            }
-        }
+        let max = device_files(&self.device_events_path(device))?
+            .iter()
+            .filter_map(|path| event_file_number(file_name(path)))
+            .max()
+            .unwrap_or(0);
         Ok(max + 1)
     }
 
@@ -51,86 +146,550 @@ impl EventLog {
         events: Vec<super::types::Event>,
         known_devices: &[(String, PublicKey)],
     ) -> Result<PathBuf> {
+        self.write_envelopes(events, known_devices)
+            .map(|(path, _)| path)
+    }
+
+    /// Write events, returning the envelopes they were wrapped in
+    ///
+    /// XPIs go to the blob store, leaving only their chunk IDs in the event
+    /// file; the returned envelopes still carry them. Fails without writing
+    /// anything if the events are over the configured limits or the file and
+    /// the chunks it adds would take this device over its storage quota.
+    #[instrument(skip_all, fields(count = events.len()))]
+    pub fn write_envelopes(
+        &mut self,
+        events: Vec<super::types::Event>,
+        known_devices: &[(String, PublicKey)],
+    ) -> Result<(PathBuf, Vec<EventEnvelope>)> {
         if events.is_empty() {
             anyhow::bail!("Cannot write empty event list");
         }
+        check_layout(&self.base_path)?;
+        limits::check_count(events.len(), &self.limits)?;
+        for event in &events {
+            limits::check_event(event, &self.limits)?;
+        }
 
-        self.clock.increment(&self.device_id);
+        let mut clock = self.clock.clone();
+        clock.increment(&self.device_id);
 
         let envelopes: Vec<EventEnvelope> = events
             .into_iter()
-            .map(|event| EventEnvelope::new(self.device_id.clone(), self.clock.clone(), event))
+            .map(|event| EventEnvelope::new(self.device_id.clone(), clock.clone(), event))
             .collect();
 
-        let shared_secret = self.derive_group_secret(known_devices);
-        let counter = self.clock.get(&self.device_id);
+        let shared_secret = *self.group_keys(known_devices).get(self.key_epoch);
+        let counter = clock.get(&self.device_id);
+        let (stored, chunks) = self.prepare_xpis(&envelopes, &shared_secret)?;
         let event_file = EventFile::new(
             self.keypair.public_key(),
             &self.device_id,
             counter,
             &shared_secret,
-            &envelopes,
+            self.key_epoch,
+            &stored,
+            self.codec,
+        )?;
+
+        let used = self.device_usage(&self.device_id)?;
+        limits::check_quota(
+            &self.device_id,
+            used,
+            event_file.encoded_len() + chunk_bytes(&chunks),
+            &self.limits,
         )?;
 
         let event_num = self.next_event_number(&self.device_id)?;
-        let path = self
-            .device_events_path(&self.device_id)
-            .join(format!("{:04}.evt", event_num));
+        let path = event_file_path(
+            &self.device_events_path(&self.device_id),
+            event_num,
+            &event_file_name(event_num, &event_file),
+        );
 
-        event_file.save(&path)?;
-        Ok(path)
+        self.save_with_chunks(&self.device_id, &chunks, || event_file.save(&path))?;
+        self.clock = clock;
+        Ok((path, envelopes))
     }
 
+    #[instrument(skip(self, known_devices))]
     pub fn read_device_events(
         &self,
         device: &str,
         known_devices: &[(String, PublicKey)],
     ) -> Result<Vec<EventEnvelope>> {
-        let path = self.device_events_path(device);
-        if !path.exists() {
-            return Ok(Vec::new());
+        check_layout(&self.base_path)?;
+        let keys = self.group_keys(known_devices);
+        let events = self.decrypt_device_events(device, &keys)?;
+        self.load_xpis(events, &keys)
+    }
+
+    /// Every device's events as stored, with XPIs left in the blob store as
+    /// chunk IDs, which is much quicker to read when they aren't needed
+    #[instrument(skip_all)]
+    pub fn read_stored_events(
+        &self,
+        known_devices: &[(String, PublicKey)],
+    ) -> Result<Vec<EventEnvelope>> {
+        let keys = self.group_keys(known_devices);
+        self.read_devices(|device| self.decrypt_device_events(device, &keys))
+    }
+
+    /// Event files each device has in the sync dir, and the bytes they take up
+    pub fn device_files(&self) -> Result<BTreeMap<String, (usize, u64)>> {
+        let mut devices = BTreeMap::new();
+        for (device, _, _) in self.event_files()? {
+            devices.entry(device).or_insert((0, 0)).0 += 1;
+        }
+        for (device, (_, bytes)) in &mut devices {
+            *bytes = self.device_usage(device)?;
         }
+        Ok(devices)
+    }
 
-        let shared_secret = self.derive_group_secret(known_devices);
-        let mut all_events = Vec::new();
+    /// Bytes the given XPI chunks take up in the blob store
+    pub fn blob_usage(&self, chunks: &[String]) -> u64 {
+        self.blobs.size_of(chunks)
+    }
 
-        let mut entries: Vec<_> = fs::read_dir(&path)?.filter_map(|e| e.ok()).collect();
-        entries.sort_by_key(|e| e.file_name());
+    /// Bytes the whole blob store takes up
+    pub fn blob_store_usage(&self) -> Result<u64> {
+        self.blobs.size()
+    }
 
-        for entry in entries {
-            if entry.path().extension().is_some_and(|ext| ext == "evt") {
-                let event_file = EventFile::load(&entry.path())
-                    .with_context(|| format!("Failed to load {}", entry.path().display()))?;
-                let events = event_file.decrypt(&shared_secret)?;
-                all_events.extend(events);
+    /// A device's events as stored, decrypted but with XPIs left out
+    fn decrypt_device_events(&self, device: &str, keys: &GroupKeys) -> Result<Vec<EventEnvelope>> {
+        let mut files = device_files(&self.device_events_path(device))?;
+        if files
+            .iter()
+            .any(|path| file_name(path).contains(SYNC_CONFLICT))
+        {
+            self.resolve_sync_conflicts(device, &files, keys)?;
+            return self.decrypt_device_events(device, keys);
+        }
+        files.retain(|path| event_file_number(file_name(path)).is_some());
+        files.sort_by_cached_key(|path| {
+            (
+                event_file_number(file_name(path)),
+                file_name(path).to_string(),
+            )
+        });
+
+        // Files decrypt independently, and collecting keeps them in order
+        let events: Vec<Vec<EventEnvelope>> = files
+            .par_iter()
+            .map(|path| self.decrypt_file(path, keys))
+            .collect::<Result<_>>()?;
+        let mut files: Vec<(PathBuf, Vec<EventEnvelope>)> = files.into_iter().zip(events).collect();
+        if files.windows(2).any(|pair| {
+            event_file_number(file_name(&pair[0].0)) == event_file_number(file_name(&pair[1].0))
+        }) {
+            self.resolve_reused_numbers(device, &mut files)?;
+        }
+        Ok(files.into_iter().flat_map(|(_, events)| events).collect())
+    }
+
+    /// Sort out a device's files that share a number, as ones written by a
+    /// device restored from a backup do
+    ///
+    /// The first by name is read. Each after it is too if it has events the
+    /// ones before it don't, and is otherwise a duplicate and moved to
+    /// `quarantine/` in the sync dir, so every device of the pack keeps the
+    /// same files.
+    fn resolve_reused_numbers(
+        &self,
+        device: &str,
+        files: &mut Vec<(PathBuf, Vec<EventEnvelope>)>,
+    ) -> Result<()> {
+        let mut number = None;
+        let mut seen = HashSet::new();
+        let mut kept = Vec::with_capacity(files.len());
+        for (path, events) in files.drain(..) {
+            let this = event_file_number(file_name(&path));
+            if this != number {
+                number = this;
+                seen.clear();
+            } else if events.iter().all(|envelope| seen.contains(&envelope.id)) {
+                warn!(file = %path.display(), "Quarantining a duplicate of an event file");
+                self.quarantine(device, &path)?;
+                continue;
             }
+            seen.extend(events.iter().map(|envelope| envelope.id));
+            kept.push((path, events));
         }
+        *files = kept;
+        Ok(())
+    }
 
-        Ok(all_events)
+    /// An event file's events, decrypted again only when the file's
+    /// modification time or size has changed since it was last read
+    fn decrypt_file(&self, path: &Path, keys: &GroupKeys) -> Result<Vec<EventEnvelope>> {
+        let metadata =
+            fs::metadata(path).with_context(|| format!("Failed to load {}", path.display()))?;
+        let (modified, len) = (metadata.modified()?, metadata.len());
+
+        let cached = self.cache.lock().ok().and_then(|mut cache| {
+            cache
+                .get(path)
+                .filter(|file| file.modified == modified && file.len == len && file.keys == *keys)
+                .map(|file| Arc::clone(&file.events))
+        });
+        if let Some(events) = cached {
+            return Ok(events.as_ref().clone());
+        }
+
+        let events = EventFile::load(path)
+            .with_context(|| format!("Failed to load {}", path.display()))?
+            .decrypt_with(keys)?;
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.put(
+                path.to_path_buf(),
+                CachedFile {
+                    modified,
+                    len,
+                    keys: *keys,
+                    events: Arc::new(events.clone()),
+                },
+            );
+        }
+        Ok(events)
     }
 
+    /// Every device's events, oldest first
+    ///
+    /// Devices are read in parallel, as are the event files of each.
+    #[instrument(skip_all)]
     pub fn read_all_events(
         &self,
         known_devices: &[(String, PublicKey)],
     ) -> Result<Vec<EventEnvelope>> {
+        let keys = self.group_keys(known_devices);
+        self.read_devices(|device| {
+            let events = self.decrypt_device_events(device, &keys)?;
+            self.load_xpis(events, &keys)
+        })
+    }
+
+    /// Read each device with event files in parallel, merging their events
+    /// by timestamp
+    fn read_devices(
+        &self,
+        read: impl Fn(&str) -> Result<Vec<EventEnvelope>> + Sync,
+    ) -> Result<Vec<EventEnvelope>> {
+        check_layout(&self.base_path)?;
+        let mut devices: Vec<String> = self
+            .event_files()?
+            .into_iter()
+            .map(|(device, _, _)| device)
+            .collect();
+        devices.sort();
+        devices.dedup();
+        let events: Vec<Vec<EventEnvelope>> = devices
+            .par_iter()
+            .map(|device| read(device))
+            .collect::<Result<_>>()?;
+
+        let mut all_events: Vec<EventEnvelope> = events.into_iter().flatten().collect();
+        all_events.sort_by_key(|envelope| envelope.timestamp);
+        Ok(all_events)
+    }
+
+    /// The event files held from each device, including ones quarantined so
+    /// a peer doesn't send them again
+    pub fn held_files(&self) -> Result<HeldFiles> {
+        let mut held = HeldFiles::default();
+        for (device, number, path) in self.event_files()? {
+            let highest = held.highest.entry(device.clone()).or_insert(0);
+            *highest = (*highest).max(u64::from(number));
+            held.names
+                .entry(device)
+                .or_default()
+                .insert(file_name(&path).to_string());
+        }
+        let quarantine = self.base_path.join("quarantine");
+        if quarantine.exists() {
+            for entry in fs::read_dir(&quarantine)? {
+                let entry = entry?;
+                if !entry.file_type()?.is_dir() {
+                    continue;
+                }
+                let device = entry.file_name().to_string_lossy().to_string();
+                for file in fs::read_dir(entry.path())? {
+                    let name = file?.file_name().to_string_lossy().to_string();
+                    if event_file_number(&name).is_some() {
+                        held.names.entry(device.clone()).or_default().insert(name);
+                    }
+                }
+            }
+        }
+        Ok(held)
+    }
+
+    /// Event files a peer doesn't hold, oldest first, with the XPI chunks
+    /// they refer to
+    ///
+    /// The page starts after the file numbered and from the device in
+    /// `after`, and stops at `max` files or once they and their chunks take
+    /// up `max_bytes`, though it always takes one.
+    #[allow(clippy::too_many_arguments)] // Where the page starts and how much it takes
+    #[instrument(skip_all)]
+    pub fn files_since(
+        &self,
+        held: &HeldFiles,
+        after: Option<(u32, &str)>,
+        max: usize,
+        max_bytes: u64,
+        known_devices: &[(String, PublicKey)],
+    ) -> Result<FilePage> {
+        let mut missing: Vec<(String, u32, PathBuf)> = self
+            .event_files()?
+            .into_iter()
+            .filter(|(device, number, path)| {
+                !held.holds(device, *number, file_name(path))
+                    && after.is_none_or(|after| (*number, device.as_str()) > after)
+            })
+            .collect();
+        missing.sort_by(|a, b| (a.1, &a.0, &a.2).cmp(&(b.1, &b.0, &b.2)));
+
+        let keys = self.group_keys(known_devices);
+        let mut page = FilePage::default();
+        let mut bytes = 0;
+        let total = missing.len();
+        for (device, number, path) in missing {
+            if page.files.len() == max {
+                break;
+            }
+            let file = EventFile::load(&path)?;
+            let chunks = self.file_chunks(&file, &keys);
+            let size = fs::metadata(&path)?.len() + chunk_bytes(&chunks);
+            if !page.files.is_empty() && bytes + size > max_bytes {
+                break;
+            }
+            bytes += size;
+            page.files.push((device, number, file, chunks));
+        }
+        if page.files.len() < total
+            && let Some((device, number, _, _)) = page.files.last()
+        {
+            page.next = Some((*number, device.clone()));
+        }
+        Ok(page)
+    }
+
+    /// Store another device's event file received from a peer, and the XPI
+    /// chunks sent with it, returning whether either was new
+    ///
+    /// The file must decrypt to that device's events, so a peer can't slip
+    /// in files that would break reading the sync dir. Only chunks its
+    /// events refer to are kept, once they're checked against their IDs, and
+    /// they count towards the device's quota along with the file.
+    #[allow(clippy::too_many_arguments)] // The file, where it goes and its chunks
+    #[instrument(skip(self, file, chunks, known_devices))]
+    pub fn store_remote_file(
+        &self,
+        device: &str,
+        number: u32,
+        file: &EventFile,
+        chunks: &[(String, Vec<u8>)],
+        known_devices: &[(String, PublicKey)],
+    ) -> Result<bool> {
+        if device == self.device_id {
+            bail!("Refusing to overwrite this device's own events");
+        }
+        if !is_safe_device_id(device) {
+            bail!("Invalid device ID: {:?}", device);
+        }
+        check_layout(&self.base_path)?;
+        let held = self.holds_file(device, number, file)?;
+        if held && chunks.is_empty() {
+            return Ok(false);
+        }
+
+        let keys = self.group_keys(known_devices);
+        let envelopes = file
+            .decrypt_with(&keys)
+            .with_context(|| format!("Event file {} from {} doesn't decrypt", number, device))?;
+        if let Some(envelope) = envelopes.iter().find(|e| e.device != device) {
+            bail!(
+                "Event file {} from {} holds events from {}",
+                number,
+                device,
+                envelope.device
+            );
+        }
+
+        let referenced = chunk_ids(&envelopes);
+        let new: ChunkBlobs = chunks
+            .iter()
+            .filter(|(id, blob)| {
+                referenced.contains(id.as_str())
+                    && !self.blobs.contains_all(std::slice::from_ref(id))
+                    && BlobStore::verify(id, blob, &keys.all())
+            })
+            .cloned()
+            .collect();
+        let adding = if held { 0 } else { file.encoded_len() };
+        let used = self.device_usage(device)?;
+        limits::check_quota(device, used, adding + chunk_bytes(&new), &self.limits)?;
+
+        let path = event_file_path(
+            &self.device_events_path(device),
+            number,
+            &event_file_name(number, file),
+        );
+        self.save_with_chunks(
+            device,
+            &new,
+            || if held { Ok(()) } else { file.save(&path) },
+        )?;
+        Ok(!held || !new.is_empty())
+    }
+
+    /// Write the chunks an event file adds, then save it, removing the
+    /// chunks again if it fails so none are left behind that nothing refers
+    /// to, and record that the device added them
+    fn save_with_chunks(
+        &self,
+        device: &str,
+        chunks: &[(String, Vec<u8>)],
+        save: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let mut written = Vec::new();
+        let saved = chunks
+            .iter()
+            .try_for_each(|(id, blob)| {
+                self.blobs.insert(id, blob)?;
+                written.push(id.clone());
+                Ok(())
+            })
+            .and_then(|()| save());
+        if let Err(e) = saved {
+            for id in &written {
+                self.blobs.remove(id);
+            }
+            return Err(e);
+        }
+        self.blobs.record_owner(device, &written)
+    }
+
+    /// Whether a device's directory has this file already, under its name or
+    /// the name files had before they carried a hash, and in its shard or
+    /// where files were before the directory was sharded, or it was
+    /// quarantined
+    fn holds_file(&self, device: &str, number: u32, file: &EventFile) -> Result<bool> {
+        let dir = self.device_events_path(device);
+        let hashed = event_file_name(number, file);
+        if [
+            event_file_path(&dir, number, &hashed),
+            dir.join(&hashed),
+            self.quarantine_path(device).join(&hashed),
+        ]
+        .iter()
+        .any(|path| path.exists())
+        {
+            return Ok(true);
+        }
+        let unhashed = format!("{:04}.evt", number);
+        for path in [
+            event_file_path(&dir, number, &unhashed),
+            dir.join(&unhashed),
+        ] {
+            if path.exists() && EventFile::load(&path)?.short_hash() == file.short_hash() {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Sort out the copies Syncthing made of a device's event files
+    ///
+    /// These come from a device reusing numbers, as one restored from a
+    /// backup did before files were named with a hash. A copy with events
+    /// the pack doesn't already have is given a name of its own, so they're
+    /// read. Copies of files that are already here, and ones that don't
+    /// decrypt, are moved to `quarantine/` in the sync dir.
+    #[allow(clippy::cognitive_complexity)] // Loop with logging
+    fn resolve_sync_conflicts(
+        &self,
+        device: &str,
+        files: &[PathBuf],
+        keys: &GroupKeys,
+    ) -> Result<()> {
+        for path in files
+            .iter()
+            .filter(|path| file_name(path).contains(SYNC_CONFLICT))
+        {
+            if let Some(renamed) = self.conflict_copy_name(device, path, keys)? {
+                warn!(file = %path.display(), "Keeping a conflicting copy of an event file");
+                fs::rename(path, &renamed)?;
+            } else {
+                warn!(file = %path.display(), "Quarantining a copy of an event file");
+                self.quarantine(device, path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Where a device's event files that aren't read are moved to
+    fn quarantine_path(&self, device: &str) -> PathBuf {
+        self.base_path.join("quarantine").join(device)
+    }
+
+    /// Move one of a device's event files aside, so it's no longer read
+    fn quarantine(&self, device: &str, path: &Path) -> Result<()> {
+        let quarantine = self.quarantine_path(device);
+        fs::create_dir_all(&quarantine)?;
+        fs::rename(path, quarantine.join(file_name(path)))?;
+        Ok(())
+    }
+
+    /// The name a conflict copy should have, if it holds events that aren't
+    /// in the device's other files
+    fn conflict_copy_name(
+        &self,
+        device: &str,
+        path: &Path,
+        keys: &GroupKeys,
+    ) -> Result<Option<PathBuf>> {
+        let Some(number) = file_name(path)
+            .split_once(SYNC_CONFLICT)
+            .and_then(|(original, _)| event_file_number(&format!("{}.evt", original)))
+        else {
+            return Ok(None);
+        };
+        let Some(file) = EventFile::load(path)
+            .ok()
+            .filter(|file| file.decrypt_with(keys).is_ok())
+        else {
+            return Ok(None);
+        };
+        if self.holds_file(device, number, &file)? {
+            return Ok(None);
+        }
+        Ok(Some(path.with_file_name(event_file_name(number, &file))))
+    }
+
+    /// Every event file in the sync dir, by device and number
+    fn event_files(&self) -> Result<Vec<(String, u32, PathBuf)>> {
         let events_path = self.base_path.join("events");
         if !events_path.exists() {
             return Ok(Vec::new());
         }
 
-        let mut all_events = Vec::new();
-
+        let mut files = Vec::new();
         for entry in fs::read_dir(&events_path)? {
             let entry = entry?;
-            if entry.file_type()?.is_dir() {
-                let device = entry.file_name().to_string_lossy().to_string();
-                let device_events = self.read_device_events(&device, known_devices)?;
-                all_events.extend(device_events);
+            if !entry.file_type()?.is_dir() {
+                continue;
+            }
+            let device = entry.file_name().to_string_lossy().to_string();
+            for path in device_files(&entry.path())? {
+                if let Some(number) = event_file_number(file_name(&path)) {
+                    files.push((device.clone(), number, path));
+                }
             }
         }
-
-        all_events.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
-        Ok(all_events)
+        Ok(files)
     }
 
     pub fn clock(&self) -> &VectorClock {
@@ -141,21 +700,230 @@ impl EventLog {
         self.clock = clock;
     }
 
-    fn derive_group_secret(&self, known_devices: &[(String, PublicKey)]) -> [u8; 32] {
-        if known_devices.is_empty() {
-            return self
-                .keypair
-                .derive_shared_secret(&self.keypair.public_key());
+    /// Copies of the envelopes with their XPIs split into chunks, leaving
+    /// only the chunk IDs, and the chunks the blob store doesn't have yet
+    fn prepare_xpis(
+        &self,
+        envelopes: &[EventEnvelope],
+        secret: &[u8; 32],
+    ) -> Result<(Vec<EventEnvelope>, ChunkBlobs)> {
+        let mut stored = envelopes.to_vec();
+        let mut new = ChunkBlobs::new();
+        for envelope in &mut stored {
+            if let Event::ExtensionInstalled {
+                xpi_data, chunks, ..
+            } = &mut envelope.event
+                && !xpi_data.is_empty()
+            {
+                let xpi = decompress_xpi(&decode_base64(xpi_data)?)?;
+                let (ids, blobs) = self.blobs.prepare(&xpi, secret)?;
+                for (id, blob) in blobs {
+                    if !new.iter().any(|(new, _)| *new == id) {
+                        new.push((id, blob));
+                    }
+                }
+                *chunks = ids;
+                xpi_data.clear();
+            }
         }
+        Ok((stored, new))
+    }
+
+    /// The stored chunks of the XPIs an event file's events refer to, to
+    /// send a peer along with it
+    fn file_chunks(&self, file: &EventFile, keys: &GroupKeys) -> ChunkBlobs {
+        let Ok(envelopes) = file.decrypt_with(keys) else {
+            return ChunkBlobs::new();
+        };
+        chunk_ids(&envelopes)
+            .into_iter()
+            .filter_map(|id| Some((id.to_string(), self.blobs.read(id)?)))
+            .collect()
+    }
 
-        let mut combined = [0u8; 32];
-        for (_device_id, public_key) in known_devices {
-            let shared = self.keypair.derive_shared_secret(public_key);
-            for (i, byte) in shared.iter().enumerate() {
-                combined[i] ^= byte;
+    /// Fill XPIs back in from the blob store
+    ///
+    /// An event whose chunks haven't all synced yet is left out until they
+    /// have, so it's read again, complete, on a later pass. Chunks are
+    /// stored under the key epoch of the file that added them, which isn't
+    /// known here, so each epoch's secret is tried, newest first.
+    fn load_xpis(
+        &self,
+        envelopes: Vec<EventEnvelope>,
+        keys: &GroupKeys,
+    ) -> Result<Vec<EventEnvelope>> {
+        let mut loaded = Vec::with_capacity(envelopes.len());
+        for mut envelope in envelopes {
+            if let Event::ExtensionInstalled {
+                id,
+                xpi_data,
+                chunks,
+                ..
+            } = &mut envelope.event
+                && !chunks.is_empty()
+            {
+                if !self.blobs.contains_all(chunks) {
+                    warn!("Waiting for the chunks of extension {} to sync", id);
+                    continue;
+                }
+                let [newest, oldest] = keys.all();
+                let xpi = self
+                    .blobs
+                    .load(chunks, newest)
+                    .or_else(|_| self.blobs.load(chunks, oldest))?;
+                // A fast level, since this runs each time the log is read
+                let compressed = zstd::encode_all(xpi.as_slice(), 0)?;
+                *xpi_data = encode_base64(&compressed);
+                chunks.clear();
             }
+            loaded.push(envelope);
         }
-        combined
+        Ok(loaded)
+    }
+
+    /// Key the pack seals its devices' network announcements with
+    pub fn announcement_key(&self, known_devices: &[(String, PublicKey)]) -> [u8; 32] {
+        self.group_keys(known_devices).announcement_key()
+    }
+
+    fn group_keys(&self, known_devices: &[(String, PublicKey)]) -> GroupKeys {
+        GroupKeys::derive(&self.keypair, known_devices)
+    }
+}
+
+/// Where an event file goes in its device's directory: in the shard for its
+/// number, such as `001/` for files 1000 to 1999
+pub(super) fn event_file_path(device_dir: &Path, number: u32, name: &str) -> PathBuf {
+    device_dir
+        .join(format!("{:03}", number / SHARD_SIZE))
+        .join(name)
+}
+
+/// Whether a directory in a device's directory is one of its shards
+pub(super) fn is_shard_name(name: &str) -> bool {
+    name.len() >= 3 && name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// IDs of the XPI chunks events refer to
+fn chunk_ids(envelopes: &[EventEnvelope]) -> BTreeSet<&str> {
+    envelopes
+        .iter()
+        .flat_map(|envelope| match &envelope.event {
+            Event::ExtensionInstalled { chunks, .. } => chunks.as_slice(),
+            _ => &[],
+        })
+        .map(String::as_str)
+        .collect()
+}
+
+/// Bytes chunks take up as stored
+fn chunk_bytes(chunks: &[(String, Vec<u8>)]) -> u64 {
+    chunks.iter().map(|(_, blob)| blob.len() as u64).sum()
+}
+
+/// Every file in a device's directory and its shards, the ones directly in
+/// it being from before the directory was sharded
+pub(super) fn device_files(device_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    if !device_dir.exists() {
+        return Ok(files);
+    }
+    for entry in fs::read_dir(device_dir)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_file() {
+            files.push(entry.path());
+        } else if file_type.is_dir() && is_shard_name(&entry.file_name().to_string_lossy()) {
+            for file in fs::read_dir(entry.path())? {
+                let file = file?;
+                if file.file_type()?.is_file() {
+                    files.push(file.path());
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
+
+/// Name of an event file: its number, and a hash of its contents so that
+/// files written under the same number don't collide
+pub(super) fn event_file_name(number: u32, file: &EventFile) -> String {
+    format!("{:04}-{}.evt", number, file.short_hash())
+}
+
+/// The number of an event file, from a name like `0007-1a2b3c4d.evt`, or
+/// `0007.evt` as files were named before they carried a hash
+pub(super) fn event_file_number(name: &str) -> Option<u32> {
+    let stem = name.strip_suffix(".evt")?;
+    let number = match stem.split_once('-') {
+        Some((number, hash)) if hash.len() == 8 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            number
+        }
+        Some(_) => return None,
+        None => stem,
+    };
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+/// Whether a device ID is safe to use as a directory name in the sync dir
+pub(super) fn is_safe_device_id(device: &str) -> bool {
+    !device.is_empty()
+        && !device.starts_with('.')
+        && device
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// An event log shared between the sync engine and the daemon's network
+/// handling, locked on its own
+///
+/// Answering a peer's clock request only needs the log, so it doesn't wait
+/// behind a profile scan holding the engine. Clones share the log.
+#[derive(Clone)]
+pub struct SharedEventLog {
+    log: Arc<Mutex<EventLog>>,
+}
+
+impl SharedEventLog {
+    pub fn new(log: EventLog) -> Self {
+        Self {
+            log: Arc::new(Mutex::new(log)),
+        }
+    }
+
+    /// Lock the log; hold the guard only as long as one operation needs it
+    ///
+    /// This blocks while the engine is writing, so async code reads through
+    /// [`Self::read`] instead.
+    pub fn lock(&self) -> MutexGuard<'_, EventLog> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Read the log on the blocking pool, so waiting for the lock doesn't
+    /// hold up an async worker
+    pub async fn read<T, F>(&self, read: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&EventLog) -> T + Send + 'static,
+    {
+        let log = self.clone();
+        tokio::task::spawn_blocking(move || read(&log.lock()))
+            .await
+            .context("Event log read panicked")
+    }
+
+    /// The current vector clock
+    pub async fn clock(&self) -> Result<HashMap<String, u64>> {
+        self.read(|log| log.clock().to_hashmap()).await
     }
 }
 
@@ -163,8 +931,29 @@ impl EventLog {
 mod tests {
     use super::*;
     use crate::events::Event;
+    use crate::events::chunking::noise;
     use tempfile::tempdir;
 
+    #[tokio::test]
+    async fn test_shared_event_log() {
+        let dir = tempdir().unwrap();
+        let log = SharedEventLog::new(EventLog::new(
+            dir.path().to_path_buf(),
+            "device-a".to_string(),
+            KeyPair::generate(),
+        ));
+        let engine_side = log.clone();
+        let event = Event::ReadingListRemoved {
+            url: "https://example.com".to_string(),
+        };
+        engine_side.lock().write_events(vec![event], &[]).unwrap();
+
+        // Seen through every clone
+        assert_eq!(log.clock().await.unwrap().get("device-a"), Some(&1));
+        let held = log.read(EventLog::held_files).await.unwrap();
+        assert_eq!(held.unwrap().highest["device-a"], 1);
+    }
+
     #[test]
     fn test_event_log_write_read() {
         let dir = tempdir().unwrap();
@@ -187,6 +976,491 @@ mod tests {
         assert!(matches!(read_events[0].event, Event::ExtensionAdded { .. }));
     }
 
+    #[test]
+    fn test_reads_files_from_earlier_key_epoch() {
+        let dir = tempdir().unwrap();
+        let mut log = EventLog::new(
+            dir.path().to_path_buf(),
+            "device-a".to_string(),
+            KeyPair::generate(),
+        );
+        let event = |n: i64| Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: crate::events::PrefValue::Int(n),
+        };
+
+        // Written before the pack moved to HKDF, then after
+        log.set_key_epoch(KeyEpoch::Xor);
+        let (legacy, _) = log.write_envelopes(vec![event(1)], &[]).unwrap();
+        log.set_key_epoch(KeyEpoch::Hkdf);
+        let (current, _) = log.write_envelopes(vec![event(2)], &[]).unwrap();
+        assert_eq!(EventFile::load(&legacy).unwrap().key_epoch, KeyEpoch::Xor);
+        assert_eq!(EventFile::load(&current).unwrap().key_epoch, KeyEpoch::Hkdf);
+
+        let events = log.read_all_events(&[]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].event, event(2));
+    }
+
+    #[test]
+    fn test_read_all_events_in_order() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let mut logs: Vec<EventLog> = ["device-a", "device-b", "device-c"]
+            .into_iter()
+            .map(|device| {
+                EventLog::new(
+                    dir.path().to_path_buf(),
+                    device.to_string(),
+                    keypair.clone(),
+                )
+            })
+            .collect();
+
+        for n in 0..12 {
+            let log = &mut logs[n % 3];
+            let event = Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: crate::events::PrefValue::Int(n as i64),
+            };
+            log.write_events(vec![event], &[]).unwrap();
+        }
+
+        let events = logs[0].read_all_events(&[]).unwrap();
+        assert_eq!(events.len(), 12);
+        assert!(events.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        let from_b: Vec<i64> = events
+            .iter()
+            .filter(|e| e.device == "device-b")
+            .filter_map(|e| match &e.event {
+                Event::PrefSet {
+                    value: crate::events::PrefValue::Int(n),
+                    ..
+                } => Some(*n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(from_b, vec![1, 4, 7, 10]);
+        assert_eq!(logs[1].read_stored_events(&[]).unwrap().len(), 12);
+    }
+
+    #[test]
+    fn test_cached_until_file_changes() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let mut log = EventLog::new(dir.path().to_path_buf(), "device-a".to_string(), keypair);
+        let pref = |n| Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: crate::events::PrefValue::Int(n),
+        };
+        let first = log.write_events(vec![pref(1)], &[]).unwrap();
+        let second = log.write_events(vec![pref(2)], &[]).unwrap();
+        assert_eq!(log.read_all_events(&[]).unwrap().len(), 2);
+
+        // Unchanged files aren't decrypted again
+        let modified = fs::metadata(&first).unwrap().modified().unwrap();
+        let contents = fs::read(&first).unwrap();
+        fs::write(&first, vec![0u8; contents.len()]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&first)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+        assert_eq!(log.read_all_events(&[]).unwrap().len(), 2);
+
+        // Changed ones are
+        fs::write(&first, fs::read(&second).unwrap()).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&first)
+            .unwrap()
+            .set_modified(modified + std::time::Duration::from_secs(1))
+            .unwrap();
+        let events = log.read_all_events(&[]).unwrap();
+        assert!(events.iter().all(|e| matches!(
+            e.event,
+            Event::PrefSet {
+                value: crate::events::PrefValue::Int(2),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn test_event_file_number() {
+        assert_eq!(event_file_number("0007-1a2b3c4d.evt"), Some(7));
+        assert_eq!(event_file_number("0007.evt"), Some(7));
+        assert_eq!(event_file_number("12345-00000000.evt"), Some(12345));
+        assert_eq!(event_file_number("0007-1a2b.evt"), None);
+        assert_eq!(event_file_number("+7.evt"), None);
+        assert_eq!(event_file_number("0007.blk"), None);
+        assert_eq!(
+            event_file_number("0007.sync-conflict-20240101-120000-ABCDEFG.evt"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sharded_and_flat_files() {
+        let dir = tempdir().unwrap();
+        let mut log = EventLog::new(
+            dir.path().to_path_buf(),
+            "device-a".to_string(),
+            KeyPair::generate(),
+        );
+        let device_dir = log.device_events_path("device-a");
+        assert_eq!(
+            event_file_path(&device_dir, 1234, "1234.evt"),
+            device_dir.join("001/1234.evt")
+        );
+
+        let event = || {
+            vec![Event::HandlerRemoved {
+                protocol: "mailto".to_string(),
+            }]
+        };
+        let first = log.write_events(event(), &[]).unwrap();
+        assert_eq!(first.parent().unwrap(), device_dir.join("000"));
+
+        // A file from before the directory was sharded is still read and
+        // counted
+        let flat = device_dir.join(first.file_name().unwrap());
+        fs::rename(&first, &flat).unwrap();
+        assert_eq!(log.next_event_number("device-a").unwrap(), 2);
+        log.write_events(event(), &[]).unwrap();
+        assert_eq!(log.read_all_events(&[]).unwrap().len(), 2);
+        assert_eq!(log.held_files().unwrap().highest["device-a"], 2);
+    }
+
+    #[test]
+    fn test_sync_conflict_copies() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let sync_dir = dir.path().join("sync");
+        let pref = |n| {
+            vec![Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: crate::events::PrefValue::Int(n),
+            }]
+        };
+        let mut log = EventLog::new(sync_dir.clone(), "device-a".to_string(), keypair.clone());
+        log.write_events(pref(1), &[]).unwrap();
+        let second = log.write_events(pref(2), &[]).unwrap();
+        let events_dir = sync_dir.join("events/device-a/000");
+        // As named before files carried a hash
+        fs::rename(&second, events_dir.join("0002.evt")).unwrap();
+
+        // The same device restored from a backup wrote its own second file
+        let mut restored =
+            EventLog::new(dir.path().join("restored"), "device-a".to_string(), keypair);
+        restored.write_events(pref(3), &[]).unwrap();
+        let reused = restored.write_events(pref(4), &[]).unwrap();
+
+        let conflict =
+            |date: &str| events_dir.join(format!("0002.sync-conflict-{}-ABCDEFG.evt", date));
+        fs::copy(&reused, conflict("20240101-120000")).unwrap();
+        fs::copy(events_dir.join("0002.evt"), conflict("20240102-120000")).unwrap();
+        fs::write(conflict("20240103-120000"), b"garbage").unwrap();
+
+        let values: Vec<i64> = log
+            .read_all_events(&[])
+            .unwrap()
+            .iter()
+            .filter_map(|e| match e.event {
+                Event::PrefSet {
+                    value: crate::events::PrefValue::Int(n),
+                    ..
+                } => Some(n),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, vec![1, 2, 4]);
+
+        // The copy with new events was kept under a name of its own
+        let mut names: Vec<String> = fs::read_dir(&events_dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        assert_eq!(names.len(), 3);
+        assert!(names.iter().all(|name| event_file_number(name).is_some()));
+        assert_eq!(
+            fs::read_dir(sync_dir.join("quarantine/device-a"))
+                .unwrap()
+                .count(),
+            2
+        );
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Sets up both copies before reading them
+    fn test_reused_numbers() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let sync_dir = dir.path().join("sync");
+        let pref = |n| {
+            vec![Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: crate::events::PrefValue::Int(n),
+            }]
+        };
+        let mut log = EventLog::new(sync_dir.clone(), "device-a".to_string(), keypair.clone());
+        let (original, envelopes) = log.write_envelopes(pref(1), &[]).unwrap();
+        let events_dir = original.parent().unwrap().to_path_buf();
+
+        // The same events written again under the same number, at another
+        // point of the clock, and a device restored from a backup writing its
+        // own first file
+        let secret = *log.group_keys(&[]).get(KeyEpoch::default());
+        let again = EventFile::new(
+            keypair.public_key(),
+            "device-a",
+            2,
+            &secret,
+            KeyEpoch::default(),
+            &envelopes,
+            EventCodec::default(),
+        )
+        .unwrap();
+        let duplicate = events_dir.join(event_file_name(1, &again));
+        again.save(&duplicate).unwrap();
+        let mut restored =
+            EventLog::new(dir.path().join("restored"), "device-a".to_string(), keypair);
+        let reused = restored.write_events(pref(2), &[]).unwrap();
+        fs::copy(&reused, events_dir.join(reused.file_name().unwrap())).unwrap();
+        assert_ne!(original, duplicate);
+        assert_ne!(original.file_name(), reused.file_name());
+
+        let mut values: Vec<i64> = log
+            .read_all_events(&[])
+            .unwrap()
+            .iter()
+            .filter_map(|e| match e.event {
+                Event::PrefSet {
+                    value: crate::events::PrefValue::Int(n),
+                    ..
+                } => Some(n),
+                _ => None,
+            })
+            .collect();
+        values.sort();
+        assert_eq!(values, vec![1, 2]);
+
+        // Whichever of the two copies is named later was moved aside, and
+        // isn't taken back from a peer
+        let (kept, moved) = if original < duplicate {
+            (original, duplicate)
+        } else {
+            (duplicate, original)
+        };
+        assert_eq!(fs::read_dir(&events_dir).unwrap().count(), 2);
+        assert!(kept.exists());
+        let quarantined = sync_dir
+            .join("quarantine/device-a")
+            .join(moved.file_name().unwrap());
+        assert!(quarantined.exists());
+        let moved = EventFile::load(&quarantined).unwrap();
+        assert!(log.holds_file("device-a", 1, &moved).unwrap());
+    }
+
+    #[test]
+    fn test_passes_on_unknown_events() {
+        let keypair = KeyPair::generate();
+        let secret = keypair.derive_shared_secret(&keypair.public_key());
+        // Written by a newer version, with an event type and fields this one
+        // doesn't know
+        let plaintext = br#"[
+            {"id": "0190a5c4-8f2e-7000-8000-000000000001", "timestamp": "2026-01-01T00:00:00Z",
+             "device": "newer", "clock": {}, "priority": 2,
+             "event": {"type": "HandlerSet", "data": {"protocol": "mailto", "handler": "x"}}},
+            {"id": "0190a5c4-8f2e-7000-8000-000000000002", "timestamp": "2026-01-01T00:00:01Z",
+             "device": "newer", "clock": {},
+             "event": {"type": "BookmarkAdded", "data": {"url": "https://example.com"}}}
+        ]"#;
+        let cipher = crate::crypto::Cipher::XChaCha20Poly1305;
+        let (nonce, ciphertext) =
+            crate::crypto::encrypt(cipher, &secret, "newer", 1, plaintext).unwrap();
+        let file = EventFile {
+            cipher,
+            key_epoch: KeyEpoch::Xor,
+            sender_public_key: keypair.public_key(),
+            nonce,
+            ciphertext,
+        };
+
+        // Received from a peer, it's kept and read rather than refused
+        let first = tempdir().unwrap();
+        let log = EventLog::new(first.path().to_path_buf(), "a".to_string(), keypair.clone());
+        assert!(log.store_remote_file("newer", 1, &file, &[], &[]).unwrap());
+        let events = log.read_all_events(&[]).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[0].event, Event::HandlerSet { .. }));
+        let Event::Unknown(unknown) = &events[1].event else {
+            panic!("expected an unknown event, got {:?}", events[1].event);
+        };
+        assert_eq!(unknown["data"]["url"], "https://example.com");
+
+        // And passed on as it came
+        let page = log.files_since(&HeldFiles::default(), None, 10, u64::MAX, &[]);
+        let (_, _, forwarded, _) = page.unwrap().files.remove(0);
+        assert_eq!(forwarded.ciphertext, file.ciphertext);
+        let second = tempdir().unwrap();
+        let log = EventLog::new(second.path().to_path_buf(), "b".to_string(), keypair);
+        assert!(
+            log.store_remote_file("newer", 1, &forwarded, &[], &[])
+                .unwrap()
+        );
+        assert_eq!(log.read_all_events(&[]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_files_since_pages() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let known_devices = vec![("device-a".to_string(), keypair.public_key())];
+        let mut log = EventLog::new(dir.path().to_path_buf(), "device-a".into(), keypair);
+        for id in ["one@example.com", "two@example.com", "three@example.com"] {
+            let event = Event::ExtensionAdded {
+                id: id.to_string(),
+                name: "Test".to_string(),
+                url: None,
+            };
+            log.write_events(vec![event], &known_devices).unwrap();
+        }
+        let held = HeldFiles::default();
+
+        let page = log
+            .files_since(&held, None, 2, u64::MAX, &known_devices)
+            .unwrap();
+        let numbers: Vec<u32> = page.files.iter().map(|(_, number, _, _)| *number).collect();
+        assert_eq!(numbers, vec![1, 2]);
+        assert_eq!(page.next, Some((2, "device-a".to_string())));
+
+        let page = log
+            .files_since(&held, Some((2, "device-a")), 2, u64::MAX, &known_devices)
+            .unwrap();
+        assert_eq!(page.files.len(), 1);
+        assert_eq!(page.next, None);
+
+        // A page takes one file even when it's over the byte budget
+        let page = log.files_since(&held, None, 10, 1, &known_devices).unwrap();
+        assert_eq!(page.files.len(), 1);
+        assert_eq!(page.next, Some((1, "device-a".to_string())));
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Walks through each kind of file stored
+    fn test_store_remote_files() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let known_devices = vec![("device-a".to_string(), keypair.public_key())];
+        let event = |id: &str| Event::ExtensionAdded {
+            id: id.to_string(),
+            name: "Test".to_string(),
+            url: None,
+        };
+
+        let mut a = EventLog::new(dir.path().join("a"), "device-a".into(), keypair.clone());
+        let b = EventLog::new(dir.path().join("b"), "device-b".into(), keypair);
+        a.write_events(vec![event("one@example.com")], &known_devices)
+            .unwrap();
+        a.write_events(vec![event("two@example.com")], &known_devices)
+            .unwrap();
+
+        let page = a.files_since(&b.held_files().unwrap(), None, 10, u64::MAX, &known_devices);
+        let files = page.unwrap().files;
+        assert_eq!(files.len(), 2);
+        for (device, number, file, chunks) in &files {
+            assert!(
+                b.store_remote_file(device, *number, file, chunks, &known_devices)
+                    .unwrap()
+            );
+            assert!(
+                !b.store_remote_file(device, *number, file, chunks, &known_devices)
+                    .unwrap()
+            );
+        }
+        assert_eq!(
+            b.held_files().unwrap().highest,
+            HashMap::from([("device-a".into(), 2)])
+        );
+        assert_eq!(
+            b.read_device_events("device-a", &known_devices)
+                .unwrap()
+                .len(),
+            2
+        );
+        assert!(
+            a.files_since(&b.held_files().unwrap(), None, 10, u64::MAX, &known_devices)
+                .unwrap()
+                .files
+                .is_empty()
+        );
+
+        // Files can't land outside the sync dir, overwrite the receiver's
+        // own, or pass off one device's events as another's
+        let (_, _, file, _) = &files[0];
+        assert!(
+            b.store_remote_file("../a", 1, file, &[], &known_devices)
+                .is_err()
+        );
+        assert!(
+            b.store_remote_file("device-b", 9, file, &[], &known_devices)
+                .is_err()
+        );
+        let err = b
+            .store_remote_file("device-c", 1, file, &[], &known_devices)
+            .unwrap_err();
+        assert!(err.to_string().contains("holds events from device-a"));
+    }
+
+    #[test]
+    fn test_sends_files_below_highest_held() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let event = |id: &str| vec![Event::ExtensionRemoved { id: id.to_string() }];
+        let mut a = EventLog::new(dir.path().join("a"), "device-a".into(), keypair.clone());
+        let b = EventLog::new(dir.path().join("b"), "device-b".into(), keypair.clone());
+        a.write_events(event("one@example.com"), &[]).unwrap();
+        a.write_events(event("two@example.com"), &[]).unwrap();
+        for (device, number, file, chunks) in a
+            .files_since(&b.held_files().unwrap(), None, 10, u64::MAX, &[])
+            .unwrap()
+            .files
+        {
+            b.store_remote_file(&device, number, &file, &chunks, &[])
+                .unwrap();
+        }
+
+        // Restored from a backup, the device numbers its next file 1 again
+        let mut restored = EventLog::new(dir.path().join("r"), "device-a".into(), keypair);
+        let reused = restored
+            .write_events(event("three@example.com"), &[])
+            .unwrap();
+        let device_dir = a.device_events_path("device-a");
+        let name = reused.file_name().unwrap().to_string_lossy().to_string();
+        fs::copy(&reused, event_file_path(&device_dir, 1, &name)).unwrap();
+
+        let held = b.held_files().unwrap();
+        let page = a.files_since(&held, None, 10, u64::MAX, &[]).unwrap();
+        assert_eq!(page.files.len(), 1);
+        assert_eq!(page.files[0].1, 1);
+        assert_eq!(event_file_name(1, &page.files[0].2), name);
+
+        // Peers that only send the highest number they hold miss it
+        let old = HeldFiles {
+            highest: held.highest,
+            names: HashMap::new(),
+        };
+        assert!(
+            a.files_since(&old, None, 10, u64::MAX, &[])
+                .unwrap()
+                .files
+                .is_empty()
+        );
+    }
+
     #[test]
     fn test_next_event_number() {
         let dir = tempdir().unwrap();
@@ -207,4 +1481,188 @@ mod tests {
 
         assert_eq!(log.next_event_number(&device_id).unwrap(), 2);
     }
+
+    #[test]
+    fn test_write_events_over_limits() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let device_id = "test-device".to_string();
+
+        let mut log = EventLog::new(dir.path().to_path_buf(), device_id.clone(), keypair.clone());
+        let known_devices = vec![(device_id.clone(), keypair.public_key())];
+        let removed = |n: usize| -> Vec<Event> {
+            (0..n)
+                .map(|i| Event::ExtensionRemoved {
+                    id: format!("ext{}@example.com", i),
+                })
+                .collect()
+        };
+
+        log.set_limits(LimitsConfig {
+            max_events_per_file: 2,
+            ..LimitsConfig::default()
+        });
+        assert!(log.write_events(removed(3), &known_devices).is_err());
+        log.write_events(removed(2), &known_devices).unwrap();
+        assert_eq!(log.clock().get(&device_id), 1);
+
+        // A write that doesn't fit the quota leaves the log as it was
+        assert!(log.device_usage(&device_id).unwrap() > 0);
+        log.set_limits(LimitsConfig {
+            max_event_kb: 0,
+            device_quota_mb: 1,
+            ..LimitsConfig::default()
+        });
+        let big = vec![Event::ExtensionAdded {
+            id: "big@example.com".to_string(),
+            name: "a".repeat(1024 * 1024),
+            url: None,
+        }];
+        assert!(log.write_events(big, &known_devices).is_err());
+        assert_eq!(log.clock().get(&device_id), 1);
+        assert_eq!(log.next_event_number(&device_id).unwrap(), 2);
+    }
+
+    fn install(id: &str, xpi: &[u8]) -> Event {
+        Event::ExtensionInstalled {
+            id: id.to_string(),
+            name: id.to_string(),
+            version: "1.0".to_string(),
+            source: crate::events::ExtensionSource::Local {
+                original_path: format!("/tmp/{}.xpi", id),
+            },
+            xpi_data: encode_base64(&crate::extensions::compress_xpi(xpi).unwrap()),
+            chunks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_xpi_chunks_count_towards_quota() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let known_devices = vec![("device-a".to_string(), keypair.public_key())];
+        let mut log = EventLog::new(dir.path().to_path_buf(), "device-a".into(), keypair);
+
+        log.write_events(
+            vec![install("a@example.com", &noise(300 * 1024, 1))],
+            &known_devices,
+        )
+        .unwrap();
+        let usage = log.device_usage("device-a").unwrap();
+        assert!(usage > 300 * 1024, "only {} bytes counted", usage);
+
+        // An XPI that doesn't fit leaves neither a file nor chunks behind
+        log.set_limits(LimitsConfig {
+            max_event_kb: 0,
+            device_quota_mb: 1,
+            ..LimitsConfig::default()
+        });
+        let big = install("b@example.com", &noise(900 * 1024, 2));
+        assert!(log.write_events(vec![big], &known_devices).is_err());
+        assert_eq!(log.device_usage("device-a").unwrap(), usage);
+        assert_eq!(
+            log.blob_store_usage().unwrap(),
+            log.blobs.device_size("device-a").unwrap()
+        );
+        assert_eq!(log.next_event_number("device-a").unwrap(), 2);
+    }
+
+    #[test]
+    fn test_xpi_chunks_sent_with_files() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let known_devices = vec![("device-a".to_string(), keypair.public_key())];
+        let mut a = EventLog::new(dir.path().join("a"), "device-a".into(), keypair.clone());
+        let b = EventLog::new(dir.path().join("b"), "device-b".into(), keypair);
+        let xpi = noise(200 * 1024, 3);
+        a.write_events(vec![install("a@example.com", &xpi)], &known_devices)
+            .unwrap();
+
+        let page = a
+            .files_since(&HeldFiles::default(), None, 10, u64::MAX, &known_devices)
+            .unwrap();
+        let (device, number, file, chunks) = &page.files[0];
+        assert!(!chunks.is_empty());
+
+        // Chunks that aren't what their IDs say are dropped, and the install
+        // waits for the real ones
+        let mut tampered = chunks.clone();
+        tampered[0].1 = chunks[1 % chunks.len()].1.clone();
+        tampered[0].1.push(0);
+        assert!(
+            b.store_remote_file(device, *number, file, &tampered, &known_devices)
+                .unwrap()
+        );
+        assert!(
+            b.read_device_events(device, &known_devices)
+                .unwrap()
+                .is_empty()
+        );
+
+        // The file is held already, but the missing chunks are still taken
+        assert!(
+            b.store_remote_file(device, *number, file, chunks, &known_devices)
+                .unwrap()
+        );
+        let read = b.read_device_events(device, &known_devices).unwrap();
+        let Event::ExtensionInstalled { xpi_data, .. } = &read[0].event else {
+            panic!("expected an install");
+        };
+        assert_eq!(
+            decompress_xpi(&decode_base64(xpi_data).unwrap()).unwrap(),
+            xpi
+        );
+        assert!(b.device_usage("device-a").unwrap() > 200 * 1024);
+    }
+
+    #[test]
+    fn test_xpis_stored_as_chunks() {
+        let dir = tempdir().unwrap();
+        let keypair = KeyPair::generate();
+        let device_id = "test-device".to_string();
+        let mut log = EventLog::new(dir.path().to_path_buf(), device_id.clone(), keypair.clone());
+        let known_devices = vec![(device_id.clone(), keypair.public_key())];
+
+        // Incompressible, like the deflated files inside an XPI
+        let library = noise(256 * 1024, 1);
+        let install = |id: &str, header: &[u8]| install(id, &[header, &library].concat());
+
+        let first = install("first@example.com", b"first");
+        let (path, envelopes) = log
+            .write_envelopes(vec![first.clone()], &known_devices)
+            .unwrap();
+        assert_eq!(envelopes[0].event, first);
+        assert!(fs::metadata(&path).unwrap().len() < 4096);
+
+        let blob_size = |dir: &std::path::Path| -> u64 {
+            fs::read_dir(dir.join("blobs"))
+                .unwrap()
+                .map(|entry| limits::dir_size(&entry.unwrap().path()).unwrap())
+                .sum()
+        };
+        let once = blob_size(dir.path());
+        log.write_events(
+            vec![install("second@example.com", b"second")],
+            &known_devices,
+        )
+        .unwrap();
+        // Only the chunks around the differing header are new
+        assert!(blob_size(dir.path()) - once < 64 * 1024);
+
+        let read = log.read_device_events(&device_id, &known_devices).unwrap();
+        assert_eq!(read.len(), 2);
+        let Event::ExtensionInstalled { xpi_data, .. } = &read[0].event else {
+            panic!("expected an install");
+        };
+        let xpi = decompress_xpi(&decode_base64(xpi_data).unwrap()).unwrap();
+        assert_eq!(xpi, [b"first".as_slice(), &library].concat());
+
+        // An install whose chunks haven't synced yet is left for later
+        fs::remove_dir_all(dir.path().join("blobs")).unwrap();
+        assert!(
+            log.read_device_events(&device_id, &known_devices)
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
@@ -1,9 +1,21 @@
+mod archive;
+mod blobs;
+mod chunking;
 mod clock;
+mod codec;
+mod layout;
+pub mod limits;
 mod log;
 mod storage;
 mod types;
 
+pub use archive::{ARCHIVE_VERSION, ArchiveImport, ArchiveManifest, import_archive, write_archive};
 pub use clock::VectorClock;
-pub use log::EventLog;
+pub use codec::EventCodec;
+pub use layout::{LAYOUT_FILE, LAYOUT_VERSION, layout_version, migrate_layout};
+pub use log::{EventLog, FilePage, HeldFiles, SharedEventLog};
 pub use storage::{EVENT_MAGIC, EventFile};
-pub use types::{Event, EventEnvelope, ExtensionSource, PrefValue};
+pub use types::{
+    Event, EventEnvelope, ExtensionSource, PinnedSite, PrefValue, SearchEngineDetails, SearchParam,
+    SyncCategory,
+};
@@ -1,53 +1,92 @@
 use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
 use std::io::{Read, Write};
 use std::path::Path;
 
-use crate::crypto::{self, Cipher, PublicKey};
+use crate::crypto::{self, Cipher, GroupKeys, KeyEpoch, PublicKey};
 
-use super::EventEnvelope;
+use super::{EventCodec, EventEnvelope};
 
 pub const EVENT_MAGIC: &[u8; 4] = b"WOLF";
-pub const EVENT_VERSION: u8 = 2; // Bumped for new format with cipher field
+pub const EVENT_VERSION: u8 = 3; // Bumped for new format with key epoch field
+
+/// Version of files keyed under `KeyEpoch::Xor`, which have no key epoch
+/// field and are still written that way so older versions can read them
+const XOR_EVENT_VERSION: u8 = 2;
 
 pub struct EventFile {
     pub cipher: Cipher,
+    /// How the group secret the file is encrypted with was derived
+    pub key_epoch: KeyEpoch,
     pub sender_public_key: PublicKey,
     pub nonce: Vec<u8>,
     pub ciphertext: Vec<u8>,
 }
 
 impl EventFile {
+    #[allow(clippy::too_many_arguments)] // Key, nonce inputs, secret, events and codec
     pub fn new(
         sender_public_key: PublicKey,
         device_id: &str,
         counter: u64,
         shared_secret: &[u8; 32],
+        key_epoch: KeyEpoch,
         events: &[EventEnvelope],
+        codec: EventCodec,
     ) -> Result<Self> {
         let cipher = crypto::detect_preferred_cipher();
-        let plaintext = serde_json::to_vec(events).context("Failed to serialize events")?;
+        let plaintext = codec.encode(events)?;
         let (nonce, ciphertext) =
             crypto::encrypt(cipher, shared_secret, device_id, counter, &plaintext)?;
 
         Ok(Self {
             cipher,
+            key_epoch,
             sender_public_key,
             nonce,
             ciphertext,
         })
     }
 
+    /// Decrypt with the group secret of the epoch the file was keyed under
+    pub fn decrypt_with(&self, keys: &GroupKeys) -> Result<Vec<EventEnvelope>> {
+        self.decrypt(keys.get(self.key_epoch))
+    }
+
     pub fn decrypt(&self, shared_secret: &[u8; 32]) -> Result<Vec<EventEnvelope>> {
         let plaintext = crypto::decrypt(self.cipher, shared_secret, &self.nonce, &self.ciphertext)?;
-        let events: Vec<EventEnvelope> =
-            serde_json::from_slice(&plaintext).context("Failed to deserialize events")?;
-        Ok(events)
+        EventCodec::detect(&plaintext).decode(&plaintext)
+    }
+
+    /// First 8 hex digits of the SHA-256 of the file's nonce and ciphertext,
+    /// which tell apart files written under the same number
+    pub fn short_hash(&self) -> String {
+        let digest = Sha256::new()
+            .chain_update(&self.nonce)
+            .chain_update(&self.ciphertext)
+            .finalize();
+        digest[..4].iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Size of the file once written
+    pub fn encoded_len(&self) -> u64 {
+        // Magic, version, cipher, key epoch, public key, nonce length,
+        // nonce, ciphertext
+        (EVENT_MAGIC.len()
+            + 3
+            + usize::from(self.version() != XOR_EVENT_VERSION)
+            + self.sender_public_key.len()
+            + self.nonce.len()
+            + self.ciphertext.len()) as u64
     }
 
     pub fn write_to<W: Write>(&self, mut writer: W) -> Result<()> {
         writer.write_all(EVENT_MAGIC)?;
-        writer.write_all(&[EVENT_VERSION])?;
+        writer.write_all(&[self.version()])?;
         writer.write_all(&[self.cipher as u8])?;
+        if self.version() != XOR_EVENT_VERSION {
+            writer.write_all(&[self.key_epoch as u8])?;
+        }
         writer.write_all(&self.sender_public_key)?;
         writer.write_all(&[self.nonce.len() as u8])?;
         writer.write_all(&self.nonce)?;
@@ -64,7 +103,7 @@ impl EventFile {
 
         let mut version = [0u8; 1];
         reader.read_exact(&mut version)?;
-        if version[0] != EVENT_VERSION {
+        if version[0] != EVENT_VERSION && version[0] != XOR_EVENT_VERSION {
             bail!(
                 "Unsupported event file version: {} (expected {})",
                 version[0],
@@ -77,6 +116,14 @@ impl EventFile {
         let cipher = Cipher::from_byte(cipher_byte[0])
             .ok_or_else(|| anyhow::anyhow!("Unknown cipher type: {}", cipher_byte[0]))?;
 
+        let key_epoch = if version[0] == XOR_EVENT_VERSION {
+            KeyEpoch::Xor
+        } else {
+            let mut epoch = [0u8; 1];
+            reader.read_exact(&mut epoch)?;
+            KeyEpoch::from_number(u64::from(epoch[0]))?
+        };
+
         let mut sender_public_key = [0u8; 32];
         reader.read_exact(&mut sender_public_key)?;
 
@@ -90,12 +137,20 @@ impl EventFile {
 
         Ok(Self {
             cipher,
+            key_epoch,
             sender_public_key,
             nonce,
             ciphertext,
         })
     }
 
+    fn version(&self) -> u8 {
+        match self.key_epoch {
+            KeyEpoch::Xor => XOR_EVENT_VERSION,
+            KeyEpoch::Hkdf => EVENT_VERSION,
+        }
+    }
+
     pub fn save(&self, path: &Path) -> Result<()> {
         if let Some(parent) = path.parent() {
             std::fs::create_dir_all(parent)?;
@@ -143,12 +198,15 @@ mod tests {
             "test-device",
             1,
             &shared_secret,
+            KeyEpoch::Hkdf,
             &events,
+            EventCodec::Json,
         )
         .unwrap();
 
         let mut buffer = Vec::new();
         event_file.write_to(&mut buffer).unwrap();
+        assert_eq!(event_file.encoded_len(), buffer.len() as u64);
 
         let loaded = EventFile::read_from(&buffer[..]).unwrap();
         let decrypted = loaded.decrypt(&shared_secret).unwrap();
@@ -172,7 +230,9 @@ mod tests {
             "test-device",
             1,
             &shared_secret,
+            KeyEpoch::Hkdf,
             &events,
+            EventCodec::Json,
         )
         .unwrap();
         event_file.save(&path).unwrap();
@@ -184,14 +244,75 @@ mod tests {
         assert_eq!(events[0].event, decrypted[0].event);
     }
 
+    #[test]
+    fn test_reads_either_codec() {
+        let alice = KeyPair::generate();
+        let shared_secret = alice.derive_shared_secret(&alice.public_key());
+        let events = make_test_events();
+
+        for codec in [EventCodec::Json, EventCodec::Cbor] {
+            let event_file = EventFile::new(
+                alice.public_key(),
+                "test",
+                1,
+                &shared_secret,
+                KeyEpoch::Hkdf,
+                &events,
+                codec,
+            )
+            .unwrap();
+            let decrypted = event_file.decrypt(&shared_secret).unwrap();
+            assert_eq!(events[0].event, decrypted[0].event);
+        }
+    }
+
+    #[test]
+    fn test_key_epochs() {
+        let alice = KeyPair::generate();
+        let keys = GroupKeys::derive(&alice, &[]);
+        let events = make_test_events();
+
+        for (epoch, version) in [(KeyEpoch::Xor, 2), (KeyEpoch::Hkdf, 3)] {
+            let event_file = EventFile::new(
+                alice.public_key(),
+                "test",
+                1,
+                keys.get(epoch),
+                epoch,
+                &events,
+                EventCodec::Json,
+            )
+            .unwrap();
+            let mut buffer = Vec::new();
+            event_file.write_to(&mut buffer).unwrap();
+            assert_eq!(buffer[4], version);
+            assert_eq!(event_file.encoded_len(), buffer.len() as u64);
+
+            let loaded = EventFile::read_from(&buffer[..]).unwrap();
+            assert_eq!(loaded.key_epoch, epoch);
+            assert_eq!(
+                loaded.decrypt_with(&keys).unwrap()[0].event,
+                events[0].event
+            );
+        }
+    }
+
     #[test]
     fn test_cipher_stored_in_file() {
         let alice = KeyPair::generate();
         let shared_secret = alice.derive_shared_secret(&alice.public_key());
         let events = make_test_events();
 
-        let event_file =
-            EventFile::new(alice.public_key(), "test", 1, &shared_secret, &events).unwrap();
+        let event_file = EventFile::new(
+            alice.public_key(),
+            "test",
+            1,
+            &shared_secret,
+            KeyEpoch::Hkdf,
+            &events,
+            EventCodec::Json,
+        )
+        .unwrap();
 
         let mut buffer = Vec::new();
         event_file.write_to(&mut buffer).unwrap();
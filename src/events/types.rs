@@ -1,8 +1,10 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 use super::VectorClock;
+use crate::profile::is_app_handler;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type", content = "data")]
@@ -23,8 +25,12 @@ pub enum Event {
         name: String,
         version: String,
         source: ExtensionSource,
-        /// Zstd-compressed XPI, base64 encoded
+        /// Zstd-compressed XPI, base64 encoded; empty in event files, where
+        /// the XPI is kept in the blob store instead
         xpi_data: String,
+        /// Blob store chunks the XPI was split into, in order
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        chunks: Vec<String>,
     },
     ExtensionUninstalled {
         id: String,
@@ -46,6 +52,21 @@ pub enum Event {
         color: Option<String>,
         icon: Option<String>,
     },
+    /// A container moved to `position` in the order: a fractional index key,
+    /// with containers sorted by key and then by ID
+    ContainerReordered {
+        id: String,
+        position: String,
+    },
+    /// Multi-Account Containers: always open a site in a container
+    ContainerSiteAssigned {
+        host: String,
+        container_id: String,
+        never_ask: bool,
+    },
+    ContainerSiteUnassigned {
+        host: String,
+    },
 
     // Protocol handlers
     HandlerSet {
@@ -61,6 +82,14 @@ pub enum Event {
         id: String,
         name: String,
         url: String,
+        #[serde(default)]
+        details: SearchEngineDetails,
+    },
+    SearchEngineUpdated {
+        id: String,
+        name: String,
+        url: String,
+        details: SearchEngineDetails,
     },
     SearchEngineRemoved {
         id: String,
@@ -78,6 +107,61 @@ pub enum Event {
         key: String,
     },
 
+    // Start page (homepage, new tab page, pinned top sites)
+    HomepageSet {
+        /// Empty means the browser's default homepage
+        urls: Vec<String>,
+    },
+    NewTabSettingSet {
+        key: String,
+        value: PrefValue,
+    },
+    NewTabSettingRemoved {
+        key: String,
+    },
+    TopSitesPinned {
+        /// Pinned sites by tile position (`None` is an empty slot)
+        sites: Vec<Option<PinnedSite>>,
+    },
+
+    // Site permissions
+    PermissionSet {
+        origin: String,
+        permission: String,
+        /// 1 = allow, 2 = block, 3 = prompt
+        capability: u32,
+    },
+    PermissionRemoved {
+        origin: String,
+        permission: String,
+    },
+
+    // Certificate exceptions (opt-in)
+    CertOverrideSet {
+        /// `host:port`, followed by origin attributes
+        host: String,
+        algorithm: String,
+        fingerprint: String,
+    },
+    CertOverrideRemoved {
+        host: String,
+    },
+
+    // Autofill addresses (opt-in)
+    AddressAdded {
+        guid: String,
+        fields: BTreeMap<String, String>,
+    },
+    AddressUpdated {
+        guid: String,
+        /// Only the fields that changed (`None` clears the field), so edits
+        /// to different fields on two devices both apply
+        fields: BTreeMap<String, Option<String>>,
+    },
+    AddressRemoved {
+        guid: String,
+    },
+
     // Tabs
     TabSent {
         to_device: String,
@@ -87,6 +171,64 @@ pub enum Event {
     TabReceived {
         event_id: Uuid,
     },
+
+    // Reading list (saved for later on any device)
+    ReadingListAdded {
+        url: String,
+        title: Option<String>,
+    },
+    ReadingListRemoved {
+        url: String,
+    },
+
+    // Devices
+    DeviceRenamed {
+        device_id: String,
+        name: String,
+    },
+    /// Emoji and color shown next to a device's name (`None` clears them)
+    DeviceLabeled {
+        device_id: String,
+        emoji: Option<String>,
+        color: Option<String>,
+    },
+    /// Tor onion endpoint (`<address>.onion:<port>`) other devices can dial
+    /// this one at (`None` withdraws it)
+    DeviceOnionSet {
+        device_id: String,
+        onion: Option<String>,
+    },
+    /// IDs of the extensions installed in a device's profile, reported by
+    /// the device whenever they change
+    DeviceExtensionsSet {
+        device_id: String,
+        extensions: Vec<String>,
+    },
+    /// What a device is running, advertised by its daemon periodically so
+    /// the pack can see which devices are behind or have stopped syncing
+    DeviceState {
+        device_id: String,
+        /// Installed extension IDs and their versions
+        extensions: BTreeMap<String, String>,
+        browser_version: Option<String>,
+        wolfpack_version: String,
+        /// When the daemon last scanned the profile
+        last_scan: Option<DateTime<Utc>>,
+    },
+
+    // Pack-wide wolfpack settings
+    /// New value of a setting in `config::PACK_SETTINGS`, taken on by every
+    /// device in the pack
+    PackConfigSet {
+        key: String,
+        value: Vec<String>,
+    },
+
+    /// An event of a type this version doesn't know, written by a newer
+    /// one, kept as it was (`{"type": ..., "data": ...}`) so it's passed on
+    /// unchanged. Never applied, so it's picked up once wolfpack is updated.
+    #[serde(untagged)]
+    Unknown(serde_json::Value),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -97,8 +239,46 @@ pub enum PrefValue {
     String(String),
 }
 
-/// Source of an extension installation
+impl std::fmt::Display for PrefValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PrefValue::Bool(b) => write!(f, "{}", b),
+            PrefValue::Int(i) => write!(f, "{}", i),
+            PrefValue::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+/// Full definition of a search engine beyond its name and search URL
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct SearchEngineDetails {
+    /// Icon URL (usually a data: URI)
+    pub icon_url: Option<String>,
+    /// Search suggestions URL template
+    pub suggest_url: Option<String>,
+    /// HTTP method for searches (`None` means GET)
+    pub method: Option<String>,
+    /// Parameters sent with the search request (form fields for POST)
+    pub params: Vec<SearchParam>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SearchParam {
+    pub name: String,
+    pub value: String,
+}
+
+/// A site pinned to the new tab page's top sites
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PinnedSite {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// Source of an extension installation
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum ExtensionSource {
     /// Built from a git repository
@@ -113,6 +293,84 @@ pub enum ExtensionSource {
     Amo { amo_slug: String },
     /// Local file (path is just metadata, XPI is in event)
     Local { original_path: String },
+    /// Downloaded directly from a URL, with the SHA-256 of the XPI
+    Url { url: String, sha256: String },
+}
+
+/// Category of synced data, used to pause sync selectively
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncCategory {
+    Extensions,
+    Containers,
+    Handlers,
+    Search,
+    Prefs,
+    StartPage,
+    Permissions,
+    Certificates,
+    Addresses,
+    Tabs,
+    ReadingList,
+    Devices,
+}
+
+impl SyncCategory {
+    pub const ALL: [SyncCategory; 12] = [
+        SyncCategory::Extensions,
+        SyncCategory::Containers,
+        SyncCategory::Handlers,
+        SyncCategory::Search,
+        SyncCategory::Prefs,
+        SyncCategory::StartPage,
+        SyncCategory::Permissions,
+        SyncCategory::Certificates,
+        SyncCategory::Addresses,
+        SyncCategory::Tabs,
+        SyncCategory::ReadingList,
+        SyncCategory::Devices,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncCategory::Extensions => "extensions",
+            SyncCategory::Containers => "containers",
+            SyncCategory::Handlers => "handlers",
+            SyncCategory::Search => "search",
+            SyncCategory::Prefs => "prefs",
+            SyncCategory::StartPage => "startpage",
+            SyncCategory::Permissions => "permissions",
+            SyncCategory::Certificates => "certificates",
+            SyncCategory::Addresses => "addresses",
+            SyncCategory::Tabs => "tabs",
+            SyncCategory::ReadingList => "readinglist",
+            SyncCategory::Devices => "devices",
+        }
+    }
+}
+
+impl std::fmt::Display for SyncCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for SyncCategory {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SyncCategory::ALL
+            .into_iter()
+            .find(|c| c.as_str() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = SyncCategory::ALL.iter().map(|c| c.as_str()).collect();
+                anyhow::anyhow!(
+                    "Unknown category '{}' (expected one of: {})",
+                    s,
+                    names.join(", ")
+                )
+            })
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -122,6 +380,10 @@ pub struct EventEnvelope {
     pub device: String,
     pub clock: VectorClock,
     pub event: Event,
+    /// Operating systems (as in `std::env::consts::OS`) the event only makes
+    /// sense on, empty when it applies everywhere
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub platforms: Vec<String>,
 }
 
 impl EventEnvelope {
@@ -131,9 +393,15 @@ impl EventEnvelope {
             timestamp: Utc::now(),
             device,
             clock,
+            platforms: event.platforms(),
             event,
         }
     }
+
+    /// Whether the event applies on this device's operating system
+    pub fn is_for_this_platform(&self) -> bool {
+        self.platforms.is_empty() || self.platforms.iter().any(|os| os == std::env::consts::OS)
+    }
 }
 
 impl Event {
@@ -152,15 +420,236 @@ impl Event {
             | Event::ExtensionUninstalled { id } => Some(id),
             Event::ContainerAdded { id, .. }
             | Event::ContainerRemoved { id }
-            | Event::ContainerUpdated { id, .. } => Some(id),
+            | Event::ContainerUpdated { id, .. }
+            | Event::ContainerReordered { id, .. } => Some(id),
+            Event::ContainerSiteAssigned { host, .. } | Event::ContainerSiteUnassigned { host } => {
+                Some(host)
+            }
             Event::HandlerSet { protocol, .. } | Event::HandlerRemoved { protocol } => {
                 Some(protocol)
             }
             Event::SearchEngineAdded { id, .. }
+            | Event::SearchEngineUpdated { id, .. }
             | Event::SearchEngineRemoved { id }
             | Event::SearchEngineDefault { id } => Some(id),
             Event::PrefSet { key, .. } | Event::PrefRemoved { key } => Some(key),
+            Event::HomepageSet { .. } => Some("homepage"),
+            Event::NewTabSettingSet { key, .. } | Event::NewTabSettingRemoved { key } => Some(key),
+            Event::TopSitesPinned { .. } => Some("pinned-sites"),
+            Event::PermissionSet { origin, .. } | Event::PermissionRemoved { origin, .. } => {
+                Some(origin)
+            }
+            Event::CertOverrideSet { host, .. } | Event::CertOverrideRemoved { host } => Some(host),
+            Event::AddressAdded { guid, .. }
+            | Event::AddressUpdated { guid, .. }
+            | Event::AddressRemoved { guid } => Some(guid),
             Event::TabSent { .. } | Event::TabReceived { .. } => None,
+            Event::ReadingListAdded { url, .. } | Event::ReadingListRemoved { url } => Some(url),
+            Event::DeviceRenamed { device_id, .. }
+            | Event::DeviceLabeled { device_id, .. }
+            | Event::DeviceOnionSet { device_id, .. }
+            | Event::DeviceExtensionsSet { device_id, .. }
+            | Event::DeviceState { device_id, .. } => Some(device_id),
+            Event::PackConfigSet { key, .. } => Some(key),
+            Event::Unknown(_) => None,
+        }
+    }
+
+    /// Short human-readable summary of the change
+    #[allow(clippy::too_many_lines)] // One arm per event type
+    pub fn describe(&self) -> String {
+        match self {
+            Event::ExtensionAdded { id, name, .. } => format!("add extension {} ({})", name, id),
+            Event::ExtensionRemoved { id } => format!("remove extension {}", id),
+            Event::ExtensionInstalled {
+                id, name, version, ..
+            } => format!("install extension {} {} ({})", name, version, id),
+            Event::ExtensionUninstalled { id } => format!("uninstall extension {}", id),
+            Event::ContainerAdded { name, color, .. } => {
+                format!("add container '{}' ({})", name, color)
+            }
+            Event::ContainerRemoved { id } => format!("remove container {}", id),
+            Event::ContainerUpdated { id, .. } => format!("update container {}", id),
+            Event::ContainerReordered { id, .. } => format!("move container {}", id),
+            Event::ContainerSiteAssigned {
+                host, container_id, ..
+            } => format!("open {} in container {}", host, container_id),
+            Event::ContainerSiteUnassigned { host } => {
+                format!("stop opening {} in a container", host)
+            }
+            Event::HandlerSet { protocol, handler } => {
+                format!("set {} handler to {}", protocol, handler)
+            }
+            Event::HandlerRemoved { protocol } => format!("remove {} handler", protocol),
+            Event::SearchEngineAdded { name, url, .. } => {
+                format!("add search engine '{}' ({})", name, url)
+            }
+            Event::SearchEngineUpdated { name, url, .. } => {
+                format!("update search engine '{}' ({})", name, url)
+            }
+            Event::SearchEngineRemoved { id } => format!("remove search engine {}", id),
+            Event::SearchEngineDefault { id } => format!("set default search engine to {}", id),
+            Event::PrefSet { key, .. } if crate::redact::is_sensitive_pref(key) => {
+                format!("set {} = [redacted]", key)
+            }
+            Event::PrefSet { key, value } => format!("set {} = {}", key, value),
+            Event::PrefRemoved { key } => format!("remove pref {}", key),
+            Event::HomepageSet { urls } if urls.is_empty() => "reset homepage".to_string(),
+            Event::HomepageSet { urls } => format!("set homepage to {}", urls.join(" | ")),
+            Event::NewTabSettingSet { key, value } => {
+                format!("set new tab setting {} = {}", key, value)
+            }
+            Event::NewTabSettingRemoved { key } => format!("reset new tab setting {}", key),
+            Event::TopSitesPinned { sites } => {
+                let pinned = sites.iter().flatten().count();
+                format!("pin {} top site(s)", pinned)
+            }
+            Event::PermissionSet {
+                origin,
+                permission,
+                capability,
+            } => format!(
+                "{} {} for {}",
+                crate::profile::capability_name(*capability),
+                permission,
+                origin
+            ),
+            Event::PermissionRemoved { origin, permission } => {
+                format!("reset {} for {}", permission, origin)
+            }
+            Event::CertOverrideSet {
+                host, fingerprint, ..
+            } => format!("trust certificate {} for {}", fingerprint, host),
+            Event::CertOverrideRemoved { host } => {
+                format!("remove certificate exception for {}", host)
+            }
+            // Field names only: addresses are personal data and this ends up in logs
+            Event::AddressAdded { guid, .. } => format!("add address {}", guid),
+            Event::AddressUpdated { guid, fields } => {
+                let names: Vec<&str> = fields.keys().map(String::as_str).collect();
+                format!("update address {} ({})", guid, names.join(", "))
+            }
+            Event::AddressRemoved { guid } => format!("remove address {}", guid),
+            Event::TabSent { to_device, url, .. } => {
+                format!("send {} to {}", crate::redact::tab_url(url), to_device)
+            }
+            Event::TabReceived { event_id } => format!("acknowledge tab {}", event_id),
+            Event::ReadingListAdded { url, .. } => {
+                format!("save {} to reading list", crate::redact::tab_url(url))
+            }
+            Event::ReadingListRemoved { url } => {
+                format!("remove {} from reading list", crate::redact::tab_url(url))
+            }
+            Event::DeviceRenamed { device_id, name } => {
+                format!("rename device {} to {}", device_id, name)
+            }
+            Event::DeviceLabeled {
+                device_id,
+                emoji,
+                color,
+            } => match (emoji, color) {
+                (None, None) => format!("clear label of device {}", device_id),
+                _ => format!(
+                    "label device {} with {}",
+                    device_id,
+                    [emoji.as_deref(), color.as_deref()]
+                        .into_iter()
+                        .flatten()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                ),
+            },
+            Event::DeviceOnionSet { device_id, onion } => match onion {
+                Some(onion) => format!("set onion endpoint of device {} to {}", device_id, onion),
+                None => format!("withdraw onion endpoint of device {}", device_id),
+            },
+            Event::DeviceExtensionsSet {
+                device_id,
+                extensions,
+            } => format!(
+                "report {} extension(s) installed on device {}",
+                extensions.len(),
+                device_id
+            ),
+            Event::DeviceState {
+                device_id,
+                extensions,
+                wolfpack_version,
+                ..
+            } => format!(
+                "report state of device {}: wolfpack {}, {} extension(s)",
+                device_id,
+                wolfpack_version,
+                extensions.len()
+            ),
+            Event::PackConfigSet { key, value } => {
+                format!("set {} to [{}] on every device", key, value.join(", "))
+            }
+            Event::Unknown(value) => format!(
+                "{} (from a newer version of wolfpack)",
+                value
+                    .get("type")
+                    .and_then(|t| t.as_str())
+                    .unwrap_or("unknown event")
+            ),
+        }
+    }
+
+    /// Operating systems the event is limited to when written on this one:
+    /// a handler that opens a local application only makes sense where
+    /// that application's path does
+    pub fn platforms(&self) -> Vec<String> {
+        match self {
+            Event::HandlerSet { handler, .. } if is_app_handler(handler) => {
+                vec![std::env::consts::OS.to_string()]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn category(&self) -> SyncCategory {
+        match self {
+            Event::ExtensionAdded { .. }
+            | Event::ExtensionRemoved { .. }
+            | Event::ExtensionInstalled { .. }
+            | Event::ExtensionUninstalled { .. } => SyncCategory::Extensions,
+            Event::ContainerAdded { .. }
+            | Event::ContainerRemoved { .. }
+            | Event::ContainerUpdated { .. }
+            | Event::ContainerReordered { .. }
+            | Event::ContainerSiteAssigned { .. }
+            | Event::ContainerSiteUnassigned { .. } => SyncCategory::Containers,
+            Event::HandlerSet { .. } | Event::HandlerRemoved { .. } => SyncCategory::Handlers,
+            Event::SearchEngineAdded { .. }
+            | Event::SearchEngineUpdated { .. }
+            | Event::SearchEngineRemoved { .. }
+            | Event::SearchEngineDefault { .. } => SyncCategory::Search,
+            Event::PrefSet { .. } | Event::PrefRemoved { .. } => SyncCategory::Prefs,
+            Event::HomepageSet { .. }
+            | Event::NewTabSettingSet { .. }
+            | Event::NewTabSettingRemoved { .. }
+            | Event::TopSitesPinned { .. } => SyncCategory::StartPage,
+            Event::PermissionSet { .. } | Event::PermissionRemoved { .. } => {
+                SyncCategory::Permissions
+            }
+            Event::CertOverrideSet { .. } | Event::CertOverrideRemoved { .. } => {
+                SyncCategory::Certificates
+            }
+            Event::AddressAdded { .. }
+            | Event::AddressUpdated { .. }
+            | Event::AddressRemoved { .. } => SyncCategory::Addresses,
+            Event::TabSent { .. } | Event::TabReceived { .. } => SyncCategory::Tabs,
+            Event::ReadingListAdded { .. } | Event::ReadingListRemoved { .. } => {
+                SyncCategory::ReadingList
+            }
+            Event::DeviceRenamed { .. }
+            | Event::DeviceLabeled { .. }
+            | Event::DeviceOnionSet { .. }
+            | Event::DeviceExtensionsSet { .. }
+            | Event::DeviceState { .. }
+            | Event::PackConfigSet { .. } => SyncCategory::Devices,
+            // Can't be told, so it goes with wolfpack's own events
+            Event::Unknown(_) => SyncCategory::Devices,
         }
     }
 }
@@ -182,6 +671,63 @@ mod tests {
         assert_eq!(event, parsed);
     }
 
+    #[test]
+    fn test_ignores_unknown_fields() {
+        // An envelope from a newer version, with fields this one doesn't have
+        let json = r#"{
+            "id": "0190a5c4-8f2e-7000-8000-000000000001",
+            "timestamp": "2026-01-01T00:00:00Z",
+            "device": "device-a",
+            "clock": {},
+            "signature": "abc",
+            "event": {
+                "type": "HandlerSet",
+                "data": {"protocol": "mailto", "handler": "mail.example.com", "priority": 1}
+            }
+        }"#;
+        let envelope: EventEnvelope = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            envelope.event,
+            Event::HandlerSet {
+                protocol: "mailto".to_string(),
+                handler: "mail.example.com".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_unknown_event_type_passes_through() {
+        let json = r#"{"type":"BookmarkAdded","data":{"url":"https://example.com","folder":3}}"#;
+        let event: Event = serde_json::from_str(json).unwrap();
+        assert!(matches!(event, Event::Unknown(_)));
+        assert_eq!(event.entity_id(), None);
+        assert_eq!(
+            event.describe(),
+            "BookmarkAdded (from a newer version of wolfpack)"
+        );
+        // Written back out as it came, so newer devices still read it
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::from_str::<serde_json::Value>(json).unwrap()
+        );
+
+        // Unit variants have no data
+        let event: Event = serde_json::from_str(r#"{"type":"HistoryCleared"}"#).unwrap();
+        assert!(matches!(event, Event::Unknown(_)));
+    }
+
+    #[test]
+    fn test_unknown_event_type_through_cbor() {
+        let event: Event = serde_json::from_str(
+            r#"{"type":"BookmarkAdded","data":{"url":"https://example.com"}}"#,
+        )
+        .unwrap();
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&event, &mut bytes).unwrap();
+        let decoded: Event = ciborium::from_reader(&bytes[..]).unwrap();
+        assert_eq!(decoded, event);
+    }
+
     #[test]
     fn test_pref_value_serialization() {
         let cases = vec![
@@ -216,6 +762,7 @@ mod tests {
                     original_path: "/path".to_string(),
                 },
                 xpi_data: "data".to_string(),
+                chunks: Vec::new(),
             },
             Event::ExtensionUninstalled {
                 id: "ext@test.com".to_string(),
@@ -235,6 +782,10 @@ mod tests {
                 color: None,
                 icon: None,
             },
+            Event::ContainerReordered {
+                id: "1".to_string(),
+                position: "V".to_string(),
+            },
             Event::HandlerSet {
                 protocol: "mailto".to_string(),
                 handler: "thunderbird".to_string(),
@@ -246,6 +797,21 @@ mod tests {
                 id: "ddg".to_string(),
                 name: "DuckDuckGo".to_string(),
                 url: "https://duckduckgo.com/?q=%s".to_string(),
+                details: SearchEngineDetails::default(),
+            },
+            Event::SearchEngineUpdated {
+                id: "ddg".to_string(),
+                name: "DuckDuckGo".to_string(),
+                url: "https://duckduckgo.com/".to_string(),
+                details: SearchEngineDetails {
+                    icon_url: Some("data:image/png;base64,AAAA".to_string()),
+                    suggest_url: Some("https://duckduckgo.com/ac/?q={searchTerms}".to_string()),
+                    method: Some("POST".to_string()),
+                    params: vec![SearchParam {
+                        name: "q".to_string(),
+                        value: "{searchTerms}".to_string(),
+                    }],
+                },
             },
             Event::SearchEngineRemoved {
                 id: "ddg".to_string(),
@@ -268,6 +834,17 @@ mod tests {
             Event::TabReceived {
                 event_id: Uuid::nil(),
             },
+            Event::DeviceExtensionsSet {
+                device_id: "device-b".to_string(),
+                extensions: vec!["ext@test.com".to_string()],
+            },
+            Event::DeviceState {
+                device_id: "device-b".to_string(),
+                extensions: BTreeMap::from([("ext@test.com".to_string(), "1.2".to_string())]),
+                browser_version: Some("128.0".to_string()),
+                wolfpack_version: "0.1.0".to_string(),
+                last_scan: Some(Utc::now()),
+            },
         ];
 
         for event in events {
@@ -296,6 +873,11 @@ mod tests {
             ExtensionSource::Local {
                 original_path: "/path/to/ext.xpi".to_string(),
             },
+            ExtensionSource::Url {
+                url: "https://example.com/ext.xpi".to_string(),
+                sha256: "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae"
+                    .to_string(),
+            },
         ];
 
         for source in sources {
@@ -320,6 +902,30 @@ mod tests {
         assert!(!envelope.id.is_nil());
     }
 
+    #[test]
+    fn test_app_handlers_are_limited_to_platform() {
+        let handler = |handler: &str| {
+            EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::HandlerSet {
+                    protocol: "mailto".to_string(),
+                    handler: handler.to_string(),
+                },
+            )
+        };
+
+        let web = handler("https://mail.example.com/compose?to=%s");
+        assert!(web.platforms.is_empty());
+        assert!(!serde_json::to_string(&web).unwrap().contains("platforms"));
+
+        let mut app = handler("/usr/bin/thunderbird");
+        assert_eq!(app.platforms, vec![std::env::consts::OS]);
+        assert!(app.is_for_this_platform());
+        app.platforms = vec!["plan9".to_string()];
+        assert!(!app.is_for_this_platform());
+    }
+
     #[test]
     fn test_is_tab_for_device() {
         let tab_event = Event::TabSent {
@@ -366,6 +972,7 @@ mod tests {
                         original_path: "/path".to_string(),
                     },
                     xpi_data: "".to_string(),
+                    chunks: Vec::new(),
                 },
                 Some("ext@test.com"),
             ),
@@ -399,6 +1006,13 @@ mod tests {
                 },
                 Some("1"),
             ),
+            (
+                Event::ContainerReordered {
+                    id: "1".to_string(),
+                    position: "V".to_string(),
+                },
+                Some("1"),
+            ),
             (
                 Event::HandlerSet {
                     protocol: "mailto".to_string(),
@@ -417,6 +1031,16 @@ mod tests {
                     id: "ddg".to_string(),
                     name: "DDG".to_string(),
                     url: "url".to_string(),
+                    details: SearchEngineDetails::default(),
+                },
+                Some("ddg"),
+            ),
+            (
+                Event::SearchEngineUpdated {
+                    id: "ddg".to_string(),
+                    name: "DDG".to_string(),
+                    url: "url".to_string(),
+                    details: SearchEngineDetails::default(),
                 },
                 Some("ddg"),
             ),
@@ -466,6 +1090,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_search_engine_added_without_details() {
+        // Events written before engine details were synced
+        let json = r#"{"type":"SearchEngineAdded","data":{"id":"ddg","name":"DDG","url":"url"}}"#;
+        let parsed: Event = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            parsed,
+            Event::SearchEngineAdded {
+                id: "ddg".to_string(),
+                name: "DDG".to_string(),
+                url: "url".to_string(),
+                details: SearchEngineDetails::default(),
+            }
+        );
+    }
+
     #[test]
     fn test_pref_value_types() {
         // Bool
@@ -482,4 +1122,99 @@ mod tests {
         let parsed: PrefValue = serde_json::from_str(&json).unwrap();
         assert_eq!(s, parsed);
     }
+
+    #[test]
+    fn test_event_category() {
+        let event = Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: PrefValue::Int(3),
+        };
+        assert_eq!(event.category(), SyncCategory::Prefs);
+
+        let event = Event::TabReceived {
+            event_id: Uuid::now_v7(),
+        };
+        assert_eq!(event.category(), SyncCategory::Tabs);
+
+        let event = Event::ReadingListRemoved {
+            url: "https://example.com".to_string(),
+        };
+        assert_eq!(event.category(), SyncCategory::ReadingList);
+        assert_eq!(event.entity_id(), Some("https://example.com"));
+
+        let event = Event::DeviceRenamed {
+            device_id: "device-a".to_string(),
+            name: "kitchen laptop".to_string(),
+        };
+        assert_eq!(event.category(), SyncCategory::Devices);
+        assert_eq!(event.entity_id(), Some("device-a"));
+    }
+
+    #[test]
+    fn test_event_describe() {
+        let event = Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: PrefValue::String("about:home".to_string()),
+        };
+        assert_eq!(
+            event.describe(),
+            "set browser.startup.page = \"about:home\""
+        );
+
+        let event = Event::HandlerRemoved {
+            protocol: "mailto".to_string(),
+        };
+        assert_eq!(event.describe(), "remove mailto handler");
+
+        // Address values never appear in descriptions
+        let event = Event::AddressUpdated {
+            guid: "abc".to_string(),
+            fields: BTreeMap::from([
+                ("street-address".to_string(), Some("1 Main St".to_string())),
+                ("tel".to_string(), None),
+            ]),
+        };
+        assert_eq!(event.describe(), "update address abc (street-address, tel)");
+
+        let event = Event::PermissionSet {
+            origin: "https://example.com".to_string(),
+            permission: "desktop-notification".to_string(),
+            capability: 2,
+        };
+        assert_eq!(
+            event.describe(),
+            "block desktop-notification for https://example.com"
+        );
+    }
+
+    #[test]
+    fn test_start_page_events() {
+        let event = Event::TopSitesPinned {
+            sites: vec![
+                Some(PinnedSite {
+                    url: "https://example.com".to_string(),
+                    label: Some("Example".to_string()),
+                }),
+                None,
+            ],
+        };
+        let json = serde_json::to_string(&event).unwrap();
+        let parsed: Event = serde_json::from_str(&json).unwrap();
+        assert_eq!(event, parsed);
+        assert_eq!(event.category(), SyncCategory::StartPage);
+        assert_eq!(event.entity_id(), Some("pinned-sites"));
+        assert_eq!(event.describe(), "pin 1 top site(s)");
+
+        let event = Event::HomepageSet { urls: Vec::new() };
+        assert_eq!(event.describe(), "reset homepage");
+        assert_eq!(event.entity_id(), Some("homepage"));
+    }
+
+    #[test]
+    fn test_sync_category_parse() {
+        for category in SyncCategory::ALL {
+            assert_eq!(category.as_str().parse::<SyncCategory>().unwrap(), category);
+        }
+        assert!("bookmarks".parse::<SyncCategory>().is_err());
+    }
 }
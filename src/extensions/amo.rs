@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+/// Add-on details on addons.mozilla.org, by slug, GUID, or numeric ID
+const AMO_ADDON_API: &str = "https://addons.mozilla.org/api/v5/addons/addon";
+
+/// The XPI of an add-on's current version on AMO
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AmoXpi {
+    pub slug: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+#[derive(Deserialize)]
+struct Addon {
+    slug: String,
+    current_version: Version,
+}
+
+#[derive(Deserialize)]
+struct Version {
+    file: File,
+}
+
+#[derive(Deserialize)]
+struct File {
+    url: String,
+    /// `sha256:<hex>`
+    hash: String,
+}
+
+/// Look up where to download an add-on's current version from
+pub async fn amo_xpi(client: &reqwest::Client, addon: &str) -> Result<AmoXpi> {
+    let mut url = reqwest::Url::parse(AMO_ADDON_API)?;
+    url.path_segments_mut()
+        .map_err(|()| anyhow::anyhow!("Invalid AMO API URL"))?
+        .push(addon)
+        .push("");
+    let addon: Addon = client
+        .get(url)
+        .send()
+        .await
+        .with_context(|| format!("Failed to look up {} on AMO", addon))?
+        .error_for_status()
+        .with_context(|| format!("{} not found on AMO", addon))?
+        .json()
+        .await
+        .with_context(|| format!("Unexpected AMO response for {}", addon))?;
+
+    let file = addon.current_version.file;
+    let Some(sha256) = file.hash.strip_prefix("sha256:") else {
+        anyhow::bail!(
+            "AMO gave an unexpected hash for {}: {}",
+            addon.slug,
+            file.hash
+        );
+    };
+    Ok(AmoXpi {
+        slug: addon.slug,
+        url: file.url,
+        sha256: sha256.to_string(),
+    })
+}
+
+/// Read the add-ons in a list, as AMO slugs, extension IDs, or AMO links
+///
+/// The list is either a JSON array of those, or one per line, with blank
+/// lines and `#` comments skipped.
+pub fn parse_addon_list(content: &str) -> Result<Vec<String>> {
+    let entries: Vec<String> = if content.trim_start().starts_with('[') {
+        serde_json::from_str(content).context("Expected a JSON array of strings")?
+    } else {
+        content
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().to_string())
+            .collect()
+    };
+
+    let mut addons: Vec<String> = Vec::new();
+    for entry in entries {
+        let entry = entry.trim();
+        if entry.is_empty() {
+            continue;
+        }
+        let addon = amo_link_slug(entry).unwrap_or(entry).to_string();
+        if !addons.contains(&addon) {
+            addons.push(addon);
+        }
+    }
+    Ok(addons)
+}
+
+/// The slug in a link to an add-on's AMO page
+fn amo_link_slug(entry: &str) -> Option<&str> {
+    let path = entry
+        .strip_prefix("https://addons.mozilla.org/")
+        .or_else(|| entry.strip_prefix("http://addons.mozilla.org/"))?;
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    segments.find(|segment| *segment == "addon")?;
+    segments.next()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_addon_list() {
+        let list = "\
+# Recommended
+ublock-origin
+https://addons.mozilla.org/en-US/firefox/addon/multi-account-containers/
+
+{446900e4-71c2-419f-a6a7-df9c091e268b}  # Bitwarden
+ublock-origin
+";
+        assert_eq!(
+            parse_addon_list(list).unwrap(),
+            vec![
+                "ublock-origin",
+                "multi-account-containers",
+                "{446900e4-71c2-419f-a6a7-df9c091e268b}",
+            ]
+        );
+
+        let json = r#"["ublock-origin", "uBlock0@raymondhill.net"]"#;
+        assert_eq!(
+            parse_addon_list(json).unwrap(),
+            vec!["ublock-origin", "uBlock0@raymondhill.net"]
+        );
+        assert!(parse_addon_list("[1, 2]").is_err());
+        assert!(parse_addon_list("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_amo_link_slug() {
+        assert_eq!(
+            amo_link_slug("https://addons.mozilla.org/firefox/addon/ublock-origin"),
+            Some("ublock-origin")
+        );
+        assert_eq!(
+            amo_link_slug("https://addons.mozilla.org/en-US/firefox/"),
+            None
+        );
+        assert_eq!(amo_link_slug("ublock-origin"), None);
+    }
+}
@@ -0,0 +1,444 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+use tracing::info;
+
+use super::build_xpi;
+use crate::events::ExtensionSource;
+
+/// Build steps checked into an extension's repository, which take the place
+/// of detecting them
+pub const BUILD_CONFIG_FILE: &str = ".wolfpack-build.toml";
+
+/// How an extension's source is built into something to package
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildSystem {
+    /// Steps pinned in the repository's `.wolfpack-build.toml`
+    Pinned(BuildConfig),
+    Npm,
+    Pnpm,
+    Yarn,
+    /// `deno task build`
+    Deno,
+    /// Rust compiled to wasm with trunk
+    Trunk,
+    /// Rust compiled to wasm with wasm-pack
+    WasmPack,
+    Make,
+    WebExt,
+    /// `manifest.json` in the root, with nothing to build
+    Prebuilt,
+}
+
+/// Directories not searched for the built `manifest.json`
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target"];
+
+/// Contents of a `.wolfpack-build.toml`
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BuildConfig {
+    /// Commands run in order from the repository root
+    pub steps: Vec<String>,
+    /// Directory the built extension's `manifest.json` ends up in, relative
+    /// to the root (found by searching when not given)
+    pub output: Option<PathBuf>,
+}
+
+impl BuildConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let config: Self = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if config.steps.is_empty() {
+            anyhow::bail!("{} has no build steps", path.display());
+        }
+        // The output is packaged, so it can't point outside the repository
+        if let Some(output) = &config.output
+            && !output
+                .components()
+                .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+        {
+            anyhow::bail!(
+                "{} has output {}, which must be a path inside the repository",
+                path.display(),
+                output.display()
+            );
+        }
+        Ok(config)
+    }
+}
+
+impl BuildSystem {
+    /// Work out how to build the extension in `dir`, or None if it's not
+    /// recognized
+    ///
+    /// A `.wolfpack-build.toml` wins; after it, JavaScript package managers
+    /// come before Rust, since their build scripts usually wrap wasm-pack
+    /// or trunk.
+    pub fn detect(dir: &Path) -> Result<Option<Self>> {
+        let config = dir.join(BUILD_CONFIG_FILE);
+        if config.exists() {
+            return Ok(Some(Self::Pinned(BuildConfig::load(&config)?)));
+        }
+
+        let has = |file: &str| dir.join(file).exists();
+        // The lockfile only says which package manager runs the build script
+        let detected = if has_build_script(&dir.join("package.json"), "scripts") {
+            if has("pnpm-lock.yaml") {
+                Self::Pnpm
+            } else if has("yarn.lock") {
+                Self::Yarn
+            } else {
+                Self::Npm
+            }
+        } else if has_build_script(&dir.join("deno.json"), "tasks")
+            || has_build_script(&dir.join("deno.jsonc"), "tasks")
+        {
+            Self::Deno
+        } else if has("Cargo.toml") && has("Trunk.toml") {
+            Self::Trunk
+        } else if is_wasm_crate(&dir.join("Cargo.toml")) {
+            Self::WasmPack
+        } else if has("Makefile") {
+            Self::Make
+        } else if has("web-ext-config.js") || has("web-ext-config.mjs") {
+            Self::WebExt
+        } else if has("manifest.json") {
+            Self::Prebuilt
+        } else {
+            return Ok(None);
+        };
+        Ok(Some(detected))
+    }
+
+    /// Commands that build the extension, run in order from the root
+    pub fn commands(&self) -> Vec<String> {
+        let commands: &[&str] = match self {
+            Self::Pinned(config) => return config.steps.clone(),
+            Self::Npm => &["npm ci", "npm run build"],
+            Self::Pnpm => &["pnpm install", "pnpm run build"],
+            Self::Yarn => &["yarn install", "yarn run build"],
+            Self::Deno => &["deno task build"],
+            Self::Trunk => &["trunk build --release"],
+            Self::WasmPack => &["wasm-pack build --release --target web"],
+            Self::Make => &["make"],
+            Self::WebExt => &["web-ext build"],
+            Self::Prebuilt => &[],
+        };
+        commands.iter().map(|command| command.to_string()).collect()
+    }
+
+    /// Where the built extension ends up, when it's pinned
+    pub fn output(&self) -> Option<&Path> {
+        match self {
+            Self::Pinned(config) => config.output.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+/// An extension built from a git repository, ready to install
+#[derive(Debug)]
+pub struct GitBuild {
+    pub xpi: Vec<u8>,
+    pub source: ExtensionSource,
+}
+
+/// Clone `url`, check out `ref_spec`, build it with `build_cmd` or else the
+/// build system detected in it, and package what it built
+///
+/// The source records the commit built when no ref is given, so other
+/// devices can see exactly what the XPI came from.
+pub fn build_from_git(
+    url: &str,
+    ref_spec: Option<&str>,
+    build_cmd: Option<&str>,
+) -> Result<GitBuild> {
+    if ref_spec.is_some_and(|r| r.starts_with('-')) {
+        anyhow::bail!("Invalid ref: {}", ref_spec.unwrap_or_default());
+    }
+    let checkout = TempDir::new()?;
+    let repo = checkout.path();
+    info!("Cloning {}", url);
+    git(Command::new("git")
+        .args(["clone", "--quiet", "--", url])
+        .arg(repo))?;
+    if let Some(ref_spec) = ref_spec {
+        git(Command::new("git")
+            .current_dir(repo)
+            .args(["checkout", "--quiet", "--detach", ref_spec]))?;
+    }
+    let commit = Command::new("git")
+        .current_dir(repo)
+        .args(["rev-parse", "HEAD"])
+        .output()
+        .context("Failed to run git")?;
+    let commit = String::from_utf8_lossy(&commit.stdout).trim().to_string();
+
+    let (built, commands) = build_extension(repo, build_cmd)?;
+    let (_, xpi) = build_xpi(&built)?;
+    Ok(GitBuild {
+        xpi,
+        source: ExtensionSource::Git {
+            url: url.to_string(),
+            ref_spec: ref_spec.map_or(commit, String::from),
+            build_cmd: (!commands.is_empty()).then(|| commands.join(" && ")),
+        },
+    })
+}
+
+/// Build the extension in `dir` with `build_cmd`, or else the build system
+/// detected in it, returning the directory the built `manifest.json` is in
+/// and the commands that were run
+pub fn build_extension(dir: &Path, build_cmd: Option<&str>) -> Result<(PathBuf, Vec<String>)> {
+    let system = BuildSystem::detect(dir)?;
+    let commands = match (build_cmd, &system) {
+        (Some(command), _) => vec![command.to_string()],
+        (None, Some(system)) => system.commands(),
+        (None, None) => anyhow::bail!(
+            "Don't know how to build {}: pass --build or add a {}",
+            dir.display(),
+            BUILD_CONFIG_FILE
+        ),
+    };
+    for command in &commands {
+        info!("Running {}", command);
+        let status = shell(command)
+            .current_dir(dir)
+            .status()
+            .with_context(|| format!("Failed to run {}", command))?;
+        if !status.success() {
+            anyhow::bail!("{} failed with {}", command, status);
+        }
+    }
+
+    let built = match system.as_ref().and_then(BuildSystem::output) {
+        Some(output) => output_dir(dir, output)?,
+        None => find_manifest(dir)?,
+    };
+    Ok((built, commands))
+}
+
+fn git(command: &mut Command) -> Result<()> {
+    let status = command.status().context("Failed to run git")?;
+    if !status.success() {
+        anyhow::bail!("git failed with {}", status);
+    }
+    Ok(())
+}
+
+#[cfg(not(windows))]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.args(["-c", command]);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.args(["/C", command]);
+    shell
+}
+
+/// A pinned output directory, which the build mustn't have pointed outside
+/// the repository with a symlink
+fn output_dir(dir: &Path, output: &Path) -> Result<PathBuf> {
+    let built = dir
+        .join(output)
+        .canonicalize()
+        .with_context(|| format!("Build output {} wasn't created", output.display()))?;
+    if !built.starts_with(dir.canonicalize()?) {
+        anyhow::bail!(
+            "Build output {} is outside the repository",
+            output.display()
+        );
+    }
+    if !built.join("manifest.json").is_file() {
+        anyhow::bail!("No manifest.json in build output {}", output.display());
+    }
+    Ok(built)
+}
+
+/// The shallowest directory with a `manifest.json`, not counting hidden
+/// directories, dependencies or symlinks
+fn find_manifest(dir: &Path) -> Result<PathBuf> {
+    let mut queue = VecDeque::from([dir.to_path_buf()]);
+    while let Some(current) = queue.pop_front() {
+        if current.join("manifest.json").is_file() {
+            return Ok(current);
+        }
+        let mut children: Vec<PathBuf> = std::fs::read_dir(&current)?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_ref())
+            })
+            .map(|entry| entry.path())
+            .collect();
+        children.sort();
+        queue.extend(children);
+    }
+    anyhow::bail!("No manifest.json found in {} after building", dir.display())
+}
+
+/// Whether a `package.json` or `deno.json` has a `build` entry under `key`
+fn has_build_script(path: &Path, key: &str) -> bool {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<serde_json::Value>(&content).ok())
+        .is_some_and(|json| json[key].get("build").is_some())
+}
+
+/// Whether a `Cargo.toml` is for a crate built to wasm: a `cdylib` or one
+/// depending on wasm-bindgen
+fn is_wasm_crate(path: &Path) -> bool {
+    let Some(cargo) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| content.parse::<toml::Table>().ok())
+    else {
+        return false;
+    };
+    let cdylib = cargo
+        .get("lib")
+        .and_then(|lib| lib.get("crate-type"))
+        .and_then(|types| types.as_array())
+        .is_some_and(|types| types.iter().any(|t| t.as_str() == Some("cdylib")));
+    let bindgen = cargo
+        .get("dependencies")
+        .is_some_and(|deps| deps.get("wasm-bindgen").is_some());
+    cdylib || bindgen
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(files: &[(&str, &str)]) -> Option<BuildSystem> {
+        let dir = tempfile::tempdir().unwrap();
+        for (name, content) in files {
+            std::fs::write(dir.path().join(name), content).unwrap();
+        }
+        BuildSystem::detect(dir.path()).unwrap()
+    }
+
+    #[test]
+    fn test_detect() {
+        let build_script = r#"{"scripts": {"build": "webpack"}}"#;
+        assert_eq!(
+            detect(&[("package.json", build_script)]),
+            Some(BuildSystem::Npm)
+        );
+        assert_eq!(
+            detect(&[("package.json", build_script), ("yarn.lock", "")]),
+            Some(BuildSystem::Yarn)
+        );
+        assert_eq!(
+            detect(&[("package.json", build_script), ("pnpm-lock.yaml", "")]),
+            Some(BuildSystem::Pnpm)
+        );
+        // A package.json without a build script is only dependencies,
+        // whichever package manager locked them
+        for lockfile in ["package-lock.json", "pnpm-lock.yaml", "yarn.lock"] {
+            assert_eq!(
+                detect(&[
+                    ("package.json", "{}"),
+                    (lockfile, ""),
+                    ("manifest.json", "{}")
+                ]),
+                Some(BuildSystem::Prebuilt)
+            );
+        }
+        assert_eq!(
+            detect(&[("deno.json", r#"{"tasks": {"build": "deno run build.ts"}}"#)]),
+            Some(BuildSystem::Deno)
+        );
+        assert_eq!(detect(&[("Makefile", "all:\n")]), Some(BuildSystem::Make));
+        assert_eq!(detect(&[("README.md", "")]), None);
+    }
+
+    #[test]
+    fn test_detect_wasm() {
+        let cdylib = "[package]\nname = \"ext\"\n\n[lib]\ncrate-type = [\"cdylib\"]\n";
+        let bindgen = "[package]\nname = \"ext\"\n\n[dependencies]\nwasm-bindgen = \"0.2\"\n";
+        let native = "[package]\nname = \"tool\"\n";
+
+        assert_eq!(
+            detect(&[("Cargo.toml", cdylib)]),
+            Some(BuildSystem::WasmPack)
+        );
+        assert_eq!(
+            detect(&[("Cargo.toml", bindgen)]),
+            Some(BuildSystem::WasmPack)
+        );
+        assert_eq!(
+            detect(&[("Cargo.toml", native), ("Trunk.toml", "")]),
+            Some(BuildSystem::Trunk)
+        );
+        assert_eq!(detect(&[("Cargo.toml", native)]), None);
+    }
+
+    #[test]
+    fn test_build_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = r#"{"name": "Test", "version": "1.0"}"#;
+        std::fs::write(dir.path().join("manifest.src.json"), manifest).unwrap();
+        std::fs::create_dir_all(dir.path().join("node_modules/dep")).unwrap();
+        std::fs::write(dir.path().join("node_modules/dep/manifest.json"), "{}").unwrap();
+
+        // Without a manifest.json in the root, the shallowest one built is used
+        let copy = "mkdir -p dist/firefox && cp manifest.src.json dist/firefox/manifest.json";
+        let (built, commands) = build_extension(dir.path(), Some(copy)).unwrap();
+        assert_eq!(built, dir.path().join("dist/firefox"));
+        assert_eq!(commands, vec![copy]);
+
+        // A pinned output is used as is
+        std::fs::write(
+            dir.path().join(BUILD_CONFIG_FILE),
+            concat!(
+                "steps = [\"mkdir -p out && cp manifest.src.json out/manifest.json\"]\n",
+                "output = \"out\"\n",
+            ),
+        )
+        .unwrap();
+        let (built, _) = build_extension(dir.path(), None).unwrap();
+        assert_eq!(built, dir.path().canonicalize().unwrap().join("out"));
+
+        assert!(build_extension(dir.path(), Some("false")).is_err());
+        std::fs::write(
+            dir.path().join(BUILD_CONFIG_FILE),
+            "steps = [\"true\"]\noutput = \"missing\"\n",
+        )
+        .unwrap();
+        assert!(build_extension(dir.path(), None).is_err());
+    }
+
+    #[test]
+    fn test_pinned_build() {
+        let config = "steps = [\"npm ci\", \"npm run build:firefox\"]\noutput = \"dist/firefox\"\n";
+        let pinned = detect(&[
+            (BUILD_CONFIG_FILE, config),
+            ("package.json", r#"{"scripts": {"build": "webpack"}}"#),
+        ])
+        .unwrap();
+        assert_eq!(pinned.commands(), vec!["npm ci", "npm run build:firefox"]);
+        assert_eq!(pinned.output(), Some(Path::new("dist/firefox")));
+
+        let dir = tempfile::tempdir().unwrap();
+        for bad in [
+            "steps = []\n",
+            "steps = [\"make\"]\nbuild = \"make\"\n",
+            "steps = [\"make\"]\noutput = \"/etc\"\n",
+            "steps = [\"make\"]\noutput = \"dist/../../elsewhere\"\n",
+        ] {
+            std::fs::write(dir.path().join(BUILD_CONFIG_FILE), bad).unwrap();
+            assert!(BuildSystem::detect(dir.path()).is_err());
+        }
+    }
+}
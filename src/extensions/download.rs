@@ -0,0 +1,98 @@
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(120);
+const MAX_REDIRECTS: usize = 10;
+
+/// Parse a URL to download an XPI from, which has to be HTTPS
+pub fn xpi_url(url: &str) -> Result<reqwest::Url> {
+    let parsed = reqwest::Url::parse(url).with_context(|| format!("Invalid URL: {}", url))?;
+    if parsed.scheme() != "https" {
+        anyhow::bail!("Only HTTPS URLs can be installed from: {}", url);
+    }
+    Ok(parsed)
+}
+
+/// Hex SHA-256 of an XPI
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Check an XPI against the SHA-256 it's expected to have
+pub fn check_sha256(bytes: &[u8], expected: &str) -> Result<()> {
+    let actual = sha256_hex(bytes);
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        anyhow::bail!("Checksum mismatch: expected {}, got {}", expected, actual);
+    }
+    Ok(())
+}
+
+/// Client for downloading extensions, which refuses redirects to anything
+/// but HTTPS
+///
+/// With a SOCKS5 proxy, requests and their DNS lookups go through it.
+pub fn download_client(socks5: Option<&str>) -> Result<reqwest::Client> {
+    let redirects = reqwest::redirect::Policy::custom(|attempt| {
+        if attempt.url().scheme() != "https" {
+            attempt.error("redirected to a URL that isn't HTTPS")
+        } else if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else {
+            attempt.follow()
+        }
+    });
+    let mut builder = reqwest::Client::builder()
+        .timeout(DOWNLOAD_TIMEOUT)
+        .redirect(redirects);
+    if let Some(proxy) = socks5 {
+        builder = builder.proxy(reqwest::Proxy::all(format!("socks5h://{}", proxy))?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Download an XPI over HTTPS, refusing anything larger than `max_bytes`
+pub async fn download_xpi(client: &reqwest::Client, url: &str, max_bytes: u64) -> Result<Vec<u8>> {
+    let url = xpi_url(url)?;
+    let mut response = client
+        .get(url.clone())
+        .send()
+        .await
+        .with_context(|| format!("Failed to download {}", url))?
+        .error_for_status()
+        .with_context(|| format!("Failed to download {}", url))?;
+    if response.content_length().is_some_and(|len| len > max_bytes) {
+        anyhow::bail!("{} is larger than {} bytes", url, max_bytes);
+    }
+
+    let mut xpi = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (xpi.len() + chunk.len()) as u64 > max_bytes {
+            anyhow::bail!("{} is larger than {} bytes", url, max_bytes);
+        }
+        xpi.extend_from_slice(&chunk);
+    }
+    Ok(xpi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xpi_url() {
+        assert!(xpi_url("https://example.com/foo.xpi").is_ok());
+        assert!(xpi_url("http://example.com/foo.xpi").is_err());
+        assert!(xpi_url("file:///tmp/foo.xpi").is_err());
+        assert!(xpi_url("example.com/foo.xpi").is_err());
+    }
+
+    #[test]
+    fn test_check_sha256() {
+        let hash = "2c26b46b68ffc68ff99b453c1d30413413422d706483bfa0f98a5e886266e7ae";
+        assert_eq!(sha256_hex(b"foo"), hash);
+        assert!(check_sha256(b"foo", hash).is_ok());
+        assert!(check_sha256(b"foo", &hash.to_uppercase()).is_ok());
+        assert!(check_sha256(b"bar", hash).is_err());
+    }
+}
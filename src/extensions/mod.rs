@@ -1,22 +1,46 @@
+mod amo;
+mod build;
+mod download;
 mod package;
 
+pub use amo::{AmoXpi, amo_xpi, parse_addon_list};
+pub use build::{BUILD_CONFIG_FILE, BuildConfig, BuildSystem, GitBuild, build_from_git};
+pub use download::{check_sha256, download_client, download_xpi, sha256_hex, xpi_url};
 pub use package::{
-    ExtensionManifest, compress_xpi, decode_base64, decompress_xpi, encode_base64,
+    ExtensionManifest, build_xpi, compress_xpi, decode_base64, decompress_xpi, encode_base64,
     install_to_profile, package_extension, read_manifest, unpack_extension,
 };
 
 use anyhow::Result;
+use rayon::prelude::*;
 use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use tempfile::TempDir;
+use tracing::info;
 
 use crate::events::ExtensionSource;
 
+/// Most extensions written to a profile at once
+const MAX_INSTALL_WORKERS: usize = 4;
+
 /// Install from a local XPI file
 pub fn install_from_xpi(xpi_path: &Path) -> Result<InstallResult> {
     let xpi_bytes = std::fs::read(xpi_path)?;
+    let source = ExtensionSource::Local {
+        original_path: xpi_path.display().to_string(),
+    };
+    install_from_bytes(&xpi_bytes, source)
+}
+
+/// Install from an XPI file downloaded from `source`
+pub fn install_from_download(xpi_path: &Path, source: ExtensionSource) -> Result<InstallResult> {
+    let xpi_bytes = std::fs::read(xpi_path)?;
+    install_from_bytes(&xpi_bytes, source)
+}
 
+fn install_from_bytes(xpi_bytes: &[u8], source: ExtensionSource) -> Result<InstallResult> {
     // Compress and encode
-    let compressed = compress_xpi(&xpi_bytes)?;
+    let compressed = compress_xpi(xpi_bytes)?;
     let xpi_data = encode_base64(&compressed);
 
     // Extract to temp to read manifest
@@ -27,13 +51,64 @@ pub fn install_from_xpi(xpi_path: &Path) -> Result<InstallResult> {
         id: manifest.id,
         name: manifest.name,
         version: manifest.version,
-        source: ExtensionSource::Local {
-            original_path: xpi_path.display().to_string(),
-        },
+        source,
         xpi_data,
     })
 }
 
+/// A synced extension to write to the profile
+#[derive(Debug, Clone)]
+pub struct PendingInstall {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    pub xpi_data: String,
+}
+
+/// Write extensions to a profile on a bounded pool of workers, logging
+/// progress as each one finishes
+///
+/// The results are in the same order as `pending`, and one failing doesn't
+/// stop the others.
+pub fn install_all_to_profile(
+    pending: &[PendingInstall],
+    profile_dir: &Path,
+) -> Result<Vec<Result<()>>> {
+    let workers = std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .clamp(1, MAX_INSTALL_WORKERS);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()?;
+    info!(
+        "Installing {} extensions, {} at a time",
+        pending.len(),
+        workers
+    );
+
+    let done = AtomicUsize::new(0);
+    let results = pool.install(|| {
+        pending
+            .par_iter()
+            .map(|extension| {
+                let result = install_to_profile(&extension.xpi_data, profile_dir, &extension.id);
+                let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+                if result.is_ok() {
+                    info!(
+                        "Installed extension {} v{} ({}/{})",
+                        extension.name,
+                        extension.version,
+                        done,
+                        pending.len()
+                    );
+                }
+                result
+            })
+            .collect()
+    });
+    Ok(results)
+}
+
 /// Result of installing an extension
 #[derive(Debug, Clone)]
 pub struct InstallResult {
@@ -5,6 +5,8 @@ use std::io::{Read, Write};
 use std::path::Path;
 use tracing::info;
 
+use crate::profile::write_atomic;
+
 /// Extension manifest data extracted from manifest.json
 #[derive(Debug, Clone)]
 pub struct ExtensionManifest {
@@ -107,9 +109,8 @@ pub fn decode_base64(data: &str) -> Result<Vec<u8>> {
     BASE64.decode(data).context("Failed to decode base64")
 }
 
-/// Full pipeline: directory -> compressed base64 XPI
-#[allow(clippy::cognitive_complexity)] // Packaging pipeline with multiple steps
-pub fn package_extension(source_dir: &Path) -> Result<(ExtensionManifest, String)> {
+/// Zip an extension's directory into an XPI
+pub fn build_xpi(source_dir: &Path) -> Result<(ExtensionManifest, Vec<u8>)> {
     // Read manifest
     let manifest = read_manifest(source_dir)?;
     info!("Packaging {} v{}", manifest.name, manifest.version);
@@ -127,6 +128,12 @@ pub fn package_extension(source_dir: &Path) -> Result<(ExtensionManifest, String
     }
 
     info!("XPI size: {} bytes", xpi_data.len());
+    Ok((manifest, xpi_data))
+}
+
+/// Full pipeline: directory -> compressed base64 XPI
+pub fn package_extension(source_dir: &Path) -> Result<(ExtensionManifest, String)> {
+    let (manifest, xpi_data) = build_xpi(source_dir)?;
 
     // Compress
     let compressed = compress_xpi(&xpi_data)?;
@@ -187,7 +194,7 @@ pub fn install_to_profile(xpi_data: &str, profile_dir: &Path, extension_id: &str
 
     // Write as {extension_id}.xpi
     let xpi_path = extensions_dir.join(format!("{}.xpi", extension_id));
-    std::fs::write(&xpi_path, &xpi_bytes)
+    write_atomic(&xpi_path, &xpi_bytes)
         .with_context(|| format!("Failed to write XPI to {}", xpi_path.display()))?;
 
     // Verify file was written
@@ -0,0 +1,453 @@
+//! The protocol the CLI and tray speak to the daemon over its Unix socket
+//!
+//! Each message is one line of JSON. A request carries the protocol version
+//! and an ID its response repeats, so a client can have several requests in
+//! flight on one connection and match up responses arriving out of order.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::events::{ExtensionSource, SyncCategory};
+
+/// Version of the protocol, bumped when a change would break older clients
+pub const IPC_VERSION: u32 = 2;
+
+/// A request from a client
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub version: u32,
+    pub id: u64,
+    #[serde(flatten)]
+    pub command: Command,
+}
+
+impl Request {
+    pub fn new(id: u64, command: Command) -> Self {
+        Self {
+            version: IPC_VERSION,
+            id,
+            command,
+        }
+    }
+}
+
+/// The part of a request read before its command, so requests from another
+/// version can be answered even if their command isn't understood
+#[derive(Debug, Deserialize)]
+pub struct Envelope {
+    pub version: u32,
+    #[serde(default)]
+    pub id: u64,
+}
+
+/// What a client asks the daemon to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum Command {
+    /// Report sync state
+    Status,
+    /// Recent sync sessions with peers, and when each device last caught up
+    SyncHistory,
+    /// List connected peers, with how each connection is carried when verbose
+    Peers {
+        verbose: bool,
+    },
+    /// List tabs waiting to be opened here
+    Tabs,
+    /// List sent tabs with their delivery status
+    SentTabs,
+    /// Queue a tab for another device
+    Send {
+        device: String,
+        url: String,
+        title: Option<String>,
+    },
+    /// Open a pending tab
+    Open {
+        tab_id: String,
+    },
+    /// Pause sync, or one category of it, for a number of seconds or until
+    /// resumed
+    Pause {
+        category: Option<SyncCategory>,
+        seconds: Option<i64>,
+    },
+    Resume {
+        category: Option<SyncCategory>,
+    },
+    /// Inspect, approve, or drop pending profile writes
+    Queue {
+        action: QueueAction,
+    },
+    Divergences,
+    /// Keep the `local` or `pack` change to a diverged item
+    ResolveDivergence {
+        category: String,
+        item: String,
+        keep: String,
+    },
+    ReadingList,
+    ReadingListAdd {
+        url: String,
+        title: Option<String>,
+    },
+    ReadingListRemove {
+        url: String,
+    },
+    /// Other devices' names
+    DeviceNames,
+    RenameDevice {
+        device: String,
+        name: String,
+    },
+    LabelDevice {
+        device: String,
+        emoji: Option<String>,
+        color: Option<String>,
+    },
+    /// Forget the peer a device is bound to, for a new p2p identity
+    UnbindDevice {
+        device: String,
+    },
+    /// Mark a device's key verified, if it's still the one on file
+    VerifyKey {
+        device_id: String,
+        key: String,
+    },
+    /// Synced extensions' IDs and names
+    Extensions,
+    /// Which devices have each synced extension installed
+    ExtensionMatrix {
+        missing: bool,
+    },
+    /// Install an extension from a local XPI file for the whole pack, with
+    /// where it was downloaded from if it was
+    InstallExtension {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        source: Option<ExtensionSource>,
+    },
+    /// Uninstall a synced extension from the whole pack
+    UninstallExtension {
+        id: String,
+    },
+    /// Import a `wolfpack export` file
+    Import {
+        path: PathBuf,
+    },
+    Gc,
+    /// Rebuild the materialized state, up to an event ID or timestamp
+    Replay {
+        until: Option<String>,
+    },
+    /// What changed between two event IDs or timestamps
+    Diff {
+        from: String,
+        to: Option<String>,
+    },
+    Report,
+    /// Stop the daemon, for `wolfpack daemon --replace`
+    Shutdown,
+    /// Keep the connection open for an `update` with the status and pending
+    /// tabs, and another, with the same ID, whenever either changes
+    Subscribe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueueAction {
+    List,
+    Apply,
+    Discard,
+}
+
+/// The daemon's answer to a request: what it did or found, or what went
+/// wrong
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    pub id: u64,
+    pub result: Result<Payload, IpcError>,
+}
+
+/// What a command found or did
+///
+/// Commands whose answer is only shown to people answer with a `Message`;
+/// those that clients read answer with a payload of their own, shown as
+/// text through `Display`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Payload {
+    Message {
+        text: String,
+    },
+    Status(DaemonStatus),
+    Tabs {
+        tabs: Vec<TabEntry>,
+    },
+    DeviceNames {
+        names: Vec<String>,
+    },
+    Extensions {
+        extensions: Vec<ExtensionEntry>,
+    },
+    /// Pushed to a subscribed client
+    Update {
+        status: Box<DaemonStatus>,
+        tabs: Vec<TabEntry>,
+    },
+}
+
+impl Payload {
+    pub fn message(text: impl ToString) -> Self {
+        Self::Message {
+            text: text.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Payload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Message { text } => f.write_str(text),
+            Self::Status(status) => status.fmt(f),
+            Self::Tabs { tabs } if tabs.is_empty() => f.write_str("No pending tabs"),
+            Self::Tabs { tabs } => write_lines(f, tabs),
+            Self::DeviceNames { names } => write_lines(f, names),
+            Self::Extensions { extensions } => write_lines(f, extensions),
+            Self::Update { status, tabs } => {
+                writeln!(f, "{}", status)?;
+                Self::Tabs { tabs: tabs.clone() }.fmt(f)
+            }
+        }
+    }
+}
+
+fn write_lines(f: &mut fmt::Formatter, items: &[impl fmt::Display]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            f.write_str("\n")?;
+        }
+        item.fmt(f)?;
+    }
+    Ok(())
+}
+
+/// The daemon's sync state, for `status`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DaemonStatus {
+    pub device_id: String,
+    /// The device's name with its label
+    pub device_label: String,
+    pub peers: usize,
+    /// Whether all of sync is paused, not just some categories
+    pub paused: bool,
+    /// How sync, or each paused category, is paused, like `sync paused
+    /// until 14:00`
+    pub pauses: Vec<String>,
+    pub observing: bool,
+    /// Times the engine's or swarm's queue was full
+    pub overflows: u64,
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Device {} ({}) - {} peers connected",
+            self.device_label, self.device_id, self.peers
+        )?;
+        if !self.pauses.is_empty() {
+            write!(f, " ({})", self.pauses.join(", "))?;
+        }
+        if self.observing {
+            f.write_str(" [observe mode]")?;
+        }
+        if self.overflows > 0 {
+            write!(f, " [queues full {} times]", self.overflows)?;
+        }
+        Ok(())
+    }
+}
+
+/// A tab waiting to be opened here
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TabEntry {
+    pub id: String,
+    pub url: String,
+    /// Name of the device it came from
+    pub from: String,
+    /// How many more times it was sent before being opened
+    pub duplicates: usize,
+}
+
+impl fmt::Display for TabEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.duplicates {
+            0 => write!(f, "{}: {} (from {})", self.id, self.url, self.from),
+            n => write!(
+                f,
+                "{}: {} (from {}, sent {} times)",
+                self.id,
+                self.url,
+                self.from,
+                n + 1
+            ),
+        }
+    }
+}
+
+/// A synced extension
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtensionEntry {
+    pub id: String,
+    pub name: String,
+}
+
+impl fmt::Display for ExtensionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.id, self.name)
+    }
+}
+
+/// Why the daemon couldn't carry out a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, thiserror::Error)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum IpcError {
+    #[error(
+        "The daemon speaks IPC version {daemon}, but this client speaks {client}; restart the daemon after upgrading"
+    )]
+    UnsupportedVersion { daemon: u32, client: u32 },
+    #[error("Bad request: {message}")]
+    BadRequest { message: String },
+    #[error("{message}")]
+    NotFound { message: String },
+    #[error("{message}")]
+    Failed { message: String },
+}
+
+impl IpcError {
+    pub fn bad_request(message: impl ToString) -> Self {
+        Self::BadRequest {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn not_found(message: impl ToString) -> Self {
+        Self::NotFound {
+            message: message.to_string(),
+        }
+    }
+
+    pub fn failed(message: impl ToString) -> Self {
+        Self::Failed {
+            message: message.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_roundtrip() {
+        let request = Request::new(
+            7,
+            Command::Pause {
+                category: Some(SyncCategory::Prefs),
+                seconds: Some(3600),
+            },
+        );
+        let line = serde_json::to_string(&request).unwrap();
+        assert_eq!(
+            line,
+            r#"{"version":2,"id":7,"command":"pause","category":"prefs","seconds":3600}"#
+        );
+        assert_eq!(serde_json::from_str::<Request>(&line).unwrap(), request);
+    }
+
+    #[test]
+    fn test_envelope_of_unknown_command() {
+        let line = r#"{"version":2,"id":3,"command":"teleport"}"#;
+        assert!(serde_json::from_str::<Request>(line).is_err());
+        let envelope: Envelope = serde_json::from_str(line).unwrap();
+        assert_eq!((envelope.version, envelope.id), (2, 3));
+    }
+
+    #[test]
+    fn test_response_roundtrip() {
+        let response = Response {
+            id: 3,
+            result: Err(IpcError::not_found("Tab not found")),
+        };
+        let line = serde_json::to_string(&response).unwrap();
+        assert_eq!(serde_json::from_str::<Response>(&line).unwrap(), response);
+        assert_eq!(response.result.unwrap_err().to_string(), "Tab not found");
+
+        let response = Response {
+            id: 4,
+            result: Ok(Payload::DeviceNames {
+                names: vec!["Laptop".to_string()],
+            }),
+        };
+        let line = serde_json::to_string(&response).unwrap();
+        assert_eq!(
+            line,
+            r#"{"id":4,"result":{"Ok":{"kind":"device_names","names":["Laptop"]}}}"#
+        );
+        assert_eq!(serde_json::from_str::<Response>(&line).unwrap(), response);
+    }
+
+    #[test]
+    fn test_payload_display() {
+        let mut status = DaemonStatus {
+            device_id: "device-a".to_string(),
+            device_label: "Laptop".to_string(),
+            peers: 2,
+            paused: false,
+            pauses: Vec::new(),
+            observing: false,
+            overflows: 0,
+        };
+        assert_eq!(
+            Payload::Status(status.clone()).to_string(),
+            "Device Laptop (device-a) - 2 peers connected"
+        );
+        status.pauses = vec!["prefs paused".to_string(), "tabs paused".to_string()];
+        status.observing = true;
+        assert_eq!(
+            status.to_string(),
+            "Device Laptop (device-a) - 2 peers connected \
+             (prefs paused, tabs paused) [observe mode]"
+        );
+
+        let tab = |id: &str, duplicates| TabEntry {
+            id: id.to_string(),
+            url: "https://example.com".to_string(),
+            from: "Desktop".to_string(),
+            duplicates,
+        };
+        let tabs = Payload::Tabs {
+            tabs: vec![tab("a", 0), tab("b", 1)],
+        };
+        assert_eq!(
+            tabs.to_string(),
+            "a: https://example.com (from Desktop)\n\
+             b: https://example.com (from Desktop, sent 2 times)"
+        );
+        let no_tabs = Payload::Tabs { tabs: Vec::new() };
+        assert_eq!(no_tabs.to_string(), "No pending tabs");
+
+        status.pauses.clear();
+        status.observing = false;
+        let update = Payload::Update {
+            status: Box::new(status),
+            tabs: Vec::new(),
+        };
+        assert_eq!(
+            update.to_string(),
+            "Device Laptop (device-a) - 2 peers connected\nNo pending tabs"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! Channels with two lanes, so small interactive messages don't queue behind
+//! bulk work
+//!
+//! The receiver always takes from the interactive lane first. A tab send or
+//! pairing response waits at most for the message being handled, not for
+//! every event exchange queued before it.
+//!
+//! Both lanes are bounded. A sender finding its lane full waits for room,
+//! and the sender counts how often that happens so a queue that can't keep
+//! up shows in `wolfpack status`.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+
+/// Which lane a message travels in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    /// Someone is waiting on it: tab sends, pairing, CLI requests
+    Interactive,
+    /// Event exchange, profile scans, and other batch work
+    Bulk,
+}
+
+/// A channel holding up to `capacity` messages in each lane
+pub fn channel<T>(capacity: usize) -> (LaneSender<T>, LaneReceiver<T>) {
+    let (interactive_tx, interactive_rx) = mpsc::channel(capacity);
+    let (bulk_tx, bulk_rx) = mpsc::channel(capacity);
+    (
+        LaneSender {
+            interactive: interactive_tx,
+            bulk: bulk_tx,
+            overflows: Arc::default(),
+        },
+        LaneReceiver {
+            interactive: interactive_rx,
+            bulk: bulk_rx,
+        },
+    )
+}
+
+#[derive(Debug)]
+pub struct LaneSender<T> {
+    interactive: mpsc::Sender<T>,
+    bulk: mpsc::Sender<T>,
+    overflows: Arc<AtomicU64>,
+}
+
+// Derived Clone would require T: Clone
+impl<T> Clone for LaneSender<T> {
+    fn clone(&self) -> Self {
+        Self {
+            interactive: self.interactive.clone(),
+            bulk: self.bulk.clone(),
+            overflows: self.overflows.clone(),
+        }
+    }
+}
+
+impl<T> LaneSender<T> {
+    /// Queue a message, waiting for room if its lane is full
+    pub async fn send(&self, lane: Lane, value: T) -> Result<(), SendError<T>> {
+        let sender = match lane {
+            Lane::Interactive => &self.interactive,
+            Lane::Bulk => &self.bulk,
+        };
+        match sender.try_send(value) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Closed(value)) => Err(SendError(value)),
+            Err(TrySendError::Full(value)) => {
+                self.overflows.fetch_add(1, Ordering::Relaxed);
+                sender.send(value).await
+            }
+        }
+    }
+
+    /// How many sends, through this sender or its clones, found their lane
+    /// full
+    pub fn overflows(&self) -> u64 {
+        self.overflows.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct LaneReceiver<T> {
+    interactive: mpsc::Receiver<T>,
+    bulk: mpsc::Receiver<T>,
+}
+
+impl<T> LaneReceiver<T> {
+    /// The next message, from the interactive lane while it has any, or
+    /// `None` once every sender is dropped
+    pub async fn recv(&mut self) -> Option<T> {
+        tokio::select! {
+            biased;
+            Some(value) = self.interactive.recv() => Some(value),
+            Some(value) = self.bulk.recv() => Some(value),
+            else => None,
+        }
+    }
+
+    /// [`recv`](Self::recv) for threads outside the async runtime
+    pub fn blocking_recv(&mut self) -> Option<T> {
+        futures::executor::block_on(self.recv())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interactive_lane_first() {
+        let (tx, mut rx) = channel(4);
+        futures::executor::block_on(async {
+            tx.send(Lane::Bulk, "events").await.unwrap();
+            tx.send(Lane::Bulk, "more events").await.unwrap();
+            tx.send(Lane::Interactive, "tab").await.unwrap();
+        });
+        drop(tx);
+
+        assert_eq!(rx.blocking_recv(), Some("tab"));
+        assert_eq!(rx.blocking_recv(), Some("events"));
+        assert_eq!(rx.blocking_recv(), Some("more events"));
+        assert_eq!(rx.blocking_recv(), None);
+    }
+
+    #[test]
+    fn test_overflows_counted() {
+        let (tx, mut rx) = channel(1);
+        futures::executor::block_on(tx.send(Lane::Interactive, 1)).unwrap();
+        // The bulk lane has its own room
+        futures::executor::block_on(tx.send(Lane::Bulk, 2)).unwrap();
+        assert_eq!(tx.overflows(), 0);
+
+        let sender = tx.clone();
+        let sending =
+            std::thread::spawn(move || futures::executor::block_on(sender.send(Lane::Bulk, 3)));
+        while tx.overflows() == 0 {
+            std::thread::yield_now();
+        }
+        assert_eq!(rx.blocking_recv(), Some(1));
+        assert_eq!(rx.blocking_recv(), Some(2));
+        assert_eq!(rx.blocking_recv(), Some(3));
+        sending.join().unwrap().unwrap();
+        assert_eq!(tx.overflows(), 1);
+    }
+}
@@ -4,10 +4,19 @@ pub mod crypto;
 pub mod daemon;
 pub mod events;
 pub mod extensions;
+pub mod ipc;
+pub mod lanes;
+pub mod logging;
 pub mod net;
 pub mod profile;
+pub mod redact;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod state;
+pub mod supervisor;
 pub mod sync;
+#[cfg(feature = "tray")]
+pub mod tray;
 
 pub use config::Config;
 pub use events::Event;
@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::io::Write;
+use tracing_subscriber::{EnvFilter, Layer, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Where the `flame` feature writes folded stacks, when set
+pub const FLAME_VAR: &str = "WOLFPACK_FLAME";
+
+/// Keeps profiling output going until it's dropped at exit
+pub struct LoggingGuard {
+    #[cfg(feature = "flame")]
+    _flame: Option<tracing_flame::FlushGuard<std::io::BufWriter<std::fs::File>>>,
+}
+
+/// Log to stderr as `RUST_LOG` says, and with the `flame` and `console`
+/// features, record spans for profiling
+///
+/// `RUST_LOG` only filters what's printed, and with `logging.redact`, what's
+/// printed is scrubbed of secrets. With `flame`, every wolfpack span is
+/// written as folded stacks to the file `WOLFPACK_FLAME` names, for
+/// `inferno-flamegraph` to draw. With `console`, tokio-console can attach
+/// while the daemon runs (this needs building with
+/// `RUSTFLAGS="--cfg tokio_unstable"`).
+pub fn init() -> Result<LoggingGuard> {
+    let registry = tracing_subscriber::registry().with(
+        tracing_subscriber::fmt::layer()
+            .with_writer(|| RedactedOutput)
+            .with_filter(EnvFilter::from_default_env()),
+    );
+
+    #[cfg(feature = "console")]
+    let registry = registry.with(console_subscriber::spawn());
+
+    #[cfg(feature = "flame")]
+    {
+        let (layer, flame) = match std::env::var_os(FLAME_VAR) {
+            Some(path) => {
+                let (layer, guard) = tracing_flame::FlameLayer::with_file(path)?;
+                let layer = layer.with_filter(EnvFilter::new("wolfpack=trace"));
+                (Some(layer), Some(guard))
+            }
+            None => (None, None),
+        };
+        registry.with(layer).init();
+        Ok(LoggingGuard { _flame: flame })
+    }
+
+    #[cfg(not(feature = "flame"))]
+    {
+        registry.init();
+        Ok(LoggingGuard {})
+    }
+}
+
+/// The log output, passed through `redact::text`
+struct RedactedOutput;
+
+impl Write for RedactedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        std::io::stdout().write_all(crate::redact::text(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stdout().flush()
+    }
+}
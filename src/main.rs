@@ -1,14 +1,42 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::{ArgValueCandidates, CompleteEnv};
 
 use wolfpack::cli;
 use wolfpack::config::Config;
-use wolfpack::daemon::run_daemon;
+use wolfpack::daemon::{DaemonOptions, run_daemon};
+
+/// Examples shown after `--help`, and in the man pages
+const EXAMPLES: &str = "\
+Examples:
+  wolfpack init --name laptop
+  wolfpack daemon
+  wolfpack pair --code 123456
+  wolfpack send example.com/article --to desktop
+  wolfpack pause --for 2h";
+
+const SEND_EXAMPLES: &str = "\
+Examples:
+  wolfpack send https://example.com --to desktop
+  wolfpack send --from-clipboard --to phone
+  echo example.com | wolfpack send --to desktop";
+
+const PAUSE_EXAMPLES: &str = "\
+Examples:
+  wolfpack pause
+  wolfpack pause --for 30m
+  wolfpack pause --category prefs --for 1d";
+
+const RESTORE_EXAMPLES: &str = "\
+Examples:
+  wolfpack restore-file user.js --list
+  wolfpack restore-file containers.json
+  wolfpack restore-file user.js --from 20240101T120000.000Z";
 
 #[derive(Parser)]
 #[command(name = "wolfpack")]
 #[command(about = "LibreWolf sync via Syncthing with E2E encryption")]
+#[command(after_help = EXAMPLES)]
 struct Cli {
     #[command(subcommand)]
     command: Commands,
@@ -25,6 +53,14 @@ enum Commands {
         /// LibreWolf profile directory (auto-detected if not specified)
         #[arg(short, long)]
         profile: Option<std::path::PathBuf>,
+
+        /// Read-only mode: materialize incoming events and report diffs, never write the profile
+        #[arg(long)]
+        observe: bool,
+
+        /// Shut down a daemon that's already running and take over from it
+        #[arg(long)]
+        replace: bool,
     },
 
     /// Initialize wolfpack
@@ -34,77 +70,481 @@ enum Commands {
         name: Option<String>,
     },
 
+    /// Restore this device's encryption key from its recovery code
+    Recover {
+        /// Recovery code printed by `wolfpack init` (quote it as one argument)
+        #[arg(long, required_unless_present = "show")]
+        code: Option<String>,
+
+        /// Replace a different key already on this device
+        #[arg(long, conflicts_with = "show")]
+        force: bool,
+
+        /// Print the recovery code for the current key instead
+        #[arg(long, conflicts_with = "code")]
+        show: bool,
+    },
+
     /// Send a tab to another device
+    #[command(after_help = SEND_EXAMPLES)]
     Send {
-        /// URL to send
-        url: String,
+        /// URL to send; read from stdin if omitted or `-`
+        #[arg(conflicts_with = "from_clipboard")]
+        url: Option<String>,
 
-        /// Target device name
-        #[arg(short, long)]
+        /// Target device name or ID
+        #[arg(short, long, add = ArgValueCandidates::new(cli::device_candidates))]
         to: String,
+
+        /// Send the URL on the clipboard
+        #[arg(long)]
+        from_clipboard: bool,
+    },
+
+    /// List tabs sent to this device
+    Tabs {
+        /// List tabs this device sent instead, with their delivery status
+        #[arg(long)]
+        sent: bool,
+    },
+
+    /// Save a page to the reading list shared by all devices
+    ReadLater {
+        /// URL to save
+        #[arg(required_unless_present = "list")]
+        url: Option<String>,
+
+        /// Title to show in the reading list
+        #[arg(long, conflicts_with = "remove")]
+        title: Option<String>,
+
+        /// Remove the URL from the reading list instead
+        #[arg(long)]
+        remove: bool,
+
+        /// Show the reading list
+        #[arg(long, conflicts_with_all = ["url", "remove"])]
+        list: bool,
     },
 
     /// List known devices
-    Devices,
+    Devices {
+        #[command(subcommand)]
+        command: Option<DevicesCommands>,
+    },
+
+    /// Show and verify device key fingerprints
+    Keys {
+        #[command(subcommand)]
+        command: KeysCommands,
+    },
 
     /// Pair with another device
     Pair {
         /// 6-digit pairing code to join an existing session
         #[arg(short, long)]
         code: Option<String>,
+
+        /// List devices advertising on the local network, and which are ready to pair
+        #[arg(long, conflicts_with = "code")]
+        nearby: bool,
     },
 
     /// Show sync status
-    Status,
+    Status {
+        /// Also show recent sync sessions and when each device last caught up
+        #[arg(long)]
+        history: bool,
+    },
+
+    /// Pause syncing (pairing and status stay available)
+    #[command(after_help = PAUSE_EXAMPLES)]
+    Pause {
+        /// Resume automatically after this long (e.g. 30m, 2h, 1d)
+        #[arg(long = "for", value_name = "DURATION")]
+        duration: Option<String>,
+
+        /// Only pause one category (extensions, containers, handlers, search, prefs, startpage, permissions, certificates, addresses, tabs, readinglist, devices)
+        #[arg(long)]
+        category: Option<String>,
+    },
+
+    /// Resume syncing after a pause
+    Resume {
+        /// Only resume one category (resumes everything if omitted)
+        #[arg(long)]
+        category: Option<String>,
+    },
 
     /// Manage synced extensions
     Extension {
         #[command(subcommand)]
         command: ExtensionCommands,
     },
+
+    /// Forget old delivered and opened tabs and compact the state database
+    Gc,
+
+    /// Rebuild the state database from the event log
+    ///
+    /// With --until, stops at a point in time and pauses sync so the state can
+    /// be inspected; `wolfpack resume` applies the later events.
+    Replay {
+        /// Last event to replay: an event ID, an RFC 3339 timestamp, or a date
+        #[arg(long)]
+        until: Option<String>,
+    },
+
+    /// Bundle the encrypted event history into an archive for cold storage
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Archive {
+        /// Archive to write (a zstd-compressed tar, e.g. pack-2024.tar.zst)
+        #[arg(short, long, required = true)]
+        output: Option<std::path::PathBuf>,
+
+        #[command(subcommand)]
+        command: Option<ArchiveCommands>,
+    },
+
+    /// Summarize event log size by device, the largest XPIs, events by type,
+    /// state database rows, and how fast the sync dir is growing
+    Report,
+
+    /// Show what changed in the synced state between two points in history
+    Diff {
+        /// Start point: an event ID, an RFC 3339 timestamp, or a date
+        #[arg(long)]
+        from: String,
+
+        /// End point, in the same forms (defaults to the latest event)
+        #[arg(long)]
+        to: Option<String>,
+    },
+
+    /// Inspect and approve pending profile writes
+    Queue {
+        #[command(subcommand)]
+        command: QueueCommands,
+    },
+
+    /// Pick a side for items changed here and in the pack while stopped
+    Divergences {
+        #[command(subcommand)]
+        command: DivergencesCommands,
+    },
+
+    /// Inspect peer-to-peer connections
+    Net {
+        #[command(subcommand)]
+        command: NetCommands,
+    },
+
+    /// Restore a profile file from a backup taken before wolfpack overwrote it
+    #[command(after_help = RESTORE_EXAMPLES)]
+    RestoreFile {
+        /// File relative to the profile (e.g. user.js, containers.json, extensions/<id>.xpi)
+        file: String,
+
+        /// Backup timestamp to restore (defaults to the latest)
+        #[arg(long)]
+        from: Option<String>,
+
+        /// List available backups instead of restoring
+        #[arg(long)]
+        list: bool,
+    },
+
+    /// Dump synced state (extensions, prefs, containers, search engines) in readable form
+    Export {
+        /// Output format
+        #[arg(long, default_value = "json", value_parser = ["json", "tar", "home-manager"])]
+        format: String,
+
+        /// File to write (prints to stdout if omitted)
+        #[arg(short, long)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Import state from an export, syncing it to the pack
+    Import {
+        /// Export file (json or tar)
+        path: std::path::PathBuf,
+    },
+
+    /// Work with the declarative profile manifest (`paths.manifest`)
+    Manifest {
+        #[command(subcommand)]
+        command: ManifestCommands,
+    },
+
+    /// Print a shell completion script (e.g. `source <(wolfpack completions bash)`)
+    Completions {
+        /// Shell to complete in
+        #[arg(value_parser = ["bash", "elvish", "fish", "powershell", "zsh"])]
+        shell: String,
+    },
+
+    /// Generate documentation from the command definitions
+    Docs {
+        #[command(subcommand)]
+        command: DocsCommands,
+    },
+
+    /// Show a system tray icon for the running daemon
+    #[cfg(feature = "tray")]
+    Tray,
+
+    /// Run a discovery server, which relays pairing between devices that
+    /// can't reach each other
+    #[cfg(feature = "server")]
+    DiscoveryServer {
+        /// Address to listen on
+        #[arg(long, default_value = "0.0.0.0:9779")]
+        bind: String,
+    },
+
+    /// Run a circuit relay for devices that can't reach each other directly
+    #[cfg(feature = "server")]
+    RelayServer {
+        /// Directory for the relay's identity, admin token, and allowlist
+        #[arg(long)]
+        data_dir: std::path::PathBuf,
+
+        /// Multiaddr to listen on for peers (repeatable)
+        #[arg(long, default_values = ["/ip4/0.0.0.0/tcp/4001", "/ip4/0.0.0.0/udp/4001/quic-v1"])]
+        listen: Vec<libp2p::Multiaddr>,
+
+        /// Public multiaddr peers reach the relay at (repeatable)
+        #[arg(long)]
+        external: Vec<libp2p::Multiaddr>,
+
+        /// Address for the admin API
+        #[arg(long, default_value = "127.0.0.1:9781")]
+        admin: std::net::SocketAddr,
+
+        /// Let any peer use the relay, ignoring the allowlist
+        #[arg(long)]
+        open: bool,
+
+        /// Most each peer may send and receive through the relay per month, in MiB
+        #[arg(long)]
+        monthly_mib: Option<u64>,
+
+        /// Most circuit time each peer may use per month, in minutes
+        #[arg(long)]
+        monthly_minutes: Option<u64>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ArchiveCommands {
+    /// Restore the event history in an archive into the sync dir
+    Import {
+        /// Archive made with `wolfpack archive --output`
+        path: std::path::PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ManifestCommands {
+    /// Validate the manifest and show where synced state has drifted from it
+    Check {
+        /// Manifest to check instead of the configured one
+        #[arg(long)]
+        file: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DevicesCommands {
+    /// Rename a device on every paired device
+    Rename {
+        /// Current name or ID of the device
+        #[arg(add = ArgValueCandidates::new(cli::device_candidates))]
+        device: String,
+
+        /// New name
+        name: String,
+    },
+
+    /// Label a device with an emoji and color on every paired device
+    Label {
+        /// Name or ID of the device
+        #[arg(add = ArgValueCandidates::new(cli::device_candidates))]
+        device: String,
+
+        /// Emoji shown before the device name (e.g. 🏠)
+        #[arg(long)]
+        emoji: Option<String>,
+
+        /// Color of the device name (blue, turquoise, green, yellow, orange, red, pink, purple)
+        #[arg(long)]
+        color: Option<String>,
+
+        /// Remove the device's label
+        #[arg(long, conflicts_with_all = ["emoji", "color"])]
+        clear: bool,
+    },
+
+    /// Forget the peer a device is bound to, after its p2p identity changed
+    Unbind {
+        /// Name or ID of the device
+        #[arg(add = ArgValueCandidates::new(cli::device_candidates))]
+        device: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum KeysCommands {
+    /// Print fingerprints of this device's key and paired devices' keys, as hex and words
+    Fingerprint {
+        /// Only show this device's fingerprint, by name or ID
+        #[arg(long, add = ArgValueCandidates::new(cli::device_candidates))]
+        device: Option<String>,
+    },
+
+    /// Mark a paired device's key as verified, after comparing its fingerprint on both devices
+    Verify {
+        /// Name or ID of the device
+        #[arg(add = ArgValueCandidates::new(cli::device_candidates))]
+        device: String,
+    },
 }
 
 #[derive(Subcommand)]
 enum ExtensionCommands {
-    /// List synced extensions
+    /// List synced extensions, and which devices have each installed
     List {
-        /// Show only extensions missing on this device
+        /// Show only extensions missing on some device in the pack (on this
+        /// device when the daemon isn't running)
         #[arg(long)]
         missing: bool,
     },
 
-    /// Install an extension from a signed XPI file
+    /// Install an extension from an XPI file, an HTTPS URL, or a git
+    /// repository to build it from
     Install {
-        /// Path to XPI file
-        path: std::path::PathBuf,
+        /// Path to an XPI file, an https:// URL to download one from, or the
+        /// URL of a git repository
+        source: String,
+
+        /// Refuse the XPI unless it has this SHA-256 (hex)
+        #[arg(long)]
+        sha256: Option<String>,
+
+        /// Tag, branch, or commit of the git repository to build
+        #[arg(long = "ref")]
+        ref_spec: Option<String>,
+
+        /// Build the git repository with this command, in place of the
+        /// detected build
+        #[arg(long)]
+        build: Option<String>,
+    },
+
+    /// Install every extension in a list from addons.mozilla.org
+    ///
+    /// The list has an AMO slug, extension ID, or AMO link per line (`#`
+    /// starts a comment), or is a JSON array of them.
+    ImportList {
+        /// Path to the list
+        file: std::path::PathBuf,
     },
 
     /// Uninstall an extension
     Uninstall {
         /// Extension ID
+        #[arg(add = ArgValueCandidates::new(cli::extension_candidates))]
         id: String,
     },
 }
 
+#[derive(Subcommand)]
+enum QueueCommands {
+    /// Show pending writes as diffs against the profile
+    List,
+
+    /// Apply pending writes now (browser must be closed)
+    Apply,
+
+    /// Drop pending writes without applying them
+    Discard,
+}
+
+#[derive(Subcommand)]
+enum DivergencesCommands {
+    /// Show held items with both changes
+    List,
+
+    /// Keep one change: local sends the profile's, pack writes the pack's
+    Resolve {
+        /// Category of the item, like prefs
+        category: String,
+
+        /// The item, like a pref's name
+        item: String,
+
+        /// Which change to keep
+        #[arg(value_parser = ["local", "pack"])]
+        keep: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum NetCommands {
+    /// List connected peers
+    Peers {
+        /// Show each connection's transport, encryption, protocols, and paired device
+        #[arg(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DocsCommands {
+    /// Write a man page for wolfpack and each of its subcommands
+    Man {
+        /// Directory to write the pages to
+        #[arg(short, long, default_value = ".")]
+        out: std::path::PathBuf,
+    },
+}
+
 #[tokio::main]
 #[allow(clippy::too_many_lines)] // CLI entry point with command routing
 async fn main() -> Result<()> {
-    tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
-        .with(tracing_subscriber::EnvFilter::from_default_env())
-        .init();
+    // Answers completion requests from the script `wolfpack completions`
+    // prints, before anything else writes to stdout
+    CompleteEnv::with_factory(Cli::command)
+        .var(cli::COMPLETE_VAR)
+        .complete();
+
+    let _logging = wolfpack::logging::init()?;
 
     let cli = Cli::parse();
 
     let config_path = cli.config.unwrap_or_else(Config::default_path);
+    cli::use_config(&config_path);
+    if let Ok(config) = Config::load(&config_path) {
+        wolfpack::redact::configure(&config.logging);
+    }
 
     match cli.command {
-        Commands::Daemon { profile } => {
+        Commands::Daemon {
+            profile,
+            observe,
+            replace,
+        } => {
             let mut config = Config::load(&config_path)?;
             if let Some(profile_path) = profile {
                 config.paths.profile = Some(profile_path);
             }
-            run_daemon(config).await?;
+            let options = DaemonOptions {
+                observe,
+                config_path: Some(config_path),
+                replace,
+            };
+            run_daemon(config, options).await?;
         }
 
         Commands::Init { name } => {
@@ -117,35 +557,196 @@ async fn main() -> Result<()> {
             println!("Device ID: {}", config.device.id);
             println!("Device name: {}", config.device.name);
             println!("Config saved to: {}", config_path.display());
+            println!();
+            cli::show_recovery_code()?;
         }
 
-        Commands::Send { url, to } => {
-            cli::send_tab(&url, &to)?;
+        Commands::Recover { code, force, .. } => match code {
+            Some(code) => cli::recover_key(&code, force)?,
+            None => cli::show_recovery_code()?,
+        },
+
+        Commands::Send {
+            url,
+            to,
+            from_clipboard,
+        } => {
+            cli::send_tab(url.as_deref(), &to, from_clipboard)?;
+        }
+
+        Commands::Tabs { sent } => {
+            cli::list_tabs(sent)?;
+        }
+
+        Commands::ReadLater {
+            url, title, remove, ..
+        } => match url {
+            Some(url) if remove => cli::remove_from_reading_list(&url)?,
+            Some(url) => cli::read_later(&url, title.as_deref())?,
+            None => cli::list_reading_list()?,
+        },
+
+        Commands::Devices { command } => match command {
+            Some(DevicesCommands::Rename { device, name }) => cli::rename_device(&device, &name)?,
+            Some(DevicesCommands::Label {
+                device,
+                emoji,
+                color,
+                clear,
+            }) => {
+                if !clear && emoji.is_none() && color.is_none() {
+                    anyhow::bail!("Give --emoji and/or --color, or --clear to remove the label");
+                }
+                cli::label_device(&device, emoji.as_deref(), color.as_deref())?;
+            }
+            Some(DevicesCommands::Unbind { device }) => cli::unbind_device(&device)?,
+            None => cli::list_devices()?,
+        },
+
+        Commands::Keys { command } => match command {
+            KeysCommands::Fingerprint { device } => cli::show_fingerprints(device.as_deref())?,
+            KeysCommands::Verify { device } => cli::verify_key(&device)?,
+        },
+
+        Commands::Pair { code, nearby } => {
+            cli::pair_device(&config_path, code.as_deref(), nearby).await?;
         }
 
-        Commands::Devices => {
-            cli::list_devices()?;
+        Commands::Status { history } => {
+            cli::show_status(history)?;
         }
 
-        Commands::Pair { code } => {
-            cli::pair_device(&config_path, code.as_deref()).await?;
+        Commands::Pause { duration, category } => {
+            cli::pause_sync(category.as_deref(), duration.as_deref())?;
         }
 
-        Commands::Status => {
-            cli::show_status()?;
+        Commands::Resume { category } => {
+            cli::resume_sync(category.as_deref())?;
         }
 
         Commands::Extension { command } => match command {
             ExtensionCommands::List { missing } => {
                 cli::list_extensions(&config_path, missing)?;
             }
-            ExtensionCommands::Install { path } => {
-                cli::install_extension(&path, &config_path)?;
+            ExtensionCommands::Install {
+                source,
+                sha256,
+                ref_spec,
+                build,
+            } => {
+                let git = cli::GitOptions { ref_spec, build };
+                cli::install_extension(&config_path, &source, sha256.as_deref(), git).await?;
+            }
+            ExtensionCommands::ImportList { file } => {
+                cli::import_extension_list(&config_path, &file).await?;
             }
             ExtensionCommands::Uninstall { id } => {
-                cli::uninstall_extension(&id, &config_path)?;
+                cli::uninstall_extension(&id)?;
+            }
+        },
+
+        Commands::Queue { command } => match command {
+            QueueCommands::List => cli::list_queue()?,
+            QueueCommands::Apply => cli::apply_queue()?,
+            QueueCommands::Discard => cli::discard_queue()?,
+        },
+
+        Commands::Divergences { command } => match command {
+            DivergencesCommands::List => cli::list_divergences()?,
+            DivergencesCommands::Resolve {
+                category,
+                item,
+                keep,
+            } => cli::resolve_divergence(&category, &item, &keep)?,
+        },
+
+        Commands::Gc => {
+            cli::collect_garbage()?;
+        }
+
+        Commands::Replay { until } => {
+            cli::replay_events(until.as_deref())?;
+        }
+
+        Commands::Archive { output, command } => match (command, output) {
+            (Some(ArchiveCommands::Import { path }), _) => {
+                cli::restore_archive(&config_path, &path)?
+            }
+            (None, Some(output)) => cli::create_archive(&config_path, &output)?,
+            (None, None) => anyhow::bail!("Give --output FILE, or `archive import FILE`"),
+        },
+
+        Commands::Report => {
+            cli::show_report()?;
+        }
+
+        Commands::Diff { from, to } => {
+            cli::diff_history(&from, to.as_deref())?;
+        }
+
+        Commands::Net { command } => match command {
+            NetCommands::Peers { verbose } => cli::list_peers(verbose)?,
+        },
+
+        Commands::RestoreFile { file, from, list } => {
+            cli::restore_file(&file, from.as_deref(), list, &config_path)?;
+        }
+
+        Commands::Export { format, out } => {
+            cli::export_state(&config_path, &format, out.as_deref())?;
+        }
+
+        Commands::Import { path } => {
+            cli::import_state(&path)?;
+        }
+
+        Commands::Manifest { command } => match command {
+            ManifestCommands::Check { file } => {
+                cli::check_manifest(&config_path, file.as_deref())?;
             }
         },
+
+        Commands::Completions { shell } => {
+            cli::print_completions(&shell, &Cli::command())?;
+        }
+
+        Commands::Docs { command } => match command {
+            DocsCommands::Man { out } => cli::write_man_pages(Cli::command(), &out)?,
+        },
+
+        #[cfg(feature = "tray")]
+        Commands::Tray => {
+            wolfpack::tray::run_tray().await?;
+        }
+
+        #[cfg(feature = "server")]
+        Commands::DiscoveryServer { bind } => {
+            wolfpack::server::run_discovery_server(&bind).await?;
+        }
+
+        #[cfg(feature = "server")]
+        Commands::RelayServer {
+            data_dir,
+            listen,
+            external,
+            admin,
+            open,
+            monthly_mib,
+            monthly_minutes,
+        } => {
+            wolfpack::server::run_relay_server(wolfpack::server::RelayServerOptions {
+                listen,
+                external,
+                data_dir,
+                admin_bind: admin,
+                open,
+                limits: wolfpack::server::UsageLimits {
+                    bytes: monthly_mib.map(|mib| mib.saturating_mul(1024 * 1024)),
+                    circuit_seconds: monthly_minutes.map(|minutes| minutes.saturating_mul(60)),
+                },
+            })
+            .await?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,293 @@
+use anyhow::{Result, bail};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD as BASE64};
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+use crate::crypto;
+
+/// DNS-SD service type wolfpack devices advertise themselves under
+pub const SERVICE_TYPE: &str = "_wolfpack._tcp.local.";
+
+/// Most bytes of device ID and name sealed into an announcement, so it fits
+/// in a TXT record string (255 bytes) after the nonce, tag, and base64
+const MAX_SEALED_LEN: usize = 140;
+
+/// A wolfpack device advertising itself on the local network
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NearbyDevice {
+    pub device_id: String,
+    pub device_name: String,
+    /// Whether the device has a pairing session open
+    pub pairing: bool,
+    pub addresses: Vec<String>,
+}
+
+impl NearbyDevice {
+    /// The device a service advertises, if it names one in the clear or
+    /// sealed with this pack's announcement key
+    fn from_service(info: &ServiceInfo, key: &[u8; 32]) -> Option<Self> {
+        let mut addresses: Vec<String> = info
+            .get_addresses()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        addresses.sort();
+
+        let (device_id, device_name) = match info.get_property_val_str("sealed") {
+            Some(sealed) => open_device(sealed, key)?,
+            None => (
+                info.get_property_val_str("id")?.to_string(),
+                info.get_property_val_str("name")?.to_string(),
+            ),
+        };
+        Some(Self {
+            device_id,
+            device_name,
+            pairing: info.get_property_val_str("pairing") == Some("1"),
+            addresses,
+        })
+    }
+}
+
+/// Advertises this device as a `_wolfpack._tcp` service, with whether it's
+/// accepting pairing, and tracks the other devices advertising nearby
+///
+/// This is separate from libp2p's mDNS discovery, which only carries peer
+/// IDs: the service lets `wolfpack pair` and the extension show which
+/// devices are waiting to be paired.
+///
+/// The device ID and name are only advertised in the clear while a pairing
+/// session is open, for the device joining; otherwise the service goes by a
+/// random name and seals them with the pack's announcement key, so only
+/// devices in the pack can tell which device it is.
+#[derive(Clone)]
+pub struct ServiceAdvertiser {
+    daemon: ServiceDaemon,
+    device_id: String,
+    device_name: String,
+    /// Name of the sealed service, random each time the daemon starts
+    instance: String,
+    key: [u8; 32],
+    port: u16,
+    pairing: Arc<AtomicBool>,
+    nearby: Arc<RwLock<HashMap<String, NearbyDevice>>>,
+}
+
+impl ServiceAdvertiser {
+    /// Start advertising and browsing for other devices
+    pub fn start(device_id: &str, device_name: &str, port: u16, key: [u8; 32]) -> Result<Self> {
+        let advertiser = Self {
+            daemon: ServiceDaemon::new()?,
+            device_id: device_id.to_string(),
+            device_name: device_name.to_string(),
+            instance: hex::encode(rand::random::<[u8; 8]>()),
+            key,
+            port,
+            pairing: Arc::new(AtomicBool::new(false)),
+            nearby: Arc::new(RwLock::new(HashMap::new())),
+        };
+        advertiser.register(false)?;
+
+        let events = advertiser.daemon.browse(SERVICE_TYPE)?;
+        let nearby = advertiser.nearby.clone();
+        let own_id = advertiser.device_id.clone();
+        let key = advertiser.key;
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv_async().await {
+                match event {
+                    ServiceEvent::ServiceResolved(info) => {
+                        let Some(device) = NearbyDevice::from_service(&info, &key) else {
+                            continue;
+                        };
+                        if device.device_id != own_id {
+                            debug!(device = %device.device_name, "Nearby device advertised");
+                            nearby
+                                .write()
+                                .await
+                                .insert(info.get_fullname().to_string(), device);
+                        }
+                    }
+                    ServiceEvent::ServiceRemoved(_, fullname) => {
+                        nearby.write().await.remove(&fullname);
+                    }
+                    _ => {}
+                }
+            }
+        });
+
+        Ok(advertiser)
+    }
+
+    /// Update whether the advertisement says a pairing session is open
+    pub fn set_pairing(&self, pairing: bool) {
+        if self.pairing.swap(pairing, Ordering::Relaxed) == pairing {
+            return;
+        }
+        if let Err(e) = self.register(pairing) {
+            warn!("Failed to update pairing advertisement: {}", e);
+        }
+    }
+
+    /// Devices currently advertising on the local network
+    pub async fn nearby(&self) -> Vec<NearbyDevice> {
+        let mut devices: Vec<NearbyDevice> = self.nearby.read().await.values().cloned().collect();
+        devices.sort_by(|a, b| a.device_name.cmp(&b.device_name));
+        devices
+    }
+
+    /// Withdraw the advertisement so other devices drop this one right away
+    pub fn shutdown(&self) {
+        let _ = self.daemon.shutdown();
+    }
+
+    /// Advertise in the clear while pairing and sealed otherwise, withdrawing
+    /// the other advertisement
+    fn register(&self, pairing: bool) -> Result<()> {
+        let clear = service_info(&self.device_id, &self.device_name, self.port)?;
+        let sealed = sealed_service_info(
+            &self.instance,
+            &self.device_id,
+            &self.device_name,
+            self.port,
+            &self.key,
+        )?;
+        let (advertised, withdrawn) = if pairing {
+            (clear, sealed)
+        } else {
+            (sealed, clear)
+        };
+        // Nothing to withdraw when first advertising
+        let _ = self.daemon.unregister(withdrawn.get_fullname());
+        self.daemon.register(advertised)?;
+        Ok(())
+    }
+}
+
+/// The advertisement while pairing, naming the device in the clear
+fn service_info(device_id: &str, device_name: &str, port: u16) -> Result<ServiceInfo> {
+    let properties = [("id", device_id), ("name", device_name), ("pairing", "1")];
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        device_id,
+        &format!("{}.local.", device_id),
+        "",
+        port,
+        &properties[..],
+    )?;
+    Ok(info.enable_addr_auto())
+}
+
+/// The advertisement outside pairing, under a name that says nothing about
+/// the device and with its ID and name sealed
+fn sealed_service_info(
+    instance: &str,
+    device_id: &str,
+    device_name: &str,
+    port: u16,
+    key: &[u8; 32],
+) -> Result<ServiceInfo> {
+    let sealed = seal_device(device_id, device_name, key)?;
+    let properties = [("sealed", sealed.as_str()), ("pairing", "0")];
+    let info = ServiceInfo::new(
+        SERVICE_TYPE,
+        instance,
+        &format!("{}.local.", instance),
+        "",
+        port,
+        &properties[..],
+    )?;
+    Ok(info.enable_addr_auto())
+}
+
+/// A device's ID and name, sealed with the announcement key, as base64
+///
+/// Names too long to fit are cut short.
+fn seal_device(device_id: &str, device_name: &str, key: &[u8; 32]) -> Result<String> {
+    if device_id.len() >= MAX_SEALED_LEN {
+        bail!("Device ID {} is too long to advertise", device_id);
+    }
+    let mut plaintext = format!("{}\n{}", device_id, device_name);
+    while plaintext.len() > MAX_SEALED_LEN {
+        plaintext.pop();
+    }
+    Ok(BASE64.encode(crypto::seal(key, plaintext.as_bytes())?))
+}
+
+/// The device ID and name in a sealed announcement, if it was sealed with
+/// this key
+fn open_device(sealed: &str, key: &[u8; 32]) -> Option<(String, String)> {
+    let bytes = BASE64.decode(sealed).ok()?;
+    let plaintext = String::from_utf8(crypto::open(key, &bytes).ok()?).ok()?;
+    let (device_id, device_name) = plaintext.split_once('\n')?;
+    Some((device_id.to_string(), device_name.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn test_service_roundtrip() {
+        let info = service_info("device-a", "laptop", 4001).unwrap();
+        assert_eq!(info.get_fullname(), "device-a._wolfpack._tcp.local.");
+
+        let device = NearbyDevice::from_service(&info, &KEY).unwrap();
+        assert_eq!(device.device_id, "device-a");
+        assert_eq!(device.device_name, "laptop");
+        assert!(device.pairing);
+    }
+
+    #[test]
+    fn test_sealed_service() {
+        let info = sealed_service_info("0a1b2c3d", "device-a", "laptop", 4001, &KEY).unwrap();
+        assert_eq!(info.get_fullname(), "0a1b2c3d._wolfpack._tcp.local.");
+        assert!(info.get_property_val_str("id").is_none());
+        assert!(info.get_property_val_str("name").is_none());
+
+        let device = NearbyDevice::from_service(&info, &KEY).unwrap();
+        assert_eq!(device.device_id, "device-a");
+        assert_eq!(device.device_name, "laptop");
+        assert!(!device.pairing);
+
+        // Devices outside the pack can't tell which device it is
+        assert_eq!(NearbyDevice::from_service(&info, &[8u8; 32]), None);
+
+        // Sealed afresh each time, so announcements don't repeat
+        let again = sealed_service_info("0a1b2c3d", "device-a", "laptop", 4001, &KEY).unwrap();
+        assert_ne!(
+            again.get_property_val_str("sealed"),
+            info.get_property_val_str("sealed")
+        );
+    }
+
+    #[test]
+    fn test_seal_cuts_long_names() {
+        let name = "ü".repeat(200);
+        let sealed = seal_device("device-a", &name, &KEY).unwrap();
+        assert!("sealed=".len() + sealed.len() <= 255);
+        let (device_id, device_name) = open_device(&sealed, &KEY).unwrap();
+        assert_eq!(device_id, "device-a");
+        assert!(name.starts_with(&device_name));
+    }
+
+    #[test]
+    fn test_service_without_device_is_ignored() {
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            "other",
+            "other.local.",
+            "192.168.1.2",
+            4001,
+            None::<std::collections::HashMap<String, String>>,
+        )
+        .unwrap();
+        assert_eq!(NearbyDevice::from_service(&info, &KEY), None);
+    }
+}
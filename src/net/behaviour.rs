@@ -4,22 +4,27 @@ use libp2p::{
 };
 use std::time::Duration;
 
-use super::protocol::{PROTOCOL_NAME, SyncCodec};
+use super::gate::PeerGate;
+use super::protocol::{SyncCodec, sync_protocols};
 
 /// Combined network behaviour for wolfpack
 #[derive(NetworkBehaviour)]
 pub struct WolfpackBehaviour {
+    /// Strict peer gate, consulted before any other behaviour accepts a
+    /// connection
+    pub gate: PeerGate,
+
     /// mDNS for local network discovery (optional)
     pub mdns: Toggle<mdns::tokio::Behaviour>,
 
-    /// Kademlia DHT for internet-wide discovery
-    pub kademlia: kad::Behaviour<kad::store::MemoryStore>,
+    /// Kademlia DHT for internet-wide discovery (absent in LAN-only mode)
+    pub kademlia: Toggle<kad::Behaviour<kad::store::MemoryStore>>,
 
-    /// Relay client for NAT traversal
-    pub relay_client: relay::client::Behaviour,
+    /// Relay client for NAT traversal (absent in LAN-only mode)
+    pub relay_client: Toggle<relay::client::Behaviour>,
 
-    /// DCUtR for direct connection upgrade through relay
-    pub dcutr: dcutr::Behaviour,
+    /// DCUtR for direct connection upgrade through relay (absent in LAN-only mode)
+    pub dcutr: Toggle<dcutr::Behaviour>,
 
     /// Identify protocol for peer metadata exchange
     pub identify: identify::Behaviour,
@@ -32,10 +37,16 @@ pub struct WolfpackBehaviour {
 }
 
 impl WolfpackBehaviour {
+    /// Build the behaviour, without the relay client in LAN-only mode
+    ///
+    /// Kademlia and DCUtR are only included alongside the relay client, so
+    /// a LAN-only swarm has nothing that could reach beyond the local network.
     pub fn new(
         local_key: &libp2p::identity::Keypair,
-        relay_client: relay::client::Behaviour,
+        relay_client: Option<relay::client::Behaviour>,
         enable_mdns: bool,
+        compression: bool,
+        gate: PeerGate,
     ) -> Self {
         let local_peer_id = local_key.public().to_peer_id();
 
@@ -49,10 +60,15 @@ impl WolfpackBehaviour {
             Toggle::from(None)
         };
 
+        let wan = relay_client.is_some();
+
         // Kademlia DHT
-        let store = kad::store::MemoryStore::new(local_peer_id);
-        let mut kademlia = kad::Behaviour::new(local_peer_id, store);
-        kademlia.set_mode(Some(kad::Mode::Client));
+        let kademlia = wan.then(|| {
+            let store = kad::store::MemoryStore::new(local_peer_id);
+            let mut kademlia = kad::Behaviour::new(local_peer_id, store);
+            kademlia.set_mode(Some(kad::Mode::Client));
+            kademlia
+        });
 
         // Identify protocol
         let identify = identify::Behaviour::new(identify::Config::new(
@@ -64,22 +80,53 @@ impl WolfpackBehaviour {
         let ping = ping::Behaviour::new(ping::Config::new().with_interval(Duration::from_secs(30)));
 
         // DCUtR for hole punching
-        let dcutr = dcutr::Behaviour::new(local_peer_id);
+        let dcutr = wan.then(|| dcutr::Behaviour::new(local_peer_id));
 
-        // Sync request-response protocol
+        // Sync request-response protocol, compressed if both peers offer it
         let sync = request_response::Behaviour::new(
-            [(PROTOCOL_NAME, request_response::ProtocolSupport::Full)],
+            sync_protocols(compression)
+                .into_iter()
+                .map(|protocol| (protocol, request_response::ProtocolSupport::Full)),
             request_response::Config::default().with_request_timeout(Duration::from_secs(30)),
         );
 
         Self {
+            gate,
             mdns,
-            kademlia,
-            relay_client,
-            dcutr,
+            kademlia: Toggle::from(kademlia),
+            relay_client: Toggle::from(relay_client),
+            dcutr: Toggle::from(dcutr),
             identify,
             ping,
             sync,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::identity::Keypair;
+
+    #[test]
+    fn test_lan_only_behaviour_has_no_wan_protocols() {
+        let key = Keypair::generate_ed25519();
+        let behaviour = WolfpackBehaviour::new(&key, None, false, true, PeerGate::default());
+
+        assert!(!behaviour.relay_client.is_enabled());
+        assert!(!behaviour.dcutr.is_enabled());
+        assert!(!behaviour.kademlia.is_enabled());
+    }
+
+    #[test]
+    fn test_wan_behaviour() {
+        let key = Keypair::generate_ed25519();
+        let (_, relay_client) = relay::client::new(key.public().to_peer_id());
+        let behaviour =
+            WolfpackBehaviour::new(&key, Some(relay_client), false, true, PeerGate::default());
+
+        assert!(behaviour.relay_client.is_enabled());
+        assert!(behaviour.dcutr.is_enabled());
+        assert!(behaviour.kademlia.is_enabled());
+    }
+}
@@ -0,0 +1,251 @@
+//! UDP broadcast discovery, for local networks that filter mDNS
+//!
+//! Each device sends a beacon every [`BEACON_INTERVAL`] to the broadcast
+//! address of each of its IPv4 interfaces, and with `net.broadcast_scan` to
+//! every address in their subnets as well. A beacon carries the node's peer
+//! ID and the ports it listens on, sealed with the pack's announcement key:
+//! devices in the pack dial the sender at the address the beacon came
+//! from, and anyone else learns nothing from it.
+
+use anyhow::{Context, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::debug;
+
+use crate::crypto;
+
+/// How often beacons are sent
+pub const BEACON_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What a beacon starts with, ahead of the sealed part
+const BEACON_MAGIC: &[u8] = b"WPB1";
+
+/// Largest beacon read
+const MAX_BEACON: usize = 1024;
+
+/// Subnets larger than this prefix are only scanned in the /24 around the
+/// interface's address
+const MIN_SCAN_PREFIX: u32 = 24;
+
+/// How broadcast discovery is set up (`net.broadcast_*`)
+#[derive(Debug, Clone)]
+pub struct BroadcastOptions {
+    pub port: u16,
+    /// Send beacons to every address in the local subnets too
+    pub scan: bool,
+    /// The pack's announcement key, beacons are sealed with
+    pub key: [u8; 32],
+}
+
+/// A socket sending and receiving beacons
+pub struct Broadcaster {
+    socket: UdpSocket,
+    options: BroadcastOptions,
+}
+
+impl Broadcaster {
+    pub async fn bind(options: BroadcastOptions) -> Result<Self> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, options.port))
+            .await
+            .with_context(|| format!("Failed to bind UDP port {}", options.port))?;
+        socket.set_broadcast(true)?;
+        Ok(Self { socket, options })
+    }
+
+    /// Send a beacon for the addresses this node listens on
+    #[allow(clippy::cognitive_complexity)] // Loop with logging
+    pub async fn announce(&self, peer_id: &PeerId, listen_addrs: &[Multiaddr]) {
+        let beacon = match seal_beacon(peer_id, listen_addrs, &self.options.key) {
+            Ok(beacon) => beacon,
+            Err(e) => {
+                debug!("Failed to seal beacon: {}", e);
+                return;
+            }
+        };
+        for target in targets(self.options.port, self.options.scan, &ipv4_interfaces()) {
+            if let Err(e) = self.socket.send_to(&beacon, target).await {
+                debug!("Failed to send beacon to {}: {}", target, e);
+            }
+        }
+    }
+
+    /// The next beacon from a pack device: its peer ID and addresses
+    pub async fn recv(&self) -> (PeerId, Vec<Multiaddr>) {
+        let mut buf = [0u8; MAX_BEACON];
+        loop {
+            match self.socket.recv_from(&mut buf).await {
+                Ok((len, from)) => {
+                    if let Some(found) = open_beacon(&buf[..len], from.ip(), &self.options.key) {
+                        return found;
+                    }
+                }
+                Err(e) => {
+                    debug!("Failed to receive beacon: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+}
+
+/// A beacon for a node: its peer ID and the listen addresses without their
+/// IPs, one per line, sealed
+fn seal_beacon(peer_id: &PeerId, listen_addrs: &[Multiaddr], key: &[u8; 32]) -> Result<Vec<u8>> {
+    let mut lines = vec![peer_id.to_string()];
+    for addr in listen_addrs {
+        let mut protocols = addr.iter();
+        if !matches!(protocols.next(), Some(Protocol::Ip4(_))) {
+            continue;
+        }
+        let suffix: Multiaddr = protocols
+            .take_while(|p| !matches!(p, Protocol::P2p(_) | Protocol::P2pCircuit))
+            .collect();
+        let suffix = suffix.to_string();
+        if !suffix.is_empty() && !lines.contains(&suffix) {
+            lines.push(suffix);
+        }
+    }
+    Ok([
+        BEACON_MAGIC,
+        &crypto::seal(key, lines.join("\n").as_bytes())?,
+    ]
+    .concat())
+}
+
+/// The peer and the addresses to dial it at in a beacon received from
+/// `from`, if it was sealed with this key
+fn open_beacon(beacon: &[u8], from: IpAddr, key: &[u8; 32]) -> Option<(PeerId, Vec<Multiaddr>)> {
+    let sealed = beacon.strip_prefix(BEACON_MAGIC)?;
+    let plaintext = String::from_utf8(crypto::open(key, sealed).ok()?).ok()?;
+    let mut lines = plaintext.lines();
+    let peer_id: PeerId = lines.next()?.parse().ok()?;
+    let IpAddr::V4(ip) = from else {
+        return None;
+    };
+    let addrs = lines
+        .filter_map(|suffix| suffix.parse::<Multiaddr>().ok())
+        .map(|suffix| {
+            std::iter::once(Protocol::Ip4(ip))
+                .chain(suffix.iter())
+                .chain(std::iter::once(Protocol::P2p(peer_id)))
+                .collect()
+        })
+        .collect();
+    Some((peer_id, addrs))
+}
+
+/// Where beacons go: each interface's broadcast address and, scanning, each
+/// address in its subnet but its own
+fn targets(port: u16, scan: bool, interfaces: &[if_addrs::Ifv4Addr]) -> Vec<SocketAddr> {
+    let mut targets = vec![SocketAddr::from((Ipv4Addr::BROADCAST, port))];
+    for interface in interfaces.iter().filter(|i| !i.ip.is_loopback()) {
+        let mask = u32::from(interface.netmask);
+        let network = u32::from(interface.ip) & mask;
+        let broadcast = interface
+            .broadcast
+            .unwrap_or(Ipv4Addr::from(network | !mask));
+        targets.push(SocketAddr::from((broadcast, port)));
+
+        if scan {
+            let scanned_mask = mask.max(u32::MAX << (32 - MIN_SCAN_PREFIX));
+            let scanned = u32::from(interface.ip) & scanned_mask;
+            let hosts = (!scanned_mask).saturating_sub(1);
+            targets.extend(
+                (1..=hosts)
+                    .map(|host| Ipv4Addr::from(scanned | host))
+                    .filter(|ip| *ip != interface.ip)
+                    .map(|ip| SocketAddr::from((ip, port))),
+            );
+        }
+    }
+    targets.dedup();
+    targets
+}
+
+fn ipv4_interfaces() -> Vec<if_addrs::Ifv4Addr> {
+    if_addrs::get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .filter_map(|interface| match interface.addr {
+                    if_addrs::IfAddr::V4(addr) => Some(addr),
+                    if_addrs::IfAddr::V6(_) => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7u8; 32];
+
+    #[test]
+    fn test_beacon_roundtrip() {
+        let peer_id = PeerId::random();
+        let listen_addrs: Vec<Multiaddr> = vec![
+            "/ip4/192.168.1.20/tcp/4001".parse().unwrap(),
+            "/ip4/10.0.0.5/tcp/4001".parse().unwrap(),
+            "/ip4/192.168.1.20/udp/4001/quic-v1".parse().unwrap(),
+            "/ip6/::1/tcp/4001".parse().unwrap(),
+        ];
+        let beacon = seal_beacon(&peer_id, &listen_addrs, &KEY).unwrap();
+
+        let from = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        let (found, addrs) = open_beacon(&beacon, from, &KEY).unwrap();
+        assert_eq!(found, peer_id);
+        let addrs: Vec<String> = addrs.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            addrs,
+            vec![
+                format!("/ip4/192.168.1.20/tcp/4001/p2p/{}", peer_id),
+                format!("/ip4/192.168.1.20/udp/4001/quic-v1/p2p/{}", peer_id),
+            ]
+        );
+
+        // Outside the pack, a beacon is noise
+        assert!(open_beacon(&beacon, from, &[8u8; 32]).is_none());
+        assert!(open_beacon(b"WPB1 not a beacon", from, &KEY).is_none());
+    }
+
+    fn interface(ip: [u8; 4], prefix: u32) -> if_addrs::Ifv4Addr {
+        let netmask = Ipv4Addr::from(u32::MAX << (32 - prefix));
+        if_addrs::Ifv4Addr {
+            ip: Ipv4Addr::from(ip),
+            netmask,
+            prefixlen: prefix as u8,
+            broadcast: None,
+        }
+    }
+
+    #[test]
+    fn test_targets() {
+        let interfaces = [
+            interface([192, 168, 1, 20], 24),
+            interface([127, 0, 0, 1], 8),
+        ];
+        let targets = targets(9779, false, &interfaces);
+        assert_eq!(
+            targets,
+            vec![
+                "255.255.255.255:9779".parse().unwrap(),
+                "192.168.1.255:9779".parse().unwrap(),
+            ]
+        );
+
+        // Scanning a /16 only covers the /24 around this address
+        let interfaces = [interface([10, 0, 3, 7], 16)];
+        let scanned = super::targets(9779, true, &interfaces);
+        assert!(scanned.contains(&"10.0.255.255:9779".parse().unwrap()));
+        assert!(scanned.contains(&"10.0.3.1:9779".parse().unwrap()));
+        assert!(scanned.contains(&"10.0.3.254:9779".parse().unwrap()));
+        assert!(!scanned.contains(&"10.0.3.7:9779".parse().unwrap()));
+        assert!(!scanned.contains(&"10.0.4.1:9779".parse().unwrap()));
+        assert_eq!(scanned.len(), 2 + 253);
+    }
+}
@@ -0,0 +1,216 @@
+use libp2p::core::{Endpoint, transport::PortUse};
+use libp2p::swarm::{
+    ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
+    THandlerOutEvent, ToSwarm, dummy,
+};
+use libp2p::{Multiaddr, PeerId};
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use tracing::debug;
+
+/// Connection gate for `sync.strict_peers`
+///
+/// Denies connections to and from peers that aren't paired devices as soon
+/// as the peer ID is known, before any protocol is negotiated on them. A
+/// disabled gate lets everyone through, and so does an open one, which is
+/// how a device that isn't paired yet reaches a pairing session.
+///
+/// Clones share their state, so a swarm rebuilt after its loop panics
+/// keeps the peers allowed since startup.
+#[derive(Debug, Clone, Default)]
+pub struct PeerGate {
+    state: Arc<Mutex<GateState>>,
+}
+
+#[derive(Debug, Default)]
+struct GateState {
+    enabled: bool,
+    open: bool,
+    allowed: HashSet<PeerId>,
+}
+
+impl PeerGate {
+    /// A gate that only admits `allowed`, or lets everyone through if `None`
+    pub fn new(allowed: Option<HashSet<PeerId>>) -> Self {
+        let state = GateState {
+            enabled: allowed.is_some(),
+            open: false,
+            allowed: allowed.unwrap_or_default(),
+        };
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    pub fn allow(&mut self, peer: PeerId) {
+        self.state().allowed.insert(peer);
+    }
+
+    /// Stop admitting a peer, without closing connections it already has
+    pub fn revoke(&mut self, peer: &PeerId) {
+        self.state().allowed.remove(peer);
+    }
+
+    pub fn set_open(&mut self, open: bool) {
+        self.state().open = open;
+    }
+
+    pub fn admits(&self, peer: &PeerId) -> bool {
+        let state = self.state();
+        !state.enabled || state.open || state.allowed.contains(peer)
+    }
+
+    fn state(&self) -> MutexGuard<'_, GateState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn enforce(&self, peer: &PeerId) -> Result<(), ConnectionDenied> {
+        if self.admits(peer) {
+            return Ok(());
+        }
+        debug!("Strict peers: denying connection with {}", peer);
+        Err(ConnectionDenied::new(NotPaired { peer: *peer }))
+    }
+}
+
+/// Why the gate denied a connection
+#[derive(Debug)]
+pub struct NotPaired {
+    pub peer: PeerId,
+}
+
+impl fmt::Display for NotPaired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Peer {} is not a paired device", self.peer)
+    }
+}
+
+impl std::error::Error for NotPaired {}
+
+impl NetworkBehaviour for PeerGate {
+    type ConnectionHandler = dummy::ConnectionHandler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(&peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn handle_pending_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: Option<PeerId>,
+        _: &[Multiaddr],
+        _: Endpoint,
+    ) -> Result<Vec<Multiaddr>, ConnectionDenied> {
+        if let Some(peer) = peer {
+            self.enforce(&peer)?;
+        }
+        Ok(Vec::new())
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        _: ConnectionId,
+        peer: PeerId,
+        _: &Multiaddr,
+        _: Endpoint,
+        _: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        self.enforce(&peer)?;
+        Ok(dummy::ConnectionHandler)
+    }
+
+    fn on_swarm_event(&mut self, _: FromSwarm) {}
+
+    fn on_connection_handler_event(
+        &mut self,
+        _: PeerId,
+        _: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(&mut self, _: &mut Context<'_>) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inbound(gate: &mut PeerGate, peer: PeerId) -> Result<(), ConnectionDenied> {
+        let addr: Multiaddr = "/ip4/192.168.1.20/tcp/4001".parse().unwrap();
+        gate.handle_established_inbound_connection(
+            ConnectionId::new_unchecked(0),
+            peer,
+            &addr,
+            &addr,
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn test_disabled_gate_admits_everyone() {
+        let mut gate = PeerGate::new(None);
+        assert!(inbound(&mut gate, PeerId::random()).is_ok());
+    }
+
+    #[test]
+    fn test_strict_gate() {
+        let paired = PeerId::random();
+        let stranger = PeerId::random();
+        let mut gate = PeerGate::new(Some(HashSet::from([paired])));
+
+        assert!(inbound(&mut gate, paired).is_ok());
+        let denied = inbound(&mut gate, stranger).unwrap_err();
+        assert_eq!(denied.downcast::<NotPaired>().unwrap().peer, stranger);
+
+        let outbound = gate.handle_pending_outbound_connection(
+            ConnectionId::new_unchecked(1),
+            Some(stranger),
+            &[],
+            Endpoint::Dialer,
+        );
+        assert!(outbound.is_err());
+
+        gate.allow(stranger);
+        assert!(inbound(&mut gate, stranger).is_ok());
+
+        gate.revoke(&stranger);
+        assert!(inbound(&mut gate, stranger).is_err());
+    }
+
+    #[test]
+    fn test_clones_share_state() {
+        let peer = PeerId::random();
+        let gate = PeerGate::new(Some(HashSet::new()));
+        let mut clone = gate.clone();
+
+        clone.allow(peer);
+        assert!(gate.admits(&peer));
+    }
+
+    #[test]
+    fn test_open_gate_admits_pairing_devices() {
+        let mut gate = PeerGate::new(Some(HashSet::new()));
+        let newcomer = PeerId::random();
+        assert!(!gate.admits(&newcomer));
+
+        gate.set_open(true);
+        assert!(gate.admits(&newcomer));
+        gate.set_open(false);
+        assert!(!gate.admits(&newcomer));
+    }
+}
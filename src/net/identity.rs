@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use libp2p::identity::Keypair;
+use std::path::Path;
+
+/// Load the node's libp2p identity, generating and saving one on first run
+///
+/// Keeping it stable across restarts keeps the peer ID stable, which is
+/// what paired devices recognize this one by in strict peer mode.
+pub fn load_or_generate_identity(path: &Path) -> Result<Keypair> {
+    if path.exists() {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read P2P identity from {}", path.display()))?;
+        return Keypair::from_protobuf_encoding(&bytes)
+            .with_context(|| format!("Failed to parse P2P identity from {}", path.display()));
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    let bytes = keypair
+        .to_protobuf_encoding()
+        .context("Failed to encode P2P identity")?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, bytes)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(path)?.permissions();
+        perms.set_mode(0o600);
+        std::fs::set_permissions(path, perms)?;
+    }
+
+    Ok(keypair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_identity_is_stable() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("p2p.key");
+
+        let first = load_or_generate_identity(&path).unwrap();
+        let second = load_or_generate_identity(&path).unwrap();
+        assert_eq!(first.public().to_peer_id(), second.public().to_peer_id());
+    }
+
+    #[test]
+    fn test_invalid_identity() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("p2p.key");
+        std::fs::write(&path, b"not a key").unwrap();
+
+        assert!(load_or_generate_identity(&path).is_err());
+    }
+}
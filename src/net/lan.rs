@@ -0,0 +1,305 @@
+use anyhow::{Context, Result};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::net::IpAddr;
+
+use super::relays::parse_relay;
+use super::transport::Transports;
+use crate::config::Config;
+
+/// Whether an IP is in a private, link-local, or loopback range
+pub fn is_lan_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local() || ip.is_loopback(),
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            // Unique local (fc00::/7) and link-local (fe80::/10)
+            ip.is_loopback() || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+/// Whether an address reaches a peer directly on the local network
+///
+/// DNS names and relayed (`p2p-circuit`) addresses could lead anywhere, so
+/// they never count as local.
+pub fn is_lan_address(addr: &Multiaddr) -> bool {
+    if addr.iter().any(|p| matches!(p, Protocol::P2pCircuit)) {
+        return false;
+    }
+    match addr.iter().next() {
+        Some(Protocol::Ip4(ip)) => is_lan_ip(IpAddr::V4(ip)),
+        Some(Protocol::Ip6(ip)) => is_lan_ip(IpAddr::V6(ip)),
+        _ => false,
+    }
+}
+
+/// Where the node listens (`sync.listen_port` and `[net]`)
+#[derive(Debug, Clone)]
+pub struct ListenOptions {
+    /// 0 for a random port
+    pub port: u16,
+    pub transports: Transports,
+    /// IPs to listen on when no interfaces are selected, usually the
+    /// unspecified `0.0.0.0` and `::`
+    pub addresses: Vec<IpAddr>,
+    /// Only listen on these interfaces' addresses
+    pub interfaces: Vec<String>,
+    /// Never listen on these interfaces' addresses
+    pub disabled_interfaces: Vec<String>,
+    /// Relays to be reachable through (`sync.relays`), with their peer IDs
+    pub relays: Vec<(PeerId, Multiaddr)>,
+}
+
+impl ListenOptions {
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let addresses = config
+            .net
+            .listen_addresses
+            .iter()
+            .map(|addr| {
+                addr.parse()
+                    .with_context(|| format!("Invalid IP '{}' in net.listen_addresses", addr))
+            })
+            .collect::<Result<_>>()?;
+        let relays = config
+            .sync
+            .relays
+            .iter()
+            .map(|addr| parse_relay(addr))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            port: config.sync.listen_port.unwrap_or(0),
+            transports: Transports::from_names(&config.net.transports)?,
+            addresses,
+            interfaces: config.net.interfaces.clone(),
+            disabled_interfaces: config.net.disabled_interfaces.clone(),
+            relays,
+        })
+    }
+
+    /// Whether listening goes by interface rather than by address
+    fn selects_interfaces(&self) -> bool {
+        !self.interfaces.is_empty() || !self.disabled_interfaces.is_empty()
+    }
+
+    fn enables(&self, interface: &str) -> bool {
+        (self.interfaces.is_empty() || self.interfaces.iter().any(|name| name == interface))
+            && !self
+                .disabled_interfaces
+                .iter()
+                .any(|name| name == interface)
+    }
+
+    /// Addresses to listen on with the selected transports
+    ///
+    /// Normally the configured addresses. With interfaces selected, the
+    /// addresses of each enabled interface instead, and in LAN-only mode
+    /// only those in private ranges, so nothing listens on a public address.
+    /// IPv6 link-local addresses are skipped, since binding them needs a
+    /// scope ID multiaddrs can't carry.
+    pub fn addresses(&self, lan_only: bool, interfaces: &[(String, IpAddr)]) -> Vec<Multiaddr> {
+        let ips: Vec<IpAddr> = if lan_only || self.selects_interfaces() {
+            interfaces
+                .iter()
+                .filter(|(name, _)| self.enables(name))
+                .map(|(_, ip)| *ip)
+                .filter(|ip| !lan_only || is_lan_ip(*ip))
+                .filter(|ip| !matches!(ip, IpAddr::V6(v6) if v6.segments()[0] & 0xffc0 == 0xfe80))
+                .collect()
+        } else {
+            self.addresses.clone()
+        };
+
+        ips.into_iter()
+            .flat_map(|ip| {
+                let ip_protocol = match ip {
+                    IpAddr::V4(ip) => Protocol::Ip4(ip),
+                    IpAddr::V6(ip) => Protocol::Ip6(ip),
+                };
+                let base = Multiaddr::empty().with(ip_protocol);
+                let tcp = self
+                    .transports
+                    .tcp
+                    .then(|| base.clone().with(Protocol::Tcp(self.port)));
+                let quic = self
+                    .transports
+                    .quic
+                    .then(|| base.with(Protocol::Udp(self.port)).with(Protocol::QuicV1));
+                tcp.into_iter().chain(quic)
+            })
+            .collect()
+    }
+}
+
+/// This machine's network interfaces and their IP addresses
+pub fn interface_addrs() -> Vec<(String, IpAddr)> {
+    if_addrs::get_if_addrs()
+        .map(|interfaces| {
+            interfaces
+                .into_iter()
+                .map(|interface| {
+                    let ip = interface.ip();
+                    (interface.name, ip)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether an address is a global IPv6 address
+///
+/// IPv6 addresses are rarely translated, so one a peer observes us at can
+/// be dialed back directly, unlike an IPv4 address behind NAT.
+pub fn is_global_ipv6(addr: &Multiaddr) -> bool {
+    if addr.iter().any(|p| matches!(p, Protocol::P2pCircuit)) {
+        return false;
+    }
+    match addr.iter().next() {
+        Some(Protocol::Ip6(ip)) => {
+            !is_lan_ip(IpAddr::V6(ip)) && !ip.is_unspecified() && !ip.is_multicast()
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str) -> Multiaddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_is_lan_address() {
+        assert!(is_lan_address(&addr("/ip4/192.168.1.20/tcp/4001")));
+        assert!(is_lan_address(&addr("/ip4/10.0.0.5/udp/4001/quic-v1")));
+        assert!(is_lan_address(&addr("/ip4/172.16.3.1/tcp/4001")));
+        assert!(is_lan_address(&addr("/ip4/127.0.0.1/tcp/4001")));
+        assert!(is_lan_address(&addr("/ip6/fd12:3456::1/tcp/4001")));
+        assert!(is_lan_address(&addr("/ip6/fe80::1/tcp/4001")));
+
+        assert!(!is_lan_address(&addr("/ip4/8.8.8.8/tcp/4001")));
+        assert!(!is_lan_address(&addr("/ip4/172.32.0.1/tcp/4001")));
+        assert!(!is_lan_address(&addr("/ip6/2001:db8::1/tcp/4001")));
+        assert!(!is_lan_address(&addr("/dns4/example.com/tcp/4001")));
+        assert!(!is_lan_address(&addr(
+            "/ip4/192.168.1.20/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"
+        )));
+    }
+
+    fn options(transports: Transports) -> ListenOptions {
+        ListenOptions {
+            port: 4001,
+            transports,
+            addresses: vec!["0.0.0.0".parse().unwrap(), "::".parse().unwrap()],
+            interfaces: Vec::new(),
+            disabled_interfaces: Vec::new(),
+            relays: Vec::new(),
+        }
+    }
+
+    fn interfaces() -> Vec<(String, IpAddr)> {
+        [
+            ("lo", "127.0.0.1"),
+            ("eth0", "192.168.1.20"),
+            ("eth0", "fe80::1"),
+            ("eth0", "2001:db8::20"),
+            ("wan0", "203.0.113.7"),
+            ("docker0", "fd00::2"),
+        ]
+        .iter()
+        .map(|(name, ip)| (name.to_string(), ip.parse().unwrap()))
+        .collect()
+    }
+
+    #[test]
+    fn test_listen_addresses_all_interfaces() {
+        assert_eq!(
+            options(Transports::default()).addresses(false, &interfaces()),
+            vec![
+                addr("/ip4/0.0.0.0/tcp/4001"),
+                addr("/ip4/0.0.0.0/udp/4001/quic-v1"),
+                addr("/ip6/::/tcp/4001"),
+                addr("/ip6/::/udp/4001/quic-v1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_listen_addresses_single_transport() {
+        let tcp_only = Transports {
+            tcp: true,
+            quic: false,
+        };
+        assert_eq!(
+            options(tcp_only).addresses(false, &[]),
+            vec![addr("/ip4/0.0.0.0/tcp/4001"), addr("/ip6/::/tcp/4001")]
+        );
+
+        let quic_only = Transports {
+            tcp: false,
+            quic: true,
+        };
+        let mut listen = options(quic_only);
+        listen.addresses = vec!["192.168.1.20".parse().unwrap()];
+        assert_eq!(
+            listen.addresses(false, &[]),
+            vec![addr("/ip4/192.168.1.20/udp/4001/quic-v1")]
+        );
+    }
+
+    #[test]
+    fn test_listen_addresses_lan_only() {
+        let addrs = options(Transports::default()).addresses(true, &interfaces());
+        assert_eq!(
+            addrs,
+            vec![
+                addr("/ip4/127.0.0.1/tcp/4001"),
+                addr("/ip4/127.0.0.1/udp/4001/quic-v1"),
+                addr("/ip4/192.168.1.20/tcp/4001"),
+                addr("/ip4/192.168.1.20/udp/4001/quic-v1"),
+                addr("/ip6/fd00::2/tcp/4001"),
+                addr("/ip6/fd00::2/udp/4001/quic-v1"),
+            ]
+        );
+        assert!(!addrs.iter().any(|a| a.to_string().contains("0.0.0.0")));
+    }
+
+    #[test]
+    fn test_listen_addresses_by_interface() {
+        let tcp_only = Transports {
+            tcp: true,
+            quic: false,
+        };
+        let mut listen = options(tcp_only);
+        listen.interfaces = vec!["eth0".to_string()];
+        assert_eq!(
+            listen.addresses(false, &interfaces()),
+            vec![
+                addr("/ip4/192.168.1.20/tcp/4001"),
+                addr("/ip6/2001:db8::20/tcp/4001"),
+            ]
+        );
+
+        // Disabling an interface listens on every other one
+        listen.interfaces.clear();
+        listen.disabled_interfaces = vec!["docker0".to_string(), "wan0".to_string()];
+        let addrs = listen.addresses(false, &interfaces());
+        assert_eq!(addrs.len(), 3);
+        assert!(!addrs.iter().any(|a| a.to_string().contains("fd00::2")));
+
+        // LAN-only mode still leaves out public addresses
+        assert_eq!(listen.addresses(true, &interfaces()).len(), 2);
+    }
+
+    #[test]
+    fn test_is_global_ipv6() {
+        assert!(is_global_ipv6(&addr("/ip6/2001:db8::20/tcp/4001")));
+        assert!(!is_global_ipv6(&addr("/ip6/fd00::2/tcp/4001")));
+        assert!(!is_global_ipv6(&addr("/ip6/fe80::1/udp/4001/quic-v1")));
+        assert!(!is_global_ipv6(&addr("/ip6/::/tcp/4001")));
+        assert!(!is_global_ipv6(&addr("/ip4/203.0.113.7/tcp/4001")));
+    }
+}
@@ -1,7 +1,26 @@
+mod advertise;
 mod behaviour;
+mod broadcast;
+mod gate;
+mod identity;
+mod lan;
 mod node;
 mod protocol;
+mod relays;
+mod socks;
+mod transport;
 
+pub use advertise::{NearbyDevice, ServiceAdvertiser};
 pub use behaviour::WolfpackBehaviour;
-pub use node::{NetworkCommand, NetworkEvent, Node};
-pub use protocol::{EncryptedEvent, PROTOCOL_NAME, SyncCodec, SyncRequest, SyncResponse};
+pub use broadcast::BroadcastOptions;
+pub use gate::PeerGate;
+pub use identity::load_or_generate_identity;
+pub use lan::ListenOptions;
+pub use node::{NetworkCommand, NetworkEvent, Node, PeerInfo};
+pub use protocol::{
+    EncryptedChunk, EncryptedEvent, EventCursor, MAX_EVENTS_PER_MESSAGE, MAX_HOPS, PAGE_BYTES,
+    PROTOCOL_NAME, SyncCodec, SyncRequest, SyncResponse, authenticate_request, check_event_count,
+    check_route,
+};
+pub use socks::onion_multiaddr;
+pub use transport::{Transports, build_transport};
@@ -1,17 +1,39 @@
 use anyhow::{Context, Result};
 use futures::StreamExt;
 use libp2p::{
-    Multiaddr, PeerId, Swarm, identify, identity, kad, mdns, noise, request_response,
-    swarm::SwarmEvent, tcp, yamux,
+    Multiaddr, PeerId, Swarm, Transport,
+    core::upgrade,
+    identify, identity, kad, mdns,
+    multiaddr::Protocol,
+    noise, ping, request_response,
+    swarm::{ConnectionId, SwarmEvent, dial_opts::DialOpts},
+    yamux,
 };
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{Mutex, mpsc};
-use tracing::{debug, error, info, warn};
+use tracing::{debug, error, info, instrument, warn};
 
 use super::behaviour::{WolfpackBehaviour, WolfpackBehaviourEvent};
-use super::protocol::{EncryptedEvent, SyncRequest, SyncResponse};
+use super::broadcast::{BEACON_INTERVAL, BroadcastOptions, Broadcaster};
+use super::gate::PeerGate;
+use super::lan::{ListenOptions, interface_addrs, is_global_ipv6, is_lan_address};
+use super::protocol::{
+    EncryptedEvent, MAX_EVENTS_PER_MESSAGE, SyncRequest, SyncResponse, authenticate_request,
+    check_event_count,
+};
+use super::relays::RelaySelector;
+use super::socks::{self, Socks5Transport};
+use super::transport::{Transports, build_transport};
+use crate::events::HeldFiles;
+use crate::lanes::{self, Lane, LaneReceiver, LaneSender};
+use crate::supervisor::supervise;
+
+/// How long an idle connection is kept open
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often configured relays that aren't connected are dialed again
+const RELAY_CHECK_INTERVAL: Duration = Duration::from_secs(30);
 
 /// Events sent from the network to the application
 #[derive(Debug)]
@@ -22,6 +44,9 @@ pub enum NetworkEvent {
         device_name: Option<String>,
     },
 
+    /// A connection to a peer was established
+    PeerConnected { peer_id: PeerId, address: Multiaddr },
+
     /// A peer disconnected
     PeerDisconnected { peer_id: PeerId },
 
@@ -29,13 +54,16 @@ pub enum NetworkEvent {
     EventsReceived {
         from: PeerId,
         events: Vec<EncryptedEvent>,
+        /// Where to ask for the rest, if the peer had more to send
+        next: Option<String>,
     },
 
     /// A peer requested our events
     EventsRequested {
         from: PeerId,
         request_id: request_response::InboundRequestId,
-        clock: HashMap<String, u64>,
+        held: HeldFiles,
+        after: Option<String>,
     },
 
     /// A peer sent us a tab
@@ -44,6 +72,18 @@ pub enum NetworkEvent {
         url: String,
         title: Option<String>,
         from_device: String,
+        tab_id: Option<String>,
+    },
+
+    /// A peer acknowledged a tab we sent
+    TabDelivered { from: PeerId, tab_id: String },
+
+    /// A peer answered our clock request
+    ClockReceived {
+        from: PeerId,
+        clock: HashMap<String, u64>,
+        device_id: String,
+        device_name: String,
     },
 
     /// A peer requested our clock
@@ -52,6 +92,21 @@ pub enum NetworkEvent {
         request_id: request_response::InboundRequestId,
     },
 
+    /// A peer asked us to prove we hold our device key
+    ProofRequested {
+        from: PeerId,
+        request_id: request_response::InboundRequestId,
+        challenge: String,
+        public_key: String,
+    },
+
+    /// A peer answered our device challenge
+    DeviceProofReceived {
+        from: PeerId,
+        device_id: String,
+        proof: String,
+    },
+
     /// A peer wants to join our pairing session
     PairingRequested {
         from: PeerId,
@@ -72,16 +127,83 @@ pub enum NetworkEvent {
     },
 }
 
+/// What the node knows about a connected peer
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// Device name the peer announced, once identified
+    pub name: Option<String>,
+    /// Remote address of the connection
+    pub address: Multiaddr,
+    /// Protocols the peer announced it supports
+    pub protocols: Vec<String>,
+    /// Hex identity key the peer proved in the Noise handshake
+    pub public_key: Option<String>,
+    /// Device the peer is bound to through the device registry, the only
+    /// one it may send tabs and events as
+    pub device_id: Option<String>,
+    pub connected_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl PeerInfo {
+    fn new(address: Multiaddr) -> Self {
+        Self {
+            name: None,
+            address,
+            protocols: Vec::new(),
+            public_key: None,
+            device_id: None,
+            connected_at: chrono::Utc::now(),
+        }
+    }
+
+    /// How the connection is carried: `relay`, `quic`, `tor`, or `tcp`
+    pub fn transport(&self) -> &'static str {
+        transport_name(&self.address)
+    }
+
+    /// How the connection is encrypted: QUIC has TLS 1.3 built in, every
+    /// other transport is upgraded with Noise
+    pub fn encryption(&self) -> &'static str {
+        match self.transport() {
+            "quic" => "TLS 1.3",
+            _ => "Noise",
+        }
+    }
+}
+
+fn transport_name(address: &Multiaddr) -> &'static str {
+    let has = |check: fn(&Protocol) -> bool| address.iter().any(|p| check(&p));
+    if has(|p| matches!(p, Protocol::P2pCircuit)) {
+        "relay"
+    } else if has(|p| matches!(p, Protocol::Quic | Protocol::QuicV1)) {
+        "quic"
+    } else if has(|p| matches!(p, Protocol::Onion3(_))) {
+        "tor"
+    } else if has(|p| matches!(p, Protocol::Tcp(_))) {
+        "tcp"
+    } else {
+        "unknown"
+    }
+}
+
 /// Commands sent to the network from the application
 #[derive(Debug)]
 pub enum NetworkCommand {
     /// Request a peer's clock
     GetClock { peer_id: PeerId },
 
-    /// Request events from a peer
+    /// Challenge a peer to prove it holds the key of the device it claims
+    ProveDevice {
+        peer_id: PeerId,
+        challenge: String,
+        public_key: String,
+    },
+
+    /// Request events from a peer, continuing from `after` if given
     GetEvents {
         peer_id: PeerId,
-        clock: HashMap<String, u64>,
+        held: HeldFiles,
+        after: Option<String>,
     },
 
     /// Push events to a peer
@@ -96,6 +218,7 @@ pub enum NetworkCommand {
         url: String,
         title: Option<String>,
         from_device: String,
+        tab_id: Option<String>,
     },
 
     /// Respond to a clock request
@@ -106,15 +229,37 @@ pub enum NetworkCommand {
         device_name: String,
     },
 
+    /// Respond to a device challenge
+    RespondProof {
+        request_id: request_response::InboundRequestId,
+        device_id: String,
+        proof: String,
+    },
+
     /// Respond to an events request
     RespondEvents {
         request_id: request_response::InboundRequestId,
         events: Vec<EncryptedEvent>,
+        next: Option<String>,
     },
 
     /// Connect to a known peer address
     Dial { addr: Multiaddr },
 
+    /// Close every connection to a peer
+    Disconnect { peer_id: PeerId },
+
+    /// Bind a peer to the device it proved to be, so it can send tabs and
+    /// events as that device
+    BindDevice { peer_id: PeerId, device_id: String },
+
+    /// Let a paired device through the strict peer gate
+    AllowPeer { peer_id: PeerId },
+
+    /// Let every peer through the strict peer gate while a pairing session
+    /// is open
+    OpenGate { open: bool },
+
     /// Add a bootstrap peer for DHT
     AddBootstrapPeer { peer_id: PeerId, addr: Multiaddr },
 
@@ -137,71 +282,97 @@ pub enum NetworkCommand {
     },
 }
 
+impl NetworkCommand {
+    /// Exchanging event files is bulk work, which tab sends and pairing
+    /// messages go ahead of
+    fn lane(&self) -> Lane {
+        match self {
+            Self::GetEvents { .. } | Self::PushEvents { .. } | Self::RespondEvents { .. } => {
+                Lane::Bulk
+            }
+            _ => Lane::Interactive,
+        }
+    }
+}
+
 /// The P2P node
 pub struct Node {
     /// Channel to send commands to the swarm
-    command_tx: mpsc::Sender<NetworkCommand>,
+    command_tx: LaneSender<NetworkCommand>,
     /// Channel to receive events from the swarm
     event_rx: mpsc::Receiver<NetworkEvent>,
     /// Our local peer ID
     peer_id: PeerId,
-    /// Known peers (peer_id -> device_name)
-    peers: Arc<Mutex<HashMap<PeerId, String>>>,
+    /// Connected peers
+    peers: Arc<Mutex<HashMap<PeerId, PeerInfo>>>,
 }
 
 impl Node {
     /// Create and start a new P2P node
+    ///
+    /// In LAN-only mode the swarm is built without the relay transport, DHT,
+    /// or hole punching, listens only on private addresses, and never dials
+    /// or accepts connections outside the local network.
+    ///
+    /// Only the selected transports are built, listening on the configured
+    /// addresses or interfaces. With a SOCKS5 proxy, every dial goes through
+    /// it over TCP and the node only listens on loopback, for a Tor hidden
+    /// service to forward to.
+    ///
+    /// With `allowed_peers`, only those peers may connect until more are
+    /// allowed or the gate is opened for pairing (`sync.strict_peers`).
+    ///
+    /// With `broadcast`, the node also finds pack devices through UDP
+    /// beacons (`net.broadcast_discovery`).
+    ///
+    /// The swarm runs supervised: if its loop panics, the swarm is built
+    /// again and picks up the same command and event channels.
+    #[allow(clippy::cognitive_complexity)] // Sequential setup with logging
+    #[allow(clippy::too_many_arguments)] // Mirrors the [sync] and [net] options
     pub async fn new(
-        device_name: String,
-        listen_port: Option<u16>,
+        local_key: identity::Keypair,
+        _device_name: String,
+        listen: ListenOptions,
         enable_mdns: bool,
         enable_dht: bool,
+        lan_only: bool,
+        socks5: Option<&str>,
+        compression: bool,
+        allowed_peers: Option<HashSet<PeerId>>,
+        broadcast: Option<BroadcastOptions>,
     ) -> Result<Self> {
-        // Generate or load identity
-        let local_key = identity::Keypair::generate_ed25519();
         let local_peer_id = local_key.public().to_peer_id();
 
         info!("Local peer ID: {}", local_peer_id);
+        if let Some(proxy) = socks5 {
+            if !listen.transports.tcp {
+                anyhow::bail!("A SOCKS5 proxy needs the tcp transport in net.transports");
+            }
+            info!("Dialing peers through SOCKS5 proxy {}", proxy);
+        } else {
+            info!("Transports: {}", listen.transports);
+        }
 
-        // Build the swarm
-        let mut swarm = libp2p::SwarmBuilder::with_existing_identity(local_key.clone())
-            .with_tokio()
-            .with_tcp(
-                tcp::Config::default(),
-                noise::Config::new,
-                yamux::Config::default,
-            )?
-            .with_quic()
-            .with_relay_client(noise::Config::new, yamux::Config::default)?
-            .with_behaviour(|key, relay| WolfpackBehaviour::new(key, relay, enable_mdns))?
-            .with_swarm_config(|c| c.with_idle_connection_timeout(Duration::from_secs(60)))
-            .build();
-
-        // Listen on TCP
-        let listen_addr: Multiaddr =
-            format!("/ip4/0.0.0.0/tcp/{}", listen_port.unwrap_or(0)).parse()?;
-        swarm.listen_on(listen_addr)?;
-
-        // Listen on QUIC
-        let quic_addr: Multiaddr =
-            format!("/ip4/0.0.0.0/udp/{}/quic-v1", listen_port.unwrap_or(0)).parse()?;
-        swarm.listen_on(quic_addr)?;
+        let setup = SwarmSetup {
+            local_key,
+            listen,
+            enable_mdns,
+            enable_dht: enable_dht && !lan_only,
+            compression,
+            lan_only,
+            socks5: socks5.map(String::from),
+            gate: PeerGate::new(allowed_peers),
+            broadcast,
+        };
+        // Built here the first time, so configuration errors stop startup
+        let swarm = setup.build()?;
 
         // Set up channels
-        let (command_tx, command_rx) = mpsc::channel(100);
+        let (command_tx, command_rx) = lanes::channel(100);
         let (event_tx, event_rx) = mpsc::channel(100);
         let peers = Arc::new(Mutex::new(HashMap::new()));
 
-        // Spawn the swarm event loop
-        let peers_clone = peers.clone();
-        tokio::spawn(run_swarm(
-            swarm,
-            command_rx,
-            event_tx,
-            peers_clone,
-            device_name,
-            enable_dht,
-        ));
+        setup.run_supervised(swarm, command_rx, event_tx, peers.clone());
 
         Ok(Self {
             command_tx,
@@ -216,8 +387,19 @@ impl Node {
         &self.peer_id
     }
 
-    /// Get list of connected peers
+    /// Get list of connected peers that identified themselves, with their
+    /// device names
     pub async fn peers(&self) -> HashMap<PeerId, String> {
+        self.peers
+            .lock()
+            .await
+            .iter()
+            .filter_map(|(peer_id, info)| Some((*peer_id, info.name.clone()?)))
+            .collect()
+    }
+
+    /// Every connected peer, identified or not, with its connection details
+    pub async fn peer_info(&self) -> HashMap<PeerId, PeerInfo> {
         self.peers.lock().await.clone()
     }
 
@@ -229,21 +411,35 @@ impl Node {
     /// Send a command to the network
     pub async fn send_command(&self, cmd: NetworkCommand) -> Result<()> {
         self.command_tx
-            .send(cmd)
+            .send(cmd.lane(), cmd)
             .await
             .context("Failed to send network command")
     }
 
+    /// How many commands had to wait for room in the swarm's queue
+    pub fn command_overflows(&self) -> u64 {
+        self.command_tx.overflows()
+    }
+
     /// Request a peer's clock
     pub async fn get_clock(&self, peer_id: PeerId) -> Result<()> {
         self.send_command(NetworkCommand::GetClock { peer_id })
             .await
     }
 
-    /// Request events from a peer
-    pub async fn get_events(&self, peer_id: PeerId, clock: HashMap<String, u64>) -> Result<()> {
-        self.send_command(NetworkCommand::GetEvents { peer_id, clock })
-            .await
+    /// Request events from a peer, continuing from a previous page's cursor
+    pub async fn get_events(
+        &self,
+        peer_id: PeerId,
+        held: HeldFiles,
+        after: Option<String>,
+    ) -> Result<()> {
+        self.send_command(NetworkCommand::GetEvents {
+            peer_id,
+            held,
+            after,
+        })
+        .await
     }
 
     /// Push events to a peer
@@ -253,27 +449,234 @@ impl Node {
     }
 
     /// Send a tab to a peer
+    #[allow(clippy::too_many_arguments)] // Fields of the tab request
     pub async fn send_tab(
         &self,
         peer_id: PeerId,
         url: String,
         title: Option<String>,
         from_device: String,
+        tab_id: Option<String>,
     ) -> Result<()> {
         self.send_command(NetworkCommand::SendTab {
             peer_id,
             url,
             title,
             from_device,
+            tab_id,
         })
         .await
     }
 }
 
-/// Run the swarm event loop
-#[allow(clippy::cognitive_complexity)] // Core P2P event loop
-#[allow(clippy::too_many_arguments)] // Required for swarm coordination
-use std::time::Instant;
+/// Everything needed to build and run the swarm, kept to build it again
+/// after its loop panics
+#[derive(Clone)]
+struct SwarmSetup {
+    local_key: identity::Keypair,
+    listen: ListenOptions,
+    enable_mdns: bool,
+    enable_dht: bool,
+    compression: bool,
+    lan_only: bool,
+    socks5: Option<String>,
+    gate: PeerGate,
+    broadcast: Option<BroadcastOptions>,
+}
+
+impl SwarmSetup {
+    fn build(&self) -> Result<Swarm<WolfpackBehaviour>> {
+        let mut swarm = build_swarm(
+            self.local_key.clone(),
+            self.enable_mdns,
+            self.compression,
+            self.lan_only,
+            self.listen.transports,
+            self.socks5.as_deref(),
+            self.gate.clone(),
+        )?;
+        for (peer_id, _) in self.relays() {
+            swarm.behaviour_mut().gate.allow(*peer_id);
+        }
+
+        // Listen on the selected transports, or only loopback TCP behind a proxy
+        let listen_addrs = if self.socks5.is_some() {
+            vec![socks::listen_address(self.listen.port)]
+        } else {
+            self.listen.addresses(self.lan_only, &interface_addrs())
+        };
+        start_listening(&mut swarm, listen_addrs)?;
+        Ok(swarm)
+    }
+
+    /// Configured relays, none in LAN-only mode where there's no relay client
+    fn relays(&self) -> &[(PeerId, Multiaddr)] {
+        if self.lan_only {
+            &[]
+        } else {
+            &self.listen.relays
+        }
+    }
+
+    /// Run the swarm event loop, rebuilding the swarm after a panic
+    fn run_supervised(
+        self,
+        swarm: Swarm<WolfpackBehaviour>,
+        command_rx: LaneReceiver<NetworkCommand>,
+        event_tx: mpsc::Sender<NetworkEvent>,
+        peers: Arc<Mutex<HashMap<PeerId, PeerInfo>>>,
+    ) {
+        let mut swarm = Some(swarm);
+        let command_rx = Arc::new(Mutex::new(command_rx));
+        supervise("P2P swarm", move || {
+            let swarm = swarm.take();
+            let setup = self.clone();
+            let command_rx = command_rx.clone();
+            let event_tx = event_tx.clone();
+            let peers = peers.clone();
+            async move {
+                let swarm = match swarm {
+                    Some(swarm) => swarm,
+                    None => {
+                        forget_peers(&peers, &event_tx).await;
+                        setup.build()?
+                    }
+                };
+                let mut command_rx = command_rx.lock().await;
+                let relays = RelaySelector::new(setup.relays());
+                let broadcaster = match setup.broadcast {
+                    Some(options) => Broadcaster::bind(options)
+                        .await
+                        .inspect_err(|e| warn!("Broadcast discovery is off: {}", e))
+                        .ok(),
+                    None => None,
+                };
+                let swarm_loop = SwarmLoop {
+                    swarm,
+                    event_tx,
+                    peers,
+                    enable_dht: setup.enable_dht,
+                    lan_only: setup.lan_only,
+                    relays,
+                    broadcaster,
+                    discovered_peers: HashSet::new(),
+                    pending_responses: HashMap::new(),
+                };
+                swarm_loop.run(&mut command_rx).await;
+                Ok(())
+            }
+        });
+    }
+}
+
+/// Report every peer of a swarm that went down as disconnected
+async fn forget_peers(
+    peers: &Mutex<HashMap<PeerId, PeerInfo>>,
+    event_tx: &mpsc::Sender<NetworkEvent>,
+) {
+    let lost: Vec<PeerId> = peers
+        .lock()
+        .await
+        .drain()
+        .map(|(peer_id, _)| peer_id)
+        .collect();
+    for peer_id in lost {
+        let _ = event_tx
+            .send(NetworkEvent::PeerDisconnected { peer_id })
+            .await;
+    }
+}
+
+/// Listen on every address that can be bound, so a machine without IPv6 or
+/// with an interface down still comes up on the rest
+#[allow(clippy::cognitive_complexity)] // Loop with logging
+fn start_listening(swarm: &mut Swarm<WolfpackBehaviour>, addrs: Vec<Multiaddr>) -> Result<()> {
+    if addrs.is_empty() {
+        warn!("No enabled network interface to listen on");
+        return Ok(());
+    }
+    let mut listening = false;
+    for addr in addrs {
+        match swarm.listen_on(addr.clone()) {
+            Ok(_) => listening = true,
+            Err(e) => warn!("Failed to listen on {}: {}", addr, e),
+        }
+    }
+    if !listening {
+        anyhow::bail!("Couldn't listen on any address");
+    }
+    Ok(())
+}
+
+/// Build the swarm with the selected transports, leaving out the relay
+/// transport in LAN-only mode and going through the proxy when one is set
+#[allow(clippy::too_many_arguments)] // Mirrors the [sync] and [net] options
+fn build_swarm(
+    local_key: identity::Keypair,
+    enable_mdns: bool,
+    compression: bool,
+    lan_only: bool,
+    transports: Transports,
+    socks5: Option<&str>,
+    gate: PeerGate,
+) -> Result<Swarm<WolfpackBehaviour>> {
+    if let Some(proxy) = socks5 {
+        return build_proxied_swarm(local_key, enable_mdns, compression, proxy, gate);
+    }
+
+    let builder = libp2p::SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(build_transport(key, transports)?)
+        })?;
+
+    let swarm = if lan_only {
+        builder
+            .with_behaviour(|key| {
+                WolfpackBehaviour::new(key, None, enable_mdns, compression, gate)
+            })?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_TIMEOUT))
+            .build()
+    } else {
+        builder
+            .with_relay_client(noise::Config::new, yamux::Config::default)?
+            .with_behaviour(|key, relay| {
+                WolfpackBehaviour::new(key, Some(relay), enable_mdns, compression, gate)
+            })?
+            .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_TIMEOUT))
+            .build()
+    };
+    Ok(swarm)
+}
+
+/// Build a swarm that only dials TCP through a SOCKS5 proxy
+///
+/// QUIC is left out, since UDP can't go through the proxy.
+fn build_proxied_swarm(
+    local_key: identity::Keypair,
+    enable_mdns: bool,
+    compression: bool,
+    proxy: &str,
+    gate: PeerGate,
+) -> Result<Swarm<WolfpackBehaviour>> {
+    let swarm = libp2p::SwarmBuilder::with_existing_identity(local_key)
+        .with_tokio()
+        .with_other_transport(|key| {
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(
+                Socks5Transport::new(proxy)
+                    .upgrade(upgrade::Version::V1)
+                    .authenticate(noise::Config::new(key)?)
+                    .multiplex(yamux::Config::default()),
+            )
+        })?
+        .with_relay_client(noise::Config::new, yamux::Config::default)?
+        .with_behaviour(|key, relay| {
+            WolfpackBehaviour::new(key, Some(relay), enable_mdns, compression, gate)
+        })?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_TIMEOUT))
+        .build();
+    Ok(swarm)
+}
 
 /// Storage for pending response channels
 struct PendingResponse {
@@ -281,113 +684,316 @@ struct PendingResponse {
     created_at: Instant,
 }
 
-async fn run_swarm(
-    mut swarm: Swarm<WolfpackBehaviour>,
-    mut command_rx: mpsc::Receiver<NetworkCommand>,
+/// How long a response channel waits for the daemon to answer
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The swarm event loop, and what it keeps between events
+struct SwarmLoop {
+    swarm: Swarm<WolfpackBehaviour>,
     event_tx: mpsc::Sender<NetworkEvent>,
-    peers: Arc<Mutex<HashMap<PeerId, String>>>,
-    _device_name: String,
+    peers: Arc<Mutex<HashMap<PeerId, PeerInfo>>>,
     enable_dht: bool,
-) {
-    let mut discovered_peers: HashSet<PeerId> = HashSet::new();
-    let mut pending_responses: HashMap<request_response::InboundRequestId, PendingResponse> =
-        HashMap::new();
-
-    // Cleanup timer for expired response channels (30 second timeout)
-    let mut cleanup_interval = tokio::time::interval(Duration::from_secs(10));
-    const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
-
-    loop {
-        tokio::select! {
-            // Handle swarm events
-            event = swarm.select_next_some() => {
-                match event {
-                    SwarmEvent::NewListenAddr { address, .. } => {
-                        info!("Listening on {}", address);
-                    }
+    lan_only: bool,
+    relays: RelaySelector,
+    broadcaster: Option<Broadcaster>,
+    discovered_peers: HashSet<PeerId>,
+    pending_responses: HashMap<request_response::InboundRequestId, PendingResponse>,
+}
 
-                    SwarmEvent::ConnectionEstablished { peer_id, .. } => {
-                        info!("Connected to peer: {}", peer_id);
-                    }
+impl SwarmLoop {
+    /// Run the swarm event loop
+    async fn run(mut self, command_rx: &mut LaneReceiver<NetworkCommand>) {
+        // Cleanup timer for expired response channels
+        let mut cleanup_interval = tokio::time::interval(Duration::from_secs(10));
+        let mut relay_check = tokio::time::interval(RELAY_CHECK_INTERVAL);
+        let mut beacon_interval = tokio::time::interval(BEACON_INTERVAL);
+
+        loop {
+            tokio::select! {
+                // Handle swarm events
+                event = self.swarm.select_next_some() => self.handle_swarm_event(event).await,
+
+                // Handle commands from application
+                Some(cmd) = command_rx.recv() => {
+                    let pending = &mut self.pending_responses;
+                    handle_command(&mut self.swarm, cmd, pending, &self.peers, self.lan_only).await;
+                }
 
-                    SwarmEvent::ConnectionClosed { peer_id, .. } => {
-                        info!("Disconnected from peer: {}", peer_id);
-                        peers.lock().await.remove(&peer_id);
-                        let _ = event_tx.send(NetworkEvent::PeerDisconnected { peer_id }).await;
-                    }
+                // Health-check relays, moving to a faster one if there is
+                _ = relay_check.tick() => check_relays(&mut self.swarm, &mut self.relays),
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Mdns(event)) => {
-                        handle_mdns_event(&mut swarm, event, &mut discovered_peers, &event_tx).await;
-                    }
+                // Announce this node to pack devices where mDNS doesn't reach
+                _ = beacon_interval.tick(), if self.broadcaster.is_some() => self.announce().await,
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Kademlia(event)) => {
-                        if enable_dht {
-                            handle_kademlia_event(event);
-                        }
-                    }
+                (peer_id, addrs) = next_beacon(self.broadcaster.as_ref()) => {
+                    let (swarm, event_tx) = (&mut self.swarm, &self.event_tx);
+                    handle_beacon(swarm, peer_id, addrs, event_tx, self.lan_only).await;
+                }
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Identify(event)) => {
-                        handle_identify_event(&mut swarm, event, &peers, &event_tx, enable_dht).await;
-                    }
+                // Periodic cleanup of expired response channels
+                _ = cleanup_interval.tick() => self.expire_responses(),
+            }
+        }
+    }
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Sync(event)) => {
-                        handle_sync_event(&mut swarm, event, &event_tx, &mut pending_responses).await;
-                    }
+    #[allow(clippy::cognitive_complexity)] // One arm per swarm event, with logging
+    async fn handle_swarm_event(&mut self, event: SwarmEvent<WolfpackBehaviourEvent>) {
+        match event {
+            SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {}", address),
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Ping(event)) => {
-                        debug!("Ping event: {:?}", event);
-                    }
+            SwarmEvent::ExpiredListenAddr { address, .. } => {
+                info!("No longer listening on {}", address);
+            }
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::RelayClient(event)) => {
-                        debug!("Relay client event: {:?}", event);
-                    }
+            SwarmEvent::ListenerError { error, .. } => warn!("Listener error: {}", error),
 
-                    SwarmEvent::Behaviour(WolfpackBehaviourEvent::Dcutr(event)) => {
-                        debug!("DCUtR event: {:?}", event);
-                    }
+            SwarmEvent::ListenerClosed { listener_id, .. }
+                if self.relays.listener_closed(listener_id) =>
+            {
+                warn!("Lost relay reservation, failing over");
+                switch_relay(&mut self.swarm, &mut self.relays);
+            }
+
+            // Peers report the address they see us at through identify. A
+            // global IPv6 one is reachable as is, so it's confirmed and
+            // reported to other peers in turn.
+            SwarmEvent::NewExternalAddrCandidate { address }
+                if !self.lan_only && is_global_ipv6(&address) =>
+            {
+                self.swarm.add_external_address(address);
+            }
+
+            SwarmEvent::ExternalAddrConfirmed { address } => info!("External address: {}", address),
+
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                ..
+            } => {
+                let address = endpoint.get_remote_address().clone();
+                self.connection_established(peer_id, connection_id, address)
+                    .await;
+            }
+
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                num_established,
+                ..
+            } => self.connection_closed(peer_id, num_established).await,
+
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                ..
+            } if self.relays.unreachable_relay(&peer_id) => {
+                switch_relay(&mut self.swarm, &mut self.relays);
+            }
+
+            SwarmEvent::Behaviour(event) => self.handle_behaviour_event(event).await,
+
+            _ => {}
+        }
+    }
+
+    #[allow(clippy::cognitive_complexity)] // Branches with logging
+    async fn connection_established(
+        &mut self,
+        peer_id: PeerId,
+        connection_id: ConnectionId,
+        address: Multiaddr,
+    ) {
+        if self.lan_only && !is_lan_address(&address) {
+            warn!("LAN-only mode: refusing {} at {}", peer_id, address);
+            self.swarm.close_connection(connection_id);
+            return;
+        }
+        info!("Connected to peer: {}", peer_id);
+        self.peers
+            .lock()
+            .await
+            .entry(peer_id)
+            .or_insert_with(|| PeerInfo::new(address.clone()));
+        let event = NetworkEvent::PeerConnected { peer_id, address };
+        let _ = self.event_tx.send(event).await;
+        if self.relays.connected(&peer_id) {
+            switch_relay(&mut self.swarm, &mut self.relays);
+        }
+    }
+
+    #[allow(clippy::cognitive_complexity)] // Branches with logging
+    async fn connection_closed(&mut self, peer_id: PeerId, num_established: u32) {
+        info!("Disconnected from peer: {}", peer_id);
+        self.peers.lock().await.remove(&peer_id);
+        let _ = self
+            .event_tx
+            .send(NetworkEvent::PeerDisconnected { peer_id })
+            .await;
+        if num_established == 0 && self.relays.unreachable_relay(&peer_id) {
+            warn!("Relay {} went down, failing over", peer_id);
+            switch_relay(&mut self.swarm, &mut self.relays);
+        }
+    }
 
-                    _ => {}
+    #[allow(clippy::cognitive_complexity)] // One arm per behaviour, with logging
+    async fn handle_behaviour_event(&mut self, event: WolfpackBehaviourEvent) {
+        let (swarm, event_tx) = (&mut self.swarm, &self.event_tx);
+        match event {
+            WolfpackBehaviourEvent::Mdns(event) => {
+                let discovered = &mut self.discovered_peers;
+                handle_mdns_event(swarm, event, discovered, event_tx, self.lan_only).await;
+            }
+
+            WolfpackBehaviourEvent::Kademlia(event) if self.enable_dht => {
+                handle_kademlia_event(event);
+            }
+
+            WolfpackBehaviourEvent::Identify(event) => {
+                handle_identify_event(swarm, event, &self.peers, event_tx, self.enable_dht).await;
+            }
+
+            WolfpackBehaviourEvent::Sync(event) => {
+                let pending = &mut self.pending_responses;
+                handle_sync_event(swarm, event, event_tx, pending, &self.peers).await;
+            }
+
+            WolfpackBehaviourEvent::Ping(event) => {
+                debug!("Ping event: {:?}", event);
+                if let ping::Event {
+                    peer,
+                    result: Ok(rtt),
+                    ..
+                } = event
+                {
+                    self.relays.record_rtt(&peer, rtt);
                 }
             }
 
-            // Handle commands from application
-            Some(cmd) = command_rx.recv() => {
-                handle_command(&mut swarm, cmd, &mut pending_responses).await;
+            WolfpackBehaviourEvent::RelayClient(event) => {
+                debug!("Relay client event: {:?}", event);
             }
 
-            // Periodic cleanup of expired response channels
-            _ = cleanup_interval.tick() => {
-                let now = Instant::now();
-                pending_responses.retain(|request_id, pending| {
-                    let elapsed = now.duration_since(pending.created_at);
-                    if elapsed > RESPONSE_TIMEOUT {
-                        warn!("Response channel for request {:?} expired after {:?}", request_id, elapsed);
-                        false
-                    } else {
-                        true
-                    }
-                });
+            WolfpackBehaviourEvent::Dcutr(event) => debug!("DCUtR event: {:?}", event),
+
+            _ => {}
+        }
+    }
+
+    async fn announce(&mut self) {
+        if let Some(broadcaster) = &self.broadcaster {
+            let listen_addrs: Vec<Multiaddr> = self.swarm.listeners().cloned().collect();
+            broadcaster
+                .announce(self.swarm.local_peer_id(), &listen_addrs)
+                .await;
+        }
+    }
+
+    fn expire_responses(&mut self) {
+        let now = Instant::now();
+        self.pending_responses.retain(|request_id, pending| {
+            let elapsed = now.duration_since(pending.created_at);
+            if elapsed > RESPONSE_TIMEOUT {
+                warn!(
+                    "Response channel for request {:?} expired after {:?}",
+                    request_id, elapsed
+                );
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// Dial configured relays that aren't connected, and move to the best one
+fn check_relays(swarm: &mut Swarm<WolfpackBehaviour>, relays: &mut RelaySelector) {
+    for addr in relays.unreachable() {
+        if let Err(e) = swarm.dial(addr.clone()) {
+            debug!("Failed to dial relay {}: {}", addr, e);
+        }
+    }
+    switch_relay(swarm, relays);
+}
+
+/// Listen through the relay the selector prefers, if it's not the one in use
+#[allow(clippy::cognitive_complexity)] // Branches with logging
+fn switch_relay(swarm: &mut Swarm<WolfpackBehaviour>, relays: &mut RelaySelector) {
+    let Some((peer_id, circuit)) = relays.choose() else {
+        return;
+    };
+    match swarm.listen_on(circuit) {
+        Ok(listener) => {
+            info!("Reachable through relay {}", peer_id);
+            if let Some(previous) = relays.activate(peer_id, listener) {
+                swarm.remove_listener(previous);
             }
         }
+        Err(e) => warn!("Failed to listen through relay {}: {}", peer_id, e),
+    }
+}
+
+/// The next beacon from a pack device, never coming without broadcast
+/// discovery
+async fn next_beacon(broadcaster: Option<&Broadcaster>) -> (PeerId, Vec<Multiaddr>) {
+    match broadcaster {
+        Some(broadcaster) => broadcaster.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Dial a pack device that sent a beacon, unless it's already connected
+#[allow(clippy::cognitive_complexity)] // Branches with logging
+async fn handle_beacon(
+    swarm: &mut Swarm<WolfpackBehaviour>,
+    peer_id: PeerId,
+    addrs: Vec<Multiaddr>,
+    event_tx: &mpsc::Sender<NetworkEvent>,
+    lan_only: bool,
+) {
+    if peer_id == *swarm.local_peer_id() || swarm.is_connected(&peer_id) {
+        return;
     }
+    let addrs: Vec<Multiaddr> = addrs
+        .into_iter()
+        .filter(|addr| !lan_only || is_lan_address(addr))
+        .collect();
+    if addrs.is_empty() {
+        return;
+    }
+    info!("Beacon from peer: {} at {}", peer_id, addrs[0]);
+    let opts = DialOpts::peer_id(peer_id).addresses(addrs).build();
+    if let Err(e) = swarm.dial(opts) {
+        warn!("Failed to dial peer from beacon: {}", e);
+        return;
+    }
+    let _ = event_tx
+        .send(NetworkEvent::PeerDiscovered {
+            peer_id,
+            device_name: None,
+        })
+        .await;
 }
 
 #[allow(clippy::cognitive_complexity)] // mDNS event handling with peer management
+#[instrument(skip_all)]
 async fn handle_mdns_event(
     swarm: &mut Swarm<WolfpackBehaviour>,
     event: mdns::Event,
     discovered_peers: &mut HashSet<PeerId>,
     event_tx: &mpsc::Sender<NetworkEvent>,
+    lan_only: bool,
 ) {
     match event {
         mdns::Event::Discovered(peers) => {
             for (peer_id, addr) in peers {
+                if lan_only && !is_lan_address(&addr) {
+                    debug!("LAN-only mode: ignoring {} at {}", peer_id, addr);
+                    continue;
+                }
                 if discovered_peers.insert(peer_id) {
                     info!("mDNS discovered peer: {} at {}", peer_id, addr);
-                    swarm
-                        .behaviour_mut()
-                        .kademlia
-                        .add_address(&peer_id, addr.clone());
+                    if let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
+                        kademlia.add_address(&peer_id, addr.clone());
+                    }
                     if let Err(e) = swarm.dial(addr) {
                         warn!("Failed to dial discovered peer: {}", e);
                     }
@@ -422,10 +1028,11 @@ fn handle_kademlia_event(event: kad::Event) {
     }
 }
 
+#[instrument(skip_all)]
 async fn handle_identify_event(
     swarm: &mut Swarm<WolfpackBehaviour>,
     event: identify::Event,
-    peers: &Arc<Mutex<HashMap<PeerId, String>>>,
+    peers: &Arc<Mutex<HashMap<PeerId, PeerInfo>>>,
     event_tx: &mpsc::Sender<NetworkEvent>,
     enable_dht: bool,
 ) {
@@ -433,15 +1040,23 @@ async fn handle_identify_event(
         debug!("Identified peer {}: {:?}", peer_id, info.protocol_version);
 
         // Add addresses to Kademlia
-        if enable_dht {
+        if enable_dht && let Some(kademlia) = swarm.behaviour_mut().kademlia.as_mut() {
             for addr in info.listen_addrs {
-                swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
+                kademlia.add_address(&peer_id, addr);
             }
         }
 
         // Extract device name from agent version if available
         let device_name = info.agent_version.clone();
-        peers.lock().await.insert(peer_id, device_name.clone());
+        if let Some(peer) = peers.lock().await.get_mut(&peer_id) {
+            peer.name = Some(device_name.clone());
+            peer.protocols = info.protocols.iter().map(ToString::to_string).collect();
+            peer.public_key = info
+                .public_key
+                .try_into_ed25519()
+                .ok()
+                .map(|key| hex::encode(key.to_bytes()));
+        }
 
         let _ = event_tx
             .send(NetworkEvent::PeerDiscovered {
@@ -453,15 +1068,23 @@ async fn handle_identify_event(
 }
 
 #[allow(clippy::cognitive_complexity)] // Request-response event handler
+#[instrument(skip_all)]
 async fn handle_sync_event(
     swarm: &mut Swarm<WolfpackBehaviour>,
     event: request_response::Event<SyncRequest, SyncResponse>,
     event_tx: &mpsc::Sender<NetworkEvent>,
     pending_responses: &mut HashMap<request_response::InboundRequestId, PendingResponse>,
+    peers: &Mutex<HashMap<PeerId, PeerInfo>>,
 ) {
     match event {
         request_response::Event::Message { peer, message, .. } => {
-            handle_sync_message(swarm, peer, message, event_tx, pending_responses).await;
+            let device_id = peers
+                .lock()
+                .await
+                .get(&peer)
+                .and_then(|p| p.device_id.clone());
+            let device_id = device_id.as_deref();
+            handle_sync_message(swarm, peer, device_id, message, event_tx, pending_responses).await;
         }
         request_response::Event::OutboundFailure { peer, error, .. } => {
             warn!("Outbound request to {} failed: {:?}", peer, error);
@@ -473,9 +1096,14 @@ async fn handle_sync_event(
     }
 }
 
+/// Handle a message from a peer and the device it's bound to, refusing
+/// requests that claim to come from another device
+#[allow(clippy::cognitive_complexity)] // Message dispatch with logging
+#[allow(clippy::too_many_arguments)] // Protocol handler requires all parameters
 async fn handle_sync_message(
     swarm: &mut Swarm<WolfpackBehaviour>,
     peer: PeerId,
+    device_id: Option<&str>,
     message: request_response::Message<SyncRequest, SyncResponse>,
     event_tx: &mpsc::Sender<NetworkEvent>,
     pending_responses: &mut HashMap<request_response::InboundRequestId, PendingResponse>,
@@ -487,7 +1115,22 @@ async fn handle_sync_message(
             channel,
         } => {
             debug!("Received request from {}: {:?}", peer, request);
-            handle_sync_request(swarm, peer, request_id, request, channel, event_tx, pending_responses).await;
+            if let Err(message) = authenticate_request(&request, device_id) {
+                warn!("Refusing request from {}: {}", peer, message);
+                let response = SyncResponse::Error { message };
+                let _ = swarm.behaviour_mut().sync.send_response(channel, response);
+                return;
+            }
+            handle_sync_request(
+                swarm,
+                peer,
+                request_id,
+                request,
+                channel,
+                event_tx,
+                pending_responses,
+            )
+            .await;
         }
         request_response::Message::Response { response, .. } => {
             handle_sync_response(peer, response, event_tx).await;
@@ -496,6 +1139,8 @@ async fn handle_sync_message(
 }
 
 #[allow(clippy::too_many_arguments)] // Protocol handler requires all parameters
+#[allow(clippy::too_many_lines)] // One arm per request type
+#[instrument(skip_all, fields(%peer))]
 async fn handle_sync_request(
     swarm: &mut Swarm<WolfpackBehaviour>,
     peer: PeerId,
@@ -505,105 +1150,74 @@ async fn handle_sync_request(
     event_tx: &mpsc::Sender<NetworkEvent>,
     pending_responses: &mut HashMap<request_response::InboundRequestId, PendingResponse>,
 ) {
-    match request {
-        SyncRequest::GetClock => {
-            // Store channel for async response
-            pending_responses.insert(
-                request_id,
-                PendingResponse {
-                    channel,
-                    created_at: Instant::now(),
-                },
-            );
-            handle_get_clock(peer, request_id, event_tx).await;
-        }
-        SyncRequest::GetEvents { clock } => {
-            // Store channel for async response
-            pending_responses.insert(
-                request_id,
-                PendingResponse {
-                    channel,
-                    created_at: Instant::now(),
-                },
-            );
-            handle_get_events(peer, request_id, clock, event_tx).await;
-        }
+    // Requests the daemon answers are passed on as events, with the channel
+    // held until it does; the rest are answered here
+    let requested = match request {
+        SyncRequest::GetClock => NetworkEvent::ClockRequested {
+            from: peer,
+            request_id,
+        },
+        SyncRequest::ProveDevice {
+            challenge,
+            public_key,
+        } => NetworkEvent::ProofRequested {
+            from: peer,
+            request_id,
+            challenge,
+            public_key,
+        },
+        SyncRequest::GetEvents { held, after } => NetworkEvent::EventsRequested {
+            from: peer,
+            request_id,
+            held,
+            after,
+        },
+        SyncRequest::JoinPairing {
+            code,
+            device_id,
+            device_name,
+            public_key,
+        } => NetworkEvent::PairingRequested {
+            from: peer,
+            request_id,
+            code,
+            device_id,
+            device_name,
+            public_key,
+        },
         SyncRequest::PushEvents { events } => {
-            // Immediate response - don't store channel
             handle_push_events(swarm, peer, events, channel, event_tx).await;
+            return;
         }
         SyncRequest::SendTab {
             url,
             title,
             from_device,
+            tab_id,
         } => {
-            // Immediate response - don't store channel
             let tab = TabData {
                 url,
                 title,
                 from_device,
+                tab_id,
             };
             handle_send_tab(swarm, peer, tab, channel, event_tx).await;
+            return;
         }
-        SyncRequest::JoinPairing {
-            code,
-            device_id,
-            device_name,
-            public_key,
-        } => {
-            // Store channel for async response
-            pending_responses.insert(
-                request_id,
-                PendingResponse {
-                    channel,
-                    created_at: Instant::now(),
-                },
-            );
-            if let Err(e) = event_tx
-                .send(NetworkEvent::PairingRequested {
-                    from: peer,
-                    request_id,
-                    code,
-                    device_id,
-                    device_name,
-                    public_key,
-                })
-                .await
-            {
-                error!("Failed to send pairing request event: {}", e);
-            }
-        }
+    };
+    pending_responses.insert(
+        request_id,
+        PendingResponse {
+            channel,
+            created_at: Instant::now(),
+        },
+    );
+    if let Err(e) = event_tx.send(requested).await {
+        error!("Failed to pass on request from {}: {}", peer, e);
     }
 }
 
-async fn handle_get_clock(
-    peer: PeerId,
-    request_id: request_response::InboundRequestId,
-    event_tx: &mpsc::Sender<NetworkEvent>,
-) {
-    let _ = event_tx
-        .send(NetworkEvent::ClockRequested {
-            from: peer,
-            request_id,
-        })
-        .await;
-}
-
-async fn handle_get_events(
-    peer: PeerId,
-    request_id: request_response::InboundRequestId,
-    clock: HashMap<String, u64>,
-    event_tx: &mpsc::Sender<NetworkEvent>,
-) {
-    let _ = event_tx
-        .send(NetworkEvent::EventsRequested {
-            from: peer,
-            request_id,
-            clock,
-        })
-        .await;
-}
-
+#[instrument(skip_all, fields(%peer, count = events.len()))]
 async fn handle_push_events(
     swarm: &mut Swarm<WolfpackBehaviour>,
     peer: PeerId,
@@ -611,14 +1225,25 @@ async fn handle_push_events(
     channel: request_response::ResponseChannel<SyncResponse>,
     event_tx: &mpsc::Sender<NetworkEvent>,
 ) {
-    let count = events.len();
-    let _ = event_tx
-        .send(NetworkEvent::EventsReceived { from: peer, events })
-        .await;
-    let _ = swarm
-        .behaviour_mut()
-        .sync
-        .send_response(channel, SyncResponse::Ack { count });
+    // Refuse oversized pushes outright; the sender can split them
+    let response = match check_event_count(&events) {
+        Ok(()) => {
+            let count = events.len();
+            let _ = event_tx
+                .send(NetworkEvent::EventsReceived {
+                    from: peer,
+                    events,
+                    next: None,
+                })
+                .await;
+            SyncResponse::Ack { count }
+        }
+        Err(message) => {
+            warn!("Refusing events from {}: {}", peer, message);
+            SyncResponse::Error { message }
+        }
+    };
+    let _ = swarm.behaviour_mut().sync.send_response(channel, response);
 }
 
 /// Tab data for send_tab requests
@@ -626,8 +1251,10 @@ struct TabData {
     url: String,
     title: Option<String>,
     from_device: String,
+    tab_id: Option<String>,
 }
 
+#[instrument(skip_all, fields(%peer))]
 async fn handle_send_tab(
     swarm: &mut Swarm<WolfpackBehaviour>,
     peer: PeerId,
@@ -641,14 +1268,35 @@ async fn handle_send_tab(
             url: tab.url,
             title: tab.title,
             from_device: tab.from_device,
+            tab_id: tab.tab_id.clone(),
         })
         .await;
-    let _ = swarm
-        .behaviour_mut()
-        .sync
-        .send_response(channel, SyncResponse::TabReceived);
+    let response = match tab.tab_id {
+        Some(tab_id) => SyncResponse::TabDelivered { tab_id },
+        None => SyncResponse::TabReceived,
+    };
+    let _ = swarm.behaviour_mut().sync.send_response(channel, response);
+}
+
+/// Keep the first [`MAX_EVENTS_PER_MESSAGE`] event files of a response
+///
+/// Files come oldest first, so what's dropped comes back on the next exchange
+/// once the clock has caught up.
+fn cap_events(peer: PeerId, mut events: Vec<EncryptedEvent>) -> Vec<EncryptedEvent> {
+    if events.len() > MAX_EVENTS_PER_MESSAGE {
+        warn!(
+            "{} sent {} event files, keeping the first {}",
+            peer,
+            events.len(),
+            MAX_EVENTS_PER_MESSAGE
+        );
+        events.truncate(MAX_EVENTS_PER_MESSAGE);
+    }
+    events
 }
 
+#[allow(clippy::too_many_lines)] // One arm per response type
+#[instrument(skip_all, fields(%peer))]
 async fn handle_sync_response(
     peer: PeerId,
     response: SyncResponse,
@@ -656,9 +1304,27 @@ async fn handle_sync_response(
 ) {
     debug!("Received response from {}: {:?}", peer, response);
     match response {
-        SyncResponse::Events { events } => {
+        SyncResponse::Events { events, next } => {
+            let events = cap_events(peer, events);
+            let received = NetworkEvent::EventsReceived {
+                from: peer,
+                events,
+                next,
+            };
+            let _ = event_tx.send(received).await;
+        }
+        SyncResponse::Clock {
+            clock,
+            device_id,
+            device_name,
+        } => {
             let _ = event_tx
-                .send(NetworkEvent::EventsReceived { from: peer, events })
+                .send(NetworkEvent::ClockReceived {
+                    from: peer,
+                    clock,
+                    device_id,
+                    device_name,
+                })
                 .await;
         }
         SyncResponse::PairingResult {
@@ -677,6 +1343,19 @@ async fn handle_sync_response(
                 })
                 .await;
         }
+        SyncResponse::TabDelivered { tab_id } => {
+            let _ = event_tx
+                .send(NetworkEvent::TabDelivered { from: peer, tab_id })
+                .await;
+        }
+        SyncResponse::DeviceProof { device_id, proof } => {
+            let received = NetworkEvent::DeviceProofReceived {
+                from: peer,
+                device_id,
+                proof,
+            };
+            let _ = event_tx.send(received).await;
+        }
         _ => {
             // Ignore other response types
         }
@@ -685,10 +1364,13 @@ async fn handle_sync_response(
 
 #[allow(clippy::cognitive_complexity)] // Command handler with many variants
 #[allow(clippy::too_many_lines)] // Complete command handling
+#[instrument(skip_all)]
 async fn handle_command(
     swarm: &mut Swarm<WolfpackBehaviour>,
     cmd: NetworkCommand,
     pending_responses: &mut HashMap<request_response::InboundRequestId, PendingResponse>,
+    peers: &Mutex<HashMap<PeerId, PeerInfo>>,
+    lan_only: bool,
 ) {
     match cmd {
         NetworkCommand::GetClock { peer_id } => {
@@ -698,11 +1380,29 @@ async fn handle_command(
                 .send_request(&peer_id, SyncRequest::GetClock);
         }
 
-        NetworkCommand::GetEvents { peer_id, clock } => {
+        NetworkCommand::ProveDevice {
+            peer_id,
+            challenge,
+            public_key,
+        } => {
+            swarm.behaviour_mut().sync.send_request(
+                &peer_id,
+                SyncRequest::ProveDevice {
+                    challenge,
+                    public_key,
+                },
+            );
+        }
+
+        NetworkCommand::GetEvents {
+            peer_id,
+            held,
+            after,
+        } => {
             swarm
                 .behaviour_mut()
                 .sync
-                .send_request(&peer_id, SyncRequest::GetEvents { clock });
+                .send_request(&peer_id, SyncRequest::GetEvents { held, after });
         }
 
         NetworkCommand::PushEvents { peer_id, events } => {
@@ -717,6 +1417,7 @@ async fn handle_command(
             url,
             title,
             from_device,
+            tab_id,
         } => {
             swarm.behaviour_mut().sync.send_request(
                 &peer_id,
@@ -724,6 +1425,7 @@ async fn handle_command(
                     url,
                     title,
                     from_device,
+                    tab_id,
                 },
             );
         }
@@ -740,7 +1442,11 @@ async fn handle_command(
                     device_id,
                     device_name,
                 };
-                if let Err(e) = swarm.behaviour_mut().sync.send_response(pending.channel, response) {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(pending.channel, response)
+                {
                     warn!("Failed to send clock response: {:?}", e);
                 }
             } else {
@@ -748,10 +1454,37 @@ async fn handle_command(
             }
         }
 
-        NetworkCommand::RespondEvents { request_id, events } => {
+        NetworkCommand::RespondProof {
+            request_id,
+            device_id,
+            proof,
+        } => {
             if let Some(pending) = pending_responses.remove(&request_id) {
-                let response = SyncResponse::Events { events };
-                if let Err(e) = swarm.behaviour_mut().sync.send_response(pending.channel, response) {
+                let response = SyncResponse::DeviceProof { device_id, proof };
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(pending.channel, response)
+                {
+                    warn!("Failed to send device proof: {:?}", e);
+                }
+            } else {
+                warn!("No pending response found for request {:?}", request_id);
+            }
+        }
+
+        NetworkCommand::RespondEvents {
+            request_id,
+            events,
+            next,
+        } => {
+            if let Some(pending) = pending_responses.remove(&request_id) {
+                let response = SyncResponse::Events { events, next };
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(pending.channel, response)
+                {
                     warn!("Failed to send events response: {:?}", e);
                 }
             } else {
@@ -760,14 +1493,43 @@ async fn handle_command(
         }
 
         NetworkCommand::Dial { addr } => {
-            if let Err(e) = swarm.dial(addr.clone()) {
+            if lan_only && !is_lan_address(&addr) {
+                warn!("LAN-only mode: not dialing {}", addr);
+            } else if let Err(e) = swarm.dial(addr.clone()) {
                 error!("Failed to dial {}: {}", addr, e);
             }
         }
 
+        NetworkCommand::Disconnect { peer_id } => {
+            if swarm.disconnect_peer_id(peer_id).is_err() {
+                debug!("Peer {} was already disconnected", peer_id);
+            }
+        }
+
+        NetworkCommand::BindDevice { peer_id, device_id } => {
+            if let Some(peer) = peers.lock().await.get_mut(&peer_id) {
+                peer.device_id = Some(device_id);
+            }
+        }
+
+        NetworkCommand::AllowPeer { peer_id } => {
+            swarm.behaviour_mut().gate.allow(peer_id);
+        }
+
+        NetworkCommand::OpenGate { open } => {
+            swarm.behaviour_mut().gate.set_open(open);
+        }
+
         NetworkCommand::AddBootstrapPeer { peer_id, addr } => {
-            swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-            if let Err(e) = swarm.behaviour_mut().kademlia.bootstrap() {
+            let behaviour = swarm.behaviour_mut();
+            let Some(kademlia) = behaviour.kademlia.as_mut() else {
+                warn!("DHT is disabled, ignoring bootstrap peer {}", peer_id);
+                return;
+            };
+            // Bootstrap nodes aren't paired devices, but were asked for by name
+            behaviour.gate.allow(peer_id);
+            kademlia.add_address(&peer_id, addr);
+            if let Err(e) = kademlia.bootstrap() {
                 warn!("Kademlia bootstrap failed: {}", e);
             }
         }
@@ -804,7 +1566,11 @@ async fn handle_command(
                     device_name,
                     public_key,
                 };
-                if let Err(e) = swarm.behaviour_mut().sync.send_response(pending.channel, response) {
+                if let Err(e) = swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(pending.channel, response)
+                {
                     warn!("Failed to send pairing response: {:?}", e);
                 }
             } else {
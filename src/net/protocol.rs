@@ -1,21 +1,74 @@
 use libp2p::request_response;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::io::Read;
+
+use crate::crypto::KeyEpoch;
+use crate::events::HeldFiles;
 
 /// Protocol name for wolfpack sync
 pub const PROTOCOL_NAME: libp2p::StreamProtocol =
     libp2p::StreamProtocol::new("/wolfpack/sync/1.0.0");
 
+/// The sync protocol with optionally zstd-compressed bodies
+///
+/// Offered ahead of [`PROTOCOL_NAME`], so stream negotiation picks it when
+/// both peers support it and falls back to plain JSON otherwise.
+pub const COMPRESSED_PROTOCOL_NAME: libp2p::StreamProtocol =
+    libp2p::StreamProtocol::new("/wolfpack/sync/1.1.0");
+
+/// Bodies smaller than this are sent uncompressed, since they'd barely shrink
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+/// Most a body may take, as read or decompressed
+const MAX_BODY: u64 = 256 * 1024 * 1024;
+
+/// Most event files one push or page of events may carry
+pub const MAX_EVENTS_PER_MESSAGE: usize = 256;
+
+/// A page of events stops taking files once they add up to this many bytes,
+/// though it always takes at least one
+pub const PAGE_BYTES: u64 = 32 * 1024 * 1024;
+
+/// First byte of a body on the compressed protocol
+const BODY_PLAIN: u8 = 0;
+const BODY_ZSTD: u8 = 1;
+
+/// Sync protocols to offer, most preferred first
+pub fn sync_protocols(compression: bool) -> Vec<libp2p::StreamProtocol> {
+    if compression {
+        vec![COMPRESSED_PROTOCOL_NAME, PROTOCOL_NAME]
+    } else {
+        vec![PROTOCOL_NAME]
+    }
+}
+
 /// Request types for the sync protocol
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum SyncRequest {
     /// Request peer's vector clock to compare state
     GetClock,
 
-    /// Request events newer than the given clock
+    /// Ask a peer to prove it holds the key of the device it answered a
+    /// clock request as
+    ProveDevice {
+        /// Random bytes (hex) the proof must answer
+        challenge: String,
+        /// Requester's device public key (hex), the other half of the
+        /// pairwise secret the proof is keyed by
+        public_key: String,
+    },
+
+    /// Request the event files we don't hold
     GetEvents {
-        /// Our current vector clock
-        clock: HashMap<String, u64>,
+        /// The event files we hold from each device
+        #[serde(flatten)]
+        held: HeldFiles,
+        /// Continue after the page that ended here, from the `next` of an
+        /// earlier response
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        after: Option<String>,
     },
 
     /// Send events to peer
@@ -30,8 +83,11 @@ pub enum SyncRequest {
         url: String,
         /// Optional title
         title: Option<String>,
-        /// Sender device name
+        /// Sender device ID, checked against the device the peer is bound to
         from_device: String,
+        /// ID of the tab's `TabSent` event, answered with `TabDelivered`
+        #[serde(default)]
+        tab_id: Option<String>,
     },
 
     /// Request to join a pairing session
@@ -57,8 +113,20 @@ pub enum SyncResponse {
         device_name: String,
     },
 
-    /// Return events the requester is missing
-    Events { events: Vec<EncryptedEvent> },
+    /// Answer a device challenge
+    DeviceProof {
+        device_id: String,
+        /// The proof (hex), from [`crate::crypto::device_proof`]
+        proof: String,
+    },
+
+    /// Return events the requester is missing, a page at a time
+    Events {
+        events: Vec<EncryptedEvent>,
+        /// Where the next page starts, when there's more
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        next: Option<String>,
+    },
 
     /// Acknowledge received events
     Ack { count: usize },
@@ -66,6 +134,9 @@ pub enum SyncResponse {
     /// Acknowledge received tab
     TabReceived,
 
+    /// Acknowledge a received tab that was sent with its ID
+    TabDelivered { tab_id: String },
+
     /// Response to pairing request
     PairingResult {
         /// Status: "accepted", "rejected", "expired", or "invalid_code"
@@ -82,14 +153,22 @@ pub enum SyncResponse {
     Error { message: String },
 }
 
-/// Encrypted event for transport
+/// Most devices an event may be forwarded through on its way from the
+/// device that wrote it
+pub const MAX_HOPS: usize = 4;
+
+/// An event file for transport
+///
+/// Sent as is, still encrypted for the pack, by the device that wrote it or
+/// by any device forwarding it, so devices that can't reach each other sync
+/// through one that reaches both.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EncryptedEvent {
     /// Unique event ID
     pub id: String,
     /// Originating device
     pub device_id: String,
-    /// Vector clock counter for this device
+    /// Number of the originating device's event file
     pub counter: u64,
     /// Encrypted payload (already E2E encrypted)
     pub ciphertext: Vec<u8>,
@@ -99,6 +178,197 @@ pub struct EncryptedEvent {
     pub cipher: u8,
     /// Nonce used for encryption
     pub nonce: Vec<u8>,
+    /// How the group secret was derived (1 = XOR, 2 = HKDF); devices from
+    /// before key epochs only send files keyed under the first
+    #[serde(default = "xor_key_epoch")]
+    pub key_epoch: u8,
+    /// Devices that forwarded the event, in order, the last being the one
+    /// that sent it; empty when sent by the device that wrote it
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hops: Vec<String>,
+    /// Chunks of the XPIs the file's events refer to, which event files only
+    /// name, so a peer that only syncs over P2P can install them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub chunks: Vec<EncryptedChunk>,
+}
+
+/// An XPI chunk as the sending device stores it, still encrypted for the
+/// pack
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptedChunk {
+    /// The chunk's ID, a hash keyed with the group secret
+    pub id: String,
+    pub data: Vec<u8>,
+}
+
+fn xor_key_epoch() -> u8 {
+    KeyEpoch::Xor as u8
+}
+
+impl EncryptedEvent {
+    /// The device that sent the event: its last forwarder, or the device
+    /// that wrote it
+    pub fn sender(&self) -> &str {
+        self.hops.last().unwrap_or(&self.device_id)
+    }
+}
+
+/// Check that an event is worth storing on this device
+///
+/// Its own events never need to come back, and an event that already
+/// passed through this device or went too many hops is going in circles.
+pub fn check_route(event: &EncryptedEvent, device_id: &str) -> Result<(), String> {
+    if event.device_id == device_id {
+        return Err("Event was written by this device".to_string());
+    }
+    if event.hops.iter().any(|hop| hop == device_id) {
+        return Err(format!(
+            "Event from {} already passed through this device",
+            event.device_id
+        ));
+    }
+    if event.hops.len() > MAX_HOPS {
+        return Err(format!(
+            "Event from {} went {} hops, more than {}",
+            event.device_id,
+            event.hops.len(),
+            MAX_HOPS
+        ));
+    }
+    Ok(())
+}
+
+/// Where a page of event files ended: the number and device of its last file
+///
+/// Files are paged in the order they're numbered, so the next page is the
+/// files past this one. Sent as `<number>:<device>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventCursor {
+    pub number: u32,
+    pub device: String,
+}
+
+impl std::fmt::Display for EventCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.number, self.device)
+    }
+}
+
+impl std::str::FromStr for EventCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, device) = s
+            .split_once(':')
+            .ok_or_else(|| format!("Invalid event cursor: {}", s))?;
+        let number = number
+            .parse()
+            .map_err(|_| format!("Invalid event cursor: {}", s))?;
+        Ok(Self {
+            number,
+            device: device.to_string(),
+        })
+    }
+}
+
+/// Check that a push carries no more event files than one message may
+pub fn check_event_count(events: &[EncryptedEvent]) -> Result<(), String> {
+    if events.len() > MAX_EVENTS_PER_MESSAGE {
+        return Err(format!(
+            "{} event files in one message is over the limit of {}",
+            events.len(),
+            MAX_EVENTS_PER_MESSAGE
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a request only speaks for the device its peer is bound to
+///
+/// `SendTab` names the device sending it and pushed events name the device
+/// that wrote or last forwarded them; neither is taken on the peer's word.
+/// Other requests don't claim a device.
+pub fn authenticate_request(request: &SyncRequest, device_id: Option<&str>) -> Result<(), String> {
+    let claimed: Vec<&str> = match request {
+        SyncRequest::SendTab { from_device, .. } => vec![from_device],
+        SyncRequest::PushEvents { events } => events.iter().map(EncryptedEvent::sender).collect(),
+        _ => return Ok(()),
+    };
+    let Some(device_id) = device_id else {
+        return Err("Peer isn't bound to a device yet".to_string());
+    };
+    match claimed.into_iter().find(|claimed| *claimed != device_id) {
+        Some(claimed) => Err(format!("Peer is device {}, not {}", device_id, claimed)),
+        None => Ok(()),
+    }
+}
+
+/// Serialize a message for the negotiated protocol
+///
+/// On the compressed protocol the JSON is prefixed with a byte saying
+/// whether it was compressed, which it is once it reaches
+/// [`COMPRESSION_THRESHOLD`].
+pub fn encode_body<T: Serialize>(
+    protocol: &libp2p::StreamProtocol,
+    message: &T,
+) -> std::io::Result<Vec<u8>> {
+    let json = serde_json::to_vec(message).map_err(invalid_data)?;
+    if *protocol != COMPRESSED_PROTOCOL_NAME {
+        return Ok(json);
+    }
+    if json.len() < COMPRESSION_THRESHOLD {
+        return Ok([&[BODY_PLAIN], json.as_slice()].concat());
+    }
+    let mut body = vec![BODY_ZSTD];
+    body.extend(zstd::encode_all(json.as_slice(), 3)?);
+    Ok(body)
+}
+
+/// Deserialize a message read on the negotiated protocol
+pub fn decode_body<T: DeserializeOwned>(
+    protocol: &libp2p::StreamProtocol,
+    body: &[u8],
+) -> std::io::Result<T> {
+    if *protocol != COMPRESSED_PROTOCOL_NAME {
+        return serde_json::from_slice(body).map_err(invalid_data);
+    }
+    match body.split_first() {
+        Some((&BODY_PLAIN, json)) => serde_json::from_slice(json).map_err(invalid_data),
+        Some((&BODY_ZSTD, compressed)) => {
+            let mut json = Vec::new();
+            zstd::Decoder::new(compressed)?
+                .take(MAX_BODY + 1)
+                .read_to_end(&mut json)?;
+            if json.len() as u64 > MAX_BODY {
+                return Err(invalid_data("Compressed message is too large"));
+            }
+            serde_json::from_slice(&json).map_err(invalid_data)
+        }
+        Some((flag, _)) => Err(invalid_data(format!("Unknown body encoding {}", flag))),
+        None => Err(invalid_data("Empty message")),
+    }
+}
+
+/// Read a whole message, refusing one over [`MAX_BODY`] rather than holding
+/// it in memory
+async fn read_body<T>(io: &mut T) -> std::io::Result<Vec<u8>>
+where
+    T: futures::AsyncRead + Unpin + Send,
+{
+    use futures::AsyncReadExt;
+    let mut buf = Vec::new();
+    io.take(MAX_BODY + 1).read_to_end(&mut buf).await?;
+    if buf.len() as u64 > MAX_BODY {
+        return Err(invalid_data("Message is too large"));
+    }
+    Ok(buf)
+}
+
+fn invalid_data<E>(error: E) -> std::io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    std::io::Error::new(std::io::ErrorKind::InvalidData, error)
 }
 
 /// Codec for serializing/deserializing sync messages
@@ -112,7 +382,7 @@ impl request_response::Codec for SyncCodec {
 
     fn read_request<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _protocol: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
     ) -> std::pin::Pin<
         Box<dyn std::future::Future<Output = std::io::Result<Self::Request>> + Send + 'async_trait>,
@@ -125,16 +395,14 @@ impl request_response::Codec for SyncCodec {
         Self: 'async_trait,
     {
         Box::pin(async move {
-            let mut buf = Vec::new();
-            futures::AsyncReadExt::read_to_end(io, &mut buf).await?;
-            serde_json::from_slice(&buf)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            let buf = read_body(io).await?;
+            decode_body(protocol, &buf)
         })
     }
 
     fn read_response<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _protocol: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
     ) -> std::pin::Pin<
         Box<
@@ -149,16 +417,14 @@ impl request_response::Codec for SyncCodec {
         Self: 'async_trait,
     {
         Box::pin(async move {
-            let mut buf = Vec::new();
-            futures::AsyncReadExt::read_to_end(io, &mut buf).await?;
-            serde_json::from_slice(&buf)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+            let buf = read_body(io).await?;
+            decode_body(protocol, &buf)
         })
     }
 
     fn write_request<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _protocol: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
         req: Self::Request,
     ) -> std::pin::Pin<
@@ -172,8 +438,7 @@ impl request_response::Codec for SyncCodec {
         Self: 'async_trait,
     {
         Box::pin(async move {
-            let buf = serde_json::to_vec(&req)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let buf = encode_body(protocol, &req)?;
             futures::AsyncWriteExt::write_all(io, &buf).await?;
             futures::AsyncWriteExt::close(io).await?;
             Ok(())
@@ -182,7 +447,7 @@ impl request_response::Codec for SyncCodec {
 
     fn write_response<'life0, 'life1, 'life2, 'async_trait, T>(
         &'life0 mut self,
-        _protocol: &'life1 Self::Protocol,
+        protocol: &'life1 Self::Protocol,
         io: &'life2 mut T,
         res: Self::Response,
     ) -> std::pin::Pin<
@@ -196,8 +461,7 @@ impl request_response::Codec for SyncCodec {
         Self: 'async_trait,
     {
         Box::pin(async move {
-            let buf = serde_json::to_vec(&res)
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let buf = encode_body(protocol, &res)?;
             futures::AsyncWriteExt::write_all(io, &buf).await?;
             futures::AsyncWriteExt::close(io).await?;
             Ok(())
@@ -223,18 +487,57 @@ mod tests {
     }
 
     #[test]
-    fn test_sync_request_get_events_serialize() {
-        let mut clock = HashMap::new();
-        clock.insert("device-a".to_string(), 5);
-        clock.insert("device-b".to_string(), 3);
+    fn test_prove_device_serialize() {
+        let req = SyncRequest::ProveDevice {
+            challenge: "00ff".to_string(),
+            public_key: "abcd".to_string(),
+        };
+        let json = serde_json::to_string(&req).unwrap();
+        let parsed: SyncRequest = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            parsed,
+            SyncRequest::ProveDevice { challenge, public_key }
+                if challenge == "00ff" && public_key == "abcd"
+        ));
+        assert!(authenticate_request(&req, None).is_ok());
+
+        let resp = SyncResponse::DeviceProof {
+            device_id: "device-a".to_string(),
+            proof: "1234".to_string(),
+        };
+        let json = serde_json::to_string(&resp).unwrap();
+        let parsed: SyncResponse = serde_json::from_str(&json).unwrap();
+        assert!(matches!(
+            parsed,
+            SyncResponse::DeviceProof { device_id, proof }
+                if device_id == "device-a" && proof == "1234"
+        ));
+    }
 
-        let req = SyncRequest::GetEvents { clock };
+    #[test]
+    fn test_sync_request_get_events_serialize() {
+        let mut held = HeldFiles::default();
+        held.highest.insert("device-a".to_string(), 5);
+        held.highest.insert("device-b".to_string(), 3);
+        held.names.insert(
+            "device-a".to_string(),
+            ["0005-1a2b3c4d.evt".to_string()].into(),
+        );
+
+        let req = SyncRequest::GetEvents {
+            held,
+            after: Some("4:device-b".to_string()),
+        };
         let json = serde_json::to_string(&req).unwrap();
+        assert!(json.contains(r#""clock":{"#));
         let parsed: SyncRequest = serde_json::from_str(&json).unwrap();
 
-        if let SyncRequest::GetEvents { clock } = parsed {
-            assert_eq!(clock.get("device-a"), Some(&5));
-            assert_eq!(clock.get("device-b"), Some(&3));
+        if let SyncRequest::GetEvents { held, after } = parsed {
+            assert_eq!(held.highest.get("device-a"), Some(&5));
+            assert_eq!(held.highest.get("device-b"), Some(&3));
+            assert!(held.holds("device-a", 5, "0005-1a2b3c4d.evt"));
+            assert!(!held.holds("device-a", 3, "0003-1a2b3c4d.evt"));
+            assert_eq!(after.as_deref(), Some("4:device-b"));
         } else {
             panic!("Expected GetEvents");
         }
@@ -250,6 +553,9 @@ mod tests {
             public_key: vec![4, 5, 6],
             cipher: 1,
             nonce: vec![7, 8, 9],
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
         }];
 
         let req = SyncRequest::PushEvents { events };
@@ -273,6 +579,7 @@ mod tests {
             url: "https://example.com".to_string(),
             title: Some("Example".to_string()),
             from_device: "device-a".to_string(),
+            tab_id: Some("tab-1".to_string()),
         };
 
         let json = serde_json::to_string(&req).unwrap();
@@ -282,11 +589,13 @@ mod tests {
             url,
             title,
             from_device,
+            tab_id,
         } = parsed
         {
             assert_eq!(url, "https://example.com");
             assert_eq!(title, Some("Example".to_string()));
             assert_eq!(from_device, "device-a");
+            assert_eq!(tab_id, Some("tab-1".to_string()));
         } else {
             panic!("Expected SendTab");
         }
@@ -298,6 +607,7 @@ mod tests {
             url: "https://example.com".to_string(),
             title: None,
             from_device: "device-a".to_string(),
+            tab_id: None,
         };
 
         let json = serde_json::to_string(&req).unwrap();
@@ -348,14 +658,18 @@ mod tests {
             public_key: vec![4, 5, 6],
             cipher: 2, // XChaCha20
             nonce: vec![7, 8, 9],
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
         }];
 
-        let res = SyncResponse::Events { events };
+        let res = SyncResponse::Events { events, next: None };
         let json = serde_json::to_string(&res).unwrap();
         let parsed: SyncResponse = serde_json::from_str(&json).unwrap();
 
-        if let SyncResponse::Events { events } = parsed {
+        if let SyncResponse::Events { events, next } = parsed {
             assert_eq!(events.len(), 1);
+            assert!(next.is_none());
             assert_eq!(events[0].cipher, 2);
         } else {
             panic!("Expected Events");
@@ -383,6 +697,15 @@ mod tests {
         assert!(matches!(parsed, SyncResponse::TabReceived));
     }
 
+    #[test]
+    fn test_sync_request_send_tab_without_id() {
+        // Peers that don't track delivery send no tab ID
+        let json =
+            r#"{"SendTab":{"url":"https://example.com","title":null,"from_device":"device-a"}}"#;
+        let parsed: SyncRequest = serde_json::from_str(json).unwrap();
+        assert!(matches!(parsed, SyncRequest::SendTab { tab_id: None, .. }));
+    }
+
     #[test]
     fn test_sync_response_error_serialize() {
         let res = SyncResponse::Error {
@@ -408,6 +731,9 @@ mod tests {
             public_key: vec![0x01, 0x02, 0x03],
             cipher: 1,
             nonce: vec![0x0a, 0x0b, 0x0c],
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
         };
 
         let json = serde_json::to_string(&event).unwrap();
@@ -422,6 +748,173 @@ mod tests {
         assert_eq!(parsed.nonce, vec![0x0a, 0x0b, 0x0c]);
     }
 
+    #[test]
+    fn test_authenticate_request() {
+        let tab = |from_device: &str| SyncRequest::SendTab {
+            url: "https://example.com".to_string(),
+            title: None,
+            from_device: from_device.to_string(),
+            tab_id: None,
+        };
+        assert!(authenticate_request(&tab("device-a"), Some("device-a")).is_ok());
+        assert_eq!(
+            authenticate_request(&tab("device-b"), Some("device-a")),
+            Err("Peer is device device-a, not device-b".to_string())
+        );
+        assert!(authenticate_request(&tab("device-a"), None).is_err());
+
+        let event = |device_id: &str| EncryptedEvent {
+            id: "event-1".to_string(),
+            device_id: device_id.to_string(),
+            counter: 1,
+            ciphertext: Vec::new(),
+            public_key: Vec::new(),
+            cipher: 1,
+            nonce: Vec::new(),
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
+        };
+        let own = SyncRequest::PushEvents {
+            events: vec![event("device-a"), event("device-a")],
+        };
+        assert!(authenticate_request(&own, Some("device-a")).is_ok());
+        let forged = SyncRequest::PushEvents {
+            events: vec![event("device-a"), event("device-b")],
+        };
+        assert!(authenticate_request(&forged, Some("device-a")).is_err());
+
+        // Forwarded events are sent as their last hop
+        let mut forwarded = event("device-b");
+        forwarded.hops = vec!["device-c".to_string(), "device-a".to_string()];
+        let relay = SyncRequest::PushEvents {
+            events: vec![event("device-a"), forwarded],
+        };
+        assert!(authenticate_request(&relay, Some("device-a")).is_ok());
+        assert!(authenticate_request(&relay, Some("device-c")).is_err());
+
+        // Requests that don't claim a device need no binding
+        assert!(authenticate_request(&SyncRequest::GetClock, None).is_ok());
+    }
+
+    #[test]
+    fn test_check_route() {
+        let event = |hops: &[&str]| EncryptedEvent {
+            id: "0001".to_string(),
+            device_id: "device-a".to_string(),
+            counter: 1,
+            ciphertext: Vec::new(),
+            public_key: Vec::new(),
+            cipher: 1,
+            nonce: Vec::new(),
+            key_epoch: 2,
+            hops: hops.iter().map(|hop| hop.to_string()).collect(),
+            chunks: Vec::new(),
+        };
+
+        assert!(check_route(&event(&[]), "device-c").is_ok());
+        assert!(check_route(&event(&["device-b"]), "device-c").is_ok());
+        assert!(check_route(&event(&[]), "device-a").is_err());
+
+        let looped = check_route(&event(&["device-c", "device-b"]), "device-c").unwrap_err();
+        assert!(looped.contains("already passed through"));
+        let far = event(&["b", "c", "d", "e", "f"]);
+        assert!(
+            check_route(&far, "device-c")
+                .unwrap_err()
+                .contains("5 hops")
+        );
+    }
+
+    #[test]
+    fn test_compressed_bodies() {
+        let small = SyncRequest::GetClock;
+        let large = SyncResponse::Events {
+            events: (0..50)
+                .map(|i| EncryptedEvent {
+                    id: format!("event-{}", i),
+                    device_id: "device-a".to_string(),
+                    counter: i,
+                    ciphertext: vec![0xab; 64],
+                    public_key: vec![0x01; 32],
+                    cipher: 1,
+                    nonce: vec![0x0a; 12],
+                    key_epoch: 2,
+                    hops: Vec::new(),
+                    chunks: Vec::new(),
+                })
+                .collect(),
+            next: None,
+        };
+
+        // The original protocol is plain JSON
+        let body = encode_body(&PROTOCOL_NAME, &large).unwrap();
+        assert_eq!(body, serde_json::to_vec(&large).unwrap());
+
+        // Small bodies aren't worth compressing
+        let body = encode_body(&COMPRESSED_PROTOCOL_NAME, &small).unwrap();
+        assert_eq!(body[0], BODY_PLAIN);
+        let decoded: SyncRequest = decode_body(&COMPRESSED_PROTOCOL_NAME, &body).unwrap();
+        assert!(matches!(decoded, SyncRequest::GetClock));
+
+        let body = encode_body(&COMPRESSED_PROTOCOL_NAME, &large).unwrap();
+        assert_eq!(body[0], BODY_ZSTD);
+        assert!(body.len() < serde_json::to_vec(&large).unwrap().len() / 2);
+        let decoded: SyncResponse = decode_body(&COMPRESSED_PROTOCOL_NAME, &body).unwrap();
+        assert!(matches!(decoded, SyncResponse::Events { events, .. } if events.len() == 50));
+
+        let unknown = [&[7u8], &body[1..]].concat();
+        assert!(decode_body::<SyncResponse>(&COMPRESSED_PROTOCOL_NAME, &unknown).is_err());
+        assert!(decode_body::<SyncResponse>(&COMPRESSED_PROTOCOL_NAME, &[]).is_err());
+    }
+
+    #[test]
+    fn test_event_paging() {
+        let cursor: EventCursor = "12:device-a".parse().unwrap();
+        assert_eq!(cursor.number, 12);
+        assert_eq!(cursor.device, "device-a");
+        assert_eq!(cursor.to_string(), "12:device-a");
+        assert!("device-a".parse::<EventCursor>().is_err());
+        assert!("x:device-a".parse::<EventCursor>().is_err());
+
+        // Messages from peers without paging read as a single page
+        let old: SyncResponse = serde_json::from_str(r#"{"Events":{"events":[]}}"#).unwrap();
+        assert!(matches!(old, SyncResponse::Events { next: None, .. }));
+        let old: SyncRequest =
+            serde_json::from_str(r#"{"GetEvents":{"clock":{"device-a":2}}}"#).unwrap();
+        let SyncRequest::GetEvents { held, after: None } = old else {
+            panic!("Expected GetEvents");
+        };
+        // Without names, files are held by number
+        assert!(held.names.is_empty());
+        assert!(held.holds("device-a", 1, "0001-1a2b3c4d.evt"));
+        assert!(!held.holds("device-a", 3, "0003-1a2b3c4d.evt"));
+
+        let event = EncryptedEvent {
+            id: "0001".to_string(),
+            device_id: "device-a".to_string(),
+            counter: 1,
+            ciphertext: Vec::new(),
+            public_key: Vec::new(),
+            cipher: 1,
+            nonce: Vec::new(),
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
+        };
+        assert!(check_event_count(&vec![event.clone(); MAX_EVENTS_PER_MESSAGE]).is_ok());
+        assert!(check_event_count(&vec![event; MAX_EVENTS_PER_MESSAGE + 1]).is_err());
+    }
+
+    #[test]
+    fn test_sync_protocols() {
+        assert_eq!(
+            sync_protocols(true),
+            vec![COMPRESSED_PROTOCOL_NAME, PROTOCOL_NAME]
+        );
+        assert_eq!(sync_protocols(false), vec![PROTOCOL_NAME]);
+    }
+
     #[test]
     fn test_sync_codec_default() {
         let codec = SyncCodec;
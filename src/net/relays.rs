@@ -0,0 +1,226 @@
+use anyhow::{Context, Result};
+use libp2p::core::transport::ListenerId;
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, PeerId};
+use std::time::Duration;
+
+/// How much faster another relay has to be before a working one is left
+const SWITCH_MARGIN: Duration = Duration::from_millis(50);
+
+/// Parse a relay address from `sync.relays`, which has to name the relay's
+/// peer ID for its reservation to be checked against
+pub fn parse_relay(addr: &str) -> Result<(PeerId, Multiaddr)> {
+    let multiaddr: Multiaddr = addr
+        .parse()
+        .with_context(|| format!("Invalid multiaddr '{}' in sync.relays", addr))?;
+    match multiaddr.iter().last() {
+        Some(Protocol::P2p(peer_id)) => Ok((peer_id, multiaddr)),
+        _ => anyhow::bail!("Relay '{}' in sync.relays must end in /p2p/<peer id>", addr),
+    }
+}
+
+/// A configured relay, and what health checks found
+#[derive(Debug, Clone)]
+struct Relay {
+    peer_id: PeerId,
+    addr: Multiaddr,
+    /// Connected, and hasn't refused a reservation since
+    reachable: bool,
+    /// Latest ping round trip
+    rtt: Option<Duration>,
+}
+
+/// Picks which relay in `sync.relays` to be reachable through
+///
+/// Relays are dialed as health checks and pinged while connected. The
+/// reachable one with the lowest latency is used, and when it goes down or
+/// refuses a reservation, the next best takes over. A relay that's working
+/// is only left for one that's clearly faster, so similar relays don't
+/// trade places on every ping.
+#[derive(Debug, Default)]
+pub struct RelaySelector {
+    relays: Vec<Relay>,
+    /// The relay listened through, and its listener
+    active: Option<(PeerId, ListenerId)>,
+}
+
+impl RelaySelector {
+    pub fn new(relays: &[(PeerId, Multiaddr)]) -> Self {
+        Self {
+            relays: relays
+                .iter()
+                .map(|(peer_id, addr)| Relay {
+                    peer_id: *peer_id,
+                    addr: addr.clone(),
+                    reachable: false,
+                    rtt: None,
+                })
+                .collect(),
+            active: None,
+        }
+    }
+
+    /// Addresses of relays to dial, to find out if they're back
+    pub fn unreachable(&self) -> Vec<Multiaddr> {
+        self.relays
+            .iter()
+            .filter(|relay| !relay.reachable)
+            .map(|relay| relay.addr.clone())
+            .collect()
+    }
+
+    /// The relay in use, if any
+    pub fn active(&self) -> Option<PeerId> {
+        self.active.map(|(peer_id, _)| peer_id)
+    }
+
+    /// Note a connection to a peer, returning whether it's a relay
+    pub fn connected(&mut self, peer_id: &PeerId) -> bool {
+        self.relay_mut(peer_id)
+            .map(|relay| relay.reachable = true)
+            .is_some()
+    }
+
+    /// Note that a relay can't be reached, returning whether it was the one
+    /// in use
+    pub fn unreachable_relay(&mut self, peer_id: &PeerId) -> bool {
+        let Some(relay) = self.relay_mut(peer_id) else {
+            return false;
+        };
+        relay.reachable = false;
+        relay.rtt = None;
+        self.active() == Some(*peer_id)
+    }
+
+    pub fn record_rtt(&mut self, peer_id: &PeerId, rtt: Duration) {
+        if let Some(relay) = self.relay_mut(peer_id) {
+            relay.rtt = Some(rtt);
+        }
+    }
+
+    /// Forget the relay in use when its listener closes, which is how a
+    /// refused or lost reservation shows up. Returns whether it was in use.
+    pub fn listener_closed(&mut self, listener: ListenerId) -> bool {
+        let Some((peer_id, _)) = self.active.filter(|(_, id)| *id == listener) else {
+            return false;
+        };
+        self.active = None;
+        if let Some(relay) = self.relay_mut(&peer_id) {
+            relay.reachable = false;
+        }
+        true
+    }
+
+    /// The relay to move to, with its circuit address, if it should change
+    pub fn choose(&self) -> Option<(PeerId, Multiaddr)> {
+        let best = self
+            .relays
+            .iter()
+            .filter(|relay| relay.reachable)
+            .min_by_key(|relay| relay.rtt.unwrap_or(Duration::MAX))?;
+        let active = self.active().and_then(|peer_id| self.relay(&peer_id));
+
+        let switch = match active {
+            Some(active) if active.peer_id == best.peer_id => false,
+            Some(active) if active.reachable => match (best.rtt, active.rtt) {
+                (Some(best), Some(active)) => best + SWITCH_MARGIN < active,
+                (Some(_), None) => true,
+                _ => false,
+            },
+            _ => true,
+        };
+        switch.then(|| (best.peer_id, best.addr.clone().with(Protocol::P2pCircuit)))
+    }
+
+    /// Record the relay now listened through, returning the listener of the
+    /// one it replaces
+    pub fn activate(&mut self, peer_id: PeerId, listener: ListenerId) -> Option<ListenerId> {
+        self.active
+            .replace((peer_id, listener))
+            .map(|(_, previous)| previous)
+    }
+
+    fn relay(&self, peer_id: &PeerId) -> Option<&Relay> {
+        self.relays.iter().find(|relay| relay.peer_id == *peer_id)
+    }
+
+    fn relay_mut(&mut self, peer_id: &PeerId) -> Option<&mut Relay> {
+        self.relays
+            .iter_mut()
+            .find(|relay| relay.peer_id == *peer_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relay() -> (PeerId, Multiaddr) {
+        let peer_id = PeerId::random();
+        let addr = format!("/ip4/203.0.113.1/tcp/4001/p2p/{}", peer_id);
+        parse_relay(&addr).unwrap()
+    }
+
+    #[test]
+    fn test_parse_relay() {
+        let (peer_id, addr) = relay();
+        assert!(addr.to_string().ends_with(&peer_id.to_string()));
+        assert!(parse_relay("/ip4/203.0.113.1/tcp/4001").is_err());
+        assert!(parse_relay("not an address").is_err());
+    }
+
+    #[test]
+    fn test_prefers_lowest_latency() {
+        let (slow, fast) = (relay(), relay());
+        let mut selector = RelaySelector::new(&[slow.clone(), fast.clone()]);
+        assert_eq!(selector.choose(), None);
+        assert_eq!(selector.unreachable().len(), 2);
+
+        assert!(selector.connected(&slow.0));
+        assert!(!selector.connected(&PeerId::random()));
+        selector.record_rtt(&slow.0, Duration::from_millis(200));
+        let (chosen, circuit) = selector.choose().unwrap();
+        assert_eq!(chosen, slow.0);
+        assert_eq!(circuit.iter().last(), Some(Protocol::P2pCircuit));
+        assert_eq!(selector.activate(chosen, ListenerId::next()), None);
+        assert_eq!(selector.choose(), None);
+
+        // A slightly faster relay isn't worth moving for, a much faster one is
+        selector.connected(&fast.0);
+        selector.record_rtt(&fast.0, Duration::from_millis(180));
+        assert_eq!(selector.choose(), None);
+        selector.record_rtt(&fast.0, Duration::from_millis(40));
+        assert_eq!(selector.choose().unwrap().0, fast.0);
+    }
+
+    #[test]
+    fn test_fails_over() {
+        let (first, second) = (relay(), relay());
+        let mut selector = RelaySelector::new(&[first.clone(), second.clone()]);
+        selector.connected(&first.0);
+        selector.connected(&second.0);
+        selector.record_rtt(&first.0, Duration::from_millis(20));
+        selector.record_rtt(&second.0, Duration::from_millis(90));
+
+        let listener = ListenerId::next();
+        selector.activate(selector.choose().unwrap().0, listener);
+        assert_eq!(selector.active(), Some(first.0));
+
+        // The active relay dying hands over to the next
+        assert!(selector.unreachable_relay(&first.0));
+        assert_eq!(selector.choose().unwrap().0, second.0);
+        let replacement = ListenerId::next();
+        assert_eq!(selector.activate(second.0, replacement), Some(listener));
+        assert_eq!(selector.unreachable(), vec![first.1.clone()]);
+
+        // A refused reservation closes the listener
+        assert!(!selector.listener_closed(listener));
+        assert!(selector.listener_closed(replacement));
+        assert_eq!(selector.active(), None);
+        assert_eq!(selector.choose(), None);
+
+        // The first relay coming back is used again
+        selector.connected(&first.0);
+        assert_eq!(selector.choose().unwrap().0, first.0);
+    }
+}
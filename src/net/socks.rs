@@ -0,0 +1,296 @@
+use anyhow::{Context as _, Result};
+use futures::future::BoxFuture;
+use libp2p::core::transport::{DialOpts, ListenerId, TransportError, TransportEvent};
+use libp2p::multiaddr::Protocol;
+use libp2p::{Multiaddr, Transport, tcp};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+/// Where a dial through the proxy should end up
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    Ip(IpAddr),
+    /// Left for the proxy to resolve, so DNS and `.onion` lookups don't leak
+    Domain(String),
+}
+
+/// TCP transport that dials through a SOCKS5 proxy such as Tor
+///
+/// Listening stays on plain TCP so a Tor hidden service can forward to it;
+/// every outgoing connection, including to DNS and `.onion` addresses, goes
+/// through the proxy.
+pub struct Socks5Transport {
+    proxy: String,
+    listener: tcp::tokio::Transport,
+}
+
+impl Socks5Transport {
+    pub fn new(proxy: &str) -> Self {
+        Self {
+            proxy: proxy.to_string(),
+            listener: tcp::tokio::Transport::new(tcp::Config::default()),
+        }
+    }
+}
+
+impl Transport for Socks5Transport {
+    type Output = tcp::tokio::TcpStream;
+    type Error = io::Error;
+    type ListenerUpgrade = <tcp::tokio::Transport as Transport>::ListenerUpgrade;
+    type Dial = BoxFuture<'static, io::Result<Self::Output>>;
+
+    fn listen_on(
+        &mut self,
+        id: ListenerId,
+        addr: Multiaddr,
+    ) -> Result<(), TransportError<Self::Error>> {
+        self.listener.listen_on(id, addr)
+    }
+
+    fn remove_listener(&mut self, id: ListenerId) -> bool {
+        self.listener.remove_listener(id)
+    }
+
+    fn dial(
+        &mut self,
+        addr: Multiaddr,
+        _opts: DialOpts,
+    ) -> Result<Self::Dial, TransportError<Self::Error>> {
+        let Some((target, port)) = dial_target(&addr) else {
+            return Err(TransportError::MultiaddrNotSupported(addr));
+        };
+        let proxy = self.proxy.clone();
+        Ok(Box::pin(async move {
+            connect(&proxy, &target, port)
+                .await
+                .map(tcp::tokio::TcpStream)
+        }))
+    }
+
+    fn poll(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<TransportEvent<Self::ListenerUpgrade, Self::Error>> {
+        Pin::new(&mut self.listener).poll(cx)
+    }
+}
+
+/// The loopback address a proxied node listens on
+///
+/// Nothing is reachable directly; peers come in through the hidden service
+/// forwarding to this port.
+pub fn listen_address(port: u16) -> Multiaddr {
+    Multiaddr::empty()
+        .with(Protocol::Ip4(Ipv4Addr::LOCALHOST))
+        .with(Protocol::Tcp(port))
+}
+
+/// Parse an onion endpoint (`<address>.onion:<port>`), as published in
+/// device records, into a multiaddr to dial through the proxy
+pub fn onion_multiaddr(endpoint: &str) -> Result<Multiaddr> {
+    let invalid = || {
+        format!(
+            "Invalid onion endpoint (expected <address>.onion:<port>): {}",
+            endpoint
+        )
+    };
+    let (host, port) = endpoint.rsplit_once(':').with_context(invalid)?;
+    let address = host.strip_suffix(".onion").with_context(invalid)?;
+    format!("/onion3/{}:{}", address.to_lowercase(), port)
+        .parse()
+        .with_context(invalid)
+}
+
+/// Parse a TCP multiaddr (optionally ending in `/p2p/...`) into a target
+fn dial_target(addr: &Multiaddr) -> Option<(Target, u16)> {
+    let mut protocols = addr.iter();
+    let (target, port) = match protocols.next()? {
+        Protocol::Onion3(onion) => {
+            let host = data_encoding::BASE32.encode(onion.hash()).to_lowercase();
+            (Target::Domain(format!("{}.onion", host)), onion.port())
+        }
+        first => {
+            let target = match first {
+                Protocol::Ip4(ip) => Target::Ip(IpAddr::V4(ip)),
+                Protocol::Ip6(ip) => Target::Ip(IpAddr::V6(ip)),
+                Protocol::Dns(host) | Protocol::Dns4(host) | Protocol::Dns6(host) => {
+                    Target::Domain(host.to_string())
+                }
+                _ => return None,
+            };
+            let Some(Protocol::Tcp(port)) = protocols.next() else {
+                return None;
+            };
+            (target, port)
+        }
+    };
+
+    let rest: Vec<Protocol> = protocols.collect();
+    matches!(rest.as_slice(), [] | [Protocol::P2p(_)]).then_some((target, port))
+}
+
+/// Encode a SOCKS5 CONNECT request
+fn connect_request(target: &Target, port: u16) -> io::Result<Vec<u8>> {
+    let mut request = vec![SOCKS_VERSION, CONNECT, 0];
+    match target {
+        Target::Ip(IpAddr::V4(ip)) => {
+            request.push(ATYP_IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Target::Ip(IpAddr::V6(ip)) => {
+            request.push(ATYP_IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Target::Domain(host) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Host name too long"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+/// Open a connection to the target through the proxy
+async fn connect(proxy: &str, target: &Target, port: u16) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    stream.write_all(&[SOCKS_VERSION, 1, NO_AUTH]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice != [SOCKS_VERSION, NO_AUTH] {
+        return Err(proxy_error("Proxy requires authentication"));
+    }
+
+    stream.write_all(&connect_request(target, port)?).await?;
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("Not a SOCKS5 proxy"));
+    }
+    if reply[1] != 0 {
+        return Err(proxy_error(reply_message(reply[1])));
+    }
+
+    // Skip the address the proxy bound, which is of no use to us
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => usize::from(stream.read_u8().await?),
+        _ => return Err(proxy_error("Invalid address in proxy reply")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(stream)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "Proxy server failure",
+        2 => "Connection not allowed by proxy",
+        3 => "Network unreachable",
+        4 => "Host unreachable",
+        5 => "Connection refused",
+        6 => "TTL expired",
+        7 => "Command not supported by proxy",
+        8 => "Address type not supported by proxy",
+        _ => "Unknown proxy error",
+    }
+}
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionRefused, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(s: &str) -> Option<(Target, u16)> {
+        dial_target(&s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_dial_target() {
+        assert_eq!(
+            target("/ip4/203.0.113.7/tcp/4001"),
+            Some((Target::Ip("203.0.113.7".parse().unwrap()), 4001))
+        );
+        assert_eq!(
+            target(
+                "/dns4/example.com/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN"
+            ),
+            Some((Target::Domain("example.com".to_string()), 4001))
+        );
+        assert_eq!(
+            target("/onion3/vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd:1234"),
+            Some((
+                Target::Domain(
+                    "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion".to_string()
+                ),
+                1234
+            ))
+        );
+
+        // Only TCP can go through the proxy
+        assert_eq!(target("/ip4/203.0.113.7/udp/4001/quic-v1"), None);
+        assert_eq!(
+            target(
+                "/ip4/203.0.113.7/tcp/4001/p2p/12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6nXTN/p2p-circuit"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_onion_multiaddr() {
+        let addr =
+            onion_multiaddr("vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:4001")
+                .unwrap();
+        assert_eq!(
+            dial_target(&addr),
+            Some((
+                Target::Domain(
+                    "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion".to_string()
+                ),
+                4001
+            ))
+        );
+
+        assert!(
+            onion_multiaddr("vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion")
+                .is_err()
+        );
+        assert!(onion_multiaddr("example.com:4001").is_err());
+        assert!(onion_multiaddr("tooshort.onion:4001").is_err());
+    }
+
+    #[test]
+    fn test_connect_request() {
+        assert_eq!(
+            connect_request(&Target::Ip("10.0.0.1".parse().unwrap()), 4001).unwrap(),
+            vec![5, 1, 0, ATYP_IPV4, 10, 0, 0, 1, 0x0f, 0xa1]
+        );
+
+        let request = connect_request(&Target::Domain("peer.onion".to_string()), 80).unwrap();
+        assert_eq!(&request[..5], &[5, 1, 0, ATYP_DOMAIN, 10]);
+        assert_eq!(&request[5..15], b"peer.onion");
+        assert_eq!(&request[15..], &[0, 80]);
+
+        assert!(connect_request(&Target::Domain("a".repeat(256)), 80).is_err());
+    }
+}
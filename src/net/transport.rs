@@ -0,0 +1,145 @@
+use anyhow::{Result, bail};
+use futures::future::Either;
+use libp2p::core::muxing::StreamMuxerBox;
+use libp2p::core::transport::Boxed;
+use libp2p::core::upgrade;
+use libp2p::{PeerId, Transport, identity, noise, quic, tcp, yamux};
+use std::fmt;
+
+/// Which transports the node listens and dials on (`net.transports`)
+///
+/// Both by default. Forcing one helps behind middleboxes that mangle the
+/// other, like firewalls that drop UDP or proxies that break long-lived TCP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transports {
+    pub tcp: bool,
+    pub quic: bool,
+}
+
+impl Default for Transports {
+    fn default() -> Self {
+        Self {
+            tcp: true,
+            quic: true,
+        }
+    }
+}
+
+impl Transports {
+    /// Parse transport names from the config
+    pub fn from_names(names: &[String]) -> Result<Self> {
+        let mut transports = Self {
+            tcp: false,
+            quic: false,
+        };
+        for name in names {
+            match name.to_ascii_lowercase().as_str() {
+                "tcp" => transports.tcp = true,
+                "quic" => transports.quic = true,
+                other => bail!(
+                    "Unknown transport '{}' in net.transports, expected tcp or quic",
+                    other
+                ),
+            }
+        }
+        if !transports.tcp && !transports.quic {
+            bail!("net.transports must enable tcp, quic, or both");
+        }
+        Ok(transports)
+    }
+}
+
+impl fmt::Display for Transports {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.tcp, self.quic) {
+            (true, true) => write!(f, "TCP and QUIC"),
+            (true, false) => write!(f, "TCP only"),
+            (false, true) => write!(f, "QUIC only"),
+            (false, false) => write!(f, "none"),
+        }
+    }
+}
+
+/// Build a transport carrying only the selected protocols
+///
+/// TCP connections are secured with Noise and multiplexed with yamux; QUIC
+/// brings its own TLS and streams.
+pub fn build_transport(
+    key: &identity::Keypair,
+    transports: Transports,
+) -> Result<Boxed<(PeerId, StreamMuxerBox)>, noise::Error> {
+    let tcp = if transports.tcp {
+        Some(
+            tcp::tokio::Transport::new(tcp::Config::default())
+                .upgrade(upgrade::Version::V1)
+                .authenticate(noise::Config::new(key)?)
+                .multiplex(yamux::Config::default())
+                .map(|(peer, muxer), _| (peer, StreamMuxerBox::new(muxer)))
+                .boxed(),
+        )
+    } else {
+        None
+    };
+    let quic = transports.quic.then(|| {
+        quic::tokio::Transport::new(quic::Config::new(key))
+            .map(|(peer, connection), _| (peer, StreamMuxerBox::new(connection)))
+            .boxed()
+    });
+
+    Ok(match (tcp, quic) {
+        (Some(tcp), Some(quic)) => quic
+            .or_transport(tcp)
+            .map(|either, _| match either {
+                Either::Left(output) | Either::Right(output) => output,
+            })
+            .boxed(),
+        (Some(only), None) | (None, Some(only)) => only,
+        (None, None) => libp2p::core::transport::dummy::DummyTransport::new().boxed(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_names() {
+        assert_eq!(
+            Transports::from_names(&names(&["tcp", "quic"])).unwrap(),
+            Transports::default()
+        );
+        assert_eq!(
+            Transports::from_names(&names(&["QUIC"])).unwrap(),
+            Transports {
+                tcp: false,
+                quic: true
+            }
+        );
+
+        let err = Transports::from_names(&names(&["tcp", "websocket"])).unwrap_err();
+        assert!(err.to_string().contains("'websocket'"));
+        assert!(Transports::from_names(&[]).is_err());
+    }
+
+    #[test]
+    fn test_build_transport() {
+        let key = identity::Keypair::generate_ed25519();
+        let quic_only = Transports {
+            tcp: false,
+            quic: true,
+        };
+        let mut transport = build_transport(&key, quic_only).unwrap();
+
+        // Only QUIC addresses can be listened on
+        let tcp_addr = "/ip4/127.0.0.1/tcp/0".parse().unwrap();
+        assert!(
+            transport
+                .listen_on(libp2p::core::transport::ListenerId::next(), tcp_addr)
+                .is_err()
+        );
+    }
+}
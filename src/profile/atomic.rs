@@ -0,0 +1,145 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Write a file atomically: the target holds either its old or its new
+/// contents, never a partial write
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    write_atomic_with(path, |file| file.write_all(contents.as_ref()))
+}
+
+/// Write to a temp file in the same directory, fsync it, then rename it over
+/// the target. If `write` fails the target is left untouched.
+fn write_atomic_with(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> Result<()> {
+    let temp_path = temp_path(path)?;
+
+    let result = write_temp(&temp_path, path, write).and_then(|()| {
+        std::fs::rename(&temp_path, path).with_context(|| {
+            format!(
+                "Failed to rename {} to {}",
+                temp_path.display(),
+                path.display()
+            )
+        })
+    });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result?;
+
+    sync_parent_dir(path)
+}
+
+fn write_temp(
+    temp_path: &Path,
+    path: &Path,
+    write: impl FnOnce(&mut File) -> std::io::Result<()>,
+) -> Result<()> {
+    let mut file = File::create(temp_path)
+        .with_context(|| format!("Failed to create {}", temp_path.display()))?;
+
+    // Keep the permissions of the file being replaced
+    if let Ok(metadata) = std::fs::metadata(path) {
+        file.set_permissions(metadata.permissions())
+            .with_context(|| format!("Failed to set permissions on {}", temp_path.display()))?;
+    }
+
+    write(&mut file).with_context(|| format!("Failed to write {}", temp_path.display()))?;
+    file.sync_all()
+        .with_context(|| format!("Failed to sync {}", temp_path.display()))
+}
+
+/// Temp file next to the target, so the rename never crosses filesystems
+fn temp_path(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .with_context(|| format!("Invalid file path: {}", path.display()))?;
+    Ok(path.with_file_name(format!(".{}.wolfpack-tmp", name.to_string_lossy())))
+}
+
+/// Persist the rename itself by syncing the containing directory
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<()> {
+    let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) else {
+        return Ok(());
+    };
+    File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .with_context(|| format!("Failed to sync {}", parent.display()))
+}
+
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_atomic_replaces_contents() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("user.js");
+
+        write_atomic(&path, "first").unwrap();
+        write_atomic(&path, "second").unwrap();
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "second");
+        assert!(!temp_path(&path).unwrap().exists());
+    }
+
+    #[test]
+    fn test_interrupted_write_keeps_original() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("containers.json");
+        std::fs::write(&path, "{\"version\": 4}").unwrap();
+
+        // Fail halfway through writing the new contents
+        let result = write_atomic_with(&path, |file| {
+            file.write_all(b"{\"vers")?;
+            Err(std::io::Error::other("simulated crash"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"version\": 4}");
+        assert!(!temp_path(&path).unwrap().exists());
+    }
+
+    #[test]
+    fn test_stale_temp_file_is_ignored() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("handlers.json");
+        std::fs::write(&path, "original").unwrap();
+
+        // A crash before rename leaves a truncated temp file behind
+        std::fs::write(temp_path(&path).unwrap(), "trunc").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "original");
+
+        write_atomic(&path, "updated").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "updated");
+        assert!(!temp_path(&path).unwrap().exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_atomic_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("user.js");
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+
+        write_atomic(&path, "new").unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,225 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use super::write_atomic;
+
+/// Address fields that are synced; record metadata (use counts,
+/// timestamps, Firefox Sync state) stays local
+pub const ADDRESS_FIELDS: &[&str] = &[
+    "given-name",
+    "additional-name",
+    "family-name",
+    "organization",
+    "street-address",
+    "address-level3",
+    "address-level2",
+    "address-level1",
+    "postal-code",
+    "country",
+    "tel",
+    "email",
+];
+
+/// A saved autofill address
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Address {
+    pub guid: String,
+    /// Non-empty address fields by name
+    pub fields: BTreeMap<String, String>,
+}
+
+pub fn read_addresses(profile_path: &Path) -> Result<Vec<Address>> {
+    let path = profile_path.join("autofill-profiles.json");
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let file: Value =
+        serde_json::from_str(&content).context("Failed to parse autofill-profiles.json")?;
+
+    let records = file
+        .get("addresses")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+
+    Ok(records.iter().filter_map(parse_address).collect())
+}
+
+fn parse_address(record: &Value) -> Option<Address> {
+    let guid = record.get("guid")?.as_str()?.to_string();
+    let fields = ADDRESS_FIELDS
+        .iter()
+        .filter_map(|&name| {
+            let value = record.get(name)?.as_str()?;
+            (!value.is_empty()).then(|| (name.to_string(), value.to_string()))
+        })
+        .collect();
+    Some(Address { guid, fields })
+}
+
+/// Replace the profile's addresses, keeping each record's local metadata
+/// and leaving credit cards untouched
+pub fn write_addresses(profile_path: &Path, addresses: &[Address]) -> Result<()> {
+    let path = profile_path.join("autofill-profiles.json");
+
+    let mut file: Map<String, Value> = if path.exists() {
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content).context("Failed to parse autofill-profiles.json")?
+    } else {
+        let mut file = Map::new();
+        file.insert("version".to_string(), Value::from(1));
+        file.insert("creditCards".to_string(), Value::Array(Vec::new()));
+        file
+    };
+
+    let existing: Vec<Value> = match file.get("addresses") {
+        Some(Value::Array(records)) => records.clone(),
+        _ => Vec::new(),
+    };
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let records = addresses
+        .iter()
+        .map(|address| {
+            let previous = existing
+                .iter()
+                .find(|r| r.get("guid").and_then(Value::as_str) == Some(&address.guid));
+            render_address(address, previous, now)
+        })
+        .collect();
+    file.insert("addresses".to_string(), Value::Array(records));
+
+    let content = serde_json::to_string(&file).context("Failed to serialize addresses")?;
+    write_atomic(&path, content)
+}
+
+fn render_address(address: &Address, previous: Option<&Value>, now: i64) -> Value {
+    let mut record = match previous {
+        Some(Value::Object(record)) => record.clone(),
+        _ => {
+            let mut record = Map::new();
+            record.insert("guid".to_string(), Value::from(address.guid.clone()));
+            record.insert("version".to_string(), Value::from(1));
+            record.insert("timeCreated".to_string(), Value::from(now));
+            record.insert("timeLastUsed".to_string(), Value::from(0));
+            record.insert("timesUsed".to_string(), Value::from(0));
+            record
+        }
+    };
+
+    let changed = previous.and_then(parse_address).as_ref() != Some(address);
+    for name in ADDRESS_FIELDS {
+        record.remove(*name);
+    }
+    for (name, value) in &address.fields {
+        record.insert(name.clone(), Value::from(value.clone()));
+    }
+    if changed {
+        record.insert("timeLastModified".to_string(), Value::from(now));
+    }
+
+    Value::Object(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const PROFILES: &str = r#"{
+        "version": 1,
+        "addresses": [{
+            "guid": "a1",
+            "version": 1,
+            "timeCreated": 1000,
+            "timeLastModified": 1000,
+            "timeLastUsed": 2000,
+            "timesUsed": 3,
+            "given-name": "Ada",
+            "family-name": "Lovelace",
+            "organization": "",
+            "street-address": "12 St James's Square",
+            "country": "GB"
+        }],
+        "creditCards": [{"guid": "c1", "cc-number-encrypted": "secret"}]
+    }"#;
+
+    fn address(guid: &str, fields: &[(&str, &str)]) -> Address {
+        Address {
+            guid: guid.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_read_addresses() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("autofill-profiles.json"), PROFILES).unwrap();
+
+        let addresses = read_addresses(dir.path()).unwrap();
+        assert_eq!(
+            addresses,
+            vec![address(
+                "a1",
+                &[
+                    ("country", "GB"),
+                    ("family-name", "Lovelace"),
+                    ("given-name", "Ada"),
+                    ("street-address", "12 St James's Square"),
+                ]
+            )]
+        );
+    }
+
+    #[test]
+    fn test_read_addresses_missing_file() {
+        let dir = tempdir().unwrap();
+        assert!(read_addresses(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_addresses_keeps_metadata_and_cards() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("autofill-profiles.json");
+        std::fs::write(&path, PROFILES).unwrap();
+
+        let updated = address("a1", &[("given-name", "Ada"), ("tel", "+44 20 7946 0000")]);
+        let added = address("b2", &[("given-name", "Charles")]);
+        write_addresses(dir.path(), &[updated.clone(), added.clone()]).unwrap();
+
+        assert_eq!(read_addresses(dir.path()).unwrap(), vec![updated, added]);
+
+        let file: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let record = &file["addresses"][0];
+        assert_eq!(record["timesUsed"], 3);
+        assert_eq!(record["timeCreated"], 1000);
+        assert!(record["timeLastModified"].as_i64().unwrap() > 1000);
+        assert!(record.get("family-name").is_none());
+        assert_eq!(file["addresses"][1]["timesUsed"], 0);
+        assert_eq!(file["creditCards"][0]["cc-number-encrypted"], "secret");
+    }
+
+    #[test]
+    fn test_write_unchanged_address_keeps_modified_time() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("autofill-profiles.json");
+        std::fs::write(&path, PROFILES).unwrap();
+
+        let addresses = read_addresses(dir.path()).unwrap();
+        write_addresses(dir.path(), &addresses).unwrap();
+
+        let file: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(file["addresses"][0]["timeLastModified"], 1000);
+    }
+}
@@ -0,0 +1,245 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Component, Path, PathBuf};
+
+use super::write_atomic;
+
+/// Timestamp format for backup directories (sorts chronologically)
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Timestamped copies of profile files taken before wolfpack overwrites them
+///
+/// Each backup lives at `<dir>/<timestamp>/<file>`, where `<file>` is the
+/// path relative to the profile (e.g. `user.js` or `extensions/foo.xpi`).
+#[derive(Debug, Clone)]
+pub struct ProfileBackups {
+    dir: PathBuf,
+    /// Number of backups kept per file (0 disables backups)
+    keep: usize,
+}
+
+impl ProfileBackups {
+    pub fn new(dir: PathBuf, keep: usize) -> Self {
+        Self { dir, keep }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Copy a profile file into a new backup before it is overwritten
+    ///
+    /// Returns the backup path, or `None` if there was nothing to back up.
+    /// A file that hasn't changed since its newest backup isn't backed up
+    /// again, so rewriting it doesn't push older versions out of retention.
+    pub fn backup(&self, profile_path: &Path, file: &str) -> Result<Option<PathBuf>> {
+        validate_file(file)?;
+        let source = profile_path.join(file);
+        if self.keep == 0 || !source.is_file() || self.is_latest(&source, file)? {
+            return Ok(None);
+        }
+
+        let timestamp = chrono::Utc::now().format(TIMESTAMP_FORMAT).to_string();
+        let target = self.dir.join(&timestamp).join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        std::fs::copy(&source, &target).with_context(|| {
+            format!(
+                "Failed to back up {} to {}",
+                source.display(),
+                target.display()
+            )
+        })?;
+
+        self.prune(file)?;
+        Ok(Some(target))
+    }
+
+    /// Whether a file is the same as its newest backup
+    fn is_latest(&self, source: &Path, file: &str) -> Result<bool> {
+        let Some(latest) = self.list(file)?.into_iter().next() else {
+            return Ok(false);
+        };
+        let backup = self.dir.join(latest).join(file);
+        let content = std::fs::read(source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        Ok(std::fs::read(backup).is_ok_and(|backup| backup == content))
+    }
+
+    /// Timestamps of the backups for a file, newest first
+    pub fn list(&self, file: &str) -> Result<Vec<String>> {
+        validate_file(file)?;
+        if !self.dir.exists() {
+            return Ok(Vec::new());
+        }
+
+        let entries = std::fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?;
+
+        let mut timestamps = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.path().join(file).is_file() {
+                timestamps.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+
+        timestamps.sort_by(|a, b| b.cmp(a));
+        Ok(timestamps)
+    }
+
+    /// Restore a file from a backup (the latest if no timestamp is given)
+    ///
+    /// The current file is backed up first, so a restore can itself be undone.
+    /// Returns the timestamp of the restored backup.
+    pub fn restore(
+        &self,
+        profile_path: &Path,
+        file: &str,
+        timestamp: Option<&str>,
+    ) -> Result<String> {
+        let timestamp = match timestamp {
+            Some(timestamp) => timestamp.to_string(),
+            None => self
+                .list(file)?
+                .into_iter()
+                .next()
+                .with_context(|| format!("No backups found for {}", file))?,
+        };
+
+        let source = self.dir.join(&timestamp).join(file);
+        if !source.is_file() {
+            bail!("No backup of {} at {}", file, timestamp);
+        }
+
+        // Read before backing up, in case pruning removes the source
+        let content = std::fs::read(&source)
+            .with_context(|| format!("Failed to read {}", source.display()))?;
+        self.backup(profile_path, file)?;
+
+        let target = profile_path.join(file);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        write_atomic(&target, content)?;
+
+        Ok(timestamp)
+    }
+
+    /// Remove backups of a file beyond the retention limit
+    fn prune(&self, file: &str) -> Result<()> {
+        for timestamp in self.list(file)?.into_iter().skip(self.keep) {
+            let snapshot = self.dir.join(&timestamp);
+            let path = snapshot.join(file);
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+
+            // Clean up directories left empty by the removal
+            let mut dir = path.parent();
+            while let Some(d) = dir {
+                if !d.starts_with(&snapshot) || std::fs::remove_dir(d).is_err() {
+                    break;
+                }
+                dir = d.parent();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Only allow plain relative paths inside the profile
+fn validate_file(file: &str) -> Result<()> {
+    let path = Path::new(file);
+    let plain = path.components().all(|c| matches!(c, Component::Normal(_)));
+    if file.is_empty() || !plain {
+        bail!("Invalid profile file path: {}", file);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_backup_and_restore() {
+        let dir = tempdir().unwrap();
+        let profile = dir.path().join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        let backups = ProfileBackups::new(dir.path().join("backups"), 5);
+
+        // Nothing to back up yet
+        assert!(backups.backup(&profile, "user.js").unwrap().is_none());
+
+        std::fs::write(profile.join("user.js"), "original").unwrap();
+        assert!(backups.backup(&profile, "user.js").unwrap().is_some());
+        std::fs::write(profile.join("user.js"), "overwritten").unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        backups.restore(&profile, "user.js", None).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(profile.join("user.js")).unwrap(),
+            "original"
+        );
+
+        // The overwritten version was backed up by the restore
+        assert_eq!(backups.list("user.js").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_backup_retention() {
+        let dir = tempdir().unwrap();
+        let profile = dir.path().join("profile");
+        std::fs::create_dir_all(profile.join("extensions")).unwrap();
+        let backups = ProfileBackups::new(dir.path().join("backups"), 2);
+
+        for i in 0..4 {
+            std::fs::write(profile.join("extensions/a.xpi"), i.to_string()).unwrap();
+            backups.backup(&profile, "extensions/a.xpi").unwrap();
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+
+        let kept = backups.list("extensions/a.xpi").unwrap();
+        assert_eq!(kept.len(), 2);
+        assert_eq!(std::fs::read_dir(backups.dir()).unwrap().count(), 2);
+
+        let latest = backups.dir().join(&kept[0]).join("extensions/a.xpi");
+        assert_eq!(std::fs::read_to_string(latest).unwrap(), "3");
+    }
+
+    #[test]
+    fn test_backup_skips_unchanged_file() {
+        let dir = tempdir().unwrap();
+        let profile = dir.path().join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        let backups = ProfileBackups::new(dir.path().join("backups"), 2);
+
+        std::fs::write(profile.join("user.js"), "original").unwrap();
+        assert!(backups.backup(&profile, "user.js").unwrap().is_some());
+
+        // Rewritten with the same content many times over
+        std::fs::write(profile.join("user.js"), "synced").unwrap();
+        for _ in 0..4 {
+            std::thread::sleep(std::time::Duration::from_millis(2));
+            backups.backup(&profile, "user.js").unwrap();
+        }
+
+        let kept = backups.list("user.js").unwrap();
+        assert_eq!(kept.len(), 2);
+        let oldest = backups.dir().join(&kept[1]).join("user.js");
+        assert_eq!(std::fs::read_to_string(oldest).unwrap(), "original");
+    }
+
+    #[test]
+    fn test_backup_rejects_paths_outside_profile() {
+        let dir = tempdir().unwrap();
+        let backups = ProfileBackups::new(dir.path().join("backups"), 5);
+
+        assert!(backups.list("../prefs.js").is_err());
+        assert!(backups.list("/etc/passwd").is_err());
+        assert!(backups.list("").is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use super::write_atomic;
+
+const HEADER: &str = "# PSM Certificate Override Settings file\n\
+                      # This is a generated file!  Do not edit.\n";
+
+/// A certificate exception the user accepted for a host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CertOverride {
+    /// `host:port`, followed by origin attributes (e.g. `example.lan:443:`)
+    pub host: String,
+    /// OID of the fingerprint hash algorithm
+    pub algorithm: String,
+    /// Colon-separated hex fingerprint of the accepted certificate
+    pub fingerprint: String,
+}
+
+pub fn read_cert_overrides(profile_path: &Path) -> Result<Vec<CertOverride>> {
+    let path = profile_path.join("cert_override.txt");
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+
+    Ok(content.lines().filter_map(parse_line).collect())
+}
+
+fn parse_line(line: &str) -> Option<CertOverride> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let mut fields = line.split('\t');
+    let host = fields.next().filter(|h| !h.is_empty())?;
+    let algorithm = fields.next()?;
+    let fingerprint = fields.next().filter(|f| !f.is_empty())?;
+
+    Some(CertOverride {
+        host: host.to_string(),
+        algorithm: algorithm.to_string(),
+        fingerprint: fingerprint.to_string(),
+    })
+}
+
+/// Replace the profile's certificate overrides
+///
+/// Lines for overrides that haven't changed are kept as they are, since
+/// older browser versions store extra fields after the fingerprint.
+pub fn write_cert_overrides(profile_path: &Path, overrides: &[CertOverride]) -> Result<()> {
+    let path = profile_path.join("cert_override.txt");
+    let existing = std::fs::read_to_string(&path).unwrap_or_default();
+
+    let mut content = String::from(HEADER);
+    for cert in overrides {
+        let kept = existing
+            .lines()
+            .find(|line| parse_line(line).as_ref() == Some(cert));
+        match kept {
+            Some(line) => content.push_str(line),
+            None => content.push_str(&format!(
+                "{}\t{}\t{}\t",
+                cert.host, cert.algorithm, cert.fingerprint
+            )),
+        }
+        content.push('\n');
+    }
+
+    write_atomic(&path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const OVERRIDES: &str = "# PSM Certificate Override Settings file\n\
+        # This is a generated file!  Do not edit.\n\
+        nas.lan:443:\tOID.2.16.840.1.101.3.4.2.1\tAB:CD:EF\tMU\tAAAAAAAAAAAAAAAJAAAAGwD\n\
+        git.lan:8443:\tOID.2.16.840.1.101.3.4.2.1\t01:02:03\t\n";
+
+    fn cert(host: &str, fingerprint: &str) -> CertOverride {
+        CertOverride {
+            host: host.to_string(),
+            algorithm: "OID.2.16.840.1.101.3.4.2.1".to_string(),
+            fingerprint: fingerprint.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_read_cert_overrides() {
+        let dir = tempdir().unwrap();
+        std::fs::write(dir.path().join("cert_override.txt"), OVERRIDES).unwrap();
+
+        assert_eq!(
+            read_cert_overrides(dir.path()).unwrap(),
+            vec![
+                cert("nas.lan:443:", "AB:CD:EF"),
+                cert("git.lan:8443:", "01:02:03")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_cert_overrides_missing_file() {
+        let dir = tempdir().unwrap();
+        assert!(read_cert_overrides(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_cert_overrides_keeps_unchanged_lines() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("cert_override.txt");
+        std::fs::write(&path, OVERRIDES).unwrap();
+
+        let overrides = vec![
+            cert("nas.lan:443:", "AB:CD:EF"),
+            cert("wiki.lan:443:", "99:88:77"),
+        ];
+        write_cert_overrides(dir.path(), &overrides).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert!(content.starts_with(HEADER));
+        assert!(content.contains("nas.lan:443:\tOID.2.16.840.1.101.3.4.2.1\tAB:CD:EF\tMU\t"));
+        assert!(!content.contains("git.lan"));
+        assert_eq!(read_cert_overrides(dir.path()).unwrap(), overrides);
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::Path;
+
+use super::write_atomic;
+
+/// Storage of the Multi-Account Containers extension, relative to the profile
+///
+/// This is the JSON storage backend; extensions whose storage was migrated
+/// to IndexedDB keep it under `storage/default` instead.
+pub const CONTAINER_SITES_FILE: &str = "browser-extension-data/@testpilot-containers/storage.js";
+
+/// Prefix of the extension's storage keys for site assignments
+const ASSIGNMENT_PREFIX: &str = "siteContainerMap@@_";
+
+/// A site the Multi-Account Containers extension always opens in a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteAssignment {
+    pub host: String,
+    /// `userContextId` of the container, as a string like container IDs
+    pub container_id: String,
+    /// Open the site in the container without asking first
+    pub never_ask: bool,
+}
+
+pub fn read_site_assignments(profile_path: &Path) -> Result<Vec<SiteAssignment>> {
+    let Some(storage) = read_storage(profile_path)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut assignments: Vec<SiteAssignment> = storage
+        .iter()
+        .filter_map(|(key, value)| {
+            let host = key.strip_prefix(ASSIGNMENT_PREFIX)?;
+            let container_id = match value.get("userContextId")? {
+                Value::String(id) => id.clone(),
+                Value::Number(id) => id.to_string(),
+                _ => return None,
+            };
+            Some(SiteAssignment {
+                host: host.to_string(),
+                container_id,
+                never_ask: value
+                    .get("neverAsk")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false),
+            })
+        })
+        .collect();
+    assignments.sort_by(|a, b| a.host.cmp(&b.host));
+
+    Ok(assignments)
+}
+
+/// Replace the extension's site assignments, keeping the rest of its storage
+///
+/// Does nothing if the extension has no storage in this profile, since
+/// the assignments would have nothing to read them.
+pub fn write_site_assignments(profile_path: &Path, assignments: &[SiteAssignment]) -> Result<()> {
+    let Some(mut storage) = read_storage(profile_path)? else {
+        return Ok(());
+    };
+
+    let mut previous = Map::new();
+    storage.retain(|key, value| {
+        if key.starts_with(ASSIGNMENT_PREFIX) {
+            previous.insert(key.clone(), value.take());
+            return false;
+        }
+        true
+    });
+
+    for assignment in assignments {
+        let key = format!("{}{}", ASSIGNMENT_PREFIX, assignment.host);
+        // Keep fields the extension adds itself (e.g. its own container UUID)
+        let mut value = match previous.remove(&key) {
+            Some(Value::Object(value)) => value,
+            _ => Map::new(),
+        };
+        value.insert(
+            "userContextId".to_string(),
+            Value::from(assignment.container_id.clone()),
+        );
+        value.insert("neverAsk".to_string(), Value::from(assignment.never_ask));
+        value.insert("hostname".to_string(), Value::from(assignment.host.clone()));
+        storage.insert(key, Value::Object(value));
+    }
+
+    let content = serde_json::to_string(&storage).context("Failed to serialize container sites")?;
+    write_atomic(&profile_path.join(CONTAINER_SITES_FILE), content)
+}
+
+fn read_storage(profile_path: &Path) -> Result<Option<Map<String, Value>>> {
+    let path = profile_path.join(CONTAINER_SITES_FILE);
+
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let storage =
+        serde_json::from_str(&content).context("Failed to parse container extension storage")?;
+
+    Ok(Some(storage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const STORAGE: &str = r#"{
+        "siteContainerMap@@_mail.example.com": {
+            "userContextId": "2",
+            "neverAsk": true,
+            "identityMacAddonUUID": "b6e1f7a2",
+            "hostname": "mail.example.com"
+        },
+        "siteContainerMap@@_shop.example.org": {"userContextId": "3", "neverAsk": false},
+        "identitiesState@@_firefox-container-2": {"hiddenTabs": []},
+        "onboarding-stage": 5
+    }"#;
+
+    fn write_storage(profile_path: &Path, content: &str) {
+        let path = profile_path.join(CONTAINER_SITES_FILE);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    fn assignment(host: &str, container_id: &str, never_ask: bool) -> SiteAssignment {
+        SiteAssignment {
+            host: host.to_string(),
+            container_id: container_id.to_string(),
+            never_ask,
+        }
+    }
+
+    #[test]
+    fn test_read_site_assignments() {
+        let dir = tempdir().unwrap();
+        write_storage(dir.path(), STORAGE);
+
+        assert_eq!(
+            read_site_assignments(dir.path()).unwrap(),
+            vec![
+                assignment("mail.example.com", "2", true),
+                assignment("shop.example.org", "3", false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_site_assignments_without_extension() {
+        let dir = tempdir().unwrap();
+        assert!(read_site_assignments(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_write_site_assignments_keeps_other_storage() {
+        let dir = tempdir().unwrap();
+        write_storage(dir.path(), STORAGE);
+
+        let assignments = vec![
+            assignment("mail.example.com", "4", true),
+            assignment("news.example.net", "2", false),
+        ];
+        write_site_assignments(dir.path(), &assignments).unwrap();
+
+        assert_eq!(read_site_assignments(dir.path()).unwrap(), assignments);
+
+        let content = std::fs::read_to_string(dir.path().join(CONTAINER_SITES_FILE)).unwrap();
+        let storage: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            storage["siteContainerMap@@_mail.example.com"]["identityMacAddonUUID"],
+            "b6e1f7a2"
+        );
+        assert_eq!(storage["onboarding-stage"], 5);
+        assert!(storage.get("siteContainerMap@@_shop.example.org").is_none());
+    }
+
+    #[test]
+    fn test_write_site_assignments_without_extension() {
+        let dir = tempdir().unwrap();
+        write_site_assignments(dir.path(), &[assignment("example.com", "1", false)]).unwrap();
+        assert!(!dir.path().join(CONTAINER_SITES_FILE).exists());
+    }
+}
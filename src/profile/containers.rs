@@ -2,6 +2,8 @@ use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+use super::write_atomic;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Container {
     #[serde(rename = "userContextId")]
@@ -54,10 +56,7 @@ pub fn write_containers(profile_path: &Path, containers: &[Container]) -> Result
 
     let content = serde_json::to_string_pretty(&file).context("Failed to serialize containers")?;
 
-    std::fs::write(&containers_path, content)
-        .with_context(|| format!("Failed to write {}", containers_path.display()))?;
-
-    Ok(())
+    write_atomic(&containers_path, content)
 }
 
 #[cfg(test)]
@@ -17,55 +17,48 @@ pub fn find_profile() -> Result<PathBuf> {
 }
 
 fn parse_profiles_ini(content: &str, base: &Path) -> Result<PathBuf> {
-    let ini = ini::Ini::load_from_str(content)
-        .context("Failed to parse profiles.ini")?;
+    let ini = ini::Ini::load_from_str(content).context("Failed to parse profiles.ini")?;
 
     // First try to find default profile from [InstallXXX] section
     // The Default field contains the profile name, which we need to match against Path fields
     let mut install_default: Option<String> = None;
     for (section, props) in ini.iter() {
-        if let Some(section_name) = section {
-            if section_name.starts_with("Install") {
-                if let Some(default_name) = props.get("Default") {
-                    install_default = Some(default_name.to_string());
-                    break;
-                }
-            }
+        if let Some(section_name) = section
+            && section_name.starts_with("Install")
+            && let Some(default_name) = props.get("Default")
+        {
+            install_default = Some(default_name.to_string());
+            break;
         }
     }
 
     // Collect all profiles with their paths
     let mut profiles = HashMap::new();
     for (section, props) in ini.iter() {
-        if let Some(section_name) = section {
-            if section_name.starts_with("Profile") {
-                if let Some(path_str) = props.get("Path") {
-                    let is_default = props.get("Default").map_or(false, |v| v == "1");
-                    let is_relative = props.get("IsRelative").map_or(false, |v| v == "1");
-                    let name = props.get("Name").map(|s| s.to_string());
-
-                    let profile_path = if is_relative {
-                        base.join(path_str)
-                    } else {
-                        PathBuf::from(path_str)
-                    };
-
-                    profiles.insert(
-                        path_str.to_string(),
-                        (profile_path, is_default, name),
-                    );
-                }
-            }
+        if let Some(section_name) = section
+            && section_name.starts_with("Profile")
+            && let Some(path_str) = props.get("Path")
+        {
+            let is_default = props.get("Default") == Some("1");
+            let is_relative = props.get("IsRelative") == Some("1");
+            let name = props.get("Name").map(|s| s.to_string());
+
+            let profile_path = if is_relative {
+                base.join(path_str)
+            } else {
+                PathBuf::from(path_str)
+            };
+
+            profiles.insert(path_str.to_string(), (profile_path, is_default, name));
         }
     }
 
     // First try to match [InstallXXX] Default= field
-    if let Some(default_name) = install_default {
-        if let Some((path, _, _)) = profiles.get(&default_name) {
-            if path.join("prefs.js").exists() {
-                return Ok(path.clone());
-            }
-        }
+    if let Some(default_name) = install_default
+        && let Some((path, _, _)) = profiles.get(&default_name)
+        && path.join("prefs.js").exists()
+    {
+        return Ok(path.clone());
     }
 
     // Fallback: use profile with Default=1
@@ -110,11 +103,32 @@ pub fn is_browser_running(profile_path: &Path) -> bool {
     profile_path.join("lock").exists() || profile_path.join(".parentlock").exists()
 }
 
+/// Version of the browser that last ran the profile, from the
+/// `LastVersion` it records in `compatibility.ini` (`128.0_20240708/...`)
+pub fn read_browser_version(profile_path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(profile_path.join("compatibility.ini")).ok()?;
+    parse_browser_version(&content)
+}
+
+fn parse_browser_version(content: &str) -> Option<String> {
+    let ini = ini::Ini::load_from_str(content).ok()?;
+    let value = ini.section(Some("Compatibility"))?.get("LastVersion")?;
+    let version = value.split(['_', '/']).next()?.trim();
+    (!version.is_empty()).then(|| version.to_string())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
 
+    #[test]
+    fn test_parse_browser_version() {
+        let content = "[Compatibility]\nLastVersion=128.0.3_20240708103046/20240708103046\nLastOSABI=Linux_x86_64-gcc3\n";
+        assert_eq!(parse_browser_version(content).as_deref(), Some("128.0.3"));
+        assert_eq!(parse_browser_version("[Compatibility]\n"), None);
+    }
+
     #[test]
     fn test_find_profile_exists() {
         // This test will only pass if LibreWolf/Firefox is installed
@@ -200,7 +214,12 @@ Path=xyz123.profile"#;
 
         let result = parse_profiles_ini(content, &temp_dir);
         assert!(result.is_err());
-        assert!(result.unwrap_err().to_string().contains("No default profile found"));
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("No default profile found")
+        );
 
         fs::remove_dir_all(&temp_dir).ok();
     }
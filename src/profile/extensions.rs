@@ -7,6 +7,7 @@ pub struct Extension {
     pub id: String,
     pub name: String,
     pub url: Option<String>,
+    pub version: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -24,6 +25,7 @@ struct Addon {
     name: Option<String>,
     #[serde(rename = "sourceURI")]
     source_uri: Option<String>,
+    version: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -54,6 +56,7 @@ pub fn read_extensions(profile_path: &Path) -> Result<Vec<Extension>> {
                 id: addon.id,
                 name,
                 url: addon.source_uri,
+                version: addon.version,
             }
         })
         .collect();
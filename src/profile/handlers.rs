@@ -1,8 +1,10 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
+use super::write_atomic;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Handler {
     pub protocol: String,
@@ -28,8 +30,40 @@ struct SchemeHandler {
 #[derive(Serialize, Deserialize)]
 struct HandlerEntry {
     name: String,
-    #[serde(rename = "uriTemplate")]
-    uri_template: String,
+    /// Web handlers have a URL template
+    #[serde(rename = "uriTemplate", skip_serializing_if = "Option::is_none")]
+    uri_template: Option<String>,
+    /// Local application handlers have the application's path
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+}
+
+/// Whether a handler opens a local application rather than a web page
+pub fn is_app_handler(handler: &str) -> bool {
+    !handler.contains("://")
+}
+
+/// The local application to use for an application handler, from a
+/// `handlers.translate` table keyed by full path or application name
+///
+/// The name is the file name without its extension, lowercased, so
+/// `/usr/bin/thunderbird`, `C:\Program Files\Mozilla Thunderbird\thunderbird.exe`,
+/// and `/Applications/Thunderbird.app` are all `thunderbird`.
+pub fn translate_handler<'a>(
+    handler: &str,
+    translate: &'a BTreeMap<String, String>,
+) -> Option<&'a String> {
+    if !is_app_handler(handler) {
+        return None;
+    }
+    translate.get(handler).or_else(|| {
+        let file_name = handler.rsplit(['/', '\\']).find(|part| !part.is_empty())?;
+        let name = match file_name.rsplit_once('.') {
+            Some((stem, _)) if !stem.is_empty() => stem,
+            _ => file_name,
+        };
+        translate.get(&name.to_lowercase())
+    })
 }
 
 pub fn read_handlers(profile_path: &Path) -> Result<Vec<Handler>> {
@@ -49,10 +83,9 @@ pub fn read_handlers(profile_path: &Path) -> Result<Vec<Handler>> {
         .schemes
         .into_iter()
         .filter_map(|(protocol, scheme)| {
-            scheme.handlers.first().map(|h| Handler {
-                protocol,
-                handler: h.uri_template.clone(),
-            })
+            let entry = scheme.handlers.first()?;
+            let handler = entry.uri_template.clone().or_else(|| entry.path.clone())?;
+            Some(Handler { protocol, handler })
         })
         .collect();
 
@@ -81,9 +114,18 @@ pub fn write_handlers(profile_path: &Path, handlers: &[Handler]) -> Result<()> {
             handler.protocol.clone(),
             SchemeHandler {
                 action: 2, // useHelperApp
-                handlers: vec![HandlerEntry {
-                    name: handler.protocol.clone(),
-                    uri_template: handler.handler.clone(),
+                handlers: vec![if is_app_handler(&handler.handler) {
+                    HandlerEntry {
+                        name: handler.protocol.clone(),
+                        uri_template: None,
+                        path: Some(handler.handler.clone()),
+                    }
+                } else {
+                    HandlerEntry {
+                        name: handler.protocol.clone(),
+                        uri_template: Some(handler.handler.clone()),
+                        path: None,
+                    }
                 }],
             },
         );
@@ -91,10 +133,7 @@ pub fn write_handlers(profile_path: &Path, handlers: &[Handler]) -> Result<()> {
 
     let content = serde_json::to_string_pretty(&file).context("Failed to serialize handlers")?;
 
-    std::fs::write(&handlers_path, content)
-        .with_context(|| format!("Failed to write {}", handlers_path.display()))?;
-
-    Ok(())
+    write_atomic(&handlers_path, content)
 }
 
 #[cfg(test)]
@@ -117,4 +156,44 @@ mod tests {
         assert_eq!(loaded[0].protocol, "mailto");
         assert!(loaded[0].handler.contains("mail.example.com"));
     }
+
+    #[test]
+    fn test_translate_handler() {
+        let translate = BTreeMap::from([
+            (
+                "thunderbird".to_string(),
+                "/Applications/Thunderbird.app".to_string(),
+            ),
+            ("/usr/bin/hexchat".to_string(), "/opt/hexchat".to_string()),
+        ]);
+        let translated = |handler: &str| translate_handler(handler, &translate).map(String::as_str);
+
+        assert_eq!(
+            translated("/usr/bin/thunderbird"),
+            Some("/Applications/Thunderbird.app")
+        );
+        assert_eq!(
+            translated(r"C:\Program Files\Mozilla Thunderbird\thunderbird.exe"),
+            Some("/Applications/Thunderbird.app")
+        );
+        assert_eq!(translated("/usr/bin/hexchat"), Some("/opt/hexchat"));
+        assert_eq!(translated("/usr/local/bin/hexchat"), None);
+        assert_eq!(translated("https://thunderbird.example.com/%s"), None);
+    }
+
+    #[test]
+    fn test_app_handler_roundtrip() {
+        let dir = tempdir().unwrap();
+        let handlers = vec![Handler {
+            protocol: "mailto".to_string(),
+            handler: "/usr/bin/thunderbird".to_string(),
+        }];
+
+        write_handlers(dir.path(), &handlers).unwrap();
+        let content = std::fs::read_to_string(dir.path().join("handlers.json")).unwrap();
+        assert!(content.contains(r#""path": "/usr/bin/thunderbird""#));
+        assert!(!content.contains("uriTemplate"));
+        let loaded = read_handlers(dir.path()).unwrap();
+        assert_eq!(loaded[0].handler, "/usr/bin/thunderbird");
+    }
 }
@@ -1,17 +1,49 @@
+mod atomic;
+mod autofill;
+mod backup;
+mod cert_overrides;
+mod container_sites;
 mod containers;
 mod discovery;
 mod extensions;
 mod handlers;
 mod mozlz4;
+mod permissions;
+mod policies;
 mod prefs;
+mod prefs_parser;
 mod search;
+mod sideload;
+mod start_page;
 mod write_queue;
 
+pub use atomic::write_atomic;
+pub use autofill::{ADDRESS_FIELDS, Address, read_addresses, write_addresses};
+pub use backup::ProfileBackups;
+pub use cert_overrides::{CertOverride, read_cert_overrides, write_cert_overrides};
+pub use container_sites::{
+    CONTAINER_SITES_FILE, SiteAssignment, read_site_assignments, write_site_assignments,
+};
 pub use containers::{Container, read_containers, write_containers};
-pub use discovery::{find_profile, is_browser_running};
+pub use discovery::{find_profile, is_browser_running, read_browser_version};
 pub use extensions::{Extension, read_extensions};
-pub use handlers::{Handler, read_handlers, write_handlers};
+pub use handlers::{Handler, is_app_handler, read_handlers, translate_handler, write_handlers};
 pub use mozlz4::{decode_mozlz4, encode_mozlz4};
-pub use prefs::{read_prefs, write_user_js};
-pub use search::{SearchEngine, read_search_engines};
+pub use permissions::{
+    Permission, SiteFilter, capability_name, read_permissions, write_permissions,
+};
+pub use policies::{prune_policy_xpis, stage_policy_xpi, write_policies};
+pub(crate) use prefs::should_include;
+pub use prefs::{PrefPatterns, PrefVariables, read_prefs, render_user_js, write_user_js};
+pub use search::{
+    DEFAULT_ENGINE_PREF, SearchEngine, parse_opensearch, read_search_engines,
+    write_default_search_engine,
+};
+pub use sideload::{
+    ADDON_STARTUP_FILE, AUTO_DISABLE_SCOPES_PREF, allow_profile_sideloads, enable_sideloaded,
+    profile_sideloads_allowed, sideloads_awaiting_confirmation,
+};
+pub use start_page::{
+    HOMEPAGE_PREF, PINNED_SITES_PREF, StartPage, is_newtab_setting, read_start_page,
+};
 pub use write_queue::{PendingWrite, WriteQueue};
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use rusqlite::{Connection, OpenFlags};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// `expireType` of permissions that never expire; session and timed
+/// permissions are left to each device
+const EXPIRE_NEVER: i64 = 0;
+
+/// A persistent site permission from permissions.sqlite
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Permission {
+    /// Site origin, e.g. `https://example.com` (may carry origin
+    /// attributes such as `^userContextId=2` for container permissions)
+    pub origin: String,
+    /// Permission type, e.g. `desktop-notification` or `camera`
+    pub permission: String,
+    /// 1 = allow, 2 = block, 3 = prompt, 8 = allow for session
+    pub capability: u32,
+}
+
+/// Human-readable name for a permission capability
+pub fn capability_name(capability: u32) -> &'static str {
+    match capability {
+        1 => "allow",
+        2 => "block",
+        3 => "prompt",
+        8 => "allow for session",
+        _ => "set",
+    }
+}
+
+/// Sites whose permissions are synced
+///
+/// Entries match the domain and its subdomains. An empty allowlist allows
+/// every site; the blocklist wins over the allowlist.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SiteFilter {
+    pub allow: Vec<String>,
+    pub block: Vec<String>,
+}
+
+impl SiteFilter {
+    pub fn includes(&self, origin: &str) -> bool {
+        let Some(host) = origin_host(origin) else {
+            return false;
+        };
+        let matches = |domain: &String| {
+            let domain = domain.trim_start_matches("*.").trim_start_matches('.');
+            host == domain
+                || host
+                    .strip_suffix(domain)
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+        };
+
+        if self.block.iter().any(matches) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(matches)
+    }
+}
+
+/// Host of a web origin, or None for origins that can't be synced
+/// (extension pages have per-device UUIDs, `about:` pages are built in)
+fn origin_host(origin: &str) -> Option<&str> {
+    let origin = origin.split('^').next().unwrap_or(origin);
+    let rest = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))?;
+    let host = rest.split(['/', ':']).next().unwrap_or(rest);
+    (!host.is_empty()).then_some(host)
+}
+
+/// Whether a permission is meaningful on other devices
+fn is_syncable(permission: &Permission) -> bool {
+    // Types like `3rdPartyStorage^https://...` are storage access grants
+    // recorded automatically by anti-tracking heuristics
+    !permission.permission.contains('^') && origin_host(&permission.origin).is_some()
+}
+
+pub fn read_permissions(profile_path: &Path) -> Result<Vec<Permission>> {
+    let path = profile_path.join("permissions.sqlite");
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    query_permissions(&conn).context("Failed to read permissions.sqlite")
+}
+
+fn query_permissions(conn: &Connection) -> Result<Vec<Permission>> {
+    let mut stmt = conn.prepare(
+        "SELECT origin, type, permission FROM moz_perms
+         WHERE expireType = ? ORDER BY origin, type",
+    )?;
+    let rows = stmt.query_map([EXPIRE_NEVER], |row| {
+        Ok(Permission {
+            origin: row.get(0)?,
+            permission: row.get(1)?,
+            capability: row.get(2)?,
+        })
+    })?;
+
+    let mut permissions = Vec::new();
+    for row in rows {
+        let permission = row?;
+        if is_syncable(&permission) {
+            permissions.push(permission);
+        }
+    }
+    Ok(permissions)
+}
+
+/// Make the profile's permissions for sites in `filter` match `permissions`
+///
+/// Permissions for other sites, and session or timed permissions, are
+/// left as they are.
+pub fn write_permissions(
+    profile_path: &Path,
+    permissions: &[Permission],
+    filter: &SiteFilter,
+) -> Result<()> {
+    let path = profile_path.join("permissions.sqlite");
+    let mut conn = Connection::open_with_flags(&path, OpenFlags::SQLITE_OPEN_READ_WRITE)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+
+    let current = query_permissions(&conn)?;
+    let target: HashMap<(&str, &str), u32> = permissions
+        .iter()
+        .filter(|p| filter.includes(&p.origin))
+        .map(|p| ((p.origin.as_str(), p.permission.as_str()), p.capability))
+        .collect();
+    let now = chrono::Utc::now().timestamp_millis();
+
+    let tx = conn.transaction()?;
+    for permission in &current {
+        let key = (permission.origin.as_str(), permission.permission.as_str());
+        if filter.includes(&permission.origin) && !target.contains_key(&key) {
+            tx.execute(
+                "DELETE FROM moz_perms WHERE origin = ? AND type = ? AND expireType = ?",
+                rusqlite::params![permission.origin, permission.permission, EXPIRE_NEVER],
+            )?;
+        }
+    }
+    for (&(origin, permission), &capability) in &target {
+        let exists: bool = tx.query_row(
+            "SELECT EXISTS(SELECT 1 FROM moz_perms WHERE origin = ? AND type = ?)",
+            [origin, permission],
+            |row| row.get(0),
+        )?;
+        if exists {
+            // A session or timed grant for the same site becomes permanent
+            tx.execute(
+                "UPDATE moz_perms SET permission = ?3, expireType = ?4, expireTime = 0,
+                     modificationTime = ?5
+                 WHERE origin = ?1 AND type = ?2
+                   AND (permission != ?3 OR expireType != ?4)",
+                rusqlite::params![origin, permission, capability, EXPIRE_NEVER, now],
+            )?;
+        } else {
+            tx.execute(
+                "INSERT INTO moz_perms
+                     (origin, type, permission, expireType, expireTime, modificationTime)
+                 VALUES (?, ?, ?, ?, 0, ?)",
+                rusqlite::params![origin, permission, capability, EXPIRE_NEVER, now],
+            )?;
+        }
+    }
+    tx.commit()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn create_permissions_db(profile_path: &Path) -> Connection {
+        let conn = Connection::open(profile_path.join("permissions.sqlite")).unwrap();
+        conn.execute_batch(
+            r#"
+            CREATE TABLE moz_perms (
+                id INTEGER PRIMARY KEY,
+                origin TEXT,
+                type TEXT,
+                permission INTEGER,
+                expireType INTEGER,
+                expireTime INTEGER,
+                modificationTime INTEGER
+            );
+            INSERT INTO moz_perms (origin, type, permission, expireType, expireTime, modificationTime) VALUES
+                ('https://example.com', 'desktop-notification', 2, 0, 0, 1000),
+                ('https://meet.example.org', 'camera', 1, 0, 0, 1000),
+                ('https://bank.example.net', 'popup', 1, 0, 0, 1000),
+                ('https://example.com', 'geo', 1, 1, 0, 1000),
+                ('https://example.com', '3rdPartyStorage^https://tracker.test', 1, 0, 0, 1000),
+                ('moz-extension://0b3c-uuid', 'persistent-storage', 1, 0, 0, 1000);
+            "#,
+        )
+        .unwrap();
+        conn
+    }
+
+    fn permission(origin: &str, permission: &str, capability: u32) -> Permission {
+        Permission {
+            origin: origin.to_string(),
+            permission: permission.to_string(),
+            capability,
+        }
+    }
+
+    #[test]
+    fn test_read_permissions() {
+        let dir = tempdir().unwrap();
+        create_permissions_db(dir.path());
+
+        // Session, heuristic, and extension permissions are skipped
+        assert_eq!(
+            read_permissions(dir.path()).unwrap(),
+            vec![
+                permission("https://bank.example.net", "popup", 1),
+                permission("https://example.com", "desktop-notification", 2),
+                permission("https://meet.example.org", "camera", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_permissions_missing_file() {
+        let dir = tempdir().unwrap();
+        assert!(read_permissions(dir.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_site_filter() {
+        let filter = SiteFilter {
+            allow: Vec::new(),
+            block: vec!["example.net".to_string()],
+        };
+        assert!(filter.includes("https://example.com"));
+        assert!(!filter.includes("https://bank.example.net"));
+        assert!(!filter.includes("https://example.net:8443"));
+        assert!(filter.includes("https://notexample.net"));
+        assert!(!filter.includes("moz-extension://0b3c-uuid"));
+
+        let filter = SiteFilter {
+            allow: vec!["*.example.org".to_string()],
+            block: vec!["private.example.org".to_string()],
+        };
+        assert!(filter.includes("https://meet.example.org^userContextId=2"));
+        assert!(!filter.includes("https://private.example.org"));
+        assert!(!filter.includes("https://example.com"));
+    }
+
+    #[test]
+    fn test_write_permissions_leaves_excluded_sites() {
+        let dir = tempdir().unwrap();
+        let conn = create_permissions_db(dir.path());
+        let filter = SiteFilter {
+            allow: Vec::new(),
+            block: vec!["example.net".to_string()],
+        };
+
+        write_permissions(
+            dir.path(),
+            &[
+                permission("https://example.com", "desktop-notification", 1),
+                permission("https://example.com", "geo", 2),
+                permission("https://new.example.com", "camera", 2),
+                permission("https://bank.example.net", "popup", 2),
+            ],
+            &filter,
+        )
+        .unwrap();
+
+        assert_eq!(
+            read_permissions(dir.path()).unwrap(),
+            vec![
+                // Blocked site keeps its local permission
+                permission("https://bank.example.net", "popup", 1),
+                permission("https://example.com", "desktop-notification", 1),
+                permission("https://example.com", "geo", 2),
+                permission("https://new.example.com", "camera", 2),
+            ]
+        );
+
+        // Unsynced entries are untouched
+        let others: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM moz_perms WHERE type LIKE '3rdPartyStorage%'
+                 OR origin LIKE 'moz-extension:%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(others, 2);
+    }
+}
@@ -0,0 +1,285 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value, json};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+
+use super::write_atomic;
+use crate::events::PrefValue;
+use crate::extensions::{decode_base64, decompress_xpi};
+
+/// Record of what wolfpack put in `policies.json`, kept in the XPI directory
+const MANAGED_FILE: &str = "managed.json";
+
+/// Extension settings and prefs wolfpack put in `policies.json`, so they
+/// can be told apart from an administrator's and taken back when they leave
+/// the pack
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ManagedPolicies {
+    #[serde(default)]
+    extensions: BTreeSet<String>,
+    #[serde(default)]
+    prefs: BTreeSet<String>,
+}
+
+impl ManagedPolicies {
+    fn load(xpi_dir: &Path) -> Result<Self> {
+        let path = xpi_dir.join(MANAGED_FILE);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    fn save(&self, xpi_dir: &Path) -> Result<()> {
+        write_atomic(
+            &xpi_dir.join(MANAGED_FILE),
+            serde_json::to_string_pretty(self)?,
+        )
+    }
+}
+
+/// Write an extension's XPI where a policy can install it from, returning
+/// its `file://` URL
+///
+/// The version is part of the file name, so a new version has a new URL,
+/// which the browser takes as an update.
+pub fn stage_policy_xpi(xpi_dir: &Path, id: &str, version: &str, xpi_data: &str) -> Result<String> {
+    let path = xpi_dir.join(format!(
+        "{}-{}.xpi",
+        safe_file_name(id),
+        safe_file_name(version)
+    ));
+    if !path.exists() {
+        std::fs::create_dir_all(xpi_dir)
+            .with_context(|| format!("Failed to create {}", xpi_dir.display()))?;
+        let xpi = decompress_xpi(&decode_base64(xpi_data)?)?;
+        write_atomic(&path, xpi)
+            .with_context(|| format!("Failed to write XPI to {}", path.display()))?;
+    }
+    file_url(&path)
+}
+
+/// Remove staged XPIs no policy installs from anymore
+pub fn prune_policy_xpis(xpi_dir: &Path, urls: &HashSet<String>) -> Result<()> {
+    if !xpi_dir.exists() {
+        return Ok(());
+    }
+    for entry in std::fs::read_dir(xpi_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "xpi") && !urls.contains(&file_url(&path)?) {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Failed to remove {}", path.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Bring `policies.json` in line with the pack, returning whether it changed
+///
+/// `extensions` maps IDs to the URLs to install them from, and `prefs` are
+/// set as user prefs at every browser start. Either left out keeps what's
+/// there, as while its category is paused. Extensions that leave the pack
+/// are blocked, which uninstalls them; prefs that leave it are dropped.
+/// Other policies in the file are kept as they are.
+pub fn write_policies(
+    path: &Path,
+    xpi_dir: &Path,
+    extensions: Option<&BTreeMap<String, String>>,
+    prefs: Option<&HashMap<String, PrefValue>>,
+) -> Result<bool> {
+    let current = if path.exists() {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?
+    } else {
+        json!({ "policies": {} })
+    };
+
+    let mut managed = ManagedPolicies::load(xpi_dir)?;
+    let mut updated = current.clone();
+    update_policies(&mut updated, &mut managed, extensions, prefs)?;
+    if updated == current {
+        return Ok(false);
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+    }
+    write_atomic(path, serde_json::to_string_pretty(&updated)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    std::fs::create_dir_all(xpi_dir)?;
+    managed.save(xpi_dir)?;
+    Ok(true)
+}
+
+fn update_policies(
+    file: &mut Value,
+    managed: &mut ManagedPolicies,
+    extensions: Option<&BTreeMap<String, String>>,
+    prefs: Option<&HashMap<String, PrefValue>>,
+) -> Result<()> {
+    let root = file
+        .as_object_mut()
+        .context("Expected a JSON object in policies.json")?;
+    let policies = object_entry(root, "policies")?;
+
+    if let Some(extensions) = extensions {
+        let settings = object_entry(policies, "ExtensionSettings")?;
+        for id in &managed.extensions {
+            if !extensions.contains_key(id) {
+                settings.insert(id.clone(), json!({ "installation_mode": "blocked" }));
+            }
+        }
+        for (id, url) in extensions {
+            settings.insert(
+                id.clone(),
+                json!({ "installation_mode": "normal_installed", "install_url": url }),
+            );
+            managed.extensions.insert(id.clone());
+        }
+    }
+
+    if let Some(prefs) = prefs {
+        let preferences = object_entry(policies, "Preferences")?;
+        for key in &managed.prefs {
+            if !prefs.contains_key(key) {
+                preferences.remove(key);
+            }
+        }
+        for (key, value) in prefs {
+            preferences.insert(key.clone(), json!({ "Value": value, "Status": "user" }));
+        }
+        managed.prefs = prefs.keys().cloned().collect();
+    }
+    Ok(())
+}
+
+/// The object under `key`, added if it's missing
+fn object_entry<'a>(
+    object: &'a mut Map<String, Value>,
+    key: &str,
+) -> Result<&'a mut Map<String, Value>> {
+    object
+        .entry(key)
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .with_context(|| format!("Expected {} in policies.json to be an object", key))
+}
+
+fn file_url(path: &Path) -> Result<String> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    url::Url::from_file_path(&path)
+        .map(String::from)
+        .map_err(|()| anyhow::anyhow!("Can't make a URL for {}", path.display()))
+}
+
+/// Keep only characters that are safe in a file name on every platform
+fn safe_file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "@.-_".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extensions::{compress_xpi, encode_base64};
+    use tempfile::tempdir;
+
+    fn xpi_data(content: &[u8]) -> String {
+        encode_base64(&compress_xpi(content).unwrap())
+    }
+
+    #[test]
+    fn test_stage_and_prune_xpis() {
+        let dir = tempdir().unwrap();
+        let xpi_dir = dir.path().join("xpis");
+
+        let url = stage_policy_xpi(&xpi_dir, "{abc}@example.com", "1.0", &xpi_data(b"v1")).unwrap();
+        assert!(url.starts_with("file://"));
+        assert!(url.ends_with("_abc_@example.com-1.0.xpi"));
+        let path = xpi_dir.join("_abc_@example.com-1.0.xpi");
+        assert_eq!(std::fs::read(&path).unwrap(), b"v1");
+
+        let newer =
+            stage_policy_xpi(&xpi_dir, "{abc}@example.com", "2.0", &xpi_data(b"v2")).unwrap();
+        prune_policy_xpis(&xpi_dir, &HashSet::from([newer])).unwrap();
+        assert!(!path.exists());
+        assert!(xpi_dir.join("_abc_@example.com-2.0.xpi").exists());
+    }
+
+    #[test]
+    fn test_write_policies() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("policies.json");
+        let xpi_dir = dir.path().join("xpis");
+        std::fs::write(
+            &path,
+            r#"{"policies": {"DisableTelemetry": true, "Preferences": {"admin.pref": {"Value": 1, "Status": "locked"}}}}"#,
+        )
+        .unwrap();
+
+        let extensions = BTreeMap::from([
+            (
+                "a@example.com".to_string(),
+                "file:///xpis/a.xpi".to_string(),
+            ),
+            (
+                "b@example.com".to_string(),
+                "file:///xpis/b.xpi".to_string(),
+            ),
+        ]);
+        let prefs = HashMap::from([("browser.startup.page".to_string(), PrefValue::Int(3))]);
+        assert!(write_policies(&path, &xpi_dir, Some(&extensions), Some(&prefs)).unwrap());
+        assert!(!write_policies(&path, &xpi_dir, Some(&extensions), Some(&prefs)).unwrap());
+
+        let file: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let policies = &file["policies"];
+        assert_eq!(policies["DisableTelemetry"], true);
+        assert_eq!(
+            policies["ExtensionSettings"]["a@example.com"]["install_url"],
+            "file:///xpis/a.xpi"
+        );
+        assert_eq!(policies["Preferences"]["browser.startup.page"]["Value"], 3);
+        assert_eq!(policies["Preferences"]["admin.pref"]["Status"], "locked");
+
+        // Leaving the pack blocks extensions and drops prefs, but leaves the
+        // administrator's
+        let extensions = BTreeMap::from([(
+            "a@example.com".to_string(),
+            "file:///xpis/a.xpi".to_string(),
+        )]);
+        assert!(write_policies(&path, &xpi_dir, Some(&extensions), Some(&HashMap::new())).unwrap());
+        let file: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let policies = &file["policies"];
+        assert_eq!(
+            policies["ExtensionSettings"]["b@example.com"]["installation_mode"],
+            "blocked"
+        );
+        assert!(
+            policies["Preferences"]
+                .get("browser.startup.page")
+                .is_none()
+        );
+        assert_eq!(policies["Preferences"]["admin.pref"]["Value"], 1);
+
+        // Paused categories are left alone
+        assert!(!write_policies(&path, &xpi_dir, None, None).unwrap());
+    }
+}
@@ -1,7 +1,10 @@
 use anyhow::{Context, Result};
+use regex::Regex;
 use std::collections::HashMap;
 use std::path::Path;
 
+use super::prefs_parser::{PrefKind, effective_prefs, parse_prefs};
+use super::write_atomic;
 use crate::events::PrefValue;
 
 pub fn read_prefs(profile_path: &Path, whitelist: &[String]) -> Result<HashMap<String, PrefValue>> {
@@ -9,67 +12,187 @@ pub fn read_prefs(profile_path: &Path, whitelist: &[String]) -> Result<HashMap<S
     let content = std::fs::read_to_string(&prefs_path)
         .with_context(|| format!("Failed to read {}", prefs_path.display()))?;
 
-    let mut prefs = HashMap::new();
+    let whitelist = PrefPatterns::new(whitelist);
+    let mut prefs = effective_prefs(parse_prefs(&content));
+    prefs.retain(|key, _| whitelist.matches(key));
+    Ok(prefs)
+}
+
+/// The pref a line of prefs.js sets with `user_pref`, when that's all the
+/// line holds
+pub(super) fn parse_pref_line(line: &str) -> Option<(String, PrefValue)> {
+    match parse_prefs(line).as_slice() {
+        [pref] if pref.kind == PrefKind::User => Some((pref.key.clone(), pref.value.clone())),
+        _ => None,
+    }
+}
+
+/// Whether a pref matches a list of patterns, ignoring any that don't parse
+pub(crate) fn should_include(key: &str, whitelist: &[String]) -> bool {
+    PrefPatterns::new(whitelist).matches(key)
+}
+
+/// A list of pref patterns, like `prefs.whitelist`, ready to match keys
+///
+/// A pattern is an exact key, a glob where `*` matches any run of characters
+/// and `?` any one, or a regex between slashes (`/^browser\.(tabs|urlbar)\./`).
+/// Patterns starting with `!` exclude what they match, and exclusions win
+/// over the rest wherever they are in the list.
+#[derive(Debug, Clone, Default)]
+pub struct PrefPatterns {
+    include: Vec<PrefPattern>,
+    exclude: Vec<PrefPattern>,
+}
 
-    for line in content.lines() {
-        let line = line.trim();
-        if !line.starts_with("user_pref(") {
-            continue;
+#[derive(Debug, Clone)]
+enum PrefPattern {
+    Exact(String),
+    Glob(Vec<char>),
+    Regex(Regex),
+}
+
+impl PrefPatterns {
+    /// Parse patterns, failing on a regex that doesn't compile
+    pub fn parse(patterns: &[String]) -> Result<Self> {
+        let mut parsed = Self::default();
+        for pattern in patterns {
+            let (list, pattern) = match pattern.strip_prefix('!') {
+                Some(pattern) => (&mut parsed.exclude, pattern),
+                None => (&mut parsed.include, pattern.as_str()),
+            };
+            list.push(PrefPattern::parse(pattern)?);
         }
+        Ok(parsed)
+    }
 
-        if let Some((key, value)) = parse_pref_line(line)
-            && should_include(&key, whitelist)
-        {
-            prefs.insert(key, value);
+    /// Parse patterns, leaving out any that don't
+    pub fn new(patterns: &[String]) -> Self {
+        let mut parsed = Self::default();
+        for pattern in patterns {
+            if let Ok(one) = Self::parse(std::slice::from_ref(pattern)) {
+                parsed.include.extend(one.include);
+                parsed.exclude.extend(one.exclude);
+            }
         }
+        parsed
     }
 
-    Ok(prefs)
+    pub fn matches(&self, key: &str) -> bool {
+        self.include.iter().any(|p| p.matches(key)) && !self.exclude.iter().any(|p| p.matches(key))
+    }
 }
 
-fn parse_pref_line(line: &str) -> Option<(String, PrefValue)> {
-    // user_pref("key", value);
-    let line = line.strip_prefix("user_pref(")?;
-    let line = line.strip_suffix(");")?;
-
-    let (key, value) = line.split_once(", ")?;
-
-    // Remove quotes from key
-    let key = key.trim_matches('"').to_string();
-
-    // Parse value
-    let value = if value == "true" {
-        PrefValue::Bool(true)
-    } else if value == "false" {
-        PrefValue::Bool(false)
-    } else if let Ok(n) = value.parse::<i64>() {
-        PrefValue::Int(n)
-    } else if value.starts_with('"') && value.ends_with('"') {
-        PrefValue::String(value[1..value.len() - 1].to_string())
-    } else {
-        return None;
-    };
-
-    Some((key, value))
+impl PrefPattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        if let Some(regex) = pattern.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            let regex =
+                Regex::new(regex).with_context(|| format!("Invalid pref pattern {}", pattern))?;
+            return Ok(Self::Regex(regex));
+        }
+        if pattern.contains(['*', '?']) {
+            return Ok(Self::Glob(pattern.chars().collect()));
+        }
+        Ok(Self::Exact(pattern.to_string()))
+    }
+
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            Self::Exact(pattern) => pattern == key,
+            Self::Glob(pattern) => glob_matches(pattern, &key.chars().collect::<Vec<_>>()),
+            Self::Regex(regex) => regex.is_match(key),
+        }
+    }
 }
 
-fn should_include(key: &str, whitelist: &[String]) -> bool {
-    for pattern in whitelist {
-        if pattern.ends_with('*') {
-            let prefix = &pattern[..pattern.len() - 1];
-            if key.starts_with(prefix) {
-                return true;
+/// Match a glob, going back to the last `*` to take one more character
+/// each time the rest doesn't match
+fn glob_matches(pattern: &[char], key: &[char]) -> bool {
+    let (mut p, mut k) = (0, 0);
+    let mut star = None;
+    while k < key.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, k));
+                p += 1;
             }
-        } else if pattern == key {
-            return true;
+            Some(&c) if c == '?' || c == key[k] => {
+                p += 1;
+                k += 1;
+            }
+            _ => match star {
+                Some((star_p, star_k)) => {
+                    star = Some((star_p, star_k + 1));
+                    p = star_p + 1;
+                    k = star_k + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Per-device values for the `{{variable}}`s a synced string pref can
+/// contain, so a pref holding a path means the same place on every device
+#[derive(Debug, Clone, Default)]
+pub struct PrefVariables {
+    /// `{{home}}`
+    pub home: Option<String>,
+    /// `{{device_name}}`
+    pub device_name: String,
+    /// `{{download_dir}}`
+    pub download_dir: Option<String>,
+}
+
+impl PrefVariables {
+    /// This device's values
+    pub fn for_device(device_name: &str) -> Self {
+        let home = dirs::home_dir();
+        let download_dir = dirs::download_dir().or_else(|| Some(home.as_ref()?.join("Downloads")));
+        Self {
+            home: home.map(|path| path.display().to_string()),
+            device_name: device_name.to_string(),
+            download_dir: download_dir.map(|path| path.display().to_string()),
+        }
+    }
+
+    /// The value with its variables replaced, leaving unknown ones and ones
+    /// without a value on this device as they are
+    pub fn expand(&self, value: &PrefValue) -> PrefValue {
+        let PrefValue::String(s) = value else {
+            return value.clone();
+        };
+        let mut expanded = s.clone();
+        if s.contains("{{") {
+            for (name, replacement) in [
+                ("home", self.home.as_deref()),
+                ("device_name", Some(self.device_name.as_str())),
+                ("download_dir", self.download_dir.as_deref()),
+            ] {
+                if let Some(replacement) = replacement {
+                    expanded = expanded.replace(&format!("{{{{{}}}}}", name), replacement);
+                }
+            }
+        }
+        PrefValue::String(expanded)
+    }
+
+    /// Expand the variables in every pref
+    pub fn expand_all(&self, prefs: &mut HashMap<String, PrefValue>) {
+        for value in prefs.values_mut() {
+            *value = self.expand(value);
         }
     }
-    false
 }
 
 pub fn write_user_js(profile_path: &Path, prefs: &HashMap<String, PrefValue>) -> Result<()> {
     let user_js_path = profile_path.join("user.js");
 
+    write_atomic(&user_js_path, render_user_js(prefs))
+}
+
+/// Render the contents of a wolfpack-generated user.js
+pub fn render_user_js(prefs: &HashMap<String, PrefValue>) -> String {
     let mut lines: Vec<String> = vec![
         "// Generated by wolfpack".to_string(),
         "// Do not edit manually - changes will be overwritten".to_string(),
@@ -88,10 +211,7 @@ pub fn write_user_js(profile_path: &Path, prefs: &HashMap<String, PrefValue>) ->
         lines.push(format!("user_pref(\"{}\", {});", key, value_str));
     }
 
-    std::fs::write(&user_js_path, lines.join("\n"))
-        .with_context(|| format!("Failed to write {}", user_js_path.display()))?;
-
-    Ok(())
+    lines.join("\n")
 }
 
 #[cfg(test)]
@@ -99,6 +219,27 @@ mod tests {
     use super::*;
     use tempfile::tempdir;
 
+    #[test]
+    fn test_expand_pref_variables() {
+        let variables = PrefVariables {
+            home: Some("/home/alex".to_string()),
+            device_name: "laptop".to_string(),
+            download_dir: None,
+        };
+        let expand = |value: &str| variables.expand(&PrefValue::String(value.to_string()));
+
+        assert_eq!(
+            expand("{{home}}/Sync/{{device_name}}"),
+            PrefValue::String("/home/alex/Sync/laptop".to_string())
+        );
+        // No download directory on this device, and an unknown variable
+        assert_eq!(
+            expand("{{download_dir}} {{user}}"),
+            PrefValue::String("{{download_dir}} {{user}}".to_string())
+        );
+        assert_eq!(variables.expand(&PrefValue::Int(3)), PrefValue::Int(3));
+    }
+
     #[test]
     fn test_parse_pref_line() {
         let cases = vec![
@@ -139,6 +280,18 @@ mod tests {
         assert_eq!(result, Some(("test.int".to_string(), PrefValue::Int(-42))));
     }
 
+    #[test]
+    fn test_parse_pref_line_escaped_string() {
+        let result = parse_pref_line(r#"user_pref("test.json", "[{\"url\":\"a\\b\"}]");"#);
+        assert_eq!(
+            result,
+            Some((
+                "test.json".to_string(),
+                PrefValue::String(r#"[{"url":"a\b"}]"#.to_string())
+            ))
+        );
+    }
+
     #[test]
     fn test_parse_pref_line_invalid() {
         // Not a user_pref line
@@ -243,6 +396,61 @@ mod tests {
         assert!(!should_include("extensions.disabled", &whitelist));
     }
 
+    #[test]
+    fn test_should_include_globs() {
+        let whitelist = vec!["browser.*.homepage".to_string(), "font.size.?".to_string()];
+        assert!(should_include("browser.startup.homepage", &whitelist));
+        assert!(should_include("browser.a.b.homepage", &whitelist));
+        assert!(!should_include(
+            "browser.startup.homepage_override",
+            &whitelist
+        ));
+        assert!(should_include("font.size.x", &whitelist));
+        assert!(!should_include("font.size.xy", &whitelist));
+    }
+
+    #[test]
+    fn test_should_include_regex() {
+        let whitelist = vec![r"/^browser\.(tabs|urlbar)\.[a-z]+$/".to_string()];
+        assert!(should_include("browser.tabs.warnonclose", &whitelist));
+        assert!(should_include("browser.urlbar.trimurls", &whitelist));
+        assert!(!should_include("browser.tabs.warnOnClose", &whitelist));
+        assert!(!should_include("browser.startup.page", &whitelist));
+
+        // A regex that doesn't compile is an error when parsed, and left out
+        // otherwise
+        let invalid = vec!["/browser.(/".to_string(), "browser.*".to_string()];
+        assert!(PrefPatterns::parse(&invalid).is_err());
+        assert!(should_include("browser.startup.page", &invalid));
+    }
+
+    #[test]
+    fn test_should_include_exclusions_win() {
+        // Overlapping patterns: the exclusion wins whatever the order
+        for whitelist in [
+            vec!["browser.*", "!browser.download.*", "browser.download.dir"],
+            vec!["!browser.download.*", "browser.download.dir", "browser.*"],
+        ] {
+            let whitelist: Vec<String> = whitelist.iter().map(|p| p.to_string()).collect();
+            assert!(should_include("browser.startup.page", &whitelist));
+            assert!(!should_include("browser.download.dir", &whitelist));
+            assert!(!should_include(
+                "browser.download.useDownloadDir",
+                &whitelist
+            ));
+        }
+
+        let whitelist = vec![r"/^network\./".to_string(), "!network.proxy.*".to_string()];
+        assert!(should_include("network.trr.mode", &whitelist));
+        assert!(!should_include("network.proxy.type", &whitelist));
+
+        // Exclusions alone match nothing
+        assert!(!should_include(
+            "browser.startup.page",
+            &["!browser.tabs.*".to_string()]
+        ));
+    }
+
     #[test]
     fn test_should_include_empty_whitelist() {
         let whitelist: Vec<String> = vec![];
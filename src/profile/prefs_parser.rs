@@ -0,0 +1,468 @@
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::events::PrefValue;
+
+/// How a pref is set, by the function in prefs.js or user.js that sets it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrefKind {
+    /// `pref` or `defaultPref`: the default, which a user value overrides
+    Default,
+    /// `sticky_pref`, or `pref` with `sticky`: a default kept in prefs.js
+    /// even when the user sets it back to it
+    Sticky,
+    /// `user_pref`: the user's own value, as the browser saves prefs.js
+    User,
+    /// `lockPref`, or `pref` with `locked`: a value the user can't change
+    Locked,
+}
+
+impl PrefKind {
+    fn from_function(name: &str) -> Option<Self> {
+        match name {
+            "pref" | "defaultPref" => Some(Self::Default),
+            "sticky_pref" => Some(Self::Sticky),
+            "user_pref" => Some(Self::User),
+            "lockPref" => Some(Self::Locked),
+            _ => None,
+        }
+    }
+
+    /// Which value the browser uses when one pref is set several ways
+    fn precedence(self) -> u8 {
+        match self {
+            Self::Default | Self::Sticky => 0,
+            Self::User => 1,
+            Self::Locked => 2,
+        }
+    }
+}
+
+/// One pref set in a prefs file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedPref {
+    pub kind: PrefKind,
+    pub key: String,
+    pub value: PrefValue,
+}
+
+/// Parse every pref a prefs.js or user.js sets, in order
+///
+/// Follows the browser's own parser: `//`, `#`, and `/* */` comments, single
+/// or double quoted strings that can span lines and hold `\x` and `\u`
+/// escapes, and any whitespace between tokens. A statement that doesn't
+/// parse is skipped up to its `;`, or up to the next pref function when it's
+/// missing one, so one bad line doesn't lose the rest of the file.
+pub fn parse_prefs(content: &str) -> Vec<ParsedPref> {
+    let tokens: Vec<Token> = Lexer::new(content).collect();
+    let mut prefs = Vec::new();
+    let mut pos = 0;
+    while pos < tokens.len() {
+        match parse_statement(&tokens[pos..]) {
+            Some((pref, len)) => {
+                prefs.push(pref);
+                pos += len;
+            }
+            None => pos += skip_statement(&tokens[pos..]),
+        }
+    }
+    prefs
+}
+
+/// The value each pref ends up with: a locked value over the user's, and
+/// the user's over a default, with the last one of each kind winning
+pub fn effective_prefs(prefs: Vec<ParsedPref>) -> HashMap<String, PrefValue> {
+    let mut effective: HashMap<String, (PrefKind, PrefValue)> = HashMap::new();
+    for pref in prefs {
+        match effective.get(&pref.key) {
+            Some((kind, _)) if kind.precedence() > pref.kind.precedence() => {}
+            _ => {
+                effective.insert(pref.key, (pref.kind, pref.value));
+            }
+        }
+    }
+    effective
+        .into_iter()
+        .map(|(key, (_, value))| (key, value))
+        .collect()
+}
+
+/// `function("key", value[, attribute]);`, and how many tokens it took
+fn parse_statement(tokens: &[Token]) -> Option<(ParsedPref, usize)> {
+    let [
+        Token::Ident(function),
+        Token::Punct('('),
+        Token::Str(key),
+        Token::Punct(','),
+        value,
+        rest @ ..,
+    ] = tokens
+    else {
+        return None;
+    };
+    let mut kind = PrefKind::from_function(function)?;
+    let value = match value {
+        Token::Str(s) => PrefValue::String(s.clone()),
+        Token::Int(n) => PrefValue::Int(*n),
+        Token::Ident(b) if b == "true" => PrefValue::Bool(true),
+        Token::Ident(b) if b == "false" => PrefValue::Bool(false),
+        _ => return None,
+    };
+    let len = match rest {
+        [Token::Punct(')'), Token::Punct(';'), ..] => 7,
+        [
+            Token::Punct(','),
+            Token::Ident(attribute),
+            Token::Punct(')'),
+            Token::Punct(';'),
+            ..,
+        ] => {
+            kind = match attribute.as_str() {
+                "sticky" => PrefKind::Sticky,
+                "locked" => PrefKind::Locked,
+                _ => return None,
+            };
+            9
+        }
+        _ => return None,
+    };
+    let pref = ParsedPref {
+        kind,
+        key: key.clone(),
+        value,
+    };
+    Some((pref, len))
+}
+
+/// How many tokens a statement that doesn't parse takes up
+fn skip_statement(tokens: &[Token]) -> usize {
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punct(';') => return i + 1,
+            Token::Ident(name) if i > 0 && PrefKind::from_function(name).is_some() => return i,
+            _ => {}
+        }
+    }
+    tokens.len()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(i64),
+    Punct(char),
+    /// Something no statement can hold, like an unterminated string
+    Invalid,
+}
+
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(content: &'a str) -> Self {
+        Self {
+            chars: content.chars().peekable(),
+        }
+    }
+
+    fn skip_line(&mut self) {
+        for c in self.chars.by_ref() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn skip_block_comment(&mut self) {
+        let mut star = false;
+        for c in self.chars.by_ref() {
+            if star && c == '/' {
+                break;
+            }
+            star = c == '*';
+        }
+    }
+
+    fn ident(&mut self) -> Token {
+        let mut ident = String::new();
+        while let Some(&c) = self.chars.peek()
+            && (c.is_ascii_alphanumeric() || c == '_')
+        {
+            ident.push(c);
+            self.chars.next();
+        }
+        Token::Ident(ident)
+    }
+
+    fn int(&mut self) -> Token {
+        let mut digits = String::new();
+        if let Some(&sign @ ('-' | '+')) = self.chars.peek() {
+            digits.push(sign);
+            self.chars.next();
+        }
+        while let Some(&c) = self.chars.peek()
+            && c.is_ascii_digit()
+        {
+            digits.push(c);
+            self.chars.next();
+        }
+        digits.parse().map_or(Token::Invalid, Token::Int)
+    }
+
+    /// A string, after its opening quote, read up to its closing quote
+    /// even when an escape in it is bad
+    fn string(&mut self, quote: char) -> Token {
+        let mut string = Some(String::new());
+        while let Some(c) = self.chars.next() {
+            match c {
+                c if c == quote => return string.map_or(Token::Invalid, Token::Str),
+                '\\' => match (self.escape(), &mut string) {
+                    (Some(c), Some(string)) => string.push(c),
+                    _ => string = None,
+                },
+                c => {
+                    if let Some(string) = &mut string {
+                        string.push(c);
+                    }
+                }
+            }
+        }
+        Token::Invalid
+    }
+
+    /// The character an escape stands for, after its backslash
+    fn escape(&mut self) -> Option<char> {
+        match self.chars.next()? {
+            'n' => Some('\n'),
+            'r' => Some('\r'),
+            't' => Some('\t'),
+            'x' => char::from_u32(self.hex(2)?),
+            'u' => {
+                let unit = self.hex(4)?;
+                if (0xD800..0xDC00).contains(&unit) {
+                    // A surrogate pair, written as two escapes
+                    let mut low = self.chars.clone();
+                    if low.next() == Some('\\') && low.next() == Some('u') {
+                        self.chars = low;
+                        let low = self.hex(4)?;
+                        return char::from_u32(0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00));
+                    }
+                }
+                char::from_u32(unit)
+            }
+            // Quotes, backslashes, and anything else stand for themselves
+            c => Some(c),
+        }
+    }
+
+    fn hex(&mut self, digits: usize) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..digits {
+            value = value * 16 + self.chars.next()?.to_digit(16)?;
+        }
+        Some(value)
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            let c = *self.chars.peek()?;
+            match c {
+                c if c.is_whitespace() => {
+                    self.chars.next();
+                }
+                '#' => self.skip_line(),
+                '/' => {
+                    self.chars.next();
+                    match self.chars.next() {
+                        Some('/') => self.skip_line(),
+                        Some('*') => self.skip_block_comment(),
+                        _ => return Some(Token::Invalid),
+                    }
+                }
+                '"' | '\'' => {
+                    self.chars.next();
+                    return Some(self.string(c));
+                }
+                '(' | ')' | ',' | ';' => {
+                    self.chars.next();
+                    return Some(Token::Punct(c));
+                }
+                '-' | '+' | '0'..='9' => return Some(self.int()),
+                c if c.is_ascii_alphabetic() || c == '_' => return Some(self.ident()),
+                _ => {
+                    self.chars.next();
+                    return Some(Token::Invalid);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user(key: &str, value: PrefValue) -> ParsedPref {
+        ParsedPref {
+            kind: PrefKind::User,
+            key: key.to_string(),
+            value,
+        }
+    }
+
+    fn string(s: &str) -> PrefValue {
+        PrefValue::String(s.to_string())
+    }
+
+    #[test]
+    fn test_parse_prefs() {
+        let prefs = parse_prefs(
+            r#"
+            user_pref("a", true); /* user_pref("commented", 1); */ user_pref("b", -3);
+            # comment
+            user_pref('c', 'it\'s');
+            "#,
+        );
+        assert_eq!(
+            prefs,
+            vec![
+                user("a", PrefValue::Bool(true)),
+                user("b", PrefValue::Int(-3)),
+                user("c", string("it's")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_escapes() {
+        let prefs = parse_prefs(r#"user_pref("s", "\"\\\n\x41\u00e9\ud83d\ude00");"#);
+        assert_eq!(prefs, vec![user("s", string("\"\\\nAé😀"))]);
+
+        // A bad escape loses the pref, not the ones after it
+        let prefs = parse_prefs(r#"user_pref("bad", "\xZZ"); user_pref("good", 1);"#);
+        assert_eq!(prefs, vec![user("good", PrefValue::Int(1))]);
+    }
+
+    #[test]
+    fn test_kinds() {
+        let prefs = parse_prefs(
+            r#"
+            pref("a", 1);
+            pref("a", 2, locked);
+            user_pref("a", 3);
+            sticky_pref("b", 1);
+            pref("b", 2);
+            user_pref("b", 3);
+            pref("c", 1, bogus);
+            "#,
+        );
+        let kinds: Vec<PrefKind> = prefs.iter().map(|p| p.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                PrefKind::Default,
+                PrefKind::Locked,
+                PrefKind::User,
+                PrefKind::Sticky,
+                PrefKind::Default,
+                PrefKind::User,
+            ]
+        );
+
+        let effective = effective_prefs(prefs);
+        assert_eq!(effective["a"], PrefValue::Int(2));
+        assert_eq!(effective["b"], PrefValue::Int(3));
+        assert!(!effective.contains_key("c"));
+    }
+
+    #[test]
+    fn test_firefox_fixture() {
+        let prefs = parse_prefs(include_str!("testdata/firefox-prefs.js"));
+        assert_eq!(prefs.len(), 27);
+        assert!(prefs.iter().all(|p| p.kind == PrefKind::User));
+
+        let prefs = effective_prefs(prefs);
+        assert_eq!(
+            prefs["browser.download.lastDir"],
+            string(r"C:\Users\alex\Downloads")
+        );
+        assert_eq!(prefs["browser.startup.page"], PrefValue::Int(3));
+        assert_eq!(
+            prefs["app.update.lastUpdateTime.addon-background-update-timer"],
+            PrefValue::Int(1760000000)
+        );
+        // JSON held in a string, escapes and all
+        let PrefValue::String(pinned) = &prefs["browser.newtabpage.pinned"] else {
+            panic!("pinned sites aren't a string");
+        };
+        let pinned: serde_json::Value = serde_json::from_str(pinned).unwrap();
+        assert_eq!(pinned[2]["label"], "Mozilla \u{2013} Home");
+    }
+
+    #[test]
+    fn test_librewolf_fixture() {
+        let prefs = parse_prefs(include_str!("testdata/librewolf-overrides.js"));
+        assert_eq!(prefs.len(), 11);
+        assert!(!prefs.iter().any(|p| p.key == "commented.out"));
+
+        let prefs = effective_prefs(prefs);
+        assert_eq!(prefs["browser.startup.page"], PrefValue::Int(3));
+        assert_eq!(prefs["network.trr.mode"], PrefValue::Int(3));
+        assert_eq!(
+            prefs["network.trr.uri"],
+            string("https://dns.example.com/dns-query")
+        );
+        assert_eq!(
+            prefs["privacy.clearOnShutdown.history"],
+            PrefValue::Bool(false)
+        );
+        assert_eq!(prefs["webgl.disabled"], PrefValue::Bool(true));
+        assert_eq!(
+            prefs["privacy.resistFingerprinting.letterboxing"],
+            PrefValue::Bool(true)
+        );
+        assert_eq!(prefs["identity.fxaccounts.enabled"], PrefValue::Bool(false));
+        assert_eq!(prefs["browser.display.background_color"], string("#1c1b22"));
+    }
+
+    #[test]
+    fn test_malformed_fixture() {
+        let prefs = parse_prefs(include_str!("testdata/malformed-prefs.js"));
+        let keys: Vec<&str> = prefs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "before.broken",
+                "after.missing.value",
+                "after.unknown.value",
+                "two.on.one.line",
+                "second.on.line",
+                "multi.line.string",
+                "escapes",
+                "negative",
+                "positive",
+                "after.out.of.range",
+                "after.no.semicolon",
+            ]
+        );
+
+        let prefs = effective_prefs(prefs);
+        assert_eq!(
+            prefs["multi.line.string"],
+            string("first line\nsecond line")
+        );
+        assert_eq!(
+            prefs["escapes"],
+            string("tab\there Aé 😀 'single' \"double\"")
+        );
+        assert_eq!(prefs["negative"], PrefValue::Int(-42));
+        assert_eq!(prefs["positive"], PrefValue::Int(7));
+    }
+}
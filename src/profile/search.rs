@@ -2,14 +2,25 @@ use anyhow::{Context, Result};
 use serde::Deserialize;
 use std::path::Path;
 
-use super::mozlz4::decode_mozlz4;
+use super::mozlz4::{decode_mozlz4, encode_mozlz4};
+use super::write_atomic;
+use crate::events::{SearchEngineDetails, SearchParam};
 
-#[derive(Debug, Clone)]
+/// Legacy pref naming the default engine, kept in step with search.json
+pub const DEFAULT_ENGINE_PREF: &str = "browser.search.defaultenginename";
+
+/// MIME type of search result URLs
+const RESULTS_TYPE: &str = "text/html";
+/// MIME type of search suggestion URLs
+const SUGGEST_TYPE: &str = "application/x-suggestions+json";
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct SearchEngine {
     pub id: String,
     pub name: String,
     pub url: String,
     pub is_default: bool,
+    pub details: SearchEngineDetails,
 }
 
 #[derive(Deserialize)]
@@ -21,12 +32,18 @@ struct SearchFile {
 
 #[derive(Deserialize)]
 struct Engine {
+    /// Browser-assigned engine ID (differs between profiles for user engines)
+    id: Option<String>,
     #[serde(rename = "_name")]
     name: String,
     #[serde(rename = "_loadPath")]
     load_path: Option<String>,
     #[serde(rename = "_metaData")]
     meta_data: Option<EngineMeta>,
+    #[serde(rename = "_iconURL")]
+    icon_url: Option<String>,
+    #[serde(rename = "_urls", default)]
+    urls: Vec<EngineUrl>,
 }
 
 #[derive(Deserialize)]
@@ -34,13 +51,37 @@ struct EngineMeta {
     alias: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct EngineUrl {
+    template: String,
+    #[serde(rename = "type")]
+    url_type: Option<String>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Vec<SearchParam>,
+}
+
 #[derive(Deserialize)]
 struct Metadata {
     #[serde(rename = "defaultEngineId")]
     default_engine_id: Option<String>,
 }
 
+/// Read search engines from search.json.mozlz4, plus any OpenSearch XML
+/// engines in the profile's `searchplugins` directory
 pub fn read_search_engines(profile_path: &Path) -> Result<Vec<SearchEngine>> {
+    let mut engines = read_search_json(profile_path)?;
+
+    for engine in read_searchplugins(profile_path)? {
+        if !engines.iter().any(|e| e.id == engine.id) {
+            engines.push(engine);
+        }
+    }
+
+    Ok(engines)
+}
+
+fn read_search_json(profile_path: &Path) -> Result<Vec<SearchEngine>> {
     let search_path = profile_path.join("search.json.mozlz4");
 
     if !search_path.exists() {
@@ -53,9 +94,66 @@ pub fn read_search_engines(profile_path: &Path) -> Result<Vec<SearchEngine>> {
     let decompressed =
         decode_mozlz4(&compressed).context("Failed to decompress search.json.mozlz4")?;
 
-    let file: SearchFile =
+    parse_search_json(&decompressed)
+}
+
+/// Make an engine the default in search.json.mozlz4
+///
+/// Returns `false` if the profile has no engine with that sync ID, since the
+/// browser can only default to engines it knows about. Other fields in the
+/// file are preserved as-is.
+pub fn write_default_search_engine(profile_path: &Path, id: &str) -> Result<bool> {
+    let search_path = profile_path.join("search.json.mozlz4");
+
+    if !search_path.exists() {
+        return Ok(false);
+    }
+
+    let compressed = std::fs::read(&search_path)
+        .with_context(|| format!("Failed to read {}", search_path.display()))?;
+    let decompressed =
+        decode_mozlz4(&compressed).context("Failed to decompress search.json.mozlz4")?;
+    let mut file: serde_json::Value =
         serde_json::from_slice(&decompressed).context("Failed to parse search.json")?;
 
+    let Some(browser_id) = find_browser_engine_id(&file, id) else {
+        return Ok(false);
+    };
+
+    let root = file
+        .as_object_mut()
+        .context("search.json is not an object")?;
+    let metadata = root
+        .entry("metaData")
+        .or_insert_with(|| serde_json::json!({}))
+        .as_object_mut()
+        .context("search.json metaData is not an object")?;
+    metadata.insert("defaultEngineId".to_string(), browser_id.into());
+
+    let json = serde_json::to_vec(&file).context("Failed to serialize search.json")?;
+    write_atomic(&search_path, encode_mozlz4(&json))?;
+    Ok(true)
+}
+
+/// Find the browser's own ID for the engine with the given sync ID
+fn find_browser_engine_id(file: &serde_json::Value, id: &str) -> Option<String> {
+    let engine = file["engines"].as_array()?.iter().find(|engine| {
+        let name = engine["_name"].as_str().unwrap_or_default();
+        match engine["_metaData"]["alias"].as_str() {
+            Some(alias) => alias == id,
+            None => engine_id(name) == id,
+        }
+    })?;
+
+    engine["id"]
+        .as_str()
+        .or_else(|| engine["_name"].as_str())
+        .map(String::from)
+}
+
+fn parse_search_json(json: &[u8]) -> Result<Vec<SearchEngine>> {
+    let file: SearchFile = serde_json::from_slice(json).context("Failed to parse search.json")?;
+
     let default_id = file
         .metadata
         .and_then(|m| m.default_engine_id)
@@ -68,16 +166,281 @@ pub fn read_search_engines(profile_path: &Path) -> Result<Vec<SearchEngine>> {
             let id = engine
                 .meta_data
                 .and_then(|m| m.alias)
-                .unwrap_or_else(|| engine.name.to_lowercase().replace(' ', "-"));
+                .unwrap_or_else(|| engine_id(&engine.name));
+            let is_default = id == default_id || engine.id.as_deref() == Some(&default_id);
+            let search_url = engine.urls.iter().find(|u| is_results_url(u));
 
             SearchEngine {
-                is_default: id == default_id,
+                is_default,
                 id,
                 name: engine.name,
-                url: engine.load_path.unwrap_or_default(),
+                url: search_url
+                    .map(|u| u.template.clone())
+                    .or(engine.load_path)
+                    .unwrap_or_default(),
+                details: SearchEngineDetails {
+                    icon_url: engine.icon_url,
+                    suggest_url: engine
+                        .urls
+                        .iter()
+                        .find(|u| u.url_type.as_deref() == Some(SUGGEST_TYPE))
+                        .map(|u| u.template.clone()),
+                    method: search_url.and_then(|u| normalize_method(u.method.as_deref())),
+                    params: search_url.map(|u| u.params.clone()).unwrap_or_default(),
+                },
             }
         })
         .collect();
 
     Ok(engines)
 }
+
+fn is_results_url(url: &EngineUrl) -> bool {
+    url.url_type.as_deref().is_none_or(|t| t == RESULTS_TYPE)
+}
+
+/// Uppercase the method, treating GET as the default
+fn normalize_method(method: Option<&str>) -> Option<String> {
+    method.map(|m| m.to_uppercase()).filter(|m| m != "GET")
+}
+
+/// Sync ID for an engine without an alias
+fn engine_id(name: &str) -> String {
+    name.to_lowercase().replace(' ', "-")
+}
+
+/// Read user-added OpenSearch engines from `searchplugins/*.xml`
+fn read_searchplugins(profile_path: &Path) -> Result<Vec<SearchEngine>> {
+    let plugins_dir = profile_path.join("searchplugins");
+
+    if !plugins_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let entries = std::fs::read_dir(&plugins_dir)
+        .with_context(|| format!("Failed to read {}", plugins_dir.display()))?;
+
+    let mut engines = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "xml") {
+            continue;
+        }
+
+        let xml = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        match parse_opensearch(&xml) {
+            Ok(engine) => engines.push(engine),
+            Err(e) => tracing::warn!("Skipping {}: {:#}", path.display(), e),
+        }
+    }
+
+    engines.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(engines)
+}
+
+/// Parse an OpenSearch description document
+///
+/// Accepts both the OpenSearch 1.1 format and Mozilla's legacy
+/// `SearchPlugin` format (namespaces are ignored).
+pub fn parse_opensearch(xml: &str) -> Result<SearchEngine> {
+    let doc = roxmltree::Document::parse(xml).context("Invalid OpenSearch XML")?;
+    let root = doc.root_element();
+
+    let child_text = |tag: &str| {
+        root.children()
+            .find(|n| n.has_tag_name(tag))
+            .and_then(|n| n.text())
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+    };
+
+    let name = child_text("ShortName").context("OpenSearch engine has no ShortName")?;
+    let urls: Vec<_> = root.children().filter(|n| n.has_tag_name("Url")).collect();
+
+    let search_url = urls
+        .iter()
+        .find(|n| n.attribute("type").is_none_or(|t| t == RESULTS_TYPE))
+        .context("OpenSearch engine has no text/html Url")?;
+    let template = search_url
+        .attribute("template")
+        .context("OpenSearch Url has no template")?;
+
+    let params = search_url
+        .children()
+        .filter(|n| n.has_tag_name("Param") || n.has_tag_name("MozParam"))
+        .filter_map(|n| {
+            Some(SearchParam {
+                name: n.attribute("name")?.to_string(),
+                value: n.attribute("value")?.to_string(),
+            })
+        })
+        .collect();
+
+    Ok(SearchEngine {
+        id: engine_id(&name),
+        name,
+        url: template.to_string(),
+        is_default: false,
+        details: SearchEngineDetails {
+            icon_url: child_text("Image"),
+            suggest_url: urls
+                .iter()
+                .find(|n| n.attribute("type") == Some(SUGGEST_TYPE))
+                .and_then(|n| n.attribute("template"))
+                .map(String::from),
+            method: normalize_method(search_url.attribute("method")),
+            params,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const OPENSEARCH_XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <ShortName>Example Search</ShortName>
+  <Description>Search example.com</Description>
+  <Image width="16" height="16">data:image/png;base64,AAAA</Image>
+  <Url type="text/html" method="post" template="https://example.com/search">
+    <Param name="q" value="{searchTerms}"/>
+    <Param name="src" value="wolfpack"/>
+  </Url>
+  <Url type="application/x-suggestions+json" template="https://example.com/suggest?q={searchTerms}"/>
+</OpenSearchDescription>"#;
+
+    #[test]
+    fn test_parse_opensearch() {
+        let engine = parse_opensearch(OPENSEARCH_XML).unwrap();
+
+        assert_eq!(engine.id, "example-search");
+        assert_eq!(engine.name, "Example Search");
+        assert_eq!(engine.url, "https://example.com/search");
+        assert_eq!(
+            engine.details.icon_url.as_deref(),
+            Some("data:image/png;base64,AAAA")
+        );
+        assert_eq!(
+            engine.details.suggest_url.as_deref(),
+            Some("https://example.com/suggest?q={searchTerms}")
+        );
+        assert_eq!(engine.details.method.as_deref(), Some("POST"));
+        assert_eq!(engine.details.params.len(), 2);
+        assert_eq!(engine.details.params[0].name, "q");
+        assert_eq!(engine.details.params[0].value, "{searchTerms}");
+    }
+
+    #[test]
+    fn test_parse_mozilla_searchplugin() {
+        let xml = r#"<SearchPlugin xmlns="http://www.mozilla.org/2006/browser/search/"
+              xmlns:os="http://a9.com/-/spec/opensearch/1.1/">
+  <os:ShortName>Legacy</os:ShortName>
+  <os:Url type="text/html" method="GET" template="https://legacy.example/?q={searchTerms}"/>
+</SearchPlugin>"#;
+
+        let engine = parse_opensearch(xml).unwrap();
+        assert_eq!(engine.name, "Legacy");
+        assert_eq!(engine.url, "https://legacy.example/?q={searchTerms}");
+        assert!(engine.details.method.is_none());
+    }
+
+    #[test]
+    fn test_parse_opensearch_requires_url() {
+        let xml = r#"<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+  <ShortName>Broken</ShortName>
+</OpenSearchDescription>"#;
+
+        assert!(parse_opensearch(xml).is_err());
+    }
+
+    #[test]
+    fn test_parse_search_json_full_definition() {
+        let json = r#"{
+            "engines": [{
+                "id": "3f1a",
+                "_name": "Startpage",
+                "_loadPath": "[https]www.startpage.com/opensearch.xml",
+                "_metaData": {"alias": "sp"},
+                "_iconURL": "data:image/x-icon;base64,BBBB",
+                "_urls": [
+                    {"template": "https://www.startpage.com/sp/search", "method": "POST",
+                     "params": [{"name": "query", "value": "{searchTerms}"}]},
+                    {"template": "https://www.startpage.com/suggestions?q={searchTerms}",
+                     "type": "application/x-suggestions+json"}
+                ]
+            }, {
+                "id": "ddg@search.mozilla.org",
+                "_name": "DuckDuckGo",
+                "_isAppProvided": true
+            }],
+            "metaData": {"defaultEngineId": "3f1a"}
+        }"#;
+
+        let engines = parse_search_json(json.as_bytes()).unwrap();
+        assert_eq!(engines.len(), 2);
+
+        let startpage = &engines[0];
+        assert_eq!(startpage.id, "sp");
+        assert!(startpage.is_default);
+        assert_eq!(startpage.url, "https://www.startpage.com/sp/search");
+        assert_eq!(startpage.details.method.as_deref(), Some("POST"));
+        assert_eq!(startpage.details.params[0].name, "query");
+        assert!(startpage.details.suggest_url.is_some());
+
+        let ddg = &engines[1];
+        assert_eq!(ddg.id, "duckduckgo");
+        assert!(!ddg.is_default);
+        assert_eq!(ddg.details, SearchEngineDetails::default());
+    }
+
+    #[test]
+    fn test_write_default_search_engine() {
+        let dir = tempdir().unwrap();
+        let json = r#"{
+            "version": 9,
+            "engines": [
+                {"id": "ddg@search.mozilla.org", "_name": "DuckDuckGo", "_isAppProvided": true},
+                {"id": "3f1a", "_name": "Startpage", "_metaData": {"alias": "sp"}}
+            ],
+            "metaData": {"defaultEngineId": "ddg@search.mozilla.org", "useSavedOrder": true}
+        }"#;
+        std::fs::write(
+            dir.path().join("search.json.mozlz4"),
+            encode_mozlz4(json.as_bytes()),
+        )
+        .unwrap();
+
+        assert!(write_default_search_engine(dir.path(), "sp").unwrap());
+        assert!(!write_default_search_engine(dir.path(), "missing").unwrap());
+
+        let engines = read_search_engines(dir.path()).unwrap();
+        let default: Vec<_> = engines.iter().filter(|e| e.is_default).collect();
+        assert_eq!(default.len(), 1);
+        assert_eq!(default[0].id, "sp");
+
+        // Unrelated fields survive the rewrite
+        let raw =
+            decode_mozlz4(&std::fs::read(dir.path().join("search.json.mozlz4")).unwrap()).unwrap();
+        let file: serde_json::Value = serde_json::from_slice(&raw).unwrap();
+        assert_eq!(file["version"], 9);
+        assert_eq!(file["metaData"]["useSavedOrder"], true);
+        assert_eq!(file["engines"][0]["_isAppProvided"], true);
+    }
+
+    #[test]
+    fn test_read_searchplugins() {
+        let dir = tempdir().unwrap();
+        let plugins = dir.path().join("searchplugins");
+        std::fs::create_dir_all(&plugins).unwrap();
+        std::fs::write(plugins.join("example.xml"), OPENSEARCH_XML).unwrap();
+        std::fs::write(plugins.join("broken.xml"), "<not xml").unwrap();
+        std::fs::write(plugins.join("notes.txt"), "ignored").unwrap();
+
+        let engines = read_search_engines(dir.path()).unwrap();
+        assert_eq!(engines.len(), 1);
+        assert_eq!(engines[0].id, "example-search");
+    }
+}
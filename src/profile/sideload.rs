@@ -0,0 +1,233 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+use super::prefs::parse_pref_line;
+use super::write_atomic;
+use crate::events::PrefValue;
+
+/// Pref holding the install scopes whose dropped-in add-ons start disabled
+pub const AUTO_DISABLE_SCOPES_PREF: &str = "extensions.autoDisableScopes";
+
+/// Startup cache of add-on state, rebuilt from `extensions.json` when missing
+pub const ADDON_STARTUP_FILE: &str = "addonStartup.json.lz4";
+
+/// The profile's `extensions/` directory (`AddonManager.SCOPE_PROFILE`)
+const SCOPE_PROFILE: i64 = 1;
+
+/// Firefox's default: every scope but the application's
+const DEFAULT_AUTO_DISABLE_SCOPES: i64 = 15;
+
+/// Location `extensions.json` gives add-ons in the profile's `extensions/`
+const PROFILE_LOCATION: &str = "app-profile";
+
+/// Whether XPIs dropped into the profile's `extensions/` directory start
+/// enabled
+pub fn profile_sideloads_allowed(profile_path: &Path) -> Result<bool> {
+    let (scopes, _) = read_auto_disable_scopes(profile_path)?;
+    Ok(scopes & SCOPE_PROFILE == 0)
+}
+
+/// Let XPIs dropped into the profile's `extensions/` directory start enabled
+///
+/// Firefox disables add-ons it finds there until the user confirms them,
+/// unless the profile scope is left out of `extensions.autoDisableScopes`.
+/// The pref is set in `prefs.js`, which the browser reads at startup, so
+/// it stays local to this device. Returns whether `prefs.js` changed.
+pub fn allow_profile_sideloads(profile_path: &Path) -> Result<bool> {
+    let (scopes, mut lines) = read_auto_disable_scopes(profile_path)?;
+    if scopes & SCOPE_PROFILE == 0 {
+        return Ok(false);
+    }
+
+    lines.push(format!(
+        "user_pref(\"{}\", {});",
+        AUTO_DISABLE_SCOPES_PREF,
+        scopes & !SCOPE_PROFILE
+    ));
+    write_atomic(&profile_path.join("prefs.js"), lines.join("\n") + "\n")?;
+    Ok(true)
+}
+
+/// The scopes `prefs.js` disables sideloads in, and its other lines
+fn read_auto_disable_scopes(profile_path: &Path) -> Result<(i64, Vec<String>)> {
+    let path = profile_path.join("prefs.js");
+    let content = if path.exists() {
+        std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?
+    } else {
+        String::new()
+    };
+
+    let mut scopes = DEFAULT_AUTO_DISABLE_SCOPES;
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        match parse_pref_line(line.trim()) {
+            Some((key, PrefValue::Int(value))) if key == AUTO_DISABLE_SCOPES_PREF => {
+                scopes = value;
+            }
+            _ => lines.push(line.to_string()),
+        }
+    }
+    Ok((scopes, lines))
+}
+
+/// Which of `ids` the browser registered as sideloads awaiting confirmation
+pub fn sideloads_awaiting_confirmation(
+    profile_path: &Path,
+    ids: &HashSet<String>,
+) -> Result<Vec<String>> {
+    let Some(mut file) = read_extensions_json(profile_path)? else {
+        return Ok(Vec::new());
+    };
+    Ok(awaiting_addons(&mut file, ids).map(|(id, _)| id).collect())
+}
+
+/// Enable extensions the browser registered as sideloads awaiting
+/// confirmation, returning the IDs enabled
+///
+/// Only add-ons in the profile's `extensions/` directory that the user has
+/// never seen are enabled, so ones disabled in about:addons stay disabled.
+/// The add-on startup cache still has them disabled, so it's removed for
+/// the browser to rebuild from `extensions.json` on its next start.
+pub fn enable_sideloaded(profile_path: &Path, ids: &HashSet<String>) -> Result<Vec<String>> {
+    let Some(mut file) = read_extensions_json(profile_path)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut enabled = Vec::new();
+    for (id, addon) in awaiting_addons(&mut file, ids) {
+        enabled.push(id);
+        let app_disabled = addon.get("appDisabled") == Some(&Value::Bool(true));
+        addon.insert("userDisabled".to_string(), Value::Bool(false));
+        addon.insert("seen".to_string(), Value::Bool(true));
+        addon.insert("active".to_string(), Value::Bool(!app_disabled));
+    }
+    if enabled.is_empty() {
+        return Ok(enabled);
+    }
+
+    write_atomic(
+        &profile_path.join("extensions.json"),
+        serde_json::to_string(&file)?,
+    )?;
+    match std::fs::remove_file(profile_path.join(ADDON_STARTUP_FILE)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).context("Failed to remove the add-on startup cache");
+        }
+        _ => {}
+    }
+    Ok(enabled)
+}
+
+fn read_extensions_json(profile_path: &Path) -> Result<Option<Value>> {
+    let path = profile_path.join("extensions.json");
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(Some(
+        serde_json::from_str(&content).context("Failed to parse extensions.json")?,
+    ))
+}
+
+/// Add-ons among `ids` in the profile's `extensions/` directory that are
+/// disabled and that the user has never seen
+fn awaiting_addons<'a>(
+    file: &'a mut Value,
+    ids: &'a HashSet<String>,
+) -> impl Iterator<Item = (String, &'a mut Map<String, Value>)> {
+    file.get_mut("addons")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_object_mut)
+        .filter_map(move |addon| {
+            let id = addon.get("id").and_then(Value::as_str)?.to_string();
+            let awaiting = ids.contains(&id)
+                && addon.get("location").and_then(Value::as_str) == Some(PROFILE_LOCATION)
+                && addon.get("userDisabled") == Some(&Value::Bool(true))
+                && addon.get("seen") == Some(&Value::Bool(false));
+            awaiting.then_some((id, addon))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_allow_profile_sideloads() {
+        let dir = tempdir().unwrap();
+        let prefs_js = dir.path().join("prefs.js");
+        std::fs::write(&prefs_js, "user_pref(\"browser.startup.page\", 3);\n").unwrap();
+
+        assert!(allow_profile_sideloads(dir.path()).unwrap());
+        let content = std::fs::read_to_string(&prefs_js).unwrap();
+        assert!(content.contains("user_pref(\"browser.startup.page\", 3);"));
+        assert!(content.contains("user_pref(\"extensions.autoDisableScopes\", 14);"));
+
+        // Already allowed
+        assert!(profile_sideloads_allowed(dir.path()).unwrap());
+        assert!(!allow_profile_sideloads(dir.path()).unwrap());
+
+        // Other scopes the user chose are kept
+        std::fs::write(
+            &prefs_js,
+            "user_pref(\"extensions.autoDisableScopes\", 3);\n",
+        )
+        .unwrap();
+        assert!(allow_profile_sideloads(dir.path()).unwrap());
+        let content = std::fs::read_to_string(&prefs_js).unwrap();
+        assert_eq!(content, "user_pref(\"extensions.autoDisableScopes\", 2);\n");
+    }
+
+    #[test]
+    fn test_enable_sideloaded() {
+        let dir = tempdir().unwrap();
+        let json = serde_json::json!({
+            "schemaVersion": 36,
+            "addons": [
+                {"id": "synced@example.com", "location": "app-profile", "userDisabled": true,
+                 "seen": false, "active": false, "appDisabled": false, "type": "extension"},
+                {"id": "disabled@example.com", "location": "app-profile", "userDisabled": true,
+                 "seen": true, "active": false, "type": "extension"},
+                {"id": "other@example.com", "location": "app-profile", "userDisabled": true,
+                 "seen": false, "active": false, "type": "extension"}
+            ]
+        });
+        std::fs::write(dir.path().join("extensions.json"), json.to_string()).unwrap();
+        std::fs::write(dir.path().join(ADDON_STARTUP_FILE), b"cache").unwrap();
+
+        let ids: HashSet<String> = ["synced@example.com", "disabled@example.com"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        assert_eq!(
+            sideloads_awaiting_confirmation(dir.path(), &ids).unwrap(),
+            vec!["synced@example.com"]
+        );
+        let enabled = enable_sideloaded(dir.path(), &ids).unwrap();
+        assert_eq!(enabled, vec!["synced@example.com"]);
+        assert!(!dir.path().join(ADDON_STARTUP_FILE).exists());
+
+        let content = std::fs::read_to_string(dir.path().join("extensions.json")).unwrap();
+        let file: Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(file["schemaVersion"], 36);
+        assert_eq!(file["addons"][0]["userDisabled"], false);
+        assert_eq!(file["addons"][0]["active"], true);
+        // Disabled by the user, or not synced
+        assert_eq!(file["addons"][1]["userDisabled"], true);
+        assert_eq!(file["addons"][2]["userDisabled"], true);
+
+        assert!(
+            sideloads_awaiting_confirmation(dir.path(), &ids)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(enable_sideloaded(dir.path(), &ids).unwrap().is_empty());
+    }
+}
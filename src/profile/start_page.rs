@@ -0,0 +1,219 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use tracing::warn;
+
+use super::prefs::should_include;
+use super::read_prefs;
+use crate::events::{PinnedSite, PrefValue};
+
+/// Homepage URLs, separated by `|`
+pub const HOMEPAGE_PREF: &str = "browser.startup.homepage";
+
+/// Pinned top sites, stored by the new tab page as a JSON array
+pub const PINNED_SITES_PREF: &str = "browser.newtabpage.pinned";
+
+/// New tab and startup prefs synced as start page layout
+///
+/// Activity stream keeps a lot of internal state in prefs too (impression
+/// IDs, cached feeds), so only the user-facing layout options are listed.
+const NEWTAB_SETTINGS: &[&str] = &[
+    "browser.startup.page",
+    "browser.newtabpage.enabled",
+    "browser.newtabpage.activity-stream.showSearch",
+    "browser.newtabpage.activity-stream.showWeather",
+    "browser.newtabpage.activity-stream.showSponsored",
+    "browser.newtabpage.activity-stream.showSponsoredTopSites",
+    "browser.newtabpage.activity-stream.feeds.topsites",
+    "browser.newtabpage.activity-stream.topSitesRows",
+    "browser.newtabpage.activity-stream.feeds.section.topstories",
+    "browser.newtabpage.activity-stream.feeds.section.highlights",
+    "browser.newtabpage.activity-stream.section.highlights.include*",
+    "browser.newtabpage.activity-stream.section.highlights.rows",
+];
+
+/// Homepage, new tab layout, and pinned top sites of a profile
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StartPage {
+    /// Homepage URLs (empty means the browser default)
+    pub homepage: Vec<String>,
+    /// New tab and startup settings by pref name
+    pub settings: HashMap<String, PrefValue>,
+    /// Pinned top sites by tile position
+    pub pinned: Vec<Option<PinnedSite>>,
+}
+
+impl StartPage {
+    /// Build the start page from its prefs, ignoring unrelated ones
+    pub fn from_prefs(prefs: &HashMap<String, PrefValue>) -> Self {
+        let homepage = match prefs.get(HOMEPAGE_PREF) {
+            Some(PrefValue::String(urls)) => split_homepage(urls),
+            _ => Vec::new(),
+        };
+        let pinned = match prefs.get(PINNED_SITES_PREF) {
+            Some(PrefValue::String(json)) => parse_pinned_sites(json),
+            _ => Vec::new(),
+        };
+        let settings = prefs
+            .iter()
+            .filter(|(key, _)| is_newtab_setting(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        Self {
+            homepage,
+            settings,
+            pinned,
+        }
+    }
+
+    /// Prefs to write to user.js for this start page
+    pub fn to_prefs(&self) -> HashMap<String, PrefValue> {
+        let mut prefs = self.settings.clone();
+        if !self.homepage.is_empty() {
+            prefs.insert(
+                HOMEPAGE_PREF.to_string(),
+                PrefValue::String(self.homepage.join("|")),
+            );
+        }
+        if !self.pinned.is_empty() {
+            prefs.insert(
+                PINNED_SITES_PREF.to_string(),
+                PrefValue::String(render_pinned_sites(&self.pinned)),
+            );
+        }
+        prefs
+    }
+}
+
+/// Whether a pref is one of the synced new tab settings
+pub fn is_newtab_setting(key: &str) -> bool {
+    let patterns: Vec<String> = NEWTAB_SETTINGS.iter().map(|s| s.to_string()).collect();
+    should_include(key, &patterns)
+}
+
+pub fn read_start_page(profile_path: &Path) -> Result<StartPage> {
+    if !profile_path.join("prefs.js").exists() {
+        return Ok(StartPage::default());
+    }
+
+    let mut patterns = vec![HOMEPAGE_PREF.to_string(), PINNED_SITES_PREF.to_string()];
+    patterns.extend(NEWTAB_SETTINGS.iter().map(|s| s.to_string()));
+    let prefs = read_prefs(profile_path, &patterns)?;
+
+    Ok(StartPage::from_prefs(&prefs))
+}
+
+fn split_homepage(urls: &str) -> Vec<String> {
+    urls.split('|')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Parse the pinned sites pref, keeping only the URL and label of each tile
+pub fn parse_pinned_sites(json: &str) -> Vec<Option<PinnedSite>> {
+    let mut sites: Vec<Option<PinnedSite>> = match serde_json::from_str(json) {
+        Ok(sites) => sites,
+        Err(e) => {
+            warn!("Skipping invalid {}: {}", PINNED_SITES_PREF, e);
+            return Vec::new();
+        }
+    };
+
+    // Trailing empty slots carry no layout
+    while sites.last().is_some_and(Option::is_none) {
+        sites.pop();
+    }
+    sites
+}
+
+fn render_pinned_sites(sites: &[Option<PinnedSite>]) -> String {
+    serde_json::to_string(sites).unwrap_or_else(|_| "[]".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_read_start_page() {
+        let dir = tempdir().unwrap();
+        let prefs = r#"
+user_pref("browser.startup.homepage", "https://example.com|https://example.org");
+user_pref("browser.startup.page", 1);
+user_pref("browser.newtabpage.activity-stream.feeds.topsites", true);
+user_pref("browser.newtabpage.activity-stream.section.highlights.includePocket", false);
+user_pref("browser.newtabpage.activity-stream.impressionId", "{abc}");
+user_pref("browser.newtabpage.pinned", "[{\"url\":\"https://example.com\",\"label\":\"Example\",\"baseDomain\":\"example.com\"},null,null]");
+"#;
+        std::fs::write(dir.path().join("prefs.js"), prefs).unwrap();
+
+        let start_page = read_start_page(dir.path()).unwrap();
+
+        assert_eq!(
+            start_page.homepage,
+            vec!["https://example.com", "https://example.org"]
+        );
+        assert_eq!(start_page.settings.len(), 3);
+        assert_eq!(
+            start_page.settings.get("browser.startup.page"),
+            Some(&PrefValue::Int(1))
+        );
+        assert!(
+            !start_page
+                .settings
+                .contains_key("browser.newtabpage.activity-stream.impressionId")
+        );
+        assert_eq!(
+            start_page.pinned,
+            vec![Some(PinnedSite {
+                url: "https://example.com".to_string(),
+                label: Some("Example".to_string()),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_read_start_page_without_prefs() {
+        let dir = tempdir().unwrap();
+        assert_eq!(read_start_page(dir.path()).unwrap(), StartPage::default());
+    }
+
+    #[test]
+    fn test_start_page_prefs_round_trip() {
+        let mut settings = HashMap::new();
+        settings.insert(
+            "browser.newtabpage.activity-stream.topSitesRows".to_string(),
+            PrefValue::Int(2),
+        );
+        let start_page = StartPage {
+            homepage: vec!["about:home".to_string(), "https://example.com".to_string()],
+            settings,
+            pinned: vec![
+                None,
+                Some(PinnedSite {
+                    url: "https://example.org".to_string(),
+                    label: None,
+                }),
+            ],
+        };
+
+        let prefs = start_page.to_prefs();
+        assert_eq!(
+            prefs.get(PINNED_SITES_PREF),
+            Some(&PrefValue::String(
+                r#"[null,{"url":"https://example.org"}]"#.to_string()
+            ))
+        );
+        assert_eq!(StartPage::from_prefs(&prefs), start_page);
+    }
+
+    #[test]
+    fn test_parse_pinned_sites_invalid() {
+        assert!(parse_pinned_sites("not json").is_empty());
+        assert!(parse_pinned_sites("[null, null]").is_empty());
+    }
+}
@@ -0,0 +1,38 @@
+// Mozilla User Preferences
+
+// DO NOT EDIT THIS FILE.
+//
+// If you make changes to this file while the application is running,
+// the changes will be overwritten when the application exits.
+//
+// To change a preference value, you can either:
+// - modify it via the UI (e.g. via about:config in the browser); or
+// - set it within a user.js file in your profile.
+
+user_pref("app.normandy.first_run", false);
+user_pref("app.update.lastUpdateTime.addon-background-update-timer", 1760000000);
+user_pref("browser.bookmarks.addedImportButton", true);
+user_pref("browser.contentblocking.category", "strict");
+user_pref("browser.download.dir", "/home/alex/Downloads/firefox");
+user_pref("browser.download.lastDir", "C:\\Users\\alex\\Downloads");
+user_pref("browser.laterrun.bookkeeping.profileCreationTime", 1735689600);
+user_pref("browser.migration.version", 152);
+user_pref("browser.newtabpage.activity-stream.impressionId", "{3f2a9c1e-7b4d-4e8a-9f61-2c5d8e0b7a13}");
+user_pref("browser.newtabpage.pinned", "[{\"url\":\"https://example.com/\",\"label\":\"Example\"},null,{\"url\":\"https://www.mozilla.org/\",\"label\":\"Mozilla \\u2013 Home\"}]");
+user_pref("browser.pageActions.persistedActions", "{\"ids\":[\"bookmark\"],\"idsInUrlbar\":[\"bookmark\"],\"idsInUrlbarPreProton\":[],\"version\":1}");
+user_pref("browser.search.region", "DE");
+user_pref("browser.startup.homepage", "https://example.com/|https://www.mozilla.org/");
+user_pref("browser.startup.page", 3);
+user_pref("browser.tabs.warnOnClose", false);
+user_pref("browser.uiCustomization.state", "{\"placements\":{\"widget-overflow-fixed-list\":[],\"nav-bar\":[\"back-button\",\"forward-button\",\"stop-reload-button\",\"urlbar-container\",\"downloads-button\"]},\"seen\":[\"developer-button\"],\"dirtyAreaCache\":[\"nav-bar\"],\"currentVersion\":20,\"newElementCount\":2}");
+user_pref("browser.urlbar.placeholderName", "DuckDuckGo");
+user_pref("devtools.toolbox.host", "right");
+user_pref("extensions.webextensions.uuids", "{\"uBlock0@raymondhill.net\":\"8d1f6c2a-5e3b-4a7c-9d0e-1f2a3b4c5d6e\"}");
+user_pref("font.name.serif.x-western", "Noto Serif");
+user_pref("intl.accept_languages", "de-DE, en-US");
+user_pref("layout.css.devPixelsPerPx", "1.25");
+user_pref("media.gmp-gmpopenh264.lastUpdate", 1759000000);
+user_pref("network.proxy.type", 0);
+user_pref("privacy.sanitize.pending", "[{\"id\":\"newtab-container\",\"itemsToClear\":[],\"options\":{}}]");
+user_pref("toolkit.telemetry.cachedClientID", "c0ffee00-0000-4000-8000-000000000000");
+user_pref("widget.gtk.overlay-scrollbars.enabled", false);
@@ -0,0 +1,22 @@
+/** LibreWolf-style overrides, as found in librewolf.overrides.cfg or user.js
+ *
+ * Block comments can span lines, and hold things that look like prefs:
+ * user_pref("commented.out", true);
+ */
+
+// Defaults, which user_pref in prefs.js overrides
+pref("browser.startup.page", 1);
+defaultPref("privacy.resistFingerprinting", true);
+pref("webgl.disabled", true, sticky);
+sticky_pref("browser.sessionstore.resume_from_crash", false);
+
+// Locked prefs win over everything
+lockPref("network.trr.mode", 3);
+pref("network.trr.uri", "https://dns.example.com/dns-query", locked);
+lockPref('privacy.clearOnShutdown.history', false); // single quotes, trailing comment
+
+# Hash comments are accepted too
+user_pref("browser.startup.page", 3);
+user_pref("privacy.resistFingerprinting.letterboxing",true);
+user_pref ( "identity.fxaccounts.enabled" , false ) ;
+user_pref("browser.display.background_color", "#1c1b22");
@@ -0,0 +1,17 @@
+// Lines a hand-edited user.js can end up with, each followed by a good pref
+user_pref("before.broken", 1);
+user_pref("missing.value", );
+user_pref("after.missing.value", 2);
+user_pref("unknown.value", undefined);
+user_pref("after.unknown.value", 3);
+user_pref("two.on.one.line", 4); user_pref("second.on.line", "yes");
+user_pref("multi.line.string", "first line
+second line");
+user_pref("escapes", "tab\there \x41\u00e9 \ud83d\ude00 \'single\' \"double\"");
+user_pref("negative", -42);
+user_pref("positive", +7);
+user_pref("out.of.range", 99999999999999999999);
+user_pref("after.out.of.range", 5);
+user_pref("no.semicolon", 6)
+user_pref("after.no.semicolon", 7);
+user_pref("unterminated", "never closed);
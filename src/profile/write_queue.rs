@@ -1,22 +1,146 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use tracing::warn;
 
-use super::{Container, Handler, write_containers, write_handlers, write_user_js};
+use super::{
+    Address, CONTAINER_SITES_FILE, CertOverride, Container, Handler, Permission, ProfileBackups,
+    SiteAssignment, SiteFilter, capability_name, read_addresses, read_cert_overrides,
+    read_containers, read_handlers, read_permissions, read_search_engines, read_site_assignments,
+    render_user_js, write_addresses, write_cert_overrides, write_containers,
+    write_default_search_engine, write_handlers, write_permissions, write_site_assignments,
+    write_user_js,
+};
 use crate::events::PrefValue;
+use crate::extensions::{PendingInstall, install_all_to_profile};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum PendingWrite {
     Containers(Vec<Container>),
+    /// Multi-Account Containers site assignments
+    ContainerSites(Vec<SiteAssignment>),
     Handlers(Vec<Handler>),
     Prefs(HashMap<String, PrefValue>),
+    /// Make the engine with this sync ID the default search engine
+    SearchDefault(String),
+    Addresses(Vec<Address>),
+    CertOverrides(Vec<CertOverride>),
+    /// Site permissions for the sites `filter` includes
+    Permissions {
+        permissions: Vec<Permission>,
+        filter: SiteFilter,
+    },
+    /// Install an extension XPI (zstd-compressed, base64 encoded)
+    Extension {
+        id: String,
+        name: String,
+        version: String,
+        xpi_data: String,
+    },
+}
+
+impl PendingWrite {
+    /// Profile file this write replaces
+    pub fn target(&self) -> String {
+        match self {
+            PendingWrite::Containers(_) => "containers.json".to_string(),
+            PendingWrite::ContainerSites(_) => CONTAINER_SITES_FILE.to_string(),
+            PendingWrite::Handlers(_) => "handlers.json".to_string(),
+            PendingWrite::Prefs(_) => "user.js".to_string(),
+            PendingWrite::SearchDefault(_) => "search.json.mozlz4".to_string(),
+            PendingWrite::Addresses(_) => "autofill-profiles.json".to_string(),
+            PendingWrite::Permissions { .. } => "permissions.sqlite".to_string(),
+            PendingWrite::CertOverrides(_) => "cert_override.txt".to_string(),
+            PendingWrite::Extension { id, .. } => format!("extensions/{}.xpi", id),
+        }
+    }
+
+    /// Describe the write as a diff against the current profile contents
+    #[allow(clippy::too_many_lines)] // One arm per write kind
+    pub fn describe(&self, profile_path: &Path) -> Result<Vec<String>> {
+        match self {
+            PendingWrite::Containers(containers) => Ok(describe_containers(
+                &read_containers(profile_path)?,
+                containers,
+            )),
+            PendingWrite::ContainerSites(sites) => Ok(describe_container_sites(
+                &read_site_assignments(profile_path)?,
+                sites,
+            )),
+            PendingWrite::Handlers(handlers) => {
+                Ok(describe_handlers(&read_handlers(profile_path)?, handlers))
+            }
+            PendingWrite::Prefs(prefs) => {
+                let current =
+                    std::fs::read_to_string(profile_path.join("user.js")).unwrap_or_default();
+                Ok(diff_lines(&current, &render_user_js(prefs)))
+            }
+            PendingWrite::SearchDefault(id) => {
+                let engines = read_search_engines(profile_path)?;
+                let current = engines.iter().find(|e| e.is_default).map(|e| e.id.as_str());
+                if current == Some(id.as_str()) {
+                    return Ok(Vec::new());
+                }
+                Ok(vec![format!(
+                    "~ default search engine: {} -> {}",
+                    current.unwrap_or("(none)"),
+                    id
+                )])
+            }
+            PendingWrite::Addresses(addresses) => Ok(describe_addresses(
+                &read_addresses(profile_path)?,
+                addresses,
+            )),
+            PendingWrite::CertOverrides(overrides) => Ok(describe_cert_overrides(
+                &read_cert_overrides(profile_path)?,
+                overrides,
+            )),
+            PendingWrite::Permissions {
+                permissions,
+                filter,
+            } => {
+                let mut current = read_permissions(profile_path)?;
+                current.retain(|p| filter.includes(&p.origin));
+                Ok(describe_permissions(&current, permissions))
+            }
+            PendingWrite::Extension {
+                name,
+                version,
+                xpi_data,
+                ..
+            } => Ok(vec![format!(
+                "+ {} {} ({} bytes compressed)",
+                name,
+                version,
+                xpi_data.len()
+            )]),
+        }
+    }
+
+    /// Stable fingerprint of the write contents
+    fn fingerprint(&self) -> String {
+        // HashMap iteration order is random, so hash prefs in sorted order
+        let canonical = match self {
+            PendingWrite::Prefs(prefs) => {
+                serde_json::to_string(&prefs.iter().collect::<BTreeMap<_, _>>())
+            }
+            other => serde_json::to_string(other),
+        }
+        .unwrap_or_default();
+
+        hex::encode(Sha256::digest(format!("{}\n{}", self.target(), canonical)))
+    }
 }
 
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct WriteQueue {
     profile_path: PathBuf,
     pending: Vec<PendingWrite>,
+    /// Fingerprints of discarded writes, so the same state isn't re-queued
+    #[serde(default)]
+    rejected: HashSet<String>,
 }
 
 impl WriteQueue {
@@ -24,6 +148,7 @@ impl WriteQueue {
         Self {
             profile_path,
             pending: Vec::new(),
+            rejected: HashSet::new(),
         }
     }
 
@@ -49,32 +174,99 @@ impl WriteQueue {
     }
 
     pub fn queue_containers(&mut self, containers: Vec<Container>) {
-        // Replace any existing pending containers write
-        self.pending
-            .retain(|w| !matches!(w, PendingWrite::Containers(_)));
-        self.pending.push(PendingWrite::Containers(containers));
+        self.queue(PendingWrite::Containers(containers));
     }
 
     pub fn queue_handlers(&mut self, handlers: Vec<Handler>) {
-        self.pending
-            .retain(|w| !matches!(w, PendingWrite::Handlers(_)));
-        self.pending.push(PendingWrite::Handlers(handlers));
+        self.queue(PendingWrite::Handlers(handlers));
     }
 
     pub fn queue_prefs(&mut self, prefs: HashMap<String, PrefValue>) {
-        self.pending
-            .retain(|w| !matches!(w, PendingWrite::Prefs(_)));
-        self.pending.push(PendingWrite::Prefs(prefs));
+        self.queue(PendingWrite::Prefs(prefs));
+    }
+
+    pub fn queue_search_default(&mut self, id: String) {
+        self.queue(PendingWrite::SearchDefault(id));
+    }
+
+    pub fn queue_addresses(&mut self, addresses: Vec<Address>) {
+        self.queue(PendingWrite::Addresses(addresses));
+    }
+
+    pub fn queue_container_sites(&mut self, sites: Vec<SiteAssignment>) {
+        self.queue(PendingWrite::ContainerSites(sites));
+    }
+
+    pub fn queue_cert_overrides(&mut self, overrides: Vec<CertOverride>) {
+        self.queue(PendingWrite::CertOverrides(overrides));
+    }
+
+    pub fn queue_permissions(&mut self, permissions: Vec<Permission>, filter: SiteFilter) {
+        self.queue(PendingWrite::Permissions {
+            permissions,
+            filter,
+        });
+    }
+
+    pub fn queue_extension(&mut self, id: String, name: String, version: String, xpi_data: String) {
+        self.queue(PendingWrite::Extension {
+            id,
+            name,
+            version,
+            xpi_data,
+        });
+    }
+
+    /// Queue a write, replacing any pending write to the same file
+    fn queue(&mut self, write: PendingWrite) {
+        if self.rejected.contains(&write.fingerprint()) {
+            return;
+        }
+        // Skip writes that wouldn't change the profile
+        if matches!(write.describe(&self.profile_path), Ok(lines) if lines.is_empty()) {
+            return;
+        }
+        let target = write.target();
+        self.pending.retain(|w| w.target() != target);
+        self.pending.push(write);
     }
 
     pub fn is_empty(&self) -> bool {
         self.pending.is_empty()
     }
 
-    pub fn flush(&mut self) -> Result<Vec<String>> {
+    pub fn pending(&self) -> &[PendingWrite] {
+        &self.pending
+    }
+
+    /// Describe each pending write as (target file, diff lines)
+    pub fn describe(&self) -> Result<Vec<(String, Vec<String>)>> {
+        self.pending
+            .iter()
+            .map(|w| Ok((w.target(), w.describe(&self.profile_path)?)))
+            .collect()
+    }
+
+    /// Drop all pending writes; identical writes won't be queued again
+    pub fn discard(&mut self) -> usize {
+        let count = self.pending.len();
+        for write in self.pending.drain(..) {
+            self.rejected.insert(write.fingerprint());
+        }
+        count
+    }
+
+    /// Apply all pending writes, backing up each file before it is replaced
+    ///
+    /// Extensions are written together on the install pool after the rest.
+    /// One that fails is logged and left out, to be queued again.
+    #[allow(clippy::too_many_lines)] // One arm per write kind
+    pub fn flush(&mut self, backups: &ProfileBackups) -> Result<Vec<String>> {
         let mut applied = Vec::new();
+        let mut extensions = Vec::new();
 
         for write in self.pending.drain(..) {
+            backups.backup(&self.profile_path, &write.target())?;
             match write {
                 PendingWrite::Containers(containers) => {
                     write_containers(&self.profile_path, &containers)?;
@@ -88,11 +280,252 @@ impl WriteQueue {
                     write_user_js(&self.profile_path, &prefs)?;
                     applied.push("user.js".to_string());
                 }
+                PendingWrite::SearchDefault(id) => {
+                    if write_default_search_engine(&self.profile_path, &id)? {
+                        applied.push("search.json.mozlz4".to_string());
+                    }
+                }
+                PendingWrite::Addresses(addresses) => {
+                    write_addresses(&self.profile_path, &addresses)?;
+                    applied.push("autofill-profiles.json".to_string());
+                }
+                PendingWrite::ContainerSites(sites) => {
+                    write_site_assignments(&self.profile_path, &sites)?;
+                    applied.push(CONTAINER_SITES_FILE.to_string());
+                }
+                PendingWrite::CertOverrides(overrides) => {
+                    write_cert_overrides(&self.profile_path, &overrides)?;
+                    applied.push("cert_override.txt".to_string());
+                }
+                PendingWrite::Permissions {
+                    permissions,
+                    filter,
+                } => {
+                    write_permissions(&self.profile_path, &permissions, &filter)?;
+                    applied.push("permissions.sqlite".to_string());
+                }
+                PendingWrite::Extension {
+                    id,
+                    name,
+                    version,
+                    xpi_data,
+                } => extensions.push(PendingInstall {
+                    id,
+                    name,
+                    version,
+                    xpi_data,
+                }),
             }
         }
 
+        applied.extend(self.install_extensions(&extensions)?);
         Ok(applied)
     }
+
+    /// Write queued extensions in parallel, returning the files written
+    fn install_extensions(&self, extensions: &[PendingInstall]) -> Result<Vec<String>> {
+        if extensions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let results = install_all_to_profile(extensions, &self.profile_path)?;
+        let mut written = Vec::new();
+        for (extension, result) in extensions.iter().zip(results) {
+            match result {
+                Ok(()) => written.push(format!("extensions/{}.xpi", extension.id)),
+                Err(e) => warn!(
+                    "Failed to install extension {} v{}: {:#}",
+                    extension.name, extension.version, e
+                ),
+            }
+        }
+        Ok(written)
+    }
+}
+
+fn describe_containers(current: &[Container], target: &[Container]) -> Vec<String> {
+    let current: HashMap<u32, &Container> =
+        current.iter().map(|c| (c.user_context_id, c)).collect();
+    let target_ids: HashSet<u32> = target.iter().map(|c| c.user_context_id).collect();
+    let mut lines = Vec::new();
+
+    for container in target {
+        match current.get(&container.user_context_id) {
+            None => lines.push(format!("+ {} ({})", container.name, container.color)),
+            Some(existing)
+                if existing.name != container.name
+                    || existing.color != container.color
+                    || existing.icon != container.icon =>
+            {
+                lines.push(format!(
+                    "~ {} ({}) -> {} ({})",
+                    existing.name, existing.color, container.name, container.color
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    for (id, container) in &current {
+        if !target_ids.contains(id) {
+            lines.push(format!("- {} ({})", container.name, container.color));
+        }
+    }
+
+    lines
+}
+
+fn describe_handlers(current: &[Handler], target: &[Handler]) -> Vec<String> {
+    let current: HashMap<&str, &str> = current
+        .iter()
+        .map(|h| (h.protocol.as_str(), h.handler.as_str()))
+        .collect();
+    let target_protocols: HashSet<&str> = target.iter().map(|h| h.protocol.as_str()).collect();
+    let mut lines = Vec::new();
+
+    for handler in target {
+        match current.get(handler.protocol.as_str()) {
+            None => lines.push(format!("+ {} -> {}", handler.protocol, handler.handler)),
+            Some(existing) if *existing != handler.handler => {
+                lines.push(format!(
+                    "~ {} -> {} (was {})",
+                    handler.protocol, handler.handler, existing
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    for (protocol, handler) in &current {
+        if !target_protocols.contains(protocol) {
+            lines.push(format!("- {} -> {}", protocol, handler));
+        }
+    }
+
+    lines
+}
+
+fn describe_container_sites(current: &[SiteAssignment], target: &[SiteAssignment]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for site in target.iter().filter(|s| !current.contains(s)) {
+        lines.push(format!(
+            "+ {} -> container {}",
+            site.host, site.container_id
+        ));
+    }
+    for site in current {
+        if !target.iter().any(|s| s.host == site.host) {
+            lines.push(format!("- {}", site.host));
+        }
+    }
+
+    lines
+}
+
+fn describe_cert_overrides(current: &[CertOverride], target: &[CertOverride]) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    for cert in target.iter().filter(|c| !current.contains(c)) {
+        lines.push(format!("+ trust {} for {}", cert.fingerprint, cert.host));
+    }
+    for cert in current {
+        if !target.iter().any(|c| c.host == cert.host) {
+            lines.push(format!("- {}", cert.host));
+        }
+    }
+
+    lines
+}
+
+fn describe_permissions(current: &[Permission], target: &[Permission]) -> Vec<String> {
+    let current_map: HashMap<(&str, &str), u32> = current
+        .iter()
+        .map(|p| ((p.origin.as_str(), p.permission.as_str()), p.capability))
+        .collect();
+    let target_keys: HashSet<(&str, &str)> = target
+        .iter()
+        .map(|p| (p.origin.as_str(), p.permission.as_str()))
+        .collect();
+    let mut lines = Vec::new();
+
+    for p in target {
+        let change = match current_map.get(&(p.origin.as_str(), p.permission.as_str())) {
+            None => "+",
+            Some(&capability) if capability != p.capability => "~",
+            Some(_) => continue,
+        };
+        lines.push(format!(
+            "{} {} {} for {}",
+            change,
+            capability_name(p.capability),
+            p.permission,
+            p.origin
+        ));
+    }
+
+    for p in current {
+        if !target_keys.contains(&(p.origin.as_str(), p.permission.as_str())) {
+            lines.push(format!("- {} for {}", p.permission, p.origin));
+        }
+    }
+
+    lines
+}
+
+/// Describe address changes by field name only, without personal data
+fn describe_addresses(current: &[Address], target: &[Address]) -> Vec<String> {
+    let current: HashMap<&str, &Address> = current.iter().map(|a| (a.guid.as_str(), a)).collect();
+    let target_guids: HashSet<&str> = target.iter().map(|a| a.guid.as_str()).collect();
+    let mut lines = Vec::new();
+
+    for address in target {
+        match current.get(address.guid.as_str()) {
+            None => lines.push(format!("+ address {}", address.guid)),
+            Some(existing) if existing.fields != address.fields => {
+                let changed: Vec<&str> = existing
+                    .fields
+                    .keys()
+                    .chain(address.fields.keys())
+                    .filter(|k| existing.fields.get(*k) != address.fields.get(*k))
+                    .map(String::as_str)
+                    .collect::<std::collections::BTreeSet<_>>()
+                    .into_iter()
+                    .collect();
+                lines.push(format!(
+                    "~ address {} ({})",
+                    address.guid,
+                    changed.join(", ")
+                ));
+            }
+            _ => {}
+        }
+    }
+
+    for guid in current.keys() {
+        if !target_guids.contains(guid) {
+            lines.push(format!("- address {}", guid));
+        }
+    }
+
+    lines
+}
+
+/// Line-level diff: lines only in `current` are removed, lines only in `target` added
+fn diff_lines(current: &str, target: &str) -> Vec<String> {
+    let current_lines: HashSet<&str> = current.lines().collect();
+    let target_lines: HashSet<&str> = target.lines().collect();
+
+    let removed = current
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !target_lines.contains(l))
+        .map(|l| format!("- {}", l));
+    let added = target
+        .lines()
+        .filter(|l| !l.trim().is_empty() && !current_lines.contains(l))
+        .map(|l| format!("+ {}", l));
+
+    removed.chain(added).collect()
 }
 
 #[cfg(test)]
@@ -136,9 +569,174 @@ mod tests {
             is_public: true,
         }]);
 
-        let applied = queue.flush().unwrap();
+        let backups = ProfileBackups::new(dir.path().join("backups"), 5);
+        let applied = queue.flush(&backups).unwrap();
         assert_eq!(applied, vec!["containers.json"]);
         assert!(queue.is_empty());
         assert!(profile_path.join("containers.json").exists());
+
+        // The replaced file is backed up on the next flush
+        queue.queue_containers(vec![test_container(1, "Office")]);
+        queue.flush(&backups).unwrap();
+        assert_eq!(backups.list("containers.json").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_write_queue_flush_extensions() {
+        let dir = tempdir().unwrap();
+        let profile_path = dir.path().join("profile");
+        std::fs::create_dir_all(&profile_path).unwrap();
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("manifest.json"),
+            r#"{"manifest_version": 2, "name": "Queued", "version": "1.0",
+                "browser_specific_settings": {"gecko": {"id": "queued@test.com"}}}"#,
+        )
+        .unwrap();
+        let (_, xpi_data) = crate::extensions::package_extension(&source).unwrap();
+
+        let mut queue = WriteQueue::new(profile_path.clone());
+        let (name, version) = ("Queued".to_string(), "1.0".to_string());
+        queue.queue_extension(
+            "broken@test.com".into(),
+            name.clone(),
+            version.clone(),
+            "?".into(),
+        );
+        queue.queue_extension("queued@test.com".into(), name, version, xpi_data);
+        queue.queue_containers(vec![test_container(1, "Office")]);
+
+        // The broken one doesn't stop the rest
+        let backups = ProfileBackups::new(dir.path().join("backups"), 5);
+        let applied = queue.flush(&backups).unwrap();
+        assert_eq!(
+            applied,
+            vec!["containers.json", "extensions/queued@test.com.xpi"]
+        );
+        assert!(profile_path.join("extensions/queued@test.com.xpi").exists());
+        assert!(queue.is_empty());
+    }
+
+    fn test_container(id: u32, name: &str) -> Container {
+        Container {
+            user_context_id: id,
+            name: name.to_string(),
+            icon: "circle".to_string(),
+            color: "blue".to_string(),
+            is_public: true,
+        }
+    }
+
+    #[test]
+    fn test_write_queue_describe_prefs() {
+        let dir = tempdir().unwrap();
+        let profile_path = dir.path().to_path_buf();
+
+        let mut prefs = HashMap::new();
+        prefs.insert("browser.startup.page".to_string(), PrefValue::Int(1));
+        write_user_js(&profile_path, &prefs).unwrap();
+
+        prefs.insert("browser.startup.page".to_string(), PrefValue::Int(3));
+        let mut queue = WriteQueue::new(profile_path);
+        queue.queue_prefs(prefs);
+
+        let described = queue.describe().unwrap();
+        assert_eq!(described.len(), 1);
+        assert_eq!(described[0].0, "user.js");
+        assert_eq!(
+            described[0].1,
+            vec![
+                "- user_pref(\"browser.startup.page\", 1);",
+                "+ user_pref(\"browser.startup.page\", 3);",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_containers() {
+        let current = vec![test_container(1, "Work"), test_container(2, "Shopping")];
+        let target = vec![test_container(1, "Office"), test_container(3, "Banking")];
+
+        let mut lines = describe_containers(&current, &target);
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "+ Banking (blue)",
+                "- Shopping (blue)",
+                "~ Work (blue) -> Office (blue)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_permissions() {
+        let permission = |origin: &str, permission: &str, capability: u32| Permission {
+            origin: origin.to_string(),
+            permission: permission.to_string(),
+            capability,
+        };
+        let current = vec![
+            permission("https://a.test", "camera", 1),
+            permission("https://a.test", "geo", 1),
+        ];
+        let target = vec![
+            permission("https://a.test", "camera", 2),
+            permission("https://b.test", "desktop-notification", 2),
+        ];
+
+        assert_eq!(
+            describe_permissions(&current, &target),
+            vec![
+                "~ block camera for https://a.test",
+                "+ block desktop-notification for https://b.test",
+                "- geo for https://a.test",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_describe_addresses() {
+        let address = |guid: &str, fields: &[(&str, &str)]| Address {
+            guid: guid.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        };
+        let current = vec![
+            address("a", &[("given-name", "Ada"), ("tel", "123")]),
+            address("b", &[("given-name", "Bob")]),
+        ];
+        let target = vec![
+            address("a", &[("given-name", "Ada"), ("email", "ada@example.com")]),
+            address("c", &[("given-name", "Cy")]),
+        ];
+
+        let mut lines = describe_addresses(&current, &target);
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec!["+ address c", "- address b", "~ address a (email, tel)"]
+        );
+    }
+
+    #[test]
+    fn test_write_queue_discard_rejects_same_state() {
+        let dir = tempdir().unwrap();
+        let mut queue = WriteQueue::new(dir.path().to_path_buf());
+
+        queue.queue_containers(vec![test_container(1, "Work")]);
+        assert_eq!(queue.discard(), 1);
+        assert!(queue.is_empty());
+
+        // Same state is not queued again
+        queue.queue_containers(vec![test_container(1, "Work")]);
+        assert!(queue.is_empty());
+
+        // New state is
+        queue.queue_containers(vec![test_container(1, "Office")]);
+        assert_eq!(queue.pending().len(), 1);
     }
 }
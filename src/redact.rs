@@ -0,0 +1,201 @@
+use std::borrow::Cow;
+use std::sync::RwLock;
+
+use crate::config::LoggingConfig;
+use crate::profile::should_include;
+
+/// Hex strings at least this long are keys (or hashes), shown by fingerprint
+const MIN_KEY_LEN: usize = 32;
+
+/// What to scrub, while `logging.redact` is set
+#[derive(Debug, Clone, Default)]
+struct Redaction {
+    tab_urls: bool,
+    sensitive_prefs: Vec<String>,
+}
+
+static REDACTION: RwLock<Option<Redaction>> = RwLock::new(None);
+
+/// Values that are never shown, like the API token, once they're known
+static SECRETS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Start or stop redacting as the config says
+pub fn configure(config: &LoggingConfig) {
+    let redaction = config.redact.then(|| Redaction {
+        tab_urls: config.redact_tab_urls,
+        sensitive_prefs: config.sensitive_prefs.clone(),
+    });
+    if let Ok(mut current) = REDACTION.write() {
+        *current = redaction;
+    }
+}
+
+/// Never show this value while redacting
+pub fn add_secret(secret: &str) {
+    if let Ok(mut secrets) = SECRETS.write()
+        && !secret.is_empty()
+        && !secrets.iter().any(|s| s == secret)
+    {
+        secrets.push(secret.to_string());
+    }
+}
+
+fn current() -> Option<Redaction> {
+    REDACTION.read().ok()?.clone()
+}
+
+pub fn is_enabled() -> bool {
+    current().is_some()
+}
+
+/// Whether a pref's value is hidden, by `logging.sensitive_prefs`
+pub fn is_sensitive_pref(key: &str) -> bool {
+    current().is_some_and(|r| should_include(key, &r.sensitive_prefs))
+}
+
+/// A public key, or just its fingerprint while redacting
+pub fn public_key(key: &str) -> Cow<'_, str> {
+    if is_enabled() {
+        Cow::Owned(fingerprint(key))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+/// A tab's URL, or just its origin while redacting tab URLs
+pub fn tab_url(url: &str) -> Cow<'_, str> {
+    match current() {
+        Some(redaction) if redaction.tab_urls => Cow::Owned(scrub_urls(url)),
+        _ => Cow::Borrowed(url),
+    }
+}
+
+/// Text with secrets scrubbed while redacting: known secrets are hidden,
+/// keys are shortened to fingerprints, and with `logging.redact_tab_urls`,
+/// URLs are cut down to their origin
+pub fn text(text: &str) -> Cow<'_, str> {
+    let Some(redaction) = current() else {
+        return Cow::Borrowed(text);
+    };
+    let secrets = SECRETS.read().map(|s| s.clone()).unwrap_or_default();
+    Cow::Owned(scrub(text, &redaction, &secrets))
+}
+
+fn scrub(text: &str, redaction: &Redaction, secrets: &[String]) -> String {
+    let mut scrubbed = text.to_string();
+    for secret in secrets {
+        scrubbed = scrubbed.replace(secret.as_str(), "[redacted]");
+    }
+    scrubbed = scrub_keys(&scrubbed);
+    if redaction.tab_urls {
+        scrubbed = scrub_urls(&scrubbed);
+    }
+    scrubbed
+}
+
+/// The first and last 8 characters of a key, as pairing shows them
+pub fn fingerprint(key: &str) -> String {
+    if key.len() >= 16 && key.is_ascii() {
+        format!("{}...{}", &key[..8], &key[key.len() - 8..])
+    } else {
+        key.to_string()
+    }
+}
+
+/// Shorten long hex strings standing on their own to fingerprints
+fn scrub_keys(text: &str) -> String {
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(|c: char| c.is_ascii_hexdigit()) {
+        let run_len = rest[start..]
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(rest.len() - start);
+        let end = start + run_len;
+        let standalone = !rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric())
+            && !rest[end..]
+                .chars()
+                .next()
+                .is_some_and(|c| c.is_alphanumeric());
+        scrubbed.push_str(&rest[..start]);
+        if run_len >= MIN_KEY_LEN && standalone {
+            scrubbed.push_str(&fingerprint(&rest[start..end]));
+        } else {
+            scrubbed.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    scrubbed.push_str(rest);
+    scrubbed
+}
+
+/// Cut web addresses down to their scheme and host
+fn scrub_urls(text: &str) -> String {
+    let mut scrubbed = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = ["https://", "http://"]
+        .iter()
+        .filter_map(|scheme| rest.find(scheme))
+        .min()
+    {
+        let url_len = rest[start..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | ')'))
+            .unwrap_or(rest.len() - start);
+        let url = &rest[start..start + url_len];
+        let host_start = url.find("://").map_or(0, |i| i + 3);
+        let host_end = url[host_start..]
+            .find(['/', '?', '#'])
+            .map_or(url.len(), |i| host_start + i);
+        scrubbed.push_str(&rest[..start]);
+        if &url[host_end..] == "/" {
+            scrubbed.push_str(url);
+        } else {
+            scrubbed.push_str(&url[..host_end]);
+            if url.len() > host_end {
+                scrubbed.push_str("/…");
+            }
+        }
+        rest = &rest[start + url_len..];
+    }
+    scrubbed.push_str(rest);
+    scrubbed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrub() {
+        let key = "a".repeat(64);
+        let token = "0123456789abcdef".repeat(4);
+        let redaction = Redaction::default();
+        let scrubbed = scrub(
+            &format!("key {} token {} file 0007-1a2b3c4d.evt", key, token),
+            &redaction,
+            std::slice::from_ref(&token),
+        );
+        assert_eq!(
+            scrubbed,
+            "key aaaaaaaa...aaaaaaaa token [redacted] file 0007-1a2b3c4d.evt"
+        );
+
+        // URLs are left alone unless tab URLs are redacted
+        let line = "send https://example.com/private?q=1 to laptop";
+        assert_eq!(scrub(line, &redaction, &[]), line);
+        let redaction = Redaction {
+            tab_urls: true,
+            ..Redaction::default()
+        };
+        assert_eq!(
+            scrub(line, &redaction, &[]),
+            "send https://example.com/… to laptop"
+        );
+        assert_eq!(
+            scrub("open \"http://example.com/\"", &redaction, &[]),
+            "open \"http://example.com/\""
+        );
+    }
+}
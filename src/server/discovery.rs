@@ -1,10 +1,183 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    extract::{ConnectInfo, DefaultBodyLimit, State},
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+use tracing::info;
 
-pub async fn run_discovery_server(_bind: &str) -> Result<()> {
-    // TODO: Implement discovery server
+use super::pairing::{CODE_EXPIRY, PairingRelay, RelayDevice, RelayStatus};
+
+const TOKEN_HEADER: &str = "X-Wolfpack-Relay-Token";
+/// Largest request body the relay reads
+const MAX_BODY: usize = 4096;
+
+type RelayState = Arc<Mutex<PairingRelay>>;
+
+/// Relayed pairing session created response
+#[derive(Serialize)]
+struct OpenResponse {
+    code: String,
+    token: String,
+    expires_in_seconds: u64,
+}
+
+/// Join a relayed pairing session
+#[derive(Deserialize)]
+struct JoinRequest {
+    code: String,
+    #[serde(flatten)]
+    device: RelayDevice,
+}
+
+#[derive(Serialize)]
+struct JoinResponse {
+    token: String,
+}
+
+/// Pending relayed pairing request response
+#[derive(Serialize)]
+struct PendingResponse {
+    pending: bool,
+    request: Option<RelayDevice>,
+}
+
+/// Accept/reject a relayed pairing request
+#[derive(Deserialize)]
+struct RespondRequest {
+    accept: bool,
+}
+
+/// Relayed pairing status, shaped like the local API's join response
+#[derive(Serialize)]
+struct StatusResponse {
+    status: &'static str,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    device: Option<RelayDevice>,
+}
+
+/// Create the discovery server router
+fn create_router() -> Router {
+    Router::new()
+        .route("/health", get(|| async { "OK" }))
+        .route("/pair/open", post(open_pairing))
+        .route("/pair/join", post(join_pairing))
+        .route("/pair/pending", get(get_pending_request))
+        .route("/pair/respond", post(respond_to_pairing))
+        .route("/pair/cancel", post(cancel_pairing))
+        .route("/pair/status", get(get_status))
+        .layer(DefaultBodyLimit::max(MAX_BODY))
+        .with_state(RelayState::default())
+}
+
+pub async fn run_discovery_server(bind: &str) -> Result<()> {
+    // TODO: Device registry
     // - Register device name → public key mapping
     // - Lookup devices by name
     // - Challenge-response authentication
-    println!("Discovery server not yet implemented");
+    let addr: SocketAddr = bind
+        .parse()
+        .with_context(|| format!("Invalid bind address '{}'", bind))?;
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    info!("Discovery server listening on http://{}", addr);
+
+    axum::serve(
+        listener,
+        create_router().into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
     Ok(())
 }
+
+async fn open_pairing(
+    State(relay): State<RelayState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(host): Json<RelayDevice>,
+) -> Result<Json<OpenResponse>, StatusCode> {
+    let (code, token) = relay.lock().await.open(host, addr.ip(), Instant::now())?;
+    Ok(Json(OpenResponse {
+        code,
+        token,
+        expires_in_seconds: CODE_EXPIRY.as_secs(),
+    }))
+}
+
+async fn join_pairing(
+    State(relay): State<RelayState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Json(req): Json<JoinRequest>,
+) -> Result<Json<JoinResponse>, StatusCode> {
+    let token = relay
+        .lock()
+        .await
+        .join(&req.code, req.device, addr.ip(), Instant::now())?;
+    Ok(Json(JoinResponse { token }))
+}
+
+async fn get_pending_request(
+    headers: HeaderMap,
+    State(relay): State<RelayState>,
+) -> Result<Json<PendingResponse>, StatusCode> {
+    let request = relay
+        .lock()
+        .await
+        .pending(session_token(&headers)?, Instant::now())?;
+    Ok(Json(PendingResponse {
+        pending: request.is_some(),
+        request,
+    }))
+}
+
+async fn respond_to_pairing(
+    headers: HeaderMap,
+    State(relay): State<RelayState>,
+    Json(req): Json<RespondRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    relay
+        .lock()
+        .await
+        .respond(session_token(&headers)?, req.accept, Instant::now())?;
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn cancel_pairing(
+    headers: HeaderMap,
+    State(relay): State<RelayState>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    relay.lock().await.cancel(session_token(&headers)?)?;
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn get_status(
+    headers: HeaderMap,
+    State(relay): State<RelayState>,
+) -> Result<Json<StatusResponse>, StatusCode> {
+    let status = relay
+        .lock()
+        .await
+        .status(session_token(&headers)?, Instant::now())?;
+    let response = StatusResponse {
+        status: status.as_str(),
+        device: match status {
+            RelayStatus::Accepted(host) => Some(host),
+            _ => None,
+        },
+    };
+    Ok(Json(response))
+}
+
+/// The session token a request presents
+fn session_token(headers: &HeaderMap) -> Result<&str, StatusCode> {
+    headers
+        .get(TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)
+}
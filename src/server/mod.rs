@@ -1,9 +1,15 @@
 #[cfg(feature = "server")]
+mod discovery;
+#[cfg(feature = "server")]
+mod pairing;
+#[cfg(feature = "server")]
 mod relay;
 #[cfg(feature = "server")]
-mod discovery;
+mod usage;
 
-#[cfg(feature = "server")]
-pub use relay::run_relay_server;
 #[cfg(feature = "server")]
 pub use discovery::run_discovery_server;
+#[cfg(feature = "server")]
+pub use relay::{RelayServerOptions, run_relay_server};
+#[cfg(feature = "server")]
+pub use usage::UsageLimits;
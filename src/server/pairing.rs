@@ -0,0 +1,416 @@
+use axum::http::StatusCode;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// How long a relayed pairing code remains valid, as for local pairing
+pub const CODE_EXPIRY: Duration = Duration::from_secs(300); // 5 minutes
+/// Open sessions the relay holds at once
+const MAX_SESSIONS: usize = 1024;
+/// Open sessions one client address may hold
+const MAX_SESSIONS_PER_ADDRESS: usize = 4;
+/// Wrong codes allowed per client address per window
+const JOIN_FAILURE_LIMIT: u32 = 10;
+const FAILURE_WINDOW: Duration = Duration::from_secs(60);
+/// Tracked addresses before stale ones are dropped
+const MAX_TRACKED: usize = 1024;
+/// Longest device ID, name, or public key the relay passes on
+const MAX_FIELD_LENGTH: usize = 256;
+/// Random bytes in a session token
+const TOKEN_LENGTH: usize = 32;
+
+/// One side of a relayed pairing, as it introduces itself to the other
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayDevice {
+    pub device_id: String,
+    pub device_name: String,
+    pub public_key: String,
+}
+
+impl RelayDevice {
+    fn is_valid(&self) -> bool {
+        [&self.device_id, &self.device_name, &self.public_key]
+            .iter()
+            .all(|field| !field.is_empty() && field.len() <= MAX_FIELD_LENGTH)
+    }
+}
+
+/// Where a relayed pairing stands, as the joiner sees it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RelayStatus {
+    /// The host hasn't answered yet
+    Waiting,
+    /// The host accepted, and introduced itself
+    Accepted(RelayDevice),
+    Rejected,
+    Expired,
+}
+
+impl RelayStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Waiting => "waiting",
+            Self::Accepted(_) => "accepted",
+            Self::Rejected => "rejected",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// A pairing brokered by code, between a host that opened it and the one
+/// device that joined it
+struct Session {
+    created_at: Instant,
+    address: IpAddr,
+    host: RelayDevice,
+    host_token: String,
+    joiner: Option<(RelayDevice, String)>,
+    accepted: Option<bool>,
+}
+
+impl Session {
+    fn is_expired(&self, now: Instant) -> bool {
+        now.saturating_duration_since(self.created_at) >= CODE_EXPIRY
+    }
+}
+
+/// Pairing sessions relayed between devices that can't reach each other's
+/// HTTP API, such as a phone and a desktop both behind NAT
+///
+/// The relay only passes device IDs, names, and public keys between the two
+/// sides, and forgets a session once the joiner has its answer. Everything
+/// after pairing is end-to-end encrypted with the exchanged keys, so the
+/// relay never needs to be trusted with sync data. Each side verifies the
+/// other's fingerprint, as with local pairing.
+#[derive(Default)]
+pub struct PairingRelay {
+    sessions: HashMap<String, Session>,
+    failures: HashMap<IpAddr, (Instant, u32)>,
+}
+
+impl PairingRelay {
+    /// Open a session for a host, returning its code and the token the host
+    /// presents for the rest of the pairing
+    pub fn open(
+        &mut self,
+        host: RelayDevice,
+        address: IpAddr,
+        now: Instant,
+    ) -> Result<(String, String), StatusCode> {
+        if !host.is_valid() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        self.prune(now);
+        let open = self
+            .sessions
+            .values()
+            .filter(|s| s.address == address)
+            .count();
+        if open >= MAX_SESSIONS_PER_ADDRESS {
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+        if self.sessions.len() >= MAX_SESSIONS {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let code = loop {
+            let code = generate_code();
+            if !self.sessions.contains_key(&code) {
+                break code;
+            }
+        };
+        let host_token = generate_token();
+        self.sessions.insert(
+            code.clone(),
+            Session {
+                created_at: now,
+                address,
+                host,
+                host_token: host_token.clone(),
+                joiner: None,
+                accepted: None,
+            },
+        );
+        Ok((code, host_token))
+    }
+
+    /// Join a session by code, returning the token the joiner polls for the
+    /// host's answer with
+    ///
+    /// A code can only be joined once. Addresses that keep trying wrong codes
+    /// are turned away until the window resets.
+    pub fn join(
+        &mut self,
+        code: &str,
+        joiner: RelayDevice,
+        address: IpAddr,
+        now: Instant,
+    ) -> Result<String, StatusCode> {
+        if !joiner.is_valid() {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        if self.is_locked_out(address, now) {
+            return Err(StatusCode::TOO_MANY_REQUESTS);
+        }
+
+        match self.sessions.get_mut(code) {
+            Some(session) if session.is_expired(now) => {
+                self.sessions.remove(code);
+                Err(StatusCode::GONE)
+            }
+            Some(session) if session.joiner.is_none() => {
+                let token = generate_token();
+                session.joiner = Some((joiner, token.clone()));
+                Ok(token)
+            }
+            _ => {
+                self.record_failure(address, now);
+                Err(StatusCode::NOT_FOUND)
+            }
+        }
+    }
+
+    /// The device waiting for the host's answer, if one has joined
+    pub fn pending(
+        &mut self,
+        host_token: &str,
+        now: Instant,
+    ) -> Result<Option<RelayDevice>, StatusCode> {
+        let session = self.host_session(host_token, now)?;
+        Ok(session.joiner.as_ref().map(|(device, _)| device.clone()))
+    }
+
+    /// Accept or reject the device that joined
+    pub fn respond(
+        &mut self,
+        host_token: &str,
+        accept: bool,
+        now: Instant,
+    ) -> Result<(), StatusCode> {
+        let session = self.host_session(host_token, now)?;
+        if session.joiner.is_none() || session.accepted.is_some() {
+            return Err(StatusCode::CONFLICT);
+        }
+        session.accepted = Some(accept);
+        Ok(())
+    }
+
+    /// Close a host's session before it's answered
+    pub fn cancel(&mut self, host_token: &str) -> Result<(), StatusCode> {
+        let code = self
+            .sessions
+            .iter()
+            .find(|(_, s)| s.host_token == host_token)
+            .map(|(code, _)| code.clone())
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        self.sessions.remove(&code);
+        Ok(())
+    }
+
+    /// The host's answer for a joiner, closing the session once there is one
+    pub fn status(&mut self, joiner_token: &str, now: Instant) -> Result<RelayStatus, StatusCode> {
+        let (code, session) = self
+            .sessions
+            .iter()
+            .find(|(_, s)| {
+                s.joiner
+                    .as_ref()
+                    .is_some_and(|(_, token)| token == joiner_token)
+            })
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let status = match session.accepted {
+            Some(true) => RelayStatus::Accepted(session.host.clone()),
+            Some(false) => RelayStatus::Rejected,
+            None if session.is_expired(now) => RelayStatus::Expired,
+            None => return Ok(RelayStatus::Waiting),
+        };
+        let code = code.clone();
+        self.sessions.remove(&code);
+        Ok(status)
+    }
+
+    /// The unexpired session a host token belongs to
+    fn host_session(&mut self, host_token: &str, now: Instant) -> Result<&mut Session, StatusCode> {
+        let session = self
+            .sessions
+            .values_mut()
+            .find(|s| s.host_token == host_token)
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+        if session.is_expired(now) {
+            return Err(StatusCode::GONE);
+        }
+        Ok(session)
+    }
+
+    fn is_locked_out(&self, address: IpAddr, now: Instant) -> bool {
+        self.failures.get(&address).is_some_and(|(started, count)| {
+            now.saturating_duration_since(*started) < FAILURE_WINDOW && *count >= JOIN_FAILURE_LIMIT
+        })
+    }
+
+    fn record_failure(&mut self, address: IpAddr, now: Instant) {
+        let (started, count) = self.failures.entry(address).or_insert((now, 0));
+        if now.saturating_duration_since(*started) >= FAILURE_WINDOW {
+            *started = now;
+            *count = 0;
+        }
+        *count += 1;
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.sessions.retain(|_, s| !s.is_expired(now));
+        if self.failures.len() > MAX_TRACKED {
+            self.failures
+                .retain(|_, (started, _)| now.saturating_duration_since(*started) < FAILURE_WINDOW);
+        }
+    }
+}
+
+/// Generate a 6-digit code, as for local pairing
+fn generate_code() -> String {
+    rand::thread_rng().gen_range(100000..1000000u32).to_string()
+}
+
+fn generate_token() -> String {
+    let mut rng = rand::thread_rng();
+    let bytes: Vec<u8> = (0..TOKEN_LENGTH).map(|_| rng.r#gen()).collect();
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    const HOST_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+    const JOINER_ADDRESS: IpAddr = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1));
+
+    fn device(id: &str) -> RelayDevice {
+        RelayDevice {
+            device_id: id.to_string(),
+            device_name: format!("{} name", id),
+            public_key: format!("{} key", id),
+        }
+    }
+
+    #[test]
+    fn test_relayed_pairing() {
+        let mut relay = PairingRelay::default();
+        let now = Instant::now();
+
+        let (code, host_token) = relay.open(device("desktop"), HOST_ADDRESS, now).unwrap();
+        assert_eq!(code.len(), 6);
+        assert_eq!(relay.pending(&host_token, now).unwrap(), None);
+        assert_eq!(
+            relay.respond(&host_token, true, now),
+            Err(StatusCode::CONFLICT)
+        );
+
+        let joiner_token = relay
+            .join(&code, device("phone"), JOINER_ADDRESS, now)
+            .unwrap();
+        assert_eq!(
+            relay.join(&code, device("other"), JOINER_ADDRESS, now),
+            Err(StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            relay.status(&joiner_token, now).unwrap(),
+            RelayStatus::Waiting
+        );
+        assert_eq!(
+            relay.pending(&host_token, now).unwrap(),
+            Some(device("phone"))
+        );
+
+        // Tokens only work for their own side
+        assert_eq!(
+            relay.respond(&joiner_token, true, now),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            relay.status(&host_token, now),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+
+        relay.respond(&host_token, true, now).unwrap();
+        assert_eq!(
+            relay.status(&joiner_token, now).unwrap(),
+            RelayStatus::Accepted(device("desktop"))
+        );
+
+        // The session is gone once the joiner has its answer
+        assert_eq!(
+            relay.status(&joiner_token, now),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+        assert_eq!(
+            relay.pending(&host_token, now),
+            Err(StatusCode::UNAUTHORIZED)
+        );
+    }
+
+    #[test]
+    fn test_relayed_pairing_expires() {
+        let mut relay = PairingRelay::default();
+        let now = Instant::now();
+        let later = now + CODE_EXPIRY;
+
+        let (code, host_token) = relay.open(device("desktop"), HOST_ADDRESS, now).unwrap();
+        let joiner_token = relay
+            .join(&code, device("phone"), JOINER_ADDRESS, now)
+            .unwrap();
+        assert_eq!(relay.pending(&host_token, later), Err(StatusCode::GONE));
+        assert_eq!(
+            relay.status(&joiner_token, later).unwrap(),
+            RelayStatus::Expired
+        );
+
+        let (code, _) = relay.open(device("desktop"), HOST_ADDRESS, now).unwrap();
+        assert_eq!(
+            relay.join(&code, device("phone"), JOINER_ADDRESS, later),
+            Err(StatusCode::GONE)
+        );
+    }
+
+    #[test]
+    fn test_relay_limits() {
+        let mut relay = PairingRelay::default();
+        let now = Instant::now();
+
+        for _ in 0..MAX_SESSIONS_PER_ADDRESS {
+            relay.open(device("desktop"), HOST_ADDRESS, now).unwrap();
+        }
+        assert_eq!(
+            relay.open(device("desktop"), HOST_ADDRESS, now),
+            Err(StatusCode::TOO_MANY_REQUESTS)
+        );
+        let mut invalid = device("desktop");
+        invalid.public_key = "k".repeat(MAX_FIELD_LENGTH + 1);
+        assert_eq!(
+            relay.open(invalid, JOINER_ADDRESS, now),
+            Err(StatusCode::BAD_REQUEST)
+        );
+
+        // Guessing codes locks the address out, even of a right one
+        let (code, _) = relay.open(device("desktop"), JOINER_ADDRESS, now).unwrap();
+        for _ in 0..JOIN_FAILURE_LIMIT {
+            assert_eq!(
+                relay.join("000000", device("phone"), JOINER_ADDRESS, now),
+                Err(StatusCode::NOT_FOUND)
+            );
+        }
+        assert_eq!(
+            relay.join(&code, device("phone"), JOINER_ADDRESS, now),
+            Err(StatusCode::TOO_MANY_REQUESTS)
+        );
+        let later = now + FAILURE_WINDOW;
+        assert!(
+            relay
+                .join(&code, device("phone"), JOINER_ADDRESS, later)
+                .is_ok()
+        );
+    }
+}
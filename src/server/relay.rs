@@ -1,10 +1,670 @@
-use anyhow::Result;
-
-pub async fn run_relay_server(_bind: &str) -> Result<()> {
-    // TODO: Implement relay server
-    // - Accept encrypted event uploads
-    // - Store events by device public key
-    // - Allow downloads of events
-    println!("Relay server not yet implemented");
+use anyhow::{Context, Result};
+use axum::{
+    Json, Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::{get, post},
+};
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use libp2p::{
+    Multiaddr, PeerId, Swarm, Transport, allow_block_list, identify, identity, ping, relay,
+    swarm::{NetworkBehaviour, SwarmEvent},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Mutex, mpsc};
+use tracing::{debug, info, warn};
+
+use super::usage::{Meters, UsageLedger, UsageLimits};
+use crate::daemon::ApiTokenManager;
+use crate::net::{PeerGate, Transports, build_transport, load_or_generate_identity};
+
+const TOKEN_HEADER: &str = "X-Wolfpack-Token";
+/// Peers allowed to use the relay, one peer ID per line, in the data directory
+const ALLOWLIST_FILE: &str = "allowlist";
+/// This month's usage per peer, in the data directory
+const USAGE_FILE: &str = "usage.json";
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often usage is added up, checked against the caps, and saved
+const TALLY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Options for `wolfpack relay-server`
+pub struct RelayServerOptions {
+    /// Addresses to listen on for peers
+    pub listen: Vec<Multiaddr>,
+    /// Public addresses peers reach the relay at, handed out with reservations
+    pub external: Vec<Multiaddr>,
+    /// Directory for the relay's identity, admin token, and allowlist
+    pub data_dir: PathBuf,
+    /// Address for the admin API
+    pub admin_bind: SocketAddr,
+    /// Let any peer use the relay, ignoring the allowlist
+    pub open: bool,
+    /// Monthly caps per peer
+    pub limits: UsageLimits,
+}
+
+#[derive(NetworkBehaviour)]
+struct RelayServerBehaviour {
+    /// Allowlist gate, consulted before the relay accepts a connection
+    gate: PeerGate,
+    /// Peers over a monthly cap, until the month is out
+    over_limit: allow_block_list::Behaviour<allow_block_list::BlockedPeers>,
+    relay: relay::Behaviour,
+    identify: identify::Behaviour,
+    ping: ping::Behaviour,
+}
+
+/// A peer's reservation on the relay
+#[derive(Debug, Clone, PartialEq)]
+struct Reservation {
+    since: DateTime<Utc>,
+    renewed_at: DateTime<Utc>,
+    /// Open circuits to the peer through the relay
+    circuits: usize,
+}
+
+/// Active reservations, kept up to date from relay events
+#[derive(Debug, Default)]
+struct Reservations {
+    peers: BTreeMap<PeerId, Reservation>,
+}
+
+impl Reservations {
+    fn record(&mut self, event: &relay::Event, now: DateTime<Utc>) {
+        debug!("Relay event: {:?}", event);
+        match event {
+            relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                self.peers
+                    .entry(*src_peer_id)
+                    .or_insert(Reservation {
+                        since: now,
+                        renewed_at: now,
+                        circuits: 0,
+                    })
+                    .renewed_at = now;
+            }
+            relay::Event::ReservationClosed { src_peer_id }
+            | relay::Event::ReservationTimedOut { src_peer_id } => {
+                self.peers.remove(src_peer_id);
+            }
+            relay::Event::CircuitReqAccepted { dst_peer_id, .. } => {
+                if let Some(reservation) = self.peers.get_mut(dst_peer_id) {
+                    reservation.circuits += 1;
+                }
+            }
+            relay::Event::CircuitClosed { dst_peer_id, .. } => {
+                if let Some(reservation) = self.peers.get_mut(dst_peer_id) {
+                    reservation.circuits = reservation.circuits.saturating_sub(1);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Peers allowed to use the relay, saved one peer ID per line
+#[derive(Debug)]
+struct Allowlist {
+    path: PathBuf,
+    peers: BTreeSet<PeerId>,
+}
+
+impl Allowlist {
+    /// Load the allowlist, skipping blank lines and `#` comments
+    fn load(path: PathBuf) -> Result<Self> {
+        let mut peers = BTreeSet::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            for line in content.lines().map(str::trim) {
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+                let peer = line
+                    .parse()
+                    .with_context(|| format!("Invalid peer ID '{}' in {}", line, path.display()))?;
+                peers.insert(peer);
+            }
+        }
+        Ok(Self { path, peers })
+    }
+
+    fn save(&self) -> Result<()> {
+        let content: String = self
+            .peers
+            .iter()
+            .map(|peer| format!("{}\n", peer))
+            .collect();
+        std::fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// State shared between the swarm loop and the admin API
+struct AdminState {
+    token_manager: ApiTokenManager,
+    reservations: Reservations,
+    usage: UsageLedger,
+    allowlist: Allowlist,
+    gate: PeerGate,
+    /// Whether the allowlist is ignored (`--open`)
+    open: bool,
+    /// Peers for the swarm loop to disconnect
+    kick_tx: mpsc::Sender<PeerId>,
+}
+
+impl AdminState {
+    fn allow(&mut self, peer: PeerId) -> Result<()> {
+        if self.allowlist.peers.insert(peer) {
+            self.allowlist.save()?;
+        }
+        self.gate.allow(peer);
+        Ok(())
+    }
+
+    /// Take a peer off the allowlist, so it can't connect again
+    fn revoke(&mut self, peer: &PeerId) -> Result<()> {
+        if self.allowlist.peers.remove(peer) {
+            self.allowlist.save()?;
+        }
+        self.gate.revoke(peer);
+        Ok(())
+    }
+}
+
+type SharedState = Arc<Mutex<AdminState>>;
+
+/// Active reservations response
+#[derive(Serialize)]
+struct ReservationsResponse {
+    reservations: Vec<ReservationEntry>,
+}
+
+#[derive(Serialize)]
+struct ReservationEntry {
+    peer_id: String,
+    since: String,
+    renewed_at: String,
+    circuits: usize,
+}
+
+/// Usage this month response
+#[derive(Serialize)]
+struct UsageResponse {
+    month: String,
+    monthly_mib: Option<u64>,
+    monthly_minutes: Option<u64>,
+    peers: Vec<UsageEntry>,
+}
+
+#[derive(Serialize)]
+struct UsageEntry {
+    peer_id: String,
+    bytes: u64,
+    circuit_minutes: u64,
+    /// Whether the peer is cut off until the month is out
+    over_limit: bool,
+}
+
+/// Allowlist response
+#[derive(Serialize)]
+struct AllowlistResponse {
+    /// Whether the allowlist is enforced (false with `--open`)
+    enforced: bool,
+    peers: Vec<String>,
+}
+
+/// Add or remove an allowlisted peer
+#[derive(Deserialize)]
+struct PeerRequest {
+    peer_id: String,
+}
+
+/// Disconnect a peer, and optionally take it off the allowlist
+#[derive(Deserialize)]
+struct KickRequest {
+    peer_id: String,
+    #[serde(default)]
+    revoke: bool,
+}
+
+/// Run a circuit relay for devices that can't reach each other directly,
+/// with an admin API for managing who may use it
+///
+/// Only allowlisted peers may connect unless the relay is run open, so a
+/// self-hosted relay doesn't relay for the whole internet.
+pub async fn run_relay_server(options: RelayServerOptions) -> Result<()> {
+    std::fs::create_dir_all(&options.data_dir)
+        .with_context(|| format!("Failed to create {}", options.data_dir.display()))?;
+    let key = load_or_generate_identity(&options.data_dir.join("relay.key"))?;
+    let token_manager = ApiTokenManager::load_or_create(&options.data_dir)?;
+    let allowlist = Allowlist::load(options.data_dir.join(ALLOWLIST_FILE))?;
+    let gate = allowlist_gate(&allowlist, options.open);
+    let usage = UsageLedger::load(
+        options.data_dir.join(USAGE_FILE),
+        options.limits,
+        Utc::now(),
+    )?;
+
+    let mut swarm = build_swarm(key, gate.clone(), usage.meters())?;
+    for addr in options.listen {
+        swarm.listen_on(addr)?;
+    }
+    for addr in options.external {
+        swarm.add_external_address(addr);
+    }
+    info!("Relay peer ID: {}", swarm.local_peer_id());
+
+    let (kick_tx, kick_rx) = mpsc::channel(16);
+    let state = Arc::new(Mutex::new(AdminState {
+        token_manager,
+        reservations: Reservations::default(),
+        usage,
+        allowlist,
+        gate,
+        open: options.open,
+        kick_tx,
+    }));
+    start_admin_api(state.clone(), options.admin_bind).await?;
+
+    run_swarm(swarm, state, kick_rx).await
+}
+
+/// The gate for the allowlist, or one that lets everyone through for an
+/// open relay
+#[allow(clippy::cognitive_complexity)] // Logs which way the relay runs
+fn allowlist_gate(allowlist: &Allowlist, open: bool) -> PeerGate {
+    if open {
+        warn!("Relay is open: any peer can reserve a slot and relay through it");
+        return PeerGate::new(None);
+    }
+    info!("Relay allows {} peers", allowlist.peers.len());
+    PeerGate::new(Some(allowlist.peers.iter().copied().collect()))
+}
+
+#[allow(clippy::cognitive_complexity)] // Setup with logging
+async fn start_admin_api(state: SharedState, bind: SocketAddr) -> Result<()> {
+    if !bind.ip().is_loopback() {
+        warn!(
+            "Relay admin API is listening on {}, not just localhost",
+            bind
+        );
+    }
+    let token_path = state.lock().await.token_manager.token_path().to_path_buf();
+    let listener = tokio::net::TcpListener::bind(bind).await?;
+    info!(
+        "Relay admin API listening on http://{} (token in {})",
+        bind,
+        token_path.display()
+    );
+
+    let admin = axum::serve(listener, create_admin_router(state));
+    tokio::spawn(async move {
+        if let Err(e) = admin.await {
+            warn!("Relay admin API stopped: {}", e);
+        }
+    });
     Ok(())
 }
+
+/// Run the swarm event loop, disconnecting peers the admin API kicks
+#[allow(clippy::cognitive_complexity)] // Relay event loop
+async fn run_swarm(
+    mut swarm: Swarm<RelayServerBehaviour>,
+    state: SharedState,
+    mut kick_rx: mpsc::Receiver<PeerId>,
+) -> Result<()> {
+    let mut tally = tokio::time::interval(TALLY_INTERVAL);
+    let mut over_limit = HashSet::new();
+    loop {
+        tokio::select! {
+            event = swarm.select_next_some() => handle_swarm_event(event, &state).await,
+            _ = tally.tick() => tally_usage(&mut swarm, &state, &mut over_limit).await,
+            Some(peer) = kick_rx.recv() => {
+                if swarm.disconnect_peer_id(peer).is_ok() {
+                    info!("Disconnected {}", peer);
+                }
+            }
+        }
+    }
+}
+
+/// Add up usage, cutting off peers that have gone over a cap and letting
+/// everyone back in when a new month starts
+#[allow(clippy::cognitive_complexity)] // Cap enforcement with logging
+async fn tally_usage(
+    swarm: &mut Swarm<RelayServerBehaviour>,
+    state: &SharedState,
+    over_limit: &mut HashSet<PeerId>,
+) {
+    let mut state = state.lock().await;
+    let tally = state.usage.tally(Utc::now());
+    if tally.new_month {
+        for peer in over_limit.drain() {
+            swarm.behaviour_mut().over_limit.unblock_peer(peer);
+        }
+    }
+    for peer in tally.over {
+        if over_limit.insert(peer) {
+            info!("{} is over its monthly cap, cutting it off", peer);
+            swarm.behaviour_mut().over_limit.block_peer(peer);
+        }
+    }
+    if let Err(e) = state.usage.save() {
+        warn!("Failed to save relay usage: {}", e);
+    }
+}
+
+/// Build the swarm, metering the bytes on every connection
+fn build_swarm(
+    key: identity::Keypair,
+    gate: PeerGate,
+    meters: Meters,
+) -> Result<Swarm<RelayServerBehaviour>> {
+    let swarm = libp2p::SwarmBuilder::with_existing_identity(key)
+        .with_tokio()
+        .with_other_transport(|key| {
+            let transport = build_transport(key, Transports::default())?;
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Transport::map(
+                transport,
+                move |(peer, muxer), _| (peer, meters.meter(peer, muxer)),
+            ))
+        })?
+        .with_behaviour(|key| RelayServerBehaviour {
+            gate,
+            over_limit: allow_block_list::Behaviour::default(),
+            relay: relay::Behaviour::new(key.public().to_peer_id(), relay::Config::default()),
+            identify: identify::Behaviour::new(identify::Config::new(
+                "/wolfpack/id/1.0.0".to_string(),
+                key.public(),
+            )),
+            ping: ping::Behaviour::new(ping::Config::new()),
+        })?
+        .with_swarm_config(|c| c.with_idle_connection_timeout(IDLE_TIMEOUT))
+        .build();
+    Ok(swarm)
+}
+
+async fn handle_swarm_event(event: SwarmEvent<RelayServerBehaviourEvent>, state: &SharedState) {
+    match event {
+        SwarmEvent::NewListenAddr { address, .. } => info!("Listening on {}", address),
+        SwarmEvent::Behaviour(RelayServerBehaviourEvent::Relay(event)) => {
+            let mut state = state.lock().await;
+            let now = Utc::now();
+            state.reservations.record(&event, now);
+            state.usage.record(&event, now);
+        }
+        SwarmEvent::ConnectionClosed {
+            peer_id,
+            num_established: 0,
+            ..
+        } => {
+            state.lock().await.reservations.peers.remove(&peer_id);
+        }
+        _ => {}
+    }
+}
+
+fn create_admin_router(state: SharedState) -> Router {
+    Router::new()
+        .route("/reservations", get(get_reservations))
+        .route("/usage", get(get_usage))
+        .route("/peers/kick", post(kick_peer))
+        .route("/allowlist", get(get_allowlist))
+        .route("/allowlist/add", post(add_to_allowlist))
+        .route("/allowlist/remove", post(remove_from_allowlist))
+        .with_state(state)
+}
+
+async fn get_reservations(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<ReservationsResponse>, StatusCode> {
+    let state = state.lock().await;
+    validate_token(&headers, &state)?;
+
+    let reservations = state
+        .reservations
+        .peers
+        .iter()
+        .map(|(peer, reservation)| ReservationEntry {
+            peer_id: peer.to_string(),
+            since: reservation.since.to_rfc3339(),
+            renewed_at: reservation.renewed_at.to_rfc3339(),
+            circuits: reservation.circuits,
+        })
+        .collect();
+    Ok(Json(ReservationsResponse { reservations }))
+}
+
+async fn get_usage(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<UsageResponse>, StatusCode> {
+    let state = state.lock().await;
+    validate_token(&headers, &state)?;
+
+    let limits = state.usage.limits();
+    let peers = state
+        .usage
+        .peers()
+        .iter()
+        .map(|(peer, usage)| UsageEntry {
+            peer_id: peer.to_string(),
+            bytes: usage.bytes,
+            circuit_minutes: usage.circuit_seconds / 60,
+            over_limit: usage.exceeds(&limits),
+        })
+        .collect();
+    Ok(Json(UsageResponse {
+        month: state.usage.month().to_string(),
+        monthly_mib: limits.bytes.map(|bytes| bytes / (1024 * 1024)),
+        monthly_minutes: limits.circuit_seconds.map(|seconds| seconds / 60),
+        peers,
+    }))
+}
+
+async fn kick_peer(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    Json(req): Json<KickRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut state = state.lock().await;
+    validate_token(&headers, &state)?;
+    let peer = parse_peer_id(&req.peer_id)?;
+
+    if req.revoke {
+        state
+            .revoke(&peer)
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+    state
+        .kick_tx
+        .send(peer)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+async fn get_allowlist(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+) -> Result<Json<AllowlistResponse>, StatusCode> {
+    let state = state.lock().await;
+    validate_token(&headers, &state)?;
+
+    Ok(Json(AllowlistResponse {
+        enforced: !state.open,
+        peers: state
+            .allowlist
+            .peers
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+    }))
+}
+
+async fn add_to_allowlist(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    Json(req): Json<PeerRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut state = state.lock().await;
+    validate_token(&headers, &state)?;
+    let peer = parse_peer_id(&req.peer_id)?;
+
+    state
+        .allow(peer)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Take a peer off the allowlist, disconnecting it if the allowlist is
+/// enforced
+async fn remove_from_allowlist(
+    headers: HeaderMap,
+    State(state): State<SharedState>,
+    Json(req): Json<PeerRequest>,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    let mut state = state.lock().await;
+    validate_token(&headers, &state)?;
+    let peer = parse_peer_id(&req.peer_id)?;
+
+    state
+        .revoke(&peer)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    if !state.open {
+        state
+            .kick_tx
+            .send(peer)
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    }
+
+    Ok(Json(serde_json::json!({ "status": "ok" })))
+}
+
+/// Validate the admin token from request headers
+fn validate_token(headers: &HeaderMap, state: &AdminState) -> Result<(), StatusCode> {
+    let token = headers
+        .get(TOKEN_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .ok_or(StatusCode::UNAUTHORIZED)?;
+    if !state.token_manager.validate(token) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    Ok(())
+}
+
+fn parse_peer_id(peer_id: &str) -> Result<PeerId, StatusCode> {
+    peer_id.trim().parse().map_err(|_| StatusCode::BAD_REQUEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_allowlist() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(ALLOWLIST_FILE);
+        let peer = PeerId::random();
+        std::fs::write(&path, format!("# Laptop\n{}\n\n", peer)).unwrap();
+
+        let (kick_tx, _kick_rx) = mpsc::channel(1);
+        let allowlist = Allowlist::load(path.clone()).unwrap();
+        let gate = PeerGate::new(Some(allowlist.peers.iter().copied().collect()));
+        let mut state = AdminState {
+            token_manager: ApiTokenManager::load_or_create(dir.path()).unwrap(),
+            reservations: Reservations::default(),
+            usage: UsageLedger::load(
+                dir.path().join(USAGE_FILE),
+                UsageLimits::default(),
+                Utc::now(),
+            )
+            .unwrap(),
+            allowlist,
+            gate: gate.clone(),
+            open: false,
+            kick_tx,
+        };
+        assert!(gate.admits(&peer));
+
+        let other = PeerId::random();
+        state.allow(other).unwrap();
+        state.revoke(&peer).unwrap();
+        assert!(gate.admits(&other));
+        assert!(!gate.admits(&peer));
+        assert_eq!(
+            Allowlist::load(path.clone()).unwrap().peers,
+            BTreeSet::from([other])
+        );
+
+        std::fs::write(&path, "not a peer\n").unwrap();
+        assert!(Allowlist::load(path).is_err());
+    }
+
+    #[test]
+    fn test_reservations() {
+        let peer = PeerId::random();
+        let source = PeerId::random();
+        let now = Utc::now();
+        let later = now + chrono::Duration::minutes(30);
+        let mut reservations = Reservations::default();
+
+        reservations.record(
+            &relay::Event::ReservationReqAccepted {
+                src_peer_id: peer,
+                renewed: false,
+            },
+            now,
+        );
+        reservations.record(
+            &relay::Event::ReservationReqAccepted {
+                src_peer_id: peer,
+                renewed: true,
+            },
+            later,
+        );
+        reservations.record(
+            &relay::Event::CircuitReqAccepted {
+                src_peer_id: source,
+                dst_peer_id: peer,
+            },
+            later,
+        );
+        assert_eq!(
+            reservations.peers[&peer],
+            Reservation {
+                since: now,
+                renewed_at: later,
+                circuits: 1,
+            }
+        );
+
+        reservations.record(
+            &relay::Event::CircuitClosed {
+                src_peer_id: source,
+                dst_peer_id: peer,
+                error: None,
+            },
+            later,
+        );
+        assert_eq!(reservations.peers[&peer].circuits, 0);
+
+        reservations.record(
+            &relay::Event::ReservationTimedOut { src_peer_id: peer },
+            later,
+        );
+        assert!(reservations.peers.is_empty());
+    }
+}
@@ -0,0 +1,427 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use futures::io::{AsyncRead, AsyncWrite};
+use libp2p::PeerId;
+use libp2p::core::muxing::{StreamMuxer, StreamMuxerBox, StreamMuxerEvent, SubstreamBox};
+use libp2p::relay;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{self, Poll, ready};
+
+/// Monthly caps on what one peer may use of the relay
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UsageLimits {
+    /// Bytes a peer may send and receive through the relay
+    pub bytes: Option<u64>,
+    /// Seconds of circuits a peer may have open, as source or destination
+    pub circuit_seconds: Option<u64>,
+}
+
+/// What one peer has used of the relay this month
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerUsage {
+    pub bytes: u64,
+    pub circuit_seconds: u64,
+}
+
+impl PeerUsage {
+    pub fn exceeds(&self, limits: &UsageLimits) -> bool {
+        limits.bytes.is_some_and(|max| self.bytes >= max)
+            || limits
+                .circuit_seconds
+                .is_some_and(|max| self.circuit_seconds >= max)
+    }
+}
+
+/// The ledger as saved in the data directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct UsageFile {
+    month: String,
+    #[serde(default)]
+    peers: BTreeMap<String, PeerUsage>,
+}
+
+/// What a tally of the meters found
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Tally {
+    /// A new month started, so everyone's usage was reset
+    pub new_month: bool,
+    /// Peers at or over a cap
+    pub over: Vec<PeerId>,
+}
+
+/// Byte counters for connections, one per peer, shared with the transport
+#[derive(Debug, Clone, Default)]
+pub struct Meters(Arc<Mutex<HashMap<PeerId, Arc<AtomicU64>>>>);
+
+impl Meters {
+    /// Wrap a connection's muxer so the bytes on it are counted for `peer`
+    pub fn meter(&self, peer: PeerId, muxer: StreamMuxerBox) -> StreamMuxerBox {
+        let meter = self
+            .0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(peer)
+            .or_default()
+            .clone();
+        StreamMuxerBox::new(MeteredMuxer {
+            inner: muxer,
+            meter,
+        })
+    }
+
+    /// Take the bytes counted since the last drain, dropping counters no
+    /// connection uses anymore
+    fn drain(&self) -> Vec<(PeerId, u64)> {
+        let mut meters = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let counted = meters
+            .iter()
+            .map(|(peer, meter)| (*peer, meter.swap(0, Ordering::Relaxed)))
+            .filter(|(_, bytes)| *bytes > 0)
+            .collect();
+        meters.retain(|_, meter| Arc::strong_count(meter) > 1);
+        counted
+    }
+}
+
+/// Per-peer usage of the relay for the current month, saved so caps hold
+/// across restarts
+#[derive(Debug)]
+pub struct UsageLedger {
+    path: PathBuf,
+    limits: UsageLimits,
+    month: String,
+    peers: BTreeMap<PeerId, PeerUsage>,
+    meters: Meters,
+    /// Open circuits by source and destination, and when they were last
+    /// charged for
+    circuits: HashMap<(PeerId, PeerId), DateTime<Utc>>,
+}
+
+impl UsageLedger {
+    /// Load this month's usage, starting afresh if the saved ledger is from
+    /// an earlier month
+    pub fn load(path: PathBuf, limits: UsageLimits, now: DateTime<Utc>) -> Result<Self> {
+        let month = month_of(now);
+        let mut peers = BTreeMap::new();
+        if path.exists() {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            let file: UsageFile = serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+            if file.month == month {
+                for (peer, usage) in file.peers {
+                    if let Ok(peer) = peer.parse() {
+                        peers.insert(peer, usage);
+                    }
+                }
+            }
+        }
+        Ok(Self {
+            path,
+            limits,
+            month,
+            peers,
+            meters: Meters::default(),
+            circuits: HashMap::new(),
+        })
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let file = UsageFile {
+            month: self.month.clone(),
+            peers: self
+                .peers
+                .iter()
+                .map(|(peer, usage)| (peer.to_string(), *usage))
+                .collect(),
+        };
+        std::fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    pub fn meters(&self) -> Meters {
+        self.meters.clone()
+    }
+
+    pub fn limits(&self) -> UsageLimits {
+        self.limits
+    }
+
+    pub fn month(&self) -> &str {
+        &self.month
+    }
+
+    pub fn peers(&self) -> &BTreeMap<PeerId, PeerUsage> {
+        &self.peers
+    }
+
+    /// Peers at or over a cap
+    pub fn over_limit(&self) -> Vec<PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, usage)| usage.exceeds(&self.limits))
+            .map(|(peer, _)| *peer)
+            .collect()
+    }
+
+    /// Start or stop charging for a circuit
+    pub fn record(&mut self, event: &relay::Event, now: DateTime<Utc>) {
+        match event {
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => {
+                self.circuits.insert((*src_peer_id, *dst_peer_id), now);
+            }
+            relay::Event::CircuitClosed {
+                src_peer_id,
+                dst_peer_id,
+                ..
+            } => {
+                if let Some(since) = self.circuits.remove(&(*src_peer_id, *dst_peer_id)) {
+                    self.charge_circuit(*src_peer_id, *dst_peer_id, since, now);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Add up the bytes counted and the circuit time since the last tally
+    pub fn tally(&mut self, now: DateTime<Utc>) -> Tally {
+        let month = month_of(now);
+        let new_month = month != self.month;
+        if new_month {
+            self.month = month;
+            self.peers.clear();
+        }
+
+        for (peer, bytes) in self.meters.drain() {
+            let usage = self.peers.entry(peer).or_default();
+            usage.bytes = usage.bytes.saturating_add(bytes);
+        }
+        let circuits: Vec<_> = self
+            .circuits
+            .iter_mut()
+            .map(|(&(src, dst), since)| (src, dst, std::mem::replace(since, now)))
+            .collect();
+        for (src, dst, since) in circuits {
+            self.charge_circuit(src, dst, since, now);
+        }
+
+        Tally {
+            new_month,
+            over: self.over_limit(),
+        }
+    }
+
+    fn charge_circuit(
+        &mut self,
+        src: PeerId,
+        dst: PeerId,
+        since: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) {
+        let seconds = u64::try_from((now - since).num_seconds()).unwrap_or(0);
+        for peer in [src, dst] {
+            let usage = self.peers.entry(peer).or_default();
+            usage.circuit_seconds = usage.circuit_seconds.saturating_add(seconds);
+        }
+    }
+}
+
+fn month_of(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m").to_string()
+}
+
+/// A connection's muxer, counting the bytes on every stream it carries
+struct MeteredMuxer {
+    inner: StreamMuxerBox,
+    meter: Arc<AtomicU64>,
+}
+
+impl StreamMuxer for MeteredMuxer {
+    type Substream = MeteredStream;
+    type Error = io::Error;
+
+    fn poll_inbound(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let inner = ready!(Pin::new(&mut this.inner).poll_inbound(cx))?;
+        Poll::Ready(Ok(MeteredStream {
+            inner,
+            meter: this.meter.clone(),
+        }))
+    }
+
+    fn poll_outbound(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<Self::Substream, Self::Error>> {
+        let this = self.get_mut();
+        let inner = ready!(Pin::new(&mut this.inner).poll_outbound(cx))?;
+        Poll::Ready(Ok(MeteredStream {
+            inner,
+            meter: this.meter.clone(),
+        }))
+    }
+
+    fn poll_close(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+
+    fn poll(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+    ) -> Poll<Result<StreamMuxerEvent, Self::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll(cx)
+    }
+}
+
+/// A stream counting the bytes read from and written to it
+struct MeteredStream {
+    inner: SubstreamBox,
+    meter: Arc<AtomicU64>,
+}
+
+impl MeteredStream {
+    fn count(&self, bytes: usize) {
+        self.meter
+            .fetch_add(u64::try_from(bytes).unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+}
+
+impl AsyncRead for MeteredStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let bytes = ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+        this.count(bytes);
+        Poll::Ready(Ok(bytes))
+    }
+}
+
+impl AsyncWrite for MeteredStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        let bytes = ready!(Pin::new(&mut this.inner).poll_write(cx, buf))?;
+        this.count(bytes);
+        Poll::Ready(Ok(bytes))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::io::{AsyncReadExt, AsyncWriteExt, Cursor};
+    use tempfile::tempdir;
+
+    fn time(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_metered_stream() {
+        let meter = Arc::new(AtomicU64::new(0));
+        let mut stream = MeteredStream {
+            inner: SubstreamBox::new(Cursor::new(b"hello".to_vec())),
+            meter: meter.clone(),
+        };
+        futures::executor::block_on(async {
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            stream.write_all(b"hi").await.unwrap();
+        });
+        assert_eq!(meter.load(Ordering::Relaxed), 7);
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Walks a month of usage through to the next
+    fn test_usage_ledger() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("usage.json");
+        let limits = UsageLimits {
+            bytes: Some(1000),
+            circuit_seconds: Some(600),
+        };
+        let (src, dst) = (PeerId::random(), PeerId::random());
+        let now = time("2026-03-10T12:00:00Z");
+        let mut ledger = UsageLedger::load(path.clone(), limits, now).unwrap();
+
+        let counter = ledger
+            .meters
+            .0
+            .lock()
+            .unwrap()
+            .entry(src)
+            .or_default()
+            .clone();
+        counter.fetch_add(400, Ordering::Relaxed);
+        ledger.record(
+            &relay::Event::CircuitReqAccepted {
+                src_peer_id: src,
+                dst_peer_id: dst,
+            },
+            now,
+        );
+        let tally = ledger.tally(time("2026-03-10T12:05:00Z"));
+        assert_eq!(tally, Tally::default());
+        assert_eq!(
+            ledger.peers()[&src],
+            PeerUsage {
+                bytes: 400,
+                circuit_seconds: 300,
+            }
+        );
+
+        // Closing the circuit charges the rest of its time to both ends
+        ledger.record(
+            &relay::Event::CircuitClosed {
+                src_peer_id: src,
+                dst_peer_id: dst,
+                error: None,
+            },
+            time("2026-03-10T12:10:00Z"),
+        );
+        counter.fetch_add(600, Ordering::Relaxed);
+        let tally = ledger.tally(time("2026-03-10T12:10:00Z"));
+        assert_eq!(tally.over.len(), 2);
+        assert!(tally.over.contains(&src) && tally.over.contains(&dst));
+        assert_eq!(ledger.peers()[&dst].circuit_seconds, 600);
+
+        // Usage survives a restart within the month, but not into the next
+        ledger.save().unwrap();
+        let ledger = UsageLedger::load(path.clone(), limits, now).unwrap();
+        assert_eq!(ledger.peers()[&src].bytes, 1000);
+        let mut ledger = UsageLedger::load(path, limits, now).unwrap();
+        let tally = ledger.tally(time("2026-04-01T00:00:00Z"));
+        assert!(tally.new_month);
+        assert!(tally.over.is_empty());
+        assert!(ledger.peers().is_empty());
+    }
+}
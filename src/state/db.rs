@@ -1,7 +1,19 @@
 use anyhow::Result;
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::path::Path;
 
+use crate::events::{Event, PrefValue, SearchEngineDetails};
+use crate::profile::{Address, CertOverride, Permission, SearchEngine, SiteAssignment, StartPage};
+
+/// A page saved to the synced reading list
+#[derive(Debug, Clone)]
+pub struct ReadingListItem {
+    pub url: String,
+    pub title: Option<String>,
+    pub added_at: String,
+}
+
 /// A tab pending to be opened (sent from another device)
 #[derive(Debug, Clone)]
 pub struct PendingTab {
@@ -9,6 +21,148 @@ pub struct PendingTab {
     pub url: String,
     pub title: Option<String>,
     pub from_device: String,
+    pub sent_at: String,
+    /// IDs of identical tabs sent again shortly after, shown as this one
+    pub duplicates: Vec<String>,
+}
+
+/// A tab this device sent, kept until it's opened and for a while after
+#[derive(Debug, Clone)]
+pub struct SentTab {
+    pub id: String,
+    pub to_device: String,
+    pub url: String,
+    pub title: Option<String>,
+    pub sent_at: String,
+    pub status: TabDelivery,
+    /// Direct deliveries tried so far
+    pub attempts: u32,
+    /// When to try delivering directly again, while still pending
+    pub next_attempt: Option<String>,
+}
+
+/// How far a sent tab got toward the device it was sent to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TabDelivery {
+    /// Only in the event log so far
+    Pending,
+    /// The receiving device acknowledged it over P2P
+    Delivered,
+    /// The receiving device opened it (its `TabReceived` event synced back)
+    Opened,
+}
+
+impl TabDelivery {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Delivered => "delivered",
+            Self::Opened => "opened",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "delivered" => Self::Delivered,
+            "opened" => Self::Opened,
+            _ => Self::Pending,
+        }
+    }
+}
+
+/// A sync round with a peer, kept for `wolfpack status --history`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyncSession {
+    pub peer: String,
+    /// The device the peer answered as, if it got that far
+    pub device: Option<String>,
+    pub finished_at: String,
+    pub duration_ms: u32,
+    pub files_received: u32,
+    /// Events in those files that were new here
+    pub events_applied: u32,
+    /// Whether the round ended with this device caught up with the peer
+    pub converged: bool,
+    pub error: Option<String>,
+}
+
+/// Colors a device can be labeled with (the container color palette)
+pub const DEVICE_COLORS: &[&str] = &[
+    "blue",
+    "turquoise",
+    "green",
+    "yellow",
+    "orange",
+    "red",
+    "pink",
+    "purple",
+];
+
+/// A paired device's name and label, and when it was last seen on the
+/// network and last synced
+#[derive(Debug, Clone, Default)]
+pub struct DeviceRecord {
+    pub id: String,
+    pub name: Option<String>,
+    pub emoji: Option<String>,
+    /// One of [`DEVICE_COLORS`]
+    pub color: Option<String>,
+    /// Last address the device was connected from
+    pub address: Option<String>,
+    /// Tor onion endpoint the device published (`<address>.onion:<port>`)
+    pub onion: Option<String>,
+    pub last_seen: Option<String>,
+    pub last_sync: Option<String>,
+    /// The public key (hex) the user compared and marked verified with
+    /// `wolfpack keys verify`, on this device only
+    pub verified_key: Option<String>,
+}
+
+/// What a device last advertised it was running, from its `DeviceState`
+/// events
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceReport {
+    pub device: String,
+    /// Installed extension IDs and their versions
+    pub extensions: BTreeMap<String, String>,
+    pub browser_version: Option<String>,
+    pub wolfpack_version: String,
+    /// When the device last scanned its profile
+    pub last_scan: Option<String>,
+    /// When the device sent the report
+    pub reported_at: String,
+}
+
+/// The synced prefs as the profile had them when the daemon last stopped,
+/// and the whitelist they were read with
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PrefsSnapshot {
+    pub whitelist: Vec<String>,
+    pub prefs: HashMap<String, PrefValue>,
+}
+
+/// An item both the profile and the pack changed while the daemon was
+/// stopped, held until the user picks which change to keep
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub category: String,
+    pub item: String,
+    /// The profile's change, sent if the user keeps it
+    pub local: Event,
+    /// The pack's value, or `None` if the pack removed the item
+    pub pack: Option<String>,
+    pub found_at: String,
+}
+
+impl Divergence {
+    /// The pack's side, as shown to the user picking one
+    pub fn describe_pack(&self) -> String {
+        match &self.pack {
+            None => "removed".to_string(),
+            Some(_) if crate::redact::is_sensitive_pref(&self.item) => "[redacted]".to_string(),
+            Some(value) => value.clone(),
+        }
+    }
 }
 
 const SCHEMA: &str = r#"
@@ -32,6 +186,12 @@ const SCHEMA: &str = r#"
         icon TEXT NOT NULL
     );
 
+    CREATE TABLE IF NOT EXISTS container_sites (
+        host TEXT PRIMARY KEY,
+        container_id TEXT NOT NULL,
+        never_ask INTEGER NOT NULL
+    );
+
     CREATE TABLE IF NOT EXISTS handlers (
         protocol TEXT PRIMARY KEY,
         handler TEXT NOT NULL
@@ -41,7 +201,8 @@ const SCHEMA: &str = r#"
         id TEXT PRIMARY KEY,
         name TEXT NOT NULL,
         url TEXT NOT NULL,
-        is_default INTEGER NOT NULL DEFAULT 0
+        is_default INTEGER NOT NULL DEFAULT 0,
+        details TEXT
     );
 
     CREATE TABLE IF NOT EXISTS prefs (
@@ -50,6 +211,29 @@ const SCHEMA: &str = r#"
         value_type TEXT NOT NULL
     );
 
+    CREATE TABLE IF NOT EXISTS start_page (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS permissions (
+        origin TEXT NOT NULL,
+        type TEXT NOT NULL,
+        capability INTEGER NOT NULL,
+        PRIMARY KEY (origin, type)
+    );
+
+    CREATE TABLE IF NOT EXISTS cert_overrides (
+        host TEXT PRIMARY KEY,
+        algorithm TEXT NOT NULL,
+        fingerprint TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS addresses (
+        guid TEXT PRIMARY KEY,
+        fields TEXT NOT NULL
+    );
+
     CREATE TABLE IF NOT EXISTS pending_tabs (
         id TEXT PRIMARY KEY,
         url TEXT NOT NULL,
@@ -58,11 +242,53 @@ const SCHEMA: &str = r#"
         sent_at TEXT NOT NULL
     );
 
+    CREATE TABLE IF NOT EXISTS received_tabs (
+        id TEXT PRIMARY KEY,
+        received_at TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS sent_tabs (
+        id TEXT PRIMARY KEY,
+        to_device TEXT NOT NULL,
+        url TEXT NOT NULL,
+        title TEXT,
+        sent_at TEXT NOT NULL,
+        status TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        next_attempt TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS devices (
+        id TEXT PRIMARY KEY,
+        name TEXT,
+        emoji TEXT,
+        color TEXT,
+        address TEXT,
+        last_seen TEXT,
+        last_sync TEXT,
+        onion TEXT,
+        peer_id TEXT,
+        verified_key TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS reading_list (
+        url TEXT PRIMARY KEY,
+        title TEXT,
+        added_at TEXT NOT NULL
+    );
+
     CREATE TABLE IF NOT EXISTS vector_clock (
         device TEXT PRIMARY KEY,
         counter INTEGER NOT NULL
     );
 
+    CREATE TABLE IF NOT EXISTS event_routes (
+        device TEXT NOT NULL,
+        number INTEGER NOT NULL,
+        hops TEXT NOT NULL,
+        PRIMARY KEY (device, number)
+    );
+
     CREATE TABLE IF NOT EXISTS extension_xpi (
         id TEXT PRIMARY KEY,
         version TEXT NOT NULL,
@@ -71,8 +297,107 @@ const SCHEMA: &str = r#"
         xpi_data TEXT NOT NULL,
         installed_at TEXT NOT NULL
     );
+
+    CREATE TABLE IF NOT EXISTS extension_installs (
+        id TEXT PRIMARY KEY,
+        browser_started INTEGER NOT NULL DEFAULT 0
+    );
+
+    CREATE TABLE IF NOT EXISTS device_extensions (
+        device TEXT NOT NULL,
+        id TEXT NOT NULL,
+        PRIMARY KEY (device, id)
+    );
+
+    CREATE TABLE IF NOT EXISTS device_state (
+        device TEXT PRIMARY KEY,
+        extensions TEXT NOT NULL,
+        browser_version TEXT,
+        wolfpack_version TEXT NOT NULL,
+        last_scan TEXT,
+        reported_at TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS sync_pauses (
+        scope TEXT PRIMARY KEY,
+        until TEXT
+    );
+
+    CREATE TABLE IF NOT EXISTS pack_config (
+        key TEXT PRIMARY KEY,
+        value TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS divergences (
+        category TEXT NOT NULL,
+        item TEXT NOT NULL,
+        local TEXT NOT NULL,
+        pack TEXT,
+        found_at TEXT NOT NULL,
+        PRIMARY KEY (category, item)
+    );
+
+    CREATE TABLE IF NOT EXISTS prefs_snapshot (
+        id INTEGER PRIMARY KEY CHECK (id = 1),
+        whitelist TEXT NOT NULL,
+        prefs TEXT NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS sync_sessions (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        peer TEXT NOT NULL,
+        device TEXT,
+        finished_at TEXT NOT NULL,
+        duration_ms INTEGER NOT NULL,
+        files_received INTEGER NOT NULL,
+        events_applied INTEGER NOT NULL,
+        converged INTEGER NOT NULL,
+        error TEXT
+    );
+
+    CREATE INDEX IF NOT EXISTS pending_tabs_sent_at ON pending_tabs (sent_at);
+    CREATE INDEX IF NOT EXISTS applied_events_device ON applied_events (device);
+    CREATE INDEX IF NOT EXISTS extension_xpi_version ON extension_xpi (version);
 "#;
 
+/// Sync sessions kept, oldest dropped first
+const MAX_SYNC_SESSIONS: u32 = 1000;
+
+/// Tables holding state materialized from events, which a replay rebuilds
+const MATERIALIZED_TABLES: [&str; 17] = [
+    "extensions",
+    "extension_xpi",
+    "containers",
+    "container_sites",
+    "handlers",
+    "search_engines",
+    "prefs",
+    "start_page",
+    "permissions",
+    "cert_overrides",
+    "addresses",
+    "pending_tabs",
+    "received_tabs",
+    "reading_list",
+    "pack_config",
+    "device_extensions",
+    "device_state",
+];
+
+fn device_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<DeviceRecord> {
+    Ok(DeviceRecord {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        emoji: row.get(2)?,
+        color: row.get(3)?,
+        address: row.get(4)?,
+        last_seen: row.get(5)?,
+        last_sync: row.get(6)?,
+        onion: row.get(7)?,
+        verified_key: row.get(8)?,
+    })
+}
+
 pub struct StateDb {
     conn: Connection,
 }
@@ -92,8 +417,96 @@ impl StateDb {
         Ok(db)
     }
 
+    /// Fold a state database an older version kept at `legacy` into the
+    /// one at `path`, and move it aside as `state.db.migrated`
+    ///
+    /// Rows already at `path` win over the legacy ones they collide with.
+    /// Nothing happens if there is no legacy database.
+    pub fn merge_legacy(path: &Path, legacy: &Path) -> Result<()> {
+        if path == legacy || !legacy.exists() {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Brings the legacy schema up to date, so every table and column
+        // it has exists here too
+        drop(Self::open(legacy)?);
+        let db = Self::open(path)?;
+        db.conn
+            .execute("ATTACH DATABASE ?1 AS legacy", [legacy.to_string_lossy()])?;
+        let merged = db.transaction(Self::merge_attached);
+        db.conn.execute("DETACH DATABASE legacy", [])?;
+        merged?;
+        drop(db);
+
+        std::fs::rename(legacy, legacy.with_extension("db.migrated"))?;
+        Ok(())
+    }
+
+    /// Copy the rows of each table in the attached `legacy` database
+    fn merge_attached(&self) -> Result<()> {
+        let mut stmt = self.conn.prepare(
+            "SELECT name FROM legacy.sqlite_master
+             WHERE type = 'table' AND name NOT LIKE 'sqlite_%'",
+        )?;
+        let tables = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        for table in tables {
+            let mut stmt = self
+                .conn
+                .prepare("SELECT name FROM pragma_table_info(?1, 'legacy')")?;
+            let columns = stmt
+                .query_map([&table], |row| row.get::<_, String>(0))?
+                .map(|name| name.map(|name| format!("\"{}\"", name)))
+                .collect::<std::result::Result<Vec<_>, _>>()?
+                .join(", ");
+            self.conn.execute(
+                &format!(
+                    "INSERT OR IGNORE INTO main.\"{table}\" ({columns})
+                     SELECT {columns} FROM legacy.\"{table}\""
+                ),
+                [],
+            )?;
+        }
+        Ok(())
+    }
+
     fn init_schema(&self) -> Result<()> {
         self.conn.execute_batch(SCHEMA)?;
+        // Columns added after the table was first created
+        self.ensure_column("search_engines", "details", "TEXT")?;
+        self.ensure_column("devices", "emoji", "TEXT")?;
+        self.ensure_column("devices", "color", "TEXT")?;
+        self.ensure_column("devices", "onion", "TEXT")?;
+        self.ensure_column("devices", "peer_id", "TEXT")?;
+        self.ensure_column("devices", "verified_key", "TEXT")?;
+        self.ensure_column("sent_tabs", "status", "TEXT NOT NULL DEFAULT 'pending'")?;
+        self.ensure_column("sent_tabs", "attempts", "INTEGER NOT NULL DEFAULT 0")?;
+        self.ensure_column("sent_tabs", "next_attempt", "TEXT")?;
+        self.ensure_column("containers", "position", "TEXT NOT NULL DEFAULT ''")?;
+        Ok(())
+    }
+
+    fn ensure_column(&self, table: &str, column: &str, decl: &str) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))?;
+        let exists = stmt
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<std::result::Result<Vec<_>, _>>()?
+            .iter()
+            .any(|name| name == column);
+
+        if !exists {
+            self.conn.execute(
+                &format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl),
+                [],
+            )?;
+        }
         Ok(())
     }
 
@@ -101,31 +514,114 @@ impl StateDb {
         &self.conn
     }
 
-    pub fn is_event_applied(&self, event_id: uuid::Uuid) -> Result<bool> {
-        let count: i64 = self.conn.query_row(
-            "SELECT COUNT(*) FROM applied_events WHERE id = ?",
-            [event_id.to_string()],
+    /// Bytes the database takes up, including pages freed by deletes
+    pub fn size(&self) -> Result<u64> {
+        let size: i64 = self.conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count, pragma_page_size",
+            [],
             |row| row.get(0),
         )?;
-        Ok(count > 0)
+        Ok(size as u64)
+    }
+
+    /// Rebuild the database file, returning the space freed by deletes to
+    /// the filesystem
+    pub fn vacuum(&self) -> Result<()> {
+        self.conn.execute_batch("VACUUM")?;
+        Ok(())
+    }
+
+    /// Run `f` in a transaction, rolling back everything it did if it fails
+    pub fn transaction<T>(&self, f: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        self.conn.execute_batch("BEGIN")?;
+        match f(self) {
+            Ok(value) => {
+                self.conn.execute_batch("COMMIT")?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.conn.execute_batch("ROLLBACK")?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run `f` in a savepoint, rolling back everything it did if it fails
+    ///
+    /// Unlike [`StateDb::transaction`], this can be nested in a transaction.
+    pub fn savepoint<T>(&self, f: impl FnOnce(&Self) -> Result<T>) -> Result<T> {
+        self.conn.execute_batch("SAVEPOINT wolfpack")?;
+        match f(self) {
+            Ok(value) => {
+                self.conn.execute_batch("RELEASE wolfpack")?;
+                Ok(value)
+            }
+            Err(e) => {
+                self.conn
+                    .execute_batch("ROLLBACK TO wolfpack; RELEASE wolfpack")?;
+                Err(e)
+            }
+        }
+    }
+
+    /// Forget all state materialized from events, and which events were
+    /// applied, so the event log can be replayed from scratch
+    ///
+    /// Local records are kept: devices and their pairing details, sent tab
+    /// delivery status, pauses, the vector clock, and event routes.
+    pub fn clear_materialized_state(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM applied_events", [])?;
+        for table in MATERIALIZED_TABLES {
+            self.conn.execute(&format!("DELETE FROM {}", table), [])?;
+        }
+        Ok(())
+    }
+
+    /// Rows in each table of state materialized from events
+    pub fn row_counts(&self) -> Result<Vec<(&'static str, u64)>> {
+        MATERIALIZED_TABLES
+            .into_iter()
+            .map(|table| {
+                let count: u64 =
+                    self.conn
+                        .query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                            row.get(0)
+                        })?;
+                Ok((table, count))
+            })
+            .collect()
+    }
+
+    pub fn is_event_applied(&self, event_id: uuid::Uuid) -> Result<bool> {
+        let applied = self
+            .conn
+            .prepare_cached("SELECT EXISTS (SELECT 1 FROM applied_events WHERE id = ?)")?
+            .query_row([event_id.to_string()], |row| row.get(0))?;
+        Ok(applied)
     }
 
+    /// Record an event as applied, returning false if it already was
     pub fn mark_event_applied(
         &self,
         event_id: uuid::Uuid,
         device: &str,
         timestamp: &str,
-    ) -> Result<()> {
-        self.conn.execute(
-            "INSERT OR IGNORE INTO applied_events (id, device, timestamp) VALUES (?, ?, ?)",
-            [&event_id.to_string(), device, timestamp],
-        )?;
-        Ok(())
+    ) -> Result<bool> {
+        let inserted = self
+            .conn
+            .prepare_cached(
+                "INSERT INTO applied_events (id, device, timestamp) VALUES (?, ?, ?)
+                 ON CONFLICT (id) DO NOTHING",
+            )?
+            .execute([&event_id.to_string(), device, timestamp])?;
+        Ok(inserted > 0)
     }
 
+    /// Add or update an extension, keeping when it was first added
     pub fn add_extension(&self, id: &str, name: &str, url: Option<&str>) -> Result<()> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO extensions (id, name, url, added_at) VALUES (?, ?, ?, datetime('now'))",
+            "INSERT INTO extensions (id, name, url, added_at) VALUES (?, ?, ?, datetime('now'))
+             ON CONFLICT (id) DO UPDATE SET name = excluded.name, url = excluded.url",
             rusqlite::params![id, name, url],
         )?;
         Ok(())
@@ -144,20 +640,81 @@ impl StateDb {
             .map_err(Into::into)
     }
 
+    /// Add or update a container, keeping its place in the order
     pub fn add_container(&self, id: &str, name: &str, color: &str, icon: &str) -> Result<()> {
         self.conn.execute(
-            "INSERT OR REPLACE INTO containers (id, name, color, icon) VALUES (?, ?, ?, ?)",
+            "INSERT INTO containers (id, name, color, icon) VALUES (?, ?, ?, ?)
+             ON CONFLICT (id) DO UPDATE SET
+                name = excluded.name, color = excluded.color, icon = excluded.icon",
             [id, name, color, icon],
         )?;
         Ok(())
     }
 
+    /// Move a container to a position key in the order
+    pub fn set_container_position(&self, id: &str, position: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE containers SET position = ? WHERE id = ?",
+            [position, id],
+        )?;
+        Ok(())
+    }
+
+    /// Each container's ID and position key, in order: by key, then by ID,
+    /// with containers not placed yet last
+    pub fn get_container_positions(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, position FROM containers
+             ORDER BY position = '', position, CAST(id AS INTEGER), id",
+        )?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Remove a container along with the sites assigned to it
     pub fn remove_container(&self, id: &str) -> Result<()> {
         self.conn
             .execute("DELETE FROM containers WHERE id = ?", [id])?;
+        self.conn
+            .execute("DELETE FROM container_sites WHERE container_id = ?", [id])?;
+        Ok(())
+    }
+
+    pub fn assign_container_site(
+        &self,
+        host: &str,
+        container_id: &str,
+        never_ask: bool,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO container_sites (host, container_id, never_ask) VALUES (?, ?, ?)",
+            rusqlite::params![host, container_id, never_ask],
+        )?;
+        Ok(())
+    }
+
+    pub fn unassign_container_site(&self, host: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM container_sites WHERE host = ?", [host])?;
         Ok(())
     }
 
+    pub fn get_container_sites(&self) -> Result<Vec<SiteAssignment>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT host, container_id, never_ask FROM container_sites ORDER BY host")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SiteAssignment {
+                host: row.get(0)?,
+                container_id: row.get(1)?,
+                never_ask: row.get(2)?,
+            })
+        })?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
     pub fn set_handler(&self, protocol: &str, handler: &str) -> Result<()> {
         self.conn.execute(
             "INSERT OR REPLACE INTO handlers (protocol, handler) VALUES (?, ?)",
@@ -186,14 +743,56 @@ impl StateDb {
         Ok(())
     }
 
-    pub fn add_search_engine(&self, id: &str, name: &str, url: &str) -> Result<()> {
+    /// Add or update a search engine, keeping its default flag
+    pub fn add_search_engine(
+        &self,
+        id: &str,
+        name: &str,
+        url: &str,
+        details: &SearchEngineDetails,
+    ) -> Result<()> {
+        let details = serde_json::to_string(details)?;
         self.conn.execute(
-            "INSERT OR REPLACE INTO search_engines (id, name, url, is_default) VALUES (?, ?, ?, 0)",
-            rusqlite::params![id, name, url],
+            "INSERT INTO search_engines (id, name, url, is_default, details) VALUES (?, ?, ?, 0, ?)
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name, url = excluded.url, details = excluded.details",
+            rusqlite::params![id, name, url, details],
         )?;
         Ok(())
     }
 
+    pub fn get_search_engines(&self) -> Result<Vec<SearchEngine>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, name, url, is_default, details FROM search_engines ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, i64>(3)?,
+                row.get::<_, Option<String>>(4)?,
+            ))
+        })?;
+
+        let mut engines = Vec::new();
+        for row in rows {
+            let (id, name, url, is_default, details) = row?;
+            // Engines synced before details existed have none stored
+            let details = details
+                .map(|json| serde_json::from_str(&json))
+                .transpose()?
+                .unwrap_or_default();
+            engines.push(SearchEngine {
+                id,
+                name,
+                url,
+                is_default: is_default != 0,
+                details,
+            });
+        }
+        Ok(engines)
+    }
+
     pub fn remove_search_engine(&self, id: &str) -> Result<()> {
         self.conn
             .execute("DELETE FROM search_engines WHERE id = ?", [id])?;
@@ -210,75 +809,625 @@ impl StateDb {
         Ok(())
     }
 
-    #[allow(clippy::too_many_arguments)]
-    pub fn add_pending_tab(
-        &self,
-        id: &str,
-        url: &str,
-        title: Option<&str>,
-        sent_by: &str,
-        sent_at: &str,
-    ) -> Result<()> {
+    /// Set one of the prefs making up the start page
+    pub fn set_start_page_pref(&self, key: &str, value: &PrefValue) -> Result<()> {
+        let value = serde_json::to_string(value)?;
         self.conn.execute(
-            "INSERT OR REPLACE INTO pending_tabs (id, url, title, sent_by, sent_at) VALUES (?, ?, ?, ?, ?)",
-            rusqlite::params![id, url, title, sent_by, sent_at],
+            "INSERT OR REPLACE INTO start_page (key, value) VALUES (?, ?)",
+            [key, value.as_str()],
         )?;
         Ok(())
     }
 
-    pub fn remove_pending_tab(&self, id: &str) -> Result<()> {
+    pub fn remove_start_page_pref(&self, key: &str) -> Result<()> {
         self.conn
-            .execute("DELETE FROM pending_tabs WHERE id = ?", [id])?;
+            .execute("DELETE FROM start_page WHERE key = ?", [key])?;
         Ok(())
     }
 
-    pub fn get_pending_tabs(&self) -> Result<Vec<PendingTab>> {
+    pub fn get_start_page(&self) -> Result<StartPage> {
+        let mut stmt = self.conn.prepare("SELECT key, value FROM start_page")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut prefs = std::collections::HashMap::new();
+        for row in rows {
+            let (key, value) = row?;
+            prefs.insert(key, serde_json::from_str(&value)?);
+        }
+        Ok(StartPage::from_prefs(&prefs))
+    }
+
+    pub fn set_permission(&self, origin: &str, permission: &str, capability: u32) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO permissions (origin, type, capability) VALUES (?, ?, ?)",
+            rusqlite::params![origin, permission, capability],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_permission(&self, origin: &str, permission: &str) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM permissions WHERE origin = ? AND type = ?",
+            [origin, permission],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_permissions(&self) -> Result<Vec<Permission>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT id, url, title, sent_by FROM pending_tabs ORDER BY sent_at")?;
+            .prepare("SELECT origin, type, capability FROM permissions ORDER BY origin, type")?;
         let rows = stmt.query_map([], |row| {
-            Ok(PendingTab {
-                id: row.get(0)?,
-                url: row.get(1)?,
-                title: row.get(2)?,
-                from_device: row.get(3)?,
+            Ok(Permission {
+                origin: row.get(0)?,
+                permission: row.get(1)?,
+                capability: row.get(2)?,
             })
         })?;
-        rows.collect::<std::result::Result<Vec<_>, _>>()
-            .map_err(Into::into)
-    }
 
-    pub fn save_vector_clock(&self, clock: &crate::events::VectorClock) -> Result<()> {
-        self.conn.execute("DELETE FROM vector_clock", [])?;
-        for (device, counter) in clock.entries() {
-            self.conn.execute(
-                "INSERT INTO vector_clock (device, counter) VALUES (?, ?)",
-                rusqlite::params![device, counter],
-            )?;
+        let mut permissions = Vec::new();
+        for row in rows {
+            permissions.push(row?);
         }
+        Ok(permissions)
+    }
+
+    pub fn set_cert_override(&self, host: &str, algorithm: &str, fingerprint: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO cert_overrides (host, algorithm, fingerprint) VALUES (?, ?, ?)",
+            [host, algorithm, fingerprint],
+        )?;
         Ok(())
     }
 
-    pub fn load_vector_clock(&self) -> Result<crate::events::VectorClock> {
-        let mut clock = crate::events::VectorClock::new();
+    pub fn remove_cert_override(&self, host: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM cert_overrides WHERE host = ?", [host])?;
+        Ok(())
+    }
+
+    pub fn get_cert_overrides(&self) -> Result<Vec<CertOverride>> {
         let mut stmt = self
             .conn
-            .prepare("SELECT device, counter FROM vector_clock")?;
+            .prepare("SELECT host, algorithm, fingerprint FROM cert_overrides ORDER BY host")?;
         let rows = stmt.query_map([], |row| {
-            Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+            Ok(CertOverride {
+                host: row.get(0)?,
+                algorithm: row.get(1)?,
+                fingerprint: row.get(2)?,
+            })
         })?;
+
+        let mut overrides = Vec::new();
         for row in rows {
-            let (device, counter) = row?;
-            clock.set(&device, counter);
+            overrides.push(row?);
         }
-        Ok(clock)
+        Ok(overrides)
     }
 
-    pub fn store_extension_xpi(
+    pub fn set_address(&self, guid: &str, fields: &BTreeMap<String, String>) -> Result<()> {
+        let fields = serde_json::to_string(fields)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO addresses (guid, fields) VALUES (?, ?)",
+            [guid, fields.as_str()],
+        )?;
+        Ok(())
+    }
+
+    /// Apply changed fields to an address, leaving other fields as they are.
+    /// Returns false if the address doesn't exist (e.g. removed elsewhere).
+    pub fn update_address(
         &self,
-        id: &str,
-        version: &str,
-        source: &crate::events::ExtensionSource,
+        guid: &str,
+        changes: &BTreeMap<String, Option<String>>,
+    ) -> Result<bool> {
+        let stored: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT fields FROM addresses WHERE guid = ?",
+                [guid],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(stored) = stored else {
+            return Ok(false);
+        };
+
+        let mut fields: BTreeMap<String, String> = serde_json::from_str(&stored)?;
+        for (name, value) in changes {
+            match value {
+                Some(value) => fields.insert(name.clone(), value.clone()),
+                None => fields.remove(name),
+            };
+        }
+        self.set_address(guid, &fields)?;
+        Ok(true)
+    }
+
+    pub fn remove_address(&self, guid: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM addresses WHERE guid = ?", [guid])?;
+        Ok(())
+    }
+
+    pub fn get_addresses(&self) -> Result<Vec<Address>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT guid, fields FROM addresses ORDER BY guid")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut addresses = Vec::new();
+        for row in rows {
+            let (guid, fields) = row?;
+            addresses.push(Address {
+                guid,
+                fields: serde_json::from_str(&fields)?,
+            });
+        }
+        Ok(addresses)
+    }
+
+    /// Add a tab to open, unless it's already pending or was received,
+    /// returning whether it was added
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_pending_tab(
+        &self,
+        id: &str,
+        url: &str,
+        title: Option<&str>,
+        sent_by: &str,
+        sent_at: &str,
+    ) -> Result<bool> {
+        let added = self.conn.execute(
+            "INSERT INTO pending_tabs (id, url, title, sent_by, sent_at)
+             SELECT ?1, ?2, ?3, ?4, ?5
+             WHERE NOT EXISTS (SELECT 1 FROM received_tabs WHERE id = ?1)
+             ON CONFLICT (id) DO NOTHING",
+            rusqlite::params![id, url, title, sent_by, sent_at],
+        )?;
+        Ok(added > 0)
+    }
+
+    /// Remove a pending tab once it's received, remembering it so the tab
+    /// being delivered again or its `TabSent` event syncing in later doesn't
+    /// add it back
+    pub fn receive_pending_tab(&self, id: &str, received_at: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM pending_tabs WHERE id = ?", [id])?;
+        self.conn.execute(
+            "INSERT OR IGNORE INTO received_tabs (id, received_at) VALUES (?, ?)",
+            [id, received_at],
+        )?;
+        Ok(())
+    }
+
+    /// Forget tabs received before the cutoff, returning how many
+    pub fn forget_received_tabs(&self, before: &str) -> Result<usize> {
+        let forgotten = self
+            .conn
+            .execute("DELETE FROM received_tabs WHERE received_at < ?", [before])?;
+        Ok(forgotten)
+    }
+
+    pub fn get_pending_tabs(&self) -> Result<Vec<PendingTab>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, url, title, sent_by, sent_at FROM pending_tabs ORDER BY sent_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(PendingTab {
+                id: row.get(0)?,
+                url: row.get(1)?,
+                title: row.get(2)?,
+                from_device: row.get(3)?,
+                sent_at: row.get(4)?,
+                duplicates: Vec::new(),
+            })
+        })?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Record a sent tab as pending, keeping the delivery state of a tab
+    /// that's already recorded
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_sent_tab(
+        &self,
+        id: &str,
+        to_device: &str,
+        url: &str,
+        title: Option<&str>,
+        sent_at: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR IGNORE INTO sent_tabs (id, to_device, url, title, sent_at) VALUES (?, ?, ?, ?, ?)",
+            rusqlite::params![id, to_device, url, title, sent_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_sent_tab(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM sent_tabs WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    /// Mark a pending tab as delivered; a tab that was already opened
+    /// stays opened
+    pub fn mark_tab_delivered(&self, id: &str) -> Result<bool> {
+        let changed = self.conn.execute(
+            "UPDATE sent_tabs SET status = 'delivered', next_attempt = NULL
+             WHERE id = ? AND status = 'pending'",
+            [id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    pub fn mark_tab_opened(&self, id: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sent_tabs SET status = 'opened', next_attempt = NULL WHERE id = ?",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    /// Count a direct delivery attempt and schedule the next one
+    pub fn record_tab_attempt(&self, id: &str, next_attempt: &str) -> Result<()> {
+        self.conn.execute(
+            "UPDATE sent_tabs SET attempts = attempts + 1, next_attempt = ? WHERE id = ?",
+            [next_attempt, id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_sent_tabs(&self) -> Result<Vec<SentTab>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, to_device, url, title, sent_at, status, attempts, next_attempt
+             FROM sent_tabs ORDER BY sent_at",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok(SentTab {
+                id: row.get(0)?,
+                to_device: row.get(1)?,
+                url: row.get(2)?,
+                title: row.get(3)?,
+                sent_at: row.get(4)?,
+                status: TabDelivery::parse(&row.get::<_, String>(5)?),
+                attempts: row.get(6)?,
+                next_attempt: row.get(7)?,
+            })
+        })?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Record that a device was seen on the network, keeping its last
+    /// known address if none is given
+    ///
+    /// The name a device reports is only used until it has one, after
+    /// that names change through `DeviceRenamed` so a device that hasn't
+    /// applied its rename yet doesn't undo it.
+    pub fn record_device_seen(
+        &self,
+        id: &str,
+        name: Option<&str>,
+        address: Option<&str>,
+        seen_at: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, name, address, last_seen) VALUES (?, ?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET
+                 name = COALESCE(name, excluded.name),
+                 address = COALESCE(excluded.address, address),
+                 last_seen = excluded.last_seen",
+            rusqlite::params![id, name, address, seen_at],
+        )?;
+        Ok(())
+    }
+
+    /// Record that events from a device were applied, which also counts
+    /// as seeing it
+    pub fn record_device_sync(&self, id: &str, synced_at: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, last_seen, last_sync) VALUES (?1, ?2, ?2)
+             ON CONFLICT(id) DO UPDATE SET
+                 last_seen = MAX(COALESCE(last_seen, ''), excluded.last_seen),
+                 last_sync = excluded.last_sync",
+            rusqlite::params![id, synced_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn rename_device(&self, id: &str, name: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, name) VALUES (?, ?)
+             ON CONFLICT(id) DO UPDATE SET name = excluded.name",
+            [id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_device_label(
+        &self,
+        id: &str,
+        emoji: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, emoji, color) VALUES (?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET emoji = excluded.emoji, color = excluded.color",
+            rusqlite::params![id, emoji, color],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_device_onion(&self, id: &str, onion: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, onion) VALUES (?, ?)
+             ON CONFLICT(id) DO UPDATE SET onion = excluded.onion",
+            rusqlite::params![id, onion],
+        )?;
+        Ok(())
+    }
+
+    /// Record the key a device was verified with, or forget it with `None`
+    pub fn set_device_verified_key(&self, id: &str, key: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, verified_key) VALUES (?, ?)
+             ON CONFLICT(id) DO UPDATE SET verified_key = excluded.verified_key",
+            rusqlite::params![id, key],
+        )?;
+        Ok(())
+    }
+
+    /// Replace the extensions a device reported as installed
+    pub fn set_device_extensions(&self, device: &str, extensions: &[String]) -> Result<()> {
+        self.savepoint(|db| {
+            db.conn
+                .execute("DELETE FROM device_extensions WHERE device = ?", [device])?;
+            let mut stmt = db.conn.prepare_cached(
+                "INSERT OR IGNORE INTO device_extensions (device, id) VALUES (?, ?)",
+            )?;
+            for id in extensions {
+                stmt.execute([device, id])?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Extensions each device that reported them has installed, by device
+    pub fn get_device_extensions(&self) -> Result<BTreeMap<String, BTreeSet<String>>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT device, id FROM device_extensions ORDER BY device, id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let mut devices: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        for row in rows {
+            let (device, id) = row?;
+            devices.entry(device).or_default().insert(id);
+        }
+        Ok(devices)
+    }
+
+    /// Record what a device reported it's running, unless a later report
+    /// from it was already recorded
+    pub fn set_device_report(&self, report: &DeviceReport) -> Result<()> {
+        let extensions = serde_json::to_string(&report.extensions)?;
+        self.conn.execute(
+            "INSERT INTO device_state
+                 (device, extensions, browser_version, wolfpack_version, last_scan, reported_at)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(device) DO UPDATE SET
+                 extensions = excluded.extensions,
+                 browser_version = excluded.browser_version,
+                 wolfpack_version = excluded.wolfpack_version,
+                 last_scan = excluded.last_scan,
+                 reported_at = excluded.reported_at
+             WHERE excluded.reported_at >= device_state.reported_at",
+            rusqlite::params![
+                report.device,
+                extensions,
+                report.browser_version,
+                report.wolfpack_version,
+                report.last_scan,
+                report.reported_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// The latest report from each device that has sent one
+    pub fn get_device_reports(&self) -> Result<Vec<DeviceReport>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT device, extensions, browser_version, wolfpack_version, last_scan, reported_at
+             FROM device_state ORDER BY device",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4)?,
+                row.get(5)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (device, extensions, browser_version, wolfpack_version, last_scan, reported_at) =
+                row?;
+            Ok(DeviceReport {
+                device,
+                extensions: serde_json::from_str(&extensions)?,
+                browser_version,
+                wolfpack_version,
+                last_scan,
+                reported_at,
+            })
+        })
+        .collect()
+    }
+
+    /// Remember the libp2p peer ID a device last answered from
+    pub fn set_device_peer_id(&self, id: &str, peer_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO devices (id, peer_id) VALUES (?, ?)
+             ON CONFLICT(id) DO UPDATE SET peer_id = excluded.peer_id",
+            [id, peer_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn get_device_peer_id(&self, id: &str) -> Result<Option<String>> {
+        self.conn
+            .query_row("SELECT peer_id FROM devices WHERE id = ?", [id], |row| {
+                row.get(0)
+            })
+            .optional()
+            .map(Option::flatten)
+            .map_err(Into::into)
+    }
+
+    /// Forget the peer a device is bound to, returning whether it had one
+    pub fn clear_device_peer_id(&self, id: &str) -> Result<bool> {
+        let changed = self.conn.execute(
+            "UPDATE devices SET peer_id = NULL WHERE id = ? AND peer_id IS NOT NULL",
+            [id],
+        )?;
+        Ok(changed > 0)
+    }
+
+    /// Devices with the peer ID they last answered from
+    pub fn get_device_peer_ids(&self) -> Result<Vec<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, peer_id FROM devices WHERE peer_id IS NOT NULL ORDER BY id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    pub fn get_device(&self, id: &str) -> Result<Option<DeviceRecord>> {
+        self.conn
+            .query_row(
+                "SELECT id, name, emoji, color, address, last_seen, last_sync, onion,
+                        verified_key
+                 FROM devices WHERE id = ?",
+                [id],
+                device_from_row,
+            )
+            .optional()
+            .map_err(Into::into)
+    }
+
+    pub fn get_devices(&self) -> Result<Vec<DeviceRecord>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, emoji, color, address, last_seen, last_sync, onion,
+                        verified_key
+             FROM devices ORDER BY id",
+        )?;
+        let rows = stmt.query_map([], device_from_row)?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Add a page to the reading list, keeping when it was first added
+    pub fn add_reading_list_item(
+        &self,
+        url: &str,
+        title: Option<&str>,
+        added_at: &str,
+    ) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO reading_list (url, title, added_at) VALUES (?, ?, ?)
+             ON CONFLICT(url) DO UPDATE SET title = COALESCE(excluded.title, title)",
+            rusqlite::params![url, title, added_at],
+        )?;
+        Ok(())
+    }
+
+    /// Remove a page from the reading list, returning whether it was there
+    pub fn remove_reading_list_item(&self, url: &str) -> Result<bool> {
+        let removed = self
+            .conn
+            .execute("DELETE FROM reading_list WHERE url = ?", [url])?;
+        Ok(removed > 0)
+    }
+
+    pub fn get_reading_list(&self) -> Result<Vec<ReadingListItem>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url, title, added_at FROM reading_list ORDER BY added_at")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(ReadingListItem {
+                url: row.get(0)?,
+                title: row.get(1)?,
+                added_at: row.get(2)?,
+            })
+        })?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    pub fn save_vector_clock(&self, clock: &crate::events::VectorClock) -> Result<()> {
+        self.conn.execute("DELETE FROM vector_clock", [])?;
+        for (device, counter) in clock.entries() {
+            self.conn.execute(
+                "INSERT INTO vector_clock (device, counter) VALUES (?, ?)",
+                rusqlite::params![device, counter],
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn load_vector_clock(&self) -> Result<crate::events::VectorClock> {
+        let mut clock = crate::events::VectorClock::new();
+        let mut stmt = self
+            .conn
+            .prepare("SELECT device, counter FROM vector_clock")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+        })?;
+        for row in rows {
+            let (device, counter) = row?;
+            clock.set(&device, counter);
+        }
+        Ok(clock)
+    }
+
+    /// Remember the devices an event file was forwarded through to get here
+    pub fn record_event_route(&self, device: &str, number: u32, hops: &[String]) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO event_routes (device, number, hops) VALUES (?, ?, ?)",
+            rusqlite::params![device, number, serde_json::to_string(hops)?],
+        )?;
+        Ok(())
+    }
+
+    /// Devices an event file was forwarded through, empty if it came
+    /// straight from the device that wrote it
+    pub fn event_route(&self, device: &str, number: u32) -> Result<Vec<String>> {
+        let hops: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT hops FROM event_routes WHERE device = ? AND number = ?",
+                rusqlite::params![device, number],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match hops {
+            Some(hops) => Ok(serde_json::from_str(&hops)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    pub fn store_extension_xpi(
+        &self,
+        id: &str,
+        version: &str,
+        source: &crate::events::ExtensionSource,
         xpi_data: &str,
     ) -> Result<()> {
         let (source_type, source_data) = match source {
@@ -303,31 +1452,299 @@ impl StateDb {
                 "local",
                 serde_json::json!({ "original_path": original_path }).to_string(),
             ),
+            crate::events::ExtensionSource::Url { url, sha256 } => (
+                "url",
+                serde_json::json!({ "url": url, "sha256": sha256 }).to_string(),
+            ),
         };
 
-        self.conn.execute(
-            "INSERT OR REPLACE INTO extension_xpi (id, version, source_type, source_data, xpi_data, installed_at) VALUES (?, ?, ?, ?, ?, datetime('now'))",
-            rusqlite::params![id, version, source_type, source_data, xpi_data],
+        // Storing the same version again keeps when it was first stored
+        self.conn.execute(
+            "INSERT INTO extension_xpi (id, version, source_type, source_data, xpi_data, installed_at)
+             VALUES (?, ?, ?, ?, ?, datetime('now'))
+             ON CONFLICT (id) DO UPDATE SET
+                 installed_at = CASE WHEN version = excluded.version
+                     THEN installed_at ELSE excluded.installed_at END,
+                 version = excluded.version,
+                 source_type = excluded.source_type,
+                 source_data = excluded.source_data,
+                 xpi_data = excluded.xpi_data",
+            rusqlite::params![id, version, source_type, source_data, xpi_data],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_extension_xpi(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM extension_xpi WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    pub fn get_extension_xpi(&self, id: &str) -> Result<Option<(String, String)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT version, xpi_data FROM extension_xpi WHERE id = ?")?;
+        let result = stmt.query_row([id], |row| Ok((row.get(0)?, row.get(1)?)));
+        match result {
+            Ok(data) => Ok(Some(data)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// IDs of the extensions whose XPIs are stored
+    pub fn extension_xpi_ids(&self) -> Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT id FROM extension_xpi")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<HashSet<String>>>()?;
+        Ok(ids)
+    }
+
+    /// Remember that an extension's XPI was written to the profile, to check
+    /// the browser picked it up once it has started
+    pub fn record_extension_written(&self, id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO extension_installs (id, browser_started) VALUES (?, 0)",
+            [id],
+        )?;
+        Ok(())
+    }
+
+    /// Note that the browser has started since every XPI written so far
+    pub fn mark_browser_started(&self) -> Result<()> {
+        self.conn
+            .execute("UPDATE extension_installs SET browser_started = 1", [])?;
+        Ok(())
+    }
+
+    /// Extensions written before the browser last started, which it should
+    /// have registered by now
+    pub fn extensions_to_verify(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id FROM extension_installs WHERE browser_started = 1 ORDER BY id")?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(ids)
+    }
+
+    pub fn forget_extension_install(&self, id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM extension_installs WHERE id = ?", [id])?;
+        Ok(())
+    }
+
+    /// Pause syncing for a scope, optionally until an RFC 3339 timestamp
+    pub fn set_sync_pause(&self, scope: &str, until: Option<&str>) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO sync_pauses (scope, until) VALUES (?, ?)",
+            rusqlite::params![scope, until],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_sync_pause(&self, scope: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM sync_pauses WHERE scope = ?", [scope])?;
+        Ok(())
+    }
+
+    pub fn clear_sync_pauses(&self) -> Result<()> {
+        self.conn.execute("DELETE FROM sync_pauses", [])?;
+        Ok(())
+    }
+
+    pub fn get_sync_pauses(&self) -> Result<Vec<(String, Option<String>)>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT scope, until FROM sync_pauses ORDER BY scope")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// Get the pause for a scope: `None` if not paused, `Some(None)` if paused indefinitely
+    pub fn get_sync_pause(&self, scope: &str) -> Result<Option<Option<String>>> {
+        let result = self.conn.query_row(
+            "SELECT until FROM sync_pauses WHERE scope = ?",
+            [scope],
+            |row| row.get(0),
+        );
+        match result {
+            Ok(until) => Ok(Some(until)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Store the value the pack agreed on for a shared setting
+    pub fn set_pack_config(&self, key: &str, value: &[String]) -> Result<()> {
+        let value = serde_json::to_string(value)?;
+        self.conn.execute(
+            "INSERT OR REPLACE INTO pack_config (key, value) VALUES (?, ?)",
+            rusqlite::params![key, value],
+        )?;
+        Ok(())
+    }
+
+    /// Get the value the pack agreed on for a shared setting, if any device
+    /// has set it
+    pub fn get_pack_config(&self, key: &str) -> Result<Option<Vec<String>>> {
+        let result = self.conn.query_row(
+            "SELECT value FROM pack_config WHERE key = ?",
+            [key],
+            |row| row.get::<_, String>(0),
+        );
+        match result {
+            Ok(value) => Ok(Some(serde_json::from_str(&value)?)),
+            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Hold an item until the user resolves how it diverged, replacing any
+    /// divergence already held for it
+    pub fn add_divergence(&self, divergence: &Divergence) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO divergences (category, item, local, pack, found_at)
+             VALUES (?, ?, ?, ?, ?)",
+            rusqlite::params![
+                divergence.category,
+                divergence.item,
+                serde_json::to_string(&divergence.local)?,
+                divergence.pack,
+                divergence.found_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Divergences awaiting the user, by category and item
+    pub fn get_divergences(&self) -> Result<Vec<Divergence>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT category, item, local, pack, found_at FROM divergences
+             ORDER BY category, item",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get(3)?,
+                row.get(4)?,
+            ))
+        })?;
+        rows.map(|row| {
+            let (category, item, local, pack, found_at) = row?;
+            Ok(Divergence {
+                category,
+                item,
+                local: serde_json::from_str(&local)?,
+                pack,
+                found_at,
+            })
+        })
+        .collect()
+    }
+
+    /// Stop holding an item, returning whether it was held
+    pub fn remove_divergence(&self, category: &str, item: &str) -> Result<bool> {
+        let removed = self.conn.execute(
+            "DELETE FROM divergences WHERE category = ? AND item = ?",
+            [category, item],
+        )?;
+        Ok(removed > 0)
+    }
+
+    /// Log a sync session, dropping the oldest past [`MAX_SYNC_SESSIONS`]
+    pub fn add_sync_session(&self, session: &SyncSession) -> Result<()> {
+        self.conn.execute(
+            "INSERT INTO sync_sessions (peer, device, finished_at, duration_ms, files_received,
+                 events_applied, converged, error)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                session.peer,
+                session.device,
+                session.finished_at,
+                session.duration_ms,
+                session.files_received,
+                session.events_applied,
+                session.converged,
+                session.error
+            ],
+        )?;
+        self.conn.execute(
+            "DELETE FROM sync_sessions WHERE id <= (SELECT MAX(id) FROM sync_sessions) - ?",
+            [MAX_SYNC_SESSIONS],
+        )?;
+        Ok(())
+    }
+
+    /// The most recent sync sessions, newest first
+    pub fn get_sync_sessions(&self, limit: u32) -> Result<Vec<SyncSession>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT peer, device, finished_at, duration_ms, files_received, events_applied,
+                 converged, error
+             FROM sync_sessions ORDER BY id DESC LIMIT ?",
+        )?;
+        let rows = stmt.query_map([limit], |row| {
+            Ok(SyncSession {
+                peer: row.get(0)?,
+                device: row.get(1)?,
+                finished_at: row.get(2)?,
+                duration_ms: row.get(3)?,
+                files_received: row.get(4)?,
+                events_applied: row.get(5)?,
+                converged: row.get(6)?,
+                error: row.get(7)?,
+            })
+        })?;
+        rows.collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Into::into)
+    }
+
+    /// When each device last finished a session caught up, by device ID
+    pub fn get_last_converged(&self) -> Result<BTreeMap<String, String>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT device, MAX(finished_at) FROM sync_sessions
+             WHERE converged AND device IS NOT NULL GROUP BY device",
         )?;
-        Ok(())
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<std::result::Result<_, _>>()
+            .map_err(Into::into)
     }
 
-    pub fn remove_extension_xpi(&self, id: &str) -> Result<()> {
-        self.conn
-            .execute("DELETE FROM extension_xpi WHERE id = ?", [id])?;
+    /// Replace the snapshot of the profile's synced prefs
+    pub fn set_prefs_snapshot(&self, snapshot: &PrefsSnapshot) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO prefs_snapshot (id, whitelist, prefs) VALUES (1, ?, ?)",
+            rusqlite::params![
+                serde_json::to_string(&snapshot.whitelist)?,
+                serde_json::to_string(&snapshot.prefs)?
+            ],
+        )?;
         Ok(())
     }
 
-    pub fn get_extension_xpi(&self, id: &str) -> Result<Option<(String, String)>> {
-        let mut stmt = self
+    /// The snapshot of the profile's synced prefs, if one was ever taken
+    pub fn get_prefs_snapshot(&self) -> Result<Option<PrefsSnapshot>> {
+        let row = self
             .conn
-            .prepare("SELECT version, xpi_data FROM extension_xpi WHERE id = ?")?;
-        let result = stmt.query_row([id], |row| Ok((row.get(0)?, row.get(1)?)));
-        match result {
-            Ok(data) => Ok(Some(data)),
-            Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
-            Err(e) => Err(e.into()),
-        }
+            .query_row(
+                "SELECT whitelist, prefs FROM prefs_snapshot WHERE id = 1",
+                [],
+                |row| Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?)),
+            )
+            .optional()?;
+        let Some((whitelist, prefs)) = row else {
+            return Ok(None);
+        };
+        Ok(Some(PrefsSnapshot {
+            whitelist: serde_json::from_str(&whitelist)?,
+            prefs: serde_json::from_str(&prefs)?,
+        }))
     }
 }
 
@@ -351,6 +1768,44 @@ mod tests {
         assert!(path.exists());
     }
 
+    #[test]
+    fn test_merge_legacy() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("data").join("state.db");
+        let legacy = temp.path().join("sync").join("state.db");
+        std::fs::create_dir_all(legacy.parent().unwrap()).unwrap();
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+
+        let old = StateDb::open(&legacy).unwrap();
+        old.add_extension("old@example.com", "Old", None).unwrap();
+        old.add_extension("both@example.com", "Legacy name", None)
+            .unwrap();
+        drop(old);
+        let current = StateDb::open(&path).unwrap();
+        current
+            .add_extension("both@example.com", "Current name", None)
+            .unwrap();
+        drop(current);
+
+        StateDb::merge_legacy(&path, &legacy).unwrap();
+        assert!(!legacy.exists());
+        assert!(legacy.with_extension("db.migrated").exists());
+
+        let db = StateDb::open(&path).unwrap();
+        let extensions = db.get_extensions().unwrap();
+        assert_eq!(extensions.len(), 2);
+        assert!(
+            extensions
+                .iter()
+                .any(|(id, name, _)| id == "both@example.com" && name == "Current name")
+        );
+        assert!(extensions.iter().any(|(id, _, _)| id == "old@example.com"));
+
+        // Once moved aside, there is nothing left to merge
+        StateDb::merge_legacy(&path, &legacy).unwrap();
+        assert_eq!(db.get_extensions().unwrap().len(), 2);
+    }
+
     #[test]
     fn test_extensions_crud() {
         let db = StateDb::open_in_memory().unwrap();
@@ -419,6 +1874,57 @@ mod tests {
         assert_eq!(name, "Work Updated");
     }
 
+    #[test]
+    fn test_container_positions() {
+        let db = StateDb::open_in_memory().unwrap();
+        for (id, name) in [("1", "Work"), ("2", "Personal"), ("10", "Shopping")] {
+            db.add_container(id, name, "blue", "circle").unwrap();
+        }
+        db.set_container_position("10", "V").unwrap();
+        db.set_container_position("2", "V").unwrap();
+
+        // Ties go by ID, and containers without a position come last
+        let order = |db: &StateDb| -> Vec<String> {
+            db.get_container_positions()
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect()
+        };
+        assert_eq!(order(&db), vec!["2", "10", "1"]);
+
+        // Updating a container keeps its place
+        db.add_container("2", "Home", "green", "tree").unwrap();
+        assert_eq!(order(&db), vec!["2", "10", "1"]);
+    }
+
+    #[test]
+    fn test_container_sites_removed_with_container() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.add_container("1", "Work", "blue", "briefcase").unwrap();
+        db.add_container("2", "Shopping", "pink", "cart").unwrap();
+        db.assign_container_site("mail.example.com", "1", true)
+            .unwrap();
+        db.assign_container_site("shop.example.com", "2", false)
+            .unwrap();
+        db.assign_container_site("mail.example.com", "2", false)
+            .unwrap();
+
+        db.remove_container("1").unwrap();
+        db.unassign_container_site("shop.example.com").unwrap();
+        assert_eq!(
+            db.get_container_sites().unwrap(),
+            vec![SiteAssignment {
+                host: "mail.example.com".to_string(),
+                container_id: "2".to_string(),
+                never_ask: false,
+            }]
+        );
+
+        db.remove_container("2").unwrap();
+        assert!(db.get_container_sites().unwrap().is_empty());
+    }
+
     #[test]
     fn test_handlers_crud() {
         let db = StateDb::open_in_memory().unwrap();
@@ -487,14 +1993,26 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)] // Walks through each operation in turn
     fn test_search_engines_crud() {
         let db = StateDb::open_in_memory().unwrap();
 
         // Add search engines
-        db.add_search_engine("google", "Google", "https://google.com/search?q=%s")
-            .unwrap();
-        db.add_search_engine("ddg", "DuckDuckGo", "https://duckduckgo.com/?q=%s")
-            .unwrap();
+        let details = SearchEngineDetails::default();
+        db.add_search_engine(
+            "google",
+            "Google",
+            "https://google.com/search?q=%s",
+            &details,
+        )
+        .unwrap();
+        db.add_search_engine(
+            "ddg",
+            "DuckDuckGo",
+            "https://duckduckgo.com/?q=%s",
+            &details,
+        )
+        .unwrap();
 
         // Set default
         db.set_default_search_engine("ddg").unwrap();
@@ -529,6 +2047,19 @@ mod tests {
         assert_eq!(ddg_default, 0);
         assert_eq!(google_default, 1);
 
+        // Updating an engine keeps it as the default
+        let details = SearchEngineDetails {
+            suggest_url: Some("https://google.com/complete?q=%s".to_string()),
+            ..Default::default()
+        };
+        db.add_search_engine("google", "Google", "https://google.com/?q=%s", &details)
+            .unwrap();
+        let engines = db.get_search_engines().unwrap();
+        let google = engines.iter().find(|e| e.id == "google").unwrap();
+        assert!(google.is_default);
+        assert_eq!(google.url, "https://google.com/?q=%s");
+        assert_eq!(google.details, details);
+
         // Remove search engine
         db.remove_search_engine("ddg").unwrap();
         let count: i64 = conn
@@ -537,6 +2068,32 @@ mod tests {
         assert_eq!(count, 1);
     }
 
+    #[test]
+    fn test_search_engines_details_migration() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.db");
+
+        // Table as created before engine details were stored
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE search_engines (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                url TEXT NOT NULL,
+                is_default INTEGER NOT NULL DEFAULT 0
+            );
+            INSERT INTO search_engines VALUES ('ddg', 'DuckDuckGo', 'https://duckduckgo.com/', 1);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = StateDb::open(&path).unwrap();
+        let engines = db.get_search_engines().unwrap();
+        assert_eq!(engines.len(), 1);
+        assert!(engines[0].is_default);
+        assert_eq!(engines[0].details, SearchEngineDetails::default());
+    }
+
     #[test]
     fn test_pending_tabs_crud() {
         let db = StateDb::open_in_memory().unwrap();
@@ -572,10 +2129,223 @@ mod tests {
         assert_eq!(tab2.title, None);
 
         // Remove pending tab
-        db.remove_pending_tab("tab1").unwrap();
+        db.receive_pending_tab("tab1", "2024-01-01T00:02:00Z")
+            .unwrap();
         let tabs = db.get_pending_tabs().unwrap();
         assert_eq!(tabs.len(), 1);
         assert_eq!(tabs[0].id, "tab2");
+
+        // A received tab isn't added back
+        db.add_pending_tab(
+            "tab1",
+            "https://example.com",
+            Some("Example"),
+            "device-a",
+            "2024-01-01T00:00:00Z",
+        )
+        .unwrap();
+        assert_eq!(db.get_pending_tabs().unwrap().len(), 1);
+
+        assert_eq!(db.forget_received_tabs("2024-01-01T00:02:00Z").unwrap(), 0);
+        assert_eq!(db.forget_received_tabs("2024-01-02T00:00:00Z").unwrap(), 1);
+    }
+
+    #[test]
+    fn test_sent_tab_delivery() {
+        let db = StateDb::open_in_memory().unwrap();
+        let sent_at = "2024-01-01T00:00:00Z";
+
+        db.add_sent_tab("tab1", "device-b", "https://example.com", None, sent_at)
+            .unwrap();
+        db.record_tab_attempt("tab1", "2024-01-01T00:00:30Z")
+            .unwrap();
+        let tab = &db.get_sent_tabs().unwrap()[0];
+        assert_eq!(tab.status, TabDelivery::Pending);
+        assert_eq!(tab.attempts, 1);
+        assert_eq!(tab.next_attempt.as_deref(), Some("2024-01-01T00:00:30Z"));
+
+        assert!(db.mark_tab_delivered("tab1").unwrap());
+        assert!(!db.mark_tab_delivered("tab1").unwrap());
+
+        // Recording the tab again (from its own event) keeps its state
+        db.add_sent_tab("tab1", "device-b", "https://example.com", None, sent_at)
+            .unwrap();
+        let tab = &db.get_sent_tabs().unwrap()[0];
+        assert_eq!(tab.status, TabDelivery::Delivered);
+        assert_eq!(tab.next_attempt, None);
+
+        // An opened tab isn't moved back to delivered
+        db.mark_tab_opened("tab1").unwrap();
+        assert!(!db.mark_tab_delivered("tab1").unwrap());
+        assert_eq!(db.get_sent_tabs().unwrap()[0].status, TabDelivery::Opened);
+    }
+
+    #[test]
+    fn test_device_presence() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.record_device_seen(
+            "device-a",
+            Some("laptop"),
+            Some("/ip4/192.168.1.20/tcp/4001"),
+            "2024-01-01T10:00:00+00:00",
+        )
+        .unwrap();
+        db.record_device_sync("device-a", "2024-01-01T11:00:00+00:00")
+            .unwrap();
+        db.record_device_seen("device-a", None, None, "2024-01-01T12:00:00+00:00")
+            .unwrap();
+
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(device.name.as_deref(), Some("laptop"));
+        assert_eq!(
+            device.address.as_deref(),
+            Some("/ip4/192.168.1.20/tcp/4001")
+        );
+        assert_eq!(
+            device.last_seen.as_deref(),
+            Some("2024-01-01T12:00:00+00:00")
+        );
+        assert_eq!(
+            device.last_sync.as_deref(),
+            Some("2024-01-01T11:00:00+00:00")
+        );
+
+        // Applying events from a device counts as seeing it
+        db.record_device_sync("device-b", "2024-01-02T00:00:00+00:00")
+            .unwrap();
+        let devices = db.get_devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(
+            devices[1].last_seen.as_deref(),
+            Some("2024-01-02T00:00:00+00:00")
+        );
+        assert!(db.get_device("device-c").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rename_device_outlasts_reported_name() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.rename_device("device-a", "kitchen laptop").unwrap();
+        // The device still reports its old name until it applies the rename
+        db.record_device_seen(
+            "device-a",
+            Some("laptop"),
+            None,
+            "2024-01-01T10:00:00+00:00",
+        )
+        .unwrap();
+
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(device.name.as_deref(), Some("kitchen laptop"));
+        assert!(device.last_seen.is_some());
+    }
+
+    #[test]
+    fn test_device_label() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.rename_device("device-a", "laptop").unwrap();
+        db.set_device_label("device-a", Some("🏠"), Some("green"))
+            .unwrap();
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(device.name.as_deref(), Some("laptop"));
+        assert_eq!(device.emoji.as_deref(), Some("🏠"));
+        assert_eq!(device.color.as_deref(), Some("green"));
+
+        db.set_device_label("device-a", None, None).unwrap();
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert!(device.emoji.is_none());
+        assert!(device.color.is_none());
+    }
+
+    #[test]
+    fn test_device_onion() {
+        let db = StateDb::open_in_memory().unwrap();
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:4001";
+
+        db.set_device_onion("device-a", Some(onion)).unwrap();
+        db.record_device_seen(
+            "device-a",
+            Some("laptop"),
+            None,
+            "2024-01-01T10:00:00+00:00",
+        )
+        .unwrap();
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(device.onion.as_deref(), Some(onion));
+
+        db.set_device_onion("device-a", None).unwrap();
+        assert!(db.get_device("device-a").unwrap().unwrap().onion.is_none());
+    }
+
+    #[test]
+    fn test_device_verified_key() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.rename_device("device-a", "laptop").unwrap();
+        db.set_device_verified_key("device-a", Some("ab12"))
+            .unwrap();
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(device.name.as_deref(), Some("laptop"));
+        assert_eq!(device.verified_key.as_deref(), Some("ab12"));
+
+        db.set_device_verified_key("device-a", None).unwrap();
+        let device = db.get_device("device-a").unwrap().unwrap();
+        assert!(device.verified_key.is_none());
+    }
+
+    #[test]
+    fn test_device_report_keeps_latest() {
+        let db = StateDb::open_in_memory().unwrap();
+        let report = |wolfpack_version: &str, reported_at: &str| DeviceReport {
+            device: "device-a".to_string(),
+            extensions: BTreeMap::from([("ext@example.com".to_string(), "1.0".to_string())]),
+            browser_version: Some("128.0".to_string()),
+            wolfpack_version: wolfpack_version.to_string(),
+            last_scan: None,
+            reported_at: reported_at.to_string(),
+        };
+
+        let newer = report("0.2.0", "2024-01-02T10:00:00+00:00");
+        db.set_device_report(&newer).unwrap();
+        // Replayed out of order
+        db.set_device_report(&report("0.1.0", "2024-01-01T10:00:00+00:00"))
+            .unwrap();
+        assert_eq!(db.get_device_reports().unwrap(), vec![newer]);
+    }
+
+    #[test]
+    fn test_device_peer_ids() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.rename_device("device-a", "laptop").unwrap();
+        assert!(db.get_device_peer_ids().unwrap().is_empty());
+
+        db.set_device_peer_id("device-a", "peer-1").unwrap();
+        db.set_device_peer_id("device-b", "peer-2").unwrap();
+        db.set_device_peer_id("device-a", "peer-3").unwrap();
+        assert_eq!(
+            db.get_device_peer_id("device-a").unwrap().as_deref(),
+            Some("peer-3")
+        );
+        assert!(db.get_device_peer_id("device-c").unwrap().is_none());
+        assert_eq!(
+            db.get_device_peer_ids().unwrap(),
+            vec![
+                ("device-a".to_string(), "peer-3".to_string()),
+                ("device-b".to_string(), "peer-2".to_string()),
+            ]
+        );
+        assert_eq!(
+            db.get_device("device-a").unwrap().unwrap().name.as_deref(),
+            Some("laptop")
+        );
+
+        assert!(db.clear_device_peer_id("device-a").unwrap());
+        assert!(!db.clear_device_peer_id("device-a").unwrap());
+        assert!(db.get_device_peer_id("device-a").unwrap().is_none());
+        assert_eq!(db.get_device_peer_ids().unwrap().len(), 1);
     }
 
     #[test]
@@ -588,15 +2358,19 @@ mod tests {
         assert!(!db.is_event_applied(event_id).unwrap());
 
         // Mark applied
-        db.mark_event_applied(event_id, "device-a", "2024-01-01T00:00:00Z")
-            .unwrap();
+        assert!(
+            db.mark_event_applied(event_id, "device-a", "2024-01-01T00:00:00Z")
+                .unwrap()
+        );
 
         // Check applied
         assert!(db.is_event_applied(event_id).unwrap());
 
-        // Marking again should be idempotent (INSERT OR IGNORE)
-        db.mark_event_applied(event_id, "device-a", "2024-01-01T00:00:00Z")
-            .unwrap();
+        // Marking again is idempotent, and says it was already applied
+        assert!(
+            !db.mark_event_applied(event_id, "device-a", "2024-01-01T00:00:00Z")
+                .unwrap()
+        );
         assert!(db.is_event_applied(event_id).unwrap());
     }
 
@@ -635,6 +2409,97 @@ mod tests {
         assert_eq!(loaded.get("device-b"), 2);
     }
 
+    #[test]
+    fn test_event_routes() {
+        let db = StateDb::open_in_memory().unwrap();
+        assert!(db.event_route("device-a", 1).unwrap().is_empty());
+
+        let hops = vec!["device-b".to_string(), "device-c".to_string()];
+        db.record_event_route("device-a", 1, &hops).unwrap();
+        assert_eq!(db.event_route("device-a", 1).unwrap(), hops);
+        assert!(db.event_route("device-a", 2).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_extension_installs() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.record_extension_written("a@example.com").unwrap();
+        assert!(db.extensions_to_verify().unwrap().is_empty());
+
+        db.mark_browser_started().unwrap();
+        // Written after the browser started, so not checked until next time
+        db.record_extension_written("b@example.com").unwrap();
+        assert_eq!(db.extensions_to_verify().unwrap(), vec!["a@example.com"]);
+
+        db.forget_extension_install("a@example.com").unwrap();
+        db.mark_browser_started().unwrap();
+        assert_eq!(db.extensions_to_verify().unwrap(), vec!["b@example.com"]);
+    }
+
+    #[test]
+    fn test_clear_materialized_state_in_transaction() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.set_pref("browser.startup.page", "3", "int").unwrap();
+        db.rename_device("device-b", "Laptop").unwrap();
+        db.set_sync_pause("all", None).unwrap();
+
+        let result: Result<()> = db.transaction(|db| {
+            db.clear_materialized_state()?;
+            anyhow::bail!("replay failed")
+        });
+        assert!(result.is_err());
+        let prefs: i64 = db
+            .connection()
+            .query_row("SELECT COUNT(*) FROM prefs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(prefs, 1);
+
+        db.transaction(|db| db.clear_materialized_state()).unwrap();
+        let prefs: i64 = db
+            .connection()
+            .query_row("SELECT COUNT(*) FROM prefs", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(prefs, 0);
+        // Local records stay
+        assert_eq!(db.get_devices().unwrap().len(), 1);
+        assert!(db.get_sync_pause("all").unwrap().is_some());
+    }
+
+    #[test]
+    fn test_savepoint_nests_in_transaction() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.transaction(|db| {
+            db.set_pref("browser.startup.page", "3", "int")?;
+            let result: Result<()> = db.savepoint(|db| {
+                db.set_handler("mailto", "thunderbird")?;
+                anyhow::bail!("apply failed")
+            });
+            assert!(result.is_err());
+            db.savepoint(|db| db.set_pref("browser.tabs.warnOnClose", "false", "bool"))
+        })
+        .unwrap();
+
+        // Only the failed savepoint was rolled back
+        let counts: BTreeMap<&str, u64> = db.row_counts().unwrap().into_iter().collect();
+        assert_eq!(counts["prefs"], 2);
+        assert_eq!(counts["handlers"], 0);
+    }
+
+    #[test]
+    fn test_row_counts() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.set_pref("browser.startup.page", "3", "int").unwrap();
+        db.set_pref("browser.tabs.warnOnClose", "false", "bool")
+            .unwrap();
+        db.set_handler("mailto", "thunderbird").unwrap();
+
+        let counts = db.row_counts().unwrap();
+        assert_eq!(counts.len(), MATERIALIZED_TABLES.len());
+        assert!(counts.contains(&("prefs", 2)));
+        assert!(counts.contains(&("handlers", 1)));
+        assert!(counts.contains(&("extensions", 0)));
+    }
+
     #[test]
     fn test_extension_xpi_git_source() {
         let db = StateDb::open_in_memory().unwrap();
@@ -727,4 +2592,251 @@ mod tests {
         assert_eq!(result.0, "2.0.0");
         assert_eq!(result.1, "newdata");
     }
+
+    #[test]
+    fn test_start_page_prefs() {
+        let db = StateDb::open_in_memory().unwrap();
+        assert_eq!(db.get_start_page().unwrap(), StartPage::default());
+
+        db.set_start_page_pref(
+            "browser.startup.homepage",
+            &PrefValue::String("https://example.com".to_string()),
+        )
+        .unwrap();
+        db.set_start_page_pref("browser.startup.page", &PrefValue::Int(1))
+            .unwrap();
+        db.set_start_page_pref("browser.newtabpage.enabled", &PrefValue::Bool(false))
+            .unwrap();
+
+        let start_page = db.get_start_page().unwrap();
+        assert_eq!(start_page.homepage, vec!["https://example.com"]);
+        assert_eq!(
+            start_page.settings.get("browser.startup.page"),
+            Some(&PrefValue::Int(1))
+        );
+        assert_eq!(
+            start_page.settings.get("browser.newtabpage.enabled"),
+            Some(&PrefValue::Bool(false))
+        );
+
+        db.remove_start_page_pref("browser.startup.homepage")
+            .unwrap();
+        assert!(db.get_start_page().unwrap().homepage.is_empty());
+    }
+
+    #[test]
+    fn test_permissions() {
+        let db = StateDb::open_in_memory().unwrap();
+        db.set_permission("https://example.com", "camera", 1)
+            .unwrap();
+        db.set_permission("https://example.com", "microphone", 1)
+            .unwrap();
+        db.set_permission("https://example.com", "camera", 2)
+            .unwrap();
+        db.remove_permission("https://example.com", "microphone")
+            .unwrap();
+
+        assert_eq!(
+            db.get_permissions().unwrap(),
+            vec![Permission {
+                origin: "https://example.com".to_string(),
+                permission: "camera".to_string(),
+                capability: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_address_field_merge() {
+        let db = StateDb::open_in_memory().unwrap();
+        let fields = BTreeMap::from([
+            ("given-name".to_string(), "Ada".to_string()),
+            ("tel".to_string(), "123".to_string()),
+        ]);
+        db.set_address("a1", &fields).unwrap();
+
+        // Two devices edit different fields; both edits survive
+        let street =
+            BTreeMap::from([("street-address".to_string(), Some("1 Main St".to_string()))]);
+        let tel = BTreeMap::from([("tel".to_string(), None)]);
+        assert!(db.update_address("a1", &street).unwrap());
+        assert!(db.update_address("a1", &tel).unwrap());
+
+        let addresses = db.get_addresses().unwrap();
+        assert_eq!(
+            addresses[0].fields,
+            BTreeMap::from([
+                ("given-name".to_string(), "Ada".to_string()),
+                ("street-address".to_string(), "1 Main St".to_string()),
+            ])
+        );
+
+        // Edits to a removed address don't bring it back
+        db.remove_address("a1").unwrap();
+        assert!(!db.update_address("a1", &street).unwrap());
+        assert!(db.get_addresses().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_reading_list() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.add_reading_list_item("https://a.example", Some("A"), "2024-01-01T00:00:00Z")
+            .unwrap();
+        db.add_reading_list_item("https://b.example", None, "2024-01-02T00:00:00Z")
+            .unwrap();
+        // Saving again keeps the original date and title
+        db.add_reading_list_item("https://a.example", None, "2024-01-03T00:00:00Z")
+            .unwrap();
+
+        let items = db.get_reading_list().unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].url, "https://a.example");
+        assert_eq!(items[0].title.as_deref(), Some("A"));
+        assert_eq!(items[0].added_at, "2024-01-01T00:00:00Z");
+
+        assert!(db.remove_reading_list_item("https://a.example").unwrap());
+        assert!(!db.remove_reading_list_item("https://a.example").unwrap());
+        assert_eq!(db.get_reading_list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_sync_pause_roundtrip() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        assert!(db.get_sync_pause("all").unwrap().is_none());
+
+        db.set_sync_pause("all", None).unwrap();
+        assert_eq!(db.get_sync_pause("all").unwrap(), Some(None));
+
+        db.set_sync_pause("all", Some("2030-01-01T00:00:00+00:00"))
+            .unwrap();
+        assert_eq!(
+            db.get_sync_pause("all").unwrap(),
+            Some(Some("2030-01-01T00:00:00+00:00".to_string()))
+        );
+
+        db.clear_sync_pause("all").unwrap();
+        assert!(db.get_sync_pause("all").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_sync_pauses_list_and_clear() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        db.set_sync_pause("prefs", None).unwrap();
+        db.set_sync_pause("all", Some("2030-01-01T00:00:00+00:00"))
+            .unwrap();
+
+        let pauses = db.get_sync_pauses().unwrap();
+        assert_eq!(pauses.len(), 2);
+        assert_eq!(pauses[0].0, "all");
+        assert_eq!(pauses[1], ("prefs".to_string(), None));
+
+        db.clear_sync_pauses().unwrap();
+        assert!(db.get_sync_pauses().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_pack_config() {
+        let db = StateDb::open_in_memory().unwrap();
+        assert!(db.get_pack_config("prefs.whitelist").unwrap().is_none());
+
+        let whitelist = vec!["browser.*".to_string(), "extensions.*".to_string()];
+        db.set_pack_config("prefs.whitelist", &whitelist).unwrap();
+        db.set_pack_config("prefs.whitelist", &whitelist[..1])
+            .unwrap();
+        assert_eq!(
+            db.get_pack_config("prefs.whitelist").unwrap(),
+            Some(vec!["browser.*".to_string()])
+        );
+
+        // Set by events, so it's replayed with them
+        db.clear_materialized_state().unwrap();
+        assert!(db.get_pack_config("prefs.whitelist").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_prefs_snapshot() {
+        let db = StateDb::open_in_memory().unwrap();
+        assert!(db.get_prefs_snapshot().unwrap().is_none());
+
+        let snapshot = PrefsSnapshot {
+            whitelist: vec!["browser.*".to_string()],
+            prefs: HashMap::from([
+                ("browser.startup.page".to_string(), PrefValue::Int(3)),
+                (
+                    "browser.search.region".to_string(),
+                    PrefValue::String("3".to_string()),
+                ),
+            ]),
+        };
+        db.set_prefs_snapshot(&PrefsSnapshot::default()).unwrap();
+        db.set_prefs_snapshot(&snapshot).unwrap();
+        assert_eq!(db.get_prefs_snapshot().unwrap(), Some(snapshot.clone()));
+
+        // What the profile had, so a replay leaves it alone
+        db.clear_materialized_state().unwrap();
+        assert_eq!(db.get_prefs_snapshot().unwrap(), Some(snapshot));
+    }
+
+    #[test]
+    fn test_divergences() {
+        let db = StateDb::open_in_memory().unwrap();
+        let divergence = |value: i64| Divergence {
+            category: "prefs".to_string(),
+            item: "browser.startup.page".to_string(),
+            local: Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: PrefValue::Int(value),
+            },
+            pack: Some("1".to_string()),
+            found_at: "2026-01-01T00:00:00+00:00".to_string(),
+        };
+        db.add_divergence(&divergence(2)).unwrap();
+        db.add_divergence(&divergence(3)).unwrap();
+        assert_eq!(db.get_divergences().unwrap(), vec![divergence(3)]);
+
+        assert!(
+            db.remove_divergence("prefs", "browser.startup.page")
+                .unwrap()
+        );
+        assert!(
+            !db.remove_divergence("prefs", "browser.startup.page")
+                .unwrap()
+        );
+        assert!(db.get_divergences().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_sessions() {
+        let db = StateDb::open_in_memory().unwrap();
+        let session = |device: Option<&str>, finished_at: &str, converged: bool| SyncSession {
+            peer: "12D3KooWpeer".to_string(),
+            device: device.map(str::to_string),
+            finished_at: finished_at.to_string(),
+            duration_ms: 1200,
+            files_received: 3,
+            events_applied: 2,
+            converged,
+            error: (!converged).then(|| "Timed out".to_string()),
+        };
+        let first = session(Some("laptop"), "2026-01-01T00:00:00+00:00", true);
+        let second = session(Some("laptop"), "2026-01-01T00:01:00+00:00", false);
+        let third = session(None, "2026-01-01T00:02:00+00:00", false);
+        for s in [&first, &second, &third] {
+            db.add_sync_session(s).unwrap();
+        }
+
+        assert_eq!(db.get_sync_sessions(2).unwrap(), vec![third, second]);
+        let converged = db.get_last_converged().unwrap();
+        assert_eq!(converged.len(), 1);
+        assert_eq!(converged["laptop"], first.finished_at);
+
+        for _ in 0..MAX_SYNC_SESSIONS {
+            db.add_sync_session(&first).unwrap();
+        }
+        let kept = db.get_sync_sessions(MAX_SYNC_SESSIONS * 2).unwrap();
+        assert_eq!(kept.len(), MAX_SYNC_SESSIONS as usize);
+    }
 }
@@ -1,9 +1,14 @@
 use anyhow::Result;
-use tracing::debug;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use tracing::{debug, instrument, warn};
 
-use crate::events::{Event, EventEnvelope, PrefValue};
+use crate::events::{Event, EventEnvelope, PrefValue, SyncCategory};
+use crate::profile::{
+    HOMEPAGE_PREF, PINNED_SITES_PREF, StartPage, is_newtab_setting, translate_handler,
+};
 
-use super::StateDb;
+use super::{DeviceReport, StateDb};
 
 fn pref_to_storage(value: &PrefValue) -> (String, &'static str) {
     match value {
@@ -18,29 +23,116 @@ pub fn materialize_events(
     events: &[EventEnvelope],
     this_device: &str,
 ) -> Result<usize> {
-    let mut applied = 0;
+    materialize_events_except(db, events, this_device, &[], &BTreeMap::new())
+}
+
+/// Materialize events, leaving events in paused categories unapplied
+/// so they are picked up once the category is resumed
+///
+/// Events limited to other operating systems are skipped for good, except
+/// handlers whose application `handler_translations` (`handlers.translate`)
+/// maps to a local one. Handlers are always applied with the local
+/// application they're mapped to.
+///
+/// Each event is applied in its own savepoint together with the record that
+/// it was, so an interrupted apply leaves it either applied and recorded or
+/// neither. An event that fails to apply is left for the next pass, and the
+/// rest still apply.
+#[allow(clippy::cognitive_complexity)] // Span around the apply loop
+#[instrument(skip_all, fields(count = events.len()))]
+pub fn materialize_events_except(
+    db: &StateDb,
+    events: &[EventEnvelope],
+    this_device: &str,
+    paused: &[SyncCategory],
+    handler_translations: &BTreeMap<String, String>,
+) -> Result<usize> {
+    // One transaction around the batch, so it's written in one go
+    db.savepoint(|db| {
+        let mut applied = 0;
+
+        for envelope in events {
+            // Not marked applied, so an update that knows it applies it
+            if matches!(envelope.event, Event::Unknown(_))
+                || paused.contains(&envelope.event.category())
+            {
+                continue;
+            }
 
-    for envelope in events {
-        if db.is_event_applied(envelope.id)? {
-            continue;
+            match db.savepoint(|db| apply_once(db, envelope, this_device, handler_translations)) {
+                Ok(true) => {
+                    applied += 1;
+                    debug!(event_id = %envelope.id, event_type = ?std::mem::discriminant(&envelope.event), "Applied event");
+                }
+                Ok(false) => {}
+                Err(e) => warn!(event_id = %envelope.id, "Failed to apply event: {:#}", e),
+            }
         }
 
-        apply_event(db, &envelope.event, this_device)?;
-        db.mark_event_applied(
-            envelope.id,
-            &envelope.device,
-            &envelope.timestamp.to_rfc3339(),
-        )?;
-        applied += 1;
-        debug!(event_id = %envelope.id, event_type = ?std::mem::discriminant(&envelope.event), "Applied event");
+        Ok(applied)
+    })
+}
+
+/// Record an event as applied and apply it, returning false if it already
+/// was or is for another platform
+fn apply_once(
+    db: &StateDb,
+    envelope: &EventEnvelope,
+    this_device: &str,
+    handler_translations: &BTreeMap<String, String>,
+) -> Result<bool> {
+    if !db.mark_event_applied(
+        envelope.id,
+        &envelope.device,
+        &envelope.timestamp.to_rfc3339(),
+    )? {
+        return Ok(false);
+    }
+
+    let adapted;
+    let envelope = match adapt_to_platform(envelope, handler_translations) {
+        Some(Cow::Borrowed(envelope)) => envelope,
+        Some(Cow::Owned(envelope)) => {
+            adapted = envelope;
+            &adapted
+        }
+        None => {
+            debug!(event_id = %envelope.id, platforms = ?envelope.platforms, "Skipped event for another platform");
+            return Ok(false);
+        }
+    };
+    apply_event(db, envelope, this_device)?;
+    if envelope.device != this_device {
+        db.record_device_sync(&envelope.device, &chrono::Utc::now().to_rfc3339())?;
     }
+    Ok(true)
+}
 
-    Ok(applied)
+/// The event as it applies on this device, with a handler's application
+/// swapped for the local one it's mapped to, or `None` if it's for another
+/// platform
+fn adapt_to_platform<'a>(
+    envelope: &'a EventEnvelope,
+    handler_translations: &BTreeMap<String, String>,
+) -> Option<Cow<'a, EventEnvelope>> {
+    if let Event::HandlerSet { protocol, handler } = &envelope.event
+        && let Some(local) = translate_handler(handler, handler_translations)
+    {
+        let mut adapted = envelope.clone();
+        adapted.event = Event::HandlerSet {
+            protocol: protocol.clone(),
+            handler: local.clone(),
+        };
+        return Some(Cow::Owned(adapted));
+    }
+    envelope
+        .is_for_this_platform()
+        .then_some(Cow::Borrowed(envelope))
 }
 
 #[allow(clippy::too_many_lines)] // Match arms for each event type - well-structured dispatcher
-fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
-    match event {
+fn apply_event(db: &StateDb, envelope: &EventEnvelope, this_device: &str) -> Result<()> {
+    match &envelope.event {
         Event::ExtensionAdded { id, name, url } => {
             db.add_extension(id, name, url.as_deref())?;
         }
@@ -53,6 +145,7 @@ fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
             version,
             source,
             xpi_data,
+            ..
         } => {
             // Store extension metadata
             db.add_extension(id, name, None)?;
@@ -74,6 +167,19 @@ fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
         Event::ContainerRemoved { id } => {
             db.remove_container(id)?;
         }
+        Event::ContainerReordered { id, position } => {
+            db.set_container_position(id, position)?;
+        }
+        Event::ContainerSiteAssigned {
+            host,
+            container_id,
+            never_ask,
+        } => {
+            db.assign_container_site(host, container_id, *never_ask)?;
+        }
+        Event::ContainerSiteUnassigned { host } => {
+            db.unassign_container_site(host)?;
+        }
         Event::ContainerUpdated {
             id,
             name,
@@ -92,8 +198,19 @@ fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
         Event::HandlerRemoved { protocol } => {
             db.remove_handler(protocol)?;
         }
-        Event::SearchEngineAdded { id, name, url } => {
-            db.add_search_engine(id, name, url)?;
+        Event::SearchEngineAdded {
+            id,
+            name,
+            url,
+            details,
+        }
+        | Event::SearchEngineUpdated {
+            id,
+            name,
+            url,
+            details,
+        } => {
+            db.add_search_engine(id, name, url, details)?;
         }
         Event::SearchEngineRemoved { id } => {
             db.remove_search_engine(id)?;
@@ -108,21 +225,143 @@ fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
         Event::PrefRemoved { key } => {
             db.remove_pref(key)?;
         }
+        Event::HomepageSet { .. }
+        | Event::NewTabSettingSet { .. }
+        | Event::NewTabSettingRemoved { .. }
+        | Event::TopSitesPinned { .. } => apply_start_page_event(db, &envelope.event)?,
+        Event::PermissionSet {
+            origin,
+            permission,
+            capability,
+        } => {
+            db.set_permission(origin, permission, *capability)?;
+        }
+        Event::PermissionRemoved { origin, permission } => {
+            db.remove_permission(origin, permission)?;
+        }
+        Event::CertOverrideSet {
+            host,
+            algorithm,
+            fingerprint,
+        } => {
+            db.set_cert_override(host, algorithm, fingerprint)?;
+        }
+        Event::CertOverrideRemoved { host } => {
+            db.remove_cert_override(host)?;
+        }
+        Event::AddressAdded { guid, fields } => {
+            db.set_address(guid, fields)?;
+        }
+        Event::AddressUpdated { guid, fields } => {
+            if !db.update_address(guid, fields)? {
+                debug!(guid = %guid, "Skipping update to unknown address");
+            }
+        }
+        Event::AddressRemoved { guid } => {
+            db.remove_address(guid)?;
+        }
         Event::TabSent {
             to_device,
             url,
             title,
         } => {
-            // Only store if this tab is for us
+            // Tabs are keyed by the sending event, which TabReceived refers to
+            let id = envelope.id.to_string();
+            let sent_at = envelope.timestamp.to_rfc3339();
             if to_device == this_device {
-                let id = uuid::Uuid::now_v7().to_string();
-                let sent_at = chrono::Utc::now().to_rfc3339();
-                db.add_pending_tab(&id, url, title.as_deref(), to_device, &sent_at)?;
+                db.add_pending_tab(&id, url, title.as_deref(), &envelope.device, &sent_at)?;
+            } else if envelope.device == this_device {
+                db.add_sent_tab(&id, to_device, url, title.as_deref(), &sent_at)?;
             }
         }
         Event::TabReceived { event_id } => {
-            db.remove_pending_tab(&event_id.to_string())?;
+            db.receive_pending_tab(&event_id.to_string(), &envelope.timestamp.to_rfc3339())?;
+            db.mark_tab_opened(&event_id.to_string())?;
+        }
+        Event::ReadingListAdded { url, title } => {
+            let added_at = envelope.timestamp.to_rfc3339();
+            db.add_reading_list_item(url, title.as_deref(), &added_at)?;
+        }
+        Event::ReadingListRemoved { url } => {
+            db.remove_reading_list_item(url)?;
+        }
+        Event::DeviceRenamed { device_id, name } => {
+            db.rename_device(device_id, name)?;
         }
+        Event::DeviceLabeled {
+            device_id,
+            emoji,
+            color,
+        } => {
+            db.set_device_label(device_id, emoji.as_deref(), color.as_deref())?;
+        }
+        Event::DeviceOnionSet { device_id, onion } => {
+            db.set_device_onion(device_id, onion.as_deref())?;
+        }
+        Event::DeviceExtensionsSet {
+            device_id,
+            extensions,
+        } => {
+            db.set_device_extensions(device_id, extensions)?;
+        }
+        Event::DeviceState {
+            device_id,
+            extensions,
+            browser_version,
+            wolfpack_version,
+            last_scan,
+        } => {
+            db.set_device_report(&DeviceReport {
+                device: device_id.clone(),
+                extensions: extensions.clone(),
+                browser_version: browser_version.clone(),
+                wolfpack_version: wolfpack_version.clone(),
+                last_scan: last_scan.map(|t| t.to_rfc3339()),
+                reported_at: envelope.timestamp.to_rfc3339(),
+            })?;
+        }
+        Event::PackConfigSet { key, value } => {
+            db.set_pack_config(key, value)?;
+        }
+        Event::Unknown(_) => {}
+    }
+    Ok(())
+}
+
+/// Store start page events as the prefs they map to
+fn apply_start_page_event(db: &StateDb, event: &Event) -> Result<()> {
+    match event {
+        Event::HomepageSet { urls } => {
+            let prefs = StartPage {
+                homepage: urls.clone(),
+                ..Default::default()
+            }
+            .to_prefs();
+            match prefs.get(HOMEPAGE_PREF) {
+                Some(value) => db.set_start_page_pref(HOMEPAGE_PREF, value)?,
+                None => db.remove_start_page_pref(HOMEPAGE_PREF)?,
+            }
+        }
+        Event::TopSitesPinned { sites } => {
+            let prefs = StartPage {
+                pinned: sites.clone(),
+                ..Default::default()
+            }
+            .to_prefs();
+            match prefs.get(PINNED_SITES_PREF) {
+                Some(value) => db.set_start_page_pref(PINNED_SITES_PREF, value)?,
+                None => db.remove_start_page_pref(PINNED_SITES_PREF)?,
+            }
+        }
+        // Only layout settings may be set this way, never arbitrary prefs
+        Event::NewTabSettingSet { key, .. } | Event::NewTabSettingRemoved { key }
+            if !is_newtab_setting(key) =>
+        {
+            warn!(key = %key, "Ignoring unknown new tab setting");
+        }
+        Event::NewTabSettingSet { key, value } => db.set_start_page_pref(key, value)?,
+        Event::NewTabSettingRemoved { key } => db.remove_start_page_pref(key)?,
+        _ => {}
     }
     Ok(())
 }
@@ -130,7 +369,7 @@ fn apply_event(db: &StateDb, event: &Event, this_device: &str) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::events::{ExtensionSource, VectorClock};
+    use crate::events::{ExtensionSource, SearchEngineDetails, VectorClock};
 
     #[test]
     fn test_materialize_extension_events() {
@@ -225,6 +464,7 @@ mod tests {
                     original_path: "/path/to/ext.xpi".to_string(),
                 },
                 xpi_data: "base64data".to_string(),
+                chunks: Vec::new(),
             },
         )];
 
@@ -256,6 +496,7 @@ mod tests {
                     original_path: "/path".to_string(),
                 },
                 xpi_data: "data".to_string(),
+                chunks: Vec::new(),
             },
         )];
         materialize_events(&db, &install_events, "device-b").unwrap();
@@ -369,6 +610,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::too_many_lines)] // Walks through each event in turn
     fn test_materialize_search_engine_events() {
         let db = StateDb::open_in_memory().unwrap();
 
@@ -380,6 +622,7 @@ mod tests {
                 id: "ddg".to_string(),
                 name: "DuckDuckGo".to_string(),
                 url: "https://duckduckgo.com/?q=%s".to_string(),
+                details: SearchEngineDetails::default(),
             },
         )];
         materialize_events(&db, &events, "device-b").unwrap();
@@ -396,6 +639,28 @@ mod tests {
         )];
         materialize_events(&db, &default_events, "device-b").unwrap();
 
+        // Update parameters
+        clock.increment("device-a");
+        let details = SearchEngineDetails {
+            method: Some("POST".to_string()),
+            ..Default::default()
+        };
+        let update_events = vec![EventEnvelope::new(
+            "device-a".to_string(),
+            clock.clone(),
+            Event::SearchEngineUpdated {
+                id: "ddg".to_string(),
+                name: "DuckDuckGo".to_string(),
+                url: "https://duckduckgo.com/".to_string(),
+                details: details.clone(),
+            },
+        )];
+        materialize_events(&db, &update_events, "device-b").unwrap();
+        let engines = db.get_search_engines().unwrap();
+        assert_eq!(engines[0].url, "https://duckduckgo.com/");
+        assert_eq!(engines[0].details, details);
+        assert!(engines[0].is_default);
+
         // Remove search engine
         clock.increment("device-a");
         let remove_events = vec![EventEnvelope::new(
@@ -455,6 +720,69 @@ mod tests {
         materialize_events(&db, &remove_events, "device-b").unwrap();
     }
 
+    #[test]
+    fn test_materialize_tab_sent_by_this_device() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        let sent = EventEnvelope::new(
+            "device-b".to_string(),
+            VectorClock::new(),
+            Event::TabSent {
+                to_device: "device-a".to_string(),
+                url: "https://example.com".to_string(),
+                title: None,
+            },
+        );
+        let sent_id = sent.id;
+        materialize_events(&db, &[sent], "device-b").unwrap();
+
+        let tabs = db.get_sent_tabs().unwrap();
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].id, sent_id.to_string());
+        assert_eq!(tabs[0].to_device, "device-a");
+        assert!(db.get_pending_tabs().unwrap().is_empty());
+
+        // The receiving device acknowledges the tab by the sending event's ID
+        let received = EventEnvelope::new(
+            "device-a".to_string(),
+            VectorClock::new(),
+            Event::TabReceived { event_id: sent_id },
+        );
+        materialize_events(&db, &[received], "device-b").unwrap();
+        let tabs = db.get_sent_tabs().unwrap();
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].status, crate::state::TabDelivery::Opened);
+    }
+
+    #[test]
+    fn test_materialize_records_device_sync() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        let events = vec![
+            EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::HandlerSet {
+                    protocol: "mailto".to_string(),
+                    handler: "https://mail.example.com/?to=%s".to_string(),
+                },
+            ),
+            EventEnvelope::new(
+                "device-b".to_string(),
+                VectorClock::new(),
+                Event::HandlerRemoved {
+                    protocol: "irc".to_string(),
+                },
+            ),
+        ];
+        materialize_events(&db, &events, "device-b").unwrap();
+
+        let devices = db.get_devices().unwrap();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].id, "device-a");
+        assert!(devices[0].last_sync.is_some());
+    }
+
     #[test]
     fn test_materialize_tab_sent_to_other_device() {
         let db = StateDb::open_in_memory().unwrap();
@@ -480,29 +808,46 @@ mod tests {
     fn test_materialize_tab_received() {
         let db = StateDb::open_in_memory().unwrap();
 
-        // First add a pending tab directly
-        db.add_pending_tab(
-            "tab-uuid",
-            "https://example.com",
-            Some("Example"),
-            "device-a",
-            "2024-01-01T00:00:00Z",
-        )
-        .unwrap();
-        assert_eq!(db.get_pending_tabs().unwrap().len(), 1);
-
-        // Then mark it received
-        let events = vec![EventEnvelope::new(
+        let sent = EventEnvelope::new(
             "device-a".to_string(),
             VectorClock::new(),
+            Event::TabSent {
+                to_device: "device-b".to_string(),
+                url: "https://example.com".to_string(),
+                title: Some("Example".to_string()),
+            },
+        );
+        materialize_events(&db, std::slice::from_ref(&sent), "device-b").unwrap();
+        let tabs = db.get_pending_tabs().unwrap();
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].id, sent.id.to_string());
+
+        // An acknowledgment for another tab leaves it pending
+        let other = EventEnvelope::new(
+            "device-b".to_string(),
+            VectorClock::new(),
             Event::TabReceived {
-                event_id: uuid::Uuid::parse_str("00000000-0000-0000-0000-000000000000").unwrap(),
+                event_id: uuid::Uuid::now_v7(),
             },
-        )];
-        materialize_events(&db, &events, "device-b").unwrap();
+        );
+        materialize_events(&db, &[other], "device-b").unwrap();
+        assert_eq!(db.get_pending_tabs().unwrap().len(), 1);
 
-        // Note: TabReceived removes by event_id converted to string
-        // Our test tab has "tab-uuid" so it won't match
+        // Acknowledged by the sending event's ID, from any device
+        let received = EventEnvelope::new(
+            "device-c".to_string(),
+            VectorClock::new(),
+            Event::TabReceived { event_id: sent.id },
+        );
+        materialize_events(&db, &[received], "device-b").unwrap();
+        assert!(db.get_pending_tabs().unwrap().is_empty());
+
+        // The tab stays received when its event is applied again
+        db.connection()
+            .execute_batch("DELETE FROM applied_events")
+            .unwrap();
+        materialize_events(&db, &[sent], "device-b").unwrap();
+        assert!(db.get_pending_tabs().unwrap().is_empty());
     }
 
     #[test]
@@ -584,4 +929,318 @@ mod tests {
         let applied = materialize_events(&db, &events, "device-b").unwrap();
         assert_eq!(applied, 0);
     }
+
+    #[test]
+    fn test_materialize_start_page() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        let event = |event| EventEnvelope::new("device-a".to_string(), VectorClock::new(), event);
+        let events = vec![
+            event(Event::HomepageSet {
+                urls: vec!["https://example.com".to_string()],
+            }),
+            event(Event::NewTabSettingSet {
+                key: "browser.newtabpage.activity-stream.topSitesRows".to_string(),
+                value: PrefValue::Int(2),
+            }),
+            // Not a new tab setting, so never applied
+            event(Event::NewTabSettingSet {
+                key: "network.proxy.type".to_string(),
+                value: PrefValue::Int(1),
+            }),
+            event(Event::TopSitesPinned {
+                sites: vec![Some(crate::events::PinnedSite {
+                    url: "https://example.org".to_string(),
+                    label: None,
+                })],
+            }),
+        ];
+
+        materialize_events(&db, &events, "device-b").unwrap();
+
+        let start_page = db.get_start_page().unwrap();
+        assert_eq!(start_page.homepage, vec!["https://example.com"]);
+        assert_eq!(start_page.settings.len(), 1);
+        assert_eq!(start_page.pinned.len(), 1);
+
+        let reset = vec![
+            event(Event::HomepageSet { urls: Vec::new() }),
+            event(Event::TopSitesPinned { sites: Vec::new() }),
+        ];
+        materialize_events(&db, &reset, "device-b").unwrap();
+
+        let start_page = db.get_start_page().unwrap();
+        assert!(start_page.homepage.is_empty());
+        assert!(start_page.pinned.is_empty());
+    }
+
+    #[test]
+    fn test_materialize_reading_list() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        let mut clock = VectorClock::new();
+        let added = EventEnvelope::new(
+            "device-a".to_string(),
+            clock.clone(),
+            Event::ReadingListAdded {
+                url: "https://example.com/article".to_string(),
+                title: Some("Article".to_string()),
+            },
+        );
+        materialize_events(&db, &[added], "device-b").unwrap();
+
+        let items = db.get_reading_list().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title.as_deref(), Some("Article"));
+
+        clock.increment("device-a");
+        let removed = EventEnvelope::new(
+            "device-a".to_string(),
+            clock,
+            Event::ReadingListRemoved {
+                url: "https://example.com/article".to_string(),
+            },
+        );
+        materialize_events(&db, &[removed], "device-b").unwrap();
+        assert!(db.get_reading_list().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_materialize_skips_paused_categories() {
+        let db = StateDb::open_in_memory().unwrap();
+
+        let events = vec![EventEnvelope::new(
+            "device-a".to_string(),
+            VectorClock::new(),
+            Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: PrefValue::Int(3),
+            },
+        )];
+
+        let applied = materialize_events_except(
+            &db,
+            &events,
+            "device-b",
+            &[SyncCategory::Prefs],
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        assert_eq!(applied, 0);
+        assert!(!db.is_event_applied(events[0].id).unwrap());
+
+        // Once resumed the held-back event is applied
+        let applied = materialize_events(&db, &events, "device-b").unwrap();
+        assert_eq!(applied, 1);
+    }
+
+    #[test]
+    fn test_materialize_skips_other_platforms() {
+        let db = StateDb::open_in_memory().unwrap();
+        let handler = |protocol: &str, handler: &str| {
+            let mut envelope = EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::HandlerSet {
+                    protocol: protocol.to_string(),
+                    handler: handler.to_string(),
+                },
+            );
+            envelope.platforms = vec!["plan9".to_string()];
+            envelope
+        };
+        let events = vec![
+            handler("mailto", "/usr/bin/thunderbird"),
+            handler("irc", "/usr/bin/hexchat"),
+        ];
+        let handler_translations = BTreeMap::from([(
+            "thunderbird".to_string(),
+            "/opt/thunderbird/thunderbird".to_string(),
+        )]);
+
+        let applied =
+            materialize_events_except(&db, &events, "device-b", &[], &handler_translations)
+                .unwrap();
+        assert_eq!(applied, 1);
+        let handlers: Vec<(String, String)> = db
+            .connection()
+            .prepare("SELECT protocol, handler FROM handlers")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            handlers,
+            vec![(
+                "mailto".to_string(),
+                "/opt/thunderbird/thunderbird".to_string()
+            )]
+        );
+        // Skipped for good rather than held back
+        assert!(db.is_event_applied(events[1].id).unwrap());
+    }
+
+    #[test]
+    fn test_materialize_leaves_unknown_events() {
+        let db = StateDb::open_in_memory().unwrap();
+        let events = vec![EventEnvelope::new(
+            "device-a".to_string(),
+            VectorClock::new(),
+            Event::Unknown(serde_json::json!({"type": "BookmarkAdded", "data": {}})),
+        )];
+
+        // Left for a version that knows the event to apply
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 0);
+        assert!(!db.is_event_applied(events[0].id).unwrap());
+    }
+
+    fn tab_sent(url: &str) -> EventEnvelope {
+        EventEnvelope::new(
+            "device-a".to_string(),
+            VectorClock::new(),
+            Event::TabSent {
+                to_device: "device-b".to_string(),
+                url: url.to_string(),
+                title: None,
+            },
+        )
+    }
+
+    #[test]
+    fn test_failed_apply_is_not_recorded() {
+        let db = StateDb::open_in_memory().unwrap();
+        let events = vec![
+            tab_sent("https://example.com/tab"),
+            EventEnvelope::new(
+                "device-a".to_string(),
+                VectorClock::new(),
+                Event::ReadingListAdded {
+                    url: "https://example.com/article".to_string(),
+                    title: None,
+                },
+            ),
+        ];
+
+        // Fails after the event is marked applied but before the tab is stored
+        db.connection()
+            .execute_batch("ALTER TABLE pending_tabs RENAME TO pending_tabs_gone")
+            .unwrap();
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 1);
+        assert!(!db.is_event_applied(events[0].id).unwrap());
+        assert!(db.is_event_applied(events[1].id).unwrap());
+
+        db.connection()
+            .execute_batch("ALTER TABLE pending_tabs_gone RENAME TO pending_tabs")
+            .unwrap();
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 1);
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 0);
+        assert_eq!(db.get_pending_tabs().unwrap().len(), 1);
+        assert_eq!(db.get_reading_list().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_device_record_rolls_back_apply() {
+        let db = StateDb::open_in_memory().unwrap();
+        let events = vec![tab_sent("https://example.com/tab")];
+
+        // Fails after the tab is stored, so the stored tab is rolled back too
+        db.connection()
+            .execute_batch("ALTER TABLE devices RENAME TO devices_gone")
+            .unwrap();
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 0);
+        assert!(!db.is_event_applied(events[0].id).unwrap());
+
+        db.connection()
+            .execute_batch("ALTER TABLE devices_gone RENAME TO devices")
+            .unwrap();
+        assert!(db.get_pending_tabs().unwrap().is_empty());
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 1);
+        assert_eq!(db.get_pending_tabs().unwrap().len(), 1);
+    }
+
+    /// Every row of every table the events write to
+    fn snapshot(db: &StateDb) -> Vec<String> {
+        let tables: Vec<String> = db
+            .connection()
+            .prepare(
+                "SELECT name FROM sqlite_master WHERE type = 'table'
+                 AND name NOT IN ('applied_events', 'devices') ORDER BY name",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+
+        let mut rows = Vec::new();
+        for table in tables {
+            let mut stmt = db
+                .connection()
+                .prepare(&format!("SELECT * FROM {table} ORDER BY 1"))
+                .unwrap();
+            let columns = stmt.column_count();
+            let table_rows = stmt
+                .query_map([], |row| {
+                    (0..columns)
+                        .map(|i| row.get::<_, rusqlite::types::Value>(i))
+                        .collect::<rusqlite::Result<Vec<_>>>()
+                })
+                .unwrap();
+            for row in table_rows {
+                rows.push(format!("{table}: {:?}", row.unwrap()));
+            }
+        }
+        rows
+    }
+
+    #[test]
+    fn test_reapply_is_idempotent() {
+        let db = StateDb::open_in_memory().unwrap();
+        let event = |event| EventEnvelope::new("device-a".to_string(), VectorClock::new(), event);
+        let events = vec![
+            tab_sent("https://example.com/tab"),
+            event(Event::ReadingListAdded {
+                url: "https://example.com/article".to_string(),
+                title: Some("Article".to_string()),
+            }),
+            event(Event::ExtensionAdded {
+                id: "ext1@test.com".to_string(),
+                name: "Test Extension".to_string(),
+                url: None,
+            }),
+            event(Event::ExtensionInstalled {
+                id: "ext2@test.com".to_string(),
+                name: "Installed Extension".to_string(),
+                version: "1.0.0".to_string(),
+                source: ExtensionSource::Local {
+                    original_path: "/path/to/ext.xpi".to_string(),
+                },
+                xpi_data: "base64data".to_string(),
+                chunks: Vec::new(),
+            }),
+            event(Event::HandlerSet {
+                protocol: "mailto".to_string(),
+                handler: "https://mail.example.com/?to=%s".to_string(),
+            }),
+        ];
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 5);
+        // Extensions are stamped with when they were first applied; set back
+        // so applying them again now would show
+        db.connection()
+            .execute_batch(
+                "UPDATE extensions SET added_at = '2020-01-01 00:00:00';
+                 UPDATE extension_xpi SET installed_at = '2020-01-01 00:00:00';",
+            )
+            .unwrap();
+        let before = snapshot(&db);
+        assert!(!before.is_empty());
+
+        // As if the journal were lost and every event applied again
+        db.connection()
+            .execute_batch("DELETE FROM applied_events")
+            .unwrap();
+        assert_eq!(materialize_events(&db, &events, "device-b").unwrap(), 5);
+        assert_eq!(snapshot(&db), before);
+    }
 }
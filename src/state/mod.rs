@@ -1,5 +1,8 @@
 mod db;
 mod materialize;
 
-pub use db::{PendingTab, StateDb};
-pub use materialize::materialize_events;
+pub use db::{
+    DEVICE_COLORS, DeviceRecord, DeviceReport, Divergence, PendingTab, PrefsSnapshot,
+    ReadingListItem, SentTab, StateDb, SyncSession, TabDelivery,
+};
+pub use materialize::{materialize_events, materialize_events_except};
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use std::any::Any;
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, warn};
+
+/// Wait before the first restart, doubling with each restart after it
+const RESTART_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/// A task that ran this long before failing is restarted right away, since
+/// it wasn't failing in a loop
+const HEALTHY_RUN: Duration = Duration::from_secs(5 * 60);
+
+/// Run a long-lived daemon task, restarting it with backoff when it panics
+/// or fails
+///
+/// `task` is called again for each restart, so it should build the task
+/// from state that outlives it: shared handles and channels rather than
+/// anything the failed run consumed. The task isn't restarted once it
+/// finishes on its own.
+pub fn supervise<F, Fut>(name: &'static str, mut task: F) -> JoinHandle<()>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut restarts = 0;
+        loop {
+            let started = Instant::now();
+            match tokio::spawn(task()).await {
+                Ok(Ok(())) => {
+                    debug!("{} finished", name);
+                    return;
+                }
+                Ok(Err(e)) => error!("{} failed: {:#}", name, e),
+                Err(e) if e.is_panic() => {
+                    error!("{} panicked: {}", name, panic_message(&*e.into_panic()));
+                }
+                Err(_) => return,
+            }
+
+            if started.elapsed() >= HEALTHY_RUN {
+                restarts = 0;
+            }
+            let delay = restart_delay(restarts);
+            restarts += 1;
+            warn!("Restarting {} in {}s", name, delay.as_secs());
+            tokio::time::sleep(delay).await;
+        }
+    })
+}
+
+fn restart_delay(restarts: u32) -> Duration {
+    RESTART_BACKOFF_BASE
+        .saturating_mul(2u32.saturating_pow(restarts))
+        .min(RESTART_BACKOFF_MAX)
+}
+
+/// The message a panic was raised with
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(no message)".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restart_delay() {
+        assert_eq!(restart_delay(0), Duration::from_secs(1));
+        assert_eq!(restart_delay(1), Duration::from_secs(2));
+        assert_eq!(restart_delay(4), Duration::from_secs(16));
+        assert_eq!(restart_delay(6), RESTART_BACKOFF_MAX);
+        assert_eq!(restart_delay(100), RESTART_BACKOFF_MAX);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload = std::panic::catch_unwind(|| panic!("swarm broke")).unwrap_err();
+        assert_eq!(panic_message(&*payload), "swarm broke");
+
+        let index = 7;
+        let payload =
+            std::panic::catch_unwind(|| panic!("index {} out of range", index)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "index 7 out of range");
+
+        let payload = std::panic::catch_unwind(|| std::panic::panic_any(42)).unwrap_err();
+        assert_eq!(panic_message(&*payload), "(no message)");
+    }
+}
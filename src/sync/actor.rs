@@ -0,0 +1,268 @@
+//! The sync engine as a task of its own
+//!
+//! Daemon subsystems hold an [`EngineHandle`] instead of a lock on the
+//! engine. Commands queue on a channel and the engine carries them out one at
+//! a time on its own thread, so its database and profile work never blocks
+//! the async runtime, and a subsystem waiting for an answer doesn't stop the
+//! others from queueing theirs.
+//!
+//! Profile scans, applying peers' events, and writing the profile travel in
+//! the bulk lane, so tab sends and requests someone is waiting on go first.
+
+use anyhow::{Result, anyhow};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use tokio::sync::oneshot;
+use tracing::error;
+
+use super::SyncEngine;
+use crate::events::Event;
+use crate::lanes::{self, Lane, LaneReceiver, LaneSender};
+use crate::net::EncryptedEvent;
+use crate::state::SentTab;
+
+/// How many commands can wait for the engine in each lane before senders
+/// wait too
+const QUEUE_DEPTH: usize = 64;
+
+type Call = Box<dyn FnOnce(&mut SyncEngine) + Send>;
+
+/// Commands for the engine task
+enum EngineCommand {
+    /// Scan the profile for local changes
+    Scan {
+        response_tx: oneshot::Sender<Result<Vec<Event>>>,
+    },
+    /// Apply event files received from a peer
+    ApplyRemote {
+        events: Vec<EncryptedEvent>,
+        response_tx: oneshot::Sender<Result<usize>>,
+    },
+    /// Queue a tab for another device
+    SendTab {
+        device: String,
+        url: String,
+        title: Option<String>,
+        response_tx: oneshot::Sender<Result<SentTab>>,
+    },
+    /// Write queued profile changes
+    Flush {
+        response_tx: oneshot::Sender<Result<Vec<String>>>,
+    },
+    /// Anything else, run against the engine
+    Call(Call),
+}
+
+/// Sends commands to the engine task, which stops once every handle is
+/// dropped
+#[derive(Clone)]
+pub struct EngineHandle {
+    command_tx: LaneSender<EngineCommand>,
+}
+
+impl EngineHandle {
+    /// Move the engine onto its own thread
+    ///
+    /// Called from within a runtime, commands can spawn tasks on it, as hooks
+    /// do.
+    pub fn spawn(engine: SyncEngine) -> Result<Self> {
+        let (command_tx, command_rx) = lanes::channel(QUEUE_DEPTH);
+        let runtime = tokio::runtime::Handle::try_current().ok();
+        std::thread::Builder::new()
+            .name("sync-engine".to_string())
+            .spawn(move || {
+                let _runtime = runtime.as_ref().map(tokio::runtime::Handle::enter);
+                run_engine(engine, command_rx);
+            })?;
+        Ok(Self { command_tx })
+    }
+
+    /// Scan the profile, returning the events written for its changes
+    pub async fn scan(&self) -> Result<Vec<Event>> {
+        self.request(Lane::Bulk, |response_tx| EngineCommand::Scan {
+            response_tx,
+        })
+        .await?
+    }
+
+    /// Apply event files from a peer, returning how many events were new
+    pub async fn apply_remote(&self, events: Vec<EncryptedEvent>) -> Result<usize> {
+        self.request(Lane::Bulk, |response_tx| EngineCommand::ApplyRemote {
+            events,
+            response_tx,
+        })
+        .await?
+    }
+
+    pub async fn send_tab(
+        &self,
+        device: String,
+        url: String,
+        title: Option<String>,
+    ) -> Result<SentTab> {
+        self.request(Lane::Interactive, |response_tx| EngineCommand::SendTab {
+            device,
+            url,
+            title,
+            response_tx,
+        })
+        .await?
+    }
+
+    /// Write queued profile changes, returning the files written
+    pub async fn flush(&self) -> Result<Vec<String>> {
+        self.request(Lane::Bulk, |response_tx| EngineCommand::Flush {
+            response_tx,
+        })
+        .await?
+    }
+
+    /// Run `f` on the engine and return what it returns
+    pub async fn call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SyncEngine) -> T + Send + 'static,
+    {
+        self.call_in(Lane::Interactive, f).await
+    }
+
+    /// [`call`](Self::call) in the given lane
+    pub async fn call_in<T, F>(&self, lane: Lane, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SyncEngine) -> T + Send + 'static,
+    {
+        self.request(lane, |response_tx| {
+            EngineCommand::Call(Box::new(move |engine| {
+                let _ = response_tx.send(f(engine));
+            }))
+        })
+        .await
+    }
+
+    /// Run `f` on the engine, for calls that can fail
+    pub async fn try_call<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SyncEngine) -> Result<T> + Send + 'static,
+    {
+        self.call(f).await?
+    }
+
+    /// [`try_call`](Self::try_call) in the given lane
+    pub async fn try_call_in<T, F>(&self, lane: Lane, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SyncEngine) -> Result<T> + Send + 'static,
+    {
+        self.call_in(lane, f).await?
+    }
+
+    /// How many commands had to wait for room in the engine's queue
+    pub fn overflows(&self) -> u64 {
+        self.command_tx.overflows()
+    }
+
+    async fn request<T>(
+        &self,
+        lane: Lane,
+        command: impl FnOnce(oneshot::Sender<T>) -> EngineCommand,
+    ) -> Result<T> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.command_tx
+            .send(lane, command(response_tx))
+            .await
+            .map_err(|_| anyhow!("The sync engine has stopped"))?;
+        response_rx
+            .await
+            .map_err(|_| anyhow!("The sync engine failed to answer"))
+    }
+}
+
+/// Carry out commands until every handle is dropped
+///
+/// A command that panics is dropped along with its response, so its caller
+/// sees an error and the engine goes on with the next one.
+fn run_engine(mut engine: SyncEngine, mut command_rx: LaneReceiver<EngineCommand>) {
+    while let Some(command) = command_rx.blocking_recv() {
+        if catch_unwind(AssertUnwindSafe(|| handle_command(&mut engine, command))).is_err() {
+            error!("Sync engine command panicked");
+        }
+    }
+}
+
+fn handle_command(engine: &mut SyncEngine, command: EngineCommand) {
+    match command {
+        EngineCommand::Scan { response_tx } => {
+            let _ = response_tx.send(engine.scan_profile());
+        }
+        EngineCommand::ApplyRemote {
+            events,
+            response_tx,
+        } => {
+            let _ = response_tx.send(engine.apply_remote_events(events));
+        }
+        EngineCommand::SendTab {
+            device,
+            url,
+            title,
+            response_tx,
+        } => {
+            let _ = response_tx.send(engine.send_tab(&device, &url, title.as_deref()));
+        }
+        EngineCommand::Flush { response_tx } => {
+            let _ = response_tx.send(engine.flush_write_queue());
+        }
+        EngineCommand::Call(f) => f(engine),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::crypto::KeyPair;
+    use crate::events::EventLog;
+    use crate::state::StateDb;
+
+    #[test]
+    fn test_engine_handle() {
+        let dir = tempfile::tempdir().unwrap();
+        let profile = dir.path().join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        std::fs::write(profile.join("prefs.js"), "").unwrap();
+        let mut config = Config::default();
+        config.paths.profile = Some(profile);
+        config.paths.sync_dir = dir.path().join("sync");
+        config.backups.dir = dir.path().join("backups");
+        let event_log = EventLog::new(
+            config.paths.sync_dir.clone(),
+            config.device.id.clone(),
+            KeyPair::generate(),
+        );
+        let engine =
+            SyncEngine::new(config, event_log, StateDb::open_in_memory().unwrap()).unwrap();
+        let device_id = engine.device_id().to_string();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let handle = EngineHandle::spawn(engine).unwrap();
+            let id = handle
+                .call(|engine| engine.device_id().to_string())
+                .await
+                .unwrap();
+            assert_eq!(id, device_id);
+
+            let sent = handle
+                .send_tab(device_id, "https://example.com".to_string(), None)
+                .await;
+            assert!(sent.is_err());
+
+            // A panicking command fails its caller, not the engine
+            let panicked = handle.call::<(), _>(|_| panic!("test")).await;
+            assert!(panicked.is_err());
+            assert!(handle.apply_remote(Vec::new()).await.is_ok());
+        });
+    }
+}
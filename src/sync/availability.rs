@@ -0,0 +1,150 @@
+use std::collections::BTreeSet;
+
+/// A synced extension, and whether each device in the matrix has it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtensionAvailability {
+    pub id: String,
+    pub name: String,
+    /// By device, in the order of the matrix's devices
+    pub installed: Vec<bool>,
+}
+
+impl ExtensionAvailability {
+    /// Whether a device in the matrix doesn't have it
+    pub fn is_missing(&self) -> bool {
+        self.installed.contains(&false)
+    }
+}
+
+/// Which devices in the pack have each synced extension installed, going by
+/// what the devices last reported, for `wolfpack extension list`
+#[derive(Debug, Default)]
+pub struct ExtensionMatrix {
+    /// Labels of the devices that have reported their extensions
+    pub devices: Vec<String>,
+    pub extensions: Vec<ExtensionAvailability>,
+}
+
+impl ExtensionMatrix {
+    /// Build the matrix from the synced extensions as `(id, name)` and the
+    /// labels and installed extensions of each device that reported them
+    pub fn new(extensions: &[(String, String)], reported: &[(String, BTreeSet<String>)]) -> Self {
+        let mut extensions: Vec<ExtensionAvailability> = extensions
+            .iter()
+            .map(|(id, name)| ExtensionAvailability {
+                id: id.clone(),
+                name: name.clone(),
+                installed: reported
+                    .iter()
+                    .map(|(_, installed)| installed.contains(id))
+                    .collect(),
+            })
+            .collect();
+        extensions.sort_by_key(|extension| extension.name.to_lowercase());
+        Self {
+            devices: reported.iter().map(|(label, _)| label.clone()).collect(),
+            extensions,
+        }
+    }
+
+    /// The matrix as a table, with only the extensions some device is
+    /// missing when `missing` is set
+    pub fn describe(&self, missing: bool) -> String {
+        let rows: Vec<&ExtensionAvailability> = self
+            .extensions
+            .iter()
+            .filter(|extension| !missing || extension.is_missing())
+            .collect();
+        if rows.is_empty() {
+            return if missing {
+                "Every device has every synced extension.".to_string()
+            } else {
+                "No synced extensions.".to_string()
+            };
+        }
+        if self.devices.is_empty() {
+            return "No device has reported its extensions yet.".to_string();
+        }
+
+        let widths: Vec<usize> = self
+            .devices
+            .iter()
+            .map(|label| label.chars().count().max(1))
+            .collect();
+        let mut header = format!("{:<40}", "Extension");
+        for (label, width) in self.devices.iter().zip(&widths) {
+            header.push_str(&format!("  {:^width$}", label, width = width));
+        }
+
+        let mut lines = vec![header.trim_end().to_string()];
+        for row in rows {
+            let mut line = format!("{:<40}", format!("{} ({})", row.name, row.id));
+            for (installed, width) in row.installed.iter().zip(&widths) {
+                let mark = if *installed { "✓" } else { "✗" };
+                line.push_str(&format!("  {:^width$}", mark, width = width));
+            }
+            lines.push(line.trim_end().to_string());
+        }
+        lines.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix() -> ExtensionMatrix {
+        let extensions = [
+            ("ublock@example.com", "uBlock"),
+            ("dark@example.com", "Dark Reader"),
+        ]
+        .map(|(id, name)| (id.to_string(), name.to_string()));
+        let installed = |ids: &[&str]| ids.iter().map(|id| id.to_string()).collect();
+        let devices = vec![
+            (
+                "laptop".to_string(),
+                installed(&["ublock@example.com", "dark@example.com"]),
+            ),
+            ("device-b".to_string(), installed(&["ublock@example.com"])),
+        ];
+        ExtensionMatrix::new(&extensions, &devices)
+    }
+
+    #[test]
+    fn test_matrix() {
+        let matrix = matrix();
+        assert_eq!(matrix.devices, vec!["laptop", "device-b"]);
+        let rows: Vec<(&str, &[bool])> = matrix
+            .extensions
+            .iter()
+            .map(|row| (row.id.as_str(), row.installed.as_slice()))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("dark@example.com", [true, false].as_slice()),
+                ("ublock@example.com", [true, true].as_slice()),
+            ]
+        );
+
+        let table = matrix.describe(false);
+        assert!(table.starts_with("Extension"));
+        assert!(table.lines().next().unwrap().contains("laptop  device-b"));
+        assert_eq!(table.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_missing_only() {
+        let matrix = matrix();
+        let table = matrix.describe(true);
+        assert_eq!(table.lines().count(), 2);
+        assert!(table.contains("Dark Reader"));
+        assert!(!table.contains("uBlock"));
+
+        let everywhere = ExtensionMatrix::new(&[], &[]);
+        assert_eq!(
+            everywhere.describe(true),
+            "Every device has every synced extension."
+        );
+    }
+}
@@ -1,7 +1,12 @@
 use std::collections::{HashMap, HashSet};
 
 use crate::events::{Event, PrefValue};
-use crate::profile::{Container, Extension, Handler};
+use crate::profile::{
+    Address, CertOverride, Container, Extension, Handler, Permission, SearchEngine, SiteAssignment,
+    StartPage,
+};
+
+use super::order::reposition;
 
 /// Diff extensions: compare current extensions with known IDs
 pub fn diff_extensions(current: &[Extension], previous: &[String]) -> Vec<Event> {
@@ -64,6 +69,45 @@ pub fn diff_containers(current: &[Container], known_ids: &[String]) -> Vec<Event
     events
 }
 
+/// Diff the order of containers: positions for the containers the profile
+/// lists out of the order known from their position keys
+pub fn diff_container_order(current: &[Container], known: &[(String, String)]) -> Vec<Event> {
+    let order: Vec<String> = current
+        .iter()
+        .map(|c| c.user_context_id.to_string())
+        .collect();
+    reposition(&order, known)
+        .into_iter()
+        .map(|(id, position)| Event::ContainerReordered { id, position })
+        .collect()
+}
+
+/// Diff container site assignments: compare current assignments with known ones
+pub fn diff_container_sites(current: &[SiteAssignment], known: &[SiteAssignment]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let current_hosts: HashSet<&str> = current.iter().map(|a| a.host.as_str()).collect();
+
+    for assignment in current {
+        if !known.contains(assignment) {
+            events.push(Event::ContainerSiteAssigned {
+                host: assignment.host.clone(),
+                container_id: assignment.container_id.clone(),
+                never_ask: assignment.never_ask,
+            });
+        }
+    }
+
+    for assignment in known {
+        if !current_hosts.contains(assignment.host.as_str()) {
+            events.push(Event::ContainerSiteUnassigned {
+                host: assignment.host.clone(),
+            });
+        }
+    }
+
+    events
+}
+
 /// Diff handlers: compare current handlers with known handlers (protocol -> handler)
 pub fn diff_handlers(current: &[Handler], known: &HashMap<String, String>) -> Vec<Event> {
     let mut events = Vec::new();
@@ -99,6 +143,61 @@ pub fn diff_handlers(current: &[Handler], known: &HashMap<String, String>) -> Ve
     events
 }
 
+/// Diff search engines: compare current engines with known engines
+pub fn diff_search_engines(current: &[SearchEngine], known: &[SearchEngine]) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    let known_by_id: HashMap<_, _> = known.iter().map(|e| (e.id.as_str(), e)).collect();
+    let current_ids: HashSet<_> = current.iter().map(|e| e.id.as_str()).collect();
+
+    // Added or changed engines
+    for engine in current {
+        match known_by_id.get(engine.id.as_str()) {
+            None => events.push(Event::SearchEngineAdded {
+                id: engine.id.clone(),
+                name: engine.name.clone(),
+                url: engine.url.clone(),
+                details: engine.details.clone(),
+            }),
+            Some(existing)
+                if existing.name != engine.name
+                    || existing.url != engine.url
+                    || existing.details != engine.details =>
+            {
+                events.push(Event::SearchEngineUpdated {
+                    id: engine.id.clone(),
+                    name: engine.name.clone(),
+                    url: engine.url.clone(),
+                    details: engine.details.clone(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    // Removed engines
+    for engine in known {
+        if !current_ids.contains(engine.id.as_str()) {
+            events.push(Event::SearchEngineRemoved {
+                id: engine.id.clone(),
+            });
+        }
+    }
+
+    // Default changed
+    let current_default = current.iter().find(|e| e.is_default);
+    let known_default = known.iter().find(|e| e.is_default);
+    if let Some(engine) = current_default
+        && known_default.is_none_or(|k| k.id != engine.id)
+    {
+        events.push(Event::SearchEngineDefault {
+            id: engine.id.clone(),
+        });
+    }
+
+    events
+}
+
 /// Diff prefs: compare current prefs with known prefs
 pub fn diff_prefs(
     current: &HashMap<String, PrefValue>,
@@ -133,6 +232,144 @@ pub fn diff_prefs(
     events
 }
 
+/// Diff site permissions: compare current permissions with known ones
+pub fn diff_permissions(current: &[Permission], known: &[Permission]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let known_map: HashMap<(&str, &str), u32> = known
+        .iter()
+        .map(|p| ((p.origin.as_str(), p.permission.as_str()), p.capability))
+        .collect();
+    let current_keys: HashSet<(&str, &str)> = current
+        .iter()
+        .map(|p| (p.origin.as_str(), p.permission.as_str()))
+        .collect();
+
+    for permission in current {
+        let key = (permission.origin.as_str(), permission.permission.as_str());
+        if known_map.get(&key) != Some(&permission.capability) {
+            events.push(Event::PermissionSet {
+                origin: permission.origin.clone(),
+                permission: permission.permission.clone(),
+                capability: permission.capability,
+            });
+        }
+    }
+
+    for permission in known {
+        let key = (permission.origin.as_str(), permission.permission.as_str());
+        if !current_keys.contains(&key) {
+            events.push(Event::PermissionRemoved {
+                origin: permission.origin.clone(),
+                permission: permission.permission.clone(),
+            });
+        }
+    }
+
+    events
+}
+
+/// Diff certificate overrides: compare current overrides with known ones
+pub fn diff_cert_overrides(current: &[CertOverride], known: &[CertOverride]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let current_hosts: HashSet<&str> = current.iter().map(|c| c.host.as_str()).collect();
+
+    for cert in current {
+        if !known.contains(cert) {
+            events.push(Event::CertOverrideSet {
+                host: cert.host.clone(),
+                algorithm: cert.algorithm.clone(),
+                fingerprint: cert.fingerprint.clone(),
+            });
+        }
+    }
+
+    for cert in known {
+        if !current_hosts.contains(cert.host.as_str()) {
+            events.push(Event::CertOverrideRemoved {
+                host: cert.host.clone(),
+            });
+        }
+    }
+
+    events
+}
+
+/// Diff addresses: new and removed addresses, and only the changed fields
+/// of edited ones
+pub fn diff_addresses(current: &[Address], known: &[Address]) -> Vec<Event> {
+    let mut events = Vec::new();
+    let known_by_guid: HashMap<&str, &Address> =
+        known.iter().map(|a| (a.guid.as_str(), a)).collect();
+    let current_guids: HashSet<&str> = current.iter().map(|a| a.guid.as_str()).collect();
+
+    for address in current {
+        let Some(existing) = known_by_guid.get(address.guid.as_str()) else {
+            events.push(Event::AddressAdded {
+                guid: address.guid.clone(),
+                fields: address.fields.clone(),
+            });
+            continue;
+        };
+
+        let mut changed = std::collections::BTreeMap::new();
+        for (name, value) in &address.fields {
+            if existing.fields.get(name) != Some(value) {
+                changed.insert(name.clone(), Some(value.clone()));
+            }
+        }
+        for name in existing.fields.keys() {
+            if !address.fields.contains_key(name) {
+                changed.insert(name.clone(), None);
+            }
+        }
+        if !changed.is_empty() {
+            events.push(Event::AddressUpdated {
+                guid: address.guid.clone(),
+                fields: changed,
+            });
+        }
+    }
+
+    for address in known {
+        if !current_guids.contains(address.guid.as_str()) {
+            events.push(Event::AddressRemoved {
+                guid: address.guid.clone(),
+            });
+        }
+    }
+
+    events
+}
+
+/// Diff start page: compare the profile's start page with the known one
+pub fn diff_start_page(current: &StartPage, known: &StartPage) -> Vec<Event> {
+    let mut events = Vec::new();
+
+    if current.homepage != known.homepage {
+        events.push(Event::HomepageSet {
+            urls: current.homepage.clone(),
+        });
+    }
+
+    events.extend(
+        diff_prefs(&current.settings, &known.settings)
+            .into_iter()
+            .filter_map(|event| match event {
+                Event::PrefSet { key, value } => Some(Event::NewTabSettingSet { key, value }),
+                Event::PrefRemoved { key } => Some(Event::NewTabSettingRemoved { key }),
+                _ => None,
+            }),
+    );
+
+    if current.pinned != known.pinned {
+        events.push(Event::TopSitesPinned {
+            sites: current.pinned.clone(),
+        });
+    }
+
+    events
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -142,6 +379,7 @@ mod tests {
             id: id.to_string(),
             name: name.to_string(),
             url: url.map(String::from),
+            version: None,
         }
     }
 
@@ -596,4 +834,222 @@ mod tests {
         let events = diff_prefs(&current, &known);
         assert!(events.is_empty());
     }
+
+    fn make_engine(id: &str, url: &str, is_default: bool) -> SearchEngine {
+        SearchEngine {
+            id: id.to_string(),
+            name: id.to_uppercase(),
+            url: url.to_string(),
+            is_default,
+            details: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_diff_search_engines_no_changes() {
+        let engines = vec![make_engine("ddg", "https://duckduckgo.com/", true)];
+        assert!(diff_search_engines(&engines, &engines).is_empty());
+    }
+
+    #[test]
+    fn test_diff_search_engines_added_and_removed() {
+        let current = vec![make_engine("sp", "https://startpage.com/", false)];
+        let known = vec![make_engine("ddg", "https://duckduckgo.com/", false)];
+
+        let events = diff_search_engines(&current, &known);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], Event::SearchEngineAdded { id, .. } if id == "sp"));
+        assert!(matches!(&events[1], Event::SearchEngineRemoved { id } if id == "ddg"));
+    }
+
+    #[test]
+    fn test_diff_search_engines_params_changed() {
+        let known = vec![make_engine("sp", "https://startpage.com/", false)];
+        let mut current = known.clone();
+        current[0].details.method = Some("POST".to_string());
+
+        let events = diff_search_engines(&current, &known);
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            Event::SearchEngineUpdated { id, details, .. } => {
+                assert_eq!(id, "sp");
+                assert_eq!(details.method.as_deref(), Some("POST"));
+            }
+            _ => panic!("Expected SearchEngineUpdated"),
+        }
+    }
+
+    #[test]
+    fn test_diff_search_engines_default_changed() {
+        let known = vec![
+            make_engine("ddg", "https://duckduckgo.com/", true),
+            make_engine("sp", "https://startpage.com/", false),
+        ];
+        let current = vec![
+            make_engine("ddg", "https://duckduckgo.com/", false),
+            make_engine("sp", "https://startpage.com/", true),
+        ];
+
+        let events = diff_search_engines(&current, &known);
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::SearchEngineDefault { id } if id == "sp"));
+    }
+
+    #[test]
+    fn test_diff_start_page() {
+        let mut known = StartPage {
+            homepage: vec!["https://example.com".to_string()],
+            ..Default::default()
+        };
+        known
+            .settings
+            .insert("browser.startup.page".to_string(), PrefValue::Int(1));
+        assert!(diff_start_page(&known, &known).is_empty());
+
+        let mut current = known.clone();
+        current.homepage = Vec::new();
+        current.settings.clear();
+        current.settings.insert(
+            "browser.newtabpage.enabled".to_string(),
+            PrefValue::Bool(false),
+        );
+        current.pinned = vec![Some(crate::events::PinnedSite {
+            url: "https://example.org".to_string(),
+            label: None,
+        })];
+
+        let events = diff_start_page(&current, &known);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], Event::HomepageSet { urls } if urls.is_empty()));
+        assert!(events.contains(&Event::NewTabSettingSet {
+            key: "browser.newtabpage.enabled".to_string(),
+            value: PrefValue::Bool(false),
+        }));
+        assert!(events.contains(&Event::NewTabSettingRemoved {
+            key: "browser.startup.page".to_string(),
+        }));
+        assert!(matches!(&events[3], Event::TopSitesPinned { sites } if sites.len() == 1));
+    }
+
+    fn make_address(guid: &str, fields: &[(&str, &str)]) -> Address {
+        Address {
+            guid: guid.to_string(),
+            fields: fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_addresses() {
+        let known = vec![
+            make_address("a", &[("given-name", "Ada"), ("tel", "123")]),
+            make_address("b", &[("given-name", "Bob")]),
+        ];
+        assert!(diff_addresses(&known, &known).is_empty());
+
+        let current = vec![
+            make_address("a", &[("given-name", "Ada"), ("email", "ada@example.com")]),
+            make_address("c", &[("given-name", "Cy")]),
+        ];
+
+        let events = diff_addresses(&current, &known);
+        assert_eq!(events.len(), 3);
+        match &events[0] {
+            Event::AddressUpdated { guid, fields } => {
+                assert_eq!(guid, "a");
+                // Unchanged fields aren't sent, so they can't overwrite other edits
+                assert_eq!(
+                    fields,
+                    &std::collections::BTreeMap::from([
+                        ("email".to_string(), Some("ada@example.com".to_string())),
+                        ("tel".to_string(), None),
+                    ])
+                );
+            }
+            _ => panic!("Expected AddressUpdated"),
+        }
+        assert!(matches!(&events[1], Event::AddressAdded { guid, .. } if guid == "c"));
+        assert!(matches!(&events[2], Event::AddressRemoved { guid } if guid == "b"));
+    }
+
+    #[test]
+    fn test_diff_permissions() {
+        let permission = |origin: &str, permission: &str, capability: u32| Permission {
+            origin: origin.to_string(),
+            permission: permission.to_string(),
+            capability,
+        };
+        let known = vec![
+            permission("https://a.test", "camera", 1),
+            permission("https://a.test", "geo", 1),
+        ];
+        assert!(diff_permissions(&known, &known).is_empty());
+
+        let current = vec![
+            permission("https://a.test", "camera", 2),
+            permission("https://b.test", "desktop-notification", 2),
+        ];
+        let events = diff_permissions(&current, &known);
+        assert_eq!(events.len(), 3);
+        assert!(
+            matches!(&events[0], Event::PermissionSet { capability: 2, permission, .. } if permission == "camera")
+        );
+        assert!(
+            matches!(&events[1], Event::PermissionSet { origin, .. } if origin == "https://b.test")
+        );
+        assert!(
+            matches!(&events[2], Event::PermissionRemoved { permission, .. } if permission == "geo")
+        );
+    }
+
+    #[test]
+    fn test_diff_cert_overrides() {
+        let cert = |host: &str, fingerprint: &str| CertOverride {
+            host: host.to_string(),
+            algorithm: "OID.2.16.840.1.101.3.4.2.1".to_string(),
+            fingerprint: fingerprint.to_string(),
+        };
+        let known = vec![cert("nas.lan:443:", "AA"), cert("git.lan:443:", "BB")];
+        assert!(diff_cert_overrides(&known, &known).is_empty());
+
+        // A renewed certificate replaces the old exception
+        let current = vec![cert("nas.lan:443:", "CC")];
+        let events = diff_cert_overrides(&current, &known);
+        assert_eq!(events.len(), 2);
+        assert!(
+            matches!(&events[0], Event::CertOverrideSet { fingerprint, .. } if fingerprint == "CC")
+        );
+        assert!(
+            matches!(&events[1], Event::CertOverrideRemoved { host } if host == "git.lan:443:")
+        );
+    }
+
+    #[test]
+    fn test_diff_container_sites() {
+        let assignment = |host: &str, container_id: &str| SiteAssignment {
+            host: host.to_string(),
+            container_id: container_id.to_string(),
+            never_ask: false,
+        };
+        let known = vec![
+            assignment("mail.example.com", "1"),
+            assignment("shop.example.com", "2"),
+        ];
+        assert!(diff_container_sites(&known, &known).is_empty());
+
+        // Moving a site to another container reassigns it
+        let current = vec![assignment("mail.example.com", "3")];
+        let events = diff_container_sites(&current, &known);
+        assert_eq!(events.len(), 2);
+        assert!(matches!(
+            &events[0],
+            Event::ContainerSiteAssigned { container_id, .. } if container_id == "3"
+        ));
+        assert!(matches!(
+            &events[1],
+            Event::ContainerSiteUnassigned { host } if host == "shop.example.com"
+        ));
+    }
 }
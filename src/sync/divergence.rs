@@ -0,0 +1,192 @@
+use anyhow::{Result, bail};
+use std::collections::{BTreeSet, HashMap};
+
+use crate::events::{Event, PrefValue, SyncCategory};
+use crate::state::{Divergence, PrefsSnapshot};
+
+/// Which change to keep for an item that diverged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// The profile's change, sent to the pack
+    Local,
+    /// The pack's value, written to the profile
+    Pack,
+}
+
+impl Keep {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "local" => Ok(Self::Local),
+            "pack" => Ok(Self::Pack),
+            other => bail!("Unknown side '{}', expected local or pack", other),
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Local => "local",
+            Self::Pack => "pack",
+        }
+    }
+}
+
+/// What comparing the profile's prefs to the snapshot from when the daemon
+/// last stopped found
+#[derive(Debug, Default)]
+pub struct PrefReconciliation {
+    /// Prefs only the profile changed, to send
+    pub changes: Vec<Event>,
+    /// Prefs the pack changed too, to hold until the user picks a side
+    pub divergences: Vec<Divergence>,
+}
+
+/// Sort the prefs changed in the profile since the snapshot into changes to
+/// send and divergences, going by whether the pack's value (`known`) moved
+/// from the snapshot's as well
+///
+/// `current` holds the profile's synced prefs; prefs the snapshot's
+/// whitelist didn't cover aren't changes, and a pref the profile no longer
+/// has is only removed while `is_synced` says it's still synced.
+pub fn reconcile_prefs(
+    current: &HashMap<String, PrefValue>,
+    snapshot: &PrefsSnapshot,
+    known: &HashMap<String, PrefValue>,
+    is_synced: impl Fn(&str) -> bool,
+) -> PrefReconciliation {
+    let was_whitelisted = crate::profile::PrefPatterns::new(&snapshot.whitelist);
+    let keys: BTreeSet<&String> = current
+        .keys()
+        .filter(|key| was_whitelisted.matches(key))
+        .chain(
+            snapshot
+                .prefs
+                .keys()
+                .filter(|key| !current.contains_key(*key) && is_synced(key)),
+        )
+        .collect();
+
+    let mut reconciliation = PrefReconciliation::default();
+    for key in keys {
+        let (local, before, pack) = (current.get(key), snapshot.prefs.get(key), known.get(key));
+        if local == before || local == pack {
+            continue;
+        }
+        let change = match local {
+            Some(value) => Event::PrefSet {
+                key: key.clone(),
+                value: value.clone(),
+            },
+            None => Event::PrefRemoved { key: key.clone() },
+        };
+        if pack == before {
+            reconciliation.changes.push(change);
+        } else {
+            reconciliation.divergences.push(Divergence {
+                category: SyncCategory::Prefs.as_str().to_string(),
+                item: key.clone(),
+                local: change,
+                pack: pack.map(ToString::to_string),
+                found_at: chrono::Utc::now().to_rfc3339(),
+            });
+        }
+    }
+    reconciliation
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prefs(prefs: &[(&str, i64)]) -> HashMap<String, PrefValue> {
+        prefs
+            .iter()
+            .map(|(key, value)| (key.to_string(), PrefValue::Int(*value)))
+            .collect()
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // One case per way the two sides differ
+    fn test_reconcile_prefs() {
+        let snapshot = PrefsSnapshot {
+            whitelist: vec!["browser.*".to_string()],
+            prefs: prefs(&[
+                ("browser.a", 1),
+                ("browser.b", 1),
+                ("browser.c", 1),
+                ("browser.d", 1),
+            ]),
+        };
+        // a changed here, b changed in the pack, c changed in both, d
+        // removed here and changed in the pack, e added here, and font.size
+        // whitelisted since
+        let current = prefs(&[
+            ("browser.a", 2),
+            ("browser.b", 1),
+            ("browser.c", 2),
+            ("browser.e", 1),
+            ("font.size", 12),
+        ]);
+        let known = prefs(&[
+            ("browser.a", 1),
+            ("browser.b", 5),
+            ("browser.c", 3),
+            ("browser.d", 4),
+        ]);
+
+        let reconciliation = reconcile_prefs(&current, &snapshot, &known, |_| true);
+        assert_eq!(
+            reconciliation.changes,
+            vec![
+                Event::PrefSet {
+                    key: "browser.a".to_string(),
+                    value: PrefValue::Int(2),
+                },
+                Event::PrefSet {
+                    key: "browser.e".to_string(),
+                    value: PrefValue::Int(1),
+                },
+            ]
+        );
+        let divergences: Vec<(&str, &Event, Option<&str>)> = reconciliation
+            .divergences
+            .iter()
+            .map(|d| (d.item.as_str(), &d.local, d.pack.as_deref()))
+            .collect();
+        assert_eq!(
+            divergences,
+            vec![
+                (
+                    "browser.c",
+                    &Event::PrefSet {
+                        key: "browser.c".to_string(),
+                        value: PrefValue::Int(2),
+                    },
+                    Some("3")
+                ),
+                (
+                    "browser.d",
+                    &Event::PrefRemoved {
+                        key: "browser.d".to_string(),
+                    },
+                    Some("4")
+                ),
+            ]
+        );
+
+        // A pref no longer synced isn't taken as removed
+        let reconciliation = reconcile_prefs(&current, &snapshot, &known, |key| key != "browser.d");
+        assert!(
+            reconciliation
+                .divergences
+                .iter()
+                .all(|d| d.item != "browser.d")
+        );
+    }
+
+    #[test]
+    fn test_keep_from_name() {
+        assert_eq!(Keep::from_name("local").unwrap(), Keep::Local);
+        assert_eq!(Keep::from_name("pack").unwrap(), Keep::Pack);
+        assert!(Keep::from_name("both").is_err());
+    }
+}
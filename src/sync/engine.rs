@@ -1,22 +1,97 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::path::PathBuf;
-use tracing::{debug, info, warn};
-
-use crate::config::Config;
-use crate::crypto::PublicKey;
-use crate::events::{Event, EventLog};
-use crate::net::EncryptedEvent;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use tracing::{debug, info, instrument, warn};
+
+use crate::config::{Config, PACK_SETTINGS};
+use crate::crypto::{
+    Cipher, KeyEpoch, PublicKey, device_proof, public_key_from_hex, public_key_to_hex,
+    verify_device_proof,
+};
+use crate::events::{
+    Event, EventCodec, EventEnvelope, EventFile, EventLog, ExtensionSource, HeldFiles,
+    SharedEventLog, SyncCategory, limits,
+};
+use crate::extensions::{
+    InstallResult, PendingInstall, install_all_to_profile, install_from_download, install_from_xpi,
+};
+use crate::net::{
+    EncryptedChunk, EncryptedEvent, EventCursor, MAX_EVENTS_PER_MESSAGE, MAX_HOPS, PAGE_BYTES,
+    check_route,
+};
 use crate::profile::{
-    Container, Handler, WriteQueue, find_profile, is_browser_running, read_containers,
-    read_extensions, read_handlers, read_prefs, write_containers, write_handlers, write_user_js,
+    ADDON_STARTUP_FILE, AUTO_DISABLE_SCOPES_PREF, Address, CONTAINER_SITES_FILE, CertOverride,
+    Container, DEFAULT_ENGINE_PREF, Handler, Permission, PrefPatterns, PrefVariables,
+    ProfileBackups, SearchEngine, SiteAssignment, WriteQueue, allow_profile_sideloads,
+    enable_sideloaded, find_profile, is_browser_running, profile_sideloads_allowed,
+    prune_policy_xpis, read_addresses, read_browser_version, read_cert_overrides, read_containers,
+    read_extensions, read_handlers, read_permissions, read_prefs, read_search_engines,
+    read_site_assignments, read_start_page, sideloads_awaiting_confirmation, stage_policy_xpi,
+    write_addresses, write_cert_overrides, write_containers, write_default_search_engine,
+    write_handlers, write_permissions, write_policies, write_site_assignments, write_user_js,
+};
+use crate::state::{
+    DEVICE_COLORS, DeviceRecord, Divergence, PendingTab, PrefsSnapshot, ReadingListItem, SentTab,
+    StateDb, SyncSession, TabDelivery, materialize_events, materialize_events_except,
+};
+
+use super::availability::ExtensionMatrix;
+use super::diff::{
+    diff_addresses, diff_cert_overrides, diff_container_order, diff_container_sites,
+    diff_containers, diff_extensions, diff_handlers, diff_permissions, diff_prefs,
+    diff_search_engines, diff_start_page,
 };
-use crate::state::{PendingTab, StateDb, materialize_events};
+use super::divergence::{Keep, reconcile_prefs};
+use super::export::StateExport;
+use super::manifest::ProfileManifest;
+use super::merge::{Conflict, find_conflicts};
+use super::pause::{PAUSE_ALL, PauseState};
+use super::replay::{ReplayPoint, ReplayReport};
+use super::report::{DeviceUsage, HealthReport, dir_growth};
+use super::search_default::{SearchDefaultCheck, Verification};
+use super::tab_url::normalize_tab_url;
 
-use super::diff::{diff_containers, diff_extensions, diff_handlers, diff_prefs};
+/// Wait before retrying direct delivery of a sent tab, doubling with each
+/// attempt up to `TAB_RETRY_MAX`
+const TAB_RETRY_BASE: chrono::TimeDelta = chrono::TimeDelta::seconds(30);
+const TAB_RETRY_MAX: chrono::TimeDelta = chrono::TimeDelta::hours(1);
+
+fn tab_retry_delay(attempts: u32) -> chrono::TimeDelta {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (TAB_RETRY_BASE * 2i32.pow(doublings)).min(TAB_RETRY_MAX)
+}
+
+/// Fold tabs sent again from the same device within `window` of the first
+/// into it, keeping the order they were sent in
+fn fold_duplicate_tabs(tabs: Vec<PendingTab>, window: chrono::TimeDelta) -> Vec<PendingTab> {
+    let sent_at = |tab: &PendingTab| chrono::DateTime::parse_from_rfc3339(&tab.sent_at).ok();
+    let mut folded: Vec<PendingTab> = Vec::with_capacity(tabs.len());
+    for tab in tabs {
+        let first = folded.iter_mut().find(|first| {
+            first.url == tab.url
+                && first.from_device == tab.from_device
+                && matches!(
+                    (sent_at(first), sent_at(&tab)),
+                    (Some(first), Some(again)) if again - first <= window
+                )
+        });
+        match first {
+            Some(first) => first.duplicates.push(tab.id),
+            None => folded.push(tab),
+        }
+    }
+    folded
+}
+
+/// Modification time of the profile's search.json
+fn search_json_modified(profile_path: &Path) -> Option<std::time::SystemTime> {
+    std::fs::metadata(profile_path.join("search.json.mozlz4"))
+        .and_then(|m| m.modified())
+        .ok()
+}
 
 /// Parse a preference value from database string representation
-fn parse_pref_value(value: &str, value_type: &str) -> Result<crate::events::PrefValue> {
+pub(super) fn parse_pref_value(value: &str, value_type: &str) -> Result<crate::events::PrefValue> {
     use crate::events::PrefValue;
 
     match value_type {
@@ -34,15 +109,30 @@ fn parse_pref_value(value: &str, value_type: &str) -> Result<crate::events::Pref
 
 pub struct SyncEngine {
     config: Config,
+    /// Where the config was loaded from, to save this device's name when
+    /// it is renamed
+    config_path: Option<PathBuf>,
     profile_path: PathBuf,
-    event_log: EventLog,
+    event_log: SharedEventLog,
     state_db: StateDb,
     write_queue: WriteQueue,
+    backups: ProfileBackups,
     known_devices: Vec<(String, PublicKey)>,
+    /// Observation mode: materialize and report, but never write the profile
+    observe: bool,
+    /// Default search engine written to the profile but not yet confirmed
+    search_default: Option<SearchDefaultCheck>,
+    /// Conflicts found in incoming events, until the daemon reports them
+    conflicts: Vec<Conflict>,
+    /// When the profile was last scanned, for `DeviceState` reports
+    last_scan: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl SyncEngine {
-    pub fn new(config: Config, event_log: EventLog, state_db: StateDb) -> Result<Self> {
+    pub fn new(config: Config, mut event_log: EventLog, state_db: StateDb) -> Result<Self> {
+        event_log.set_limits(config.limits.clone());
+        event_log.set_codec(EventCodec::from_name(&config.sync.event_codec)?);
+        event_log.set_key_epoch(KeyEpoch::from_number(config.sync.key_epoch)?);
         let profile_path = config
             .paths
             .profile
@@ -50,16 +140,47 @@ impl SyncEngine {
             .map(Ok)
             .unwrap_or_else(find_profile)?;
         let write_queue = WriteQueue::new(profile_path.clone());
+        let backups = config.profile_backups();
+        if config.certificates.overrides {
+            warn!(
+                "Certificate exception sync is enabled: certificates accepted on any paired \
+                 device will be trusted on this one"
+            );
+        }
         Ok(Self {
             config,
+            config_path: None,
             profile_path,
-            event_log,
+            event_log: SharedEventLog::new(event_log),
             state_db,
             write_queue,
+            backups,
             known_devices: Vec::new(),
+            observe: false,
+            search_default: None,
+            conflicts: Vec::new(),
+            last_scan: None,
         })
     }
 
+    /// Conflicts found in incoming events since the last call
+    pub fn take_conflicts(&mut self) -> Vec<Conflict> {
+        std::mem::take(&mut self.conflicts)
+    }
+
+    /// Enable or disable read-only observation mode
+    pub fn set_observe(&mut self, observe: bool) {
+        self.observe = observe;
+    }
+
+    pub fn is_observing(&self) -> bool {
+        self.observe
+    }
+
+    pub fn set_config_path(&mut self, path: PathBuf) {
+        self.config_path = Some(path);
+    }
+
     pub fn add_known_device(&mut self, device_id: String, public_key: PublicKey) {
         self.known_devices.push((device_id, public_key));
     }
@@ -68,6 +189,11 @@ impl SyncEngine {
         self.config.paths.sync_dir.clone()
     }
 
+    /// Key this device's local network advertisement is sealed with
+    pub fn announcement_key(&self) -> [u8; 32] {
+        self.event_log.lock().announcement_key(&self.known_devices)
+    }
+
     pub fn profile_path(&self) -> &PathBuf {
         &self.profile_path
     }
@@ -76,80 +202,306 @@ impl SyncEngine {
         &self.config.device.id
     }
 
+    /// Pause sync (or a single category), optionally for a limited time
+    pub fn pause(
+        &mut self,
+        category: Option<SyncCategory>,
+        duration: Option<chrono::TimeDelta>,
+    ) -> Result<PauseState> {
+        let until = duration.map(|d| chrono::Utc::now() + d);
+        let stored = until.map(|u| u.to_rfc3339());
+        let scope = category.map_or(PAUSE_ALL, |c| c.as_str());
+        self.state_db.set_sync_pause(scope, stored.as_deref())?;
+        info!("Sync paused ({})", scope);
+        Ok(PauseState { until })
+    }
+
+    /// Resume a paused category, or everything when no category is given
+    pub fn resume(&mut self, category: Option<SyncCategory>) -> Result<()> {
+        match category {
+            Some(category) => self.state_db.clear_sync_pause(category.as_str())?,
+            None => self.state_db.clear_sync_pauses()?,
+        }
+        info!(
+            "Sync resumed ({})",
+            category.map_or(PAUSE_ALL, |c| c.as_str())
+        );
+        Ok(())
+    }
+
+    /// Get the active pause of all sync, if any
+    pub fn pause_state(&self) -> Result<Option<PauseState>> {
+        let Some(until) = self.state_db.get_sync_pause(PAUSE_ALL)? else {
+            return Ok(None);
+        };
+        PauseState::from_stored(until.as_deref(), chrono::Utc::now())
+    }
+
+    /// Get active per-category pauses
+    pub fn category_pauses(&self) -> Result<Vec<(SyncCategory, PauseState)>> {
+        let now = chrono::Utc::now();
+        let mut pauses = Vec::new();
+        for (scope, until) in self.state_db.get_sync_pauses()? {
+            let Ok(category) = scope.parse::<SyncCategory>() else {
+                continue;
+            };
+            if let Some(state) = PauseState::from_stored(until.as_deref(), now)? {
+                pauses.push((category, state));
+            }
+        }
+        Ok(pauses)
+    }
+
+    /// Check whether sync is currently paused
+    pub fn is_paused(&self) -> bool {
+        match self.pause_state() {
+            Ok(state) => state.is_some(),
+            Err(e) => {
+                warn!("Failed to read pause state: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Categories currently paused, or disabled for the whole pack
+    pub fn paused_categories(&self) -> Vec<SyncCategory> {
+        let mut paused = match self.category_pauses() {
+            Ok(pauses) => pauses.into_iter().map(|(c, _)| c).collect(),
+            Err(e) => {
+                warn!("Failed to read category pauses: {}", e);
+                Vec::new()
+            }
+        };
+        paused.extend(self.config.disabled_categories());
+        paused
+    }
+
     /// Process incoming events from the sync directory
+    #[allow(clippy::cognitive_complexity)] // Span and logging around the apply
+    #[instrument(skip_all)]
     pub fn process_incoming(&mut self) -> Result<usize> {
-        let events = self.event_log.read_all_events(&self.known_devices)?;
-        let applied = materialize_events(&self.state_db, &events, &self.config.device.id)?;
+        let events = self.event_log.lock().read_all_events(&self.known_devices)?;
+        let paused = self.paused_categories();
+
+        let new = self.unapplied_remote_events(&events)?;
+        let applied = materialize_events_except(
+            &self.state_db,
+            &events,
+            &self.config.device.id,
+            &paused,
+            &self.config.handlers.translate,
+        )?;
+        self.record_conflicts(&new, &events);
 
         if applied > 0 {
             info!(count = applied, "Applied incoming events");
+            self.adopt_device_name()?;
+            self.adopt_pack_config()?;
             // Update vector clock from merged events
-            let (_, new_clock) = super::merge_events(&[], &events, self.event_log.clock());
-            self.event_log.set_clock(new_clock.clone());
+            let (_, new_clock) = super::merge_events(&[], &events, self.event_log.lock().clock());
+            self.event_log.lock().set_clock(new_clock.clone());
             self.state_db.save_vector_clock(&new_clock)?;
         }
 
         Ok(applied)
     }
 
+    /// Events from other devices that haven't been materialized yet
+    fn unapplied_remote_events(&self, events: &[EventEnvelope]) -> Result<Vec<EventEnvelope>> {
+        let mut new = Vec::new();
+        for envelope in events {
+            if envelope.device != self.config.device.id
+                && !self.state_db.is_event_applied(envelope.id)?
+            {
+                new.push(envelope.clone());
+            }
+        }
+        Ok(new)
+    }
+
+    fn record_conflicts(&mut self, new: &[EventEnvelope], events: &[EventEnvelope]) {
+        for conflict in find_conflicts(new, events) {
+            warn!(
+                category = conflict.category.as_str(),
+                entity = %conflict.entity,
+                devices = ?conflict.devices,
+                "Concurrent changes, keeping the latest"
+            );
+            self.conflicts.push(conflict);
+        }
+    }
+
     /// Scan profile for changes and generate outbound events
+    #[allow(clippy::cognitive_complexity)] // Span with an early return for pauses
+    #[instrument(skip_all)]
     pub fn scan_profile(&mut self) -> Result<Vec<Event>> {
         let mut events = Vec::new();
 
-        // Scan extensions
-        let current_extensions = read_extensions(&self.profile_path)?;
-        let known_extensions = self.state_db.get_extensions()?;
-        let known_ids: Vec<String> = known_extensions
-            .iter()
-            .map(|(id, _, _)| id.clone())
-            .collect();
+        if self.is_paused() {
+            debug!("Sync paused, skipping profile scan");
+            return Ok(events);
+        }
+
+        let paused = self.paused_categories();
+        for category in SyncCategory::ALL
+            .into_iter()
+            .filter(|c| !paused.contains(c))
+        {
+            events.extend(self.scan_category(category)?);
+        }
+        self.last_scan = Some(chrono::Utc::now());
+
+        Ok(events)
+    }
+
+    #[allow(clippy::too_many_lines)] // One arm per category
+    #[instrument(skip_all, fields(category = category.as_str()))]
+    fn scan_category(&mut self, category: SyncCategory) -> Result<Vec<Event>> {
+        match category {
+            SyncCategory::Extensions => {
+                let current_extensions = read_extensions(&self.profile_path)?;
+                let known_extensions = self.state_db.get_extensions()?;
+                let known_ids: Vec<String> = known_extensions
+                    .iter()
+                    .map(|(id, _, _)| id.clone())
+                    .collect();
 
-        let ext_events = diff_extensions(&current_extensions, &known_ids);
-        events.extend(ext_events);
+                Ok(diff_extensions(&current_extensions, &known_ids))
+            }
+            SyncCategory::Containers => {
+                let current_containers = read_containers(&self.profile_path)?;
+                let mut events = self.diff_containers_from_profile(&current_containers)?;
+                let known_order = self.state_db.get_container_positions()?;
+                events.extend(diff_container_order(&current_containers, &known_order));
 
-        // Scan containers
-        let current_containers = read_containers(&self.profile_path)?;
-        let container_events = self.diff_containers_from_profile(&current_containers)?;
-        events.extend(container_events);
+                let current_sites = read_site_assignments(&self.profile_path)?;
+                let known_sites = self.state_db.get_container_sites()?;
+                events.extend(diff_container_sites(&current_sites, &known_sites));
+                Ok(events)
+            }
+            SyncCategory::Handlers => {
+                let current_handlers = read_handlers(&self.profile_path)?;
+                self.diff_handlers_from_profile(&current_handlers)
+            }
+            // Scan prefs (if whitelist is configured)
+            SyncCategory::Prefs if !self.config.prefs.whitelist.is_empty() => {
+                let current_prefs = self.read_profile_prefs()?;
+                self.diff_prefs_from_profile(current_prefs)
+            }
+            SyncCategory::Search => self.scan_search_engines(),
+            SyncCategory::StartPage => {
+                let current = read_start_page(&self.profile_path)?;
+                let known = self.state_db.get_start_page()?;
+                Ok(diff_start_page(&current, &known))
+            }
+            SyncCategory::Permissions => {
+                let filter = self.config.permissions.site_filter();
+                let mut current = read_permissions(&self.profile_path)?;
+                let mut known = self.state_db.get_permissions()?;
+                // Excluded sites are neither sent nor treated as removed
+                current.retain(|p| filter.includes(&p.origin));
+                known.retain(|p| filter.includes(&p.origin));
+                Ok(diff_permissions(&current, &known))
+            }
+            SyncCategory::Certificates if self.config.certificates.overrides => {
+                let current = read_cert_overrides(&self.profile_path)?;
+                let known = self.state_db.get_cert_overrides()?;
+                Ok(diff_cert_overrides(&current, &known))
+            }
+            SyncCategory::Addresses if self.config.autofill.addresses => {
+                let current = read_addresses(&self.profile_path)?;
+                let known = self.state_db.get_addresses()?;
+                Ok(diff_addresses(&current, &known))
+            }
+            SyncCategory::Prefs
+            | SyncCategory::Certificates
+            | SyncCategory::Addresses
+            | SyncCategory::Tabs
+            | SyncCategory::ReadingList
+            | SyncCategory::Devices => Ok(Vec::new()),
+        }
+    }
 
-        // Scan handlers
-        let current_handlers = read_handlers(&self.profile_path)?;
-        let handler_events = self.diff_handlers_from_profile(&current_handlers)?;
-        events.extend(handler_events);
+    fn scan_search_engines(&mut self) -> Result<Vec<Event>> {
+        let current_engines = read_search_engines(&self.profile_path)?;
+        let known_engines = self.state_db.get_search_engines()?;
+        let mut events = diff_search_engines(&current_engines, &known_engines);
 
-        // Scan prefs (if whitelist is configured)
-        if !self.config.prefs.whitelist.is_empty() {
-            let current_prefs = read_prefs(&self.profile_path, &self.config.prefs.whitelist)?;
-            let pref_events = self.diff_prefs_from_profile(&current_prefs)?;
-            events.extend(pref_events);
+        if self.search_default_unconfirmed(&current_engines) {
+            // Don't sync the browser's old default back over the one we wrote
+            events.retain(|e| !matches!(e, Event::SearchEngineDefault { .. }));
         }
 
         Ok(events)
     }
 
+    /// Check a written default search engine against the profile, re-queueing
+    /// it if the browser replaced it. Returns true while it is unconfirmed.
+    #[allow(clippy::cognitive_complexity)] // Match arms with logging
+    fn search_default_unconfirmed(&mut self, current: &[SearchEngine]) -> bool {
+        let modified = search_json_modified(&self.profile_path);
+        let Some(check) = self.search_default.as_mut() else {
+            return false;
+        };
+
+        match check.verify(current, modified) {
+            Verification::Pending => true,
+            Verification::Adopted => {
+                info!(engine = %check.id, "Browser adopted default search engine");
+                self.search_default = None;
+                false
+            }
+            Verification::Retry => {
+                warn!(engine = %check.id, "Browser did not adopt default search engine, queuing it again");
+                self.write_queue.queue_search_default(check.id.clone());
+                true
+            }
+            Verification::GaveUp => {
+                warn!(engine = %check.id, "Browser keeps replacing the default search engine, giving up");
+                self.search_default = None;
+                false
+            }
+        }
+    }
+
     /// Write events to the sync directory
+    #[allow(clippy::cognitive_complexity)] // Span with logging
+    #[instrument(skip_all, fields(count = events.len()))]
     pub fn write_events(&mut self, events: Vec<Event>) -> Result<Option<PathBuf>> {
         if events.is_empty() {
             return Ok(None);
         }
 
-        let path = self.event_log.write_events(events, &self.known_devices)?;
+        let path = self
+            .event_log
+            .lock()
+            .write_events(events, &self.known_devices)?;
         info!(path = %path.display(), "Wrote events to sync directory");
         Ok(Some(path))
     }
 
     /// Apply materialized state to the profile
+    #[allow(clippy::cognitive_complexity)] // Span with early returns for holds
+    #[instrument(skip_all)]
     pub fn apply_to_profile(&mut self) -> Result<Vec<String>> {
-        let browser_running = is_browser_running(&self.profile_path);
+        if self.is_paused() {
+            debug!("Sync paused, skipping profile writes");
+            return Ok(Vec::new());
+        }
+
+        if self.observe {
+            self.report_profile_diff()?;
+            return Ok(Vec::new());
+        }
 
-        if browser_running {
-            warn!("Browser is running, queuing writes for later");
+        if let Some(reason) = self.profile_write_hold() {
+            info!("{}, queuing profile writes", reason);
             self.queue_profile_writes()?;
             return Ok(Vec::new());
         }
 
         // Flush any queued writes first
-        let mut applied = self.write_queue.flush()?;
+        let mut applied = self.flush_queue()?;
 
         // Then apply current state
         let profile_applied = self.write_profile_state()?;
@@ -158,60 +510,442 @@ impl SyncEngine {
         Ok(applied)
     }
 
+    /// Why profile writes must be queued instead of applied right now
+    fn profile_write_hold(&self) -> Option<&'static str> {
+        if self.config.sync.require_approval {
+            Some("Awaiting approval (wolfpack queue list)")
+        } else if is_browser_running(&self.profile_path) {
+            Some("Browser is running")
+        } else {
+            None
+        }
+    }
+
     /// Flush queued writes (call when browser closes)
     pub fn flush_write_queue(&mut self) -> Result<Vec<String>> {
-        self.write_queue.flush()
+        if self.observe || self.config.sync.require_approval {
+            return Ok(Vec::new());
+        }
+        self.flush_queue()
+    }
+
+    fn flush_queue(&mut self) -> Result<Vec<String>> {
+        let applied = self.write_queue.flush(&self.backups)?;
+        let mut wrote_xpi = false;
+        for id in applied
+            .iter()
+            .filter_map(|f| f.strip_prefix("extensions/")?.strip_suffix(".xpi"))
+        {
+            self.state_db.record_extension_written(id)?;
+            wrote_xpi = true;
+        }
+        if wrote_xpi {
+            self.allow_sideloads()?;
+        }
+        if applied.iter().any(|f| f == "search.json.mozlz4")
+            && let Some(check) = self.search_default.as_mut()
+        {
+            check.mark_written(search_json_modified(&self.profile_path));
+        }
+        Ok(applied)
+    }
+
+    /// Whether queued writes wait for explicit approval
+    pub fn requires_approval(&self) -> bool {
+        self.config.sync.require_approval
+    }
+
+    /// Describe pending profile writes as (target file, diff lines)
+    pub fn describe_write_queue(&self) -> Result<Vec<(String, Vec<String>)>> {
+        self.write_queue.describe()
+    }
+
+    /// Apply pending writes now (explicit user approval)
+    pub fn apply_write_queue(&mut self) -> Result<Vec<String>> {
+        if self.observe {
+            anyhow::bail!("Daemon is in observe mode; profile writes are disabled");
+        }
+        if is_browser_running(&self.profile_path) {
+            anyhow::bail!("Browser is running; close it before applying queued writes");
+        }
+        let files = self.flush_queue()?;
+        // Approving the queue approves enabling the synced extensions the
+        // browser left awaiting confirmation, which isn't done on close
+        let enabled = self.enable_sideloaded_extensions()?;
+        if !enabled.is_empty() {
+            info!(
+                "Enabled synced extensions awaiting confirmation: {:?}",
+                enabled
+            );
+        }
+        Ok(files)
+    }
+
+    /// Drop pending writes without applying them
+    pub fn discard_write_queue(&mut self) -> usize {
+        self.write_queue.discard()
+    }
+
+    /// Describe the changes applying materialized state would make to the profile
+    #[allow(clippy::too_many_lines, clippy::cognitive_complexity)] // One block per profile file
+    pub fn preview_profile_writes(&self) -> Result<Vec<(String, Event)>> {
+        let paused = self.paused_categories();
+        let mut changes = Vec::new();
+
+        if !paused.contains(&SyncCategory::Containers) {
+            let profile_ids: Vec<String> = read_containers(&self.profile_path)?
+                .iter()
+                .map(|c| c.user_context_id.to_string())
+                .collect();
+            let target = self.get_materialized_containers()?;
+            for event in diff_containers(&target, &profile_ids) {
+                changes.push(("containers.json".to_string(), event));
+            }
+        }
+
+        if let Some(target) = self.container_sites_to_write(&paused)? {
+            let current = read_site_assignments(&self.profile_path)?;
+            for event in diff_container_sites(&target, &current) {
+                changes.push((CONTAINER_SITES_FILE.to_string(), event));
+            }
+        }
+
+        if !paused.contains(&SyncCategory::Handlers) {
+            let profile_handlers: HashMap<String, String> = read_handlers(&self.profile_path)?
+                .into_iter()
+                .map(|h| (h.protocol, h.handler))
+                .collect();
+            let target = self.get_materialized_handlers()?;
+            for event in diff_handlers(&target, &profile_handlers) {
+                changes.push(("handlers.json".to_string(), event));
+            }
+        }
+
+        if let Some((target, current)) = self.permissions_to_write(&paused)? {
+            for event in diff_permissions(&target, &current) {
+                changes.push(("permissions.sqlite".to_string(), event));
+            }
+        }
+
+        if let Some(target) = self.cert_overrides_to_write(&paused)? {
+            let current = read_cert_overrides(&self.profile_path)?;
+            for event in diff_cert_overrides(&target, &current) {
+                changes.push(("cert_override.txt".to_string(), event));
+            }
+        }
+
+        if let Some(target) = self.addresses_to_write(&paused)? {
+            let current = read_addresses(&self.profile_path)?;
+            for event in diff_addresses(&target, &current) {
+                changes.push(("autofill-profiles.json".to_string(), event));
+            }
+        }
+
+        if let Some(id) = self.search_default_to_write(&paused)? {
+            changes.push((
+                "search.json.mozlz4".to_string(),
+                Event::SearchEngineDefault { id },
+            ));
+        }
+
+        if !paused.contains(&SyncCategory::Prefs) {
+            let target = self.get_profile_prefs(&paused)?;
+            let keys: Vec<String> = target.keys().cloned().collect();
+            let profile_prefs = read_prefs(&self.profile_path, &keys)?;
+            for event in diff_prefs(&target, &profile_prefs) {
+                changes.push(("user.js".to_string(), event));
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn report_profile_diff(&self) -> Result<()> {
+        for (file, event) in self.preview_profile_writes()? {
+            info!("Observe mode: would update {}: {}", file, event.describe());
+        }
+        Ok(())
     }
 
     fn queue_profile_writes(&mut self) -> Result<()> {
+        let paused = self.paused_categories();
+
         // Queue containers
         let containers = self.get_materialized_containers()?;
-        if !containers.is_empty() {
+        if !containers.is_empty() && !paused.contains(&SyncCategory::Containers) {
             self.write_queue.queue_containers(containers);
         }
+        if let Some(sites) = self.container_sites_to_write(&paused)? {
+            self.write_queue.queue_container_sites(sites);
+        }
 
         // Queue handlers
         let handlers = self.get_materialized_handlers()?;
-        if !handlers.is_empty() {
+        if !handlers.is_empty() && !paused.contains(&SyncCategory::Handlers) {
             self.write_queue.queue_handlers(handlers);
         }
 
         // Queue prefs
-        let prefs = self.get_materialized_prefs()?;
-        if !prefs.is_empty() {
+        let prefs = self.get_profile_prefs(&paused)?;
+        if !prefs.is_empty() && !paused.contains(&SyncCategory::Prefs) {
             self.write_queue.queue_prefs(prefs);
         }
 
+        // Queue site permissions
+        if let Some((permissions, _)) = self.permissions_to_write(&paused)? {
+            self.write_queue
+                .queue_permissions(permissions, self.config.permissions.site_filter());
+        }
+
+        // Queue certificate exceptions
+        if let Some(overrides) = self.cert_overrides_to_write(&paused)? {
+            self.write_queue.queue_cert_overrides(overrides);
+        }
+
+        // Queue addresses
+        if let Some(addresses) = self.addresses_to_write(&paused)? {
+            self.write_queue.queue_addresses(addresses);
+        }
+
+        // Queue default search engine
+        if let Some(id) = self.search_default_to_write(&paused)? {
+            self.write_queue.queue_search_default(id.clone());
+            self.search_default = Some(SearchDefaultCheck::queued(id));
+        }
+
         Ok(())
     }
 
-    fn write_profile_state(&self) -> Result<Vec<String>> {
+    #[allow(clippy::too_many_lines)] // One block per profile file
+    fn write_profile_state(&mut self) -> Result<Vec<String>> {
         let mut written = Vec::new();
+        let paused = self.paused_categories();
 
         let containers = self.get_materialized_containers()?;
-        if !containers.is_empty() {
+        if !containers.is_empty() && !paused.contains(&SyncCategory::Containers) {
+            self.backups.backup(&self.profile_path, "containers.json")?;
             write_containers(&self.profile_path, &containers)?;
             written.push("containers.json".to_string());
         }
 
+        if let Some(sites) = self.container_sites_to_write(&paused)? {
+            self.backups
+                .backup(&self.profile_path, CONTAINER_SITES_FILE)?;
+            write_site_assignments(&self.profile_path, &sites)?;
+            written.push(CONTAINER_SITES_FILE.to_string());
+        }
+
         let handlers = self.get_materialized_handlers()?;
-        if !handlers.is_empty() {
+        if !handlers.is_empty() && !paused.contains(&SyncCategory::Handlers) {
+            self.backups.backup(&self.profile_path, "handlers.json")?;
             write_handlers(&self.profile_path, &handlers)?;
             written.push("handlers.json".to_string());
         }
 
-        let prefs = self.get_materialized_prefs()?;
-        if !prefs.is_empty() {
+        let prefs = self.get_profile_prefs(&paused)?;
+        if !prefs.is_empty() && !paused.contains(&SyncCategory::Prefs) {
+            self.backups.backup(&self.profile_path, "user.js")?;
             write_user_js(&self.profile_path, &prefs)?;
             written.push("user.js".to_string());
         }
 
+        if let Some((permissions, _)) = self.permissions_to_write(&paused)? {
+            self.backups
+                .backup(&self.profile_path, "permissions.sqlite")?;
+            write_permissions(
+                &self.profile_path,
+                &permissions,
+                &self.config.permissions.site_filter(),
+            )?;
+            written.push("permissions.sqlite".to_string());
+        }
+
+        if let Some(overrides) = self.cert_overrides_to_write(&paused)? {
+            self.trust_cert_overrides(&overrides)?;
+            written.push("cert_override.txt".to_string());
+        }
+
+        if let Some(addresses) = self.addresses_to_write(&paused)? {
+            self.backups
+                .backup(&self.profile_path, "autofill-profiles.json")?;
+            write_addresses(&self.profile_path, &addresses)?;
+            written.push("autofill-profiles.json".to_string());
+        }
+
+        if let Some(id) = self.search_default_to_write(&paused)? {
+            self.backups
+                .backup(&self.profile_path, "search.json.mozlz4")?;
+            if write_default_search_engine(&self.profile_path, &id)? {
+                let mut check = SearchDefaultCheck::queued(id);
+                check.mark_written(search_json_modified(&self.profile_path));
+                self.search_default = Some(check);
+                written.push("search.json.mozlz4".to_string());
+            }
+        }
+
         Ok(written)
     }
 
+    /// Synced permissions for included sites and the profile's current ones,
+    /// if they differ
+    ///
+    /// permissions.sqlite is created by the browser on first run; until
+    /// then there is nothing to write into.
+    fn permissions_to_write(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<Option<(Vec<Permission>, Vec<Permission>)>> {
+        if paused.contains(&SyncCategory::Permissions)
+            || !self.profile_path.join("permissions.sqlite").exists()
+        {
+            return Ok(None);
+        }
+        let filter = self.config.permissions.site_filter();
+        let mut target = self.state_db.get_permissions()?;
+        let mut current = read_permissions(&self.profile_path)?;
+        target.retain(|p| filter.includes(&p.origin));
+        current.retain(|p| filter.includes(&p.origin));
+        if target.is_empty() || target == current {
+            return Ok(None);
+        }
+        Ok(Some((target, current)))
+    }
+
+    /// Synced container site assignments, if the Multi-Account Containers
+    /// extension keeps storage in this profile and they differ from it
+    fn container_sites_to_write(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<Option<Vec<SiteAssignment>>> {
+        if paused.contains(&SyncCategory::Containers)
+            || !self.profile_path.join(CONTAINER_SITES_FILE).exists()
+        {
+            return Ok(None);
+        }
+        let target = self.state_db.get_container_sites()?;
+        if target.is_empty() || target == read_site_assignments(&self.profile_path)? {
+            return Ok(None);
+        }
+        Ok(Some(target))
+    }
+
+    /// Write synced certificate exceptions, warning about each newly trusted one
+    fn trust_cert_overrides(&self, overrides: &[CertOverride]) -> Result<()> {
+        let current = read_cert_overrides(&self.profile_path)?;
+        for cert in overrides.iter().filter(|c| !current.contains(c)) {
+            warn!(
+                "Trusting certificate {} for {} from another device",
+                cert.fingerprint, cert.host
+            );
+        }
+        self.backups
+            .backup(&self.profile_path, "cert_override.txt")?;
+        write_cert_overrides(&self.profile_path, overrides)
+    }
+
+    /// Synced certificate exceptions, if their sync is enabled and they
+    /// differ from the profile
+    fn cert_overrides_to_write(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<Option<Vec<CertOverride>>> {
+        if !self.config.certificates.overrides || paused.contains(&SyncCategory::Certificates) {
+            return Ok(None);
+        }
+        let target = self.state_db.get_cert_overrides()?;
+        if target.is_empty() || target == read_cert_overrides(&self.profile_path)? {
+            return Ok(None);
+        }
+        Ok(Some(target))
+    }
+
+    /// Synced addresses, if address sync is enabled and they differ from the profile
+    fn addresses_to_write(&self, paused: &[SyncCategory]) -> Result<Option<Vec<Address>>> {
+        if !self.config.autofill.addresses || paused.contains(&SyncCategory::Addresses) {
+            return Ok(None);
+        }
+        let target = self.state_db.get_addresses()?;
+        if target.is_empty() || target == read_addresses(&self.profile_path)? {
+            return Ok(None);
+        }
+        Ok(Some(target))
+    }
+
+    /// Synced default search engine, unless search sync is paused
+    fn get_materialized_search_default(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<Option<SearchEngine>> {
+        if paused.contains(&SyncCategory::Search) {
+            return Ok(None);
+        }
+        Ok(self
+            .state_db
+            .get_search_engines()?
+            .into_iter()
+            .find(|e| e.is_default))
+    }
+
+    /// Sync ID of the default search engine, if the profile doesn't use it yet
+    fn search_default_to_write(&self, paused: &[SyncCategory]) -> Result<Option<String>> {
+        let Some(target) = self.get_materialized_search_default(paused)? else {
+            return Ok(None);
+        };
+        let current = read_search_engines(&self.profile_path)?;
+        if current.iter().any(|e| e.is_default && e.id == target.id) {
+            return Ok(None);
+        }
+        // The browser can only default to engines it has
+        if !current.iter().any(|e| e.id == target.id) {
+            debug!(engine = %target.id, "Default search engine not in profile yet");
+            return Ok(None);
+        }
+        Ok(Some(target.id))
+    }
+
+    /// Prefs to write to user.js, none when they're managed through
+    /// policies.json instead
+    fn get_profile_prefs(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<std::collections::HashMap<String, crate::events::PrefValue>> {
+        if self.config.policies.enabled {
+            return Ok(HashMap::new());
+        }
+        self.synced_prefs(paused)
+    }
+
+    /// Materialized prefs plus the start page prefs and the legacy default
+    /// search engine pref, so the profile's prefs agree with search.json,
+    /// leaving out this device's local-only prefs
+    fn synced_prefs(
+        &self,
+        paused: &[SyncCategory],
+    ) -> Result<std::collections::HashMap<String, crate::events::PrefValue>> {
+        let mut prefs = self.get_materialized_prefs()?;
+        self.pref_variables().expand_all(&mut prefs);
+        if !paused.contains(&SyncCategory::StartPage) {
+            prefs.extend(self.state_db.get_start_page()?.to_prefs());
+        }
+        if let Some(engine) = self.get_materialized_search_default(paused)? {
+            prefs.insert(
+                DEFAULT_ENGINE_PREF.to_string(),
+                crate::events::PrefValue::String(engine.name),
+            );
+        }
+        // Held prefs keep the profile's value until the user picks a side
+        let held = self.held_prefs()?;
+        prefs.retain(|key, _| !self.config.prefs.is_local_only(key) && !held.contains(key));
+        Ok(prefs)
+    }
+
     fn get_materialized_containers(&self) -> Result<Vec<Container>> {
         let conn = self.state_db.connection();
-        let mut stmt = conn.prepare("SELECT id, name, color, icon FROM containers")?;
+        // In the synced order
+        let mut stmt = conn.prepare(
+            "SELECT id, name, color, icon FROM containers
+             ORDER BY position = '', position, CAST(id AS INTEGER), id",
+        )?;
         let rows = stmt.query_map([], |row| {
             Ok(Container {
                 user_context_id: row.get::<_, String>(0)?.parse().unwrap_or(0),
@@ -308,25 +1042,155 @@ impl SyncEngine {
 
     fn diff_prefs_from_profile(
         &self,
-        current: &HashMap<String, crate::events::PrefValue>,
+        mut current: HashMap<String, crate::events::PrefValue>,
     ) -> Result<Vec<Event>> {
-        let known = self.query_prefs()?;
-        Ok(diff_prefs(current, &known))
+        // Compared as written to the profile, so a pref with variables
+        // isn't sent back expanded
+        let mut known = self.query_prefs()?;
+        // Local-only prefs aren't scanned, so they'd look removed
+        known.retain(|key, _| !self.config.prefs.is_local_only(key));
+        self.pref_variables().expand_all(&mut known);
+        // Held prefs wait for the user to pick a side
+        let held = self.held_prefs()?;
+        current.retain(|key, _| !held.contains(key));
+        known.retain(|key, _| !held.contains(key));
+        Ok(diff_prefs(&current, &known))
+    }
+
+    /// The whitelisted prefs the profile has, leaving out local-only ones
+    fn read_profile_prefs(&self) -> Result<HashMap<String, crate::events::PrefValue>> {
+        let mut prefs = read_prefs(&self.profile_path, &self.config.prefs.whitelist)?;
+        prefs.retain(|key, _| !self.config.prefs.is_local_only(key));
+        Ok(prefs)
+    }
+
+    /// Whether prefs are being synced at all
+    fn syncs_prefs(&self) -> bool {
+        !self.config.prefs.whitelist.is_empty()
+            && !self.is_paused()
+            && !self.paused_categories().contains(&SyncCategory::Prefs)
+    }
+
+    /// Remember the profile's synced prefs, for `reconcile_offline_prefs`
+    /// to tell what changed while the daemon was stopped
+    pub fn save_prefs_snapshot(&self) -> Result<()> {
+        if !self.syncs_prefs() {
+            return Ok(());
+        }
+        self.state_db.set_prefs_snapshot(&PrefsSnapshot {
+            whitelist: self.config.prefs.whitelist.clone(),
+            prefs: self.read_profile_prefs()?,
+        })
+    }
+
+    /// Send the prefs changed in the profile while the daemon was stopped,
+    /// before any incoming event is applied, returning how many were sent
+    /// and how many are held as divergences
+    ///
+    /// Only prefs that differ from the snapshot taken when the daemon last
+    /// stopped count as changed, so one another device changed meanwhile
+    /// isn't reverted to the profile's stale value, and one whitelisted
+    /// since isn't taken for a change. A pref another device changed too is
+    /// held, neither sent nor written to the profile, until the user picks
+    /// which change to keep with `resolve_divergence`. Without a snapshot
+    /// there's nothing to compare to, and the profile scans as usual.
+    pub fn reconcile_offline_prefs(&mut self) -> Result<(usize, usize)> {
+        if !self.syncs_prefs() {
+            return Ok((0, 0));
+        }
+        let Some(snapshot) = self.state_db.get_prefs_snapshot()? else {
+            self.save_prefs_snapshot()?;
+            return Ok((0, 0));
+        };
+        let current = self.read_profile_prefs()?;
+        let mut known = self.query_prefs()?;
+        self.pref_variables().expand_all(&mut known);
+
+        let is_whitelisted = PrefPatterns::new(&self.config.prefs.whitelist);
+        let reconciliation = reconcile_prefs(&current, &snapshot, &known, |key| {
+            is_whitelisted.matches(key) && !self.config.prefs.is_local_only(key)
+        });
+        for divergence in &reconciliation.divergences {
+            self.state_db.add_divergence(divergence)?;
+        }
+
+        let counts = (
+            reconciliation.changes.len(),
+            reconciliation.divergences.len(),
+        );
+        if !reconciliation.changes.is_empty() {
+            self.write_and_materialize(reconciliation.changes)?;
+        }
+        self.save_prefs_snapshot()?;
+        Ok(counts)
+    }
+
+    /// Items held because the profile and the pack both changed them while
+    /// the daemon was stopped
+    pub fn divergences(&self) -> Result<Vec<Divergence>> {
+        self.state_db.get_divergences()
+    }
+
+    /// Keep one side of a divergence: the profile's change is sent to the
+    /// pack, or the pack's value is written to the profile with the next
+    /// profile write
+    pub fn resolve_divergence(
+        &mut self,
+        category: &str,
+        item: &str,
+        keep: Keep,
+    ) -> Result<Divergence> {
+        let Some(divergence) = self
+            .divergences()?
+            .into_iter()
+            .find(|d| d.category == category && d.item == item)
+        else {
+            anyhow::bail!("No divergence for {} {}", category, item);
+        };
+        if keep == Keep::Local {
+            self.write_and_materialize(vec![divergence.local.clone()])?;
+        }
+        self.state_db.remove_divergence(category, item)?;
+        Ok(divergence)
+    }
+
+    /// Prefs held as divergences, left out of scans and profile writes
+    fn held_prefs(&self) -> Result<BTreeSet<String>> {
+        Ok(self
+            .divergences()?
+            .into_iter()
+            .filter(|d| d.category == SyncCategory::Prefs.as_str())
+            .map(|d| d.item)
+            .collect())
+    }
+
+    /// This device's values for variables in synced prefs
+    fn pref_variables(&self) -> PrefVariables {
+        PrefVariables::for_device(&self.config.device.name)
     }
 
     /// Full sync cycle: process incoming, scan profile, write outbound
+    #[allow(clippy::cognitive_complexity)] // Span over each sync phase
+    #[instrument(skip_all)]
     pub fn sync(&mut self) -> Result<SyncResult> {
+        if self.is_paused() {
+            return Ok(SyncResult::default());
+        }
+
         debug!("Starting sync cycle");
 
         let incoming = self.process_incoming()?;
-        let events = self.scan_profile()?;
-        let outbound = events.len();
-        let path = self.write_events(events)?;
+        let scanned = self.scan_profile()?;
+        let drift = self.manifest_drift()?;
+        let outbound = scanned.len() + drift.len();
+        let path = self.write_cycle_events(scanned, drift)?;
         let mut applied = self.apply_to_profile()?;
 
         // Handle extension installation/removal
-        let installed_extensions = self.install_pending_extensions()?;
-        let removed_extensions = self.remove_uninstalled_extensions()?;
+        let (installed_extensions, removed_extensions) = self.sync_extensions()?;
+        if self.write_policies()? {
+            applied.push("policies.json".to_string());
+        }
 
         for ext_id in installed_extensions {
             applied.push(format!("extensions/{}.xpi", ext_id));
@@ -343,186 +1207,3178 @@ impl SyncEngine {
         })
     }
 
-    /// Send a tab to another device
-    pub fn send_tab(&mut self, to_device: &str, url: &str, title: Option<&str>) -> Result<PathBuf> {
+    /// Send a tab to another device, given by ID or name
+    ///
+    /// The tab's ID is the ID of its `TabSent` event. It is tracked as
+    /// pending until the device acknowledges it.
+    pub fn send_tab(&mut self, to_device: &str, url: &str, title: Option<&str>) -> Result<SentTab> {
+        let to_device = self.resolve_device(to_device)?;
+        if to_device == self.config.device.id {
+            anyhow::bail!("Can't send a tab to this device");
+        }
+        let url = normalize_tab_url(url)?;
+
         let event = Event::TabSent {
-            to_device: to_device.to_string(),
-            url: url.to_string(),
+            to_device: to_device.clone(),
+            url: url.clone(),
             title: title.map(String::from),
         };
 
-        self.write_events(vec![event])?
-            .ok_or_else(|| anyhow::anyhow!("Failed to write tab event"))
+        let (path, envelopes) = self
+            .event_log
+            .lock()
+            .write_envelopes(vec![event], &self.known_devices)?;
+        info!(path = %path.display(), "Wrote events to sync directory");
+
+        let envelope = &envelopes[0];
+        let tab = SentTab {
+            id: envelope.id.to_string(),
+            to_device,
+            url,
+            title: title.map(String::from),
+            sent_at: envelope.timestamp.to_rfc3339(),
+            status: TabDelivery::Pending,
+            attempts: 0,
+            next_attempt: None,
+        };
+        self.state_db
+            .add_sent_tab(&tab.id, &tab.to_device, &tab.url, title, &tab.sent_at)?;
+        Ok(tab)
+    }
+
+    /// Log a finished sync round with a peer
+    pub fn record_sync_session(&self, session: &SyncSession) -> Result<()> {
+        self.state_db.add_sync_session(session)
+    }
+
+    /// The most recent sync sessions, newest first
+    pub fn sync_sessions(&self, limit: u32) -> Result<Vec<SyncSession>> {
+        self.state_db.get_sync_sessions(limit)
+    }
+
+    /// When each device last finished a sync session caught up
+    pub fn last_converged(&self) -> Result<BTreeMap<String, String>> {
+        self.state_db.get_last_converged()
+    }
+
+    /// Tabs this device sent, with their delivery state
+    pub fn get_sent_tabs(&self) -> Result<Vec<SentTab>> {
+        self.state_db.get_sent_tabs()
+    }
+
+    /// Pending tabs due for another direct delivery attempt
+    pub fn tabs_to_deliver(&self) -> Result<Vec<SentTab>> {
+        let now = chrono::Utc::now();
+        Ok(self
+            .state_db
+            .get_sent_tabs()?
+            .into_iter()
+            .filter(|tab| tab.status == TabDelivery::Pending)
+            .filter(|tab| {
+                tab.next_attempt
+                    .as_deref()
+                    .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+                    .is_none_or(|t| t <= now)
+            })
+            .collect())
+    }
+
+    /// Count a direct delivery attempt, backing off before the next one
+    pub fn record_tab_attempt(&self, tab: &SentTab) -> Result<()> {
+        let next = chrono::Utc::now() + tab_retry_delay(tab.attempts + 1);
+        self.state_db
+            .record_tab_attempt(&tab.id, &next.to_rfc3339())
+    }
+
+    /// Record that the receiving device acknowledged a tab
+    pub fn mark_tab_delivered(&self, tab_id: &str) -> Result<bool> {
+        self.state_db.mark_tab_delivered(tab_id)
+    }
+
+    /// Synced extensions as (ID, name, URL)
+    pub fn get_extensions(&self) -> Result<Vec<(String, String, Option<String>)>> {
+        self.state_db.get_extensions()
+    }
+
+    /// Materialized state in its exported form
+    pub fn export_state(&self) -> Result<StateExport> {
+        StateExport::from_db(&self.state_db, &self.config.device.id)
+    }
+
+    /// Import exported state as this device's own changes, so that it
+    /// syncs to the rest of the pack
+    ///
+    /// Returns the number of events written; entries already matching
+    /// the materialized state are skipped.
+    pub fn import_state(&mut self, export: &StateExport) -> Result<usize> {
+        let events = export.changes_from(&self.export_state()?);
+        self.write_and_materialize(events)
+    }
+
+    /// Write events for where the state has drifted from the profile
+    /// manifest, if one is configured
+    ///
+    /// Returns the number of events written. Paused categories are left
+    /// to drift until they're resumed.
+    pub fn reconcile_manifest(&mut self) -> Result<usize> {
+        let events = self.manifest_drift()?;
+        self.write_and_materialize(events)
+    }
+
+    /// Events that would bring the state back in line with the profile
+    /// manifest, leaving out paused categories
+    #[allow(clippy::cognitive_complexity)] // Span with per-category checks
+    #[instrument(skip_all)]
+    fn manifest_drift(&mut self) -> Result<Vec<Event>> {
+        let Some(path) = self.config.paths.manifest.clone() else {
+            return Ok(Vec::new());
+        };
+
+        let manifest = ProfileManifest::load(&path)?;
+        let paused = self.paused_categories();
+        let events: Vec<Event> = manifest
+            .drift(&self.export_state()?)
+            .into_iter()
+            .filter(|event| !paused.contains(&event.category()))
+            .collect();
+
+        for event in &events {
+            info!(change = %event.describe(), "Reconciling toward profile manifest");
+        }
+        Ok(events)
+    }
+
+    /// Write everything a sync cycle changed to one event file, with one
+    /// clock increment, rather than a file for each kind of change
+    ///
+    /// Manifest drift is applied to the state right away, as
+    /// [`SyncEngine::reconcile_manifest`] does, so the profile is written
+    /// with it this cycle. Profile changes are applied on the next pass
+    /// over the event log.
+    fn write_cycle_events(
+        &mut self,
+        scanned: Vec<Event>,
+        drift: Vec<Event>,
+    ) -> Result<Option<PathBuf>> {
+        let scanned_count = scanned.len();
+        let events: Vec<Event> = scanned.into_iter().chain(drift).collect();
+        if events.is_empty() {
+            return Ok(None);
+        }
+
+        let (path, envelopes) = self
+            .event_log
+            .lock()
+            .write_envelopes(events, &self.known_devices)?;
+        info!(path = %path.display(), count = envelopes.len(), "Wrote events to sync directory");
+        materialize_events(
+            &self.state_db,
+            &envelopes[scanned_count..],
+            &self.config.device.id,
+        )?;
+        Ok(Some(path))
+    }
+
+    /// Write this device's own changes and apply them to the state right
+    /// away, rather than on the next pass over the event log
+    fn write_and_materialize(&mut self, events: Vec<Event>) -> Result<usize> {
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let (path, envelopes) = self
+            .event_log
+            .lock()
+            .write_envelopes(events, &self.known_devices)?;
+        info!(path = %path.display(), "Wrote events to sync directory");
+
+        materialize_events(&self.state_db, &envelopes, &self.config.device.id)
     }
 
-    /// Get pending tabs for this device
+    /// Get pending tabs for this device, with the same URL sent again by
+    /// the same device within `sync.tab_dedup_minutes` shown once
     pub fn get_pending_tabs(&self) -> Result<Vec<PendingTab>> {
-        self.state_db.get_pending_tabs()
+        let tabs = self.state_db.get_pending_tabs()?;
+        match self.config.sync.tab_dedup_minutes {
+            0 => Ok(tabs),
+            minutes => Ok(fold_duplicate_tabs(
+                tabs,
+                chrono::TimeDelta::minutes(minutes as i64),
+            )),
+        }
     }
 
-    /// Mark a tab as received (acknowledged)
+    /// Mark a tab as received (acknowledged), along with the duplicates
+    /// shown as it
     pub fn acknowledge_tab(&mut self, tab_id: &str) -> Result<PathBuf> {
-        let event_id = uuid::Uuid::parse_str(tab_id)?;
-        let event = Event::TabReceived { event_id };
+        let duplicates = self
+            .get_pending_tabs()?
+            .into_iter()
+            .find(|tab| tab.id == tab_id)
+            .map(|tab| tab.duplicates)
+            .unwrap_or_default();
 
-        self.state_db.remove_pending_tab(tab_id)?;
-        self.write_events(vec![event])?
+        let now = chrono::Utc::now().to_rfc3339();
+        let mut events = Vec::new();
+        for id in std::iter::once(tab_id).chain(duplicates.iter().map(String::as_str)) {
+            let event_id = uuid::Uuid::parse_str(id)?;
+            self.state_db.receive_pending_tab(id, &now)?;
+            events.push(Event::TabReceived { event_id });
+        }
+        self.write_events(events)?
             .ok_or_else(|| anyhow::anyhow!("Failed to write tab acknowledgment"))
     }
 
-    /// Open a URL in the default browser
-    pub fn open_tab(&self, url: &str) -> Result<()> {
-        #[cfg(target_os = "linux")]
-        {
-            std::process::Command::new("xdg-open")
-                .arg(url)
-                .spawn()
-                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+    /// Display name of a device, falling back to its ID
+    pub fn device_name(&self, device_id: &str) -> String {
+        if device_id == self.config.device.id {
+            return self.config.device.name.clone();
+        }
+        match self.state_db.get_device(device_id) {
+            Ok(Some(device)) => device.name.unwrap_or_else(|| device_id.to_string()),
+            Ok(None) => device_id.to_string(),
+            Err(e) => {
+                warn!("Failed to look up device {}: {}", device_id, e);
+                device_id.to_string()
+            }
         }
+    }
 
-        #[cfg(target_os = "macos")]
-        {
-            std::process::Command::new("open")
-                .arg(url)
-                .spawn()
-                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+    /// Device name prefixed with its emoji label, for listings
+    pub fn device_label(&self, device_id: &str) -> String {
+        let name = self.device_name(device_id);
+        match self.state_db.get_device(device_id) {
+            Ok(Some(DeviceRecord {
+                emoji: Some(emoji), ..
+            })) => format!("{} {}", emoji, name),
+            _ => name,
+        }
+    }
+
+    /// Known devices, including this one, with their labels and presence
+    pub fn devices(&self) -> Result<Vec<DeviceRecord>> {
+        let this_device = &self.config.device;
+        let (mut devices, others): (Vec<_>, Vec<_>) = self
+            .state_db
+            .get_devices()?
+            .into_iter()
+            .partition(|d| d.id == this_device.id);
+
+        if devices.is_empty() {
+            devices.push(DeviceRecord {
+                id: this_device.id.clone(),
+                ..DeviceRecord::default()
+            });
         }
+        devices[0].name = Some(this_device.name.clone());
+        devices.extend(others);
+        Ok(devices)
+    }
 
-        #[cfg(target_os = "windows")]
+    /// Label a device, given by ID or current name, with an emoji and a
+    /// color on every device; passing neither clears its label
+    pub fn label_device(
+        &mut self,
+        device: &str,
+        emoji: Option<&str>,
+        color: Option<&str>,
+    ) -> Result<String> {
+        if let Some(emoji) = emoji
+            && (emoji.is_empty()
+                || emoji.chars().count() > 8
+                || emoji.contains(char::is_whitespace))
         {
-            std::process::Command::new("cmd")
-                .args(["/C", "start", "", url])
-                .spawn()
-                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+            anyhow::bail!(
+                "Label should be a single emoji or short symbol: {:?}",
+                emoji
+            );
+        }
+        if let Some(color) = color
+            && !DEVICE_COLORS.contains(&color)
+        {
+            anyhow::bail!(
+                "Unknown color '{}' (expected one of: {})",
+                color,
+                DEVICE_COLORS.join(", ")
+            );
         }
 
-        Ok(())
+        let device_id = self.resolve_device(device)?;
+        self.state_db.set_device_label(&device_id, emoji, color)?;
+        self.write_events(vec![Event::DeviceLabeled {
+            device_id: device_id.clone(),
+            emoji: emoji.map(String::from),
+            color: color.map(String::from),
+        }])?;
+
+        Ok(device_id)
     }
 
-    /// Get the current vector clock
-    pub fn get_vector_clock(&self) -> HashMap<String, u64> {
-        self.event_log.clock().to_hashmap()
+    /// Rename a device, given by ID or current name, on every device
+    ///
+    /// Returns the ID of the renamed device.
+    pub fn rename_device(&mut self, device: &str, name: &str) -> Result<String> {
+        let name = name.trim();
+        if name.is_empty() {
+            anyhow::bail!("Device name can't be empty");
+        }
+
+        let device_id = self.resolve_device(device)?;
+        self.state_db.rename_device(&device_id, name)?;
+        self.write_events(vec![Event::DeviceRenamed {
+            device_id: device_id.clone(),
+            name: name.to_string(),
+        }])?;
+        self.adopt_device_name()?;
+
+        Ok(device_id)
     }
 
-    /// Get events since the given clock (for P2P sync)
-    pub fn get_events_since(
-        &self,
-        _remote_clock: &HashMap<String, u64>,
-    ) -> Result<Vec<EncryptedEvent>> {
-        // TODO: Implement proper event filtering based on clock comparison
-        // For now, return empty - events will be re-sent on demand
-        Ok(Vec::new())
+    fn resolve_device(&self, device: &str) -> Result<String> {
+        if device == self.config.device.id || device == self.config.device.name {
+            return Ok(self.config.device.id.clone());
+        }
+
+        // Paired devices may not have been seen yet
+        let devices = self.state_db.get_devices()?;
+        let mut known = devices
+            .iter()
+            .map(|d| &d.id)
+            .chain(self.known_devices.iter().map(|(id, _)| id));
+        if let Some(found) = known.find(|id| *id == device) {
+            return Ok(found.clone());
+        }
+        let named: Vec<_> = devices
+            .iter()
+            .filter(|d| d.name.as_deref() == Some(device))
+            .collect();
+        match named.as_slice() {
+            [found] => Ok(found.id.clone()),
+            [] => anyhow::bail!("Unknown device: {}", device),
+            _ => anyhow::bail!("Several devices are named {}, use the device ID", device),
+        }
     }
 
-    /// Apply events received from a remote peer
-    pub fn apply_remote_events(&mut self, events: Vec<EncryptedEvent>) -> Result<usize> {
-        if events.is_empty() {
-            return Ok(0);
+    /// Take on a name this device was given with `DeviceRenamed`, saving
+    /// it so it is announced to peers after a restart
+    fn adopt_device_name(&mut self) -> Result<()> {
+        let Some(name) = self
+            .state_db
+            .get_device(&self.config.device.id)?
+            .and_then(|device| device.name)
+        else {
+            return Ok(());
+        };
+        if name == self.config.device.name {
+            return Ok(());
+        }
+
+        info!("This device was renamed to {}", name);
+        if let Some(path) = &self.config_path {
+            // Reload rather than saving our copy, which may carry
+            // command-line overrides
+            let mut saved = Config::load(path)?;
+            saved.device.name = name.clone();
+            saved.save(path)?;
+        }
+        self.config.device.name = name;
+        Ok(())
+    }
+
+    /// Publish `net.onion_address` in this device's record so other devices
+    /// can dial it through Tor, or withdraw it once it's unset
+    ///
+    /// Returns whether anything changed.
+    pub fn publish_onion_address(&mut self) -> Result<bool> {
+        let onion = self.config.net.onion_address.clone();
+        if let Some(onion) = &onion {
+            crate::net::onion_multiaddr(onion)?;
+        }
+
+        let device_id = self.config.device.id.clone();
+        let published = self
+            .state_db
+            .get_device(&device_id)?
+            .and_then(|device| device.onion);
+        if published == onion {
+            return Ok(false);
         }
 
-        // TODO: Decrypt and apply events
-        // For now, just count them
-        info!("Would apply {} remote events", events.len());
-        Ok(events.len())
+        self.state_db
+            .set_device_onion(&device_id, onion.as_deref())?;
+        self.write_events(vec![Event::DeviceOnionSet { device_id, onion }])?;
+        Ok(true)
     }
 
-    /// Receive a tab from another device (via P2P)
-    pub fn receive_tab(&mut self, url: &str, title: Option<&str>, from_device: &str) -> Result<()> {
-        let tab_id = uuid::Uuid::now_v7().to_string();
-        let now = chrono::Utc::now().to_rfc3339();
+    /// Let the pack know which extensions are installed in this device's
+    /// profile, when they've changed since it last did
+    ///
+    /// Returns whether anything changed.
+    pub fn publish_installed_extensions(&mut self) -> Result<bool> {
+        if self.is_paused() || self.paused_categories().contains(&SyncCategory::Extensions) {
+            return Ok(false);
+        }
+        let installed: BTreeSet<String> = read_extensions(&self.profile_path)?
+            .into_iter()
+            .map(|extension| extension.id)
+            .collect();
+
+        let device_id = self.config.device.id.clone();
+        let reported = self.state_db.get_device_extensions()?.remove(&device_id);
+        if reported.as_ref() == Some(&installed) {
+            return Ok(false);
+        }
+
+        let extensions: Vec<String> = installed.into_iter().collect();
         self.state_db
-            .add_pending_tab(&tab_id, url, title, from_device, &now)?;
-        info!("Received tab from {}: {}", from_device, url);
+            .set_device_extensions(&device_id, &extensions)?;
+        self.write_events(vec![Event::DeviceExtensionsSet {
+            device_id,
+            extensions,
+        }])?;
+        Ok(true)
+    }
+
+    /// Install an extension from a local XPI file for the whole pack,
+    /// recording `source` as where it came from if it was downloaded
+    ///
+    /// Like an extension installed on another device, it's written to the
+    /// profile with the next profile write.
+    pub fn install_extension(
+        &mut self,
+        xpi_path: &Path,
+        source: Option<ExtensionSource>,
+    ) -> Result<InstallResult> {
+        let result = match source {
+            Some(source) => install_from_download(xpi_path, source),
+            None => install_from_xpi(xpi_path),
+        }
+        .with_context(|| format!("Failed to load {}", xpi_path.display()))?;
+        self.write_and_materialize(vec![Event::ExtensionInstalled {
+            id: result.id.clone(),
+            name: result.name.clone(),
+            version: result.version.clone(),
+            source: result.source.clone(),
+            xpi_data: result.xpi_data.clone(),
+            chunks: Vec::new(),
+        }])?;
+        info!(id = %result.id, version = %result.version, "Installed extension");
+        Ok(result)
+    }
+
+    /// Uninstall a synced extension from the whole pack, removing its XPI
+    /// from this profile
+    pub fn uninstall_extension(&mut self, id: &str) -> Result<()> {
+        if !self
+            .state_db
+            .get_extensions()?
+            .iter()
+            .any(|(known, _, _)| known == id)
+        {
+            anyhow::bail!("Extension {} not found in sync database", id);
+        }
+
+        self.write_and_materialize(vec![Event::ExtensionUninstalled { id: id.to_string() }])?;
+
+        let file = format!("extensions/{}.xpi", id);
+        let xpi_path = self.profile_path.join(&file);
+        if xpi_path.exists() {
+            self.backups.backup(&self.profile_path, &file)?;
+            std::fs::remove_file(&xpi_path)
+                .with_context(|| format!("Failed to remove {}", xpi_path.display()))?;
+        }
+        info!(id, "Uninstalled extension");
         Ok(())
     }
 
-    /// Install any extensions that are in the database but not yet installed to the profile
-    #[allow(clippy::cognitive_complexity)] // Loop with multiple conditions
-    pub fn install_pending_extensions(&self) -> Result<Vec<String>> {
-        let extensions = self.state_db.get_extensions()?;
-        let extensions_dir = self.profile_path.join("extensions");
-        let mut installed = Vec::new();
+    /// Which devices have each synced extension installed, as they last
+    /// reported
+    pub fn extension_matrix(&self) -> Result<ExtensionMatrix> {
+        let extensions: Vec<(String, String)> = self
+            .state_db
+            .get_extensions()?
+            .into_iter()
+            .map(|(id, name, _)| (id, name))
+            .collect();
+        let reported: Vec<(String, BTreeSet<String>)> = self
+            .state_db
+            .get_device_extensions()?
+            .into_iter()
+            .map(|(device, installed)| (self.device_label(&device), installed))
+            .collect();
+        Ok(ExtensionMatrix::new(&extensions, &reported))
+    }
 
-        for (id, name, _url) in extensions {
-            let xpi_path = extensions_dir.join(format!("{}.xpi", id));
+    /// Advertise what this device is running, so the pack can see devices
+    /// that are behind or have stopped scanning their profile
+    pub fn publish_device_state(&mut self) -> Result<()> {
+        if self.is_paused() || self.paused_categories().contains(&SyncCategory::Devices) {
+            return Ok(());
+        }
+        let extensions = read_extensions(&self.profile_path)?
+            .into_iter()
+            .map(|extension| (extension.id, extension.version.unwrap_or_default()))
+            .collect();
+        self.write_and_materialize(vec![Event::DeviceState {
+            device_id: self.config.device.id.clone(),
+            extensions,
+            browser_version: read_browser_version(&self.profile_path),
+            wolfpack_version: env!("CARGO_PKG_VERSION").to_string(),
+            last_scan: self.last_scan,
+        }])?;
+        Ok(())
+    }
 
-            // Skip if already installed
-            if xpi_path.exists() {
-                continue;
+    /// Share pack-wide settings (`PACK_SETTINGS`) whose value in this
+    /// device's config differs from what the pack last agreed on, so editing
+    /// them on any device applies everywhere
+    ///
+    /// Returns how many settings were published.
+    pub fn publish_pack_config(&mut self) -> Result<usize> {
+        let categories = self
+            .config
+            .sync
+            .disabled_categories
+            .iter()
+            .filter_map(|name| {
+                name.parse::<SyncCategory>()
+                    .err()
+                    .map(|e| format!("sync.disabled_categories entry: {}", e))
+            });
+        for e in categories.chain(self.config.prefs.pattern_errors()) {
+            warn!("Ignoring {}", e);
+        }
+
+        let mut events = Vec::new();
+        for key in PACK_SETTINGS {
+            let value = self.config.pack_setting(key).unwrap_or_default();
+            match self.state_db.get_pack_config(key)? {
+                Some(shared) if shared == value => continue,
+                // Left unset, so a new device doesn't clear the pack's value
+                None if value.is_empty() => continue,
+                _ => {}
             }
+            self.state_db.set_pack_config(key, &value)?;
+            events.push(Event::PackConfigSet {
+                key: key.to_string(),
+                value,
+            });
+        }
 
-            // Check if we have XPI data
-            if let Some((version, xpi_data)) = self.state_db.get_extension_xpi(&id)? {
-                info!("Installing extension {} v{}", name, version);
-                crate::extensions::install_to_profile(&xpi_data, &self.profile_path, &id)?;
-                installed.push(id);
+        let published = events.len();
+        if published > 0 {
+            self.write_events(events)?;
+        }
+        Ok(published)
+    }
+
+    /// Take on pack-wide settings another device changed, saving them to
+    /// this device's config as well
+    fn adopt_pack_config(&mut self) -> Result<()> {
+        let mut changed = Vec::new();
+        for key in PACK_SETTINGS {
+            if let Some(value) = self.adopt_pack_setting(key)? {
+                changed.push((key, value));
             }
         }
+        if changed.is_empty() {
+            return Ok(());
+        }
 
-        if !installed.is_empty() {
-            info!(count = installed.len(), "Installed pending extensions");
+        let epoch = KeyEpoch::from_number(self.config.sync.key_epoch)?;
+        self.event_log.lock().set_key_epoch(epoch);
+        if let Some(path) = &self.config_path {
+            // Reload rather than saving our copy, which may carry
+            // command-line overrides
+            let mut saved = Config::load(path)?;
+            for (key, value) in changed {
+                saved.set_pack_setting(key, value);
+            }
+            saved.save(path)?;
         }
+        Ok(())
+    }
 
-        Ok(installed)
+    /// Take on one pack-wide setting if another device changed it, returning
+    /// the new value
+    fn adopt_pack_setting(&mut self, key: &str) -> Result<Option<Vec<String>>> {
+        let Some(value) = self.state_db.get_pack_config(key)? else {
+            return Ok(None);
+        };
+        if self.config.pack_setting(key).as_ref() == Some(&value) {
+            return Ok(None);
+        }
+        if !self.config.set_pack_setting(key, value.clone()) {
+            warn!("Ignoring {} = [{}] from the pack", key, value.join(", "));
+            return Ok(None);
+        }
+        info!("{} was changed to [{}]", key, value.join(", "));
+        Ok(Some(value))
     }
 
-    /// Remove extensions that have been uninstalled (in db but marked for removal)
-    #[allow(clippy::cognitive_complexity)] // Loop with file system checks
-    pub fn remove_uninstalled_extensions(&self) -> Result<Vec<String>> {
-        let extensions_dir = self.profile_path.join("extensions");
-        let mut removed = Vec::new();
+    /// Onion endpoints other devices published, by device ID
+    pub fn onion_endpoints(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .state_db
+            .get_devices()?
+            .into_iter()
+            .filter(|device| device.id != self.config.device.id)
+            .filter_map(|device| Some((device.id, device.onion?)))
+            .collect())
+    }
 
-        if !extensions_dir.exists() {
-            return Ok(removed);
+    /// Whether a device is paired with this one: its key was exchanged, or
+    /// events from it have been applied
+    pub fn is_paired(&self, device_id: &str) -> bool {
+        if self.known_devices.iter().any(|(id, _)| id == device_id) {
+            return true;
         }
+        matches!(
+            self.state_db.get_device(device_id),
+            Ok(Some(DeviceRecord {
+                last_sync: Some(_),
+                ..
+            }))
+        )
+    }
 
-        // Get list of extensions in the database
-        let known_extensions = self.state_db.get_extensions()?;
-        let known_ids: std::collections::HashSet<_> = known_extensions
-            .iter()
-            .map(|(id, _, _)| id.clone())
-            .collect();
+    /// Bind a peer to the device it answered a clock request as, through
+    /// the device registry
+    ///
+    /// For a device with no key on file to prove itself with, a paired
+    /// device stays bound to the peer it was first seen from, so another
+    /// peer can't send tabs or events as it; such a claim returns false
+    /// until the device is unbound. Strict peer mode also lets paired
+    /// devices in by this binding.
+    pub fn claim_device_peer(&self, device_id: &str, peer_id: &str) -> Result<bool> {
+        if self.is_paired(device_id) {
+            let bound = self.state_db.get_device_peer_id(device_id)?;
+            if bound.is_some_and(|bound| bound != peer_id) {
+                return Ok(false);
+            }
+        }
+        self.state_db.set_device_peer_id(device_id, peer_id)?;
+        Ok(true)
+    }
 
-        // Find XPI files that aren't in the database
-        for entry in std::fs::read_dir(&extensions_dir)? {
-            let entry = entry?;
-            let path = entry.path();
+    /// The key a device was paired with, from `keys/` in the sync dir, which
+    /// a peer claiming the device has to prove it holds
+    pub fn device_key(&self, device_id: &str) -> Result<Option<PublicKey>> {
+        let mut components = Path::new(device_id).components();
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(std::path::Component::Normal(_)), None)
+        ) {
+            return Ok(None);
+        }
+        let path = self
+            .sync_dir()
+            .join("keys")
+            .join(format!("{}.pub", device_id));
+        if !path.exists() {
+            return Ok(None);
+        }
+        let hex = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        public_key_from_hex(hex.trim()).map(Some)
+    }
 
-            let is_xpi = path.extension().map(|e| e == "xpi").unwrap_or(false);
-            let stem = path.file_stem().and_then(|s| s.to_str());
+    /// This device's public key, sent with a challenge so the challenged
+    /// device can derive the secret its proof is keyed by
+    pub fn public_key(&self) -> PublicKey {
+        self.event_log.lock().keypair().public_key()
+    }
 
-            if is_xpi && let Some(stem) = stem {
-                // Check if this is a managed extension (has XPI data in our db or is tracked)
-                let has_xpi_data = self.state_db.get_extension_xpi(stem)?.is_some();
-                if has_xpi_data && !known_ids.contains(stem) {
-                    info!("Removing uninstalled extension {}", stem);
-                    std::fs::remove_file(&path)?;
-                    self.state_db.remove_extension_xpi(stem)?;
-                    removed.push(stem.to_string());
-                }
-            }
+    /// Answer a device challenge from the device holding `their_public`,
+    /// as this device on the peer `peer_id`
+    pub fn prove_device(
+        &self,
+        challenge: &str,
+        their_public: &str,
+        peer_id: &str,
+    ) -> Result<String> {
+        let challenge = hex::decode(challenge).context("Invalid challenge hex")?;
+        let their_public = public_key_from_hex(their_public)?;
+        let claim = (peer_id, self.config.device.id.as_str());
+        let proof = device_proof(
+            self.event_log.lock().keypair(),
+            &their_public,
+            &challenge,
+            claim,
+        );
+        Ok(hex::encode(proof))
+    }
+
+    /// Bind a peer to a device once it has proven it holds the device's
+    /// key, moving the device off any peer it was bound to before
+    ///
+    /// Returns false, binding nothing, when the device has no key on file or
+    /// the proof doesn't check out.
+    pub fn bind_proven_device(
+        &self,
+        device_id: &str,
+        peer_id: &str,
+        challenge: &[u8],
+        proof: &str,
+    ) -> Result<bool> {
+        let Some(key) = self.device_key(device_id)? else {
+            return Ok(false);
+        };
+        let Ok(proof) = hex::decode(proof) else {
+            return Ok(false);
+        };
+        let keypair = self.event_log.lock().keypair().clone();
+        if !verify_device_proof(&keypair, &key, challenge, (peer_id, device_id), &proof) {
+            return Ok(false);
         }
+        self.state_db.set_device_peer_id(device_id, peer_id)?;
+        Ok(true)
+    }
 
-        if !removed.is_empty() {
-            info!(count = removed.len(), "Removed uninstalled extensions");
+    /// Mark a device's key verified, as long as it's still the key on file,
+    /// so a key that changed since its fingerprint was compared isn't
+    pub fn verify_device_key(&self, device_id: &str, key: &str) -> Result<()> {
+        let Some(on_file) = self.device_key(device_id)? else {
+            anyhow::bail!("No paired key for device {}", device_id);
+        };
+        if public_key_from_hex(key)? != on_file {
+            anyhow::bail!(
+                "The key of {} changed; compare fingerprints again",
+                device_id
+            );
         }
+        self.state_db
+            .set_device_verified_key(device_id, Some(&public_key_to_hex(&on_file)))
+    }
 
-        Ok(removed)
+    /// Forget the peer a device is bound to, so it can be claimed again from
+    /// a new p2p identity, e.g. after recovering its key on a reinstall
+    pub fn unbind_device(&self, device: &str) -> Result<String> {
+        let device_id = self.resolve_device(device)?;
+        if !self.state_db.clear_device_peer_id(&device_id)? {
+            anyhow::bail!("{} isn't bound to a peer", device);
+        }
+        Ok(device_id)
     }
-}
 
-#[derive(Debug)]
-pub struct SyncResult {
-    pub incoming_applied: usize,
-    pub outbound_written: usize,
-    pub profile_files_written: Vec<String>,
-    pub event_file: Option<PathBuf>,
-}
+    /// Peer IDs of paired devices, for the strict peer gate
+    pub fn paired_peer_ids(&self) -> Result<Vec<String>> {
+        Ok(self
+            .state_db
+            .get_device_peer_ids()?
+            .into_iter()
+            .filter(|(device_id, _)| self.is_paired(device_id))
+            .map(|(_, peer_id)| peer_id)
+            .collect())
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::events::PrefValue;
+    /// Record that a paired device answered on the network
+    pub fn record_device_seen(
+        &self,
+        device_id: &str,
+        device_name: &str,
+        address: Option<&str>,
+    ) -> Result<()> {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.state_db
+            .record_device_seen(device_id, Some(device_name), address, &now)
+    }
+
+    /// Withdraw unopened tabs sent to devices that haven't been seen for
+    /// `sync.tab_expiry_days`, so they don't open long after they were sent
+    pub fn prune_stale_tabs(&mut self) -> Result<usize> {
+        let days = self.config.sync.tab_expiry_days;
+        if days == 0 {
+            return Ok(0);
+        }
+        let cutoff = chrono::Utc::now() - chrono::TimeDelta::days(days as i64);
+
+        let mut events = Vec::new();
+        for tab in self.state_db.get_sent_tabs()? {
+            // Opened tabs are left for garbage collection
+            if tab.status == TabDelivery::Opened {
+                continue;
+            }
+
+            // Devices never seen count from when the tab was sent
+            let last_seen = self
+                .state_db
+                .get_device(&tab.to_device)?
+                .and_then(|device| device.last_seen)
+                .unwrap_or_else(|| tab.sent_at.clone());
+            let last_seen = chrono::DateTime::parse_from_rfc3339(&last_seen)?;
+            if last_seen >= cutoff {
+                continue;
+            }
+
+            warn!(
+                "Dropping tab sent to {}, not seen since {}: {}",
+                self.device_name(&tab.to_device),
+                last_seen,
+                tab.url
+            );
+            self.state_db.remove_sent_tab(&tab.id)?;
+            events.push(Event::TabReceived {
+                event_id: uuid::Uuid::parse_str(&tab.id)?,
+            });
+        }
+
+        let pruned = events.len();
+        self.write_events(events)?;
+        Ok(pruned)
+    }
+
+    /// Forget sent tabs that were delivered or opened longer than
+    /// `sync.tab_retention_days` ago, and tabs received that long ago, then
+    /// compact the state database
+    ///
+    /// Those tabs are only kept to show in `tabs --sent`, and to keep a
+    /// received tab from coming back if it's delivered again. Records of applied
+    /// events stay, since they're what keeps events still in the sync dir
+    /// from being applied again.
+    pub fn collect_garbage(&mut self) -> Result<GcReport> {
+        let size_before = self.state_db.size()?;
+        let mut report = GcReport::default();
+
+        let days = self.config.sync.tab_retention_days;
+        if days > 0 {
+            let cutoff = chrono::Utc::now() - chrono::TimeDelta::days(days as i64);
+            for tab in self.state_db.get_sent_tabs()? {
+                if tab.status == TabDelivery::Pending
+                    || chrono::DateTime::parse_from_rfc3339(&tab.sent_at)? >= cutoff
+                {
+                    continue;
+                }
+                self.state_db.remove_sent_tab(&tab.id)?;
+                match tab.status {
+                    TabDelivery::Opened => report.opened_tabs += 1,
+                    _ => report.delivered_tabs += 1,
+                }
+            }
+            report.received_tabs = self.state_db.forget_received_tabs(&cutoff.to_rfc3339())?;
+        }
+
+        self.state_db.vacuum()?;
+        report.reclaimed_bytes = size_before.saturating_sub(self.state_db.size()?);
+        Ok(report)
+    }
+
+    /// Summarize what the sync dir and state database hold, to find what's
+    /// making them grow
+    pub fn health_report(&self) -> Result<HealthReport> {
+        let mut report = HealthReport::default();
+        let events = self
+            .event_log
+            .lock()
+            .read_stored_events(&self.known_devices)?;
+        report.count_events(&events, |chunks| self.event_log.lock().blob_usage(chunks));
+
+        report.devices = self
+            .event_log
+            .lock()
+            .device_files()?
+            .into_iter()
+            .map(|(device, (files, bytes))| DeviceUsage {
+                device: format!("{} ({})", self.device_label(&device), device),
+                files,
+                bytes,
+            })
+            .collect();
+        report
+            .devices
+            .sort_by_key(|usage| std::cmp::Reverse(usage.bytes));
+        report.blob_bytes = self.event_log.lock().blob_store_usage()?;
+        report.rows = self.state_db.row_counts()?;
+        report.growth = dir_growth(&self.config.paths.sync_dir, std::time::SystemTime::now())?;
+        Ok(report)
+    }
+
+    /// Rebuild the materialized state from the event log, up to `until` or
+    /// the whole log
+    ///
+    /// Everything materialized is dropped and the events are applied again
+    /// in one transaction, so a failed replay leaves the state as it was.
+    /// Events in paused categories are left for when they're resumed, as
+    /// they are in a sync. Replaying up to a point pauses sync, since the
+    /// next sync would otherwise apply the later events right away.
+    pub fn replay(&mut self, until: Option<ReplayPoint>) -> Result<ReplayReport> {
+        let all_events = self.event_log.lock().read_all_events(&self.known_devices)?;
+        let events = match &until {
+            Some(point) => point.events_until(&all_events)?,
+            None => &all_events[..],
+        };
+        let paused = self.paused_categories();
+
+        let applied = self.state_db.transaction(|db| {
+            db.clear_materialized_state()?;
+            materialize_events_except(
+                db,
+                events,
+                &self.config.device.id,
+                &paused,
+                &self.config.handlers.translate,
+            )
+        })?;
+        info!(
+            applied,
+            events = events.len(),
+            "Replayed event log{}",
+            until.map_or(String::new(), |point| format!(" up to {}", point))
+        );
+
+        if until.is_some() {
+            self.pause(None, None)?;
+        }
+        Ok(ReplayReport {
+            events: events.len(),
+            applied,
+            until,
+        })
+    }
+
+    /// Changes to the synced state between two points in the event log, or
+    /// from one point to the end of it
+    ///
+    /// The log is materialized up to each point into a scratch database,
+    /// leaving this device's state alone.
+    pub fn history_diff(&self, from: ReplayPoint, to: Option<ReplayPoint>) -> Result<Vec<Event>> {
+        let events = self.event_log.lock().read_all_events(&self.known_devices)?;
+        let state_at = |point: Option<ReplayPoint>| -> Result<StateExport> {
+            let events = match point {
+                Some(point) => point.events_until(&events)?,
+                None => &events[..],
+            };
+            let db = StateDb::open_in_memory()?;
+            materialize_events(&db, events, &self.config.device.id)?;
+            StateExport::from_db(&db, &self.config.device.id)
+        };
+
+        let before = state_at(Some(from))?;
+        let after = state_at(to)?;
+        Ok(after.changes_since(&before))
+    }
+
+    /// Save a page to the synced reading list
+    pub fn add_to_reading_list(&mut self, url: &str, title: Option<&str>) -> Result<PathBuf> {
+        let now = chrono::Utc::now().to_rfc3339();
+        self.state_db.add_reading_list_item(url, title, &now)?;
+
+        let event = Event::ReadingListAdded {
+            url: url.to_string(),
+            title: title.map(String::from),
+        };
+        self.write_events(vec![event])?
+            .ok_or_else(|| anyhow::anyhow!("Failed to write reading list event"))
+    }
+
+    /// Remove a page from the synced reading list
+    pub fn remove_from_reading_list(&mut self, url: &str) -> Result<PathBuf> {
+        if !self.state_db.remove_reading_list_item(url)? {
+            anyhow::bail!("Not in reading list: {}", url);
+        }
+
+        let event = Event::ReadingListRemoved {
+            url: url.to_string(),
+        };
+        self.write_events(vec![event])?
+            .ok_or_else(|| anyhow::anyhow!("Failed to write reading list event"))
+    }
+
+    /// Get the synced reading list, oldest first
+    pub fn get_reading_list(&self) -> Result<Vec<ReadingListItem>> {
+        self.state_db.get_reading_list()
+    }
+
+    /// Open a URL in the default browser
+    pub fn open_tab(&self, url: &str) -> Result<()> {
+        #[cfg(target_os = "linux")]
+        {
+            std::process::Command::new("xdg-open")
+                .arg(url)
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+        }
+
+        #[cfg(target_os = "macos")]
+        {
+            std::process::Command::new("open")
+                .arg(url)
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+        }
+
+        #[cfg(target_os = "windows")]
+        {
+            std::process::Command::new("cmd")
+                .args(["/C", "start", "", url])
+                .spawn()
+                .map_err(|e| anyhow::anyhow!("Failed to open URL: {}", e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Get the current vector clock
+    pub fn get_vector_clock(&self) -> HashMap<String, u64> {
+        self.event_log.lock().clock().to_hashmap()
+    }
+
+    /// The event log, for answering peers without holding the engine
+    pub fn shared_event_log(&self) -> SharedEventLog {
+        self.event_log.clone()
+    }
+
+    /// A page of the event files a peer doesn't hold yet (for P2P sync),
+    /// starting after `after`
+    ///
+    /// Other devices' files are passed on too unless `sync.forward_events`
+    /// is off, with this device added to their hops, so a peer that can't
+    /// reach those devices still gets their events. Files that already went
+    /// [`MAX_HOPS`] hops aren't passed on.
+    #[instrument(skip_all)]
+    pub fn get_events_since(
+        &self,
+        held: &HeldFiles,
+        after: Option<&EventCursor>,
+    ) -> Result<EventPage> {
+        let max = match self.config.limits.max_events_per_file {
+            0 => MAX_EVENTS_PER_MESSAGE,
+            max => max.min(MAX_EVENTS_PER_MESSAGE),
+        };
+        let own = &self.config.device.id;
+
+        let after = after.map(|cursor| (cursor.number, cursor.device.as_str()));
+        let page =
+            self.event_log
+                .lock()
+                .files_since(held, after, max, PAGE_BYTES, &self.known_devices)?;
+        let mut events = Vec::new();
+        for (device, number, file, chunks) in page.files {
+            let mut hops = Vec::new();
+            if device != *own {
+                if !self.config.sync.forward_events {
+                    continue;
+                }
+                hops = self.state_db.event_route(&device, number)?;
+                if hops.len() >= MAX_HOPS {
+                    continue;
+                }
+                hops.push(own.clone());
+            }
+            events.push(EncryptedEvent {
+                id: format!("{:04}", number),
+                device_id: device,
+                counter: u64::from(number),
+                ciphertext: file.ciphertext,
+                public_key: file.sender_public_key.to_vec(),
+                cipher: file.cipher as u8,
+                nonce: file.nonce,
+                key_epoch: file.key_epoch as u8,
+                hops,
+                chunks: chunks
+                    .into_iter()
+                    .map(|(id, data)| EncryptedChunk { id, data })
+                    .collect(),
+            });
+        }
+        let next = page
+            .next
+            .map(|(number, device)| EventCursor { number, device });
+        Ok(EventPage { events, next })
+    }
+
+    /// Store event files received from a peer and apply the events in them,
+    /// returning how many were applied
+    ///
+    /// Files that come back around, went too many hops, or are already held
+    /// are skipped. Where the rest came through is kept, so they can be
+    /// passed on.
+    #[allow(clippy::cognitive_complexity)] // Loop with logging
+    #[instrument(skip_all, fields(count = events.len()))]
+    pub fn apply_remote_events(&mut self, events: Vec<EncryptedEvent>) -> Result<usize> {
+        if events.is_empty() {
+            return Ok(0);
+        }
+        self.check_remote_events(&events)?;
+
+        let mut stored = 0;
+        for event in events {
+            if let Err(reason) = check_route(&event, &self.config.device.id) {
+                debug!("Skipping event file: {}", reason);
+                continue;
+            }
+            match self.store_remote_event(&event) {
+                Ok(true) => stored += 1,
+                Ok(false) => {}
+                Err(e) => warn!(
+                    "Skipping event file {} from {}: {}",
+                    event.id, event.device_id, e
+                ),
+            }
+        }
+        if stored == 0 {
+            return Ok(0);
+        }
+        info!("Stored {} event files from peers", stored);
+        self.process_incoming()
+    }
+
+    fn store_remote_event(&self, event: &EncryptedEvent) -> Result<bool> {
+        let number = u32::try_from(event.counter).context("Event file number out of range")?;
+        let file = EventFile {
+            cipher: Cipher::from_byte(event.cipher)
+                .ok_or_else(|| anyhow::anyhow!("Unknown cipher type: {}", event.cipher))?,
+            key_epoch: KeyEpoch::from_number(u64::from(event.key_epoch))?,
+            sender_public_key: event
+                .public_key
+                .as_slice()
+                .try_into()
+                .context("Invalid public key")?,
+            nonce: event.nonce.clone(),
+            ciphertext: event.ciphertext.clone(),
+        };
+        let chunks: Vec<(String, Vec<u8>)> = event
+            .chunks
+            .iter()
+            .map(|chunk| (chunk.id.clone(), chunk.data.clone()))
+            .collect();
+        let stored = self.event_log.lock().store_remote_file(
+            &event.device_id,
+            number,
+            &file,
+            &chunks,
+            &self.known_devices,
+        )?;
+        if stored && !event.hops.is_empty() {
+            self.state_db
+                .record_event_route(&event.device_id, number, &event.hops)?;
+        }
+        Ok(stored)
+    }
+
+    /// Refuse events from another device that are over the configured
+    /// limits: too many at once, larger than any event can be, or enough to
+    /// take the devices that wrote them over their storage quota
+    fn check_remote_events(&self, events: &[EncryptedEvent]) -> Result<()> {
+        let limits = &self.config.limits;
+        limits::check_count(events.len(), limits)?;
+
+        let mut adding: HashMap<&str, u64> = HashMap::new();
+        for event in events {
+            let size = event.ciphertext.len() as u64;
+            limits::check_encrypted_event(size, limits)?;
+            let chunks: u64 = event.chunks.iter().map(|c| c.data.len() as u64).sum();
+            *adding.entry(&event.device_id).or_default() += size + chunks;
+        }
+        for (device, size) in adding {
+            let used = self.event_log.lock().device_usage(device)?;
+            limits::check_quota(device, used, size, limits)?;
+        }
+        Ok(())
+    }
+
+    /// Receive a tab from another device (via P2P)
+    ///
+    /// Tabs sent with the ID of their `TabSent` event keep it, so the
+    /// event syncing in later doesn't add the tab a second time. Returns
+    /// whether the tab is new, rather than one already pending or received,
+    /// or a duplicate shown as one that is.
+    pub fn receive_tab(
+        &mut self,
+        url: &str,
+        title: Option<&str>,
+        from_device: &str,
+        tab_id: Option<&str>,
+    ) -> Result<bool> {
+        let tab_id = tab_id
+            .map(String::from)
+            .unwrap_or_else(|| uuid::Uuid::now_v7().to_string());
+        let now = chrono::Utc::now().to_rfc3339();
+        if !self
+            .state_db
+            .add_pending_tab(&tab_id, url, title, from_device, &now)?
+        {
+            debug!("Tab {} was already received", tab_id);
+            return Ok(false);
+        }
+        info!("Received tab from {}: {}", from_device, url);
+        Ok(self.get_pending_tabs()?.iter().any(|tab| tab.id == tab_id))
+    }
+
+    /// Install any extensions that are in the database but not yet installed to the profile
+    ///
+    /// They're written in parallel, so a new device catching up on many
+    /// isn't held up by each in turn. One that fails is logged and left for
+    /// the next sync.
+    #[allow(clippy::cognitive_complexity)] // Loop with multiple conditions
+    pub fn install_pending_extensions(&self) -> Result<Vec<String>> {
+        let extensions = self.state_db.get_extensions()?;
+        let extensions_dir = self.profile_path.join("extensions");
+        let mut pending = Vec::new();
+
+        for (id, name, _url) in extensions {
+            let xpi_path = extensions_dir.join(format!("{}.xpi", id));
+
+            // Skip if already installed
+            if xpi_path.exists() {
+                continue;
+            }
+
+            // Check if we have XPI data
+            if let Some((version, xpi_data)) = self.state_db.get_extension_xpi(&id)? {
+                pending.push(PendingInstall {
+                    id,
+                    name,
+                    version,
+                    xpi_data,
+                });
+            }
+        }
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let results = install_all_to_profile(&pending, &self.profile_path)?;
+        let mut installed = Vec::new();
+        for (extension, result) in pending.iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    self.state_db.record_extension_written(&extension.id)?;
+                    installed.push(extension.id.clone());
+                }
+                Err(e) => warn!(
+                    "Failed to install extension {} v{}: {:#}",
+                    extension.name, extension.version, e
+                ),
+            }
+        }
+
+        if !installed.is_empty() {
+            self.allow_sideloads()?;
+        }
+        info!(
+            count = installed.len(),
+            failed = pending.len() - installed.len(),
+            "Installed pending extensions"
+        );
+        Ok(installed)
+    }
+
+    /// Install and remove extension XPIs, returning (installed, removed) IDs
+    ///
+    /// Installs are queued instead while the browser runs or writes await
+    /// approval, and nothing is written in observe mode, while extensions are
+    /// paused, or when `policies.json` manages them.
+    #[instrument(skip_all)]
+    pub fn sync_extensions(&mut self) -> Result<(Vec<String>, Vec<String>)> {
+        if self.observe
+            || self.config.policies.enabled
+            || self.paused_categories().contains(&SyncCategory::Extensions)
+        {
+            return Ok((Vec::new(), Vec::new()));
+        }
+
+        // The browser may ignore or overwrite XPIs written while it runs, so
+        // they wait in the write queue like other profile writes
+        let installed = if self.profile_write_hold().is_some() {
+            self.queue_pending_extensions()?;
+            Vec::new()
+        } else {
+            self.install_pending_extensions()?
+        };
+
+        Ok((installed, self.remove_uninstalled_extensions()?))
+    }
+
+    /// Bring policies.json in line with the pack's extensions and prefs,
+    /// returning whether it changed
+    ///
+    /// The browser reads policies at startup and installs extensions from
+    /// them whether or not it was running when they changed, so this isn't
+    /// held for the browser or for approval like profile writes are.
+    pub fn write_policies(&mut self) -> Result<bool> {
+        if !self.config.policies.enabled || self.observe || self.is_paused() {
+            return Ok(false);
+        }
+        let paused = self.paused_categories();
+        let xpi_dir = &self.config.policies.xpi_dir;
+
+        let extensions = if paused.contains(&SyncCategory::Extensions) {
+            None
+        } else {
+            let mut urls = std::collections::BTreeMap::new();
+            for (id, _, _) in self.state_db.get_extensions()? {
+                if let Some((version, xpi_data)) = self.state_db.get_extension_xpi(&id)? {
+                    urls.insert(
+                        id.clone(),
+                        stage_policy_xpi(xpi_dir, &id, &version, &xpi_data)?,
+                    );
+                }
+            }
+            Some(urls)
+        };
+        let prefs = if paused.contains(&SyncCategory::Prefs) {
+            None
+        } else {
+            Some(self.synced_prefs(&paused)?)
+        };
+
+        let path = &self.config.policies.path;
+        let written = write_policies(path, xpi_dir, extensions.as_ref(), prefs.as_ref())
+            .with_context(|| format!("Failed to update {}", path.display()))?;
+        if let Some(extensions) = &extensions {
+            prune_policy_xpis(xpi_dir, &extensions.values().cloned().collect())?;
+        }
+        if written {
+            info!(path = %path.display(), "Updated policies.json");
+        }
+        Ok(written)
+    }
+
+    /// Have the browser load XPIs written to the profile enabled, instead of
+    /// waiting for the user to confirm each one
+    fn allow_sideloads(&self) -> Result<()> {
+        if profile_sideloads_allowed(&self.profile_path)? {
+            return Ok(());
+        }
+        self.backups.backup(&self.profile_path, "prefs.js")?;
+        allow_profile_sideloads(&self.profile_path)?;
+        info!(
+            "Set {} so synced extensions load enabled",
+            AUTO_DISABLE_SCOPES_PREF
+        );
+        Ok(())
+    }
+
+    /// Enable synced extensions the browser registered as sideloads awaiting
+    /// confirmation, returning their IDs
+    ///
+    /// Call while the browser is closed; they load enabled on its next start.
+    /// Nothing is written while sync or extensions are paused.
+    pub fn enable_sideloaded_extensions(&self) -> Result<Vec<String>> {
+        if self.observe
+            || self.is_paused()
+            || self.paused_categories().contains(&SyncCategory::Extensions)
+        {
+            return Ok(Vec::new());
+        }
+        let ids = self.state_db.extension_xpi_ids()?;
+        if sideloads_awaiting_confirmation(&self.profile_path, &ids)?.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        self.backups.backup(&self.profile_path, "extensions.json")?;
+        self.backups
+            .backup(&self.profile_path, ADDON_STARTUP_FILE)?;
+        let enabled = enable_sideloaded(&self.profile_path, &ids)?;
+        self.allow_sideloads()?;
+        Ok(enabled)
+    }
+
+    /// Note that the browser started, so it registers the XPIs written so far
+    pub fn note_browser_started(&self) -> Result<()> {
+        self.state_db.mark_browser_started()
+    }
+
+    /// Check that the browser registered the XPIs written before it last
+    /// started, returning the IDs of any it didn't
+    ///
+    /// Each XPI is only checked once: one the browser skipped stays in the
+    /// profile for it to pick up later, or for the user to enable.
+    pub fn verify_extension_installs(&self) -> Result<Vec<String>> {
+        let ids = self.state_db.extensions_to_verify()?;
+        if ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let registered: std::collections::HashSet<String> = read_extensions(&self.profile_path)?
+            .into_iter()
+            .map(|ext| ext.id)
+            .collect();
+        let mut missing = Vec::new();
+        for id in ids {
+            if registered.contains(&id) {
+                debug!("Browser registered extension {}", id);
+            } else {
+                missing.push(id.clone());
+            }
+            self.state_db.forget_extension_install(&id)?;
+        }
+        Ok(missing)
+    }
+
+    /// Queue installs for extensions that aren't in the profile yet
+    fn queue_pending_extensions(&mut self) -> Result<()> {
+        let extensions_dir = self.profile_path.join("extensions");
+
+        for (id, name, _url) in self.state_db.get_extensions()? {
+            if extensions_dir.join(format!("{}.xpi", id)).exists() {
+                continue;
+            }
+            if let Some((version, xpi_data)) = self.state_db.get_extension_xpi(&id)? {
+                self.write_queue
+                    .queue_extension(id, name, version, xpi_data);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove extensions that have been uninstalled (in db but marked for removal)
+    #[allow(clippy::cognitive_complexity)] // Loop with file system checks
+    pub fn remove_uninstalled_extensions(&self) -> Result<Vec<String>> {
+        let extensions_dir = self.profile_path.join("extensions");
+        let mut removed = Vec::new();
+
+        if !extensions_dir.exists() {
+            return Ok(removed);
+        }
+
+        // Get list of extensions in the database
+        let known_extensions = self.state_db.get_extensions()?;
+        let known_ids: std::collections::HashSet<_> = known_extensions
+            .iter()
+            .map(|(id, _, _)| id.clone())
+            .collect();
+
+        // Find XPI files that aren't in the database
+        for entry in std::fs::read_dir(&extensions_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+
+            let is_xpi = path.extension().map(|e| e == "xpi").unwrap_or(false);
+            let stem = path.file_stem().and_then(|s| s.to_str());
+
+            if is_xpi && let Some(stem) = stem {
+                // Check if this is a managed extension (has XPI data in our db or is tracked)
+                let has_xpi_data = self.state_db.get_extension_xpi(stem)?.is_some();
+                if has_xpi_data && !known_ids.contains(stem) {
+                    info!("Removing uninstalled extension {}", stem);
+                    self.backups
+                        .backup(&self.profile_path, &format!("extensions/{}.xpi", stem))?;
+                    std::fs::remove_file(&path)?;
+                    self.state_db.remove_extension_xpi(stem)?;
+                    removed.push(stem.to_string());
+                }
+            }
+        }
+
+        if !removed.is_empty() {
+            info!(count = removed.len(), "Removed uninstalled extensions");
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Event files for a peer, and where the page after them starts
+#[derive(Debug, Default)]
+pub struct EventPage {
+    pub events: Vec<EncryptedEvent>,
+    pub next: Option<EventCursor>,
+}
+
+/// What a garbage collection run removed
+#[derive(Debug, Default)]
+pub struct GcReport {
+    /// Sent tabs forgotten after they were opened
+    pub opened_tabs: usize,
+    /// Sent tabs forgotten after the receiving device acknowledged them
+    pub delivered_tabs: usize,
+    /// Received tabs forgotten
+    pub received_tabs: usize,
+    /// Bytes the state database shrank by
+    pub reclaimed_bytes: u64,
+}
+
+impl GcReport {
+    pub fn describe(&self) -> String {
+        format!(
+            "Forgot {} opened, {} delivered and {} received tabs, reclaimed {:.1} KiB",
+            self.opened_tabs,
+            self.delivered_tabs,
+            self.received_tabs,
+            self.reclaimed_bytes as f64 / 1024.0
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncResult {
+    pub incoming_applied: usize,
+    pub outbound_written: usize,
+    pub profile_files_written: Vec<String>,
+    pub event_file: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyPair;
+    use crate::events::{PrefValue, VectorClock};
+
+    fn test_engine(dir: &std::path::Path) -> SyncEngine {
+        let profile = dir.join("profile");
+        std::fs::create_dir_all(&profile).unwrap();
+        std::fs::write(profile.join("prefs.js"), "").unwrap();
+
+        let mut config = Config::default();
+        config.paths.profile = Some(profile);
+        config.paths.sync_dir = dir.join("sync");
+        config.backups.dir = dir.join("backups");
+
+        let event_log = EventLog::new(
+            config.paths.sync_dir.clone(),
+            config.device.id.clone(),
+            KeyPair::generate(),
+        );
+        let state_db = StateDb::open_in_memory().unwrap();
+        SyncEngine::new(config, event_log, state_db).unwrap()
+    }
+
+    #[test]
+    fn test_install_and_uninstall_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        let source = dir.path().join("source");
+        std::fs::create_dir_all(&source).unwrap();
+        std::fs::write(
+            source.join("manifest.json"),
+            r#"{"manifest_version": 2, "name": "Daemon Install", "version": "1.2.0",
+                "browser_specific_settings": {"gecko": {"id": "install@test.com"}}}"#,
+        )
+        .unwrap();
+        let (_, xpi_data) = crate::extensions::package_extension(&source).unwrap();
+        let xpi = crate::extensions::decompress_xpi(
+            &crate::extensions::decode_base64(&xpi_data).unwrap(),
+        )
+        .unwrap();
+        let xpi_path = dir.path().join("install.xpi");
+        std::fs::write(&xpi_path, xpi).unwrap();
+
+        let result = engine.install_extension(&xpi_path, None).unwrap();
+        assert_eq!(result.id, "install@test.com");
+        assert_eq!(engine.get_extensions().unwrap().len(), 1);
+        let (version, _) = engine
+            .state_db
+            .get_extension_xpi("install@test.com")
+            .unwrap()
+            .unwrap();
+        assert_eq!(version, "1.2.0");
+        assert_eq!(engine.install_pending_extensions().unwrap().len(), 1);
+        let installed = engine
+            .profile_path()
+            .join("extensions/install@test.com.xpi");
+        assert!(installed.exists());
+
+        engine.uninstall_extension("install@test.com").unwrap();
+        assert!(engine.get_extensions().unwrap().is_empty());
+        assert!(!installed.exists());
+        assert!(engine.uninstall_extension("install@test.com").is_err());
+
+        // Downloaded XPIs record where they came from
+        let source = crate::events::ExtensionSource::Amo {
+            amo_slug: "daemon-install".to_string(),
+        };
+        let result = engine
+            .install_extension(&xpi_path, Some(source.clone()))
+            .unwrap();
+        assert_eq!(result.source, source);
+    }
+
+    #[test]
+    fn test_install_pending_extensions_in_parallel() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = test_engine(dir.path());
+        let source = crate::events::ExtensionSource::Amo {
+            amo_slug: "test".to_string(),
+        };
+
+        for i in 0..6 {
+            let id = format!("ext{}@test.com", i);
+            let extension = dir.path().join(&id);
+            std::fs::create_dir_all(&extension).unwrap();
+            std::fs::write(
+                extension.join("manifest.json"),
+                format!(
+                    r#"{{"manifest_version": 2, "name": "Ext {i}", "version": "1.0.{i}",
+                        "browser_specific_settings": {{"gecko": {{"id": "{id}"}}}}}}"#
+                ),
+            )
+            .unwrap();
+            let (_, xpi_data) = crate::extensions::package_extension(&extension).unwrap();
+            engine.state_db.add_extension(&id, "Ext", None).unwrap();
+            engine
+                .state_db
+                .store_extension_xpi(&id, "1.0.0", &source, &xpi_data)
+                .unwrap();
+        }
+        // One that can't be decoded doesn't stop the rest
+        engine
+            .state_db
+            .add_extension("broken@test.com", "Broken", None)
+            .unwrap();
+        engine
+            .state_db
+            .store_extension_xpi("broken@test.com", "1.0.0", &source, "not base64!")
+            .unwrap();
+
+        let mut installed = engine.install_pending_extensions().unwrap();
+        installed.sort();
+        let expected: Vec<String> = (0..6).map(|i| format!("ext{}@test.com", i)).collect();
+        assert_eq!(installed, expected);
+        let extensions_dir = engine.profile_path().join("extensions");
+        assert!(extensions_dir.join("ext5@test.com.xpi").exists());
+        assert!(!extensions_dir.join("broken@test.com.xpi").exists());
+
+        // Only the failed one is tried again
+        assert!(engine.install_pending_extensions().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_observe_mode_never_writes_profile() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine
+            .state_db
+            .set_pref("browser.startup.page", "3", "int")
+            .unwrap();
+        engine.set_observe(true);
+
+        let written = engine.apply_to_profile().unwrap();
+        assert!(written.is_empty());
+        assert!(!engine.profile_path().join("user.js").exists());
+
+        let preview = engine.preview_profile_writes().unwrap();
+        assert_eq!(preview.len(), 1);
+        assert_eq!(preview[0].0, "user.js");
+    }
+
+    fn write_search_json(profile: &Path, default_id: &str) {
+        let json = serde_json::json!({
+            "engines": [
+                {"id": "ddg@search.mozilla.org", "_name": "DuckDuckGo", "_metaData": {"alias": "ddg"}},
+                {"id": "3f1a", "_name": "Startpage", "_metaData": {"alias": "sp"}}
+            ],
+            "metaData": {"defaultEngineId": default_id}
+        });
+        let data = crate::profile::encode_mozlz4(json.to_string().as_bytes());
+        std::fs::write(profile.join("search.json.mozlz4"), data).unwrap();
+    }
+
+    #[test]
+    fn test_default_search_engine_requeued_when_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        write_search_json(&profile, "ddg@search.mozilla.org");
+
+        let details = crate::events::SearchEngineDetails::default();
+        for (id, name) in [("ddg", "DuckDuckGo"), ("sp", "Startpage")] {
+            engine
+                .state_db
+                .add_search_engine(id, name, "", &details)
+                .unwrap();
+        }
+        engine.state_db.set_default_search_engine("sp").unwrap();
+
+        let written = engine.apply_to_profile().unwrap();
+        assert!(written.contains(&"search.json.mozlz4".to_string()));
+        assert!(written.contains(&"user.js".to_string()));
+        let engines = read_search_engines(&profile).unwrap();
+        assert!(engines.iter().any(|e| e.id == "sp" && e.is_default));
+
+        // The browser resets the default when it rewrites search.json
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        write_search_json(&profile, "ddg@search.mozilla.org");
+
+        // The old default isn't synced back, and the write is queued again
+        let events = engine.scan_search_engines().unwrap();
+        assert!(
+            !events
+                .iter()
+                .any(|e| matches!(e, Event::SearchEngineDefault { .. }))
+        );
+        let queued = engine.describe_write_queue().unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].0, "search.json.mozlz4");
+    }
+
+    #[test]
+    fn test_replay_rebuilds_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let set = |value: i64| Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: PrefValue::Int(value),
+        };
+
+        engine.write_and_materialize(vec![set(1)]).unwrap();
+        let until = engine
+            .event_log
+            .lock()
+            .read_all_events(&engine.known_devices)
+            .unwrap()[0]
+            .id;
+        engine.write_and_materialize(vec![set(3)]).unwrap();
+        // State that drifted from the log
+        engine
+            .state_db
+            .set_pref("browser.startup.page", "7", "int")
+            .unwrap();
+        engine
+            .state_db
+            .set_handler("mailto", "thunderbird")
+            .unwrap();
+
+        let report = engine.replay(None).unwrap();
+        assert_eq!((report.events, report.applied), (2, 2));
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs["browser.startup.page"], PrefValue::Int(3));
+        assert!(engine.get_materialized_handlers().unwrap().is_empty());
+        assert!(!engine.is_paused());
+
+        let report = engine.replay(Some(ReplayPoint::Event(until))).unwrap();
+        assert_eq!((report.events, report.applied), (1, 1));
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs["browser.startup.page"], PrefValue::Int(1));
+        assert!(engine.is_paused());
+
+        // A replay that fails leaves the state alone
+        assert!(
+            engine
+                .replay(Some(ReplayPoint::Event(uuid::Uuid::now_v7())))
+                .is_err()
+        );
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs["browser.startup.page"], PrefValue::Int(1));
+    }
+
+    #[test]
+    fn test_pref_variables_expand_without_echo() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.prefs.whitelist = vec!["browser.download.dir".to_string()];
+        engine.config.device.name = "laptop".to_string();
+        engine
+            .state_db
+            .set_pref("browser.download.dir", "/srv/{{device_name}}", "string")
+            .unwrap();
+
+        let prefs = engine.get_profile_prefs(&[]).unwrap();
+        assert_eq!(
+            prefs["browser.download.dir"],
+            PrefValue::String("/srv/laptop".to_string())
+        );
+
+        // Once the browser has it expanded, it isn't sent back that way
+        std::fs::write(
+            engine.profile_path().join("prefs.js"),
+            crate::profile::render_user_js(&prefs),
+        )
+        .unwrap();
+        assert!(
+            engine
+                .scan_category(SyncCategory::Prefs)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_local_only_prefs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.prefs.whitelist = vec!["network.*".to_string()];
+        engine.config.prefs.local_only = vec!["network.proxy.*".to_string()];
+        engine
+            .state_db
+            .set_pref("network.proxy.type", "1", "int")
+            .unwrap();
+        assert!(engine.get_profile_prefs(&[]).unwrap().is_empty());
+
+        let prefs = HashMap::from([
+            ("network.proxy.type".to_string(), PrefValue::Int(2)),
+            ("network.trr.mode".to_string(), PrefValue::Int(3)),
+        ]);
+        std::fs::write(
+            engine.profile_path().join("prefs.js"),
+            crate::profile::render_user_js(&prefs),
+        )
+        .unwrap();
+        assert_eq!(
+            engine.scan_category(SyncCategory::Prefs).unwrap(),
+            vec![Event::PrefSet {
+                key: "network.trr.mode".to_string(),
+                value: PrefValue::Int(3),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_reconcile_offline_prefs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.prefs.whitelist = vec!["browser.*".to_string()];
+        let write_profile = |engine: &SyncEngine, prefs: &[(&str, i64)]| {
+            let prefs = prefs
+                .iter()
+                .map(|(key, value)| (key.to_string(), PrefValue::Int(*value)))
+                .collect();
+            std::fs::write(
+                engine.profile_path().join("prefs.js"),
+                crate::profile::render_user_js(&prefs),
+            )
+            .unwrap();
+        };
+        for key in ["browser.a", "browser.b"] {
+            engine.state_db.set_pref(key, "1", "int").unwrap();
+        }
+        write_profile(&engine, &[("browser.a", 1), ("browser.b", 1)]);
+
+        // The first start has nothing to compare to
+        assert_eq!(engine.reconcile_offline_prefs().unwrap(), (0, 0));
+        engine.save_prefs_snapshot().unwrap();
+
+        // While stopped, a is changed and c added in the browser, and
+        // another device changes b
+        write_profile(
+            &engine,
+            &[("browser.a", 2), ("browser.b", 1), ("browser.c", 1)],
+        );
+        engine.state_db.set_pref("browser.b", "5", "int").unwrap();
+
+        assert_eq!(engine.reconcile_offline_prefs().unwrap(), (2, 0));
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs["browser.a"], PrefValue::Int(2));
+        assert_eq!(prefs["browser.b"], PrefValue::Int(5));
+        assert_eq!(prefs["browser.c"], PrefValue::Int(1));
+
+        // Once sent, they aren't sent again
+        assert_eq!(engine.reconcile_offline_prefs().unwrap(), (0, 0));
+
+        // A pref whitelisted while stopped isn't a change
+        engine.config.prefs.whitelist.push("font.*".to_string());
+        std::fs::write(
+            engine.profile_path().join("prefs.js"),
+            r#"user_pref("browser.a", 2); user_pref("browser.b", 1); user_pref("browser.c", 1); user_pref("font.size", 12);"#,
+        )
+        .unwrap();
+        assert_eq!(engine.reconcile_offline_prefs().unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn test_divergence_held_until_resolved() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.prefs.whitelist = vec!["browser.*".to_string()];
+        let prefs_js = engine.profile_path().join("prefs.js");
+        for key in ["browser.a", "browser.b"] {
+            engine.state_db.set_pref(key, "1", "int").unwrap();
+        }
+        std::fs::write(
+            &prefs_js,
+            r#"user_pref("browser.a", 1); user_pref("browser.b", 1);"#,
+        )
+        .unwrap();
+        engine.save_prefs_snapshot().unwrap();
+
+        // While stopped, both are changed here and in the pack
+        std::fs::write(
+            &prefs_js,
+            r#"user_pref("browser.a", 2); user_pref("browser.b", 2);"#,
+        )
+        .unwrap();
+        engine.state_db.set_pref("browser.a", "3", "int").unwrap();
+        engine.state_db.set_pref("browser.b", "3", "int").unwrap();
+
+        assert_eq!(engine.reconcile_offline_prefs().unwrap(), (0, 2));
+        let held: Vec<String> = engine
+            .divergences()
+            .unwrap()
+            .into_iter()
+            .map(|d| d.item)
+            .collect();
+        assert_eq!(held, vec!["browser.a", "browser.b"]);
+
+        // Held prefs are neither scanned nor written to the profile
+        let current = engine.read_profile_prefs().unwrap();
+        assert!(engine.diff_prefs_from_profile(current).unwrap().is_empty());
+        let synced = engine.synced_prefs(&[]).unwrap();
+        assert!(!synced.contains_key("browser.a"));
+
+        engine
+            .resolve_divergence("prefs", "browser.a", Keep::Local)
+            .unwrap();
+        engine
+            .resolve_divergence("prefs", "browser.b", Keep::Pack)
+            .unwrap();
+        assert!(engine.divergences().unwrap().is_empty());
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs["browser.a"], PrefValue::Int(2));
+        assert_eq!(prefs["browser.b"], PrefValue::Int(3));
+        let synced = engine.synced_prefs(&[]).unwrap();
+        assert_eq!(synced["browser.b"], PrefValue::Int(3));
+
+        assert!(
+            engine
+                .resolve_divergence("prefs", "browser.a", Keep::Local)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_history_diff() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine
+            .write_and_materialize(vec![Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: PrefValue::Int(1),
+            }])
+            .unwrap();
+        let first = engine
+            .event_log
+            .lock()
+            .read_all_events(&engine.known_devices)
+            .unwrap()[0]
+            .id;
+        engine
+            .write_and_materialize(vec![
+                Event::PrefRemoved {
+                    key: "browser.startup.page".to_string(),
+                },
+                Event::ContainerAdded {
+                    id: "1".to_string(),
+                    name: "Work".to_string(),
+                    color: "blue".to_string(),
+                    icon: "briefcase".to_string(),
+                },
+            ])
+            .unwrap();
+
+        let changes = engine
+            .history_diff(ReplayPoint::Event(first), None)
+            .unwrap();
+        assert_eq!(changes.len(), 2);
+        assert!(changes.contains(&Event::PrefRemoved {
+            key: "browser.startup.page".to_string()
+        }));
+        assert!(matches!(changes[0], Event::ContainerAdded { .. }));
+        assert!(
+            engine
+                .history_diff(ReplayPoint::Event(first), Some(ReplayPoint::Event(first)))
+                .unwrap()
+                .is_empty()
+        );
+        // The state itself is left alone
+        assert_eq!(engine.get_materialized_containers().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_policies_mode_skips_user_js_and_extensions_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.policies.enabled = true;
+        engine.config.policies.path = dir.path().join("distribution").join("policies.json");
+        engine.config.policies.xpi_dir = dir.path().join("policy-extensions");
+        let profile = engine.profile_path().clone();
+
+        engine
+            .state_db
+            .set_pref("browser.startup.page", "3", "int")
+            .unwrap();
+        let xpi_data =
+            crate::extensions::encode_base64(&crate::extensions::compress_xpi(b"xpi").unwrap());
+        let source = crate::events::ExtensionSource::Amo {
+            amo_slug: "ext".to_string(),
+        };
+        engine
+            .state_db
+            .add_extension("ext@example.com", "Ext", None)
+            .unwrap();
+        engine
+            .state_db
+            .store_extension_xpi("ext@example.com", "1.0", &source, &xpi_data)
+            .unwrap();
+
+        // Written while the browser runs
+        std::fs::write(profile.join("extensions.json"), r#"{"addons": []}"#).unwrap();
+        std::fs::write(profile.join("lock"), "").unwrap();
+        let result = engine.sync().unwrap();
+        assert_eq!(result.profile_files_written, vec!["policies.json"]);
+        assert!(!profile.join("user.js").exists());
+        assert!(!profile.join("extensions").exists());
+
+        let content = std::fs::read_to_string(&engine.config.policies.path).unwrap();
+        let policies: serde_json::Value = serde_json::from_str(&content).unwrap();
+        let extension = &policies["policies"]["ExtensionSettings"]["ext@example.com"];
+        assert_eq!(extension["installation_mode"], "normal_installed");
+        assert!(
+            extension["install_url"]
+                .as_str()
+                .unwrap()
+                .ends_with("/policy-extensions/ext@example.com-1.0.xpi")
+        );
+        assert_eq!(
+            policies["policies"]["Preferences"]["browser.startup.page"]["Value"],
+            3
+        );
+        assert!(!engine.write_policies().unwrap());
+    }
+
+    #[test]
+    fn test_extension_installs_wait_for_browser_to_close() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        let xpi_path = profile.join("extensions").join("ext@example.com.xpi");
+
+        let xpi_data =
+            crate::extensions::encode_base64(&crate::extensions::compress_xpi(b"xpi").unwrap());
+        let source = crate::events::ExtensionSource::Amo {
+            amo_slug: "ext".to_string(),
+        };
+        engine
+            .state_db
+            .add_extension("ext@example.com", "Ext", None)
+            .unwrap();
+        engine
+            .state_db
+            .store_extension_xpi("ext@example.com", "1.0", &source, &xpi_data)
+            .unwrap();
+
+        // Queued while the browser runs
+        std::fs::write(profile.join("lock"), "").unwrap();
+        let (installed, _) = engine.sync_extensions().unwrap();
+        assert!(installed.is_empty());
+        assert!(!xpi_path.exists());
+        let queued = engine.describe_write_queue().unwrap();
+        assert_eq!(queued[0].0, "extensions/ext@example.com.xpi");
+
+        std::fs::remove_file(profile.join("lock")).unwrap();
+        engine.flush_write_queue().unwrap();
+        assert!(xpi_path.exists());
+        let prefs_js = std::fs::read_to_string(profile.join("prefs.js")).unwrap();
+        assert!(prefs_js.contains("user_pref(\"extensions.autoDisableScopes\", 14);"));
+
+        // Checked once the browser has started, and only once
+        assert!(engine.verify_extension_installs().unwrap().is_empty());
+        engine.note_browser_started().unwrap();
+        std::fs::write(profile.join("extensions.json"), r#"{"addons": []}"#).unwrap();
+        assert_eq!(
+            engine.verify_extension_installs().unwrap(),
+            vec!["ext@example.com"]
+        );
+        assert!(engine.verify_extension_installs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_start_page_written_to_user_js() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+
+        engine
+            .state_db
+            .set_start_page_pref(
+                crate::profile::HOMEPAGE_PREF,
+                &crate::events::PrefValue::String("https://example.com".to_string()),
+            )
+            .unwrap();
+
+        let written = engine.apply_to_profile().unwrap();
+        assert_eq!(written, vec!["user.js".to_string()]);
+        let user_js = std::fs::read_to_string(profile.join("user.js")).unwrap();
+        assert!(
+            user_js.contains(r#"user_pref("browser.startup.homepage", "https://example.com");"#)
+        );
+
+        // Once the browser picks it up, nothing is synced back
+        std::fs::write(profile.join("prefs.js"), user_js).unwrap();
+        assert!(
+            engine
+                .scan_category(SyncCategory::StartPage)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_addresses_only_synced_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        std::fs::write(
+            profile.join("autofill-profiles.json"),
+            r#"{"version":1,"addresses":[{"guid":"a1","given-name":"Ada"}],"creditCards":[]}"#,
+        )
+        .unwrap();
+        let remote =
+            std::collections::BTreeMap::from([("given-name".to_string(), "Charles".to_string())]);
+        engine.state_db.set_address("b2", &remote).unwrap();
+
+        assert!(
+            engine
+                .scan_category(SyncCategory::Addresses)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(engine.apply_to_profile().unwrap().is_empty());
+
+        engine.config.autofill.addresses = true;
+        let events = engine.scan_category(SyncCategory::Addresses).unwrap();
+        assert!(matches!(&events[0], Event::AddressAdded { guid, .. } if guid == "a1"));
+
+        let written = engine.apply_to_profile().unwrap();
+        assert_eq!(written, vec!["autofill-profiles.json".to_string()]);
+        assert_eq!(read_addresses(&profile).unwrap()[0].guid, "b2");
+    }
+
+    #[test]
+    fn test_permissions_respect_site_filter() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.permissions.block = vec!["bank.test".to_string()];
+        let profile = engine.profile_path().clone();
+        let conn = rusqlite::Connection::open(profile.join("permissions.sqlite")).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE moz_perms (id INTEGER PRIMARY KEY, origin TEXT, type TEXT,
+                permission INTEGER, expireType INTEGER, expireTime INTEGER,
+                modificationTime INTEGER);
+             INSERT INTO moz_perms (origin, type, permission, expireType, expireTime, modificationTime)
+             VALUES ('https://news.test', 'desktop-notification', 2, 0, 0, 0),
+                    ('https://bank.test', 'popup', 1, 0, 0, 0);",
+        )
+        .unwrap();
+
+        // Blocked sites aren't sent
+        let events = engine.scan_category(SyncCategory::Permissions).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(
+            matches!(&events[0], Event::PermissionSet { origin, .. } if origin == "https://news.test")
+        );
+
+        // ...and aren't overwritten by other devices
+        engine
+            .state_db
+            .set_permission("https://bank.test", "popup", 2)
+            .unwrap();
+        engine
+            .state_db
+            .set_permission("https://meet.test", "camera", 1)
+            .unwrap();
+        let written = engine.apply_to_profile().unwrap();
+        assert_eq!(written, vec!["permissions.sqlite".to_string()]);
+
+        let permissions = read_permissions(&profile).unwrap();
+        assert_eq!(permissions.len(), 2);
+        assert_eq!(permissions[0].origin, "https://bank.test");
+        assert_eq!(permissions[0].capability, 1);
+        assert_eq!(permissions[1].origin, "https://meet.test");
+    }
+
+    #[test]
+    fn test_cert_overrides_only_synced_when_enabled() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        std::fs::write(
+            profile.join("cert_override.txt"),
+            "nas.lan:443:\tOID.2.16.840.1.101.3.4.2.1\tAA:BB\t\n",
+        )
+        .unwrap();
+        engine
+            .state_db
+            .set_cert_override("git.lan:443:", "OID.2.16.840.1.101.3.4.2.1", "CC:DD")
+            .unwrap();
+
+        assert!(
+            engine
+                .scan_category(SyncCategory::Certificates)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(engine.apply_to_profile().unwrap().is_empty());
+
+        engine.config.certificates.overrides = true;
+        let events = engine.scan_category(SyncCategory::Certificates).unwrap();
+        assert!(
+            matches!(&events[0], Event::CertOverrideSet { host, .. } if host == "nas.lan:443:")
+        );
+
+        let written = engine.apply_to_profile().unwrap();
+        assert_eq!(written, vec!["cert_override.txt".to_string()]);
+        assert_eq!(
+            read_cert_overrides(&profile).unwrap()[0].host,
+            "git.lan:443:"
+        );
+    }
+
+    #[test]
+    fn test_container_sites_written_to_extension_storage() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        let storage = profile.join(CONTAINER_SITES_FILE);
+        std::fs::create_dir_all(storage.parent().unwrap()).unwrap();
+        std::fs::write(
+            &storage,
+            r#"{"siteContainerMap@@_mail.example.com":{"userContextId":"1","neverAsk":true}}"#,
+        )
+        .unwrap();
+
+        let events = engine.scan_category(SyncCategory::Containers).unwrap();
+        assert!(matches!(
+            &events[0],
+            Event::ContainerSiteAssigned { host, never_ask: true, .. } if host == "mail.example.com"
+        ));
+
+        engine
+            .state_db
+            .assign_container_site("shop.example.com", "2", false)
+            .unwrap();
+        let written = engine.apply_to_profile().unwrap();
+        assert_eq!(written, vec![CONTAINER_SITES_FILE.to_string()]);
+        assert_eq!(
+            read_site_assignments(&profile).unwrap()[0].host,
+            "shop.example.com"
+        );
+    }
+
+    #[test]
+    #[allow(clippy::too_many_lines)] // Walks through each way the order changes
+    fn test_container_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let profile = engine.profile_path().clone();
+        let container = |id: u32, name: &str| Container {
+            user_context_id: id,
+            name: name.to_string(),
+            icon: "circle".to_string(),
+            color: "blue".to_string(),
+            is_public: true,
+        };
+        let ids = |containers: &[Container]| -> Vec<u32> {
+            containers.iter().map(|c| c.user_context_id).collect()
+        };
+        write_containers(
+            &profile,
+            &[
+                container(3, "Banking"),
+                container(1, "Work"),
+                container(2, "Home"),
+            ],
+        )
+        .unwrap();
+
+        let events = engine.scan_category(SyncCategory::Containers).unwrap();
+        let reordered = |events: &[Event]| {
+            events
+                .iter()
+                .filter(|e| matches!(e, Event::ContainerReordered { .. }))
+                .count()
+        };
+        assert_eq!(reordered(&events), 3);
+        engine.write_and_materialize(events).unwrap();
+        assert_eq!(
+            ids(&engine.get_materialized_containers().unwrap()),
+            vec![3, 1, 2]
+        );
+
+        // Moving one container in the browser moves only that one
+        write_containers(
+            &profile,
+            &[
+                container(1, "Work"),
+                container(3, "Banking"),
+                container(2, "Home"),
+            ],
+        )
+        .unwrap();
+        let events = engine.scan_category(SyncCategory::Containers).unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Event::ContainerReordered { id, .. } if id == "1"));
+        engine.write_and_materialize(events).unwrap();
+        assert!(
+            engine
+                .scan_category(SyncCategory::Containers)
+                .unwrap()
+                .is_empty()
+        );
+
+        // The synced order is what's written to the profile
+        engine.state_db.set_container_position("2", "0V").unwrap();
+        engine.apply_to_profile().unwrap();
+        assert_eq!(ids(&read_containers(&profile).unwrap()), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn test_is_paired() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        // Answering on the network isn't enough
+        engine
+            .record_device_seen("device-a", "laptop", None)
+            .unwrap();
+        assert!(!engine.is_paired("device-a"));
+
+        engine
+            .state_db
+            .record_device_sync("device-a", "2024-01-01T10:00:00+00:00")
+            .unwrap();
+        assert!(engine.is_paired("device-a"));
+
+        engine.add_known_device("device-b".to_string(), KeyPair::generate().public_key());
+        assert!(engine.is_paired("device-b"));
+        assert!(!engine.is_paired("device-c"));
+    }
+
+    #[test]
+    fn test_paired_peer_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = test_engine(dir.path());
+
+        assert!(engine.claim_device_peer("device-a", "peer-a").unwrap());
+        assert!(engine.claim_device_peer("device-b", "peer-b").unwrap());
+        engine
+            .state_db
+            .record_device_sync("device-a", "2024-01-01T10:00:00+00:00")
+            .unwrap();
+
+        assert_eq!(
+            engine.paired_peer_ids().unwrap(),
+            vec!["peer-a".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_remote_events_over_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.limits.max_events_per_file = 2;
+        engine.config.limits.device_quota_mb = 1;
+        let event = |size: usize| EncryptedEvent {
+            id: uuid::Uuid::now_v7().to_string(),
+            device_id: "device-a".to_string(),
+            counter: 1,
+            ciphertext: vec![0; size],
+            public_key: Vec::new(),
+            cipher: 1,
+            nonce: Vec::new(),
+            key_epoch: 2,
+            hops: Vec::new(),
+            chunks: Vec::new(),
+        };
+
+        // Within limits, but not an event file, so nothing is stored
+        assert_eq!(engine.apply_remote_events(vec![event(10)]).unwrap(), 0);
+        assert!(engine.apply_remote_events(vec![event(10); 3]).is_err());
+
+        // Together they'd take device-a over its quota
+        let half = 600 * 1024;
+        assert!(
+            engine
+                .apply_remote_events(vec![event(half), event(half)])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_events_forwarded_between_unreachable_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        // One pack secret, standing in for pairing
+        let keypair = KeyPair::generate();
+        let engine = |device_id: &str| {
+            let mut engine = test_engine(&dir.path().join(device_id));
+            engine.config.device.id = device_id.to_string();
+            engine.event_log = SharedEventLog::new(EventLog::new(
+                engine.config.paths.sync_dir.clone(),
+                device_id.to_string(),
+                keypair.clone(),
+            ));
+            engine
+        };
+        let mut a = engine("device-a");
+        let mut b = engine("device-b");
+        let mut c = engine("device-c");
+
+        a.write_events(vec![Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: crate::events::PrefValue::Int(3),
+        }])
+        .unwrap();
+
+        // A reaches B, and B reaches C
+        let from_a = a
+            .get_events_since(&b.event_log.lock().held_files().unwrap(), None)
+            .unwrap()
+            .events;
+        assert!(from_a[0].hops.is_empty());
+        assert_eq!(b.apply_remote_events(from_a).unwrap(), 1);
+        let from_b = b
+            .get_events_since(&c.event_log.lock().held_files().unwrap(), None)
+            .unwrap()
+            .events;
+        assert_eq!(from_b.len(), 1);
+        assert_eq!(from_b[0].device_id, "device-a");
+        assert_eq!(from_b[0].hops, vec!["device-b"]);
+        assert_eq!(c.apply_remote_events(from_b).unwrap(), 1);
+        assert_eq!(c.query_prefs().unwrap().len(), 1);
+
+        // Passed back to B, or back to A, it goes nowhere
+        let from_c = c
+            .get_events_since(&HeldFiles::default(), None)
+            .unwrap()
+            .events;
+        assert_eq!(from_c[0].hops, vec!["device-b", "device-c"]);
+        assert_eq!(b.apply_remote_events(from_c.clone()).unwrap(), 0);
+        assert_eq!(a.apply_remote_events(from_c).unwrap(), 0);
+
+        // Nothing is forwarded with forwarding off
+        b.config.sync.forward_events = false;
+        assert!(
+            b.get_events_since(&HeldFiles::default(), None)
+                .unwrap()
+                .events
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_claim_device_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = test_engine(dir.path());
+
+        // Unpaired devices can move between peers
+        assert!(engine.claim_device_peer("device-a", "peer-a").unwrap());
+        assert!(engine.claim_device_peer("device-a", "peer-b").unwrap());
+
+        // Paired ones stay with the peer they're bound to
+        engine
+            .state_db
+            .record_device_sync("device-a", "2024-01-01T10:00:00+00:00")
+            .unwrap();
+        assert!(!engine.claim_device_peer("device-a", "peer-c").unwrap());
+        assert!(engine.claim_device_peer("device-a", "peer-b").unwrap());
+        assert_eq!(
+            engine.paired_peer_ids().unwrap(),
+            vec!["peer-b".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_bind_proven_device() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (a, b) = (test_engine(dir_a.path()), test_engine(dir_b.path()));
+        let a_id = a.config.device.id.clone();
+        let challenge = [3u8; 32];
+        let proof = a
+            .prove_device(
+                &hex::encode(challenge),
+                &public_key_to_hex(&b.public_key()),
+                "peer-a",
+            )
+            .unwrap();
+
+        // Without a key on file there's nothing to prove against
+        assert!(b.device_key(&a_id).unwrap().is_none());
+        assert!(
+            !b.bind_proven_device(&a_id, "peer-a", &challenge, &proof)
+                .unwrap()
+        );
+
+        let keys = b.sync_dir().join("keys");
+        std::fs::create_dir_all(&keys).unwrap();
+        let key = public_key_to_hex(&a.public_key());
+        std::fs::write(keys.join(format!("{}.pub", a_id)), key).unwrap();
+        assert_eq!(b.device_key(&a_id).unwrap(), Some(a.public_key()));
+        assert!(b.device_key("../keys/x").unwrap().is_none());
+
+        // A proof moves the device even off the peer it was bound to
+        b.state_db
+            .record_device_sync(&a_id, "2024-01-01T10:00:00+00:00")
+            .unwrap();
+        assert!(b.claim_device_peer(&a_id, "peer-old").unwrap());
+        assert!(
+            !b.bind_proven_device(&a_id, "peer-b", &challenge, &proof)
+                .unwrap()
+        );
+        assert!(
+            !b.bind_proven_device(&a_id, "peer-a", &[4u8; 32], &proof)
+                .unwrap()
+        );
+        assert!(
+            !b.bind_proven_device(&a_id, "peer-a", &challenge, "zz")
+                .unwrap()
+        );
+        assert_eq!(
+            b.state_db.get_device_peer_id(&a_id).unwrap().as_deref(),
+            Some("peer-old")
+        );
+        assert!(
+            b.bind_proven_device(&a_id, "peer-a", &challenge, &proof)
+                .unwrap()
+        );
+        assert_eq!(
+            b.state_db.get_device_peer_id(&a_id).unwrap().as_deref(),
+            Some("peer-a")
+        );
+    }
+
+    #[test]
+    fn test_verify_device_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = test_engine(dir.path());
+        let key = public_key_to_hex(&KeyPair::generate().public_key());
+        assert!(engine.verify_device_key("device-a", &key).is_err());
+
+        let keys = engine.sync_dir().join("keys");
+        std::fs::create_dir_all(&keys).unwrap();
+        std::fs::write(keys.join("device-a.pub"), &key).unwrap();
+        let other = public_key_to_hex(&KeyPair::generate().public_key());
+        assert!(engine.verify_device_key("device-a", &other).is_err());
+
+        engine.verify_device_key("device-a", &key).unwrap();
+        let record = engine.state_db.get_device("device-a").unwrap().unwrap();
+        assert_eq!(record.verified_key, Some(key));
+    }
+
+    #[test]
+    fn test_unbind_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = test_engine(dir.path());
+        engine
+            .state_db
+            .record_device_sync("device-a", "2024-01-01T10:00:00+00:00")
+            .unwrap();
+        assert!(engine.claim_device_peer("device-a", "peer-a").unwrap());
+        assert!(!engine.claim_device_peer("device-a", "peer-b").unwrap());
+
+        assert_eq!(engine.unbind_device("device-a").unwrap(), "device-a");
+        assert!(engine.unbind_device("device-a").is_err());
+        assert!(engine.claim_device_peer("device-a", "peer-b").unwrap());
+    }
+
+    #[test]
+    fn test_paused_leaves_sideloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let xpi_data =
+            crate::extensions::encode_base64(&crate::extensions::compress_xpi(b"xpi").unwrap());
+        let source = crate::events::ExtensionSource::Amo {
+            amo_slug: "ext".to_string(),
+        };
+        engine
+            .state_db
+            .store_extension_xpi("ext@example.com", "1.0", &source, &xpi_data)
+            .unwrap();
+        let json = serde_json::json!({
+            "schemaVersion": 36,
+            "addons": [
+                {"id": "ext@example.com", "location": "app-profile", "userDisabled": true,
+                 "seen": false, "active": false, "appDisabled": false, "type": "extension"}
+            ]
+        })
+        .to_string();
+        let extensions_json = engine.profile_path.join("extensions.json");
+        std::fs::write(&extensions_json, &json).unwrap();
+
+        engine.pause(None, None).unwrap();
+        assert!(engine.enable_sideloaded_extensions().unwrap().is_empty());
+        assert_eq!(std::fs::read_to_string(&extensions_json).unwrap(), json);
+
+        engine.resume(None).unwrap();
+        assert_eq!(
+            engine.enable_sideloaded_extensions().unwrap(),
+            vec!["ext@example.com"]
+        );
+        assert_ne!(std::fs::read_to_string(&extensions_json).unwrap(), json);
+    }
+
+    #[test]
+    fn test_rename_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let config_path = dir.path().join("config.toml");
+        engine.config.save(&config_path).unwrap();
+        engine.set_config_path(config_path.clone());
+        engine
+            .state_db
+            .record_device_seen(
+                "device-a",
+                Some("laptop"),
+                None,
+                "2024-01-01T10:00:00+00:00",
+            )
+            .unwrap();
+
+        assert_eq!(
+            engine.rename_device("laptop", "kitchen laptop").unwrap(),
+            "device-a"
+        );
+        assert_eq!(engine.device_name("device-a"), "kitchen laptop");
+        assert!(engine.rename_device("laptop", "desk").is_err());
+
+        // Renaming this device also updates its config
+        let this_device = engine.device_id().to_string();
+        engine.rename_device(&this_device, "study desktop").unwrap();
+        assert_eq!(engine.device_name(&this_device), "study desktop");
+        let saved = Config::load(&config_path).unwrap();
+        assert_eq!(saved.device.name, "study desktop");
+    }
+
+    #[test]
+    fn test_label_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.state_db.rename_device("device-a", "laptop").unwrap();
+
+        engine
+            .label_device("laptop", Some("🏠"), Some("green"))
+            .unwrap();
+        assert_eq!(engine.device_label("device-a"), "🏠 laptop");
+        assert!(engine.label_device("laptop", None, Some("mauve")).is_err());
+        assert!(
+            engine
+                .label_device("laptop", Some("home laptop"), None)
+                .is_err()
+        );
+
+        // This device is listed first, with its label
+        let this_device = engine.device_id().to_string();
+        engine.label_device(&this_device, Some("💻"), None).unwrap();
+        let devices = engine.devices().unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].id, this_device);
+        assert_eq!(devices[0].emoji.as_deref(), Some("💻"));
+
+        engine.label_device("laptop", None, None).unwrap();
+        assert_eq!(engine.device_label("device-a"), "laptop");
+    }
+
+    #[test]
+    fn test_pack_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let config_path = dir.path().join("config.toml");
+        engine.config.save(&config_path).unwrap();
+        engine.set_config_path(config_path.clone());
+
+        // Nothing to share until a setting is changed
+        assert_eq!(engine.publish_pack_config().unwrap(), 0);
+        engine.config.prefs.whitelist = vec!["browser.*".to_string()];
+        assert_eq!(engine.publish_pack_config().unwrap(), 1);
+        assert_eq!(engine.publish_pack_config().unwrap(), 0);
+
+        // Another device's change is applied and saved
+        let events = vec![EventEnvelope::new(
+            "device-b".to_string(),
+            VectorClock::new(),
+            Event::PackConfigSet {
+                key: "sync.disabled_categories".to_string(),
+                value: vec!["tabs".to_string()],
+            },
+        )];
+        materialize_events(&engine.state_db, &events, engine.device_id()).unwrap();
+        engine.adopt_pack_config().unwrap();
+        assert!(engine.paused_categories().contains(&SyncCategory::Tabs));
+        let saved = Config::load(&config_path).unwrap();
+        assert_eq!(saved.sync.disabled_categories, vec!["tabs".to_string()]);
+        assert_eq!(engine.publish_pack_config().unwrap(), 0);
+
+        // Raising the key epoch on one device raises it everywhere
+        let events = vec![EventEnvelope::new(
+            "device-b".to_string(),
+            VectorClock::new(),
+            Event::PackConfigSet {
+                key: "sync.key_epoch".to_string(),
+                value: vec!["2".to_string()],
+            },
+        )];
+        materialize_events(&engine.state_db, &events, engine.device_id()).unwrap();
+        engine.adopt_pack_config().unwrap();
+        assert_eq!(engine.config.sync.key_epoch, 2);
+        assert_eq!(Config::load(&config_path).unwrap().sync.key_epoch, 2);
+        let file = engine
+            .write_events(vec![Event::ReadingListRemoved {
+                url: "https://example.com".to_string(),
+            }])
+            .unwrap()
+            .unwrap();
+        assert_eq!(EventFile::load(&file).unwrap().key_epoch, KeyEpoch::Hkdf);
+    }
+
+    #[test]
+    fn test_publish_onion_address() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let onion = "vww6ybal4bd7szmgncyruucpgfkqahzddi37ktceo3ah7ngmcopnpyyd.onion:4001";
+
+        assert!(!engine.publish_onion_address().unwrap());
+
+        engine.config.net.onion_address = Some(onion.to_string());
+        assert!(engine.publish_onion_address().unwrap());
+        assert!(!engine.publish_onion_address().unwrap());
+        let devices = engine.devices().unwrap();
+        assert_eq!(devices[0].onion.as_deref(), Some(onion));
+        // Only other devices' endpoints are dialed
+        assert!(engine.onion_endpoints().unwrap().is_empty());
+
+        engine.config.net.onion_address = Some("example.com:4001".to_string());
+        assert!(engine.publish_onion_address().is_err());
+
+        engine.config.net.onion_address = None;
+        assert!(engine.publish_onion_address().unwrap());
+        assert!(engine.devices().unwrap()[0].onion.is_none());
+
+        engine
+            .state_db
+            .set_device_onion("device-a", Some(onion))
+            .unwrap();
+        assert_eq!(
+            engine.onion_endpoints().unwrap(),
+            vec![("device-a".to_string(), onion.to_string())]
+        );
+    }
+
+    #[test]
+    fn test_publish_installed_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let addon = |id: &str| {
+            format!(
+                r#"{{"id": "{}", "type": "extension", "name": "{}"}}"#,
+                id, id
+            )
+        };
+        let write_addons = |engine: &SyncEngine, ids: &[&str]| {
+            let addons: Vec<String> = ids.iter().map(|id| addon(id)).collect();
+            std::fs::write(
+                engine.profile_path().join("extensions.json"),
+                format!(r#"{{"addons": [{}]}}"#, addons.join(", ")),
+            )
+            .unwrap();
+        };
+        for (id, name) in [("a@example.com", "A"), ("b@example.com", "B")] {
+            engine.state_db.add_extension(id, name, None).unwrap();
+        }
+
+        write_addons(&engine, &["a@example.com", "b@example.com"]);
+        assert!(engine.publish_installed_extensions().unwrap());
+        assert!(!engine.publish_installed_extensions().unwrap());
+
+        // Another device reported only one of them
+        engine
+            .state_db
+            .set_device_extensions("device-b", &["a@example.com".to_string()])
+            .unwrap();
+        let matrix = engine.extension_matrix().unwrap();
+        assert_eq!(matrix.devices.len(), 2);
+        let missing: Vec<&str> = matrix
+            .extensions
+            .iter()
+            .filter(|row| row.is_missing())
+            .map(|row| row.id.as_str())
+            .collect();
+        assert_eq!(missing, vec!["b@example.com"]);
+
+        // Removing one here is reported too
+        write_addons(&engine, &["a@example.com"]);
+        assert!(engine.publish_installed_extensions().unwrap());
+        let events = engine.event_log.lock().read_all_events(&[]).unwrap();
+        assert!(matches!(
+            &events.last().unwrap().event,
+            Event::DeviceExtensionsSet { extensions, .. } if extensions.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_publish_device_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        std::fs::write(
+            engine.profile_path().join("extensions.json"),
+            r#"{"addons": [{"id": "a@example.com", "type": "extension", "version": "1.2"}]}"#,
+        )
+        .unwrap();
+        std::fs::write(
+            engine.profile_path().join("compatibility.ini"),
+            "[Compatibility]\nLastVersion=128.0_20240708103046/20240708103046\n",
+        )
+        .unwrap();
+
+        engine.publish_device_state().unwrap();
+        let reports = engine.state_db.get_device_reports().unwrap();
+        assert_eq!(reports.len(), 1);
+        let report = &reports[0];
+        assert_eq!(report.device, engine.config.device.id);
+        assert_eq!(report.extensions["a@example.com"], "1.2");
+        assert_eq!(report.browser_version.as_deref(), Some("128.0"));
+        assert_eq!(report.wolfpack_version, env!("CARGO_PKG_VERSION"));
+        assert!(report.last_scan.is_none());
+    }
+
+    #[test]
+    fn test_prune_tabs_for_unseen_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let long_ago = (chrono::Utc::now() - chrono::TimeDelta::days(30)).to_rfc3339();
+        let recently = chrono::Utc::now().to_rfc3339();
+
+        for (id, device) in [
+            ("01900000-0000-7000-8000-000000000001", "old-laptop"),
+            ("01900000-0000-7000-8000-000000000002", "phone"),
+            ("01900000-0000-7000-8000-000000000003", "new-desktop"),
+        ] {
+            engine
+                .state_db
+                .add_sent_tab(id, device, "https://example.com", None, &long_ago)
+                .unwrap();
+        }
+        engine
+            .state_db
+            .record_device_seen("old-laptop", None, None, &long_ago)
+            .unwrap();
+        engine
+            .state_db
+            .record_device_seen("phone", None, None, &recently)
+            .unwrap();
+
+        // Only the phone has been seen within the expiry
+        assert_eq!(engine.prune_stale_tabs().unwrap(), 2);
+        let remaining = engine.state_db.get_sent_tabs().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].to_device, "phone");
+
+        engine.config.sync.tab_expiry_days = 0;
+        engine
+            .state_db
+            .add_sent_tab(
+                "01900000-0000-7000-8000-000000000004",
+                "old-laptop",
+                "https://example.com",
+                None,
+                &long_ago,
+            )
+            .unwrap();
+        assert_eq!(engine.prune_stale_tabs().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let long_ago = (chrono::Utc::now() - chrono::TimeDelta::days(60)).to_rfc3339();
+        let recently = chrono::Utc::now().to_rfc3339();
+
+        let tabs = [
+            ("01900000-0000-7000-8000-000000000001", &long_ago),
+            ("01900000-0000-7000-8000-000000000002", &long_ago),
+            ("01900000-0000-7000-8000-000000000003", &long_ago),
+            ("01900000-0000-7000-8000-000000000004", &recently),
+        ];
+        for (id, sent_at) in tabs {
+            engine
+                .state_db
+                .add_sent_tab(id, "phone", "https://example.com", None, sent_at)
+                .unwrap();
+        }
+        engine.state_db.mark_tab_opened(tabs[0].0).unwrap();
+        engine.state_db.mark_tab_delivered(tabs[1].0).unwrap();
+        engine.state_db.mark_tab_opened(tabs[3].0).unwrap();
+        engine
+            .state_db
+            .receive_pending_tab(tabs[0].0, &long_ago)
+            .unwrap();
+        engine
+            .state_db
+            .receive_pending_tab(tabs[1].0, &recently)
+            .unwrap();
+
+        // Old pending tabs are left for expiry, recent ones for `tabs --sent`
+        let report = engine.collect_garbage().unwrap();
+        assert_eq!(report.opened_tabs, 1);
+        assert_eq!(report.delivered_tabs, 1);
+        assert_eq!(report.received_tabs, 1);
+        let remaining: Vec<_> = engine
+            .state_db
+            .get_sent_tabs()
+            .unwrap()
+            .into_iter()
+            .map(|tab| tab.id)
+            .collect();
+        assert_eq!(remaining, vec![tabs[2].0, tabs[3].0]);
+
+        engine.config.sync.tab_retention_days = 0;
+        engine.state_db.mark_tab_opened(tabs[2].0).unwrap();
+        assert_eq!(engine.collect_garbage().unwrap().opened_tabs, 0);
+    }
+
+    #[test]
+    fn test_send_tab_delivery() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        engine
+            .state_db
+            .record_device_seen(
+                "phone-id",
+                Some("Phone"),
+                None,
+                &chrono::Utc::now().to_rfc3339(),
+            )
+            .unwrap();
+
+        // Devices are found by name, and unknown devices are refused
+        assert!(engine.send_tab("tablet", "example.com", None).is_err());
+        assert!(
+            engine
+                .send_tab("Phone", "javascript:alert(1)", None)
+                .is_err()
+        );
+        let tab_id = engine
+            .send_tab("Phone", "example.com", Some("Example"))
+            .unwrap()
+            .id;
+        let due = engine.tabs_to_deliver().unwrap();
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, tab_id);
+        assert_eq!(due[0].to_device, "phone-id");
+        assert_eq!(due[0].url, "https://example.com/");
+
+        // Not retried until the backoff passes
+        engine.record_tab_attempt(&due[0]).unwrap();
+        assert!(engine.tabs_to_deliver().unwrap().is_empty());
+
+        assert!(engine.mark_tab_delivered(&tab_id).unwrap());
+        let sent = engine.get_sent_tabs().unwrap();
+        assert_eq!(sent[0].status, TabDelivery::Delivered);
+        assert_eq!(sent[0].attempts, 1);
+    }
+
+    #[test]
+    fn test_acknowledge_tab() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        let sent = EventEnvelope::new(
+            "phone-id".to_string(),
+            VectorClock::new(),
+            Event::TabSent {
+                to_device: engine.device_id().to_string(),
+                url: "https://example.com".to_string(),
+                title: None,
+            },
+        );
+        let tab_id = sent.id.to_string();
+        engine
+            .receive_tab("https://example.com", None, "phone-id", Some(&tab_id))
+            .unwrap();
+        assert_eq!(engine.get_pending_tabs().unwrap()[0].id, tab_id);
+
+        // The acknowledgment refers to the sending event
+        engine.acknowledge_tab(&tab_id).unwrap();
+        assert!(engine.get_pending_tabs().unwrap().is_empty());
+        let events = engine
+            .event_log
+            .lock()
+            .read_all_events(&engine.known_devices)
+            .unwrap();
+        assert!(matches!(
+            events[0].event,
+            Event::TabReceived { event_id } if event_id == sent.id
+        ));
+
+        // Neither the event syncing in later nor another delivery brings it back
+        let device = engine.device_id().to_string();
+        materialize_events(&engine.state_db, &[sent], &device).unwrap();
+        engine
+            .receive_tab("https://example.com", None, "phone-id", Some(&tab_id))
+            .unwrap();
+        assert!(engine.get_pending_tabs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_tabs() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        let ids: Vec<String> = (0..4).map(|_| uuid::Uuid::now_v7().to_string()).collect();
+        let receive = |engine: &mut SyncEngine, from: &str, id: &str| {
+            engine
+                .receive_tab("https://example.com", None, from, Some(id))
+                .unwrap()
+        };
+
+        assert!(receive(&mut engine, "phone-id", &ids[0]));
+        // Neither the same tab delivered again nor the same URL sent again is
+        // new, but another device's is
+        assert!(!receive(&mut engine, "phone-id", &ids[0]));
+        assert!(!receive(&mut engine, "phone-id", &ids[1]));
+        assert!(receive(&mut engine, "laptop-id", &ids[2]));
+
+        let tabs = engine.get_pending_tabs().unwrap();
+        assert_eq!(tabs.len(), 2);
+        assert_eq!(tabs[0].id, ids[0]);
+        assert_eq!(tabs[0].duplicates, vec![ids[1].clone()]);
+
+        // Opening it acknowledges the duplicate too
+        engine.acknowledge_tab(&ids[0]).unwrap();
+        let events = engine
+            .event_log
+            .lock()
+            .read_all_events(&engine.known_devices)
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(engine.get_pending_tabs().unwrap().len(), 1);
+
+        engine.config.sync.tab_dedup_minutes = 0;
+        assert!(receive(&mut engine, "laptop-id", &ids[3]));
+        assert_eq!(engine.get_pending_tabs().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_fold_duplicate_tabs() {
+        let tab = |id: &str, url: &str, sent_at: &str| PendingTab {
+            id: id.to_string(),
+            url: url.to_string(),
+            title: None,
+            from_device: "phone-id".to_string(),
+            sent_at: sent_at.to_string(),
+            duplicates: Vec::new(),
+        };
+        let tabs = vec![
+            tab("a", "https://example.com", "2024-01-01T00:00:00Z"),
+            tab("b", "https://other.com", "2024-01-01T00:01:00Z"),
+            tab("c", "https://example.com", "2024-01-01T00:10:00Z"),
+            tab("d", "https://example.com", "2024-01-01T02:00:00Z"),
+        ];
+
+        let folded = fold_duplicate_tabs(tabs, chrono::TimeDelta::minutes(60));
+        let ids: Vec<_> = folded.iter().map(|tab| tab.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "b", "d"]);
+        assert_eq!(folded[0].duplicates, vec!["c".to_string()]);
+    }
+
+    #[test]
+    fn test_import_state() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        let source = StateDb::open_in_memory().unwrap();
+        source
+            .add_container("1", "Work", "blue", "briefcase")
+            .unwrap();
+        source.set_pref("browser.startup.page", "3", "int").unwrap();
+        let export = StateExport::from_db(&source, "other-device").unwrap();
+
+        assert_eq!(engine.import_state(&export).unwrap(), 2);
+        let state = engine.export_state().unwrap();
+        assert_eq!(state.containers, export.containers);
+        assert_eq!(state.prefs, export.prefs);
+
+        // Imported entries are written as events for the rest of the pack
+        let events = engine
+            .event_log
+            .lock()
+            .read_all_events(&engine.known_devices)
+            .unwrap();
+        assert_eq!(events.len(), 2);
+
+        // Importing again changes nothing
+        assert_eq!(engine.import_state(&export).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_reconcile_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        assert_eq!(engine.reconcile_manifest().unwrap(), 0);
+
+        let manifest = dir.path().join("profile.toml");
+        std::fs::write(
+            &manifest,
+            "[prefs]\n\"browser.startup.page\" = 3\n\"browser.tabs.warnOnClose\" = false\n",
+        )
+        .unwrap();
+        engine.config.paths.manifest = Some(manifest);
+        engine
+            .state_db
+            .set_pref("browser.startup.page", "1", "int")
+            .unwrap();
+
+        // Paused categories are left to drift
+        engine.pause(Some(SyncCategory::Prefs), None).unwrap();
+        assert_eq!(engine.reconcile_manifest().unwrap(), 0);
+        engine.resume(Some(SyncCategory::Prefs)).unwrap();
+
+        assert_eq!(engine.reconcile_manifest().unwrap(), 2);
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs.get("browser.startup.page"), Some(&PrefValue::Int(3)));
+        assert_eq!(engine.reconcile_manifest().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sync_writes_one_file_per_cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+        engine.config.prefs.whitelist = vec!["general.smoothScroll".to_string()];
+        std::fs::write(
+            engine.profile_path().join("prefs.js"),
+            "user_pref(\"general.smoothScroll\", false);\n",
+        )
+        .unwrap();
+        std::fs::write(
+            engine.profile_path().join("extensions.json"),
+            r#"{"addons": []}"#,
+        )
+        .unwrap();
+        let manifest = dir.path().join("profile.toml");
+        std::fs::write(&manifest, "[prefs]\n\"browser.startup.page\" = 3\n").unwrap();
+        engine.config.paths.manifest = Some(manifest);
+
+        // A profile change and manifest drift go in the same file
+        let result = engine.sync().unwrap();
+        assert_eq!(result.outbound_written, 2);
+        assert!(result.event_file.is_some());
+        let own = engine.device_id().to_string();
+        assert_eq!(engine.event_log.lock().device_files().unwrap()[&own].0, 1);
+        assert_eq!(engine.get_vector_clock().get(&own), Some(&1));
+        let prefs = engine.get_materialized_prefs().unwrap();
+        assert_eq!(prefs.get("browser.startup.page"), Some(&PrefValue::Int(3)));
+    }
+
+    #[test]
+    fn test_tab_retry_delay() {
+        assert_eq!(tab_retry_delay(1), chrono::TimeDelta::seconds(30));
+        assert_eq!(tab_retry_delay(3), chrono::TimeDelta::minutes(2));
+        assert_eq!(tab_retry_delay(40), TAB_RETRY_MAX);
+    }
+
+    #[test]
+    fn test_reading_list_add_and_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = test_engine(dir.path());
+
+        engine
+            .add_to_reading_list("https://example.com", Some("Example"))
+            .unwrap();
+        let items = engine.get_reading_list().unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].title.as_deref(), Some("Example"));
+
+        engine
+            .remove_from_reading_list("https://example.com")
+            .unwrap();
+        assert!(engine.get_reading_list().unwrap().is_empty());
+        assert!(
+            engine
+                .remove_from_reading_list("https://example.com")
+                .is_err()
+        );
+    }
 
     #[test]
     fn test_parse_pref_value_bool_true() {
@@ -0,0 +1,548 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use crate::events::{Event, PrefValue, SearchEngineDetails};
+use crate::profile::SiteAssignment;
+use crate::state::StateDb;
+
+use super::engine::parse_pref_value;
+use super::home_manager::render_home_manager;
+
+/// Layout version of exported state, bumped when fields change meaning
+pub const EXPORT_VERSION: u32 = 1;
+
+/// Directory holding the files of a tar export
+const TAR_DIR: &str = "wolfpack-export";
+
+/// File of a tar export holding the fields that aren't a category
+const MANIFEST_FILE: &str = "manifest";
+
+/// Materialized state in a decrypted, human-readable form
+///
+/// Extensions are listed by ID and name only; their XPIs stay in the
+/// state database.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateExport {
+    pub version: u32,
+    pub exported_at: String,
+    /// Device the state was exported from
+    pub device_id: String,
+    #[serde(default)]
+    pub extensions: Vec<ExportedExtension>,
+    #[serde(default)]
+    pub prefs: BTreeMap<String, PrefValue>,
+    #[serde(default)]
+    pub containers: Vec<ExportedContainer>,
+    #[serde(default)]
+    pub container_sites: Vec<SiteAssignment>,
+    #[serde(default)]
+    pub search_engines: Vec<ExportedSearchEngine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedExtension {
+    pub id: String,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedContainer {
+    pub id: String,
+    pub name: String,
+    pub color: String,
+    pub icon: String,
+    /// Position key in the container order, if it's been placed
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub position: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedSearchEngine {
+    pub id: String,
+    pub name: String,
+    pub url: String,
+    #[serde(default)]
+    pub is_default: bool,
+    #[serde(default)]
+    pub details: SearchEngineDetails,
+}
+
+/// Format of an export file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// A single JSON document
+    Json,
+    /// A tar archive with a JSON file per category
+    Tar,
+    /// A home-manager `programs.librewolf` module, which can't be imported
+    HomeManager,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "json" => Some(Self::Json),
+            "tar" => Some(Self::Tar),
+            "home-manager" => Some(Self::HomeManager),
+            _ => None,
+        }
+    }
+}
+
+impl StateExport {
+    /// Read the materialized state of a state database
+    pub fn from_db(db: &StateDb, device_id: &str) -> Result<Self> {
+        let mut extensions: Vec<ExportedExtension> = db
+            .get_extensions()?
+            .into_iter()
+            .map(|(id, name, url)| ExportedExtension { id, name, url })
+            .collect();
+        extensions.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let search_engines = db
+            .get_search_engines()?
+            .into_iter()
+            .map(|engine| ExportedSearchEngine {
+                id: engine.id,
+                name: engine.name,
+                url: engine.url,
+                is_default: engine.is_default,
+                details: engine.details,
+            })
+            .collect();
+
+        Ok(Self {
+            version: EXPORT_VERSION,
+            exported_at: chrono::Utc::now().to_rfc3339(),
+            device_id: device_id.to_string(),
+            extensions,
+            prefs: read_prefs(db)?,
+            containers: read_containers(db)?,
+            container_sites: db.get_container_sites()?,
+            search_engines,
+        })
+    }
+
+    /// Events that bring `current` state up to this export
+    ///
+    /// Importing only adds and updates: anything in `current` but missing
+    /// from the export is left alone.
+    pub fn changes_from(&self, current: &StateExport) -> Vec<Event> {
+        let mut events = Vec::new();
+
+        for extension in &self.extensions {
+            if !current.extensions.contains(extension) {
+                events.push(Event::ExtensionAdded {
+                    id: extension.id.clone(),
+                    name: extension.name.clone(),
+                    url: extension.url.clone(),
+                });
+            }
+        }
+
+        for container in &self.containers {
+            let known = current.containers.iter().find(|c| c.id == container.id);
+            if known.is_none_or(|c| {
+                (&c.name, &c.color, &c.icon) != (&container.name, &container.color, &container.icon)
+            }) {
+                events.push(Event::ContainerAdded {
+                    id: container.id.clone(),
+                    name: container.name.clone(),
+                    color: container.color.clone(),
+                    icon: container.icon.clone(),
+                });
+            }
+            if !container.position.is_empty()
+                && known.is_none_or(|c| c.position != container.position)
+            {
+                events.push(Event::ContainerReordered {
+                    id: container.id.clone(),
+                    position: container.position.clone(),
+                });
+            }
+        }
+
+        for site in &self.container_sites {
+            if !current.container_sites.contains(site) {
+                events.push(Event::ContainerSiteAssigned {
+                    host: site.host.clone(),
+                    container_id: site.container_id.clone(),
+                    never_ask: site.never_ask,
+                });
+            }
+        }
+
+        for (key, value) in &self.prefs {
+            if current.prefs.get(key) != Some(value) {
+                events.push(Event::PrefSet {
+                    key: key.clone(),
+                    value: value.clone(),
+                });
+            }
+        }
+
+        self.push_search_engine_changes(current, &mut events);
+        events
+    }
+
+    /// Events that turn `earlier` state into this one, removals included
+    pub fn changes_since(&self, earlier: &StateExport) -> Vec<Event> {
+        let mut events = self.changes_from(earlier);
+
+        for extension in &earlier.extensions {
+            if !self.extensions.iter().any(|e| e.id == extension.id) {
+                events.push(Event::ExtensionRemoved {
+                    id: extension.id.clone(),
+                });
+            }
+        }
+        for container in &earlier.containers {
+            if !self.containers.iter().any(|c| c.id == container.id) {
+                events.push(Event::ContainerRemoved {
+                    id: container.id.clone(),
+                });
+            }
+        }
+        for site in &earlier.container_sites {
+            if !self.container_sites.iter().any(|s| s.host == site.host) {
+                events.push(Event::ContainerSiteUnassigned {
+                    host: site.host.clone(),
+                });
+            }
+        }
+        for key in earlier.prefs.keys() {
+            if !self.prefs.contains_key(key) {
+                events.push(Event::PrefRemoved { key: key.clone() });
+            }
+        }
+        for engine in &earlier.search_engines {
+            if !self.search_engines.iter().any(|e| e.id == engine.id) {
+                events.push(Event::SearchEngineRemoved {
+                    id: engine.id.clone(),
+                });
+            }
+        }
+        events
+    }
+
+    /// Search engines are updated in place, and the default is only
+    /// changed if the export has one
+    fn push_search_engine_changes(&self, current: &StateExport, events: &mut Vec<Event>) {
+        for engine in &self.search_engines {
+            let existing = current.search_engines.iter().find(|e| e.id == engine.id);
+            if existing.is_none_or(|e| {
+                e.name != engine.name || e.url != engine.url || e.details != engine.details
+            }) {
+                events.push(Event::SearchEngineAdded {
+                    id: engine.id.clone(),
+                    name: engine.name.clone(),
+                    url: engine.url.clone(),
+                    details: engine.details.clone(),
+                });
+            }
+        }
+
+        let default = self.search_engines.iter().find(|e| e.is_default);
+        let current_default = current.search_engines.iter().find(|e| e.is_default);
+        if let Some(default) = default
+            && current_default.is_none_or(|e| e.id != default.id)
+        {
+            events.push(Event::SearchEngineDefault {
+                id: default.id.clone(),
+            });
+        }
+    }
+
+    pub fn write(&self, format: ExportFormat, mut writer: impl Write) -> Result<()> {
+        match format {
+            ExportFormat::Json => self.write_json(writer),
+            ExportFormat::Tar => self.write_tar(writer),
+            ExportFormat::HomeManager => {
+                writer.write_all(render_home_manager(self).as_bytes())?;
+                Ok(())
+            }
+        }
+    }
+
+    fn write_json(&self, mut writer: impl Write) -> Result<()> {
+        serde_json::to_writer_pretty(&mut writer, self).context("Failed to serialize state")?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Write a tar archive with the manifest and each category in its
+    /// own JSON file, e.g. `wolfpack-export/prefs.json`
+    fn write_tar(&self, writer: impl Write) -> Result<()> {
+        let Value::Object(fields) = serde_json::to_value(self)? else {
+            bail!("State export is not a JSON object");
+        };
+
+        let mut manifest = Map::new();
+        let mut files = Vec::new();
+        for (key, value) in fields {
+            if value.is_array() || value.is_object() {
+                files.push((key.replace('_', "-"), value));
+            } else {
+                manifest.insert(key, value);
+            }
+        }
+        files.insert(0, (MANIFEST_FILE.to_string(), Value::Object(manifest)));
+
+        let mut builder = tar::Builder::new(writer);
+        for (name, value) in files {
+            let mut content = serde_json::to_vec_pretty(&value)?;
+            content.push(b'\n');
+
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_mtime(chrono::Utc::now().timestamp().max(0) as u64);
+            header.set_cksum();
+            builder.append_data(
+                &mut header,
+                format!("{}/{}.json", TAR_DIR, name),
+                content.as_slice(),
+            )?;
+        }
+        builder.into_inner()?.flush()?;
+        Ok(())
+    }
+
+    /// Read an export in either format
+    pub fn read(mut reader: impl Read) -> Result<Self> {
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content)?;
+
+        let export: Self = if is_tar(&content) {
+            serde_json::from_value(read_tar(&content)?)
+        } else {
+            serde_json::from_slice(&content)
+        }
+        .context("Failed to parse state export")?;
+
+        if export.version > EXPORT_VERSION {
+            bail!(
+                "State export version {} is newer than this wolfpack supports ({})",
+                export.version,
+                EXPORT_VERSION
+            );
+        }
+        Ok(export)
+    }
+}
+
+/// Tar archives have `ustar` at this offset of their first header
+fn is_tar(content: &[u8]) -> bool {
+    content.get(257..262) == Some(b"ustar".as_slice())
+}
+
+/// Put the files of a tar export back into a single JSON object
+fn read_tar(content: &[u8]) -> Result<Value> {
+    let mut fields = Map::new();
+    let mut archive = tar::Archive::new(content);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .replace('-', "_");
+
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data)?;
+        let value: Value = serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        match value {
+            Value::Object(manifest) if name == MANIFEST_FILE => fields.extend(manifest),
+            value => {
+                fields.insert(name, value);
+            }
+        }
+    }
+
+    Ok(Value::Object(fields))
+}
+
+fn read_prefs(db: &StateDb) -> Result<BTreeMap<String, PrefValue>> {
+    let conn = db.connection();
+    let mut stmt = conn.prepare("SELECT key, value, value_type FROM prefs")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+
+    let mut prefs = BTreeMap::new();
+    for row in rows {
+        let (key, value, value_type) = row?;
+        prefs.insert(key, parse_pref_value(&value, &value_type)?);
+    }
+    Ok(prefs)
+}
+
+fn read_containers(db: &StateDb) -> Result<Vec<ExportedContainer>> {
+    let conn = db.connection();
+    let mut stmt =
+        conn.prepare("SELECT id, name, color, icon, position FROM containers ORDER BY id")?;
+    let rows = stmt.query_map([], |row| {
+        Ok(ExportedContainer {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            color: row.get(2)?,
+            icon: row.get(3)?,
+            position: row.get(4)?,
+        })
+    })?;
+    rows.collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(Into::into)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_db() -> StateDb {
+        let db = StateDb::open_in_memory().unwrap();
+        db.add_extension("ublock@test", "uBlock", None).unwrap();
+        db.add_container("1", "Work", "blue", "briefcase").unwrap();
+        db.assign_container_site("mail.example.com", "1", true)
+            .unwrap();
+        db.set_pref("browser.startup.page", "3", "int").unwrap();
+        db.set_pref("privacy.resistFingerprinting", "true", "bool")
+            .unwrap();
+        db.add_search_engine(
+            "ddg",
+            "DuckDuckGo",
+            "https://duckduckgo.com/?q={searchTerms}",
+            &SearchEngineDetails::default(),
+        )
+        .unwrap();
+        db.set_default_search_engine("ddg").unwrap();
+        db
+    }
+
+    #[test]
+    fn test_from_db() {
+        let export = StateExport::from_db(&sample_db(), "device-a").unwrap();
+
+        assert_eq!(export.version, EXPORT_VERSION);
+        assert_eq!(export.device_id, "device-a");
+        assert_eq!(export.extensions[0].id, "ublock@test");
+        assert_eq!(export.containers[0].name, "Work");
+        assert_eq!(export.container_sites[0].host, "mail.example.com");
+        assert_eq!(
+            export.prefs.get("browser.startup.page"),
+            Some(&PrefValue::Int(3))
+        );
+        assert!(export.search_engines[0].is_default);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let export = StateExport::from_db(&sample_db(), "device-a").unwrap();
+
+        let mut content = Vec::new();
+        export.write(ExportFormat::Json, &mut content).unwrap();
+
+        assert_eq!(StateExport::read(content.as_slice()).unwrap(), export);
+    }
+
+    #[test]
+    fn test_tar_round_trip() {
+        let export = StateExport::from_db(&sample_db(), "device-a").unwrap();
+
+        let mut content = Vec::new();
+        export.write(ExportFormat::Tar, &mut content).unwrap();
+
+        let mut archive = tar::Archive::new(content.as_slice());
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(names.contains(&"wolfpack-export/manifest.json".to_string()));
+        assert!(names.contains(&"wolfpack-export/search-engines.json".to_string()));
+
+        assert_eq!(StateExport::read(content.as_slice()).unwrap(), export);
+    }
+
+    #[test]
+    fn test_read_rejects_newer_version() {
+        let content = format!(
+            r#"{{"version": {}, "exported_at": "", "device_id": "device-a"}}"#,
+            EXPORT_VERSION + 1
+        );
+        assert!(StateExport::read(content.as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_changes_from() {
+        let export = StateExport::from_db(&sample_db(), "device-a").unwrap();
+        assert!(export.changes_from(&export).is_empty());
+
+        let current =
+            StateExport::from_db(&StateDb::open_in_memory().unwrap(), "device-b").unwrap();
+        let events = export.changes_from(&current);
+        assert_eq!(events.len(), 7);
+        assert!(events.contains(&Event::SearchEngineDefault {
+            id: "ddg".to_string()
+        }));
+
+        let mut changed = export.clone();
+        changed
+            .prefs
+            .insert("browser.startup.page".to_string(), PrefValue::Int(1));
+        assert_eq!(
+            changed.changes_from(&export),
+            vec![Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: PrefValue::Int(1),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_changes_since() {
+        let export = StateExport::from_db(&sample_db(), "device-a").unwrap();
+        assert!(export.changes_since(&export).is_empty());
+
+        let empty = StateExport::from_db(&StateDb::open_in_memory().unwrap(), "device-a").unwrap();
+        assert_eq!(
+            empty.changes_since(&export),
+            vec![
+                Event::ExtensionRemoved {
+                    id: "ublock@test".to_string()
+                },
+                Event::ContainerRemoved {
+                    id: "1".to_string()
+                },
+                Event::ContainerSiteUnassigned {
+                    host: "mail.example.com".to_string()
+                },
+                Event::PrefRemoved {
+                    key: "browser.startup.page".to_string()
+                },
+                Event::PrefRemoved {
+                    key: "privacy.resistFingerprinting".to_string()
+                },
+                Event::SearchEngineRemoved {
+                    id: "ddg".to_string()
+                },
+            ]
+        );
+        assert_eq!(export.changes_since(&empty), export.changes_from(&empty));
+    }
+}
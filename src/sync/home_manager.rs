@@ -0,0 +1,224 @@
+use crate::events::PrefValue;
+
+use super::export::{ExportedContainer, ExportedSearchEngine, StateExport};
+
+/// Profile the snippet configures under `programs.librewolf.profiles`
+const PROFILE_NAME: &str = "default";
+
+/// Render exported state as a home-manager `programs.librewolf` module
+///
+/// Extensions are listed as comments: home-manager installs them from Nix
+/// packages, which can't be derived from an extension ID.
+pub fn render_home_manager(export: &StateExport) -> String {
+    let mut lines = vec![
+        format!(
+            "# Generated by wolfpack from {} at {}",
+            export.device_id, export.exported_at
+        ),
+        "{".to_string(),
+        "  programs.librewolf = {".to_string(),
+        "    enable = true;".to_string(),
+        format!("    profiles.{} = {{", PROFILE_NAME),
+    ];
+
+    if !export.prefs.is_empty() {
+        lines.push("      settings = {".to_string());
+        for (key, value) in &export.prefs {
+            lines.push(format!(
+                "        {} = {};",
+                nix_string(key),
+                nix_pref(value)
+            ));
+        }
+        lines.push("      };".to_string());
+    }
+
+    if !export.containers.is_empty() {
+        lines.extend(render_containers(&export.containers));
+    }
+
+    if !export.search_engines.is_empty() {
+        lines.extend(render_search(&export.search_engines));
+    }
+
+    if !export.extensions.is_empty() {
+        lines.push("      # Synced extensions, to add to extensions.packages:".to_string());
+        for extension in &export.extensions {
+            let source = extension
+                .url
+                .as_deref()
+                .map(|url| format!(" from {}", url))
+                .unwrap_or_default();
+            lines.push(format!(
+                "      #   {} ({}){}",
+                extension.name, extension.id, source
+            ));
+        }
+    }
+
+    lines.push("    };".to_string());
+    lines.push("  };".to_string());
+    lines.push("}".to_string());
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn render_containers(containers: &[ExportedContainer]) -> Vec<String> {
+    let mut lines = vec![
+        "      containersForce = true;".to_string(),
+        "      containers = {".to_string(),
+    ];
+    for container in containers {
+        lines.push(format!(
+            "        {} = {{ id = {}; color = {}; icon = {}; }};",
+            nix_string(&container.name),
+            container.id.parse::<u32>().unwrap_or(0),
+            nix_string(&container.color),
+            nix_string(&container.icon)
+        ));
+    }
+    lines.push("      };".to_string());
+    lines
+}
+
+fn render_search(engines: &[ExportedSearchEngine]) -> Vec<String> {
+    let mut lines = vec![
+        "      search = {".to_string(),
+        "        force = true;".to_string(),
+    ];
+    if let Some(default) = engines.iter().find(|e| e.is_default) {
+        lines.push(format!("        default = {};", nix_string(&default.name)));
+    }
+
+    lines.push("        engines = {".to_string());
+    for engine in engines {
+        lines.push(format!("          {} = {{", nix_string(&engine.name)));
+        lines.push("            urls = [".to_string());
+
+        let mut search_url = vec![format!("template = {};", nix_string(&engine.url))];
+        if let Some(method) = &engine.details.method {
+            search_url.push(format!("method = {};", nix_string(method)));
+        }
+        if !engine.details.params.is_empty() {
+            let params: Vec<String> = engine
+                .details
+                .params
+                .iter()
+                .map(|p| {
+                    format!(
+                        "{{ name = {}; value = {}; }}",
+                        nix_string(&p.name),
+                        nix_string(&p.value)
+                    )
+                })
+                .collect();
+            search_url.push(format!("params = [ {} ];", params.join(" ")));
+        }
+        lines.push(format!("              {{ {} }}", search_url.join(" ")));
+
+        if let Some(suggest_url) = &engine.details.suggest_url {
+            lines.push(format!(
+                "              {{ template = {}; type = \"application/x-suggestions+json\"; }}",
+                nix_string(suggest_url)
+            ));
+        }
+        lines.push("            ];".to_string());
+        lines.push("          };".to_string());
+    }
+    lines.push("        };".to_string());
+    lines.push("      };".to_string());
+    lines
+}
+
+fn nix_pref(value: &PrefValue) -> String {
+    match value {
+        PrefValue::Bool(b) => b.to_string(),
+        PrefValue::Int(n) => n.to_string(),
+        PrefValue::String(s) => nix_string(s),
+    }
+}
+
+/// Quote a Nix string, escaping interpolation as well as quotes
+fn nix_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace("${", "\\${")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{SearchEngineDetails, SearchParam};
+    use crate::sync::export::ExportedExtension;
+
+    fn sample_export() -> StateExport {
+        StateExport {
+            version: 1,
+            exported_at: "2026-01-01T00:00:00+00:00".to_string(),
+            device_id: "device-a".to_string(),
+            extensions: vec![ExportedExtension {
+                id: "uBlock0@raymondhill.net".to_string(),
+                name: "uBlock Origin".to_string(),
+                url: None,
+            }],
+            prefs: [
+                ("browser.startup.page".to_string(), PrefValue::Int(3)),
+                (
+                    "browser.startup.homepage".to_string(),
+                    PrefValue::String("https://example.com".to_string()),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            containers: vec![ExportedContainer {
+                id: "2".to_string(),
+                name: "Work".to_string(),
+                color: "blue".to_string(),
+                icon: "briefcase".to_string(),
+                position: String::new(),
+            }],
+            container_sites: Vec::new(),
+            search_engines: vec![ExportedSearchEngine {
+                id: "ddg".to_string(),
+                name: "DuckDuckGo".to_string(),
+                url: "https://duckduckgo.com/".to_string(),
+                is_default: true,
+                details: SearchEngineDetails {
+                    params: vec![SearchParam {
+                        name: "q".to_string(),
+                        value: "{searchTerms}".to_string(),
+                    }],
+                    ..Default::default()
+                },
+            }],
+        }
+    }
+
+    #[test]
+    fn test_render_home_manager() {
+        let nix = render_home_manager(&sample_export());
+
+        assert!(nix.contains("programs.librewolf = {"));
+        assert!(nix.contains(r#"        "browser.startup.page" = 3;"#));
+        assert!(nix.contains(r#"        "browser.startup.homepage" = "https://example.com";"#));
+        assert!(
+            nix.contains(r#"        "Work" = { id = 2; color = "blue"; icon = "briefcase"; };"#)
+        );
+        assert!(nix.contains(r#"        default = "DuckDuckGo";"#));
+        assert!(nix.contains(
+            r#"{ template = "https://duckduckgo.com/"; params = [ { name = "q"; value = "{searchTerms}"; } ]; }"#
+        ));
+        assert!(nix.contains("#   uBlock Origin (uBlock0@raymondhill.net)"));
+
+        // Braces balance, so the snippet is a complete attribute set
+        assert_eq!(nix.matches('{').count(), nix.matches('}').count());
+    }
+
+    #[test]
+    fn test_nix_string_escapes_interpolation() {
+        assert_eq!(nix_string(r#"a "b" ${c}"#), r#""a \"b\" \${c}""#);
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::events::{Event, PrefValue};
+
+use super::export::{EXPORT_VERSION, ExportedContainer, ExportedExtension, StateExport};
+
+/// Profile state declared in a `profile.toml`, which the daemon keeps the
+/// pack's state reconciled toward
+///
+/// Only what's listed is enforced: extensions, prefs, and containers
+/// missing from the manifest sync as usual.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileManifest {
+    pub extensions: Vec<ManifestExtension>,
+    pub prefs: BTreeMap<String, PrefValue>,
+    pub containers: Vec<ManifestContainer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestExtension {
+    pub id: String,
+    /// Display name (defaults to the ID)
+    pub name: Option<String>,
+    /// Where to install the extension from (git repository or XPI URL)
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestContainer {
+    /// `userContextId` of the container
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_container_color")]
+    pub color: String,
+    #[serde(default = "default_container_icon")]
+    pub icon: String,
+}
+
+fn default_container_color() -> String {
+    "blue".to_string()
+}
+
+fn default_container_icon() -> String {
+    "fingerprint".to_string()
+}
+
+impl ProfileManifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read manifest {}", path.display()))?;
+        toml::from_str(&content)
+            .with_context(|| format!("Failed to parse manifest {}", path.display()))
+    }
+
+    /// Events for where the materialized state has drifted from the manifest
+    pub fn drift(&self, current: &StateExport) -> Vec<Event> {
+        self.to_export(current).changes_from(current)
+    }
+
+    fn to_export(&self, current: &StateExport) -> StateExport {
+        // Extensions listed without a name or source keep the synced ones
+        let synced = |id: &str| current.extensions.iter().find(|e| e.id == id);
+
+        StateExport {
+            version: EXPORT_VERSION,
+            exported_at: String::new(),
+            device_id: current.device_id.clone(),
+            extensions: self
+                .extensions
+                .iter()
+                .map(|extension| {
+                    let synced = synced(&extension.id);
+                    ExportedExtension {
+                        id: extension.id.clone(),
+                        name: extension
+                            .name
+                            .clone()
+                            .or_else(|| synced.map(|e| e.name.clone()))
+                            .unwrap_or_else(|| extension.id.clone()),
+                        url: extension
+                            .source
+                            .clone()
+                            .or_else(|| synced.and_then(|e| e.url.clone())),
+                    }
+                })
+                .collect(),
+            prefs: self.prefs.clone(),
+            containers: self
+                .containers
+                .iter()
+                .map(|container| ExportedContainer {
+                    id: container.id.clone(),
+                    name: container.name.clone(),
+                    color: container.color.clone(),
+                    icon: container.icon.clone(),
+                    position: String::new(),
+                })
+                .collect(),
+            container_sites: Vec::new(),
+            search_engines: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::StateDb;
+
+    const MANIFEST: &str = r#"
+        [[extensions]]
+        id = "uBlock0@raymondhill.net"
+        name = "uBlock Origin"
+        source = "https://github.com/gorhill/uBlock"
+
+        [[extensions]]
+        id = "sidebery@test"
+
+        [prefs]
+        "browser.startup.page" = 3
+        "privacy.resistFingerprinting" = true
+
+        [[containers]]
+        id = "1"
+        name = "Work"
+        icon = "briefcase"
+    "#;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest: ProfileManifest = toml::from_str(MANIFEST).unwrap();
+
+        assert_eq!(manifest.extensions[1].name, None);
+        assert_eq!(
+            manifest.extensions[0].source.as_deref(),
+            Some("https://github.com/gorhill/uBlock")
+        );
+        assert_eq!(
+            manifest.prefs.get("browser.startup.page"),
+            Some(&PrefValue::Int(3))
+        );
+        assert_eq!(manifest.containers[0].color, "blue");
+        assert_eq!(manifest.containers[0].icon, "briefcase");
+    }
+
+    #[test]
+    fn test_parse_manifest_rejects_unknown_fields() {
+        assert!(toml::from_str::<ProfileManifest>("[[extension]]\nid = \"a\"").is_err());
+    }
+
+    #[test]
+    fn test_drift() {
+        let manifest: ProfileManifest = toml::from_str(MANIFEST).unwrap();
+        let db = StateDb::open_in_memory().unwrap();
+        db.set_pref("browser.startup.page", "1", "int").unwrap();
+        db.set_pref("privacy.resistFingerprinting", "true", "bool")
+            .unwrap();
+        db.set_pref("browser.tabs.warnOnClose", "false", "bool")
+            .unwrap();
+        db.add_container("1", "Work", "blue", "briefcase").unwrap();
+        db.add_extension(
+            "sidebery@test",
+            "Sidebery",
+            Some("https://example.com/sidebery"),
+        )
+        .unwrap();
+
+        let current = StateExport::from_db(&db, "device-a").unwrap();
+        let drift = manifest.drift(&current);
+
+        // Prefs outside the manifest are left alone, and extensions listed
+        // without a name or source already match
+        assert_eq!(
+            drift,
+            vec![
+                Event::ExtensionAdded {
+                    id: "uBlock0@raymondhill.net".to_string(),
+                    name: "uBlock Origin".to_string(),
+                    url: Some("https://github.com/gorhill/uBlock".to_string()),
+                },
+                Event::PrefSet {
+                    key: "browser.startup.page".to_string(),
+                    value: PrefValue::Int(3),
+                },
+            ]
+        );
+    }
+}
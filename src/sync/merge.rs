@@ -1,4 +1,15 @@
-use crate::events::{EventEnvelope, VectorClock};
+use crate::events::{EventEnvelope, SyncCategory, VectorClock};
+
+/// Changes to the same entity made on different devices without either
+/// seeing the other, settled by timestamp order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub category: SyncCategory,
+    /// Extension ID, pref key, host, etc. that both changes touched
+    pub entity: String,
+    /// Devices that made the concurrent changes
+    pub devices: Vec<String>,
+}
 
 pub fn merge_events(
     local: &[EventEnvelope],
@@ -19,11 +30,56 @@ pub fn merge_events(
     }
 
     // Sort by timestamp for deterministic ordering
-    merged.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    merged.sort_by_key(|e| e.timestamp);
 
     (merged, clock)
 }
 
+/// Find conflicts between newly received events and the rest of the log
+pub fn find_conflicts(new: &[EventEnvelope], all: &[EventEnvelope]) -> Vec<Conflict> {
+    let mut conflicts: Vec<Conflict> = Vec::new();
+
+    for event in new {
+        let Some(entity) = event.event.entity_id() else {
+            continue;
+        };
+        let category = event.event.category();
+
+        for other in all {
+            if other.device == event.device
+                || other.event.category() != category
+                || other.event.entity_id() != Some(entity)
+                || !other.clock.concurrent_with(&event.clock)
+            {
+                continue;
+            }
+
+            let index = match conflicts
+                .iter()
+                .position(|c| c.category == category && c.entity == entity)
+            {
+                Some(index) => index,
+                None => {
+                    conflicts.push(Conflict {
+                        category,
+                        entity: entity.to_string(),
+                        devices: Vec::new(),
+                    });
+                    conflicts.len() - 1
+                }
+            };
+            let conflict = &mut conflicts[index];
+            for device in [&event.device, &other.device] {
+                if !conflict.devices.contains(device) {
+                    conflict.devices.push(device.clone());
+                }
+            }
+        }
+    }
+
+    conflicts
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,4 +119,51 @@ mod tests {
         assert_eq!(new_clock.get("A"), 1);
         assert_eq!(new_clock.get("B"), 1);
     }
+
+    fn pref_set(device: &str, clock: &VectorClock, value: i64) -> EventEnvelope {
+        EventEnvelope::new(
+            device.to_string(),
+            clock.clone(),
+            Event::PrefSet {
+                key: "browser.startup.page".to_string(),
+                value: crate::events::PrefValue::Int(value),
+            },
+        )
+    }
+
+    #[test]
+    fn test_find_conflicts() {
+        let mut clock_a = VectorClock::new();
+        clock_a.set("A", 1);
+        let mut clock_b = VectorClock::new();
+        clock_b.set("B", 1);
+
+        let local = pref_set("A", &clock_a, 1);
+        let remote = pref_set("B", &clock_b, 3);
+        let all = vec![local.clone(), remote.clone()];
+
+        assert_eq!(
+            find_conflicts(std::slice::from_ref(&remote), &all),
+            vec![Conflict {
+                category: SyncCategory::Prefs,
+                entity: "browser.startup.page".to_string(),
+                devices: vec!["B".to_string(), "A".to_string()],
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_conflicts_ignores_ordered_changes() {
+        let mut clock_a = VectorClock::new();
+        clock_a.set("A", 1);
+        // B saw A's change before making its own
+        let mut clock_b = clock_a.clone();
+        clock_b.set("B", 1);
+
+        let local = pref_set("A", &clock_a, 1);
+        let remote = pref_set("B", &clock_b, 3);
+        let all = vec![local, remote.clone()];
+
+        assert!(find_conflicts(&[remote], &all).is_empty());
+    }
 }
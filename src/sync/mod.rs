@@ -1,8 +1,36 @@
+mod actor;
+mod availability;
 mod diff;
+mod divergence;
 mod engine;
+mod export;
+mod home_manager;
+mod manifest;
 mod merge;
+mod order;
+mod pause;
+mod replay;
+mod report;
+mod search_default;
+mod tab_url;
 
-pub use crate::state::PendingTab;
-pub use diff::{diff_containers, diff_extensions, diff_handlers, diff_prefs};
-pub use engine::{SyncEngine, SyncResult};
-pub use merge::merge_events;
+pub use crate::state::{Divergence, PendingTab, ReadingListItem};
+pub use actor::EngineHandle;
+pub use availability::{ExtensionAvailability, ExtensionMatrix};
+pub use diff::{
+    diff_addresses, diff_cert_overrides, diff_container_order, diff_container_sites,
+    diff_containers, diff_extensions, diff_handlers, diff_permissions, diff_prefs, diff_start_page,
+};
+pub use divergence::{Keep, PrefReconciliation, reconcile_prefs};
+pub use engine::{EventPage, GcReport, SyncEngine, SyncResult};
+pub use export::{
+    EXPORT_VERSION, ExportFormat, ExportedContainer, ExportedExtension, ExportedSearchEngine,
+    StateExport,
+};
+pub use manifest::{ManifestContainer, ManifestExtension, ProfileManifest};
+pub use merge::{Conflict, find_conflicts, merge_events};
+pub use order::{position_between, reposition};
+pub use pause::{PAUSE_ALL, PauseState, parse_duration};
+pub use replay::{ReplayPoint, ReplayReport};
+pub use report::{DeviceUsage, HealthReport, XpiUsage};
+pub use tab_url::normalize_tab_url;
@@ -0,0 +1,216 @@
+//! Fractional indexing, for orders devices can change concurrently
+//!
+//! Each item's place is a position key, and the order is the keys sorted as
+//! strings. Moving an item gives it a key between its new neighbors' keys,
+//! so a move is one event about one item that never has to renumber the
+//! rest. Two devices moving items to the same place at once can pick the
+//! same key; ties are broken by the items' IDs, the same way everywhere.
+
+/// Digits of a position key, in ASCII order so keys sort as plain strings
+const DIGITS: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// A position key sorting after `before` and before `after`, either of which
+/// may be missing at the ends of the order
+///
+/// Keys made here never end in the lowest digit, which leaves room before
+/// every key. `before` must sort before `after`.
+pub fn position_between(before: Option<&str>, after: Option<&str>) -> String {
+    let before = before.unwrap_or("").as_bytes();
+    let after = after.filter(|a| a.as_bytes() > before).map(str::as_bytes);
+    // Only keys from here are valid: digits alone, with no trailing zero
+    let valid =
+        |key: &[u8]| key.iter().all(|c| DIGITS.contains(c)) && key.last() != Some(&DIGITS[0]);
+    let key = if valid(before) && after.is_none_or(valid) {
+        midpoint(before, after)
+    } else {
+        // Keys not made here can only be followed
+        let mut key = before.to_vec();
+        key.push(DIGITS[DIGITS.len() / 2]);
+        key
+    };
+    String::from_utf8(key).unwrap_or_default()
+}
+
+/// The key halfway between `a` and `b` (or the end, without `b`), where
+/// `a < b` and neither ends in the lowest digit
+fn midpoint(a: &[u8], b: Option<&[u8]>) -> Vec<u8> {
+    let digit = |key: &[u8], i: usize| key.get(i).copied().unwrap_or(DIGITS[0]);
+    let index = |c: u8| DIGITS.iter().position(|&d| d == c).unwrap_or(0);
+
+    if let Some(b) = b {
+        // Keep the prefix they share
+        let shared = (0..b.len()).take_while(|&i| digit(a, i) == b[i]).count();
+        if shared > 0 {
+            let mut key = b[..shared].to_vec();
+            key.extend(midpoint(a.get(shared..).unwrap_or(&[]), Some(&b[shared..])));
+            return key;
+        }
+    }
+
+    let low = a.first().map_or(0, |&c| index(c));
+    let high = b.map_or(DIGITS.len(), |b| index(b[0]));
+    if high - low > 1 {
+        return vec![DIGITS[(low + high).div_ceil(2)]];
+    }
+    match b {
+        // The first digits are consecutive, and b has more after its first
+        Some(b) if b.len() > 1 => vec![b[0]],
+        _ => {
+            let mut key = vec![DIGITS[low]];
+            key.extend(midpoint(a.get(1..).unwrap_or(&[]), None));
+            key
+        }
+    }
+}
+
+/// Positions that give `order` the order it lists, keeping the positions
+/// in `known` of as many items as can stay where they are
+///
+/// Returns the items that need a new position, with it. Items without a
+/// known position, or with an empty one, always get one.
+pub fn reposition(order: &[String], known: &[(String, String)]) -> Vec<(String, String)> {
+    let known_position = |id: &str| {
+        known
+            .iter()
+            .find(|(known_id, position)| known_id == id && !position.is_empty())
+            .map(|(_, position)| position.as_str())
+    };
+    let kept = longest_ordered_run(order, &known_position);
+
+    let mut moved = Vec::new();
+    let mut previous: Option<String> = None;
+    for (i, id) in order.iter().enumerate() {
+        if kept.contains(&i) {
+            previous = known_position(id).map(ToString::to_string);
+            continue;
+        }
+        let next = order[i + 1..]
+            .iter()
+            .enumerate()
+            .find(|(offset, _)| kept.contains(&(i + 1 + offset)))
+            .and_then(|(_, id)| known_position(id));
+        let position = position_between(previous.as_deref(), next);
+        previous = Some(position.clone());
+        moved.push((id.clone(), position));
+    }
+    moved
+}
+
+/// Indexes into `order` of the longest run of items, not necessarily
+/// adjacent, whose known positions already sort in that order
+fn longest_ordered_run<'a>(
+    order: &[String],
+    known_position: &impl Fn(&str) -> Option<&'a str>,
+) -> Vec<usize> {
+    let positions: Vec<Option<&str>> = order.iter().map(|id| known_position(id)).collect();
+    // The longest run ending at each item, and the item before it there
+    let mut runs: Vec<(usize, Option<usize>)> = Vec::with_capacity(order.len());
+    for (i, position) in positions.iter().enumerate() {
+        let Some(position) = position else {
+            runs.push((0, None));
+            continue;
+        };
+        let best = (0..i)
+            .filter(|&j| positions[j].is_some_and(|p| p < *position))
+            .max_by_key(|&j| runs[j].0);
+        runs.push(match best {
+            Some(j) => (runs[j].0 + 1, Some(j)),
+            None => (1, None),
+        });
+    }
+
+    let mut end = (0..order.len())
+        .filter(|&i| runs[i].0 > 0)
+        .max_by_key(|&i| runs[i].0);
+    let mut kept = Vec::new();
+    while let Some(i) = end {
+        kept.push(i);
+        end = runs[i].1;
+    }
+    kept.reverse();
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_between() {
+        let first = position_between(None, None);
+        let after = position_between(Some(&first), None);
+        let before = position_between(None, Some(&first));
+        let middle = position_between(Some(&first), Some(&after));
+        assert!(before < first && first < middle && middle < after);
+
+        // Room is left however often an item goes first
+        let mut key = first;
+        for _ in 0..100 {
+            let next = position_between(None, Some(&key));
+            assert!(next < key);
+            assert!(!next.ends_with('0'));
+            key = next;
+        }
+
+        // Neighbors sharing a prefix, or consecutive digits
+        for (a, b) in [("a1", "a2"), ("a", "b"), ("a", "aV"), ("Z", "a01")] {
+            let key = position_between(Some(a), Some(b));
+            assert!(
+                a < key.as_str() && key.as_str() < b,
+                "{} < {} < {}",
+                a,
+                key,
+                b
+            );
+        }
+    }
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    fn known(positions: &[(&str, &str)]) -> Vec<(String, String)> {
+        positions
+            .iter()
+            .map(|(id, position)| (id.to_string(), position.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_reposition() {
+        let known = known(&[("1", "a"), ("2", "b"), ("3", "c"), ("4", "d")]);
+
+        // Already in order
+        assert!(reposition(&ids(&["1", "2", "3", "4"]), &known).is_empty());
+
+        // Moving one item to the front only moves that one
+        let moved = reposition(&ids(&["4", "1", "2", "3"]), &known);
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].0, "4");
+        assert!(moved[0].1.as_str() < "a");
+
+        // A new item, and one moved between others
+        let moved = reposition(&ids(&["1", "3", "5", "2", "4"]), &known);
+        let moved_ids: Vec<&str> = moved.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(moved_ids.len(), 2);
+        assert!(moved_ids.contains(&"5"));
+        let mut order = known.clone();
+        for (id, position) in moved {
+            order.retain(|(known_id, _)| *known_id != id);
+            order.push((id, position));
+        }
+        order.sort_by(|a, b| a.1.cmp(&b.1));
+        let order: Vec<&str> = order.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(order, vec!["1", "3", "5", "2", "4"]);
+    }
+
+    #[test]
+    fn test_reposition_without_positions() {
+        // Items synced before they had positions all get one, in order
+        let known = known(&[("1", ""), ("2", "")]);
+        let moved = reposition(&ids(&["2", "1"]), &known);
+        assert_eq!(moved.len(), 2);
+        assert_eq!(moved[0].0, "2");
+        assert!(moved[0].1 < moved[1].1);
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// State DB scope used for pausing all sync activity
+pub const PAUSE_ALL: &str = "all";
+
+/// An active sync pause
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PauseState {
+    /// When the pause ends on its own (`None` means until resumed)
+    pub until: Option<DateTime<Utc>>,
+}
+
+impl PauseState {
+    /// Build a pause state from its stored form, dropping expired pauses
+    pub fn from_stored(until: Option<&str>, now: DateTime<Utc>) -> Result<Option<Self>> {
+        let until = until
+            .map(|s| {
+                DateTime::parse_from_rfc3339(s)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .with_context(|| format!("Invalid pause timestamp: {}", s))
+            })
+            .transpose()?;
+
+        match until {
+            Some(until) if until <= now => Ok(None),
+            until => Ok(Some(Self { until })),
+        }
+    }
+
+    /// Human-readable description for status output
+    pub fn describe(&self) -> String {
+        match self.until {
+            Some(until) => format!("paused until {}", until.to_rfc3339()),
+            None => "paused".to_string(),
+        }
+    }
+}
+
+/// Parse a duration such as `30m`, `2h`, `1d` or `1h30m`
+pub fn parse_duration(input: &str) -> Result<TimeDelta> {
+    let input = input.trim();
+    if input.is_empty() {
+        bail!("Empty duration");
+    }
+
+    let mut total = TimeDelta::zero();
+    let mut digits = String::new();
+
+    for c in input.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let value: i64 = digits
+            .parse()
+            .with_context(|| format!("Invalid duration: {}", input))?;
+        digits.clear();
+
+        let part = match c {
+            's' => TimeDelta::try_seconds(value),
+            'm' => TimeDelta::try_minutes(value),
+            'h' => TimeDelta::try_hours(value),
+            'd' => TimeDelta::try_days(value),
+            _ => bail!("Invalid duration unit '{}' in {}", c, input),
+        };
+        total += part.with_context(|| format!("Duration out of range: {}", input))?;
+    }
+
+    if !digits.is_empty() {
+        bail!("Duration is missing a unit (s, m, h, d): {}", input);
+    }
+
+    if total <= TimeDelta::zero() {
+        bail!("Duration must be positive: {}", input);
+    }
+
+    Ok(total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration_units() {
+        assert_eq!(parse_duration("90s").unwrap(), TimeDelta::seconds(90));
+        assert_eq!(parse_duration("30m").unwrap(), TimeDelta::minutes(30));
+        assert_eq!(parse_duration("2h").unwrap(), TimeDelta::hours(2));
+        assert_eq!(parse_duration("1d").unwrap(), TimeDelta::days(1));
+    }
+
+    #[test]
+    fn test_parse_duration_compound() {
+        assert_eq!(parse_duration("1h30m").unwrap(), TimeDelta::minutes(90));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("2").is_err());
+        assert!(parse_duration("h").is_err());
+        assert!(parse_duration("2w").is_err());
+        assert!(parse_duration("0m").is_err());
+    }
+
+    #[test]
+    fn test_pause_state_expiry() {
+        let now = Utc::now();
+        let past = (now - TimeDelta::minutes(5)).to_rfc3339();
+        let future = (now + TimeDelta::minutes(5)).to_rfc3339();
+
+        assert!(PauseState::from_stored(Some(&past), now).unwrap().is_none());
+        assert!(
+            PauseState::from_stored(Some(&future), now)
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            PauseState::from_stored(None, now).unwrap(),
+            Some(PauseState { until: None })
+        );
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::events::EventEnvelope;
+
+/// A point in the event log, for `wolfpack replay --until` and `wolfpack diff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayPoint {
+    /// The last event at or before this time
+    Time(DateTime<Utc>),
+    /// This event, and everything before it
+    Event(Uuid),
+}
+
+impl ReplayPoint {
+    /// The point as one word, for passing to the daemon
+    pub fn to_arg(&self) -> String {
+        match self {
+            Self::Time(time) => time.to_rfc3339(),
+            Self::Event(id) => id.to_string(),
+        }
+    }
+
+    /// The events up to this point, from events sorted by timestamp
+    pub fn events_until<'a>(&self, events: &'a [EventEnvelope]) -> Result<&'a [EventEnvelope]> {
+        let end = match self {
+            Self::Time(until) => events.partition_point(|e| e.timestamp <= *until),
+            Self::Event(id) => {
+                events
+                    .iter()
+                    .position(|e| e.id == *id)
+                    .with_context(|| format!("No event {} in the event log", id))?
+                    + 1
+            }
+        };
+        Ok(&events[..end])
+    }
+}
+
+impl FromStr for ReplayPoint {
+    type Err = anyhow::Error;
+
+    /// Parse an event ID, an RFC 3339 timestamp, or a date (the end of that
+    /// day, UTC)
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if let Ok(id) = Uuid::parse_str(s) {
+            return Ok(Self::Event(id));
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+            return Ok(Self::Time(time.with_timezone(&Utc)));
+        }
+        if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+            && let Some(end) = date.and_hms_opt(23, 59, 59)
+        {
+            return Ok(Self::Time(end.and_utc()));
+        }
+        bail!(
+            "Invalid replay point '{}', expected an event ID, an RFC 3339 timestamp, or a date (YYYY-MM-DD)",
+            s
+        )
+    }
+}
+
+impl fmt::Display for ReplayPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Time(time) => write!(f, "{}", time.to_rfc3339()),
+            Self::Event(id) => write!(f, "event {}", id),
+        }
+    }
+}
+
+/// What a replay of the event log rebuilt
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    /// Events in the log up to the replay point
+    pub events: usize,
+    /// Events materialized, leaving out those in paused categories
+    pub applied: usize,
+    /// Where the replay stopped, when it didn't replay the whole log
+    pub until: Option<ReplayPoint>,
+}
+
+impl ReplayReport {
+    pub fn describe(&self) -> String {
+        let applied = format!(
+            "Rebuilt state from {} of {} events",
+            self.applied, self.events
+        );
+        match self.until {
+            Some(until) => format!(
+                "{} up to {}. Sync is paused so later events aren't applied; \
+                 `wolfpack resume` applies them",
+                applied, until
+            ),
+            None => applied,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Event, VectorClock};
+
+    fn envelope(timestamp: &str) -> EventEnvelope {
+        EventEnvelope {
+            id: Uuid::now_v7(),
+            device: "device-a".to_string(),
+            timestamp: DateTime::parse_from_rfc3339(timestamp)
+                .unwrap()
+                .with_timezone(&Utc),
+            clock: VectorClock::new(),
+            event: Event::PrefRemoved {
+                key: "browser.startup.page".to_string(),
+            },
+            platforms: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_parse_replay_point() {
+        let id = Uuid::now_v7();
+        assert_eq!(
+            id.to_string().parse::<ReplayPoint>().unwrap(),
+            ReplayPoint::Event(id)
+        );
+        assert_eq!(
+            "2026-03-01T12:00:00+01:00".parse::<ReplayPoint>().unwrap(),
+            ReplayPoint::Time(
+                DateTime::parse_from_rfc3339("2026-03-01T11:00:00Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+        assert_eq!(
+            "2026-03-01".parse::<ReplayPoint>().unwrap(),
+            ReplayPoint::Time(
+                DateTime::parse_from_rfc3339("2026-03-01T23:59:59Z")
+                    .unwrap()
+                    .with_timezone(&Utc)
+            )
+        );
+        assert!("yesterday".parse::<ReplayPoint>().is_err());
+    }
+
+    #[test]
+    fn test_events_until() {
+        let events = vec![
+            envelope("2026-03-01T10:00:00Z"),
+            envelope("2026-03-01T11:00:00Z"),
+            envelope("2026-03-02T09:00:00Z"),
+        ];
+
+        let until: ReplayPoint = "2026-03-01T11:00:00Z".parse().unwrap();
+        assert_eq!(until.events_until(&events).unwrap().len(), 2);
+        let until: ReplayPoint = "2026-02-28".parse().unwrap();
+        assert!(until.events_until(&events).unwrap().is_empty());
+
+        let until = ReplayPoint::Event(events[0].id);
+        assert_eq!(until.events_until(&events).unwrap().len(), 1);
+        assert!(
+            ReplayPoint::Event(Uuid::now_v7())
+                .events_until(&events)
+                .is_err()
+        );
+    }
+}
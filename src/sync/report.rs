@@ -0,0 +1,321 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::events::{Event, EventEnvelope};
+
+/// How many of the largest XPIs and most changed settings are listed
+const TOP: usize = 5;
+/// Days the sync dir's growth is measured over
+const GROWTH_DAYS: [u64; 2] = [7, 30];
+
+/// Event files a device has in the sync dir
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceUsage {
+    /// Device name and ID, for display
+    pub device: String,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// An extension version's XPI in the blob store
+#[derive(Debug, Clone, PartialEq)]
+pub struct XpiUsage {
+    pub id: String,
+    pub name: String,
+    pub version: String,
+    /// Bytes its chunks take up, including chunks shared with other versions
+    pub bytes: u64,
+}
+
+/// What the sync dir and state database hold, for `wolfpack report`
+#[derive(Debug, Default)]
+pub struct HealthReport {
+    /// Event log usage by device, largest first
+    pub devices: Vec<DeviceUsage>,
+    /// Bytes the whole blob store takes up
+    pub blob_bytes: u64,
+    /// Largest XPIs, largest first
+    pub largest_xpis: Vec<XpiUsage>,
+    /// Events in the log by type, most first
+    pub event_types: Vec<(String, usize)>,
+    /// Things changed by the most events, most first
+    pub most_changed: Vec<(String, usize)>,
+    /// Rows in each table of materialized state
+    pub rows: Vec<(&'static str, u64)>,
+    /// Bytes added to the sync dir over the last few days, by days
+    pub growth: Vec<(u64, u64)>,
+}
+
+impl HealthReport {
+    /// Tally the event log, with `xpi_size` giving the bytes an XPI's chunks
+    /// take up
+    pub fn count_events(&mut self, events: &[EventEnvelope], xpi_size: impl Fn(&[String]) -> u64) {
+        let mut types: HashMap<String, usize> = HashMap::new();
+        let mut changes: HashMap<String, usize> = HashMap::new();
+        let mut xpis: HashMap<(String, String), XpiUsage> = HashMap::new();
+
+        for envelope in events {
+            let event = &envelope.event;
+            *types.entry(type_name(event)).or_default() += 1;
+            if let Some(entity) = event.entity_id() {
+                *changes
+                    .entry(format!("{} {}", event.category(), entity))
+                    .or_default() += 1;
+            }
+            if let Event::ExtensionInstalled {
+                id,
+                name,
+                version,
+                chunks,
+                ..
+            } = event
+            {
+                xpis.entry((id.clone(), version.clone()))
+                    .or_insert_with(|| XpiUsage {
+                        id: id.clone(),
+                        name: name.clone(),
+                        version: version.clone(),
+                        bytes: xpi_size(chunks),
+                    });
+            }
+        }
+
+        self.event_types = most_first(types);
+        self.most_changed = most_first(changes)
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .take(TOP)
+            .collect();
+        self.largest_xpis = xpis.into_values().collect();
+        self.largest_xpis
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.id.cmp(&b.id)));
+        self.largest_xpis.truncate(TOP);
+    }
+
+    pub fn describe(&self) -> String {
+        let mut lines = self.describe_storage();
+
+        let total: usize = self.event_types.iter().map(|(_, count)| count).sum();
+        lines.push(format!("Events: {}", total));
+        for (event_type, count) in &self.event_types {
+            lines.push(format!("  {:<40} {:>10}", event_type, count));
+        }
+        if !self.most_changed.is_empty() {
+            lines.push("Most changed:".to_string());
+            for (entity, count) in &self.most_changed {
+                lines.push(format!("  {:<40} {:>10}", entity, count));
+            }
+        }
+
+        lines.push("State database rows:".to_string());
+        for (table, count) in self.rows.iter().filter(|(_, count)| *count > 0) {
+            lines.push(format!("  {:<40} {:>10}", table, count));
+        }
+
+        let growth: Vec<String> = self
+            .growth
+            .iter()
+            .map(|(days, bytes)| {
+                format!(
+                    "{} in {} days ({}/day)",
+                    human_size(*bytes),
+                    days,
+                    human_size(bytes / days)
+                )
+            })
+            .collect();
+        lines.push(format!("Sync dir growth: {}", growth.join(", ")));
+
+        lines.join("\n")
+    }
+
+    /// Lines for the event log by device and the blob store
+    fn describe_storage(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        let (files, bytes) = self.devices.iter().fold((0, 0), |(files, bytes), d| {
+            (files + d.files, bytes + d.bytes)
+        });
+        lines.push(format!(
+            "Event log: {} in {} files",
+            human_size(bytes),
+            files
+        ));
+        for device in &self.devices {
+            lines.push(format!(
+                "  {:<40} {:>10}  {} files",
+                device.device,
+                human_size(device.bytes),
+                device.files
+            ));
+        }
+
+        lines.push(format!("Blob store: {}", human_size(self.blob_bytes)));
+        for xpi in &self.largest_xpis {
+            let extension = format!("{} {} ({})", xpi.name, xpi.version, xpi.id);
+            lines.push(format!("  {:<40} {:>10}", extension, human_size(xpi.bytes)));
+        }
+        lines
+    }
+}
+
+/// Bytes added to (or rewritten in) a directory since each number of days
+/// before `now`, going by when files were last modified
+pub fn dir_growth(path: &Path, now: SystemTime) -> Result<Vec<(u64, u64)>> {
+    let mut files = Vec::new();
+    collect_files(path, &mut files)?;
+    Ok(GROWTH_DAYS
+        .into_iter()
+        .map(|days| {
+            let since = now - Duration::from_secs(days * 24 * 60 * 60);
+            let bytes = files
+                .iter()
+                .filter(|(modified, _)| *modified >= since)
+                .map(|(_, len)| len)
+                .sum();
+            (days, bytes)
+        })
+        .collect())
+}
+
+/// Modification time and size of every file under a directory
+fn collect_files(path: &Path, files: &mut Vec<(SystemTime, u64)>) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if metadata.is_dir() {
+            collect_files(&entry.path(), files)?;
+        } else if metadata.is_file() {
+            files.push((metadata.modified()?, metadata.len()));
+        }
+    }
+    Ok(())
+}
+
+/// The event's type, as it's tagged in event files
+fn type_name(event: &Event) -> String {
+    serde_json::to_value(event)
+        .ok()
+        .and_then(|value| value.get("type")?.as_str().map(String::from))
+        .unwrap_or_default()
+}
+
+fn most_first(counts: HashMap<String, usize>) -> Vec<(String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts
+}
+
+fn human_size(bytes: u64) -> String {
+    const KIB: f64 = 1024.0;
+    let bytes = bytes as f64;
+    if bytes < KIB {
+        format!("{} B", bytes)
+    } else if bytes < KIB * KIB {
+        format!("{:.1} KiB", bytes / KIB)
+    } else if bytes < KIB * KIB * KIB {
+        format!("{:.1} MiB", bytes / KIB / KIB)
+    } else {
+        format!("{:.1} GiB", bytes / KIB / KIB / KIB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{ExtensionSource, PrefValue, VectorClock};
+
+    fn envelope(event: Event) -> EventEnvelope {
+        EventEnvelope::new("device-a".to_string(), VectorClock::new(), event)
+    }
+
+    fn pref(value: i64) -> Event {
+        Event::PrefSet {
+            key: "browser.startup.page".to_string(),
+            value: PrefValue::Int(value),
+        }
+    }
+
+    fn install(version: &str, chunks: usize) -> Event {
+        Event::ExtensionInstalled {
+            id: "ext@example.com".to_string(),
+            name: "Example".to_string(),
+            version: version.to_string(),
+            source: ExtensionSource::Local {
+                original_path: "ext.xpi".to_string(),
+            },
+            xpi_data: String::new(),
+            chunks: vec!["ab".repeat(32); chunks],
+        }
+    }
+
+    #[test]
+    fn test_count_events() {
+        let events: Vec<EventEnvelope> = [
+            pref(1),
+            pref(3),
+            pref(1),
+            Event::HandlerRemoved {
+                protocol: "mailto".to_string(),
+            },
+            install("1.0", 2),
+            install("1.1", 3),
+        ]
+        .into_iter()
+        .map(envelope)
+        .collect();
+
+        let mut report = HealthReport::default();
+        report.count_events(&events, |chunks| chunks.len() as u64 * 1000);
+
+        assert_eq!(
+            report.event_types,
+            vec![
+                ("PrefSet".to_string(), 3),
+                ("ExtensionInstalled".to_string(), 2),
+                ("HandlerRemoved".to_string(), 1),
+            ]
+        );
+        // Only things changed more than once
+        assert_eq!(
+            report.most_changed,
+            vec![
+                ("prefs browser.startup.page".to_string(), 3),
+                ("extensions ext@example.com".to_string(), 2),
+            ]
+        );
+        let versions: Vec<(&str, u64)> = report
+            .largest_xpis
+            .iter()
+            .map(|xpi| (xpi.version.as_str(), xpi.bytes))
+            .collect();
+        assert_eq!(versions, vec![("1.1", 3000), ("1.0", 2000)]);
+        assert!(report.describe().contains("Events: 6"));
+    }
+
+    #[test]
+    fn test_dir_growth() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("events/device-a")).unwrap();
+        fs::write(dir.path().join("events/device-a/0001.evt"), [0u8; 700]).unwrap();
+        fs::write(dir.path().join("state.db"), [0u8; 300]).unwrap();
+
+        let now = SystemTime::now();
+        assert_eq!(
+            dir_growth(dir.path(), now).unwrap(),
+            vec![(7, 1000), (30, 1000)]
+        );
+        // Nothing was modified more than a week from now
+        let later = now + Duration::from_secs(8 * 24 * 60 * 60);
+        assert_eq!(
+            dir_growth(dir.path(), later).unwrap(),
+            vec![(7, 0), (30, 1000)]
+        );
+        assert_eq!(human_size(1536), "1.5 KiB");
+    }
+}
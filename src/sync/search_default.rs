@@ -0,0 +1,138 @@
+use std::time::SystemTime;
+
+use crate::profile::SearchEngine;
+
+/// How many times a rejected default engine is written before giving up
+const MAX_ATTEMPTS: u32 = 3;
+
+/// A default search engine written to the profile, awaiting confirmation
+/// that the browser adopted it
+///
+/// The browser only reads search.json at startup and rewrites it on exit,
+/// so the write can't be verified until the file changes again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchDefaultCheck {
+    pub id: String,
+    /// Modification time of search.json right after our write
+    /// (`None` while the write is still queued)
+    written: Option<SystemTime>,
+    attempts: u32,
+}
+
+/// Outcome of checking a pending default engine against the profile
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// Not verifiable yet: the write is queued or the browser hasn't rewritten the file
+    Pending,
+    /// The browser kept the engine as its default
+    Adopted,
+    /// The browser replaced the default; write it again
+    Retry,
+    /// The browser kept replacing the default; stop trying
+    GaveUp,
+}
+
+impl SearchDefaultCheck {
+    pub fn queued(id: String) -> Self {
+        Self {
+            id,
+            written: None,
+            attempts: 0,
+        }
+    }
+
+    /// Record that the write reached the profile
+    pub fn mark_written(&mut self, modified: Option<SystemTime>) {
+        self.written = modified;
+        self.attempts += 1;
+    }
+
+    /// Compare the profile's engines (and search.json modification time)
+    /// against the engine we wrote
+    pub fn verify(
+        &mut self,
+        current: &[SearchEngine],
+        modified: Option<SystemTime>,
+    ) -> Verification {
+        let Some(written) = self.written else {
+            return Verification::Pending;
+        };
+        if modified == Some(written) {
+            return Verification::Pending;
+        }
+
+        if current.iter().any(|e| e.is_default && e.id == self.id) {
+            return Verification::Adopted;
+        }
+
+        if self.attempts >= MAX_ATTEMPTS {
+            return Verification::GaveUp;
+        }
+
+        self.written = None;
+        Verification::Retry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn engine(id: &str, is_default: bool) -> SearchEngine {
+        SearchEngine {
+            id: id.to_string(),
+            name: id.to_string(),
+            url: String::new(),
+            is_default,
+            details: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_pending_until_browser_rewrites() {
+        let written = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let mut check = SearchDefaultCheck::queued("sp".to_string());
+        let engines = vec![engine("ddg", true), engine("sp", false)];
+
+        // Still queued
+        assert_eq!(check.verify(&engines, Some(written)), Verification::Pending);
+
+        // Written, but the file hasn't changed since
+        check.mark_written(Some(written));
+        assert_eq!(check.verify(&engines, Some(written)), Verification::Pending);
+    }
+
+    #[test]
+    fn test_adopted() {
+        let written = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let mut check = SearchDefaultCheck::queued("sp".to_string());
+        check.mark_written(Some(written));
+
+        let engines = vec![engine("ddg", false), engine("sp", true)];
+        let later = written + Duration::from_secs(60);
+        assert_eq!(check.verify(&engines, Some(later)), Verification::Adopted);
+    }
+
+    #[test]
+    fn test_retry_then_give_up() {
+        let mut modified = SystemTime::UNIX_EPOCH;
+        let mut check = SearchDefaultCheck::queued("sp".to_string());
+        let engines = vec![engine("ddg", true), engine("sp", false)];
+
+        for _ in 1..MAX_ATTEMPTS {
+            check.mark_written(Some(modified));
+            modified += Duration::from_secs(60);
+            assert_eq!(check.verify(&engines, Some(modified)), Verification::Retry);
+            // Queued again, so nothing to verify until it's written
+            assert_eq!(
+                check.verify(&engines, Some(modified)),
+                Verification::Pending
+            );
+        }
+
+        check.mark_written(Some(modified));
+        modified += Duration::from_secs(60);
+        assert_eq!(check.verify(&engines, Some(modified)), Verification::GaveUp);
+    }
+}
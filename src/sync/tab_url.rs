@@ -0,0 +1,85 @@
+use anyhow::{Context, Result, bail};
+use url::Url;
+
+/// Schemes that run code or embed content instead of pointing at a page,
+/// so opening them on another device would be unsafe
+const REJECTED_SCHEMES: &[&str] = &["javascript", "data", "vbscript"];
+
+/// Schemes written without `//` that are kept as given
+const OPAQUE_SCHEMES: &[&str] = &["about", "mailto"];
+
+/// Check a URL before sending it to another device
+///
+/// URLs without a scheme (e.g. `example.com/page`) are sent as https.
+pub fn normalize_tab_url(input: &str) -> Result<String> {
+    let input = input.trim();
+    if input.is_empty() {
+        bail!("Empty URL");
+    }
+
+    let scheme = input
+        .split_once(':')
+        .map(|(scheme, _)| scheme.to_ascii_lowercase());
+    let has_scheme = input.contains("://")
+        || scheme.as_deref().is_some_and(|scheme| {
+            REJECTED_SCHEMES.contains(&scheme) || OPAQUE_SCHEMES.contains(&scheme)
+        });
+    let candidate = if has_scheme {
+        input.to_string()
+    } else {
+        format!("https://{}", input)
+    };
+
+    let url = Url::parse(&candidate).with_context(|| format!("Invalid URL: {}", input))?;
+    if REJECTED_SCHEMES.contains(&url.scheme()) {
+        bail!("Refusing to send {}: URL", url.scheme());
+    }
+    if matches!(url.scheme(), "http" | "https") && url.host_str().is_none_or(str::is_empty) {
+        bail!("Invalid URL: {} has no host", input);
+    }
+
+    Ok(url.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_adds_https() {
+        assert_eq!(
+            normalize_tab_url("example.com/page").unwrap(),
+            "https://example.com/page"
+        );
+        assert_eq!(
+            normalize_tab_url("  localhost:8080/app \n").unwrap(),
+            "https://localhost:8080/app"
+        );
+    }
+
+    #[test]
+    fn test_normalize_keeps_scheme() {
+        assert_eq!(
+            normalize_tab_url("http://example.com").unwrap(),
+            "http://example.com/"
+        );
+        assert_eq!(
+            normalize_tab_url("about:preferences").unwrap(),
+            "about:preferences"
+        );
+    }
+
+    #[test]
+    fn test_normalize_rejects_unsafe_schemes() {
+        assert!(normalize_tab_url("javascript:alert(1)").is_err());
+        assert!(normalize_tab_url("JavaScript:alert(1)").is_err());
+        assert!(normalize_tab_url("data:text/html,<h1>hi</h1>").is_err());
+    }
+
+    #[test]
+    fn test_normalize_rejects_invalid() {
+        assert!(normalize_tab_url("").is_err());
+        assert!(normalize_tab_url("https://").is_err());
+        assert!(normalize_tab_url("exa mple.com").is_err());
+    }
+}
@@ -0,0 +1,342 @@
+//! System tray companion for the daemon.
+//!
+//! Exposes a StatusNotifierItem showing sync status and the pending tab
+//! count, with quick actions that are forwarded to the daemon over IPC.
+//! The daemon pushes status and tab changes to the tray over a subscription.
+
+use anyhow::{Result, bail};
+use ksni::TrayMethods;
+use std::time::Duration;
+use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+use tracing::{debug, info, warn};
+
+use crate::cli::{IpcClient, send_command};
+use crate::ipc::{Command, DaemonStatus, Payload, TabEntry};
+
+/// First wait before looking for the daemon again once it's gone, doubled
+/// each time it still isn't there
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(120);
+
+/// Actions triggered from the tray menu
+#[derive(Debug)]
+enum TrayAction {
+    OpenAllTabs,
+    TogglePause,
+    Refresh,
+    Quit,
+}
+
+/// Snapshot of daemon state shown in the tray
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct DaemonSnapshot {
+    running: bool,
+    paused: bool,
+    status: String,
+    pending_tabs: Vec<String>,
+}
+
+impl DaemonSnapshot {
+    fn running(status: &DaemonStatus, tabs: Vec<TabEntry>) -> Self {
+        Self {
+            running: true,
+            paused: status.paused,
+            status: status.to_string(),
+            pending_tabs: tabs.into_iter().map(|tab| tab.id).collect(),
+        }
+    }
+
+    fn not_running() -> Self {
+        Self {
+            status: "Daemon not running".into(),
+            ..Self::default()
+        }
+    }
+}
+
+struct WolfpackTray {
+    snapshot: DaemonSnapshot,
+    actions: UnboundedSender<TrayAction>,
+}
+
+impl WolfpackTray {
+    fn send(&self, action: TrayAction) {
+        let _ = self.actions.send(action);
+    }
+}
+
+impl ksni::Tray for WolfpackTray {
+    fn id(&self) -> String {
+        env!("CARGO_PKG_NAME").into()
+    }
+
+    fn title(&self) -> String {
+        "Wolfpack".into()
+    }
+
+    fn icon_name(&self) -> String {
+        if !self.snapshot.running {
+            "network-offline".into()
+        } else if self.snapshot.paused {
+            "media-playback-pause".into()
+        } else if self.snapshot.pending_tabs.is_empty() {
+            "emblem-synchronizing".into()
+        } else {
+            "mail-unread".into()
+        }
+    }
+
+    fn status(&self) -> ksni::Status {
+        if self.snapshot.pending_tabs.is_empty() {
+            ksni::Status::Active
+        } else {
+            ksni::Status::NeedsAttention
+        }
+    }
+
+    fn tool_tip(&self) -> ksni::ToolTip {
+        ksni::ToolTip {
+            title: "Wolfpack".into(),
+            description: format!(
+                "{}\n{}",
+                self.snapshot.status,
+                pending_label(self.snapshot.pending_tabs.len())
+            ),
+            ..Default::default()
+        }
+    }
+
+    fn activate(&mut self, _x: i32, _y: i32) {
+        self.send(TrayAction::Refresh);
+    }
+
+    fn menu(&self) -> Vec<ksni::MenuItem<Self>> {
+        use ksni::menu::StandardItem;
+
+        let pending = self.snapshot.pending_tabs.len();
+        vec![
+            StandardItem {
+                label: self.snapshot.status.clone(),
+                enabled: false,
+                ..Default::default()
+            }
+            .into(),
+            ksni::MenuItem::Separator,
+            StandardItem {
+                label: format!("Open all tabs ({})", pending),
+                enabled: pending > 0,
+                activate: Box::new(|this: &mut Self| this.send(TrayAction::OpenAllTabs)),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: if self.snapshot.paused {
+                    "Resume sync".into()
+                } else {
+                    "Pause sync".into()
+                },
+                enabled: self.snapshot.running,
+                activate: Box::new(|this: &mut Self| this.send(TrayAction::TogglePause)),
+                ..Default::default()
+            }
+            .into(),
+            StandardItem {
+                label: "Refresh".into(),
+                activate: Box::new(|this: &mut Self| this.send(TrayAction::Refresh)),
+                ..Default::default()
+            }
+            .into(),
+            ksni::MenuItem::Separator,
+            StandardItem {
+                label: "Quit".into(),
+                icon_name: "application-exit".into(),
+                activate: Box::new(|this: &mut Self| this.send(TrayAction::Quit)),
+                ..Default::default()
+            }
+            .into(),
+        ]
+    }
+}
+
+/// Run the tray until the user quits it
+#[allow(clippy::cognitive_complexity)] // tokio::select! event loop pattern
+pub async fn run_tray() -> Result<()> {
+    let (actions, mut action_rx) = unbounded_channel();
+    let tray = WolfpackTray {
+        snapshot: fetch_snapshot().await,
+        actions,
+    };
+    let handle = tray.spawn().await?;
+    info!("Tray icon started");
+
+    // Reads from the daemon block, so they're kept off the runtime
+    let (updates, mut update_rx) = unbounded_channel();
+    std::thread::Builder::new()
+        .name("tray-updates".into())
+        .spawn(move || follow_daemon(&updates))?;
+
+    loop {
+        tokio::select! {
+            Some(snapshot) = update_rx.recv() => {
+                handle.update(|tray| tray.snapshot = snapshot).await;
+            }
+            Some(action) = action_rx.recv() => match action {
+                TrayAction::OpenAllTabs => open_all_tabs().await,
+                TrayAction::TogglePause => {
+                    let paused = handle.update(|tray| tray.snapshot.paused).await;
+                    toggle_pause(paused.unwrap_or(false)).await;
+                }
+                TrayAction::Refresh => {
+                    let snapshot = fetch_snapshot().await;
+                    handle.update(|tray| tray.snapshot = snapshot).await;
+                }
+                TrayAction::Quit => break,
+            },
+        }
+    }
+
+    handle.shutdown().await;
+    Ok(())
+}
+
+/// Pass on the daemon's updates for as long as the tray runs, looking for
+/// the daemon again with backoff whenever it's gone
+fn follow_daemon(updates: &UnboundedSender<DaemonSnapshot>) {
+    let mut retry = None;
+    while !updates.is_closed() {
+        if let Err(e) = follow_updates(updates, &mut retry) {
+            debug!("Lost the daemon's updates: {:#}", e);
+        }
+        let _ = updates.send(DaemonSnapshot::not_running());
+        let wait = next_retry(retry);
+        retry = Some(wait);
+        std::thread::sleep(wait);
+    }
+}
+
+/// Subscribe to the daemon and pass on each update until the connection
+/// closes, starting the backoff over once it answers
+fn follow_updates(
+    updates: &UnboundedSender<DaemonSnapshot>,
+    retry: &mut Option<Duration>,
+) -> Result<()> {
+    let mut client = IpcClient::connect()?;
+    let id = client.send(Command::Subscribe)?;
+    loop {
+        let Payload::Update { status, tabs } = client.response(id)? else {
+            bail!("Unexpected answer to subscribe");
+        };
+        *retry = None;
+        if updates
+            .send(DaemonSnapshot::running(&status, tabs))
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+/// How long to wait before looking for a daemon that isn't there, backing
+/// off from the last wait
+fn next_retry(retry: Option<Duration>) -> Duration {
+    retry.map_or(RETRY_INTERVAL, |retry| {
+        retry.saturating_mul(2).min(MAX_RETRY_INTERVAL)
+    })
+}
+
+/// Send an IPC command without blocking the runtime
+async fn ipc(command: Command) -> Result<Payload> {
+    tokio::task::spawn_blocking(move || send_command(command)).await?
+}
+
+async fn fetch_snapshot() -> DaemonSnapshot {
+    let status = match ipc(Command::Status).await {
+        Ok(Payload::Status(status)) => status,
+        _ => return DaemonSnapshot::not_running(),
+    };
+
+    let tabs = pending_tabs().await.unwrap_or_else(|e| {
+        warn!("Failed to fetch pending tabs: {}", e);
+        Vec::new()
+    });
+    DaemonSnapshot::running(&status, tabs)
+}
+
+/// The tabs waiting to be opened here
+async fn pending_tabs() -> Result<Vec<TabEntry>> {
+    match ipc(Command::Tabs).await? {
+        Payload::Tabs { tabs } => Ok(tabs),
+        other => bail!("Unexpected answer to tabs: {}", other),
+    }
+}
+
+#[allow(clippy::cognitive_complexity)] // Loop with error logging
+async fn open_all_tabs() {
+    let tabs = match pending_tabs().await {
+        Ok(tabs) => tabs,
+        Err(e) => {
+            warn!("Failed to fetch pending tabs: {}", e);
+            return;
+        }
+    };
+
+    for tab in tabs {
+        if let Err(e) = open_tab(&tab.id).await {
+            warn!("Failed to open tab {}: {}", tab.id, e);
+        }
+    }
+}
+
+#[allow(clippy::cognitive_complexity)] // Match arms with logging
+async fn toggle_pause(paused: bool) {
+    let (verb, command) = if paused {
+        ("resume", Command::Resume { category: None })
+    } else {
+        (
+            "pause",
+            Command::Pause {
+                category: None,
+                seconds: None,
+            },
+        )
+    };
+    match ipc(command).await {
+        Ok(response) => info!("{}", response),
+        Err(e) => warn!("Failed to {} sync: {}", verb, e),
+    }
+}
+
+async fn open_tab(id: &str) -> Result<()> {
+    ipc(Command::Open {
+        tab_id: id.to_string(),
+    })
+    .await?;
+    Ok(())
+}
+
+fn pending_label(count: usize) -> String {
+    match count {
+        0 => "No pending tabs".to_string(),
+        1 => "1 pending tab".to_string(),
+        n => format!("{} pending tabs", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_retry() {
+        assert_eq!(next_retry(None), RETRY_INTERVAL);
+        assert_eq!(next_retry(Some(RETRY_INTERVAL)), RETRY_INTERVAL * 2);
+        assert_eq!(next_retry(Some(MAX_RETRY_INTERVAL)), MAX_RETRY_INTERVAL);
+    }
+
+    #[test]
+    fn test_pending_label() {
+        assert_eq!(pending_label(0), "No pending tabs");
+        assert_eq!(pending_label(1), "1 pending tab");
+        assert_eq!(pending_label(3), "3 pending tabs");
+    }
+}